@@ -0,0 +1,205 @@
+//! The system's accessibility preferences, for the UI to follow: reduced
+//! motion, high contrast, reduced transparency and text size. The webview's
+//! media queries miss most of these on Windows and Linux, so they are read
+//! from the platform here instead, at startup, whenever a window comes to
+//! the front (the usual moment after changing them) and every so often in
+//! between. A change is announced with `accessibility-changed`.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, Window, WindowEvent};
+
+use crate::error::Result;
+
+/// How often preferences are read while no window comes to the front.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccessibilityPreferences {
+    pub reduced_motion: bool,
+    pub high_contrast: bool,
+    pub reduced_transparency: bool,
+    /// The preferred text size relative to the default, from the system's
+    /// text scaling; 1.0 where there is no such setting.
+    pub text_scale: f64,
+}
+
+impl Default for AccessibilityPreferences {
+    fn default() -> Self {
+        Self {
+            reduced_motion: false,
+            high_contrast: false,
+            reduced_transparency: false,
+            text_scale: 1.0,
+        }
+    }
+}
+
+/// Managed as Tauri state.
+pub struct Accessibility(Mutex<AccessibilityPreferences>);
+
+#[cfg(target_os = "macos")]
+fn detect() -> AccessibilityPreferences {
+    use objc2::msg_send;
+    use objc2::rc::{autoreleasepool, Retained};
+    use objc2::runtime::{AnyClass, AnyObject};
+
+    autoreleasepool(|_| {
+        let Some(class) = AnyClass::get(c"NSWorkspace") else {
+            return AccessibilityPreferences::default();
+        };
+        let workspace: Option<Retained<AnyObject>> = unsafe { msg_send![class, sharedWorkspace] };
+        let Some(workspace) = workspace else {
+            return AccessibilityPreferences::default();
+        };
+        unsafe {
+            AccessibilityPreferences {
+                reduced_motion: msg_send![&workspace, accessibilityDisplayShouldReduceMotion],
+                high_contrast: msg_send![&workspace, accessibilityDisplayShouldIncreaseContrast],
+                reduced_transparency: msg_send![
+                    &workspace,
+                    accessibilityDisplayShouldReduceTransparency
+                ],
+                text_scale: 1.0,
+            }
+        }
+    })
+}
+
+/// A value from the current user's registry, as `reg query` prints it.
+#[cfg(windows)]
+fn registry_value(key: &str, name: &str) -> Option<String> {
+    let output = crate::process::command("reg")
+        .args(["query", &format!(r"HKCU\{key}"), "/v", name])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // `    <name>    <type>    <value>`
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with(name))
+        .and_then(|line| line.split_whitespace().nth(2))
+        .map(str::to_string)
+}
+
+#[cfg(windows)]
+fn detect() -> AccessibilityPreferences {
+    // `reg` prints a DWORD in hex and a string as it is.
+    let number = |value: String| match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    };
+    /// Set in the high-contrast flags while it is on.
+    const HCF_HIGHCONTRASTON: u32 = 0x1;
+    let high_contrast = registry_value(r"Control Panel\Accessibility\HighContrast", "Flags")
+        .and_then(number)
+        .is_some_and(|flags| flags & HCF_HIGHCONTRASTON != 0);
+    // Cleared with the rest of the animation effects.
+    let reduced_motion = registry_value(r"Control Panel\Desktop\WindowMetrics", "MinAnimate")
+        .is_some_and(|value| value == "0");
+    let reduced_transparency = registry_value(
+        r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize",
+        "EnableTransparency",
+    )
+    .and_then(number)
+    .is_some_and(|enabled| enabled == 0);
+    // A percentage, from 100 to 225.
+    let text_scale = registry_value(r"Software\Microsoft\Accessibility", "TextScaleFactor")
+        .and_then(number)
+        .map_or(1.0, |percent| f64::from(percent) / 100.0);
+    AccessibilityPreferences {
+        reduced_motion,
+        high_contrast,
+        reduced_transparency,
+        text_scale,
+    }
+}
+
+/// A GNOME setting, which other desktops running GNOME apps also keep.
+#[cfg(target_os = "linux")]
+fn gsetting(schema: &str, key: &str) -> Option<String> {
+    let output = crate::process::command("gsettings")
+        .args(["get", schema, key])
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn detect() -> AccessibilityPreferences {
+    let reduced_motion = gsetting("org.gnome.desktop.interface", "enable-animations")
+        .is_some_and(|value| value == "false");
+    let high_contrast = gsetting("org.gnome.desktop.a11y.interface", "high-contrast")
+        .is_some_and(|value| value == "true")
+        || gsetting("org.gnome.desktop.interface", "gtk-theme")
+            .is_some_and(|theme| theme.to_lowercase().contains("highcontrast"));
+    let text_scale = gsetting("org.gnome.desktop.interface", "text-scaling-factor")
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|scale| *scale > 0.0)
+        .unwrap_or(1.0);
+    AccessibilityPreferences {
+        reduced_motion,
+        high_contrast,
+        reduced_transparency: false,
+        text_scale,
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows, target_os = "linux")))]
+fn detect() -> AccessibilityPreferences {
+    AccessibilityPreferences::default()
+}
+
+/// Reads the preferences again, announcing them if they changed.
+fn refresh(app: &AppHandle) -> AccessibilityPreferences {
+    let current = detect();
+    let Some(accessibility) = app.try_state::<Accessibility>() else {
+        return current;
+    };
+    let mut preferences = accessibility.0.lock().unwrap();
+    if *preferences != current {
+        *preferences = current.clone();
+        drop(preferences);
+        tracing::debug!(?current, "accessibility preferences changed");
+        let _ = app.emit("accessibility-changed", &current);
+    }
+    current
+}
+
+pub fn init(app: &AppHandle) {
+    app.manage(Accessibility(Mutex::new(
+        AccessibilityPreferences::default(),
+    )));
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let handle = app.clone();
+            let _ = tauri::async_runtime::spawn_blocking(move || refresh(&handle)).await;
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+}
+
+/// Reads the preferences when a window comes to the front.
+pub fn on_window_event(window: &Window, event: &WindowEvent) {
+    if !matches!(event, WindowEvent::Focused(true)) {
+        return;
+    }
+    let app = window.app_handle().clone();
+    tauri::async_runtime::spawn_blocking(move || refresh(&app));
+}
+
+/// The preferences as they are now, read fresh rather than from the last
+/// poll.
+#[tauri::command]
+pub async fn get_accessibility_preferences(app: AppHandle) -> Result<AccessibilityPreferences> {
+    Ok(tauri::async_runtime::spawn_blocking(move || refresh(&app)).await?)
+}
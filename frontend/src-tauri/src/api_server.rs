@@ -0,0 +1,414 @@
+//! Opt-in HTTP API on localhost, so scripts, editors and launcher
+//! extensions can drive the app. It serves JSON versions of the chat,
+//! fan-out and conversation commands (streamed tokens still go to the
+//! window as events). Every route except `/health` needs the bearer token,
+//! which is kept in the OS credential store.
+//!
+//! Routes:
+//! - `GET /health`
+//! - `GET /v1/providers`
+//! - `POST /v1/chat` (a chat request) and `POST /v1/fanout` (a fan-out
+//!   request); an `X-Request-Id` header picks the id to cancel by
+//! - `POST /v1/requests/{id}/cancel`
+//! - `GET` and `POST /v1/conversations`, `GET` and `DELETE
+//!   /v1/conversations/{id}`, `POST /v1/conversations/{id}/messages`
+
+use std::convert::Infallible;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Mutex;
+
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE, HOST};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tauri::{AppHandle, Manager, State};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::fanout::{self, FanoutRequest};
+use crate::keys;
+use crate::llm::{self, ChatRequest};
+use crate::os_search;
+use crate::providers::Providers;
+use crate::requests::Requests;
+use crate::storage::conversations::{self, NewMessage};
+use crate::storage::{new_id, Database};
+use crate::windows;
+
+const CONFIG_FILE: &str = "api_server.json";
+/// Credential store entry holding the token.
+pub(crate) const TOKEN_ENTRY: &str = "api-server-token";
+const DEFAULT_PORT: u16 = 4319;
+const MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct ApiServerConfig {
+    /// Start with the app.
+    enabled: bool,
+    port: u16,
+}
+
+impl Default for ApiServerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: DEFAULT_PORT,
+        }
+    }
+}
+
+/// What the server commands return.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiServerStatus {
+    pub running: bool,
+    pub port: u16,
+    /// Base URL while running.
+    pub url: Option<String>,
+    pub token: Option<String>,
+}
+
+struct Running {
+    port: u16,
+    stop: CancellationToken,
+}
+
+/// The server's settings and, while it runs, its port. Managed as Tauri
+/// state.
+#[derive(Default)]
+pub struct ApiServer {
+    config: Mutex<ApiServerConfig>,
+    running: Mutex<Option<Running>>,
+    token: Mutex<Option<String>>,
+}
+
+impl ApiServer {
+    fn status(&self) -> ApiServerStatus {
+        let running = self.running.lock().unwrap();
+        let port = running
+            .as_ref()
+            .map_or(self.config.lock().unwrap().port, |r| r.port);
+        ApiServerStatus {
+            running: running.is_some(),
+            port,
+            url: running
+                .as_ref()
+                .map(|r| format!("http://127.0.0.1:{}", r.port)),
+            token: self.token.lock().unwrap().clone(),
+        }
+    }
+
+    fn save(&self, app: &AppHandle) -> Result<()> {
+        config::write(app, CONFIG_FILE, &*self.config.lock().unwrap())
+    }
+
+    /// The stored token, creating one the first time.
+    fn ensure_token(&self) -> Result<String> {
+        let mut token = self.token.lock().unwrap();
+        if let Some(token) = token.as_ref() {
+            return Ok(token.clone());
+        }
+        match keys::load(TOKEN_ENTRY)? {
+            Some(value) => {
+                *token = Some(value.clone());
+                Ok(value)
+            }
+            None => new_token(&mut token),
+        }
+    }
+}
+
+fn new_token(slot: &mut Option<String>) -> Result<String> {
+    let value = format!("pm_{}{}", new_id(), new_id()).replace('-', "");
+    keys::store(TOKEN_ENTRY, &value)?;
+    *slot = Some(value.clone());
+    Ok(value)
+}
+
+/// An error response.
+struct ApiError(StatusCode, String);
+
+impl From<Error> for ApiError {
+    fn from(err: Error) -> Self {
+        let status = match err {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Locked => StatusCode::LOCKED,
+            Error::Cancelled => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self(status, err.to_string())
+    }
+}
+
+type ApiResult = std::result::Result<Response<Full<Bytes>>, ApiError>;
+
+fn json_response(status: StatusCode, body: &impl Serialize) -> Response<Full<Bytes>> {
+    let bytes = serde_json::to_vec(body).unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(bytes)))
+        .unwrap_or_default()
+}
+
+fn ok(body: impl Serialize) -> ApiResult {
+    Ok(json_response(StatusCode::OK, &body))
+}
+
+async fn read_json<T: DeserializeOwned>(
+    request: Request<Incoming>,
+) -> std::result::Result<T, ApiError> {
+    let body = Limited::new(request.into_body(), MAX_BODY_BYTES)
+        .collect()
+        .await
+        .map_err(|e| ApiError(StatusCode::PAYLOAD_TOO_LARGE, e.to_string()))?
+        .to_bytes();
+    serde_json::from_slice(&body).map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+/// Compares without an early exit, so timing doesn't reveal a prefix.
+fn same_secret(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Only requests addressed to this machine by name count, so a web page
+/// can't reach the server by pointing its own domain at 127.0.0.1.
+fn local_host(request: &Request<Incoming>) -> bool {
+    let Some(host) = request.headers().get(HOST).and_then(|h| h.to_str().ok()) else {
+        return false;
+    };
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    matches!(name, "127.0.0.1" | "localhost")
+}
+
+fn authorized(app: &AppHandle, request: &Request<Incoming>) -> bool {
+    let Some(expected) = app.state::<ApiServer>().token.lock().unwrap().clone() else {
+        return false;
+    };
+    request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .is_some_and(|given| same_secret(given.trim(), &expected))
+}
+
+async fn route(app: &AppHandle, request: Request<Incoming>) -> ApiResult {
+    if !local_host(&request) {
+        return Err(ApiError(
+            StatusCode::FORBIDDEN,
+            "unexpected Host header".into(),
+        ));
+    }
+    let method = request.method().clone();
+    let path = request.uri().path().trim_end_matches('/').to_string();
+    let segments: Vec<&str> = path.split('/').skip(1).collect();
+    if method == Method::GET && segments == ["health"] {
+        return ok(json!({ "ok": true, "version": env!("CARGO_PKG_VERSION") }));
+    }
+    if !authorized(app, &request) {
+        return Err(ApiError(
+            StatusCode::UNAUTHORIZED,
+            "missing or wrong bearer token".into(),
+        ));
+    }
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map_or_else(new_id, str::to_string);
+    let db = || app.state::<Database>();
+
+    match (&method, segments.as_slice()) {
+        (&Method::GET, ["v1", "providers"]) => ok(app
+            .state::<Providers>()
+            .all()
+            .map(|p| p.info())
+            .collect::<Vec<_>>()),
+        (&Method::POST, ["v1", "chat"]) => {
+            let chat: ChatRequest = read_json(request).await?;
+            let response = llm::stream_chat(
+                app.clone(),
+                app.state(),
+                app.state(),
+                app.state(),
+                request_id,
+                chat,
+            )
+            .await?;
+            ok(response)
+        }
+        (&Method::POST, ["v1", "fanout"]) => {
+            let fanout: FanoutRequest = read_json(request).await?;
+            let results = fanout::fanout_prompt(
+                app.clone(),
+                app.state(),
+                app.state(),
+                app.state(),
+                request_id,
+                fanout,
+            )
+            .await?;
+            ok(results)
+        }
+        (&Method::POST, ["v1", "requests", id, "cancel"]) => {
+            ok(json!({ "cancelled": app.state::<Requests>().cancel(id) }))
+        }
+        (&Method::GET, ["v1", "conversations"]) => ok(db().list_conversations()?),
+        (&Method::POST, ["v1", "conversations"]) => {
+            #[derive(Deserialize)]
+            struct NewConversation {
+                title: Option<String>,
+            }
+            let body: NewConversation = read_json(request).await?;
+            ok(db().create_conversation(body.title)?)
+        }
+        (&Method::GET, ["v1", "conversations", id]) => ok(db().get_conversation(id)?),
+        (&Method::DELETE, ["v1", "conversations", id]) => {
+            db().delete_conversation(id)?;
+            windows::conversation_deleted(app, id);
+            os_search::conversation_deleted(app, id);
+            ok(json!({ "deleted": true }))
+        }
+        (&Method::POST, ["v1", "conversations", id, "messages"]) => {
+            let id = id.to_string();
+            let message: NewMessage = read_json(request).await?;
+            ok(conversations::append_message(app.clone(), app.state(), id, message).await?)
+        }
+        _ => Err(ApiError(
+            StatusCode::NOT_FOUND,
+            format!("no route for {method} {path}"),
+        )),
+    }
+}
+
+async fn handle(
+    app: AppHandle,
+    request: Request<Incoming>,
+) -> std::result::Result<Response<Full<Bytes>>, Infallible> {
+    Ok(match route(&app, request).await {
+        Ok(response) => response,
+        Err(ApiError(status, message)) => json_response(status, &json!({ "error": message })),
+    })
+}
+
+async fn serve(app: AppHandle, listener: TcpListener, stop: CancellationToken) {
+    loop {
+        let stream = tokio::select! {
+            _ = stop.cancelled() => return,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(_) => continue,
+            },
+        };
+        let app = app.clone();
+        let stop = stop.clone();
+        tauri::async_runtime::spawn(async move {
+            let service = service_fn(move |request| handle(app.clone(), request));
+            let connection = http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+            tokio::pin!(connection);
+            tokio::select! {
+                _ = connection.as_mut() => {}
+                _ = stop.cancelled() => connection.as_mut().graceful_shutdown(),
+            }
+        });
+    }
+}
+
+async fn start(app: &AppHandle, port: u16) -> Result<ApiServerStatus> {
+    let server = app.state::<ApiServer>();
+    server.ensure_token()?;
+    stop(&server);
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, port))).await?;
+    let port = listener.local_addr()?.port();
+    let token = CancellationToken::new();
+    *server.running.lock().unwrap() = Some(Running {
+        port,
+        stop: token.clone(),
+    });
+    tauri::async_runtime::spawn(serve(app.clone(), listener, token));
+    Ok(server.status())
+}
+
+fn stop(server: &ApiServer) {
+    if let Some(running) = server.running.lock().unwrap().take() {
+        running.stop.cancel();
+    }
+}
+
+/// Loads the settings and starts the server if it was left on.
+pub fn init(app: &AppHandle) {
+    let config = config::read::<ApiServerConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    let (enabled, port) = (config.enabled, config.port);
+    app.manage(ApiServer {
+        config: Mutex::new(config),
+        ..Default::default()
+    });
+    if enabled {
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(err) = start(&app, port).await {
+                tracing::warn!(port, "API server failed to start: {err}");
+            }
+        });
+    }
+}
+
+/// Starts (or restarts) the server on `port`, or the saved port, and keeps
+/// it on across launches. Port 0 picks a free one.
+#[tauri::command]
+pub async fn start_api_server(
+    app: AppHandle,
+    server: State<'_, ApiServer>,
+    port: Option<u16>,
+) -> Result<ApiServerStatus> {
+    let port = port.unwrap_or_else(|| server.config.lock().unwrap().port);
+    let status = start(&app, port).await?;
+    {
+        let mut config = server.config.lock().unwrap();
+        config.enabled = true;
+        config.port = port;
+    }
+    server.save(&app)?;
+    Ok(status)
+}
+
+#[tauri::command]
+pub fn stop_api_server(app: AppHandle, server: State<'_, ApiServer>) -> Result<ApiServerStatus> {
+    stop(&server);
+    server.config.lock().unwrap().enabled = false;
+    server.save(&app)?;
+    Ok(server.status())
+}
+
+#[tauri::command]
+pub fn get_api_server_status(server: State<'_, ApiServer>) -> Result<ApiServerStatus> {
+    server.ensure_token()?;
+    Ok(server.status())
+}
+
+/// Replaces the token; clients using the old one are refused from now on.
+#[tauri::command]
+pub fn regenerate_api_token(server: State<'_, ApiServer>) -> Result<ApiServerStatus> {
+    new_token(&mut server.token.lock().unwrap())?;
+    Ok(server.status())
+}
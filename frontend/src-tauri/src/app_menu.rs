@@ -0,0 +1,328 @@
+//! The native application menu: the menu bar on macOS, each window's menu
+//! on Windows and Linux. Zoom, window and Help items are handled here; the
+//! rest reach the focused window as `menu-action` events. The Model menu
+//! has a checkbox per provider for whether untargeted fan-outs include it.
+//! Accelerators come from the shortcut registry and follow it when
+//! rebound.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu};
+use tauri::{AppHandle, Emitter, EventTarget, Manager, WebviewWindow, Wry};
+use tauri_plugin_opener::OpenerExt;
+
+use crate::error::Result;
+use crate::logging;
+use crate::mini_window;
+use crate::providers::Providers;
+use crate::settings::{self, Settings, SettingsStore};
+use crate::shortcuts;
+use crate::windows;
+
+const REPOSITORY: &str = "https://github.com/bshiribaiev/pentamind";
+/// Prefix of the Model menu's item ids; the provider id follows.
+const PROVIDER_PREFIX: &str = "menu.provider.";
+const ZOOM_STEP: f64 = 1.1;
+const ZOOM_MIN: f64 = 0.5;
+const ZOOM_MAX: f64 = 3.0;
+
+/// Payload of the `menu-action` event for items the frontend handles.
+#[derive(Debug, Clone, Serialize)]
+pub struct MenuAction {
+    pub action: &'static str,
+}
+
+/// Items that change while the app runs, and each window's zoom. Managed as
+/// Tauri state.
+pub struct AppMenu {
+    providers: Vec<(String, CheckMenuItem<Wry>)>,
+    /// Items with an accelerator, by shortcut id.
+    accelerated: Vec<(&'static str, MenuItem<Wry>)>,
+    zoom: Mutex<HashMap<String, f64>>,
+}
+
+/// Items whose clicks become `menu-action` events, by id.
+const FRONTEND_ACTIONS: &[(&str, &str)] = &[
+    ("menu.new_conversation", "new_conversation"),
+    ("menu.export", "export"),
+    ("menu.settings", "open_settings"),
+    ("menu.find", "find"),
+    ("menu.toggle_sidebar", "toggle_sidebar"),
+];
+
+/// Items whose accelerator is a registry shortcut, by id.
+const ACCELERATORS: &[(&str, &str)] = &[
+    ("menu.new_conversation", "new_conversation"),
+    ("menu.open_in_window", "open_in_window"),
+    ("menu.export", "export"),
+    ("menu.settings", "open_settings"),
+    ("menu.find", "find"),
+    ("menu.zoom_in", "zoom_in"),
+    ("menu.zoom_out", "zoom_out"),
+    ("menu.zoom_reset", "zoom_reset"),
+    ("menu.toggle_sidebar", "toggle_sidebar"),
+    ("menu.mini_window", "toggle_mini_window"),
+];
+
+/// Builds items, keeping those with an accelerator so it can follow the
+/// registry.
+struct Items<'a> {
+    app: &'a AppHandle,
+    accelerated: RefCell<Vec<(&'static str, MenuItem<Wry>)>>,
+}
+
+impl Items<'_> {
+    fn item(&self, id: &str, text: &str) -> Result<MenuItem<Wry>> {
+        let shortcut = ACCELERATORS
+            .iter()
+            .find(|(item, _)| *item == id)
+            .map(|(_, shortcut)| *shortcut);
+        let accelerator = shortcut.and_then(|s| shortcuts::binding(self.app, s));
+        let item = MenuItem::with_id(self.app, id, text, true, accelerator.as_deref())?;
+        if let Some(shortcut) = shortcut {
+            self.accelerated.borrow_mut().push((shortcut, item.clone()));
+        }
+        Ok(item)
+    }
+}
+
+pub fn init(app: &AppHandle) -> Result<()> {
+    let disabled = app.state::<SettingsStore>().get().disabled_providers;
+    let separator = || PredefinedMenuItem::separator(app);
+    let items = Items {
+        app,
+        accelerated: RefCell::default(),
+    };
+    let item = |id: &str, text: &str| items.item(id, text);
+
+    let file = Submenu::with_items(
+        app,
+        "File",
+        true,
+        &[
+            &item("menu.new_conversation", "New Conversation")?,
+            &item("menu.open_in_window", "Open Conversation in New Window")?,
+            &separator()?,
+            &item("menu.export", "Export…")?,
+            &separator()?,
+            &PredefinedMenuItem::close_window(app, None)?,
+            #[cfg(not(target_os = "macos"))]
+            &item("menu.settings", "Settings…")?,
+            #[cfg(not(target_os = "macos"))]
+            &PredefinedMenuItem::quit(app, None)?,
+        ],
+    )?;
+    let edit = Submenu::with_items(
+        app,
+        "Edit",
+        true,
+        &[
+            &PredefinedMenuItem::undo(app, None)?,
+            &PredefinedMenuItem::redo(app, None)?,
+            &separator()?,
+            &PredefinedMenuItem::cut(app, None)?,
+            &PredefinedMenuItem::copy(app, None)?,
+            &PredefinedMenuItem::paste(app, None)?,
+            &PredefinedMenuItem::select_all(app, None)?,
+            &separator()?,
+            &item("menu.find", "Find…")?,
+        ],
+    )?;
+    let view = Submenu::with_items(
+        app,
+        "View",
+        true,
+        &[
+            &item("menu.zoom_in", "Zoom In")?,
+            &item("menu.zoom_out", "Zoom Out")?,
+            &item("menu.zoom_reset", "Actual Size")?,
+            &separator()?,
+            &item("menu.toggle_sidebar", "Toggle Sidebar")?,
+            &item("menu.mini_window", "Mini Window")?,
+            &PredefinedMenuItem::fullscreen(app, None)?,
+        ],
+    )?;
+
+    let model = Submenu::new(app, "Model", true)?;
+    let mut providers = Vec::new();
+    for provider in app.state::<Providers>().all() {
+        let check = CheckMenuItem::with_id(
+            app,
+            format!("{PROVIDER_PREFIX}{}", provider.id()),
+            provider.name(),
+            true,
+            !disabled.iter().any(|d| d == provider.id()),
+            None::<&str>,
+        )?;
+        model.append(&check)?;
+        providers.push((provider.id().to_string(), check));
+    }
+
+    let help = Submenu::with_items(
+        app,
+        "Help",
+        true,
+        &[
+            &item("menu.docs", "Pentamind Help")?,
+            &item("menu.report_issue", "Report an Issue…")?,
+            &separator()?,
+            &item("menu.logs", "Show Logs")?,
+        ],
+    )?;
+
+    let menu = Menu::new(app)?;
+    #[cfg(target_os = "macos")]
+    menu.append(&Submenu::with_items(
+        app,
+        "Pentamind",
+        true,
+        &[
+            &PredefinedMenuItem::about(app, None, Some(tauri::menu::AboutMetadata::default()))?,
+            &separator()?,
+            &item("menu.settings", "Settings…")?,
+            &separator()?,
+            &PredefinedMenuItem::services(app, None)?,
+            &separator()?,
+            &PredefinedMenuItem::hide(app, None)?,
+            &PredefinedMenuItem::hide_others(app, None)?,
+            &PredefinedMenuItem::show_all(app, None)?,
+            &separator()?,
+            &PredefinedMenuItem::quit(app, None)?,
+        ],
+    )?)?;
+    menu.append(&file)?;
+    menu.append(&edit)?;
+    menu.append(&view)?;
+    menu.append(&model)?;
+    #[cfg(target_os = "macos")]
+    menu.append(&Submenu::with_items(
+        app,
+        "Window",
+        true,
+        &[
+            &PredefinedMenuItem::minimize(app, None)?,
+            &PredefinedMenuItem::maximize(app, None)?,
+            &separator()?,
+            &PredefinedMenuItem::bring_all_to_front(app, None)?,
+        ],
+    )?)?;
+    menu.append(&help)?;
+    app.set_menu(menu)?;
+    app.manage(AppMenu {
+        providers,
+        accelerated: items.accelerated.into_inner(),
+        zoom: Mutex::default(),
+    });
+    Ok(())
+}
+
+/// Keeps the Model menu's checkmarks in step with the settings.
+pub fn sync(app: &AppHandle, settings: &Settings) {
+    let Some(menu) = app.try_state::<AppMenu>() else {
+        return;
+    };
+    for (id, check) in &menu.providers {
+        let _ = check.set_checked(!settings.disabled_providers.contains(id));
+    }
+}
+
+/// Gives the items the registry's current accelerators.
+pub fn shortcuts_changed(app: &AppHandle) {
+    let Some(menu) = app.try_state::<AppMenu>() else {
+        return;
+    };
+    for (shortcut, item) in &menu.accelerated {
+        let accelerator = shortcuts::binding(app, shortcut);
+        if let Err(err) = item.set_accelerator(accelerator.as_deref()) {
+            tracing::warn!("couldn't set the accelerator for {shortcut}: {err}");
+        }
+    }
+}
+
+/// The window the user is working in, or the main one.
+fn focused_window(app: &AppHandle) -> Option<WebviewWindow> {
+    let windows = app.webview_windows();
+    windows
+        .values()
+        .find(|w| w.is_focused().unwrap_or(false))
+        .or_else(|| windows.get(windows::MAIN_LABEL))
+        .cloned()
+}
+
+fn zoom(app: &AppHandle, change: Option<f64>) -> Result<()> {
+    let Some(window) = focused_window(app) else {
+        return Ok(());
+    };
+    let menu = app.state::<AppMenu>();
+    let mut zoom = menu.zoom.lock().unwrap();
+    let level = zoom.entry(window.label().to_string()).or_insert(1.0);
+    *level = match change {
+        Some(factor) => (*level * factor).clamp(ZOOM_MIN, ZOOM_MAX),
+        None => 1.0,
+    };
+    window.set_zoom(*level)?;
+    Ok(())
+}
+
+fn handle(app: &AppHandle, id: &str) -> Result<()> {
+    if let Some(provider) = id.strip_prefix(PROVIDER_PREFIX) {
+        let enabled = app
+            .state::<SettingsStore>()
+            .get()
+            .disabled_providers
+            .iter()
+            .any(|d| d == provider);
+        settings::set_provider_enabled(app, provider, enabled)?;
+        return Ok(());
+    }
+    if let Some((_, action)) = FRONTEND_ACTIONS.iter().find(|(item, _)| *item == id) {
+        let target = focused_window(app)
+            .map(|w| w.label().to_string())
+            .unwrap_or_else(|| windows::MAIN_LABEL.to_string());
+        app.emit_to(
+            EventTarget::webview_window(target),
+            "menu-action",
+            MenuAction { action },
+        )?;
+        return Ok(());
+    }
+    match id {
+        "menu.open_in_window" => {
+            app.emit_to(
+                EventTarget::webview_window(windows::MAIN_LABEL),
+                "menu-action",
+                MenuAction {
+                    action: "open_in_window",
+                },
+            )?;
+        }
+        "menu.zoom_in" => zoom(app, Some(ZOOM_STEP))?,
+        "menu.zoom_out" => zoom(app, Some(1.0 / ZOOM_STEP))?,
+        "menu.zoom_reset" => zoom(app, None)?,
+        "menu.mini_window" => {
+            mini_window::toggle_mini_window(app.clone())?;
+        }
+        "menu.docs" => app.opener().open_url(REPOSITORY, None::<&str>)?,
+        "menu.report_issue" => app
+            .opener()
+            .open_url(format!("{REPOSITORY}/issues/new"), None::<&str>)?,
+        "menu.logs" => logging::open_log_folder(app.clone())?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Handles clicks on the app menu. Tray menu clicks arrive here too and
+/// are left to the tray.
+pub fn on_menu_event(app: &AppHandle, event: MenuEvent) {
+    let id = event.id.as_ref();
+    if !id.starts_with("menu.") {
+        return;
+    }
+    if let Err(err) = handle(app, id) {
+        tracing::warn!("menu item {id} failed: {err}");
+    }
+}
@@ -0,0 +1,379 @@
+//! Picks between (or merges) the answers a fan-out produced. Ranking runs one
+//! of three strategies: exact agreement on normalised answers, embedding
+//! similarity clusters, or a judge model scoring each response.
+
+use std::collections::HashMap;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, State};
+
+use crate::error::{Error, Result};
+use crate::llm::{self, ChatMessage, ChatRequest, ChatResponse, Role};
+use crate::providers::Providers;
+use crate::rag::{cosine, Embedder, EmbeddingOptions};
+use crate::requests::{cancellable, Requests};
+use crate::usage;
+
+/// Cosine similarity above which two responses count as the same answer.
+const SIMILARITY_THRESHOLD: f32 = 0.85;
+const JUDGE_TEMPERATURE: f32 = 0.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candidate {
+    pub provider: String,
+    pub model: String,
+    pub content: String,
+}
+
+/// A model used to judge or synthesise; falls back to the provider's default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelChoice {
+    pub provider: String,
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Strategy {
+    /// Groups identical answers after normalising case, whitespace and JSON
+    /// formatting. Suited to short or structured answers.
+    MajorityVote,
+    /// Clusters responses by embedding similarity.
+    Similarity {
+        #[serde(default)]
+        embedding: EmbeddingOptions,
+    },
+    /// Asks a judge model to score every response.
+    Judge { judge: ModelChoice },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RankRequest {
+    /// The conversation the candidates answered.
+    pub messages: Vec<ChatMessage>,
+    pub candidates: Vec<Candidate>,
+    pub strategy: Strategy,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RankedResponse {
+    /// Position in the request's `candidates`.
+    pub index: usize,
+    pub provider: String,
+    pub model: String,
+    /// Between 0 and 1, higher is better.
+    pub score: f32,
+    /// Responses sharing a group agree with each other.
+    pub group: Option<usize>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Ranking {
+    /// Best first.
+    pub responses: Vec<RankedResponse>,
+    /// Share of candidates in the largest group, when the strategy groups.
+    pub agreement: Option<f32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SynthesisRequest {
+    pub messages: Vec<ChatMessage>,
+    pub candidates: Vec<Candidate>,
+    pub synthesizer: ModelChoice,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+}
+
+pub(crate) fn resolve(providers: &Providers, choice: &ModelChoice) -> Result<(String, String)> {
+    let provider = providers.get(&choice.provider)?;
+    let model = choice
+        .model
+        .clone()
+        .unwrap_or_else(|| provider.default_model().to_string());
+    Ok((choice.provider.clone(), model))
+}
+
+/// Drops a surrounding Markdown code fence, if any.
+pub(crate) fn strip_fence(text: &str) -> &str {
+    let text = text.trim();
+    let Some(rest) = text.strip_prefix("```") else {
+        return text;
+    };
+    let body = rest.split_once('\n').map_or("", |(_, body)| body);
+    body.trim_end().strip_suffix("```").unwrap_or(body).trim()
+}
+
+/// Canonical form for vote counting: re-serialised JSON (keys sorted) when
+/// the answer parses as JSON, otherwise lower-cased text with collapsed
+/// whitespace and no trailing punctuation.
+fn normalize(content: &str) -> String {
+    let content = strip_fence(content);
+    if let Ok(value) = serde_json::from_str::<Value>(content) {
+        return value.to_string();
+    }
+    content
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches(['.', '!', ';'])
+        .to_lowercase()
+}
+
+/// Turns group assignments into a ranking: bigger groups first, and within a
+/// group by each member's own score.
+fn rank_groups(candidates: &[Candidate], groups: Vec<usize>, scores: Vec<f32>) -> Ranking {
+    let mut sizes: HashMap<usize, usize> = HashMap::new();
+    for &group in &groups {
+        *sizes.entry(group).or_default() += 1;
+    }
+    let total = candidates.len().max(1) as f32;
+    let mut responses: Vec<RankedResponse> = candidates
+        .iter()
+        .enumerate()
+        .map(|(index, candidate)| RankedResponse {
+            index,
+            provider: candidate.provider.clone(),
+            model: candidate.model.clone(),
+            score: scores[index],
+            group: Some(groups[index]),
+            reason: None,
+        })
+        .collect();
+    responses.sort_by(|a, b| {
+        let size = |r: &RankedResponse| sizes[&r.group.unwrap_or_default()];
+        size(b).cmp(&size(a)).then(b.score.total_cmp(&a.score))
+    });
+    Ranking {
+        agreement: sizes.values().max().map(|&n| n as f32 / total),
+        responses,
+    }
+}
+
+fn majority_vote(candidates: &[Candidate]) -> Ranking {
+    let mut keys: Vec<String> = Vec::new();
+    let groups: Vec<usize> = candidates
+        .iter()
+        .map(|candidate| {
+            let key = normalize(&candidate.content);
+            keys.iter().position(|k| *k == key).unwrap_or_else(|| {
+                keys.push(key);
+                keys.len() - 1
+            })
+        })
+        .collect();
+    let total = candidates.len().max(1) as f32;
+    let scores = groups
+        .iter()
+        .map(|g| groups.iter().filter(|other| *other == g).count() as f32 / total)
+        .collect();
+    rank_groups(candidates, groups, scores)
+}
+
+/// Greedy clustering: each response joins the first group whose founder it
+/// is similar enough to. Scores are mean similarity to the other responses.
+async fn similarity(
+    providers: &Providers,
+    client: &Client,
+    candidates: &[Candidate],
+    embedding: &EmbeddingOptions,
+) -> Result<Ranking> {
+    let embedder = Embedder::resolve(providers, embedding)?;
+    let inputs: Vec<String> = candidates.iter().map(|c| c.content.clone()).collect();
+    let vectors = embedder.embed(client, &inputs).await?;
+
+    let mut founders: Vec<usize> = Vec::new();
+    let groups = (0..vectors.len())
+        .map(|i| {
+            founders
+                .iter()
+                .position(|&f| cosine(&vectors[f], &vectors[i]) >= SIMILARITY_THRESHOLD)
+                .unwrap_or_else(|| {
+                    founders.push(i);
+                    founders.len() - 1
+                })
+        })
+        .collect();
+    let scores = (0..vectors.len())
+        .map(|i| {
+            let others = vectors.len().saturating_sub(1).max(1) as f32;
+            let total: f32 = (0..vectors.len())
+                .filter(|&j| j != i)
+                .map(|j| cosine(&vectors[i], &vectors[j]))
+                .sum();
+            (total / others).max(0.0)
+        })
+        .collect();
+    Ok(rank_groups(candidates, groups, scores))
+}
+
+/// The last user message, which is what the candidates were answering.
+fn question(messages: &[ChatMessage]) -> &str {
+    messages
+        .iter()
+        .rev()
+        .find(|m| m.role == Role::User)
+        .map_or("", |m| m.content.as_str())
+}
+
+fn numbered(candidates: &[Candidate]) -> String {
+    candidates
+        .iter()
+        .enumerate()
+        .map(|(i, c)| format!("### Response {}\n{}\n", i + 1, c.content.trim()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn judge(
+    app: &AppHandle,
+    providers: &Providers,
+    client: &Client,
+    messages: &[ChatMessage],
+    candidates: &[Candidate],
+    choice: &ModelChoice,
+) -> Result<Ranking> {
+    let (provider_id, model) = resolve(providers, choice)?;
+    let prompt = format!(
+        "You are judging {n} answers to the same question. Score each for \
+         correctness, completeness and clarity from 0 to 10.\n\n\
+         ## Question\n{question}\n\n## Answers\n{answers}\n\
+         Reply with JSON only, in the form \
+         {{\"scores\": [{{\"response\": 1, \"score\": 7, \"reason\": \"...\"}}]}}.",
+        n = candidates.len(),
+        question = question(messages),
+        answers = numbered(candidates),
+    );
+    let request = ChatRequest {
+        provider: provider_id,
+        model,
+        messages: vec![ChatMessage {
+            role: Role::User,
+            content: prompt,
+        }],
+        temperature: Some(JUDGE_TEMPERATURE),
+        top_p: None,
+        max_tokens: None,
+        use_tools: false,
+        bypass_cache: false,
+        response_schema: None,
+        conversation_id: None,
+        tools: Vec::new(),
+        tool_rounds: Vec::new(),
+    };
+    let completion = providers
+        .get(&request.provider)?
+        .stream(client, &request, &mut |_| {})
+        .await?;
+    usage::record(app, &request, &completion.content, completion.usage);
+
+    let verdict: Value = serde_json::from_str(strip_fence(&completion.content)).map_err(|_| {
+        Error::Provider(format!(
+            "{} returned an unreadable verdict",
+            request.provider
+        ))
+    })?;
+    let mut scores: Vec<Option<(f32, Option<String>)>> = vec![None; candidates.len()];
+    for entry in verdict["scores"].as_array().into_iter().flatten() {
+        let index = entry["response"].as_u64().unwrap_or_default() as usize;
+        if let Some(slot) = index.checked_sub(1).and_then(|i| scores.get_mut(i)) {
+            let score = entry["score"].as_f64().unwrap_or_default().clamp(0.0, 10.0) as f32;
+            *slot = Some((score / 10.0, entry["reason"].as_str().map(str::to_string)));
+        }
+    }
+
+    let mut responses: Vec<RankedResponse> = candidates
+        .iter()
+        .zip(scores)
+        .enumerate()
+        .map(|(index, (candidate, verdict))| {
+            let (score, reason) = verdict.unwrap_or((0.0, None));
+            RankedResponse {
+                index,
+                provider: candidate.provider.clone(),
+                model: candidate.model.clone(),
+                score,
+                group: None,
+                reason,
+            }
+        })
+        .collect();
+    responses.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok(Ranking {
+        responses,
+        agreement: None,
+    })
+}
+
+#[tauri::command]
+pub async fn rank_responses(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    request: RankRequest,
+) -> Result<Ranking> {
+    match &request.strategy {
+        Strategy::MajorityVote => Ok(majority_vote(&request.candidates)),
+        Strategy::Similarity { embedding } => {
+            similarity(&providers, &client, &request.candidates, embedding).await
+        }
+        Strategy::Judge { judge: choice } => {
+            judge(
+                &app,
+                &providers,
+                &client,
+                &request.messages,
+                &request.candidates,
+                choice,
+            )
+            .await
+        }
+    }
+}
+
+/// Streams a single answer merged from the candidates, emitting `chat-token`
+/// under `request_id` like `stream_chat`.
+#[tauri::command]
+pub async fn synthesize_answer(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    requests: State<'_, Requests>,
+    request_id: String,
+    request: SynthesisRequest,
+) -> Result<ChatResponse> {
+    let (provider, model) = resolve(&providers, &request.synthesizer)?;
+    let mut messages = request.messages.clone();
+    messages.push(ChatMessage {
+        role: Role::User,
+        content: format!(
+            "Several assistants answered the question above. Write one answer \
+             that combines their strengths. Where they disagree, side with the \
+             better-supported claim and say so briefly. Don't mention the \
+             assistants or number the answers.\n\n{}",
+            numbered(&request.candidates)
+        ),
+    });
+    let chat = ChatRequest {
+        provider,
+        model,
+        messages,
+        temperature: request.temperature,
+        top_p: None,
+        max_tokens: request.max_tokens,
+        use_tools: false,
+        bypass_cache: false,
+        response_schema: None,
+        conversation_id: None,
+        tools: Vec::new(),
+        tool_rounds: Vec::new(),
+    };
+    let guard = requests.register(&request_id);
+    cancellable(
+        guard.token(),
+        llm::run_stream(&app, &providers, &client, &request_id, &chat),
+    )
+    .await
+}
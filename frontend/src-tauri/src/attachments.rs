@@ -0,0 +1,270 @@
+//! Files dropped onto a window. The drop is handled here rather than in the
+//! webview so large files never cross the JS bridge: each one is checked,
+//! copied into the profile's attachments directory under its SHA-256 (so the
+//! same file dropped twice is stored once) and announced with an
+//! `attachment-added` event. Files that can't be attached get an
+//! `attachment-rejected` event with the reason.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, DragDropEvent, Emitter, Manager, Window, WindowEvent};
+
+use crate::backup::{copy_hashed, hex};
+use crate::error::{Error, Result};
+use crate::ingest::{code_language, MAX_FILE_SIZE};
+use crate::profile;
+use crate::storage::now_ms;
+
+const DIR: &str = "attachments";
+/// Bytes read to tell a text file from a binary one.
+const SNIFF_LEN: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentKind {
+    Image,
+    /// Anything `extract_document` can read.
+    Document,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Attachment {
+    /// SHA-256 of the contents, hex.
+    pub id: String,
+    /// File name as dropped.
+    pub name: String,
+    /// The stored copy.
+    pub path: PathBuf,
+    pub source: PathBuf,
+    pub size: u64,
+    pub kind: AttachmentKind,
+    pub mime: &'static str,
+    /// The same contents were already stored.
+    pub duplicate: bool,
+    /// Label of the window the file was dropped on.
+    pub window: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Rejected {
+    path: PathBuf,
+    reason: String,
+    window: String,
+}
+
+pub(crate) fn image_mime(extension: &str) -> Option<&'static str> {
+    Some(match extension {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        _ => return None,
+    })
+}
+
+fn document_mime(extension: &str) -> Option<&'static str> {
+    Some(match extension {
+        "pdf" => "application/pdf",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "md" | "markdown" => "text/markdown",
+        "csv" => "text/csv",
+        "txt" | "log" | "json" | "yaml" | "yml" | "toml" | "xml" | "html" => "text/plain",
+        other => code_language(other).map(|_| "text/plain")?,
+    })
+}
+
+/// Whether the first bytes of a file match what its extension claims.
+fn matches_type(mime: &str, head: &[u8]) -> bool {
+    match mime {
+        "image/png" => head.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "image/jpeg" => head.starts_with(b"\xff\xd8\xff"),
+        "image/gif" => head.starts_with(b"GIF8"),
+        "image/webp" => head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP",
+        "application/pdf" => head.starts_with(b"%PDF"),
+        mime if mime.ends_with("wordprocessingml.document") => head.starts_with(b"PK\x03\x04"),
+        _ => !head.contains(&0),
+    }
+}
+
+/// Checks `source` and returns its kind and MIME type.
+fn validate(source: &Path) -> Result<(AttachmentKind, &'static str)> {
+    let reject = |reason: &str| Error::InvalidAttachment(format!("{}: {reason}", source.display()));
+    let metadata = std::fs::metadata(source)?;
+    if metadata.is_dir() {
+        return Err(reject("it is a folder"));
+    }
+    if metadata.len() > MAX_FILE_SIZE {
+        return Err(reject(&format!(
+            "it is larger than {} MB",
+            MAX_FILE_SIZE / 1024 / 1024
+        )));
+    }
+    let extension = source
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    let (kind, mime) = match image_mime(&extension) {
+        Some(mime) => (AttachmentKind::Image, mime),
+        None => match document_mime(&extension) {
+            Some(mime) => (AttachmentKind::Document, mime),
+            None => return Err(reject("the file type isn't supported")),
+        },
+    };
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    File::open(source)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    if !matches_type(mime, &head) {
+        return Err(reject("the contents don't match the file type"));
+    }
+    Ok((kind, mime))
+}
+
+/// Copies `source` into `dir` as `<sha256>.<extension>`, hashing on the way
+/// so the file is read once. A copy that is already there is kept.
+fn store(dir: &Path, source: &Path, window: &str) -> Result<Attachment> {
+    let (kind, mime) = validate(source)?;
+    std::fs::create_dir_all(dir)?;
+    let tmp = dir.join(format!(".incoming-{}-{}", std::process::id(), now_ms()));
+    let hashed = File::open(source)
+        .map_err(Error::from)
+        .and_then(|input| copy_hashed(input, File::create(&tmp)?));
+    let (size, id) = match hashed {
+        Ok(hashed) => hashed,
+        Err(err) => {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(err);
+        }
+    };
+    let mut path = dir.join(&id);
+    if let Some(extension) = source.extension() {
+        path.set_extension(extension.to_ascii_lowercase());
+    }
+    let duplicate = path.exists();
+    if duplicate {
+        std::fs::remove_file(&tmp)?;
+    } else {
+        std::fs::rename(&tmp, &path)?;
+    }
+    Ok(Attachment {
+        id,
+        name: source
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        path,
+        source: source.to_path_buf(),
+        size,
+        kind,
+        mime,
+        duplicate,
+        window: window.to_string(),
+    })
+}
+
+pub(crate) fn dir(app: &AppHandle) -> Result<PathBuf> {
+    Ok(profile::data_dir(app)?.join(DIR))
+}
+
+/// The stored file of the attachment `id`. Anything but a SHA-256 in hex is
+/// refused, so an id can't point outside the attachments directory.
+pub(crate) fn locate(app: &AppHandle, id: &str) -> Result<PathBuf> {
+    let id = id.to_ascii_lowercase();
+    if id.len() != 64 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(Error::NotFound(format!("attachment {id}")));
+    }
+    let entries = match std::fs::read_dir(dir(app)?) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::NotFound(format!("attachment {id}")))
+        }
+        Err(err) => return Err(err.into()),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.file_stem().and_then(|s| s.to_str()) == Some(id.as_str()) && path.is_file() {
+            return Ok(path);
+        }
+    }
+    Err(Error::NotFound(format!("attachment {id}")))
+}
+
+/// Stores contents made by the app itself, such as a generated image,
+/// next to the dropped files and under the same naming. Returns the id and
+/// the stored path.
+pub(crate) fn store_bytes(
+    app: &AppHandle,
+    bytes: &[u8],
+    extension: &str,
+) -> Result<(String, PathBuf)> {
+    let dir = dir(app)?;
+    std::fs::create_dir_all(&dir)?;
+    let id = hex(&Sha256::digest(bytes));
+    let path = dir.join(format!("{id}.{extension}"));
+    if !path.exists() {
+        let tmp = dir.join(format!(".incoming-{}-{}", std::process::id(), now_ms()));
+        std::fs::write(&tmp, bytes)?;
+        std::fs::rename(&tmp, &path)?;
+    }
+    Ok((id, path))
+}
+
+fn attach(app: &AppHandle, paths: &[PathBuf], window: &str) -> Result<Vec<Attachment>> {
+    let dir = dir(app)?;
+    let mut attached = Vec::new();
+    for source in paths {
+        match store(&dir, source, window) {
+            Ok(attachment) => {
+                let _ = app.emit("attachment-added", &attachment);
+                attached.push(attachment);
+            }
+            Err(err) => {
+                tracing::info!("rejected dropped file {}: {err}", source.display());
+                let _ = app.emit(
+                    "attachment-rejected",
+                    Rejected {
+                        path: source.clone(),
+                        reason: err.to_string(),
+                        window: window.to_string(),
+                    },
+                );
+            }
+        }
+    }
+    Ok(attached)
+}
+
+/// Takes over file drops; copying runs off the event loop.
+pub fn on_window_event(window: &Window, event: &WindowEvent) {
+    let WindowEvent::DragDrop(DragDropEvent::Drop { paths, .. }) = event else {
+        return;
+    };
+    let app = window.app_handle().clone();
+    let paths = paths.clone();
+    let label = window.label().to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        if let Err(err) = attach(&app, &paths, &label) {
+            tracing::warn!("couldn't store dropped files: {err}");
+        }
+    });
+}
+
+/// Attaches files picked some other way, such as from a file dialog, the
+/// same way as a drop. Rejected files are reported through events and left
+/// out of the result.
+#[tauri::command]
+pub async fn attach_files(
+    app: AppHandle,
+    window: Window,
+    paths: Vec<PathBuf>,
+) -> Result<Vec<Attachment>> {
+    let label = window.label().to_string();
+    tauri::async_runtime::spawn_blocking(move || attach(&app, &paths, &label))
+        .await
+        .map_err(|e| Error::InvalidAttachment(e.to_string()))?
+}
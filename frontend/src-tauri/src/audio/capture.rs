@@ -0,0 +1,92 @@
+//! Microphone input via cpal. The stream isn't `Send`, so it lives on a
+//! thread of its own until told to stop.
+
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, Sample, SampleFormat, SizedSample};
+
+use super::Capture;
+use crate::error::{Error, Result};
+
+/// Recording stops growing after this long, bounding memory use.
+const MAX_SECONDS: usize = 15 * 60;
+
+fn capture_error(err: impl std::fmt::Display) -> Error {
+    Error::Audio(err.to_string())
+}
+
+fn build<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    samples: Arc<Mutex<Vec<f32>>>,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample + Send + 'static,
+    f32: FromSample<T>,
+{
+    let channels = usize::from(config.channels).max(1);
+    let limit = config.sample_rate.0 as usize * MAX_SECONDS;
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], _| {
+                let mut samples = samples.lock().unwrap();
+                if samples.len() >= limit {
+                    return;
+                }
+                // Downmix to mono as it arrives.
+                samples.extend(data.chunks(channels).map(|frame| {
+                    frame.iter().map(|&s| f32::from_sample(s)).sum::<f32>() / frame.len() as f32
+                }));
+            },
+            |_| {},
+            None,
+        )
+        .map_err(capture_error)
+}
+
+/// Starts recording from the default input device into `samples`.
+pub fn start(samples: Arc<Mutex<Vec<f32>>>) -> Result<Capture> {
+    let (ready_tx, ready_rx) = mpsc::channel::<Result<u32>>();
+    let (stop_tx, stop_rx) = mpsc::channel::<()>();
+    std::thread::spawn(move || {
+        let started = (|| {
+            let device = cpal::default_host()
+                .default_input_device()
+                .ok_or_else(|| Error::Audio("no microphone found".into()))?;
+            let supported = device.default_input_config().map_err(capture_error)?;
+            let config = supported.config();
+            let stream = match supported.sample_format() {
+                SampleFormat::F32 => build::<f32>(&device, &config, samples),
+                SampleFormat::I16 => build::<i16>(&device, &config, samples),
+                SampleFormat::U16 => build::<u16>(&device, &config, samples),
+                SampleFormat::I32 => build::<i32>(&device, &config, samples),
+                format => Err(Error::Audio(format!("unsupported sample format {format}"))),
+            }?;
+            stream.play().map_err(capture_error)?;
+            Ok((stream, config.sample_rate.0))
+        })();
+        match started {
+            Ok((stream, sample_rate)) => {
+                let _ = ready_tx.send(Ok(sample_rate));
+                // Either an explicit stop or the handle being dropped ends it.
+                let _ = stop_rx.recv();
+                drop(stream);
+            }
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+            }
+        }
+    });
+    let sample_rate = ready_rx
+        .recv()
+        .map_err(|_| Error::Audio("the recording thread exited".into()))??;
+    Ok(Capture {
+        sample_rate,
+        stop: Box::new(move || {
+            let _ = stop_tx.send(());
+        }),
+    })
+}
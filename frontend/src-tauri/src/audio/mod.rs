@@ -0,0 +1,375 @@
+//! Voice prompting: records the microphone and transcribes it with a
+//! provider's speech-to-text endpoint. While recording, audio is sent in
+//! chunks cut at pauses, so a partial transcript can be shown as the user
+//! speaks; stopping transcribes whatever is left.
+
+#[cfg(feature = "voice")]
+mod capture;
+#[cfg(feature = "wake-word")]
+mod openwakeword;
+pub mod wake;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Emitter, State};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{Error, Result};
+use crate::providers::{Provider, Providers, TranscriptionRequest};
+use crate::storage::new_id;
+
+const DEFAULT_PROVIDER: &str = "openai";
+/// Speech-to-text models all want 16 kHz mono; sending more is wasted upload.
+const TARGET_RATE: u32 = 16_000;
+const CHUNK_INTERVAL: Duration = Duration::from_secs(3);
+/// Shorter chunks transcribe poorly and cost a request each.
+const MIN_CHUNK_SECONDS: f32 = 4.0;
+/// A chunk ends at the quietest point of its last second.
+const CUT_WINDOW_SECONDS: f32 = 1.0;
+/// Chunks quieter than this are skipped; models tend to invent words for
+/// silence.
+const SILENCE_RMS: f32 = 0.01;
+/// How much of the transcript so far is passed along as context.
+const PROMPT_CHARS: usize = 500;
+
+/// A running microphone capture.
+struct Capture {
+    sample_rate: u32,
+    stop: Box<dyn FnOnce() + Send>,
+}
+
+#[cfg(feature = "voice")]
+use capture::start as start_capture;
+
+#[cfg(not(feature = "voice"))]
+fn start_capture(_samples: Arc<Mutex<Vec<f32>>>) -> Result<Capture> {
+    Err(Error::Unsupported("voice input in this build".into()))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingOptions {
+    /// Defaults to OpenAI.
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub language: Option<String>,
+    /// Emit `transcript-partial` while recording. On by default.
+    pub partials: Option<bool>,
+}
+
+/// Payload of the `transcript-partial` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct PartialTranscript {
+    pub recording_id: String,
+    /// Everything transcribed so far.
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Transcript {
+    pub recording_id: String,
+    pub text: String,
+    pub duration_ms: u64,
+}
+
+#[derive(Clone)]
+struct Transcriber {
+    provider: Arc<dyn Provider>,
+    client: Client,
+    model: String,
+    language: Option<String>,
+}
+
+#[derive(Default)]
+struct Progress {
+    /// Samples already sent, at the capture rate.
+    transcribed: usize,
+    text: String,
+}
+
+struct Active {
+    id: String,
+    samples: Arc<Mutex<Vec<f32>>>,
+    sample_rate: u32,
+    stop: Box<dyn FnOnce() + Send>,
+    token: CancellationToken,
+    chunker: JoinHandle<Progress>,
+    transcriber: Transcriber,
+}
+
+/// The recording in progress, if any. Managed as Tauri state.
+#[derive(Default)]
+pub struct Recorder(Mutex<Option<Active>>);
+
+impl Recorder {
+    pub fn is_recording(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// Resamples by averaging each output sample's span of input, which doubles
+/// as the low-pass filter downsampling needs.
+pub(crate) fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let ratio = f64::from(from) / f64::from(to);
+    let len = (samples.len() as f64 / ratio) as usize;
+    (0..len)
+        .map(|i| {
+            let start = (i as f64 * ratio) as usize;
+            let end = (((i + 1) as f64 * ratio) as usize).clamp(start + 1, samples.len());
+            let span = &samples[start.min(samples.len() - 1)..end];
+            span.iter().sum::<f32>() / span.len() as f32
+        })
+        .collect()
+}
+
+/// 16-bit PCM mono WAV.
+fn wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    out.extend_from_slice(&1u16.to_le_bytes()); // mono
+    out.extend_from_slice(&sample_rate.to_le_bytes());
+    out.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    out.extend_from_slice(&2u16.to_le_bytes());
+    out.extend_from_slice(&16u16.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        out.extend_from_slice(
+            &((sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16).to_le_bytes(),
+        );
+    }
+    out
+}
+
+/// Where to end a chunk of `pending`: the start of the quietest 20 ms frame
+/// in its trailing window, so words aren't split.
+fn quietest_cut(pending: &[f32], sample_rate: u32) -> usize {
+    let frame = (sample_rate / 50).max(1) as usize;
+    let window = (CUT_WINDOW_SECONDS * sample_rate as f32) as usize;
+    let start = pending.len().saturating_sub(window);
+    (start..pending.len().saturating_sub(frame))
+        .step_by(frame)
+        .min_by(|&a, &b| rms(&pending[a..a + frame]).total_cmp(&rms(&pending[b..b + frame])))
+        .filter(|&cut| cut > 0)
+        .unwrap_or(pending.len())
+}
+
+/// The last `max` characters of `text`, starting on a word boundary.
+fn tail(text: &str, max: usize) -> &str {
+    let Some((start, _)) = text.char_indices().rev().nth(max) else {
+        return text;
+    };
+    let rest = &text[start..];
+    rest.split_once(' ').map_or(rest, |(_, words)| words)
+}
+
+fn append(transcript: &mut String, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    if !transcript.is_empty() {
+        transcript.push(' ');
+    }
+    transcript.push_str(text);
+}
+
+impl Transcriber {
+    fn resolve(providers: &Providers, client: &Client, options: &RecordingOptions) -> Result<Self> {
+        let id = options.provider.as_deref().unwrap_or(DEFAULT_PROVIDER);
+        let provider = providers.get(id)?;
+        let model = match &options.model {
+            Some(model) => model.clone(),
+            None => provider
+                .transcription_model()
+                .ok_or_else(|| Error::Unsupported(format!("{} transcription", provider.name())))?
+                .to_string(),
+        };
+        Ok(Self {
+            provider,
+            client: client.clone(),
+            model,
+            language: options.language.clone(),
+        })
+    }
+
+    async fn run(&self, samples: &[f32], sample_rate: u32, context: &str) -> Result<String> {
+        if rms(samples) < SILENCE_RMS {
+            return Ok(String::new());
+        }
+        let audio = wav(&resample(samples, sample_rate, TARGET_RATE), TARGET_RATE);
+        let prompt = tail(context, PROMPT_CHARS);
+        let request = TranscriptionRequest {
+            model: &self.model,
+            audio: &audio,
+            language: self.language.as_deref(),
+            prompt: (!prompt.is_empty()).then_some(prompt),
+        };
+        let text = self.provider.transcribe(&self.client, &request).await?;
+        Ok(text.trim().to_string())
+    }
+}
+
+/// Transcribes each stretch of at least `MIN_CHUNK_SECONDS` as it arrives,
+/// until cancelled. A failed chunk ends partials; its audio is picked up by
+/// the final pass instead.
+async fn transcribe_chunks(
+    app: AppHandle,
+    recording_id: String,
+    samples: Arc<Mutex<Vec<f32>>>,
+    sample_rate: u32,
+    transcriber: Transcriber,
+    token: CancellationToken,
+) -> Progress {
+    let mut progress = Progress::default();
+    let min_chunk = (MIN_CHUNK_SECONDS * sample_rate as f32) as usize;
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = tokio::time::sleep(CHUNK_INTERVAL) => {}
+        }
+        let pending = samples.lock().unwrap()[progress.transcribed..].to_vec();
+        if pending.len() < min_chunk {
+            continue;
+        }
+        let cut = quietest_cut(&pending, sample_rate);
+        let Ok(text) = transcriber
+            .run(&pending[..cut], sample_rate, &progress.text)
+            .await
+        else {
+            break;
+        };
+        progress.transcribed += cut;
+        if !text.is_empty() {
+            append(&mut progress.text, &text);
+            let _ = app.emit(
+                "transcript-partial",
+                PartialTranscript {
+                    recording_id: recording_id.clone(),
+                    text: progress.text.clone(),
+                },
+            );
+        }
+    }
+    progress
+}
+
+/// Starts recording from the default microphone and returns the recording
+/// id used in `transcript-partial` events.
+#[tauri::command]
+pub async fn start_recording(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    recorder: State<'_, Recorder>,
+    options: Option<RecordingOptions>,
+) -> Result<String> {
+    start(
+        app,
+        &providers,
+        &client,
+        &recorder,
+        options.unwrap_or_default(),
+    )
+}
+
+pub(crate) fn start(
+    app: AppHandle,
+    providers: &Providers,
+    client: &Client,
+    recorder: &Recorder,
+    options: RecordingOptions,
+) -> Result<String> {
+    let transcriber = Transcriber::resolve(providers, client, &options)?;
+    let mut active = recorder.0.lock().unwrap();
+    if active.is_some() {
+        return Err(Error::Audio("already recording".into()));
+    }
+
+    let samples: Arc<Mutex<Vec<f32>>> = Arc::default();
+    let capture = start_capture(samples.clone())?;
+    let id = new_id();
+    let token = CancellationToken::new();
+    let chunker = if options.partials.unwrap_or(true) {
+        tauri::async_runtime::spawn(transcribe_chunks(
+            app,
+            id.clone(),
+            samples.clone(),
+            capture.sample_rate,
+            transcriber.clone(),
+            token.clone(),
+        ))
+    } else {
+        tauri::async_runtime::spawn(async { Progress::default() })
+    };
+    *active = Some(Active {
+        id: id.clone(),
+        samples,
+        sample_rate: capture.sample_rate,
+        stop: capture.stop,
+        token,
+        chunker,
+        transcriber,
+    });
+    Ok(id)
+}
+
+/// Stops recording and returns the full transcript.
+#[tauri::command]
+pub async fn stop_recording(recorder: State<'_, Recorder>) -> Result<Transcript> {
+    let active = recorder
+        .0
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or_else(|| Error::NotFound("recording".into()))?;
+    (active.stop)();
+    active.token.cancel();
+    let mut progress = active
+        .chunker
+        .await
+        .map_err(|e| Error::Audio(e.to_string()))?;
+
+    let (rest, total) = {
+        let samples = active.samples.lock().unwrap();
+        (samples[progress.transcribed..].to_vec(), samples.len())
+    };
+    let text = active
+        .transcriber
+        .run(&rest, active.sample_rate, &progress.text)
+        .await?;
+    append(&mut progress.text, &text);
+    Ok(Transcript {
+        recording_id: active.id,
+        text: progress.text,
+        duration_ms: total as u64 * 1000 / u64::from(active.sample_rate.max(1)),
+    })
+}
+
+/// Stops recording and throws the audio away.
+#[tauri::command]
+pub async fn cancel_recording(recorder: State<'_, Recorder>) -> Result<()> {
+    if let Some(active) = recorder.0.lock().unwrap().take() {
+        (active.stop)();
+        active.chunker.abort();
+    }
+    Ok(())
+}
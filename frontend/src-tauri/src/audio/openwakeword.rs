@@ -0,0 +1,139 @@
+//! openWakeWord models run through ONNX Runtime. Audio goes through the
+//! shared melspectrogram and embedding models in 80 ms steps, and the wake
+//! word's own model scores the last 16 embeddings.
+
+use std::collections::VecDeque;
+use std::path::Path;
+
+use ort::session::Session;
+use ort::value::Tensor;
+
+use crate::error::{Error, Result};
+
+const MEL_MODEL: &str = "melspectrogram.onnx";
+const EMBEDDING_MODEL: &str = "embedding_model.onnx";
+/// 80 ms at 16 kHz.
+const STEP: usize = 1280;
+/// Three 10 ms hops carried over from the previous step.
+const MEL_CONTEXT: usize = 480;
+const MEL_BINS: usize = 32;
+/// Melspectrogram frames per embedding.
+const EMBEDDING_WINDOW: usize = 76;
+/// Embeddings per score.
+const FEATURE_WINDOW: usize = 16;
+
+fn model_error(err: ort::Error) -> Error {
+    Error::Audio(format!("wake word model: {err}"))
+}
+
+fn open(path: &Path) -> Result<Session> {
+    if !path.exists() {
+        return Err(Error::NotFound(format!(
+            "wake word model {}",
+            path.display()
+        )));
+    }
+    Session::builder()
+        .and_then(|builder| builder.commit_from_file(path))
+        .map_err(model_error)
+}
+
+pub struct Detector {
+    mel: Session,
+    embedding: Session,
+    wake: Session,
+    /// Samples not yet making up a step, at 16-bit scale.
+    pending: Vec<f32>,
+    /// The tail of the previous step.
+    context: Vec<f32>,
+    mels: VecDeque<[f32; MEL_BINS]>,
+    features: VecDeque<Vec<f32>>,
+}
+
+impl Detector {
+    pub fn load(model: &Path) -> Result<Self> {
+        let dir = model.parent().unwrap_or(Path::new("."));
+        Ok(Self {
+            mel: open(&dir.join(MEL_MODEL))?,
+            embedding: open(&dir.join(EMBEDDING_MODEL))?,
+            wake: open(model)?,
+            pending: Vec::new(),
+            context: Vec::new(),
+            mels: VecDeque::new(),
+            features: VecDeque::new(),
+        })
+    }
+
+    /// Feeds 16 kHz mono samples and returns the highest score among the
+    /// steps they completed.
+    pub fn push(&mut self, samples: &[f32]) -> Result<f32> {
+        // The models were trained on 16-bit samples.
+        self.pending
+            .extend(samples.iter().map(|s| s * f32::from(i16::MAX)));
+        let mut best: f32 = 0.0;
+        while self.pending.len() >= STEP {
+            let mut audio = std::mem::take(&mut self.context);
+            audio.extend(self.pending.drain(..STEP));
+            self.context = audio[audio.len() - MEL_CONTEXT..].to_vec();
+            self.push_mels(audio)?;
+            if let Some(score) = self.score()? {
+                best = best.max(score);
+            }
+        }
+        Ok(best)
+    }
+
+    fn push_mels(&mut self, audio: Vec<f32>) -> Result<()> {
+        let input = Tensor::from_array(([1, audio.len()], audio)).map_err(model_error)?;
+        let outputs = self.mel.run(ort::inputs![input]).map_err(model_error)?;
+        let (_, frames) = outputs[0]
+            .try_extract_tensor::<f32>()
+            .map_err(model_error)?;
+        for frame in frames.chunks_exact(MEL_BINS) {
+            let mut mel = [0.0; MEL_BINS];
+            // The scaling openWakeWord applies before the embedding model.
+            for (out, value) in mel.iter_mut().zip(frame) {
+                *out = value / 10.0 + 2.0;
+            }
+            self.mels.push_back(mel);
+        }
+        while self.mels.len() > EMBEDDING_WINDOW {
+            self.mels.pop_front();
+        }
+        Ok(())
+    }
+
+    fn score(&mut self) -> Result<Option<f32>> {
+        if self.mels.len() < EMBEDDING_WINDOW {
+            return Ok(None);
+        }
+        let window: Vec<f32> = self.mels.iter().flatten().copied().collect();
+        let input = Tensor::from_array(([1, EMBEDDING_WINDOW, MEL_BINS, 1], window))
+            .map_err(model_error)?;
+        let outputs = self
+            .embedding
+            .run(ort::inputs![input])
+            .map_err(model_error)?;
+        let (_, embedding) = outputs[0]
+            .try_extract_tensor::<f32>()
+            .map_err(model_error)?;
+        let width = embedding.len();
+        self.features.push_back(embedding.to_vec());
+        drop(outputs);
+        while self.features.len() > FEATURE_WINDOW {
+            self.features.pop_front();
+        }
+        if self.features.len() < FEATURE_WINDOW {
+            return Ok(None);
+        }
+
+        let features: Vec<f32> = self.features.iter().flatten().copied().collect();
+        let input =
+            Tensor::from_array(([1, FEATURE_WINDOW, width], features)).map_err(model_error)?;
+        let outputs = self.wake.run(ort::inputs![input]).map_err(model_error)?;
+        let (_, scores) = outputs[0]
+            .try_extract_tensor::<f32>()
+            .map_err(model_error)?;
+        Ok(scores.first().copied())
+    }
+}
@@ -0,0 +1,348 @@
+//! Hands-free prompting: an optional listener that waits for a wake word,
+//! then opens the quick-prompt window and starts a recording. Detection runs
+//! on-device with openWakeWord models; nothing is sent anywhere until the
+//! word is heard.
+//!
+//! Listening is off until turned on. While it runs only the last moment of
+//! audio is held in memory, the microphone is released whenever listening
+//! is paused or another recording has it, and the tray shows a mic whenever
+//! it is open.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::Notify;
+
+use super::{resample, start_capture, Capture, Recorder, RecordingOptions, TARGET_RATE};
+use crate::config;
+use crate::error::{Error, Result};
+use crate::hotkey;
+use crate::providers::Providers;
+use crate::storage::now_ms;
+use crate::tray;
+
+const CONFIG_FILE: &str = "wake.json";
+const TICK: Duration = Duration::from_millis(100);
+/// Quiet time after a detection that doesn't start a recording, so the
+/// same utterance isn't heard twice.
+const COOLDOWN: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WakeConfig {
+    pub enabled: bool,
+    /// An openWakeWord model such as `hey_jarvis.onnx`. The shared
+    /// `melspectrogram.onnx` and `embedding_model.onnx` are looked for in
+    /// the same folder.
+    pub model: Option<PathBuf>,
+    /// Score from 0 to 1 a frame needs to count as the wake word.
+    pub threshold: f32,
+    /// Start recording once woken; otherwise only the window opens.
+    pub transcribe: bool,
+    pub recording: RecordingOptions,
+}
+
+impl Default for WakeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            model: None,
+            threshold: 0.5,
+            transcribe: true,
+            recording: RecordingOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WakeStatus {
+    /// The microphone is open and being listened to.
+    pub listening: bool,
+    /// Listening is paused until then; `i64::MAX` means until resumed.
+    pub paused_until: Option<i64>,
+    pub last_detected_at: Option<i64>,
+    /// Why listening couldn't start, until the settings change.
+    pub error: Option<String>,
+}
+
+/// Payload of the `wake-word-detected` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct WakeDetected {
+    pub score: f32,
+    /// Set when a recording was started.
+    pub recording_id: Option<String>,
+}
+
+/// Managed as Tauri state.
+pub struct Wake {
+    config: Mutex<WakeConfig>,
+    status: Mutex<WakeStatus>,
+    /// Wakes the listener when the settings or pause change.
+    changed: Notify,
+}
+
+#[cfg(feature = "wake-word")]
+use super::openwakeword::Detector;
+
+#[cfg(not(feature = "wake-word"))]
+struct Detector;
+
+#[cfg(not(feature = "wake-word"))]
+impl Detector {
+    fn load(_model: &std::path::Path) -> Result<Self> {
+        Err(Error::Unsupported(
+            "wake word detection in this build".into(),
+        ))
+    }
+
+    fn push(&mut self, _samples: &[f32]) -> Result<f32> {
+        Ok(0.0)
+    }
+}
+
+/// An open microphone and the detector it feeds.
+struct Session {
+    model: PathBuf,
+    samples: Arc<Mutex<Vec<f32>>>,
+    capture: Capture,
+    detector: Detector,
+}
+
+impl Session {
+    fn start(config: &WakeConfig) -> Result<Self> {
+        let model = config
+            .model
+            .clone()
+            .ok_or_else(|| Error::InvalidSetting("no wake word model is set".into()))?;
+        let detector = Detector::load(&model)?;
+        let samples: Arc<Mutex<Vec<f32>>> = Arc::default();
+        let capture = start_capture(samples.clone())?;
+        Ok(Self {
+            model,
+            samples,
+            capture,
+            detector,
+        })
+    }
+
+    /// Scores the audio since the last call. The samples are dropped as
+    /// they are read.
+    fn score(&mut self) -> Result<f32> {
+        let pending = std::mem::take(&mut *self.samples.lock().unwrap());
+        let audio = resample(&pending, self.capture.sample_rate, TARGET_RATE);
+        self.detector.push(&audio)
+    }
+
+    fn stop(self) {
+        (self.capture.stop)();
+    }
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<WakeConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(Wake {
+        config: Mutex::new(config),
+        status: Mutex::default(),
+        changed: Notify::new(),
+    });
+    tauri::async_runtime::spawn(listen(app.clone()));
+}
+
+pub fn enabled(app: &AppHandle) -> bool {
+    app.try_state::<Wake>()
+        .is_some_and(|wake| wake.config.lock().unwrap().enabled)
+}
+
+fn update_status(app: &AppHandle, update: impl FnOnce(&mut WakeStatus)) {
+    let wake = app.state::<Wake>();
+    let status = {
+        let mut status = wake.status.lock().unwrap();
+        update(&mut status);
+        status.clone()
+    };
+    let _ = app.emit("wake-status", status);
+}
+
+fn set_listening(app: &AppHandle, listening: bool) {
+    tray::set_listening(app, listening);
+    update_status(app, |status| status.listening = listening);
+}
+
+/// Whether the microphone may be open right now.
+fn wanted(app: &AppHandle, config: &WakeConfig) -> bool {
+    let paused = {
+        let wake = app.state::<Wake>();
+        let mut status = wake.status.lock().unwrap();
+        match status.paused_until {
+            Some(until) if until > now_ms() => true,
+            Some(_) => {
+                status.paused_until = None;
+                false
+            }
+            None => false,
+        }
+    };
+    config.enabled && !paused && !app.state::<Recorder>().is_recording()
+}
+
+fn on_wake(app: &AppHandle, config: &WakeConfig, score: f32) -> Option<String> {
+    if let Err(err) = hotkey::show_quick_window(app) {
+        tracing::warn!("couldn't open the quick prompt: {err}");
+    }
+    let recording_id = config
+        .transcribe
+        .then(|| {
+            super::start(
+                app.clone(),
+                &app.state::<Providers>(),
+                &app.state::<Client>(),
+                &app.state::<Recorder>(),
+                config.recording.clone(),
+            )
+        })
+        .and_then(|started| {
+            started
+                .map_err(|err| tracing::warn!("couldn't start recording: {err}"))
+                .ok()
+        });
+    update_status(app, |status| status.last_detected_at = Some(now_ms()));
+    let _ = app.emit(
+        "wake-word-detected",
+        WakeDetected {
+            score,
+            recording_id: recording_id.clone(),
+        },
+    );
+    recording_id
+}
+
+/// Opens and closes the microphone as settings, pauses and recordings come
+/// and go, and scores what it hears.
+async fn listen(app: AppHandle) {
+    let mut session: Option<Session> = None;
+    // Settings that failed to start, so they aren't retried every tick.
+    let mut failed: Option<WakeConfig> = None;
+    let wake = app.state::<Wake>();
+    loop {
+        tokio::select! {
+            _ = wake.changed.notified() => {}
+            _ = tokio::time::sleep(TICK) => {}
+        }
+        let config = wake.config.lock().unwrap().clone();
+        let stale = session
+            .as_ref()
+            .is_some_and(|s| Some(&s.model) != config.model.as_ref());
+        let wanted = wanted(&app, &config);
+        if stale || !wanted {
+            if let Some(session) = session.take() {
+                session.stop();
+                set_listening(&app, false);
+            }
+        }
+        if !wanted {
+            continue;
+        }
+        if session.is_none() {
+            if failed.as_ref() == Some(&config) {
+                continue;
+            }
+            match Session::start(&config) {
+                Ok(started) => {
+                    failed = None;
+                    session = Some(started);
+                    update_status(&app, |status| status.error = None);
+                    set_listening(&app, true);
+                }
+                Err(err) => {
+                    tracing::warn!("couldn't listen for the wake word: {err}");
+                    update_status(&app, |status| status.error = Some(err.to_string()));
+                    failed = Some(config);
+                    continue;
+                }
+            }
+        }
+        let Some(active) = session.as_mut() else {
+            continue;
+        };
+        match active.score() {
+            Ok(score) if score >= config.threshold => {
+                if let Some(session) = session.take() {
+                    session.stop();
+                }
+                set_listening(&app, false);
+                if on_wake(&app, &config, score).is_none() {
+                    tokio::time::sleep(COOLDOWN).await;
+                }
+            }
+            Ok(_) => {}
+            Err(err) => {
+                tracing::warn!("wake word detection failed: {err}");
+                if let Some(session) = session.take() {
+                    session.stop();
+                }
+                set_listening(&app, false);
+                update_status(&app, |status| status.error = Some(err.to_string()));
+                failed = Some(config);
+            }
+        }
+    }
+}
+
+fn apply(app: &AppHandle, config: WakeConfig) -> Result<WakeConfig> {
+    if !(0.0..=1.0).contains(&config.threshold) {
+        return Err(Error::InvalidSetting(
+            "wake word threshold must be between 0 and 1".into(),
+        ));
+    }
+    config::write(app, CONFIG_FILE, &config)?;
+    let wake = app.state::<Wake>();
+    *wake.config.lock().unwrap() = config.clone();
+    wake.changed.notify_one();
+    tray::set_wake_word_enabled(app, config.enabled);
+    Ok(config)
+}
+
+/// Turns listening on or off, for the tray menu.
+pub fn toggle(app: &AppHandle) -> Result<()> {
+    let mut config = app.state::<Wake>().config.lock().unwrap().clone();
+    config.enabled = !config.enabled;
+    apply(app, config).map(|_| ())
+}
+
+#[tauri::command]
+pub fn get_wake_config(wake: State<'_, Wake>) -> WakeConfig {
+    wake.config.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn set_wake_config(app: AppHandle, config: WakeConfig) -> Result<WakeConfig> {
+    apply(&app, config)
+}
+
+#[tauri::command]
+pub fn get_wake_status(wake: State<'_, Wake>) -> WakeStatus {
+    wake.status.lock().unwrap().clone()
+}
+
+/// Closes the microphone for `minutes`, or until resumed when unset.
+#[tauri::command]
+pub fn pause_wake_word(app: AppHandle, minutes: Option<u32>) -> WakeStatus {
+    let until = minutes.map_or(i64::MAX, |m| now_ms() + i64::from(m) * 60_000);
+    update_status(&app, |status| status.paused_until = Some(until));
+    app.state::<Wake>().changed.notify_one();
+    app.state::<Wake>().status.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn resume_wake_word(app: AppHandle) -> WakeStatus {
+    update_status(&app, |status| status.paused_until = None);
+    app.state::<Wake>().changed.notify_one();
+    app.state::<Wake>().status.lock().unwrap().clone()
+}
@@ -0,0 +1,434 @@
+//! A record of every request sent to a provider: when, where to, which
+//! model, the headers (secrets replaced), how big the body was, and how it
+//! went. Bodies themselves aren't kept. Rows are only ever added; the
+//! table refuses updates, and old rows go only when they pass the
+//! retention period.
+//!
+//! Shell commands run for models are recorded here too, so everything
+//! the app did on the user's behalf is in one place.
+//!
+//! Providers have no app handle, so requests are recorded through a
+//! channel that a task started in `init` drains into the database.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use reqwest::{RequestBuilder, Response, Url};
+use rusqlite::types::Type;
+use rusqlite::{params, Row};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::mpsc;
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::storage::{now_ms, Database};
+
+const CONFIG_FILE: &str = "audit.json";
+const PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+const REDACTED: &str = "[redacted]";
+/// Headers that carry credentials.
+const SECRET_HEADERS: [&str; 6] = [
+    "authorization",
+    "proxy-authorization",
+    "x-api-key",
+    "x-goog-api-key",
+    "api-key",
+    "cookie",
+];
+/// Query parameters that carry credentials, as Gemini's `key` does.
+const SECRET_PARAMS: [&str; 4] = ["key", "api_key", "token", "access_token"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// Rows older than this many days are deleted; `None` keeps them all.
+    pub retention_days: Option<u32>,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: Some(90),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub provider: String,
+    pub model: Option<String>,
+    pub method: String,
+    /// The URL, with credentials in the query replaced.
+    pub endpoint: String,
+    pub request_headers: BTreeMap<String, String>,
+    pub request_bytes: Option<u64>,
+    /// The response status; `None` when no response came back.
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    /// Until the response headers arrived.
+    pub latency_ms: u64,
+    pub created_at: i64,
+}
+
+impl AuditEntry {
+    const COLUMNS: &'static str = "id, provider, model, method, endpoint, request_headers, \
+                                   request_bytes, status, error, latency_ms, created_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let headers: String = row.get(5)?;
+        Ok(Self {
+            id: row.get(0)?,
+            provider: row.get(1)?,
+            model: row.get(2)?,
+            method: row.get(3)?,
+            endpoint: row.get(4)?,
+            request_headers: serde_json::from_str(&headers).map_err(|err| {
+                rusqlite::Error::FromSqlConversionFailure(5, Type::Text, Box::new(err))
+            })?,
+            request_bytes: row.get(6)?,
+            status: row.get(7)?,
+            error: row.get(8)?,
+            latency_ms: row.get(9)?,
+            created_at: row.get(10)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditFormat {
+    Csv,
+    /// One JSON object per line.
+    Jsonl,
+}
+
+/// Narrows `list_audit_log` and `export_audit_log`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AuditFilter {
+    pub provider: Option<String>,
+    /// Timestamps in milliseconds, inclusive.
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+impl Database {
+    fn insert_audit_entry(&self, entry: &AuditEntry) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO audit_log (provider, model, method, endpoint, request_headers,
+                                    request_bytes, status, error, latency_ms, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                entry.provider,
+                entry.model,
+                entry.method,
+                entry.endpoint,
+                serde_json::to_string(&entry.request_headers)?,
+                entry.request_bytes,
+                entry.status,
+                entry.error,
+                entry.latency_ms,
+                entry.created_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Newest first.
+    pub fn list_audit_log(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM audit_log
+             WHERE (?1 IS NULL OR provider = ?1)
+               AND (?2 IS NULL OR created_at >= ?2)
+               AND (?3 IS NULL OR created_at <= ?3)
+             ORDER BY id DESC
+             LIMIT ?4",
+            AuditEntry::COLUMNS
+        ))?;
+        let entries = stmt
+            .query_map(
+                params![
+                    filter.provider,
+                    filter.since,
+                    filter.until,
+                    filter.limit.map_or(-1, i64::from),
+                ],
+                AuditEntry::from_row,
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(entries)
+    }
+
+    fn prune_audit_log(&self, before: i64) -> Result<usize> {
+        Ok(self
+            .conn()?
+            .execute("DELETE FROM audit_log WHERE created_at < ?1", [before])?)
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SINK: OnceLock<mpsc::UnboundedSender<AuditEntry>> = OnceLock::new();
+
+/// Managed as Tauri state.
+pub struct Audit {
+    config: Mutex<AuditConfig>,
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<AuditConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    ENABLED.store(config.enabled, Ordering::Relaxed);
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let _ = SINK.set(sender);
+    app.manage(Audit {
+        config: Mutex::new(config),
+    });
+    tauri::async_runtime::spawn(write_entries(app.clone(), receiver));
+}
+
+/// Writes entries as they arrive, pruning once a day.
+async fn write_entries(app: AppHandle, mut receiver: mpsc::UnboundedReceiver<AuditEntry>) {
+    let db = app.state::<Database>();
+    let mut prune = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        tokio::select! {
+            entry = receiver.recv() => {
+                let Some(entry) = entry else { return };
+                if let Err(err) = db.insert_audit_entry(&entry) {
+                    tracing::warn!("couldn't record a request to {}: {err}", entry.provider);
+                }
+            }
+            _ = prune.tick() => {
+                if let Err(err) = apply_retention(&app) {
+                    tracing::warn!("couldn't prune the audit log: {err}");
+                }
+            }
+        }
+    }
+}
+
+fn apply_retention(app: &AppHandle) -> Result<usize> {
+    let retention = app.state::<Audit>().config.lock().unwrap().retention_days;
+    match retention {
+        Some(days) => app
+            .state::<Database>()
+            .prune_audit_log(now_ms() - i64::from(days) * DAY_MS),
+        None => Ok(0),
+    }
+}
+
+fn redact_url(url: &Url) -> String {
+    let mut url = url.clone();
+    if url.query().is_some() {
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(name, value)| {
+                let secret = SECRET_PARAMS.contains(&name.to_ascii_lowercase().as_str());
+                let value = if secret {
+                    REDACTED.into()
+                } else {
+                    value.into_owned()
+                };
+                (name.into_owned(), value)
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    let _ = url.set_password(None);
+    url.to_string()
+}
+
+/// Records a shell command run for a model, or one refused before it ran,
+/// alongside the provider requests: `shell` stands in for the provider,
+/// `EXEC` for the method and the command for the endpoint, and the exit
+/// code is the status.
+pub fn record_command(
+    command: &str,
+    cwd: Option<&str>,
+    request_id: &str,
+    exit_code: Option<i32>,
+    error: Option<String>,
+    duration: Duration,
+) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut request_headers = BTreeMap::new();
+    request_headers.insert("request-id".to_string(), request_id.to_string());
+    if let Some(cwd) = cwd {
+        request_headers.insert("cwd".to_string(), cwd.to_string());
+    }
+    let entry = AuditEntry {
+        id: 0,
+        provider: "shell".into(),
+        model: None,
+        method: "EXEC".into(),
+        endpoint: command.to_string(),
+        request_headers,
+        request_bytes: Some(command.len() as u64),
+        status: exit_code.and_then(|code| u16::try_from(code).ok()),
+        error,
+        latency_ms: duration.as_millis() as u64,
+        created_at: now_ms(),
+    };
+    if let Some(sink) = SINK.get() {
+        let _ = sink.send(entry);
+    }
+}
+
+/// Sends `request` for `provider` and records it. Providers use this in
+/// place of `RequestBuilder::send`.
+pub async fn send(
+    provider: &str,
+    model: Option<&str>,
+    request: RequestBuilder,
+) -> Result<Response> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Ok(client.execute(request).await?);
+    }
+    let request_headers = request
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            let value = if SECRET_HEADERS.contains(&name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value)
+        })
+        .collect();
+    let mut entry = AuditEntry {
+        id: 0,
+        provider: provider.to_string(),
+        model: model.map(str::to_string),
+        method: request.method().to_string(),
+        endpoint: redact_url(request.url()),
+        request_headers,
+        request_bytes: request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(|bytes| bytes.len() as u64),
+        status: None,
+        error: None,
+        latency_ms: 0,
+        created_at: now_ms(),
+    };
+    let started = Instant::now();
+    // The URL can hold a key, so it is left out of the error.
+    let outcome = client.execute(request).await.map_err(|e| e.without_url());
+    entry.latency_ms = started.elapsed().as_millis() as u64;
+    match &outcome {
+        Ok(response) => entry.status = Some(response.status().as_u16()),
+        Err(err) => entry.error = Some(err.to_string()),
+    }
+    if let Some(sink) = SINK.get() {
+        let _ = sink.send(entry);
+    }
+    Ok(outcome?)
+}
+
+#[tauri::command]
+pub fn get_audit_config(audit: State<'_, Audit>) -> AuditConfig {
+    audit.config.lock().unwrap().clone()
+}
+
+/// Replaces the settings and applies a shorter retention right away.
+#[tauri::command]
+pub fn set_audit_config(
+    app: AppHandle,
+    audit: State<'_, Audit>,
+    config: AuditConfig,
+) -> Result<AuditConfig> {
+    if config.retention_days == Some(0) {
+        return Err(Error::InvalidSetting(
+            "audit retention must be at least a day".into(),
+        ));
+    }
+    config::write(&app, CONFIG_FILE, &config)?;
+    ENABLED.store(config.enabled, Ordering::Relaxed);
+    *audit.config.lock().unwrap() = config.clone();
+    apply_retention(&app)?;
+    Ok(config)
+}
+
+#[tauri::command]
+pub fn list_audit_log(db: State<'_, Database>, filter: AuditFilter) -> Result<Vec<AuditEntry>> {
+    db.list_audit_log(&filter)
+}
+
+/// Writes the matching entries, oldest first, and returns how many.
+#[tauri::command]
+pub fn export_audit_log(
+    db: State<'_, Database>,
+    path: PathBuf,
+    format: AuditFormat,
+    filter: Option<AuditFilter>,
+) -> Result<usize> {
+    let mut entries = db.list_audit_log(&filter.unwrap_or_default())?;
+    entries.reverse();
+    match format {
+        AuditFormat::Jsonl => {
+            let mut out = String::new();
+            for entry in &entries {
+                out.push_str(&serde_json::to_string(entry)?);
+                out.push('\n');
+            }
+            std::fs::write(&path, out)?;
+        }
+        AuditFormat::Csv => {
+            let mut writer = csv::Writer::from_path(&path).map_err(std::io::Error::from)?;
+            writer
+                .write_record([
+                    "id",
+                    "created_at",
+                    "provider",
+                    "model",
+                    "method",
+                    "endpoint",
+                    "status",
+                    "error",
+                    "latency_ms",
+                    "request_bytes",
+                    "request_headers",
+                ])
+                .map_err(std::io::Error::from)?;
+            for entry in &entries {
+                writer
+                    .write_record([
+                        entry.id.to_string(),
+                        entry.created_at.to_string(),
+                        entry.provider.clone(),
+                        entry.model.clone().unwrap_or_default(),
+                        entry.method.clone(),
+                        entry.endpoint.clone(),
+                        entry.status.map(|s| s.to_string()).unwrap_or_default(),
+                        entry.error.clone().unwrap_or_default(),
+                        entry.latency_ms.to_string(),
+                        entry
+                            .request_bytes
+                            .map(|b| b.to_string())
+                            .unwrap_or_default(),
+                        serde_json::to_string(&entry.request_headers)?,
+                    ])
+                    .map_err(std::io::Error::from)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(entries.len())
+}
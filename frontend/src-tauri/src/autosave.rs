@@ -0,0 +1,204 @@
+//! Streamed answers written to disk as they arrive, so a crash or forced
+//! quit mid-generation doesn't lose what the model had already said. Each
+//! stream gets a log in the data directory: a header line, then one JSON
+//! string per delta, synced now and then. A stream that finishes, fails or
+//! is stopped removes its log; one still present on the next launch was cut
+//! off, and `recover_partial_responses` hands it to the frontend.
+//!
+//! Nothing is logged while the database is encrypted, since the logs would
+//! keep its contents in the clear.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::error::Result;
+use crate::incognito;
+use crate::llm::ChatRequest;
+use crate::profile;
+use crate::storage::{new_id, now_ms, Database};
+
+const DIR: &str = "partial";
+/// How often a log is flushed to the disk itself rather than just the OS.
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Logs of streams running now, which recovery leaves alone.
+static ACTIVE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Header {
+    request_id: String,
+    provider: String,
+    model: String,
+    conversation_id: Option<String>,
+    started_at: i64,
+}
+
+/// An answer that was still streaming when the app last stopped.
+#[derive(Debug, Clone, Serialize)]
+pub struct PartialResponse {
+    pub request_id: String,
+    pub provider: String,
+    pub model: String,
+    pub conversation_id: Option<String>,
+    pub content: String,
+    pub started_at: i64,
+    /// When the last delta was written.
+    pub updated_at: i64,
+}
+
+fn dir(app: &AppHandle) -> Result<PathBuf> {
+    Ok(profile::data_dir(app)?.join(DIR))
+}
+
+/// The log of one stream, removed when dropped.
+pub(crate) struct Log {
+    path: PathBuf,
+    /// `None` once a write has failed; the stream goes on without it.
+    file: Option<File>,
+    synced: Instant,
+}
+
+impl Log {
+    /// Starts a log for `request`, or gives `None` if it can't or shouldn't
+    /// be kept.
+    pub(crate) fn begin(app: &AppHandle, request_id: &str, request: &ChatRequest) -> Option<Self> {
+        if app
+            .try_state::<Database>()
+            .is_none_or(|db| db.is_encrypted())
+            || incognito::is_ephemeral_request(request.conversation_id.as_deref())
+        {
+            return None;
+        }
+        let header = Header {
+            request_id: request_id.to_string(),
+            provider: request.provider.clone(),
+            model: request.model.clone(),
+            conversation_id: request.conversation_id.clone(),
+            started_at: now_ms(),
+        };
+        let started = (|| -> Result<(PathBuf, File)> {
+            let dir = dir(app)?;
+            std::fs::create_dir_all(&dir)?;
+            let path = dir.join(format!("{}.log", new_id()));
+            let mut file = File::create(&path)?;
+            let mut line = serde_json::to_vec(&header)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+            Ok((path, file))
+        })();
+        match started {
+            Ok((path, file)) => {
+                ACTIVE.lock().unwrap().push(path.clone());
+                Some(Self {
+                    path,
+                    file: Some(file),
+                    synced: Instant::now(),
+                })
+            }
+            Err(err) => {
+                tracing::warn!("couldn't start the autosave log: {err}");
+                None
+            }
+        }
+    }
+
+    pub(crate) fn append(&mut self, delta: &str) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        let mut line = serde_json::to_vec(delta).unwrap_or_default();
+        line.push(b'\n');
+        let mut written = file.write_all(&line);
+        if written.is_ok() && self.synced.elapsed() >= SYNC_INTERVAL {
+            written = file.sync_data();
+            self.synced = Instant::now();
+        }
+        if let Err(err) = written {
+            tracing::warn!("couldn't write the autosave log: {err}");
+            self.file = None;
+        }
+    }
+}
+
+impl Drop for Log {
+    fn drop(&mut self) {
+        self.file = None;
+        let _ = std::fs::remove_file(&self.path);
+        ACTIVE.lock().unwrap().retain(|p| p != &self.path);
+    }
+}
+
+/// Reads a log back, ignoring a last line the crash cut short.
+fn read(path: &std::path::Path) -> Result<Option<PartialResponse>> {
+    let file = File::open(path)?;
+    let updated_at = file
+        .metadata()?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default();
+    let mut lines = BufReader::new(file).lines();
+    let Some(Ok(first)) = lines.next() else {
+        return Ok(None);
+    };
+    let Ok(header) = serde_json::from_str::<Header>(&first) else {
+        return Ok(None);
+    };
+    let mut content = String::new();
+    for line in lines {
+        match line
+            .ok()
+            .and_then(|l| serde_json::from_str::<String>(&l).ok())
+        {
+            Some(delta) => content.push_str(&delta),
+            None => break,
+        }
+    }
+    if content.trim().is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(PartialResponse {
+        request_id: header.request_id,
+        provider: header.provider,
+        model: header.model,
+        conversation_id: header.conversation_id,
+        content,
+        started_at: header.started_at,
+        updated_at,
+    }))
+}
+
+/// Answers cut off when the app last stopped, oldest first. Meant to be
+/// called at startup; the logs are removed as they are returned, so the
+/// frontend should keep what it wants of them.
+#[tauri::command]
+pub async fn recover_partial_responses(app: AppHandle) -> Result<Vec<PartialResponse>> {
+    let dir = dir(&app)?;
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut recovered = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("log")
+            || ACTIVE.lock().unwrap().contains(&path)
+        {
+            continue;
+        }
+        match read(&path) {
+            Ok(partial) => recovered.extend(partial),
+            Err(err) => tracing::warn!("couldn't read {}: {err}", path.display()),
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+    recovered.sort_by_key(|p| p.started_at);
+    Ok(recovered)
+}
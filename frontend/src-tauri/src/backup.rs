@@ -0,0 +1,533 @@
+//! Backups of the database and settings into a user-chosen folder, taken on
+//! a schedule and on demand. Each backup is a zip holding a database snapshot,
+//! the config files and a manifest with their sizes and SHA-256 digests;
+//! restoring checks all of them before anything live is touched. Bundles
+//! (see `bundle`) are the same archive with the attachments added.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Emitter, Manager, State};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::attachments;
+use crate::config;
+use crate::error::{Error, Result};
+use crate::profile;
+use crate::startup;
+use crate::storage::{now_ms, Database, DB_FILE};
+
+const CONFIG_FILE: &str = "backup.json";
+const MANIFEST: &str = "manifest.json";
+const CONFIG_DIR: &str = "config";
+const ATTACHMENTS_DIR: &str = "attachments";
+const FILE_PREFIX: &str = "pentamind-backup-";
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+const FORMAT_VERSION: u32 = 1;
+/// How often the scheduler wakes to see whether a backup is due.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const HOUR_MS: i64 = 60 * 60 * 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupConfig {
+    /// Where backups are written. Scheduled backups are off while unset.
+    pub folder: Option<PathBuf>,
+    pub interval_hours: u32,
+    /// Backups to keep; older ones are deleted after each new one. Zero keeps
+    /// everything.
+    pub retain: usize,
+    pub last_backup_at: Option<i64>,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            folder: None,
+            interval_hours: 24,
+            retain: 7,
+            last_backup_at: None,
+        }
+    }
+}
+
+impl BackupConfig {
+    fn due(&self, now: i64) -> bool {
+        self.folder.is_some()
+            && self.interval_hours > 0
+            && self
+                .last_backup_at
+                .is_none_or(|last| now - last >= i64::from(self.interval_hours) * HOUR_MS)
+    }
+}
+
+#[derive(Default)]
+pub struct Backups {
+    config: Mutex<BackupConfig>,
+    /// Held for the whole of a backup or restore so they never overlap.
+    running: Mutex<()>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    created_at: i64,
+    app_version: String,
+    encrypted: bool,
+    files: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestEntry {
+    name: String,
+    size: u64,
+    sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupInfo {
+    pub path: PathBuf,
+    pub created_at: i64,
+    pub size: u64,
+    pub encrypted: bool,
+}
+
+fn file_name(created_at: i64) -> String {
+    let stamp = chrono::DateTime::from_timestamp_millis(created_at)
+        .unwrap_or_default()
+        .format(TIMESTAMP_FORMAT);
+    format!("{FILE_PREFIX}{stamp}.zip")
+}
+
+/// The creation time encoded in a backup's file name, if it is one of ours.
+fn parse_file_name(path: &Path) -> Option<i64> {
+    let stem = path
+        .file_name()?
+        .to_str()?
+        .strip_prefix(FILE_PREFIX)?
+        .strip_suffix(".zip")?;
+    let parsed = chrono::NaiveDateTime::parse_from_str(stem, TIMESTAMP_FORMAT).ok()?;
+    Some(parsed.and_utc().timestamp_millis())
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Copies `reader` into `writer`, returning the byte count and SHA-256.
+pub(crate) fn copy_hashed(mut reader: impl Read, mut writer: impl Write) -> Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        writer.write_all(&buf[..n])?;
+        size += n as u64;
+    }
+    Ok((size, hex(&hasher.finalize())))
+}
+
+/// Settings files worth carrying over. The backup config itself stays put so
+/// restoring never points backups somewhere else.
+fn config_files(app: &AppHandle) -> Result<Vec<PathBuf>> {
+    let dir = profile::config_dir(app)?;
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        if path.is_file()
+            && name.ends_with(".json")
+            && name != CONFIG_FILE
+            && name != profile::REGISTRY_FILE
+        {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+fn add_file(
+    zip: &mut ZipWriter<File>,
+    name: &str,
+    path: &Path,
+    files: &mut Vec<ManifestEntry>,
+) -> Result<()> {
+    let large = std::fs::metadata(path)?.len() > u64::from(u32::MAX);
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .large_file(large);
+    zip.start_file(name, options)?;
+    let (size, sha256) = copy_hashed(File::open(path)?, &mut *zip)?;
+    files.push(ManifestEntry {
+        name: name.to_string(),
+        size,
+        sha256,
+    });
+    Ok(())
+}
+
+/// Writes the archive to `dest`, with the attachments if `with_attachments`.
+/// Returns whether the database in it is encrypted.
+pub(crate) fn write_archive(
+    app: &AppHandle,
+    dest: &Path,
+    created_at: i64,
+    with_attachments: bool,
+) -> Result<bool> {
+    let db = app.state::<Database>();
+    let snapshot = dest.with_extension("snapshot");
+    let _ = std::fs::remove_file(&snapshot);
+    db.snapshot(&snapshot)?;
+
+    let mut zip = ZipWriter::new(File::create(dest)?);
+    let mut files = Vec::new();
+    let result = add_file(&mut zip, DB_FILE, &snapshot, &mut files);
+    let _ = std::fs::remove_file(&snapshot);
+    result?;
+    for path in config_files(app)? {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        add_file(&mut zip, &format!("{CONFIG_DIR}/{name}"), &path, &mut files)?;
+    }
+    if with_attachments {
+        let dir = attachments::dir(app)?;
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries.collect::<std::io::Result<Vec<_>>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let path = entry.path();
+            if let (true, Some(name)) = (path.is_file(), path.file_name().and_then(|n| n.to_str()))
+            {
+                add_file(
+                    &mut zip,
+                    &format!("{ATTACHMENTS_DIR}/{name}"),
+                    &path,
+                    &mut files,
+                )?;
+            }
+        }
+    }
+
+    let manifest = Manifest {
+        version: FORMAT_VERSION,
+        created_at,
+        app_version: app.package_info().version.to_string(),
+        encrypted: db.is_encrypted(),
+        files,
+    };
+    zip.start_file(MANIFEST, SimpleFileOptions::default())?;
+    zip.write_all(&serde_json::to_vec_pretty(&manifest)?)?;
+    zip.finish()?;
+    Ok(manifest.encrypted)
+}
+
+/// Writes a new backup into `folder`. The archive is built under a temporary
+/// name so a half-written one never looks like a backup.
+fn create(app: &AppHandle, folder: &Path) -> Result<BackupInfo> {
+    std::fs::create_dir_all(folder)?;
+    let created_at = now_ms();
+    let path = folder.join(file_name(created_at));
+    let partial = path.with_extension("zip.partial");
+    let encrypted = match write_archive(app, &partial, created_at, false) {
+        Ok(encrypted) => encrypted,
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            return Err(e);
+        }
+    };
+    std::fs::rename(&partial, &path)?;
+    Ok(BackupInfo {
+        size: std::fs::metadata(&path)?.len(),
+        path,
+        created_at,
+        encrypted,
+    })
+}
+
+/// Backups in `folder`, newest first.
+fn list(folder: &Path) -> Result<Vec<BackupInfo>> {
+    let entries = match std::fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut backups = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if let Some(created_at) = parse_file_name(&path) {
+            backups.push(BackupInfo {
+                size: entry.metadata()?.len(),
+                encrypted: read_manifest(&path).is_ok_and(|m| m.encrypted),
+                path,
+                created_at,
+            });
+        }
+    }
+    backups.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+    Ok(backups)
+}
+
+fn rotate(folder: &Path, retain: usize) -> Result<()> {
+    if retain == 0 {
+        return Ok(());
+    }
+    for old in list(folder)?.into_iter().skip(retain) {
+        std::fs::remove_file(old.path)?;
+    }
+    Ok(())
+}
+
+/// Maps an archive entry to where it is staged, rejecting anything that
+/// isn't the database, a top-level config file or an attachment.
+fn staged_path(staging: &Path, name: &str) -> Result<PathBuf> {
+    if name == DB_FILE {
+        return Ok(staging.join(DB_FILE));
+    }
+    [CONFIG_DIR, ATTACHMENTS_DIR]
+        .into_iter()
+        .find_map(|dir| {
+            name.strip_prefix(dir)?
+                .strip_prefix('/')
+                .filter(|file| !file.is_empty() && !file.contains(['/', '\\']) && *file != "..")
+                .map(|file| staging.join(dir).join(file))
+        })
+        .ok_or_else(|| Error::InvalidBackup(format!("unexpected entry `{name}`")))
+}
+
+fn open_archive(path: &Path) -> Result<(ZipArchive<File>, Manifest)> {
+    let mut archive =
+        ZipArchive::new(File::open(path)?).map_err(|e| Error::InvalidBackup(e.to_string()))?;
+    let manifest = serde_json::from_reader(
+        archive
+            .by_name(MANIFEST)
+            .map_err(|_| Error::InvalidBackup("no manifest".into()))?,
+    )
+    .map_err(|e| Error::InvalidBackup(format!("unreadable manifest: {e}")))?;
+    Ok((archive, manifest))
+}
+
+fn read_manifest(path: &Path) -> Result<Manifest> {
+    open_archive(path).map(|(_, manifest)| manifest)
+}
+
+/// Extracts every file in the manifest into `staging`, checking each against
+/// its recorded size and digest.
+fn extract(archive_path: &Path, staging: &Path) -> Result<Manifest> {
+    let (mut archive, manifest) = open_archive(archive_path)?;
+    if manifest.version > FORMAT_VERSION {
+        return Err(Error::Unsupported(format!(
+            "backup format version {}",
+            manifest.version
+        )));
+    }
+    if !manifest.files.iter().any(|f| f.name == DB_FILE) {
+        return Err(Error::InvalidBackup("no database in the archive".into()));
+    }
+
+    std::fs::create_dir_all(staging.join(CONFIG_DIR))?;
+    std::fs::create_dir_all(staging.join(ATTACHMENTS_DIR))?;
+    for entry in &manifest.files {
+        let dest = staged_path(staging, &entry.name)?;
+        let file = archive
+            .by_name(&entry.name)
+            .map_err(|_| Error::InvalidBackup(format!("{} is missing", entry.name)))?;
+        // The zip reader also checks each entry's CRC as it is read.
+        let (size, sha256) = copy_hashed(file, File::create(&dest)?)
+            .map_err(|e| Error::InvalidBackup(format!("{}: {e}", entry.name)))?;
+        if size != entry.size || sha256 != entry.sha256 {
+            return Err(Error::InvalidBackup(format!("{} is corrupt", entry.name)));
+        }
+    }
+    Ok(manifest)
+}
+
+/// Runs SQLite's own consistency check. Encrypted snapshots can't be opened
+/// without the passphrase, so for those the digest check has to do.
+fn check_database(path: &Path, encrypted: bool) -> Result<()> {
+    if encrypted {
+        return Ok(());
+    }
+    let conn =
+        rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let result: String = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+    if result != "ok" {
+        return Err(Error::InvalidBackup(format!(
+            "database check failed: {result}"
+        )));
+    }
+    Ok(())
+}
+
+/// Replaces the database and settings with the archive's, and adds any
+/// attachments it carries.
+pub(crate) fn restore(app: &AppHandle, archive_path: &Path) -> Result<BackupInfo> {
+    let staging = profile::data_dir(app)?.join("restore");
+    let _ = std::fs::remove_dir_all(&staging);
+    let result = extract(archive_path, &staging).and_then(|manifest| {
+        check_database(&staging.join(DB_FILE), manifest.encrypted)?;
+        app.state::<Database>().replace(&staging.join(DB_FILE))?;
+
+        let config_dir = profile::config_dir(app)?;
+        std::fs::create_dir_all(&config_dir)?;
+        for entry in std::fs::read_dir(staging.join(CONFIG_DIR))? {
+            let entry = entry?;
+            std::fs::copy(entry.path(), config_dir.join(entry.file_name()))?;
+        }
+        // Attachments are named by their digest, so one already here is
+        // the same file.
+        let attachments_dir = attachments::dir(app)?;
+        for entry in std::fs::read_dir(staging.join(ATTACHMENTS_DIR))? {
+            let entry = entry?;
+            let dest = attachments_dir.join(entry.file_name());
+            if !dest.exists() {
+                std::fs::create_dir_all(&attachments_dir)?;
+                std::fs::copy(entry.path(), dest)?;
+            }
+        }
+        Ok(BackupInfo {
+            path: archive_path.to_path_buf(),
+            created_at: manifest.created_at,
+            size: std::fs::metadata(archive_path)?.len(),
+            encrypted: manifest.encrypted,
+        })
+    });
+    let _ = std::fs::remove_dir_all(&staging);
+    result
+}
+
+/// Backs up into the configured folder, prunes old backups and records the
+/// time. The caller holds `Backups::running`.
+fn backup_now(app: &AppHandle) -> Result<BackupInfo> {
+    let backups = app.state::<Backups>();
+    let config = backups.config.lock().unwrap().clone();
+    let folder = config
+        .folder
+        .ok_or_else(|| Error::NotFound("backup folder".into()))?;
+    let info = create(app, &folder)?;
+    rotate(&folder, config.retain)?;
+    let mut config = backups.config.lock().unwrap();
+    config.last_backup_at = Some(info.created_at);
+    config::write(app, CONFIG_FILE, &*config)?;
+    Ok(info)
+}
+
+fn run_scheduled(app: &AppHandle) -> Result<Option<BackupInfo>> {
+    let backups = app.state::<Backups>();
+    let due = backups.config.lock().unwrap().due(now_ms());
+    if !due || app.state::<Database>().is_locked() {
+        return Ok(None);
+    }
+    let _running = backups.running.lock().unwrap();
+    backup_now(app).map(Some)
+}
+
+/// Loads the backup settings and starts the scheduler, which emits
+/// `backup-created` or `backup-failed` after each scheduled run.
+pub fn init(app: &AppHandle) {
+    let config = config::read::<BackupConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(Backups {
+        config: Mutex::new(config),
+        running: Mutex::new(()),
+    });
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        startup::warmed().await;
+        loop {
+            let handle = app.clone();
+            match tauri::async_runtime::spawn_blocking(move || run_scheduled(&handle)).await {
+                Ok(Ok(Some(info))) => {
+                    let _ = app.emit("backup-created", info);
+                }
+                Ok(Err(e)) => {
+                    tracing::warn!("scheduled backup failed: {e}");
+                    let _ = app.emit("backup-failed", e.to_string());
+                }
+                _ => {}
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+/// Runs backup work off the async runtime, never alongside another backup or
+/// restore.
+pub(crate) async fn blocking<T: Send + 'static>(
+    app: AppHandle,
+    f: impl FnOnce(&AppHandle) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let backups = app.state::<Backups>();
+        let _running = backups.running.lock().unwrap();
+        f(&app)
+    })
+    .await
+    .map_err(|e| Error::Io(std::io::Error::other(e)))?
+}
+
+#[tauri::command]
+pub async fn get_backup_config(backups: State<'_, Backups>) -> Result<BackupConfig> {
+    Ok(backups.config.lock().unwrap().clone())
+}
+
+#[tauri::command]
+pub async fn set_backup_config(
+    app: AppHandle,
+    backups: State<'_, Backups>,
+    folder: Option<PathBuf>,
+    interval_hours: u32,
+    retain: usize,
+) -> Result<BackupConfig> {
+    let mut config = backups.config.lock().unwrap();
+    config.folder = folder;
+    config.interval_hours = interval_hours;
+    config.retain = retain;
+    config::write(&app, CONFIG_FILE, &*config)?;
+    Ok(config.clone())
+}
+
+#[tauri::command]
+pub async fn list_backups(backups: State<'_, Backups>) -> Result<Vec<BackupInfo>> {
+    let folder = backups.config.lock().unwrap().folder.clone();
+    folder.map_or(Ok(Vec::new()), |folder| list(&folder))
+}
+
+/// Backs up into the configured folder right away, whatever the schedule.
+#[tauri::command]
+pub async fn create_backup_now(app: AppHandle) -> Result<BackupInfo> {
+    blocking(app, backup_now).await
+}
+
+/// Replaces the database and settings with those in the backup at `path`.
+/// The archive is fully extracted and verified first; if anything is off the
+/// live data is left alone. Restored settings apply from the next launch.
+#[tauri::command]
+pub async fn restore_from_backup(app: AppHandle, path: PathBuf) -> Result<BackupInfo> {
+    blocking(app, move |app| restore(app, &path)).await
+}
@@ -0,0 +1,5 @@
+//! The command-line front end; see `pentamind help`.
+
+fn main() {
+    std::process::exit(hack_lib::run_headless(std::env::args().skip(1).collect()))
+}
@@ -0,0 +1,244 @@
+//! Bookmarked messages and message tags, for keeping good answers where
+//! they can be found again. Tags work on any message, bookmarked or not,
+//! and search takes `#tag` terms to narrow to them.
+//!
+//! Tags are lowercase with no spaces, so `#tag` in a search box always
+//! means the whole tag. Both go with their message when it is deleted.
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use tauri::State;
+
+use crate::error::{Error, Result};
+use crate::llm::Role;
+use crate::storage::{now_ms, Database};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Bookmark {
+    pub message_id: String,
+    pub conversation_id: String,
+    pub conversation_title: String,
+    pub role: Role,
+    pub content: String,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub note: Option<String>,
+    pub tags: Vec<String>,
+    pub bookmarked_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageTagCount {
+    pub tag: String,
+    pub messages: u32,
+}
+
+/// `tag` as it is stored: trimmed, lowercase, without a leading `#` and
+/// with spaces turned into dashes.
+pub(crate) fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().trim_start_matches('#').trim();
+    let tag = tag
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase();
+    (!tag.is_empty()).then_some(tag)
+}
+
+fn normalize_tags(tags: &[String]) -> Result<Vec<String>> {
+    let mut normalized = tags
+        .iter()
+        .map(|tag| {
+            normalize_tag(tag).ok_or_else(|| Error::InvalidSetting("a tag can't be empty".into()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    normalized.sort();
+    normalized.dedup();
+    Ok(normalized)
+}
+
+/// Tags of a message, in order.
+pub(crate) fn message_tags(conn: &Connection, message_id: &str) -> Result<Vec<String>> {
+    let mut stmt =
+        conn.prepare_cached("SELECT tag FROM message_tags WHERE message_id = ?1 ORDER BY tag")?;
+    let tags = stmt
+        .query_map([message_id], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(tags)
+}
+
+fn ensure_message(conn: &Connection, message_id: &str) -> Result<()> {
+    conn.query_row("SELECT 1 FROM messages WHERE id = ?1", [message_id], |_| {
+        Ok(())
+    })
+    .optional()?
+    .ok_or_else(|| Error::NotFound(format!("message {message_id}")))
+}
+
+fn add_tags(conn: &Connection, message_id: &str, tags: &[String]) -> Result<()> {
+    for tag in tags {
+        conn.execute(
+            "INSERT OR IGNORE INTO message_tags (message_id, tag, created_at) VALUES (?1, ?2, ?3)",
+            params![message_id, tag, now_ms()],
+        )?;
+    }
+    Ok(())
+}
+
+impl Database {
+    /// Bookmarks a message, or updates the note of one already bookmarked.
+    /// `tags` are added to whatever it already has.
+    pub fn bookmark_message(
+        &self,
+        message_id: &str,
+        note: Option<String>,
+        tags: &[String],
+    ) -> Result<Bookmark> {
+        let tags = normalize_tags(tags)?;
+        let note = note.filter(|n| !n.trim().is_empty());
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        ensure_message(&tx, message_id)?;
+        tx.execute(
+            "INSERT INTO bookmarks (message_id, note, created_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (message_id) DO UPDATE SET note = excluded.note",
+            params![message_id, note, now_ms()],
+        )?;
+        add_tags(&tx, message_id, &tags)?;
+        tx.commit()?;
+        drop(conn);
+        self.get_bookmark(message_id)
+    }
+
+    pub fn remove_bookmark(&self, message_id: &str) -> Result<()> {
+        self.conn()?
+            .execute("DELETE FROM bookmarks WHERE message_id = ?1", [message_id])?;
+        Ok(())
+    }
+
+    pub fn get_bookmark(&self, message_id: &str) -> Result<Bookmark> {
+        self.bookmarks(Some(message_id), None)?
+            .pop()
+            .ok_or_else(|| Error::NotFound(format!("bookmark {message_id}")))
+    }
+
+    /// Bookmarks, newest first, only those tagged `tag` if given.
+    pub fn list_bookmarks(&self, tag: Option<&str>) -> Result<Vec<Bookmark>> {
+        let tag = tag.and_then(normalize_tag);
+        self.bookmarks(None, tag.as_deref())
+    }
+
+    fn bookmarks(&self, message_id: Option<&str>, tag: Option<&str>) -> Result<Vec<Bookmark>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT m.id, m.conversation_id, c.title, m.role, m.content, m.provider, m.model,
+                    b.note, b.created_at
+             FROM bookmarks b
+             JOIN messages m ON m.id = b.message_id
+             JOIN conversations c ON c.id = m.conversation_id
+             WHERE (?1 IS NULL OR b.message_id = ?1)
+               AND (?2 IS NULL OR EXISTS (
+                   SELECT 1 FROM message_tags t WHERE t.message_id = m.id AND t.tag = ?2))
+             ORDER BY b.created_at DESC",
+        )?;
+        let rows = stmt.query_map(params![message_id, tag], |row| {
+            Ok(Bookmark {
+                message_id: row.get(0)?,
+                conversation_id: row.get(1)?,
+                conversation_title: row.get(2)?,
+                role: row.get(3)?,
+                content: row.get(4)?,
+                provider: row.get(5)?,
+                model: row.get(6)?,
+                note: row.get(7)?,
+                tags: Vec::new(),
+                bookmarked_at: row.get(8)?,
+            })
+        })?;
+        let mut bookmarks = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        for bookmark in &mut bookmarks {
+            bookmark.tags = message_tags(&conn, &bookmark.message_id)?;
+        }
+        Ok(bookmarks)
+    }
+
+    /// Adds `tags` to a message and returns all of its tags.
+    pub fn tag_message(&self, message_id: &str, tags: &[String]) -> Result<Vec<String>> {
+        let tags = normalize_tags(tags)?;
+        let conn = self.conn()?;
+        ensure_message(&conn, message_id)?;
+        add_tags(&conn, message_id, &tags)?;
+        message_tags(&conn, message_id)
+    }
+
+    /// Removes `tags` from a message and returns the ones it has left.
+    pub fn untag_message(&self, message_id: &str, tags: &[String]) -> Result<Vec<String>> {
+        let tags = normalize_tags(tags)?;
+        let conn = self.conn()?;
+        ensure_message(&conn, message_id)?;
+        for tag in &tags {
+            conn.execute(
+                "DELETE FROM message_tags WHERE message_id = ?1 AND tag = ?2",
+                params![message_id, tag],
+            )?;
+        }
+        message_tags(&conn, message_id)
+    }
+
+    pub fn message_tag_counts(&self) -> Result<Vec<MessageTagCount>> {
+        let conn = self.conn()?;
+        let mut stmt =
+            conn.prepare("SELECT tag, COUNT(*) FROM message_tags GROUP BY tag ORDER BY tag")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(MessageTagCount {
+                tag: row.get(0)?,
+                messages: row.get(1)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+#[tauri::command]
+pub async fn bookmark_message(
+    db: State<'_, Database>,
+    message_id: String,
+    note: Option<String>,
+    tags: Option<Vec<String>>,
+) -> Result<Bookmark> {
+    db.bookmark_message(&message_id, note, &tags.unwrap_or_default())
+}
+
+/// Removes the bookmark and leaves the message's tags alone.
+#[tauri::command]
+pub async fn remove_bookmark(db: State<'_, Database>, message_id: String) -> Result<()> {
+    db.remove_bookmark(&message_id)
+}
+
+#[tauri::command]
+pub async fn list_bookmarks(db: State<'_, Database>, tag: Option<String>) -> Result<Vec<Bookmark>> {
+    db.list_bookmarks(tag.as_deref())
+}
+
+#[tauri::command]
+pub async fn tag_message(
+    db: State<'_, Database>,
+    message_id: String,
+    tags: Vec<String>,
+) -> Result<Vec<String>> {
+    db.tag_message(&message_id, &tags)
+}
+
+#[tauri::command]
+pub async fn untag_message(
+    db: State<'_, Database>,
+    message_id: String,
+    tags: Vec<String>,
+) -> Result<Vec<String>> {
+    db.untag_message(&message_id, &tags)
+}
+
+#[tauri::command]
+pub async fn list_message_tags(db: State<'_, Database>) -> Result<Vec<MessageTagCount>> {
+    db.message_tag_counts()
+}
@@ -0,0 +1,248 @@
+//! Moving the whole app to a new machine in one file. A bundle is a backup
+//! archive (see `backup`) with the attachments added, encrypted under a
+//! passphrase chosen at export. Keys kept in the system keychain stay
+//! behind and have to be entered again on the new machine.
+//!
+//! The file is a short header, holding what is needed besides the
+//! passphrase to derive the key, followed by the archive sealed in chunks.
+//! Each chunk is sealed under its index, whether it is the last and a
+//! digest of the header, so chunks can't be reordered, dropped or moved
+//! to another bundle without the import noticing.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::AppHandle;
+
+use crate::backup;
+use crate::error::{Error, Result};
+use crate::profile;
+use crate::storage::now_ms;
+use crate::sync::crypto::{Key, KeyFile};
+
+const MAGIC: &[u8] = b"PMB1";
+const FORMAT_VERSION: u32 = 1;
+/// Plaintext bytes per sealed chunk.
+const CHUNK_LEN: usize = 1 << 20;
+/// Headers are small; anything longer isn't one of ours.
+const MAX_HEADER_LEN: u32 = 64 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    created_at: i64,
+    app_version: String,
+    key: KeyFile,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BundleInfo {
+    pub path: PathBuf,
+    pub created_at: i64,
+    pub size: u64,
+    /// The version of the app that wrote the bundle.
+    pub app_version: String,
+}
+
+fn damaged(what: &str) -> Error {
+    Error::InvalidBackup(format!("the bundle {what}"))
+}
+
+fn chunk_name(header_digest: &str, index: u64, last: bool) -> String {
+    let position = if last { "last" } else { "more" };
+    format!("bundle/{header_digest}/{index}/{position}")
+}
+
+/// Reads until `buf` is full or the input ends, returning the byte count.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+/// A big-endian length, or `None` at a clean end of input.
+fn read_len(reader: &mut impl Read) -> Result<Option<u32>> {
+    let mut bytes = [0u8; 4];
+    match read_full(reader, &mut bytes)? {
+        0 => Ok(None),
+        4 => Ok(Some(u32::from_be_bytes(bytes))),
+        _ => Err(damaged("is cut short")),
+    }
+}
+
+fn seal(source: &Path, dest: &Path, passphrase: &str, created_at: i64) -> Result<()> {
+    let (key, key_file) = Key::create(passphrase)?;
+    let header = serde_json::to_vec(&Header {
+        version: FORMAT_VERSION,
+        created_at,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        key: key_file,
+    })?;
+    let digest = backup::hex(&Sha256::digest(&header));
+
+    let mut reader = BufReader::new(File::open(source)?);
+    let mut writer = BufWriter::new(File::create(dest)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(header.len() as u32).to_be_bytes())?;
+    writer.write_all(&header)?;
+
+    // One chunk is read ahead, to know which is the last.
+    let mut current = vec![0; CHUNK_LEN];
+    let mut next = vec![0; CHUNK_LEN];
+    let mut current_len = read_full(&mut reader, &mut current)?;
+    let mut index = 0;
+    loop {
+        let next_len = if current_len == CHUNK_LEN {
+            read_full(&mut reader, &mut next)?
+        } else {
+            0
+        };
+        let last = next_len == 0;
+        let sealed = key.seal(&chunk_name(&digest, index, last), &current[..current_len])?;
+        writer.write_all(&(sealed.len() as u32).to_be_bytes())?;
+        writer.write_all(&sealed)?;
+        if last {
+            break;
+        }
+        std::mem::swap(&mut current, &mut next);
+        current_len = next_len;
+        index += 1;
+    }
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    Ok(())
+}
+
+fn read_header(reader: &mut impl Read) -> Result<(Header, String)> {
+    let mut magic = [0u8; 4];
+    if read_full(reader, &mut magic)? != MAGIC.len() || magic != MAGIC {
+        return Err(Error::InvalidBackup("not a Pentamind bundle".into()));
+    }
+    let len = read_len(reader)?
+        .filter(|len| *len <= MAX_HEADER_LEN)
+        .ok_or_else(|| damaged("has a damaged header"))?;
+    let mut bytes = vec![0; len as usize];
+    if read_full(reader, &mut bytes)? != bytes.len() {
+        return Err(damaged("is cut short"));
+    }
+    let header: Header =
+        serde_json::from_slice(&bytes).map_err(|_| damaged("has a damaged header"))?;
+    if header.version > FORMAT_VERSION {
+        return Err(Error::Unsupported(
+            "this bundle was made by a newer version of Pentamind".into(),
+        ));
+    }
+    Ok((header, backup::hex(&Sha256::digest(&bytes))))
+}
+
+fn open(source: &Path, dest: &Path, passphrase: &str) -> Result<Header> {
+    let mut reader = BufReader::new(File::open(source)?);
+    let (header, digest) = read_header(&mut reader)?;
+    let key = Key::unlock(passphrase, &header.key)?;
+
+    let mut writer = BufWriter::new(File::create(dest)?);
+    let mut len = read_len(&mut reader)?.ok_or_else(|| damaged("is empty"))?;
+    let mut index = 0;
+    loop {
+        if len as usize > CHUNK_LEN + 1024 {
+            return Err(damaged("is damaged"));
+        }
+        let mut sealed = vec![0; len as usize];
+        if read_full(&mut reader, &mut sealed)? != sealed.len() {
+            return Err(damaged("is cut short"));
+        }
+        let next = read_len(&mut reader)?;
+        let last = next.is_none();
+        let chunk = key
+            .open(&chunk_name(&digest, index, last), &sealed)
+            .map_err(|_| {
+                if last {
+                    damaged("is cut short or damaged")
+                } else {
+                    damaged("is damaged")
+                }
+            })?;
+        writer.write_all(&chunk)?;
+        match next {
+            Some(next) => len = next,
+            None => break,
+        }
+        index += 1;
+    }
+    writer.flush()?;
+    Ok(header)
+}
+
+fn export(app: &AppHandle, path: &Path, passphrase: &str) -> Result<BundleInfo> {
+    let created_at = now_ms();
+    let archive = profile::data_dir(app)?.join("bundle-export.zip");
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let result = backup::write_archive(app, &archive, created_at, true)
+        .and_then(|_| seal(&archive, &partial, passphrase, created_at))
+        .and_then(|()| Ok(std::fs::rename(&partial, path)?));
+    let _ = std::fs::remove_file(&archive);
+    if let Err(err) = result {
+        let _ = std::fs::remove_file(&partial);
+        return Err(err);
+    }
+    Ok(BundleInfo {
+        path: path.to_path_buf(),
+        created_at,
+        size: std::fs::metadata(path)?.len(),
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+    })
+}
+
+fn import(app: &AppHandle, path: &Path, passphrase: &str) -> Result<BundleInfo> {
+    let archive = profile::data_dir(app)?.join("bundle-import.zip");
+    let result = open(path, &archive, passphrase)
+        .and_then(|header| backup::restore(app, &archive).map(|_| header));
+    let _ = std::fs::remove_file(&archive);
+    let header = result?;
+    Ok(BundleInfo {
+        path: path.to_path_buf(),
+        created_at: header.created_at,
+        size: std::fs::metadata(path)?.len(),
+        app_version: header.app_version,
+    })
+}
+
+/// Writes everything but the keychain secrets to `path`, encrypted with
+/// `passphrase`.
+#[tauri::command]
+pub async fn export_app_bundle(
+    app: AppHandle,
+    path: PathBuf,
+    passphrase: String,
+) -> Result<BundleInfo> {
+    if passphrase.is_empty() {
+        return Err(Error::InvalidSetting("a bundle needs a passphrase".into()));
+    }
+    backup::blocking(app, move |app| export(app, &path, &passphrase)).await
+}
+
+/// Replaces the database and settings with the bundle's and adds its
+/// attachments. Fails with `WrongPassphrase` before anything is touched if
+/// the passphrase is wrong. Restored settings apply from the next launch.
+#[tauri::command]
+pub async fn import_app_bundle(
+    app: AppHandle,
+    path: PathBuf,
+    passphrase: String,
+) -> Result<BundleInfo> {
+    backup::blocking(app, move |app| import(app, &path, &passphrase)).await
+}
@@ -0,0 +1,342 @@
+//! Response cache, so comparing the same prompt again doesn't bill every
+//! provider a second time. Answers are keyed by provider, model, sampling
+//! parameters and a hash of the normalized messages, and expire after a
+//! TTL. With semantic lookup on, a miss falls back to an earlier answer in
+//! the same conversation whose last message embeds close enough to this one.
+//!
+//! Requests that offer tools are never cached, since their answers depend on
+//! what the tools returned at the time.
+
+use std::sync::Mutex;
+
+use reqwest::Client;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::incognito;
+use crate::llm::{ChatMessage, ChatRequest, Usage};
+use crate::providers::{Completion, Providers};
+use crate::rag::{self, Embedder, EmbeddingOptions};
+use crate::storage::{now_ms, Database};
+
+const CONFIG_FILE: &str = "cache.json";
+/// Longest message embedded for semantic lookup, in bytes.
+const EMBED_MAX_LEN: usize = 8000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub ttl_secs: u64,
+    /// Also reuse answers to prompts that are worded differently but mean
+    /// the same. Costs an embedding call per request.
+    pub semantic: bool,
+    /// Cosine similarity a prompt needs to reuse another's answer.
+    pub similarity_threshold: f32,
+    /// Falls back to the default embedding provider and its model.
+    pub embedding_provider: Option<String>,
+    pub embedding_model: Option<String>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ttl_secs: 24 * 60 * 60,
+            semantic: false,
+            similarity_threshold: 0.97,
+            embedding_provider: None,
+            embedding_model: None,
+        }
+    }
+}
+
+/// Managed as Tauri state.
+pub struct Cache(Mutex<CacheConfig>);
+
+/// A cacheable request, looked up before it is sent and then stored with
+/// the answer it got.
+pub struct Lookup {
+    key: String,
+    scope: String,
+    /// Embedding model and the last message's vector, with semantic lookup on.
+    embedding: Option<(String, Vec<f32>)>,
+    ttl_secs: u64,
+    pub hit: Option<Completion>,
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<CacheConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(Cache(Mutex::new(config)));
+}
+
+fn hash(value: &serde_json::Value) -> String {
+    Sha256::digest(value.to_string().as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Content with runs of whitespace collapsed, so reflowed or re-indented
+/// prompts share an entry.
+fn normalize(content: &str) -> String {
+    content.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn normalized(messages: &[ChatMessage]) -> Vec<serde_json::Value> {
+    messages
+        .iter()
+        .map(|m| json!([m.role, normalize(&m.content)]))
+        .collect()
+}
+
+fn truncate(text: &str, max_len: usize) -> &str {
+    if text.len() <= max_len {
+        return text;
+    }
+    let mut end = max_len;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+async fn embed(
+    app: &AppHandle,
+    client: &Client,
+    config: &CacheConfig,
+    text: &str,
+) -> Result<(String, Vec<f32>)> {
+    let options = EmbeddingOptions {
+        provider: config.embedding_provider.clone(),
+        model: config.embedding_model.clone(),
+    };
+    let embedder = Embedder::resolve(&app.state::<Providers>(), &options)?;
+    let vector = embedder
+        .embed(client, &[truncate(text, EMBED_MAX_LEN).to_string()])
+        .await?
+        .pop()
+        .unwrap_or_default();
+    Ok((embedder.key(), vector))
+}
+
+/// Looks `request` up, or `None` if it can't be cached. With
+/// `bypass_cache` nothing is reused, but the answer still replaces the
+/// stored one. Cache failures count as misses.
+pub async fn lookup(app: &AppHandle, client: &Client, request: &ChatRequest) -> Option<Lookup> {
+    let config = app.try_state::<Cache>()?.0.lock().unwrap().clone();
+    if !config.enabled
+        || request.use_tools
+        || !request.tools.is_empty()
+        || incognito::is_ephemeral_request(request.conversation_id.as_deref())
+    {
+        return None;
+    }
+    let (last, earlier) = request.messages.split_last()?;
+    let mut scope = json!([
+        request.provider,
+        request.model,
+        request.temperature,
+        request.max_tokens,
+        normalized(earlier),
+    ]);
+    // Appended only when set, so entries from before these existed still match.
+    if let Some(parts) = scope.as_array_mut() {
+        if let Some(top_p) = request.top_p {
+            parts.push(json!({ "top_p": top_p }));
+        }
+        if let Some(schema) = &request.response_schema {
+            parts.push(schema.clone());
+        }
+    }
+    let scope = hash(&scope);
+    let key = hash(&json!([scope, normalized(std::slice::from_ref(last))]));
+    let embedding = if config.semantic {
+        match embed(app, client, &config, &normalize(&last.content)).await {
+            Ok(embedding) => Some(embedding),
+            Err(err) => {
+                tracing::debug!("no embedding for the response cache: {err}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut lookup = Lookup {
+        key,
+        scope,
+        embedding,
+        ttl_secs: config.ttl_secs,
+        hit: None,
+    };
+    if request.bypass_cache {
+        return Some(lookup);
+    }
+    let db = app.try_state::<Database>()?;
+    let found = match db.cached_response(&lookup.key) {
+        Ok(None) => match &lookup.embedding {
+            Some((model, vector)) => {
+                db.similar_response(&lookup.scope, model, vector, config.similarity_threshold)
+            }
+            None => Ok(None),
+        },
+        found => found,
+    };
+    lookup.hit = found.unwrap_or_else(|err| {
+        tracing::debug!("response cache lookup failed: {err}");
+        None
+    });
+    Some(lookup)
+}
+
+/// Stores the answer `lookup` was waiting for. Answers that ended in tool
+/// calls or came back empty aren't worth reusing.
+pub fn store(app: &AppHandle, request: &ChatRequest, lookup: Lookup, completion: &Completion) {
+    if completion.content.trim().is_empty() || !completion.tool_calls.is_empty() {
+        return;
+    }
+    let Some(db) = app.try_state::<Database>() else {
+        return;
+    };
+    if let Err(err) = db.store_response(request, &lookup, completion) {
+        tracing::debug!("couldn't cache the response: {err}");
+    }
+}
+
+fn read_completion(row: &rusqlite::Row<'_>) -> rusqlite::Result<Completion> {
+    let prompt_tokens: Option<u32> = row.get(1)?;
+    let completion_tokens: Option<u32> = row.get(2)?;
+    Ok(Completion {
+        content: row.get(0)?,
+        usage: prompt_tokens.zip(completion_tokens).map(|(p, c)| Usage {
+            prompt_tokens: p,
+            completion_tokens: c,
+        }),
+        tool_calls: Vec::new(),
+    })
+}
+
+impl Database {
+    fn cached_response(&self, key: &str) -> Result<Option<Completion>> {
+        Ok(self
+            .conn()?
+            .query_row(
+                "SELECT content, prompt_tokens, completion_tokens FROM response_cache
+                 WHERE key = ?1 AND expires_at > ?2",
+                params![key, now_ms()],
+                read_completion,
+            )
+            .optional()?)
+    }
+
+    /// The answer whose last message is nearest `vector` within `scope`,
+    /// if it is at least `threshold` similar.
+    fn similar_response(
+        &self,
+        scope: &str,
+        model: &str,
+        vector: &[f32],
+        threshold: f32,
+    ) -> Result<Option<Completion>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT content, prompt_tokens, completion_tokens, embedding FROM response_cache
+             WHERE scope = ?1 AND embedding_model = ?2 AND expires_at > ?3",
+        )?;
+        let rows = stmt.query_map(params![scope, model, now_ms()], |row| {
+            let embedding: Vec<u8> = row.get(3)?;
+            let score = rag::cosine(vector, &rag::decode_vector(&embedding));
+            Ok((score, read_completion(row)?))
+        })?;
+        let mut best: Option<(f32, Completion)> = None;
+        for row in rows {
+            let (score, completion) = row?;
+            if score >= threshold && best.as_ref().is_none_or(|(top, _)| score > *top) {
+                best = Some((score, completion));
+            }
+        }
+        Ok(best.map(|(_, completion)| completion))
+    }
+
+    fn store_response(
+        &self,
+        request: &ChatRequest,
+        lookup: &Lookup,
+        completion: &Completion,
+    ) -> Result<()> {
+        let now = now_ms();
+        let ttl_ms = i64::try_from(lookup.ttl_secs.saturating_mul(1000)).unwrap_or(i64::MAX);
+        let (embedding_model, embedding) = match &lookup.embedding {
+            Some((model, vector)) => (Some(model.as_str()), Some(rag::encode_vector(vector))),
+            None => (None, None),
+        };
+        let conn = self.conn()?;
+        conn.execute("DELETE FROM response_cache WHERE expires_at <= ?1", [now])?;
+        conn.execute(
+            "INSERT OR REPLACE INTO response_cache
+                 (key, scope, provider, model, content, prompt_tokens, completion_tokens,
+                  embedding_model, embedding, created_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                lookup.key,
+                lookup.scope,
+                request.provider,
+                request.model,
+                completion.content,
+                completion.usage.map(|u| u.prompt_tokens),
+                completion.usage.map(|u| u.completion_tokens),
+                embedding_model,
+                embedding,
+                now,
+                now.saturating_add(ttl_ms)
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn clear_response_cache(&self, provider: Option<&str>) -> Result<usize> {
+        let conn = self.conn()?;
+        Ok(match provider {
+            Some(provider) => {
+                conn.execute("DELETE FROM response_cache WHERE provider = ?1", [provider])?
+            }
+            None => conn.execute("DELETE FROM response_cache", [])?,
+        })
+    }
+}
+
+#[tauri::command]
+pub fn get_cache_config(cache: State<'_, Cache>) -> CacheConfig {
+    cache.0.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn set_cache_config(
+    app: AppHandle,
+    cache: State<'_, Cache>,
+    config: CacheConfig,
+) -> Result<CacheConfig> {
+    if !(0.0..=1.0).contains(&config.similarity_threshold) {
+        return Err(Error::InvalidSetting(
+            "similarity threshold must be between 0 and 1".into(),
+        ));
+    }
+    config::write(&app, CONFIG_FILE, &config)?;
+    *cache.0.lock().unwrap() = config.clone();
+    Ok(config)
+}
+
+/// Drops cached answers, only `provider`'s when given. Returns how many
+/// were removed.
+#[tauri::command]
+pub fn clear_cache(db: State<'_, Database>, provider: Option<String>) -> Result<usize> {
+    db.clear_response_cache(provider.as_deref())
+}
@@ -0,0 +1,286 @@
+//! Which sources an answer drew on. RAG excerpts and web pages put into a
+//! request's prompt are recorded under the request id, numbered as the
+//! prompt cites them, and linked to each answer saved from that request,
+//! so `get_response_sources` can list them under the answer. A page a
+//! model fetched with a tool is recorded for that model alone. Nothing is
+//! recorded for an incognito conversation.
+//!
+//! Sources of a request that no answer was saved from are dropped after a
+//! day.
+
+use std::future::Future;
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, Row, ToSql};
+use serde::Serialize;
+use tauri::State;
+
+use crate::error::{Error, Result};
+use crate::incognito;
+use crate::llm::ChatRequest;
+use crate::rag::ScoredChunk;
+use crate::storage::{now_ms, Database};
+use crate::web::WebPage;
+
+const UNLINKED_TTL_MS: i64 = 24 * 60 * 60 * 1000;
+const PAGE_EXCERPT_CHARS: usize = 1000;
+
+struct Scope {
+    provider: String,
+    incognito: bool,
+}
+
+tokio::task_local! {
+    static SCOPE: Scope;
+}
+
+/// Runs `work` with the pages it fetches attributed to the model `request`
+/// went to, or not recorded at all for an incognito conversation.
+pub async fn for_request<F: Future>(request: &ChatRequest, work: F) -> F::Output {
+    let scope = Scope {
+        provider: request.provider.clone(),
+        incognito: incognito::is_ephemeral_request(request.conversation_id.as_deref()),
+    };
+    SCOPE.scope(scope, work).await
+}
+
+/// Whether pages fetched here should be recorded as sources.
+pub fn recording() -> bool {
+    SCOPE.try_with(|scope| !scope.incognito).unwrap_or(true)
+}
+
+/// The request id to record a command's sources under: none for an
+/// incognito conversation.
+pub fn recorded_under(request_id: Option<String>, conversation_id: Option<&str>) -> Option<String> {
+    request_id.filter(|_| !incognito::is_ephemeral_request(conversation_id))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    /// An excerpt of an indexed document.
+    Chunk,
+    Page,
+}
+
+impl ToSql for SourceKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let kind = match self {
+            SourceKind::Chunk => "chunk",
+            SourceKind::Page => "page",
+        };
+        Ok(kind.into())
+    }
+}
+
+impl FromSql for SourceKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "chunk" => Ok(SourceKind::Chunk),
+            "page" => Ok(SourceKind::Page),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResponseSource {
+    /// The `[n]` the prompt cites it by.
+    pub number: u32,
+    pub kind: SourceKind,
+    /// The document's source, or the page's address.
+    pub source: String,
+    pub title: Option<String>,
+    /// Where to open it, when there is somewhere.
+    pub url: Option<String>,
+    pub document_id: Option<String>,
+    pub chunk_id: Option<String>,
+    /// Byte offsets of the excerpt within the document.
+    pub start_offset: Option<usize>,
+    pub end_offset: Option<usize>,
+    pub excerpt: String,
+    pub score: Option<f32>,
+    /// The model that fetched it; `None` when every model was given it.
+    pub provider: Option<String>,
+}
+
+impl ResponseSource {
+    const COLUMNS: &'static str = "s.number, s.kind, s.source, s.title, s.url, s.document_id,
+        s.chunk_id, s.start_offset, s.end_offset, s.excerpt, s.score, s.provider";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            number: row.get(0)?,
+            kind: row.get(1)?,
+            source: row.get(2)?,
+            title: row.get(3)?,
+            url: row.get(4)?,
+            document_id: row.get(5)?,
+            chunk_id: row.get(6)?,
+            start_offset: row.get(7)?,
+            end_offset: row.get(8)?,
+            excerpt: row.get(9)?,
+            score: row.get(10)?,
+            provider: row.get(11)?,
+        })
+    }
+}
+
+fn is_web_address(source: &str) -> bool {
+    source.starts_with("https://") || source.starts_with("http://")
+}
+
+fn next_number(conn: &Connection, request_id: &str) -> Result<u32> {
+    let last: Option<u32> = conn.query_row(
+        "SELECT MAX(number) FROM request_sources WHERE request_id = ?1",
+        [request_id],
+        |row| row.get(0),
+    )?;
+    Ok(last.unwrap_or(0) + 1)
+}
+
+fn insert(conn: &Connection, request_id: &str, source: &ResponseSource) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO request_sources
+             (request_id, number, provider, kind, source, title, url, document_id, chunk_id,
+              start_offset, end_offset, excerpt, score, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+        params![
+            request_id,
+            source.number,
+            source.provider,
+            source.kind,
+            source.source,
+            source.title,
+            source.url,
+            source.document_id,
+            source.chunk_id,
+            source.start_offset,
+            source.end_offset,
+            source.excerpt,
+            source.score,
+            now_ms()
+        ],
+    )?;
+    Ok(())
+}
+
+/// Drops sources no saved answer came from.
+fn prune_unlinked(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "DELETE FROM request_sources WHERE created_at < ?1
+           AND request_id NOT IN (SELECT request_id FROM message_requests)",
+        [now_ms() - UNLINKED_TTL_MS],
+    )?;
+    Ok(())
+}
+
+impl Database {
+    /// The number the next source recorded for `request_id` is cited by.
+    pub(crate) fn next_source_number(&self, request_id: &str) -> Result<u32> {
+        next_number(&*self.conn()?, request_id)
+    }
+
+    /// Records the excerpts of a RAG context, numbered from `first` as
+    /// `rag::format_context` numbered them.
+    pub(crate) fn record_chunks(
+        &self,
+        request_id: &str,
+        first: u32,
+        chunks: &[ScoredChunk],
+    ) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        prune_unlinked(&tx)?;
+        for (number, chunk) in (first..).zip(chunks) {
+            let source = ResponseSource {
+                number,
+                kind: SourceKind::Chunk,
+                source: chunk.source.clone(),
+                title: Some(chunk.title.clone()),
+                url: is_web_address(&chunk.source).then(|| chunk.source.clone()),
+                document_id: Some(chunk.document_id.clone()),
+                chunk_id: Some(chunk.chunk_id.clone()),
+                start_offset: Some(chunk.start_offset),
+                end_offset: Some(chunk.end_offset),
+                excerpt: chunk.content.clone(),
+                score: Some(chunk.score),
+                provider: None,
+            };
+            insert(&tx, request_id, &source)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Records a fetched page as the request's next source. Inside
+    /// [`for_request`] it belongs to that model alone.
+    pub(crate) fn record_page(&self, request_id: &str, page: &WebPage) -> Result<u32> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        prune_unlinked(&tx)?;
+        let number = next_number(&tx, request_id)?;
+        let excerpt = match page.text.char_indices().nth(PAGE_EXCERPT_CHARS) {
+            Some((end, _)) => format!("{}…", &page.text[..end]),
+            None => page.text.clone(),
+        };
+        let source = ResponseSource {
+            number,
+            kind: SourceKind::Page,
+            source: page.url.clone(),
+            title: page.title.clone(),
+            url: Some(page.url.clone()),
+            document_id: None,
+            chunk_id: None,
+            start_offset: None,
+            end_offset: None,
+            excerpt,
+            score: None,
+            provider: SCOPE.try_with(|scope| scope.provider.clone()).ok(),
+        };
+        insert(&tx, request_id, &source)?;
+        tx.commit()?;
+        Ok(number)
+    }
+
+    /// Ties an answer to the request it came from.
+    pub(crate) fn link_response(&self, message_id: &str, request_id: &str) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO message_requests (message_id, request_id) VALUES (?1, ?2)",
+            params![message_id, request_id],
+        )?;
+        Ok(())
+    }
+
+    fn response_sources(&self, message_id: &str) -> Result<Vec<ResponseSource>> {
+        let conn = self.conn()?;
+        let provider: Option<String> = conn
+            .query_row(
+                "SELECT provider FROM messages WHERE id = ?1",
+                [message_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("message {message_id}")))?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM request_sources s
+             JOIN message_requests m ON m.request_id = s.request_id
+             WHERE m.message_id = ?1
+               AND (?2 IS NULL OR s.provider IS NULL OR s.provider = ?2)
+             ORDER BY s.number",
+            ResponseSource::COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![message_id, provider], ResponseSource::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+/// The excerpts and pages the answer's request was given, in citation
+/// order; empty for a message saved without a request id.
+#[tauri::command]
+pub fn get_response_sources(
+    db: State<'_, Database>,
+    message_id: String,
+) -> Result<Vec<ResponseSource>> {
+    db.response_sources(&message_id)
+}
@@ -0,0 +1,438 @@
+//! `pentamind` on the command line: the `pentamind` binary, or the app
+//! started with `--headless`. Fan-outs, consensus and exports run through
+//! the same code as the GUI, against the same profile and database, but no
+//! window opens and none of the background services (sync, backups,
+//! schedules, the API server) start. Prompts from scripts are sent as
+//! given: scrubbing asks the user in the window, and there is none.
+
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::arbiter::{
+    self, Candidate, ModelChoice, RankRequest, Ranking, Strategy, SynthesisRequest,
+};
+use crate::error::{Error, Result};
+use crate::export::{self, ExportFormat};
+use crate::fanout::{self, FanoutRequest, FanoutResult, FanoutTarget};
+use crate::llm::{ChatMessage, ChatResponse, Role};
+use crate::providers;
+use crate::storage::Database;
+
+/// Makes the GUI binary behave as the CLI; must come first.
+pub const HEADLESS_FLAG: &str = "--headless";
+
+pub const USAGE: &str = "\
+Usage: pentamind <command> [options]
+
+Commands:
+  ask [options] <prompt>       Send a prompt to several providers at once
+                               (`-` reads it from stdin)
+      -p, --provider ID[:MODEL]  Ask this provider; repeat for more
+                                 (default: every configured one)
+          --preset ID            Fill in targets and parameters from a preset
+      -s, --system TEXT          System prompt
+      -t, --temperature N
+          --max-tokens N
+          --consensus STRATEGY   Rank the answers: vote, similar or
+                                 judge:ID[:MODEL]
+          --synthesize ID[:MODEL]  Merge the answers into one
+          --no-cache             Ask every provider afresh
+          --json                 Print the results as JSON
+  export <id> [options]        Export a conversation
+      -f, --format FORMAT        markdown (default), json or pdf
+      -o, --output PATH          Write to a file instead of stdout
+  conversations [--json]       List conversations, most recent first
+  providers [--json]           List providers and whether they are set up
+  help                         Show this message";
+
+#[derive(Debug)]
+pub enum Command {
+    Ask(Ask),
+    Export {
+        id: String,
+        format: ExportFormat,
+        output: Option<PathBuf>,
+    },
+    Conversations {
+        json: bool,
+    },
+    Providers {
+        json: bool,
+    },
+    Help,
+}
+
+#[derive(Debug)]
+pub struct Ask {
+    targets: Vec<FanoutTarget>,
+    preset_id: Option<String>,
+    system: Option<String>,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+    consensus: Option<Strategy>,
+    synthesize: Option<ModelChoice>,
+    bypass_cache: bool,
+    json: bool,
+    /// `-` for stdin.
+    prompt: String,
+}
+
+/// `openai` or `openai:gpt-4o`.
+fn model_choice(value: &str) -> ModelChoice {
+    match value.split_once(':') {
+        Some((provider, model)) => ModelChoice {
+            provider: provider.to_string(),
+            model: Some(model.to_string()),
+        },
+        None => ModelChoice {
+            provider: value.to_string(),
+            model: None,
+        },
+    }
+}
+
+fn strategy(value: &str) -> std::result::Result<Strategy, String> {
+    match value {
+        "vote" => Ok(Strategy::MajorityVote),
+        "similar" => Ok(Strategy::Similarity {
+            embedding: Default::default(),
+        }),
+        _ => match value.strip_prefix("judge:") {
+            Some(judge) if !judge.is_empty() => Ok(Strategy::Judge {
+                judge: model_choice(judge),
+            }),
+            _ => Err(format!(
+                "unknown consensus strategy `{value}`; use vote, similar or judge:ID[:MODEL]"
+            )),
+        },
+    }
+}
+
+fn format(value: &str) -> std::result::Result<ExportFormat, String> {
+    match value {
+        "markdown" | "md" => Ok(ExportFormat::Markdown),
+        "json" => Ok(ExportFormat::Json),
+        "pdf" => Ok(ExportFormat::Pdf),
+        _ => Err(format!(
+            "unknown format `{value}`; use markdown, json or pdf"
+        )),
+    }
+}
+
+fn number<T: std::str::FromStr>(option: &str, value: &str) -> std::result::Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{option} expects a number, not `{value}`"))
+}
+
+/// Walks the arguments after the command.
+struct Args<'a> {
+    rest: std::slice::Iter<'a, String>,
+}
+
+impl<'a> Args<'a> {
+    fn value(&mut self, option: &str) -> std::result::Result<&'a str, String> {
+        self.rest
+            .next()
+            .map(String::as_str)
+            .ok_or_else(|| format!("{option} expects a value"))
+    }
+}
+
+/// The command `args` (without the program name) ask for, or what is
+/// wrong with them.
+pub fn parse(args: &[String]) -> std::result::Result<Command, String> {
+    let Some((command, rest)) = args.split_first() else {
+        return Ok(Command::Help);
+    };
+    let mut args = Args { rest: rest.iter() };
+    let mut json = false;
+    let mut positional = Vec::new();
+
+    match command.as_str() {
+        "ask" | "fanout" => {
+            let mut ask = Ask {
+                targets: Vec::new(),
+                preset_id: None,
+                system: None,
+                temperature: None,
+                max_tokens: None,
+                consensus: None,
+                synthesize: None,
+                bypass_cache: false,
+                json: false,
+                prompt: String::new(),
+            };
+            while let Some(arg) = args.rest.next() {
+                match arg.as_str() {
+                    "-p" | "--provider" => {
+                        let choice = model_choice(args.value(arg)?);
+                        let mut target = FanoutTarget::new(&choice.provider);
+                        target.model = choice.model;
+                        ask.targets.push(target);
+                    }
+                    "--preset" => ask.preset_id = Some(args.value(arg)?.to_string()),
+                    "-s" | "--system" => ask.system = Some(args.value(arg)?.to_string()),
+                    "-t" | "--temperature" => {
+                        ask.temperature = Some(number(arg, args.value(arg)?)?)
+                    }
+                    "--max-tokens" => ask.max_tokens = Some(number(arg, args.value(arg)?)?),
+                    "--consensus" => ask.consensus = Some(strategy(args.value(arg)?)?),
+                    "--synthesize" => ask.synthesize = Some(model_choice(args.value(arg)?)),
+                    "--no-cache" => ask.bypass_cache = true,
+                    "--json" => ask.json = true,
+                    "-" => positional.push(arg.clone()),
+                    _ if arg.starts_with('-') => return Err(format!("unknown option `{arg}`")),
+                    _ => positional.push(arg.clone()),
+                }
+            }
+            if positional.is_empty() {
+                return Err("ask needs a prompt".into());
+            }
+            ask.prompt = positional.join(" ");
+            Ok(Command::Ask(ask))
+        }
+        "export" => {
+            let mut format = ExportFormat::Markdown;
+            let mut output = None;
+            while let Some(arg) = args.rest.next() {
+                match arg.as_str() {
+                    "-f" | "--format" => format = self::format(args.value(arg)?)?,
+                    "-o" | "--output" => output = Some(PathBuf::from(args.value(arg)?)),
+                    _ if arg.starts_with('-') => return Err(format!("unknown option `{arg}`")),
+                    _ => positional.push(arg.clone()),
+                }
+            }
+            let [id] = <[String; 1]>::try_from(positional)
+                .map_err(|_| "export needs exactly one conversation id".to_string())?;
+            Ok(Command::Export { id, format, output })
+        }
+        "conversations" | "providers" => {
+            for arg in args.rest {
+                match arg.as_str() {
+                    "--json" => json = true,
+                    _ => return Err(format!("unknown argument `{arg}`")),
+                }
+            }
+            Ok(if command == "providers" {
+                Command::Providers { json }
+            } else {
+                Command::Conversations { json }
+            })
+        }
+        "help" | "-h" | "--help" => Ok(Command::Help),
+        _ => Err(format!("unknown command `{command}`")),
+    }
+}
+
+/// Everything `ask --json` prints.
+#[derive(Serialize)]
+struct AskOutput {
+    results: Vec<FanoutResult>,
+    ranking: Option<Ranking>,
+    synthesis: Option<ChatResponse>,
+}
+
+fn print_json(value: &impl Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+async fn ask(app: &AppHandle, ask: Ask) -> Result<()> {
+    let prompt = if ask.prompt == "-" {
+        let mut prompt = String::new();
+        std::io::stdin().read_to_string(&mut prompt)?;
+        prompt
+    } else {
+        ask.prompt
+    };
+    let mut messages = Vec::new();
+    if let Some(system) = ask.system {
+        messages.push(ChatMessage {
+            role: Role::System,
+            content: system,
+        });
+    }
+    messages.push(ChatMessage {
+        role: Role::User,
+        content: prompt,
+    });
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let request = FanoutRequest {
+        targets: ask.targets,
+        messages: messages.clone(),
+        temperature: ask.temperature,
+        top_p: None,
+        max_tokens: ask.max_tokens,
+        preset_id: ask.preset_id,
+        use_tools: false,
+        bypass_cache: ask.bypass_cache,
+        conversation_id: None,
+    };
+    let results = fanout::fan_out(app, &request_id, &request).await?;
+
+    let candidates: Vec<Candidate> = results
+        .iter()
+        .filter_map(|r| {
+            Some(Candidate {
+                provider: r.provider.clone(),
+                model: r.model.clone(),
+                content: r.content.clone()?,
+            })
+        })
+        .collect();
+    if candidates.is_empty() {
+        report(&results, None, None, ask.json)?;
+        return Err(Error::Provider("no provider answered".into()));
+    }
+    let ranking = match ask.consensus {
+        Some(strategy) => Some(
+            arbiter::rank_responses(
+                app.clone(),
+                app.state(),
+                app.state(),
+                RankRequest {
+                    messages: messages.clone(),
+                    candidates: candidates.clone(),
+                    strategy,
+                },
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    let synthesis = match ask.synthesize {
+        Some(synthesizer) => Some(
+            arbiter::synthesize_answer(
+                app.clone(),
+                app.state(),
+                app.state(),
+                app.state(),
+                uuid::Uuid::new_v4().to_string(),
+                SynthesisRequest {
+                    messages,
+                    candidates,
+                    synthesizer,
+                    temperature: ask.temperature,
+                    max_tokens: ask.max_tokens,
+                },
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    report(&results, ranking, synthesis, ask.json)
+}
+
+fn report(
+    results: &[FanoutResult],
+    ranking: Option<Ranking>,
+    synthesis: Option<ChatResponse>,
+    json: bool,
+) -> Result<()> {
+    if json {
+        return print_json(&AskOutput {
+            results: results.to_vec(),
+            ranking,
+            synthesis,
+        });
+    }
+    for result in results {
+        println!("## {} ({})\n", result.provider, result.model);
+        match (&result.content, &result.error) {
+            (Some(content), _) => println!("{}\n", content.trim_end()),
+            (None, Some(error)) => println!("error: {error}\n"),
+            (None, None) => println!("(no answer)\n"),
+        }
+    }
+    if let Some(ranking) = ranking {
+        match ranking.agreement {
+            Some(agreement) => println!("## Consensus ({:.0}% agree)\n", agreement * 100.0),
+            None => println!("## Consensus\n"),
+        }
+        for (place, response) in ranking.responses.iter().enumerate() {
+            print!(
+                "{}. {} ({}) {:.2}",
+                place + 1,
+                response.provider,
+                response.model,
+                response.score
+            );
+            match &response.reason {
+                Some(reason) => println!(": {reason}"),
+                None => println!(),
+            }
+        }
+        println!();
+    }
+    if let Some(synthesis) = synthesis {
+        println!(
+            "## Synthesis ({} {})\n",
+            synthesis.provider, synthesis.model
+        );
+        println!("{}", synthesis.content.trim_end());
+    }
+    Ok(())
+}
+
+/// Runs `command`, returning the process exit code.
+pub async fn execute(app: &AppHandle, command: Command) -> i32 {
+    let outcome = match command {
+        Command::Ask(request) => ask(app, request).await,
+        Command::Export { id, format, output } => export(app, &id, format, output),
+        Command::Conversations { json } => conversations(app, json),
+        Command::Providers { json } => list_providers(app, json),
+        Command::Help => {
+            println!("{USAGE}");
+            Ok(())
+        }
+    };
+    match outcome {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("pentamind: {err}");
+            1
+        }
+    }
+}
+
+fn export(app: &AppHandle, id: &str, format: ExportFormat, output: Option<PathBuf>) -> Result<()> {
+    let detail = app.state::<Database>().get_conversation(id)?;
+    let bytes = export::render(&detail, format)?;
+    match output {
+        Some(path) => std::fs::write(path, bytes)?,
+        None => std::io::stdout().write_all(&bytes)?,
+    }
+    Ok(())
+}
+
+fn conversations(app: &AppHandle, json: bool) -> Result<()> {
+    let conversations = app.state::<Database>().list_conversations()?;
+    if json {
+        return print_json(&conversations);
+    }
+    for conversation in conversations {
+        println!("{}\t{}", conversation.id, conversation.title);
+    }
+    Ok(())
+}
+
+fn list_providers(app: &AppHandle, json: bool) -> Result<()> {
+    let providers = providers::list_providers(app.state());
+    if json {
+        return print_json(&providers);
+    }
+    for provider in providers {
+        let status = if provider.configured {
+            "configured"
+        } else {
+            "not configured"
+        };
+        println!("{}\t{}\t{status}", provider.id, provider.default_model);
+    }
+    Ok(())
+}
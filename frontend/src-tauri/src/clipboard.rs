@@ -0,0 +1,387 @@
+//! System clipboard access from the backend: copying responses in different
+//! shapes, and an opt-in history of what the user copied elsewhere so a
+//! prompt can refer to it. The webview's clipboard API can write but can't
+//! watch for changes made in other apps.
+//!
+//! The clipboard is driven through each platform's own tools (`pbcopy` on
+//! macOS, PowerShell on Windows, `wl-clipboard`, `xclip` or `xsel` on Linux).
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::markdown;
+use crate::process::command;
+use crate::storage::{now_ms, Database};
+
+const CONFIG_FILE: &str = "clipboard.json";
+const POLL_INTERVAL: Duration = Duration::from_millis(1000);
+/// Larger clipboard contents aren't worth keeping as prompt context.
+const MAX_ENTRY_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    /// Entries kept, newest first.
+    pub limit: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            limit: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClipboardEntry {
+    pub text: String,
+    pub copied_at: i64,
+}
+
+/// Clipboard history. Entries live in memory only and are gone on quit.
+#[derive(Default)]
+pub struct ClipboardHistory {
+    config: Mutex<HistoryConfig>,
+    entries: Mutex<VecDeque<ClipboardEntry>>,
+    watcher: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// A clipboard program and its arguments.
+type Tool = (&'static str, &'static [&'static str]);
+
+/// Programs that can set the clipboard from stdin, in order of preference.
+fn write_tools() -> Vec<Tool> {
+    if cfg!(target_os = "macos") {
+        vec![("pbcopy", &[])]
+    } else if cfg!(windows) {
+        vec![(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "[Console]::InputEncoding = [Text.Encoding]::UTF8; \
+                 Set-Clipboard -Value ([Console]::In.ReadToEnd())",
+            ],
+        )]
+    } else {
+        let mut tools: Vec<Tool> = Vec::new();
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            tools.push(("wl-copy", &[]));
+        }
+        tools.push(("xclip", &["-selection", "clipboard"]));
+        tools.push(("xsel", &["--clipboard", "--input"]));
+        tools
+    }
+}
+
+/// Programs that print the clipboard's text, in order of preference.
+fn read_tools() -> Vec<Tool> {
+    if cfg!(target_os = "macos") {
+        vec![("pbpaste", &[])]
+    } else if cfg!(windows) {
+        vec![(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "[Console]::OutputEncoding = [Text.Encoding]::UTF8; Get-Clipboard -Raw",
+            ],
+        )]
+    } else {
+        let mut tools: Vec<Tool> = Vec::new();
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            tools.push(("wl-paste", &["--no-newline"]));
+        }
+        tools.push(("xclip", &["-selection", "clipboard", "-o"]));
+        tools.push(("xsel", &["--clipboard", "--output"]));
+        tools
+    }
+}
+
+/// Programs that write the clipboard's image to stdout (as hex on macOS,
+/// where AppleScript is the only way in), in order of preference.
+fn image_tools() -> Vec<Tool> {
+    if cfg!(target_os = "macos") {
+        vec![("osascript", &["-e", "the clipboard as «class PNGf»"])]
+    } else if cfg!(windows) {
+        vec![(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "Add-Type -AssemblyName System.Windows.Forms, System.Drawing; \
+                 $image = [Windows.Forms.Clipboard]::GetImage(); \
+                 if ($image -eq $null) { exit 1 }; \
+                 $png = New-Object IO.MemoryStream; \
+                 $image.Save($png, [Drawing.Imaging.ImageFormat]::Png); \
+                 $out = [Console]::OpenStandardOutput(); \
+                 $out.Write($png.ToArray(), 0, $png.Length); $out.Flush()",
+            ],
+        )]
+    } else {
+        let mut tools: Vec<Tool> = Vec::new();
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            tools.push(("wl-paste", &["--type", "image/png"]));
+        }
+        tools.push((
+            "xclip",
+            &["-selection", "clipboard", "-t", "image/png", "-o"],
+        ));
+        tools
+    }
+}
+
+fn unavailable() -> Error {
+    Error::Unsupported(
+        "clipboard access without pbcopy, PowerShell, wl-clipboard, xclip or xsel".into(),
+    )
+}
+
+pub fn write_text(text: &str) -> Result<()> {
+    for (program, args) in write_tools() {
+        let Ok(mut child) = command(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            continue;
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        if child.wait()?.success() {
+            return Ok(());
+        }
+    }
+    Err(unavailable())
+}
+
+/// The clipboard's text, or `None` when it holds something else.
+pub fn read_text() -> Result<Option<String>> {
+    for (program, args) in read_tools() {
+        let Ok(output) = command(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+        else {
+            continue;
+        };
+        if !output.status.success() {
+            // wl-paste and xclip fail when there is no text to paste.
+            return Ok(None);
+        }
+        let text = String::from_utf8_lossy(&output.stdout).into_owned();
+        return Ok(Some(text).filter(|t| !t.is_empty()));
+    }
+    Err(unavailable())
+}
+
+/// The clipboard's image as PNG, or `None` when it holds something else.
+pub fn read_image() -> Result<Option<Vec<u8>>> {
+    for (program, args) in image_tools() {
+        let Ok(output) = command(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+        else {
+            continue;
+        };
+        if !output.status.success() || output.stdout.is_empty() {
+            return Ok(None);
+        }
+        if !cfg!(target_os = "macos") {
+            return Ok(Some(output.stdout));
+        }
+        // «data PNGf89504E47…»
+        let printed = String::from_utf8_lossy(&output.stdout);
+        let Some(hex) = printed
+            .trim()
+            .strip_prefix("«data PNGf")
+            .and_then(|h| h.strip_suffix('»'))
+        else {
+            return Ok(None);
+        };
+        let bytes = (0..hex.len() / 2)
+            .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16))
+            .collect::<std::result::Result<Vec<_>, _>>();
+        return Ok(bytes.ok());
+    }
+    Err(unavailable())
+}
+
+impl ClipboardHistory {
+    /// Adds `text` unless it repeats the newest entry, returning the new entry.
+    fn push(&self, text: String) -> Option<ClipboardEntry> {
+        let limit = self.config.lock().unwrap().limit;
+        let mut entries = self.entries.lock().unwrap();
+        if text.trim().is_empty()
+            || text.len() > MAX_ENTRY_BYTES
+            || entries.front().is_some_and(|e| e.text == text)
+        {
+            return None;
+        }
+        let entry = ClipboardEntry {
+            text,
+            copied_at: now_ms(),
+        };
+        entries.push_front(entry.clone());
+        entries.truncate(limit);
+        Some(entry)
+    }
+}
+
+/// Polls the clipboard, emitting `clipboard-changed` with each new entry.
+fn start_watcher(app: &AppHandle) -> JoinHandle<()> {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut last = tauri::async_runtime::spawn_blocking(read_text)
+            .await
+            .ok()
+            .and_then(Result::ok)
+            .flatten();
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let Ok(read) = tauri::async_runtime::spawn_blocking(read_text).await else {
+                continue;
+            };
+            let current = match read {
+                Ok(current) => current,
+                // No clipboard tool; polling won't start working later.
+                Err(_) => break,
+            };
+            if current == last {
+                continue;
+            }
+            last.clone_from(&current);
+            let history = app.state::<ClipboardHistory>();
+            if let Some(entry) = current.and_then(|text| history.push(text)) {
+                let _ = app.emit("clipboard-changed", entry);
+            }
+        }
+    })
+}
+
+fn apply(app: &AppHandle, history: &ClipboardHistory) {
+    let enabled = history.config.lock().unwrap().enabled;
+    let mut watcher = history.watcher.lock().unwrap();
+    match (enabled, watcher.is_some()) {
+        (true, false) => *watcher = Some(start_watcher(app)),
+        (false, true) => {
+            if let Some(handle) = watcher.take() {
+                handle.abort();
+            }
+            history.entries.lock().unwrap().clear();
+        }
+        _ => {}
+    }
+}
+
+/// Loads the history settings and starts watching if the user opted in.
+pub fn init(app: &AppHandle) {
+    let config = config::read::<HistoryConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(ClipboardHistory {
+        config: Mutex::new(config),
+        ..Default::default()
+    });
+    apply(app, &app.state::<ClipboardHistory>());
+}
+
+async fn copy(text: String) -> Result<()> {
+    tauri::async_runtime::spawn_blocking(move || write_text(&text))
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?
+}
+
+/// Copies a message as plain text, without Markdown syntax.
+#[tauri::command]
+pub async fn copy_response(db: State<'_, Database>, message_id: String) -> Result<()> {
+    let message = db.get_message(&message_id)?;
+    copy(markdown::plain_text(&message.content)).await
+}
+
+#[tauri::command]
+pub async fn copy_as_markdown(db: State<'_, Database>, message_id: String) -> Result<()> {
+    let message = db.get_message(&message_id)?;
+    copy(message.content).await
+}
+
+/// Copies the message's code block at `index`, or all of them separated by
+/// blank lines.
+#[tauri::command]
+pub async fn copy_as_code(
+    db: State<'_, Database>,
+    message_id: String,
+    index: Option<usize>,
+) -> Result<()> {
+    let message = db.get_message(&message_id)?;
+    let blocks = markdown::code_blocks(&message.content);
+    let code = match index {
+        Some(index) => blocks
+            .into_iter()
+            .nth(index)
+            .map(|block| block.code)
+            .ok_or_else(|| Error::NotFound(format!("code block {index}")))?,
+        None if blocks.is_empty() => return Err(Error::NotFound("code block".into())),
+        None => blocks
+            .into_iter()
+            .map(|block| block.code)
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+    };
+    copy(code).await
+}
+
+#[tauri::command]
+pub async fn get_clipboard_history(
+    history: State<'_, ClipboardHistory>,
+) -> Result<Vec<ClipboardEntry>> {
+    Ok(history.entries.lock().unwrap().iter().cloned().collect())
+}
+
+#[tauri::command]
+pub async fn clear_clipboard_history(history: State<'_, ClipboardHistory>) -> Result<()> {
+    history.entries.lock().unwrap().clear();
+    Ok(())
+}
+
+/// Turns the history watcher on or off. Turning it off also forgets what
+/// was recorded.
+#[tauri::command]
+pub async fn set_clipboard_history(
+    app: AppHandle,
+    history: State<'_, ClipboardHistory>,
+    enabled: bool,
+    limit: Option<usize>,
+) -> Result<HistoryConfig> {
+    let config = {
+        let mut config = history.config.lock().unwrap();
+        config.enabled = enabled;
+        if let Some(limit) = limit {
+            config.limit = limit.max(1);
+        }
+        config::write(&app, CONFIG_FILE, &*config)?;
+        config.clone()
+    };
+    history.entries.lock().unwrap().truncate(config.limit);
+    apply(&app, &history);
+    Ok(config)
+}
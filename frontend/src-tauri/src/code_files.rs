@@ -0,0 +1,396 @@
+//! Saves the fenced code blocks of a response as files under a folder.
+//! A block's file name comes from its info string (`rust src/main.rs`,
+//! `title="src/main.rs"`), from a leading comment that names it, or from
+//! the line of prose just before it; blocks without one can be saved as
+//! numbered snippets with an extension for their language.
+//!
+//! Existing files are only replaced when asked. Either way each one gets a
+//! `code-block-diff` event with a line diff against what is on disk, so the
+//! UI can preview the change and ask again with `overwrite`.
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter};
+
+use crate::diff::{self, DiffSpan, Granularity};
+use crate::error::{Error, Result};
+use crate::markdown::{self, CodeBlock};
+
+const MAX_PATH_LEN: usize = 255;
+/// File names without an extension that still name a file.
+const BARE_NAMES: &[&str] = &[
+    "Makefile",
+    "Dockerfile",
+    "Gemfile",
+    "Procfile",
+    "Rakefile",
+    "Justfile",
+    ".gitignore",
+    ".env",
+];
+const PATH_KEYS: &[&str] = &["title=", "file=", "filename=", "path="];
+/// Comment openers a first line naming the file may start with.
+const COMMENT_MARKERS: &[&str] = &["//", "#", "--", "/*", "<!--", ";"];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SaveOptions {
+    /// Indices of the blocks to save; all of them by default.
+    pub blocks: Option<Vec<usize>>,
+    /// Replace files that already exist.
+    pub overwrite: bool,
+    /// Work out what would be written and send the diffs, but write
+    /// nothing.
+    pub dry_run: bool,
+    /// Save blocks without a file name as `snippet-<n>.<ext>`.
+    pub name_unnamed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SaveStatus {
+    Created,
+    Overwritten,
+    /// The file already held exactly this.
+    Unchanged,
+    /// The file exists and `overwrite` wasn't set.
+    Conflict,
+    /// Not saved; `reason` says why.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedBlock {
+    pub index: usize,
+    pub language: Option<String>,
+    /// Relative to the folder.
+    pub path: Option<String>,
+    pub status: SaveStatus,
+    pub reason: Option<String>,
+    pub bytes: usize,
+}
+
+/// Payload of the `code-block-diff` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct CodeBlockDiff {
+    pub index: usize,
+    pub path: String,
+    /// Deleted spans are on disk, inserted ones in the block.
+    pub spans: Vec<DiffSpan>,
+    pub similarity: f32,
+    /// The file was replaced; otherwise this is only a preview.
+    pub written: bool,
+}
+
+fn extension(language: &str) -> Option<&'static str> {
+    Some(match language.to_ascii_lowercase().as_str() {
+        "rust" | "rs" => "rs",
+        "python" | "py" => "py",
+        "javascript" | "js" | "node" => "js",
+        "typescript" | "ts" => "ts",
+        "tsx" => "tsx",
+        "jsx" => "jsx",
+        "go" | "golang" => "go",
+        "java" => "java",
+        "kotlin" | "kt" => "kt",
+        "swift" => "swift",
+        "c" => "c",
+        "cpp" | "c++" | "cc" => "cpp",
+        "csharp" | "cs" | "c#" => "cs",
+        "ruby" | "rb" => "rb",
+        "php" => "php",
+        "bash" | "sh" | "shell" | "zsh" => "sh",
+        "sql" => "sql",
+        "html" => "html",
+        "css" => "css",
+        "scss" => "scss",
+        "json" => "json",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "xml" => "xml",
+        "markdown" | "md" => "md",
+        "diff" | "patch" => "diff",
+        "text" | "txt" | "plaintext" => "txt",
+        _ => return None,
+    })
+}
+
+fn looks_like_path(text: &str) -> bool {
+    if text.is_empty()
+        || text.len() > MAX_PATH_LEN
+        || text.contains("://")
+        || text
+            .chars()
+            .any(|c| c.is_whitespace() || "\"'<>|*?`".contains(c))
+    {
+        return false;
+    }
+    let name = text.rsplit('/').next().unwrap_or(text);
+    if BARE_NAMES.contains(&name) {
+        return true;
+    }
+    match name.rsplit_once('.') {
+        Some((stem, ext)) => {
+            !stem.is_empty()
+                && (1..=10).contains(&ext.len())
+                && ext.chars().all(|c| c.is_ascii_alphanumeric())
+        }
+        None => false,
+    }
+}
+
+fn trim_quotes(text: &str) -> &str {
+    text.trim_matches(|c| c == '"' || c == '\'' || c == '`')
+}
+
+/// A file name in the info string: after the language, after `:`, or as
+/// `title="..."`.
+fn path_from_info(info: &str) -> Option<String> {
+    for word in info.split_whitespace() {
+        for key in PATH_KEYS {
+            if let Some(value) = word.strip_prefix(key) {
+                let value = trim_quotes(value);
+                if looks_like_path(value) {
+                    return Some(value.to_string());
+                }
+            }
+        }
+    }
+    let mut words = info.split_whitespace();
+    let first = words.next()?;
+    if let Some((_, path)) = first.split_once(':') {
+        if looks_like_path(path) {
+            return Some(path.to_string());
+        }
+    }
+    [first]
+        .into_iter()
+        .chain(words)
+        .map(trim_quotes)
+        .find(|word| looks_like_path(word))
+        .map(str::to_string)
+}
+
+/// A file name in a leading comment such as `// src/main.rs` or
+/// `# File: app.py`.
+fn path_from_comment(first_line: &str) -> Option<String> {
+    let line = first_line.trim();
+    let marker = COMMENT_MARKERS.iter().find(|m| line.starts_with(*m))?;
+    let text = line[marker.len()..]
+        .trim_end_matches("-->")
+        .trim_end_matches("*/")
+        .trim();
+    let text = ["file:", "File:", "filename:", "path:"]
+        .iter()
+        .find_map(|prefix| text.strip_prefix(prefix))
+        .unwrap_or(text)
+        .trim();
+    let text = trim_quotes(text);
+    looks_like_path(text).then(|| text.to_string())
+}
+
+/// A file name the prose before the block gives: the whole line, as in
+/// `**src/main.rs**`, or a code span in it, as in "update `src/main.rs`:".
+fn path_from_caption(caption: &str) -> Option<String> {
+    let spans = caption.split('`').skip(1).step_by(2);
+    if let Some(path) = spans.filter(|s| looks_like_path(s)).last() {
+        return Some(path.to_string());
+    }
+    let cleaned = caption
+        .trim_start_matches(['#', '>', '-', ' '])
+        .trim_matches(|c| c == '*' || c == '_')
+        .trim_end_matches(':')
+        .trim_matches(|c| c == '*' || c == '_');
+    let cleaned = cleaned
+        .strip_prefix("File:")
+        .or_else(|| cleaned.strip_prefix("file:"))
+        .unwrap_or(cleaned)
+        .trim();
+    looks_like_path(cleaned).then(|| cleaned.to_string())
+}
+
+/// Where `block` goes, and its code without a leading comment that only
+/// named the file.
+fn target(block: &CodeBlock) -> (Option<String>, String) {
+    if let Some(path) = path_from_info(&block.info) {
+        return (Some(path), block.code.clone());
+    }
+    let (first, rest) = block.code.split_once('\n').unwrap_or((&block.code, ""));
+    if let Some(path) = path_from_comment(first) {
+        return (Some(path), rest.to_string());
+    }
+    let path = block.caption.as_deref().and_then(path_from_caption);
+    (path, block.code.clone())
+}
+
+/// `relative` under `root`, refusing anything that would end up outside
+/// it, through `..` or a symlink.
+pub(crate) fn resolve(root: &Path, relative: &str) -> std::result::Result<PathBuf, String> {
+    let relative = Path::new(relative);
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err("the path leaves the folder".into());
+    }
+    let path = root.join(relative);
+    let parent = path.parent().unwrap_or(root);
+    let existing = parent
+        .ancestors()
+        .find(|dir| dir.exists())
+        .and_then(|dir| std::fs::canonicalize(dir).ok());
+    if !existing.is_some_and(|dir| dir.starts_with(root)) {
+        return Err("the path leaves the folder".into());
+    }
+    // Writing through a symlink, even a dangling one, lands wherever it
+    // points.
+    if path
+        .symlink_metadata()
+        .is_ok_and(|meta| meta.file_type().is_symlink())
+    {
+        return Err("the path is a symlink".into());
+    }
+    if path.is_dir() {
+        return Err("a folder has that name".into());
+    }
+    Ok(path)
+}
+
+/// `code` as the file should hold it: ending in a newline, and with CRLF
+/// line endings if the file it replaces used them.
+fn file_contents(code: &str, existing: Option<&str>) -> String {
+    let mut text = code.replace("\r\n", "\n");
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    match existing {
+        Some(old) if old.contains("\r\n") => text.replace('\n', "\r\n"),
+        _ => text,
+    }
+}
+
+fn save(app: &AppHandle, content: &str, root: &Path, options: &SaveOptions) -> Vec<SavedBlock> {
+    let blocks = markdown::code_blocks(content);
+    let wanted = |index: usize| {
+        options
+            .blocks
+            .as_ref()
+            .is_none_or(|indices| indices.contains(&index))
+    };
+    let planned: Vec<(usize, CodeBlock, Option<String>, String)> = blocks
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| wanted(*index))
+        .map(|(index, block)| {
+            let (path, code) = target(&block);
+            let path = path.or_else(|| {
+                let ext = block.language.as_deref().and_then(extension)?;
+                options
+                    .name_unnamed
+                    .then(|| format!("snippet-{}.{ext}", index + 1))
+            });
+            (index, block, path, code)
+        })
+        .collect();
+    // A file shown more than once is saved from its last version.
+    let mut last: HashMap<&str, usize> = HashMap::new();
+    for (index, _, path, _) in &planned {
+        if let Some(path) = path {
+            last.insert(path.as_str(), *index);
+        }
+    }
+
+    let mut saved = Vec::new();
+    for (index, block, path, code) in &planned {
+        let mut result = SavedBlock {
+            index: *index,
+            language: block.language.clone(),
+            path: path.clone(),
+            status: SaveStatus::Skipped,
+            reason: None,
+            bytes: 0,
+        };
+        let Some(relative) = path else {
+            result.reason = Some("no file name".into());
+            saved.push(result);
+            continue;
+        };
+        if last[relative.as_str()] != *index {
+            result.reason = Some(format!(
+                "block {} is saved there instead",
+                last[relative.as_str()]
+            ));
+            saved.push(result);
+            continue;
+        }
+        let path = match resolve(root, relative) {
+            Ok(path) => path,
+            Err(reason) => {
+                result.reason = Some(reason);
+                saved.push(result);
+                continue;
+            }
+        };
+        let existing = std::fs::read_to_string(&path).ok();
+        let exists = path.exists();
+        let contents = file_contents(code, existing.as_deref());
+        result.bytes = contents.len();
+        result.status = match &existing {
+            Some(old) if *old == contents => SaveStatus::Unchanged,
+            _ if exists && !options.overwrite => SaveStatus::Conflict,
+            _ if exists => SaveStatus::Overwritten,
+            _ => SaveStatus::Created,
+        };
+        if exists && result.status != SaveStatus::Unchanged {
+            let (spans, similarity) = diff::diff_pair(
+                existing.as_deref().unwrap_or_default(),
+                &contents,
+                Granularity::Line,
+            );
+            let _ = app.emit(
+                "code-block-diff",
+                CodeBlockDiff {
+                    index: *index,
+                    path: relative.clone(),
+                    spans,
+                    similarity,
+                    written: result.status == SaveStatus::Overwritten && !options.dry_run,
+                },
+            );
+        }
+        let write = matches!(result.status, SaveStatus::Created | SaveStatus::Overwritten);
+        if write && !options.dry_run {
+            let outcome = path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| std::fs::write(&path, &contents));
+            if let Err(err) = outcome {
+                result.status = SaveStatus::Skipped;
+                result.reason = Some(err.to_string());
+            }
+        }
+        saved.push(result);
+    }
+    saved
+}
+
+/// Saves the code blocks in `content` under `root`, reporting what became
+/// of each.
+#[tauri::command]
+pub async fn save_code_blocks(
+    app: AppHandle,
+    content: String,
+    root: PathBuf,
+    options: Option<SaveOptions>,
+) -> Result<Vec<SavedBlock>> {
+    let root = std::fs::canonicalize(&root)
+        .ok()
+        .filter(|root| root.is_dir())
+        .ok_or_else(|| Error::NotFound(format!("folder {}", root.display())))?;
+    let options = options.unwrap_or_default();
+    let saved =
+        tauri::async_runtime::spawn_blocking(move || save(&app, &content, &root, &options)).await?;
+    Ok(saved)
+}
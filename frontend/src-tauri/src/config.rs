@@ -0,0 +1,46 @@
+//! Small JSON files kept in the active profile's config directory.
+
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tauri::AppHandle;
+
+use crate::error::Result;
+use crate::profile;
+
+/// Reads `name` from the config directory; `None` if it doesn't exist yet.
+pub fn read<T: DeserializeOwned>(app: &AppHandle, name: &str) -> Result<Option<T>> {
+    read_in(&profile::config_dir(app)?, name)
+}
+
+/// Writes `value` to `name` in the config directory.
+pub fn write<T: Serialize>(app: &AppHandle, name: &str, value: &T) -> Result<()> {
+    write_in(&profile::config_dir(app)?, name, value)
+}
+
+pub fn remove(app: &AppHandle, name: &str) -> Result<()> {
+    match std::fs::remove_file(profile::config_dir(app)?.join(name)) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Reads `name` from `dir`; `None` if it doesn't exist yet.
+pub fn read_in<T: DeserializeOwned>(dir: &Path, name: &str) -> Result<Option<T>> {
+    match std::fs::read(dir.join(name)) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Writes `value` to `name` in `dir`, going through a temp file so a crash
+/// mid-write never leaves a truncated file behind.
+pub fn write_in<T: Serialize>(dir: &Path, name: &str, value: &T) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let tmp = dir.join(format!("{name}.tmp"));
+    std::fs::write(&tmp, serde_json::to_vec_pretty(value)?)?;
+    std::fs::rename(tmp, dir.join(name))?;
+    Ok(())
+}
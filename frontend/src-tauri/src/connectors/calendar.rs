@@ -0,0 +1,330 @@
+//! Calendar events from a CalDAV collection or an ICS feed. CalDAV servers
+//! are asked for the events in the window with recurrences expanded; a feed
+//! is read whole and filtered here, with a repeating event kept once, as
+//! written.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Utc};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Method, RequestBuilder, Response, Url};
+
+use crate::error::{Error, Result};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+const CALDAV_TIME: &str = "%Y%m%dT%H%M%SZ";
+
+#[derive(Debug, Clone)]
+pub(super) enum When {
+    Utc(DateTime<Utc>),
+    /// A local time in the named zone, or wherever the reader is.
+    Floating(NaiveDateTime, Option<String>),
+    AllDay(NaiveDate),
+}
+
+impl When {
+    fn parse(value: &str, params: &[(String, String)]) -> Option<Self> {
+        let value = value.trim();
+        if value.len() == 8 || params.iter().any(|(k, v)| k == "VALUE" && v == "DATE") {
+            return NaiveDate::parse_from_str(&value[..value.len().min(8)], "%Y%m%d")
+                .ok()
+                .map(Self::AllDay);
+        }
+        let (value, utc) = match value.strip_suffix('Z') {
+            Some(value) => (value, true),
+            None => (value, false),
+        };
+        let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+        Some(if utc {
+            Self::Utc(time.and_utc())
+        } else {
+            let zone = params
+                .iter()
+                .find(|(k, _)| k == "TZID")
+                .map(|(_, v)| v.clone());
+            Self::Floating(time, zone)
+        })
+    }
+
+    /// Close enough to place the event in the window; zones are ignored.
+    fn approx_ms(&self) -> i64 {
+        match self {
+            Self::Utc(time) => time.timestamp_millis(),
+            Self::Floating(time, _) => time.and_utc().timestamp_millis(),
+            Self::AllDay(date) => date
+                .and_hms_opt(0, 0, 0)
+                .unwrap_or_default()
+                .and_utc()
+                .timestamp_millis(),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Utc(time) => time
+                .with_timezone(&Local)
+                .format("%a %-d %b %Y, %H:%M")
+                .to_string(),
+            Self::Floating(time, Some(zone)) => {
+                format!("{} ({zone})", time.format("%a %-d %b %Y, %H:%M"))
+            }
+            Self::Floating(time, None) => time.format("%a %-d %b %Y, %H:%M").to_string(),
+            Self::AllDay(date) => format!("{} (all day)", date.format("%a %-d %b %Y")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct CalendarEvent {
+    pub uid: String,
+    /// Which occurrence of a repeating event this is.
+    pub recurrence_id: Option<String>,
+    pub summary: String,
+    pub start: Option<When>,
+    pub end: Option<When>,
+    pub location: Option<String>,
+    pub description: Option<String>,
+    pub organizer: Option<String>,
+    pub status: Option<String>,
+    pub rrule: Option<String>,
+}
+
+impl CalendarEvent {
+    fn overlaps(&self, from: i64, until: i64) -> bool {
+        let Some(start) = self.start.as_ref().map(When::approx_ms) else {
+            return false;
+        };
+        if self.rrule.is_some() {
+            return start <= until;
+        }
+        let end = self.end.as_ref().map_or(start, When::approx_ms);
+        start <= until && end >= from
+    }
+}
+
+/// A calendar server's address and login. The password goes to the
+/// server at `url` and nowhere else.
+pub(super) struct Calendar<'a> {
+    pub url: &'a Url,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+}
+
+impl Calendar<'_> {
+    fn request(&self, client: &Client, method: Method) -> RequestBuilder {
+        let request = client.request(method, self.url.clone()).timeout(TIMEOUT);
+        match self.username {
+            Some(username) => request.basic_auth(username, self.password),
+            None => request,
+        }
+    }
+
+    /// Checks the response came from the configured host. The client drops
+    /// the login on a redirect elsewhere, but the answer isn't ours either.
+    async fn read(&self, response: Response) -> Result<String> {
+        if response.url().host_str() != self.url.host_str() {
+            return Err(Error::Connector(format!(
+                "{} redirected to another host",
+                self.url.host_str().unwrap_or_default()
+            )));
+        }
+        let status = response.status();
+        if !status.is_success() {
+            return Err(Error::Connector(format!(
+                "{} returned {status}",
+                self.url.host_str().unwrap_or_default()
+            )));
+        }
+        Ok(response.text().await?)
+    }
+}
+
+/// Events from `from` to `until` in a CalDAV collection.
+pub(super) async fn fetch_caldav(
+    client: &Client,
+    calendar: &Calendar<'_>,
+    from: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> Result<Vec<CalendarEvent>> {
+    let (start, end) = (from.format(CALDAV_TIME), until.format(CALDAV_TIME));
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+  <d:prop>
+    <c:calendar-data><c:expand start="{start}" end="{end}"/></c:calendar-data>
+  </d:prop>
+  <c:filter>
+    <c:comp-filter name="VCALENDAR">
+      <c:comp-filter name="VEVENT"><c:time-range start="{start}" end="{end}"/></c:comp-filter>
+    </c:comp-filter>
+  </c:filter>
+</c:calendar-query>"#
+    );
+    let report = Method::from_bytes(b"REPORT").expect("REPORT is a valid method");
+    let response = calendar
+        .request(client, report)
+        .header("Depth", "1")
+        .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+        .body(body)
+        .send()
+        .await?;
+    let xml = calendar.read(response).await?;
+    Ok(calendar_data(&xml)?
+        .iter()
+        .flat_map(|ics| parse_ics(ics))
+        .collect())
+}
+
+/// Events from `from` to `until` in an ICS feed.
+pub(super) async fn fetch_ics(
+    client: &Client,
+    calendar: &Calendar<'_>,
+    from: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> Result<Vec<CalendarEvent>> {
+    let response = calendar.request(client, Method::GET).send().await?;
+    let ics = calendar.read(response).await?;
+    let (from, until) = (from.timestamp_millis(), until.timestamp_millis());
+    Ok(parse_ics(&ics)
+        .into_iter()
+        .filter(|event| event.overlaps(from, until))
+        .collect())
+}
+
+/// The calendar data in a CalDAV multistatus response.
+fn calendar_data(xml: &str) -> Result<Vec<String>> {
+    let invalid =
+        |e: quick_xml::Error| Error::Connector(format!("unreadable calendar response: {e}"));
+    let mut reader = Reader::from_str(xml);
+    let mut calendars = Vec::new();
+    let mut current: Option<String> = None;
+    loop {
+        match reader.read_event().map_err(invalid)? {
+            Event::Start(e) if e.local_name().as_ref() == b"calendar-data" => {
+                current = Some(String::new());
+            }
+            Event::End(e) if e.local_name().as_ref() == b"calendar-data" => {
+                calendars.extend(current.take());
+            }
+            Event::Text(text) => {
+                if let Some(current) = &mut current {
+                    current.push_str(&text.unescape().map_err(invalid)?);
+                }
+            }
+            Event::CData(data) => {
+                if let Some(current) = &mut current {
+                    current.push_str(&String::from_utf8_lossy(&data.into_inner()));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(calendars)
+}
+
+/// Content lines with folded continuations joined.
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// A content line's name, parameters and value.
+type Property = (String, Vec<(String, String)>, String);
+
+/// Splits a content line, minding quoted parameter values.
+fn property(line: &str) -> Option<Property> {
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(i, c)| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        (c == ':' && !quoted).then_some(i)
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let mut pieces = head.split(';');
+    let name = pieces.next()?.trim().to_ascii_uppercase();
+    let params = pieces
+        .filter_map(|piece| {
+            let (key, value) = piece.split_once('=')?;
+            Some((
+                key.trim().to_ascii_uppercase(),
+                value.trim_matches('"').to_string(),
+            ))
+        })
+        .collect();
+    Some((name, params, value.to_string()))
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+pub(super) fn parse_ics(text: &str) -> Vec<CalendarEvent> {
+    let mut events = Vec::new();
+    let mut current: Option<CalendarEvent> = None;
+    // Components inside the event, such as alarms, whose lines are skipped.
+    let mut nested = 0;
+    for line in unfold(text) {
+        let Some((name, params, value)) = property(&line) else {
+            continue;
+        };
+        let Some(event) = &mut current else {
+            if name == "BEGIN" && value.eq_ignore_ascii_case("VEVENT") {
+                current = Some(CalendarEvent::default());
+            }
+            continue;
+        };
+        match name.as_str() {
+            "BEGIN" => nested += 1,
+            "END" if nested > 0 => nested -= 1,
+            "END" => {
+                events.extend(current.take());
+                nested = 0;
+            }
+            _ if nested > 0 => {}
+            "UID" => event.uid = value,
+            "RECURRENCE-ID" => event.recurrence_id = Some(value),
+            "SUMMARY" => event.summary = unescape(&value),
+            "DTSTART" => event.start = When::parse(&value, &params),
+            "DTEND" => event.end = When::parse(&value, &params),
+            "LOCATION" => event.location = Some(unescape(&value)).filter(|v| !v.is_empty()),
+            "DESCRIPTION" => event.description = Some(unescape(&value)).filter(|v| !v.is_empty()),
+            "STATUS" => event.status = Some(value.to_ascii_lowercase()),
+            "RRULE" => event.rrule = Some(value),
+            "ORGANIZER" => {
+                let name = params
+                    .iter()
+                    .find(|(k, _)| k == "CN")
+                    .map(|(_, v)| v.clone());
+                let address = value
+                    .trim_start_matches("mailto:")
+                    .trim_start_matches("MAILTO:");
+                event.organizer = Some(name.unwrap_or_else(|| address.to_string()));
+            }
+            _ => {}
+        }
+    }
+    events
+}
@@ -0,0 +1,610 @@
+//! Just enough IMAP to read recent mail: log in over TLS, open a mailbox
+//! read-only, search it by date and fetch the headers and the start of
+//! each body, which is then reduced to plain text.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+use crate::error::{Error, Result};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+const HEADER_FIELDS: &str = "FROM TO CC SUBJECT DATE CONTENT-TYPE CONTENT-TRANSFER-ENCODING";
+/// Bytes of each body fetched, which covers the gist of most mail.
+const BODY_BYTES: usize = 64 * 1024;
+const MAX_LINE: u64 = 64 * 1024;
+const MAX_LITERAL: usize = 4 * 1024 * 1024;
+/// Characters of body text kept per message.
+const MAX_TEXT: usize = 8000;
+/// How deep nested multiparts are followed.
+const MAX_DEPTH: usize = 4;
+
+/// Where to log in. The password goes to `host` and nowhere else.
+pub(super) struct Account<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub username: &'a str,
+    pub password: &'a str,
+    pub mailbox: &'a str,
+}
+
+pub(super) struct Mail {
+    pub uid_validity: u64,
+    pub uid: u64,
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    /// The `Date` header as sent.
+    pub date: String,
+    pub sent_at: Option<i64>,
+    pub text: String,
+}
+
+fn imap_error(message: impl Into<String>) -> Error {
+    Error::Connector(message.into())
+}
+
+async fn timed<T>(work: impl Future<Output = std::io::Result<T>>) -> Result<T> {
+    match tokio::time::timeout(TIMEOUT, work).await {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(imap_error("the mail server stopped responding")),
+    }
+}
+
+/// A server response, split around the literals it carries.
+#[derive(Default)]
+struct Response {
+    /// Each literal with the text leading up to it.
+    literals: Vec<(String, Vec<u8>)>,
+    /// The text after the last literal, or all of it.
+    tail: String,
+}
+
+impl Response {
+    fn head(&self) -> &str {
+        self.literals.first().map_or(&self.tail, |(text, _)| text)
+    }
+}
+
+/// The length of the literal a line ends with, as in `{123}`.
+fn literal_len(line: &str) -> Option<usize> {
+    let inner = line.strip_suffix('}')?;
+    let open = inner.rfind('{')?;
+    inner[open + 1..].trim_end_matches('+').parse().ok()
+}
+
+/// A quoted string. Line breaks can't be quoted, and no name or password
+/// needs one.
+fn quote(value: &str) -> Result<String> {
+    if value.contains(['\r', '\n', '\0']) {
+        return Err(imap_error("names and passwords can't contain line breaks"));
+    }
+    Ok(format!(
+        "\"{}\"",
+        value.replace('\\', "\\\\").replace('"', "\\\"")
+    ))
+}
+
+/// The number in a response code such as `[UIDVALIDITY 42]`.
+fn response_code(text: &str, code: &str) -> Option<u64> {
+    let start = text.find(&format!("[{code} "))? + code.len() + 2;
+    let digits: String = text[start..]
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok()
+}
+
+struct Session {
+    stream: BufReader<TlsStream<TcpStream>>,
+    tag: u32,
+}
+
+impl Session {
+    async fn connect(host: &str, port: u16) -> Result<Self> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let roots = RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| imap_error(e.to_string()))?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let name = ServerName::try_from(host.to_string())
+            .map_err(|_| imap_error(format!("{host} isn't a valid host name")))?;
+        let tcp = timed(TcpStream::connect((host, port))).await?;
+        let tls = timed(TlsConnector::from(Arc::new(config)).connect(name, tcp)).await?;
+        let mut session = Self {
+            stream: BufReader::new(tls),
+            tag: 0,
+        };
+        let greeting = session.read_response().await?;
+        if !greeting.head().starts_with("* OK") {
+            return Err(imap_error(format!(
+                "{host} didn't greet like an IMAP server"
+            )));
+        }
+        Ok(session)
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut line = Vec::new();
+        let read = timed(
+            (&mut self.stream)
+                .take(MAX_LINE)
+                .read_until(b'\n', &mut line),
+        )
+        .await?;
+        if read == 0 {
+            return Err(imap_error("the mail server closed the connection"));
+        }
+        if !line.ends_with(b"\n") {
+            return Err(imap_error("the mail server sent an overlong line"));
+        }
+        let text = String::from_utf8_lossy(&line);
+        Ok(text.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    async fn read_response(&mut self) -> Result<Response> {
+        let mut response = Response::default();
+        loop {
+            let line = self.read_line().await?;
+            let Some(len) = literal_len(&line) else {
+                response.tail = line;
+                return Ok(response);
+            };
+            if len > MAX_LITERAL {
+                return Err(imap_error("the mail server sent an oversized message"));
+            }
+            let mut literal = vec![0; len];
+            timed(self.stream.read_exact(&mut literal)).await?;
+            response.literals.push((line, literal));
+        }
+    }
+
+    /// Sends `command` and collects the untagged responses to it. Errors
+    /// name the command by `what`, so a password never ends up in one.
+    async fn command(&mut self, command: &str, what: &str) -> Result<Vec<Response>> {
+        self.tag += 1;
+        let tag = format!("a{} ", self.tag);
+        let stream = self.stream.get_mut();
+        timed(stream.write_all(format!("{tag}{command}\r\n").as_bytes())).await?;
+        timed(stream.flush()).await?;
+        let mut untagged = Vec::new();
+        loop {
+            let response = self.read_response().await?;
+            match response.head().strip_prefix(&tag) {
+                Some(status) if status.starts_with("OK") => return Ok(untagged),
+                Some(status) => return Err(imap_error(format!("{what} failed: {status}"))),
+                None => untagged.push(response),
+            }
+        }
+    }
+}
+
+/// The newest `limit` messages in the mailbox since `since`, oldest first.
+pub(super) async fn fetch(
+    account: &Account<'_>,
+    since: DateTime<Utc>,
+    limit: usize,
+) -> Result<Vec<Mail>> {
+    let mut session = Session::connect(account.host, account.port).await?;
+    session
+        .command(
+            &format!(
+                "LOGIN {} {}",
+                quote(account.username)?,
+                quote(account.password)?
+            ),
+            "logging in",
+        )
+        .await?;
+    let opened = session
+        .command(
+            &format!("EXAMINE {}", quote(account.mailbox)?),
+            "opening the mailbox",
+        )
+        .await?;
+    let uid_validity = opened
+        .iter()
+        .find_map(|r| response_code(r.head(), "UIDVALIDITY"))
+        .unwrap_or(0);
+
+    let found = session
+        .command(
+            &format!("UID SEARCH SINCE {}", since.format("%-d-%b-%Y")),
+            "searching the mailbox",
+        )
+        .await?;
+    let mut uids: Vec<u64> = found
+        .iter()
+        .filter_map(|r| r.head().strip_prefix("* SEARCH"))
+        .flat_map(|rest| rest.split_whitespace().filter_map(|n| n.parse().ok()))
+        .collect();
+    uids.sort_unstable();
+    let uids = &uids[uids.len().saturating_sub(limit)..];
+    if uids.is_empty() {
+        let _ = session.command("LOGOUT", "logging out").await;
+        return Ok(Vec::new());
+    }
+
+    let set = uids
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    let fetched = session
+        .command(
+            &format!(
+                "UID FETCH {set} (UID BODY.PEEK[HEADER.FIELDS ({HEADER_FIELDS})] BODY.PEEK[TEXT]<0.{BODY_BYTES}>)"
+            ),
+            "fetching messages",
+        )
+        .await?;
+    let _ = session.command("LOGOUT", "logging out").await;
+    let mut mail: Vec<Mail> = fetched
+        .iter()
+        .filter_map(|r| parse_fetch(r, uid_validity))
+        .collect();
+    mail.sort_by_key(|m| m.uid);
+    Ok(mail)
+}
+
+fn parse_fetch(response: &Response, uid_validity: u64) -> Option<Mail> {
+    if !response.head().contains(" FETCH (") {
+        return None;
+    }
+    let mut text = String::new();
+    let (mut header, mut body) = (None, None);
+    for (prefix, literal) in &response.literals {
+        text.push_str(prefix);
+        text.push(' ');
+        if prefix.contains("BODY[HEADER") {
+            header = Some(literal.as_slice());
+        } else if prefix.contains("BODY[TEXT]") {
+            body = Some(literal.as_slice());
+        }
+    }
+    text.push_str(&response.tail);
+    let uid = text
+        .split(|c: char| c == '(' || c.is_whitespace())
+        .skip_while(|word| *word != "UID")
+        .nth(1)?
+        .parse()
+        .ok()?;
+
+    let fields = parse_headers(header.unwrap_or_default());
+    let decoded = |name: &str| field(&fields, name).map(decode_words).unwrap_or_default();
+    let date = field(&fields, "date").unwrap_or_default().to_string();
+    let sent_at = DateTime::parse_from_rfc2822(date.trim())
+        .ok()
+        .map(|d| d.timestamp_millis());
+    let text = body
+        .and_then(|body| part_text(&fields, body, 0))
+        .map(|text| tidy(&text.into_string()))
+        .unwrap_or_default();
+    Some(Mail {
+        uid_validity,
+        uid,
+        from: decoded("from"),
+        to: [decoded("to"), decoded("cc")]
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(", "),
+        subject: decoded("subject"),
+        date,
+        sent_at,
+        text,
+    })
+}
+
+/// Header fields with their names lowercased and folded lines joined.
+fn parse_headers(raw: &[u8]) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in String::from_utf8_lossy(raw).lines() {
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            fields.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    fields
+}
+
+fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    fields
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.as_str())
+}
+
+/// A header value such as a content type: the lowercased value and its
+/// parameters.
+fn parameterized(value: &str) -> (String, Vec<(String, String)>) {
+    let mut pieces = value.split(';');
+    let main = pieces
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let params = pieces
+        .filter_map(|piece| {
+            let (name, value) = piece.split_once('=')?;
+            Some((
+                name.trim().to_ascii_lowercase(),
+                value.trim().trim_matches('"').to_string(),
+            ))
+        })
+        .collect();
+    (main, params)
+}
+
+fn param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    params
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.as_str())
+}
+
+fn decode_charset(bytes: &[u8], charset: &str) -> String {
+    match charset.to_ascii_lowercase().as_str() {
+        "iso-8859-1" | "latin1" | "latin-1" | "windows-1252" => {
+            bytes.iter().map(|&b| char::from(b)).collect()
+        }
+        _ => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+fn decode_quoted_printable(input: &[u8], underscores: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'=' => {
+                let rest = &input[i + 1..];
+                // A soft line break.
+                if rest.starts_with(b"\r\n") {
+                    i += 3;
+                } else if rest.starts_with(b"\n") {
+                    i += 2;
+                } else if let Some(byte) = rest
+                    .get(..2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    out.push(byte);
+                    i += 3;
+                } else {
+                    out.push(b'=');
+                    i += 1;
+                }
+            }
+            b'_' if underscores => {
+                out.push(b' ');
+                i += 1;
+            }
+            byte => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Base64 that may have been cut off partway, as fetched bodies are.
+fn decode_base64(input: &[u8]) -> Vec<u8> {
+    let clean: Vec<u8> = input
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    let whole = clean.len() / 4 * 4;
+    STANDARD.decode(&clean[..whole]).unwrap_or_default()
+}
+
+fn decode_transfer(body: &[u8], encoding: &str) -> Vec<u8> {
+    match encoding.trim().to_ascii_lowercase().as_str() {
+        "base64" => decode_base64(body),
+        "quoted-printable" => decode_quoted_printable(body, false),
+        _ => body.to_vec(),
+    }
+}
+
+/// One `=?charset?encoding?text?=` word, and how much of `text` it took.
+fn encoded_word(text: &str) -> Option<(String, usize)> {
+    let inner = text.strip_prefix("=?")?;
+    let (charset, rest) = inner.split_once('?')?;
+    let (encoding, rest) = rest.split_once('?')?;
+    let end = rest.find("?=")?;
+    let data = &rest[..end];
+    let bytes = match encoding {
+        "B" | "b" => decode_base64(data.as_bytes()),
+        "Q" | "q" => decode_quoted_printable(data.as_bytes(), true),
+        _ => return None,
+    };
+    let len = text.len() - rest.len() + end + 2;
+    // A language may follow the charset, as in `utf-8*en`.
+    let charset = charset.split('*').next().unwrap_or_default();
+    Some((decode_charset(&bytes, charset), len))
+}
+
+/// A header value with its encoded words decoded.
+fn decode_words(value: &str) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let (before, candidate) = rest.split_at(start);
+        match encoded_word(candidate) {
+            Some((text, len)) => {
+                // Space between two encoded words isn't part of the text.
+                if !(after_word && before.trim().is_empty()) {
+                    out.push_str(before);
+                }
+                out.push_str(&text);
+                rest = &candidate[len..];
+                after_word = true;
+            }
+            None => {
+                out.push_str(before);
+                out.push_str("=?");
+                rest = &candidate[2..];
+                after_word = false;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// The parts of a multipart body. A body cut short ends in a part with no
+/// closing delimiter, which is kept.
+fn split_parts<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{boundary}");
+    let delimiter = delimiter.as_bytes();
+    let mut starts = Vec::new();
+    let mut offset = 0;
+    while let Some(found) = find(&body[offset..], delimiter) {
+        starts.push(offset + found);
+        offset += found + delimiter.len();
+    }
+    let mut parts: Vec<&[u8]> = starts
+        .windows(2)
+        .map(|pair| &body[pair[0] + delimiter.len()..pair[1]])
+        .collect();
+    if let Some(&last) = starts.last() {
+        let tail = &body[last + delimiter.len()..];
+        if !tail.starts_with(b"--") {
+            parts.push(tail);
+        }
+    }
+    parts
+}
+
+/// A part split into its header and body.
+fn split_part(part: &[u8]) -> (&[u8], &[u8]) {
+    let part = part
+        .strip_prefix(b"\r\n")
+        .or_else(|| part.strip_prefix(b"\n"))
+        .unwrap_or(part);
+    match find(part, b"\r\n\r\n") {
+        Some(i) => (&part[..i], &part[i + 4..]),
+        None => match find(part, b"\n\n") {
+            Some(i) => (&part[..i], &part[i + 2..]),
+            None => (part, &[]),
+        },
+    }
+}
+
+enum Text {
+    Plain(String),
+    Html(String),
+}
+
+impl Text {
+    fn into_string(self) -> String {
+        match self {
+            Self::Plain(text) => text,
+            Self::Html(html) => {
+                let doc = dom_query::Document::from(html.as_str());
+                doc.select("script, style, head").remove();
+                doc.root().text().to_string()
+            }
+        }
+    }
+}
+
+/// The readable text of a part: its plain text if it has any, else its
+/// HTML. Attachments are passed over.
+fn part_text(fields: &[(String, String)], body: &[u8], depth: usize) -> Option<Text> {
+    let (disposition, _) = parameterized(field(fields, "content-disposition").unwrap_or_default());
+    if disposition == "attachment" {
+        return None;
+    }
+    let (kind, params) = parameterized(field(fields, "content-type").unwrap_or("text/plain"));
+    if kind.starts_with("multipart/") {
+        let boundary = param(&params, "boundary")?;
+        if depth >= MAX_DEPTH {
+            return None;
+        }
+        let mut html = None;
+        for part in split_parts(body, boundary) {
+            let (header, content) = split_part(part);
+            match part_text(&parse_headers(header), content, depth + 1) {
+                Some(Text::Plain(text)) if !text.trim().is_empty() => {
+                    return Some(Text::Plain(text))
+                }
+                Some(Text::Html(text)) if html.is_none() => html = Some(text),
+                _ => {}
+            }
+        }
+        return html.map(Text::Html);
+    }
+    let text = || {
+        let bytes = decode_transfer(
+            body,
+            field(fields, "content-transfer-encoding").unwrap_or_default(),
+        );
+        decode_charset(&bytes, param(&params, "charset").unwrap_or("utf-8"))
+    };
+    match kind.as_str() {
+        "text/plain" => Some(Text::Plain(text())),
+        "text/html" => Some(Text::Html(text())),
+        _ => None,
+    }
+}
+
+/// Drops quoted replies, squeezes blank lines and caps the length.
+fn tidy(text: &str) -> String {
+    let mut out = String::new();
+    let mut blank = false;
+    for line in text.lines().map(str::trim_end) {
+        if line.trim_start().starts_with('>') {
+            continue;
+        }
+        if line.trim().is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        out.push_str(line.trim_start_matches('\u{a0}'));
+        out.push('\n');
+        if out.len() > MAX_TEXT {
+            break;
+        }
+    }
+    if out.len() > MAX_TEXT {
+        let mut end = MAX_TEXT;
+        while !out.is_char_boundary(end) {
+            end -= 1;
+        }
+        out.truncate(end);
+    }
+    out.trim_end().to_string()
+}
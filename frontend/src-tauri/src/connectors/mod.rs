@@ -0,0 +1,558 @@
+//! Optional connectors that bring recent email (IMAP) and calendar events
+//! (CalDAV or an ICS feed) into the RAG index. Nothing is fetched until
+//! `sync_connector` asks; each item then becomes a plain-text document
+//! under a `connector:<id>/` source, and items an earlier sync indexed that
+//! have since left the window are dropped.
+//!
+//! Connector settings are kept in `connectors.json`; passwords go to the OS
+//! credential store and are only ever sent to the connector's own server.
+//! Mail is read over TLS only, and a calendar with a login must be served
+//! over HTTPS.
+
+mod calendar;
+mod imap;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration as ChronoDuration, Local, Utc};
+use reqwest::{Client, Url};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::keys;
+use crate::providers::Providers;
+use crate::rag::{self, Embedder, EmbeddingOptions};
+use crate::storage::{new_id, now_ms, Database};
+use calendar::{Calendar, CalendarEvent};
+use imap::{Account, Mail};
+
+const CONFIG_FILE: &str = "connectors.json";
+const SOURCE_PREFIX: &str = "connector:";
+const DEFAULT_IMAP_PORT: u16 = 993;
+const DEFAULT_MAILBOX: &str = "INBOX";
+const DEFAULT_LOOKBACK_DAYS: u32 = 14;
+const DEFAULT_LOOKAHEAD_DAYS: u32 = 30;
+const DEFAULT_MAX_ITEMS: usize = 50;
+const MAX_ITEMS: usize = 500;
+const MAX_DAYS: u32 = 365;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ConnectorSource {
+    Imap {
+        host: String,
+        #[serde(default = "default_imap_port")]
+        port: u16,
+        username: String,
+        #[serde(default = "default_mailbox")]
+        mailbox: String,
+    },
+    Caldav {
+        /// The calendar collection.
+        url: String,
+        username: String,
+    },
+    Ics {
+        url: String,
+        /// For feeds behind a login; most are addressed by a secret URL.
+        #[serde(default)]
+        username: Option<String>,
+    },
+}
+
+fn default_imap_port() -> u16 {
+    DEFAULT_IMAP_PORT
+}
+
+fn default_mailbox() -> String {
+    DEFAULT_MAILBOX.to_string()
+}
+
+fn default_lookback_days() -> u32 {
+    DEFAULT_LOOKBACK_DAYS
+}
+
+fn default_lookahead_days() -> u32 {
+    DEFAULT_LOOKAHEAD_DAYS
+}
+
+fn default_max_items() -> usize {
+    DEFAULT_MAX_ITEMS
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectorConfig {
+    pub id: String,
+    pub name: String,
+    pub source: ConnectorSource,
+    /// How far back mail and events are fetched.
+    #[serde(default = "default_lookback_days")]
+    pub lookback_days: u32,
+    /// How far ahead events are fetched.
+    #[serde(default = "default_lookahead_days")]
+    pub lookahead_days: u32,
+    /// The most items one sync indexes, newest first.
+    #[serde(default = "default_max_items")]
+    pub max_items: usize,
+    #[serde(default)]
+    pub last_synced_at: Option<i64>,
+}
+
+/// What `save_connector` takes. Without an id a new connector is made.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConnectorInput {
+    pub id: Option<String>,
+    pub name: String,
+    pub source: ConnectorSource,
+    pub lookback_days: Option<u32>,
+    pub lookahead_days: Option<u32>,
+    pub max_items: Option<usize>,
+    /// Kept as it is when absent; an empty string removes it.
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectorStatus {
+    #[serde(flatten)]
+    pub config: ConnectorConfig,
+    pub has_password: bool,
+    /// Why the last sync failed.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncReport {
+    pub connector_id: String,
+    pub indexed: usize,
+    /// Documents from earlier syncs that dropped out of the window.
+    pub removed: usize,
+    pub synced_at: i64,
+}
+
+/// A fetched item, ready for the index.
+struct ContextDocument {
+    source: String,
+    title: String,
+    text: String,
+}
+
+/// Managed as Tauri state.
+#[derive(Default)]
+pub struct Connectors {
+    config: Mutex<Vec<ConnectorConfig>>,
+    errors: Mutex<HashMap<String, String>>,
+}
+
+fn password_entry(id: &str) -> String {
+    format!("connector:{id}")
+}
+
+fn source_prefix(id: &str) -> String {
+    format!("{SOURCE_PREFIX}{id}/")
+}
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidSetting(format!("connector: {message}"))
+}
+
+/// A calendar address, with `webcal:` read as HTTPS. A login needs HTTPS.
+fn calendar_url(url: &str, with_login: bool) -> Result<Url> {
+    let url = match url.trim().strip_prefix("webcal://") {
+        Some(rest) => format!("https://{rest}"),
+        None => url.trim().to_string(),
+    };
+    let url = Url::parse(&url).map_err(|_| invalid(&format!("{url} isn't a valid address")))?;
+    match url.scheme() {
+        "https" => Ok(url),
+        "http" if !with_login => Ok(url),
+        "http" => Err(invalid("a calendar with a login must use https")),
+        _ => Err(invalid(&format!("{url} isn't a web address"))),
+    }
+}
+
+impl ConnectorInput {
+    fn validate(self) -> Result<(ConnectorConfig, Option<String>)> {
+        if let Some(id) = &self.id {
+            if id.is_empty()
+                || !id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(invalid("ids may only use letters, digits, - and _"));
+            }
+        }
+        let name = self.name.trim().to_string();
+        if name.is_empty() {
+            return Err(invalid("a connector needs a name"));
+        }
+        let source = match self.source {
+            ConnectorSource::Imap {
+                host,
+                port,
+                username,
+                mailbox,
+            } => {
+                let host = host.trim().to_string();
+                if host.is_empty() || host.contains(['/', ':', ' ']) {
+                    return Err(invalid("the mail server needs a host name, without a port"));
+                }
+                if username.trim().is_empty() {
+                    return Err(invalid("the mail server needs a username"));
+                }
+                let mailbox = match mailbox.trim() {
+                    "" => default_mailbox(),
+                    mailbox => mailbox.to_string(),
+                };
+                ConnectorSource::Imap {
+                    host,
+                    port,
+                    username: username.trim().to_string(),
+                    mailbox,
+                }
+            }
+            ConnectorSource::Caldav { url, username } => {
+                if username.trim().is_empty() {
+                    return Err(invalid("the calendar server needs a username"));
+                }
+                ConnectorSource::Caldav {
+                    url: calendar_url(&url, true)?.to_string(),
+                    username: username.trim().to_string(),
+                }
+            }
+            ConnectorSource::Ics { url, username } => {
+                let username = username
+                    .map(|u| u.trim().to_string())
+                    .filter(|u| !u.is_empty());
+                ConnectorSource::Ics {
+                    url: calendar_url(&url, username.is_some())?.to_string(),
+                    username,
+                }
+            }
+        };
+        let days = |days: Option<u32>, default: u32| days.unwrap_or(default).min(MAX_DAYS);
+        let config = ConnectorConfig {
+            id: self.id.unwrap_or_else(new_id),
+            name,
+            source,
+            lookback_days: days(self.lookback_days, DEFAULT_LOOKBACK_DAYS).max(1),
+            lookahead_days: days(self.lookahead_days, DEFAULT_LOOKAHEAD_DAYS),
+            max_items: self
+                .max_items
+                .unwrap_or(DEFAULT_MAX_ITEMS)
+                .clamp(1, MAX_ITEMS),
+            last_synced_at: None,
+        };
+        Ok((config, self.password))
+    }
+}
+
+impl Connectors {
+    fn save(&self, app: &AppHandle) -> Result<()> {
+        config::write(app, CONFIG_FILE, &*self.config.lock().unwrap())
+    }
+
+    fn connector(&self, id: &str) -> Result<ConnectorConfig> {
+        self.config
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|c| c.id == id)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("connector {id}")))
+    }
+
+    fn status(&self, config: ConnectorConfig) -> ConnectorStatus {
+        ConnectorStatus {
+            has_password: keys::load(&password_entry(&config.id))
+                .ok()
+                .flatten()
+                .is_some(),
+            error: self.errors.lock().unwrap().get(&config.id).cloned(),
+            config,
+        }
+    }
+}
+
+impl Database {
+    /// Drops what a connector indexed earlier apart from `keep`, returning
+    /// how many documents went.
+    fn prune_connector_documents(&self, connector_id: &str, keep: &[String]) -> Result<usize> {
+        let prefix = source_prefix(connector_id);
+        let removed = self.conn()?.execute(
+            "DELETE FROM documents
+             WHERE substr(source, 1, ?1) = ?2
+               AND source NOT IN (SELECT value FROM json_each(?3))",
+            params![prefix.len(), prefix, serde_json::to_string(keep)?],
+        )?;
+        Ok(removed)
+    }
+}
+
+fn mail_document(connector_id: &str, mail: Mail) -> ContextDocument {
+    let subject = if mail.subject.is_empty() {
+        "(no subject)".to_string()
+    } else {
+        mail.subject
+    };
+    let mut text = format!("Email\nFrom: {}\n", mail.from);
+    if !mail.to.is_empty() {
+        text.push_str(&format!("To: {}\n", mail.to));
+    }
+    let date = mail
+        .sent_at
+        .and_then(DateTime::from_timestamp_millis)
+        .map_or(mail.date, |sent| {
+            sent.with_timezone(&Local)
+                .format("%a %-d %b %Y, %H:%M")
+                .to_string()
+        });
+    text.push_str(&format!("Date: {date}\nSubject: {subject}\n"));
+    if !mail.text.is_empty() {
+        text.push('\n');
+        text.push_str(&mail.text);
+    }
+    ContextDocument {
+        source: format!(
+            "{}mail/{}-{}",
+            source_prefix(connector_id),
+            mail.uid_validity,
+            mail.uid
+        ),
+        title: subject,
+        text,
+    }
+}
+
+fn event_document(connector_id: &str, event: CalendarEvent) -> ContextDocument {
+    let summary = if event.summary.is_empty() {
+        "(untitled event)".to_string()
+    } else {
+        event.summary
+    };
+    let mut text = format!("Calendar event: {summary}\n");
+    if let Some(start) = &event.start {
+        text.push_str(&format!("Starts: {}\n", start.describe()));
+    }
+    if let Some(end) = &event.end {
+        text.push_str(&format!("Ends: {}\n", end.describe()));
+    }
+    if let Some(rule) = &event.rrule {
+        text.push_str(&format!("Repeats: {rule}\n"));
+    }
+    for (label, value) in [
+        ("Location", &event.location),
+        ("Organizer", &event.organizer),
+        ("Status", &event.status),
+    ] {
+        if let Some(value) = value {
+            text.push_str(&format!("{label}: {value}\n"));
+        }
+    }
+    if let Some(description) = &event.description {
+        text.push('\n');
+        text.push_str(description);
+    }
+    let occurrence = event
+        .recurrence_id
+        .map(|id| format!("@{id}"))
+        .unwrap_or_default();
+    ContextDocument {
+        source: format!(
+            "{}event/{}{occurrence}",
+            source_prefix(connector_id),
+            event.uid
+        ),
+        title: summary,
+        text,
+    }
+}
+
+async fn fetch(
+    client: &Client,
+    config: &ConnectorConfig,
+    password: Option<&str>,
+) -> Result<Vec<ContextDocument>> {
+    let now = Utc::now();
+    let from = now - ChronoDuration::days(i64::from(config.lookback_days));
+    let until = now + ChronoDuration::days(i64::from(config.lookahead_days));
+    let needs_password =
+        || password.ok_or_else(|| Error::Connector(format!("{} has no password", config.name)));
+    let mut documents: Vec<ContextDocument> = match &config.source {
+        ConnectorSource::Imap {
+            host,
+            port,
+            username,
+            mailbox,
+        } => {
+            let account = Account {
+                host,
+                port: *port,
+                username,
+                password: needs_password()?,
+                mailbox,
+            };
+            imap::fetch(&account, from, config.max_items)
+                .await?
+                .into_iter()
+                .rev()
+                .map(|mail| mail_document(&config.id, mail))
+                .collect()
+        }
+        ConnectorSource::Caldav { url, username } => {
+            let url = calendar_url(url, true)?;
+            let calendar = Calendar {
+                url: &url,
+                username: Some(username),
+                password: Some(needs_password()?),
+            };
+            calendar::fetch_caldav(client, &calendar, from, until)
+                .await?
+                .into_iter()
+                .map(|event| event_document(&config.id, event))
+                .collect()
+        }
+        ConnectorSource::Ics { url, username } => {
+            let url = calendar_url(url, username.is_some())?;
+            let calendar = Calendar {
+                url: &url,
+                username: username.as_deref(),
+                password,
+            };
+            calendar::fetch_ics(client, &calendar, from, until)
+                .await?
+                .into_iter()
+                .map(|event| event_document(&config.id, event))
+                .collect()
+        }
+    };
+    documents.truncate(config.max_items);
+    Ok(documents)
+}
+
+pub fn init(app: &AppHandle) {
+    let saved = config::read::<Vec<ConnectorConfig>>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(Connectors {
+        config: Mutex::new(saved),
+        ..Connectors::default()
+    });
+}
+
+#[tauri::command]
+pub fn list_connectors(connectors: State<'_, Connectors>) -> Vec<ConnectorStatus> {
+    let saved = connectors.config.lock().unwrap().clone();
+    saved.into_iter().map(|c| connectors.status(c)).collect()
+}
+
+/// Adds a connector, or updates the one with the given id.
+#[tauri::command]
+pub fn save_connector(
+    app: AppHandle,
+    connectors: State<'_, Connectors>,
+    connector: ConnectorInput,
+) -> Result<ConnectorStatus> {
+    let (mut config, password) = connector.validate()?;
+    {
+        let mut saved = connectors.config.lock().unwrap();
+        if let Some(existing) = saved.iter().find(|c| c.id == config.id) {
+            config.last_synced_at = existing.last_synced_at;
+        }
+        saved.retain(|c| c.id != config.id);
+        saved.push(config.clone());
+    }
+    match password.as_deref().map(str::trim) {
+        Some("") => keys::delete(&password_entry(&config.id))?,
+        Some(password) => keys::store(&password_entry(&config.id), password)?,
+        None => {}
+    }
+    connectors.save(&app)?;
+    connectors.errors.lock().unwrap().remove(&config.id);
+    Ok(connectors.status(config))
+}
+
+/// Removes a connector, its password and everything it indexed.
+#[tauri::command]
+pub fn delete_connector(
+    app: AppHandle,
+    connectors: State<'_, Connectors>,
+    db: State<'_, Database>,
+    id: String,
+) -> Result<()> {
+    connectors.connector(&id)?;
+    connectors.config.lock().unwrap().retain(|c| c.id != id);
+    connectors.save(&app)?;
+    connectors.errors.lock().unwrap().remove(&id);
+    keys::delete(&password_entry(&id))?;
+    db.prune_connector_documents(&id, &[])?;
+    Ok(())
+}
+
+/// Fetches the connector's recent items and indexes them.
+#[tauri::command]
+pub async fn sync_connector(
+    app: AppHandle,
+    connectors: State<'_, Connectors>,
+    db: State<'_, Database>,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    id: String,
+    options: Option<EmbeddingOptions>,
+) -> Result<SyncReport> {
+    let config = connectors.connector(&id)?;
+    let embedder = Embedder::resolve(&providers, &options.unwrap_or_default())?;
+    let password = keys::load(&password_entry(&id))?;
+    let result = async {
+        let documents = fetch(&client, &config, password.as_deref()).await?;
+        let mut kept = Vec::with_capacity(documents.len());
+        for document in &documents {
+            rag::index_text(
+                &db,
+                &embedder,
+                &client,
+                &document.source,
+                &document.title,
+                &document.text,
+            )
+            .await?;
+            kept.push(document.source.clone());
+        }
+        let removed = db.prune_connector_documents(&id, &kept)?;
+        Ok::<_, Error>((kept.len(), removed))
+    }
+    .await;
+    let (indexed, removed) = match result {
+        Ok(counts) => counts,
+        Err(err) => {
+            connectors
+                .errors
+                .lock()
+                .unwrap()
+                .insert(id.clone(), err.to_string());
+            return Err(err);
+        }
+    };
+    let synced_at = now_ms();
+    connectors.errors.lock().unwrap().remove(&id);
+    if let Some(saved) = connectors
+        .config
+        .lock()
+        .unwrap()
+        .iter_mut()
+        .find(|c| c.id == id)
+    {
+        saved.last_synced_at = Some(synced_at);
+    }
+    connectors.save(&app)?;
+    Ok(SyncReport {
+        connector_id: id,
+        indexed,
+        removed,
+        synced_at,
+    })
+}
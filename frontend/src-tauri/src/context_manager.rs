@@ -0,0 +1,362 @@
+//! Keeps long conversations inside each model's context window. Before a
+//! request goes out, its messages are counted against the model's window;
+//! past the threshold, the older turns are replaced with a summary written
+//! by a cheap model, and only the most recent turns go verbatim. The
+//! leading system messages always stay.
+//!
+//! Summaries are stored by a digest of the messages they cover, so the
+//! next turn reuses one instead of writing it again, and extends it only
+//! once the conversation has outgrown it. The messages themselves are
+//! never touched: what is in SQLite is the full history. Unlike the
+//! summary in `titling`, which follows a stored branch, this works on
+//! whatever messages a request carries.
+
+use std::sync::Mutex;
+
+use reqwest::Client;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::incognito;
+use crate::llm::tokens;
+use crate::llm::{ChatMessage, ChatRequest, Role};
+use crate::storage::{now_ms, Database};
+use crate::titling;
+use crate::usage;
+
+const CONFIG_FILE: &str = "context.json";
+/// Longest stretch of history summarised in one call, in tokens.
+const MAX_BATCH_TOKENS: usize = 24_000;
+const SUMMARY_MAX_TOKENS: u32 = 600;
+/// Summaries of requests outside any conversation are dropped after this.
+const UNATTACHED_RETENTION_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContextConfig {
+    pub enabled: bool,
+    /// Share of the model's context window a request may fill before its
+    /// older turns are summarised.
+    pub threshold: f32,
+    /// Messages at the end always sent as they are.
+    pub keep_recent: usize,
+    /// Falls back to the first configured of a few cheap models.
+    pub provider: Option<String>,
+    pub model: Option<String>,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: 0.75,
+            keep_recent: 6,
+            provider: None,
+            model: None,
+        }
+    }
+}
+
+/// Payload of the `context-compressed` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ContextCompressed {
+    pub request_id: String,
+    pub provider: String,
+    pub model: String,
+    /// Messages the summary stands in for.
+    pub summarized: usize,
+    pub tokens_before: usize,
+    pub tokens_after: usize,
+    /// A stored summary was reused as it was.
+    pub reused: bool,
+}
+
+/// Managed as Tauri state.
+pub struct ContextManager {
+    config: Mutex<ContextConfig>,
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<ContextConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(ContextManager {
+        config: Mutex::new(config),
+    });
+}
+
+impl Database {
+    /// The summary covering the most messages among `digests`, with how
+    /// many it covers.
+    fn longest_context_summary(&self, digests: &[String]) -> Result<Option<(usize, String)>> {
+        if digests.is_empty() {
+            return Ok(None);
+        }
+        let placeholders = vec!["?"; digests.len()].join(", ");
+        let conn = self.conn()?;
+        Ok(conn
+            .query_row(
+                &format!(
+                    "SELECT covers, summary FROM context_summaries
+                     WHERE digest IN ({placeholders}) ORDER BY covers DESC LIMIT 1"
+                ),
+                rusqlite::params_from_iter(digests),
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?)
+    }
+
+    fn save_context_summary(
+        &self,
+        digest: &str,
+        conversation_id: Option<&str>,
+        covers: usize,
+        summary: &str,
+    ) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute(
+            "INSERT OR REPLACE INTO context_summaries
+                 (digest, conversation_id, covers, summary, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![digest, conversation_id, covers, summary, now_ms()],
+        )?;
+        conn.execute(
+            "DELETE FROM context_summaries WHERE conversation_id IS NULL AND created_at < ?1",
+            [now_ms() - UNATTACHED_RETENTION_MS],
+        )?;
+        Ok(())
+    }
+}
+
+/// Digests of every prefix of `messages`: entry `i` covers the first
+/// `i + 1`.
+fn prefix_digests(messages: &[ChatMessage]) -> Vec<String> {
+    let mut previous = String::new();
+    messages
+        .iter()
+        .map(|message| {
+            let mut hasher = Sha256::new();
+            hasher.update(previous.as_bytes());
+            hasher.update(serde_json::to_string(&message.role).unwrap_or_default());
+            hasher.update([0]);
+            hasher.update(message.content.as_bytes());
+            previous = hasher
+                .finalize()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect();
+            previous.clone()
+        })
+        .collect()
+}
+
+fn transcript(messages: &[ChatMessage]) -> String {
+    messages
+        .iter()
+        .map(|m| {
+            let speaker = match m.role {
+                Role::User => "User",
+                Role::System => "System",
+                Role::Assistant => "Assistant",
+            };
+            format!("{speaker}: {}", m.content.trim())
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+fn summary_message(summary: &str) -> ChatMessage {
+    ChatMessage {
+        role: Role::System,
+        content: format!("Summary of the earlier conversation:\n{summary}"),
+    }
+}
+
+/// Folds `messages` into `summary`, a batch at a time so no call outgrows
+/// the summarising model's own window.
+async fn summarize(
+    app: &AppHandle,
+    config: &ContextConfig,
+    mut summary: Option<String>,
+    messages: &[ChatMessage],
+) -> Result<String> {
+    let (provider, model) =
+        titling::pick_model(app, config.provider.as_deref(), config.model.as_deref())?;
+    let budget = (tokens::context_window(provider.id(), &model) / 2).min(MAX_BATCH_TOKENS);
+    let client = app.state::<Client>();
+
+    let mut start = 0;
+    while start < messages.len() {
+        let mut end = start + 1;
+        let mut used = tokens::count(provider.id(), &model, &messages[start..end]).tokens;
+        while end < messages.len() {
+            let next = tokens::count(provider.id(), &model, &messages[end..end + 1]).tokens;
+            if used + next > budget {
+                break;
+            }
+            used += next;
+            end += 1;
+        }
+        let batch = &messages[start..end];
+        let instructions = "Summarize the conversation for someone who will carry it on. \
+                            Keep facts, decisions, names, numbers, code and open questions. \
+                            Use at most 300 words and reply with the summary only.";
+        let prompt = match &summary {
+            Some(summary) => format!(
+                "{instructions}\n\n## Summary so far\n{summary}\n\n## Newer messages\n{}",
+                transcript(batch)
+            ),
+            None => format!("{instructions}\n\n{}", transcript(batch)),
+        };
+        let request = ChatRequest {
+            provider: provider.id().to_string(),
+            model: model.clone(),
+            messages: vec![ChatMessage {
+                role: Role::User,
+                content: prompt,
+            }],
+            temperature: Some(0.2),
+            top_p: None,
+            max_tokens: Some(SUMMARY_MAX_TOKENS),
+            use_tools: false,
+            bypass_cache: false,
+            response_schema: None,
+            conversation_id: None,
+            tools: Vec::new(),
+            tool_rounds: Vec::new(),
+        };
+        let completion = provider.stream(&client, &request, &mut |_| {}).await?;
+        usage::record(app, &request, &completion.content, completion.usage);
+        let written = completion.content.trim();
+        if written.is_empty() {
+            return Err(Error::Provider(format!(
+                "{} wrote an empty summary",
+                request.provider
+            )));
+        }
+        summary = Some(written.to_string());
+        start = end;
+    }
+    summary.ok_or_else(|| Error::Provider("nothing to summarize".into()))
+}
+
+async fn try_compress(
+    app: &AppHandle,
+    request_id: &str,
+    request: &ChatRequest,
+) -> Result<Option<ChatRequest>> {
+    let Some(manager) = app.try_state::<ContextManager>() else {
+        return Ok(None);
+    };
+    let config = manager.config.lock().unwrap().clone();
+    if !config.enabled {
+        return Ok(None);
+    }
+    let window = tokens::context_window(&request.provider, &request.model);
+    let limit = (window as f32 * config.threshold.clamp(0.1, 1.0)) as usize;
+    let count = |messages: &[ChatMessage]| {
+        tokens::count(&request.provider, &request.model, messages).tokens
+    };
+    let tokens_before = count(&request.messages);
+    if tokens_before <= limit {
+        return Ok(None);
+    }
+
+    let leading = request
+        .messages
+        .iter()
+        .take_while(|m| m.role == Role::System)
+        .count();
+    let (system, rest) = request.messages.split_at(leading);
+    let older = rest.len().saturating_sub(config.keep_recent);
+    if older == 0 {
+        return Ok(None);
+    }
+    let digests = prefix_digests(&rest[..older]);
+    let db = app.state::<Database>();
+    let stored = db.longest_context_summary(&digests)?;
+
+    let assemble = |summary: &str, from: usize| {
+        let mut messages = system.to_vec();
+        messages.push(summary_message(summary));
+        messages.extend_from_slice(&rest[from..]);
+        messages
+    };
+    // A stored summary may still leave enough room for what came after it.
+    let (messages, summarized, reused) = match stored {
+        Some((covers, summary)) if count(&assemble(&summary, covers)) <= limit => {
+            (assemble(&summary, covers), covers, true)
+        }
+        stored => {
+            let (covers, previous) = stored.unzip();
+            let from = covers.unwrap_or_default();
+            let summary = summarize(app, &config, previous, &rest[from..older]).await?;
+            let conversation_id = request.conversation_id.as_deref();
+            if !incognito::is_ephemeral_request(conversation_id) {
+                db.save_context_summary(&digests[older - 1], conversation_id, older, &summary)?;
+            }
+            (assemble(&summary, older), older, false)
+        }
+    };
+
+    let tokens_after = count(&messages);
+    let _ = app.emit(
+        "context-compressed",
+        ContextCompressed {
+            request_id: request_id.to_string(),
+            provider: request.provider.clone(),
+            model: request.model.clone(),
+            summarized,
+            tokens_before,
+            tokens_after,
+            reused,
+        },
+    );
+    let mut compressed = request.clone();
+    compressed.messages = messages;
+    Ok(Some(compressed))
+}
+
+/// `request` with its older turns summarised, when it runs past the
+/// threshold for its model. `None` leaves it as it is, which is also what
+/// happens when the summary can't be written.
+pub(crate) async fn compress(
+    app: &AppHandle,
+    request_id: &str,
+    request: &ChatRequest,
+) -> Option<ChatRequest> {
+    match try_compress(app, request_id, request).await {
+        Ok(compressed) => compressed,
+        Err(err) => {
+            tracing::warn!("couldn't compress the context for {request_id}: {err}");
+            None
+        }
+    }
+}
+
+#[tauri::command]
+pub fn get_context_config(manager: State<'_, ContextManager>) -> ContextConfig {
+    manager.config.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn set_context_config(
+    app: AppHandle,
+    manager: State<'_, ContextManager>,
+    config: ContextConfig,
+) -> Result<ContextConfig> {
+    if !(0.1..=1.0).contains(&config.threshold) {
+        return Err(Error::InvalidSetting(format!(
+            "threshold {} is not between 0.1 and 1",
+            config.threshold
+        )));
+    }
+    config::write(&app, CONFIG_FILE, &config)?;
+    *manager.config.lock().unwrap() = config.clone();
+    Ok(config)
+}
@@ -0,0 +1,441 @@
+//! Debates between models. Two or more debaters argue their positions on a
+//! motion for a set number of rounds, speaking in the order given and each
+//! seeing the whole debate so far, and after every round a judge model
+//! scores it. Turns stream as `debate-token` events tagged with the round
+//! and debater, each verdict fires a `debate-round` event, and the debate
+//! is kept as an orchestration run, one artifact per turn and verdict.
+
+use std::time::Instant;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, State};
+
+use crate::arbiter::{self, ModelChoice};
+use crate::error::{Error, Result};
+use crate::llm::{ChatMessage, ChatRequest, Role};
+use crate::orchestration::{self, Artifact, OrchestrationRun, StageKind, StageStatus};
+use crate::providers::{DeltaSink, Providers};
+use crate::requests::{cancellable, Requests};
+use crate::storage::Database;
+use crate::usage;
+
+const DEBATE: &str = "debate";
+const DEFAULT_ROUNDS: u32 = 3;
+const MAX_ROUNDS: u32 = 10;
+const JUDGE_TEMPERATURE: f32 = 0.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Debater {
+    pub provider: String,
+    /// Falls back to the provider's default model.
+    pub model: Option<String>,
+    /// The position to argue. The first two debaters default to for and
+    /// against the motion.
+    #[serde(default)]
+    pub stance: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebateRequest {
+    pub motion: String,
+    /// In speaking order.
+    pub debaters: Vec<Debater>,
+    pub judge: ModelChoice,
+    /// 3 when unset, and at most 10.
+    #[serde(default)]
+    pub rounds: Option<u32>,
+    pub temperature: Option<f32>,
+    /// For each turn.
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub conversation_id: Option<String>,
+}
+
+/// Payload of the `debate-token` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct DebateToken {
+    pub request_id: String,
+    pub run_id: String,
+    /// Counted from 1.
+    pub round: u32,
+    /// Position of the debater in the request.
+    pub debater: usize,
+    pub provider: String,
+    pub delta: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebaterScore {
+    /// Position of the debater in the request.
+    pub debater: usize,
+    /// Between 0 and 10.
+    pub score: f32,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundVerdict {
+    pub round: u32,
+    /// One per debater, in speaking order.
+    pub scores: Vec<DebaterScore>,
+    pub summary: Option<String>,
+}
+
+/// Payload of the `debate-round` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct RoundProgress {
+    pub request_id: String,
+    pub run_id: String,
+    #[serde(flatten)]
+    pub verdict: RoundVerdict,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Debate {
+    pub run: OrchestrationRun,
+    pub rounds: Vec<RoundVerdict>,
+    /// Each debater's scores summed over the rounds.
+    pub totals: Vec<f32>,
+    /// The debater with the highest total, unless it's a tie.
+    pub winner: Option<usize>,
+}
+
+/// A debater with their model and position settled.
+struct Seat {
+    target: (String, String),
+    stance: String,
+}
+
+/// Who takes part, and for how long.
+struct Lineup {
+    seats: Vec<Seat>,
+    judge: (String, String),
+    rounds: u32,
+}
+
+struct Turn {
+    round: u32,
+    debater: usize,
+    content: String,
+}
+
+fn stance(index: usize, debater: &Debater) -> String {
+    match (&debater.stance, index) {
+        (Some(stance), _) if !stance.trim().is_empty() => stance.trim().to_string(),
+        (_, 0) => "in favour of the motion".to_string(),
+        (_, 1) => "against the motion".to_string(),
+        _ => "a position of your own, different from the other debaters'".to_string(),
+    }
+}
+
+fn transcript(seats: &[Seat], turns: &[Turn]) -> String {
+    turns
+        .iter()
+        .map(|turn| {
+            format!(
+                "### Round {}, debater {} ({})\n{}\n",
+                turn.round,
+                turn.debater + 1,
+                seats[turn.debater].stance,
+                turn.content.trim()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn turn_messages(
+    motion: &str,
+    seats: &[Seat],
+    turns: &[Turn],
+    debater: usize,
+    round: u32,
+    rounds: u32,
+) -> Vec<ChatMessage> {
+    let system = format!(
+        "You are debater {n} of {count} in a debate on the motion: {motion}\n\
+         Your position: {stance}.\n\
+         Argue it as persuasively as you can and answer the strongest points \
+         made against it, without conceding. Keep each turn to a few paragraphs.",
+        n = debater + 1,
+        count = seats.len(),
+        stance = seats[debater].stance,
+    );
+    let mut prompt = if turns.is_empty() {
+        format!("Open the debate. This is round 1 of {rounds}.")
+    } else {
+        format!(
+            "## The debate so far\n{}\n\nIt's your turn, in round {round} of {rounds}.",
+            transcript(seats, turns)
+        )
+    };
+    if round == rounds && rounds > 1 {
+        prompt.push_str(" This is the last round, so close your case.");
+    }
+    vec![
+        ChatMessage {
+            role: Role::System,
+            content: system,
+        },
+        ChatMessage {
+            role: Role::User,
+            content: prompt,
+        },
+    ]
+}
+
+fn judge_prompt(motion: &str, seats: &[Seat], turns: &[Turn], round: u32) -> String {
+    let debaters = seats
+        .iter()
+        .enumerate()
+        .map(|(i, seat)| format!("{}. {}", i + 1, seat.stance))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "You are judging a debate on the motion: {motion}\n\n\
+         ## Debaters\n{debaters}\n\n## Transcript\n{transcript}\n\n\
+         Score each debater's turn in round {round} from 0 to 10 for the \
+         strength of the argument and how well it answers the others, \
+         whatever your own view of the motion. Reply with JSON only, in the \
+         form {{\"scores\": [{{\"debater\": 1, \"score\": 7, \"reason\": \"...\"}}], \
+         \"summary\": \"...\"}}.",
+        transcript = transcript(seats, turns),
+    )
+}
+
+fn parse_verdict(
+    provider: &str,
+    content: &str,
+    round: u32,
+    debaters: usize,
+) -> Result<RoundVerdict> {
+    let verdict: Value = serde_json::from_str(arbiter::strip_fence(content))
+        .map_err(|_| Error::Provider(format!("{provider} returned an unreadable verdict")))?;
+    let mut scores: Vec<DebaterScore> = (0..debaters)
+        .map(|debater| DebaterScore {
+            debater,
+            score: 0.0,
+            reason: None,
+        })
+        .collect();
+    for entry in verdict["scores"].as_array().into_iter().flatten() {
+        let index = entry["debater"].as_u64().unwrap_or_default() as usize;
+        if let Some(slot) = index.checked_sub(1).and_then(|i| scores.get_mut(i)) {
+            slot.score = entry["score"].as_f64().unwrap_or_default().clamp(0.0, 10.0) as f32;
+            slot.reason = entry["reason"].as_str().map(str::to_string);
+        }
+    }
+    Ok(RoundVerdict {
+        round,
+        scores,
+        summary: verdict["summary"].as_str().map(str::to_string),
+    })
+}
+
+fn chat(
+    target: &(String, String),
+    messages: Vec<ChatMessage>,
+    request: &DebateRequest,
+) -> ChatRequest {
+    ChatRequest {
+        provider: target.0.clone(),
+        model: target.1.clone(),
+        messages,
+        temperature: request.temperature,
+        top_p: None,
+        max_tokens: request.max_tokens,
+        use_tools: false,
+        bypass_cache: false,
+        response_schema: None,
+        conversation_id: request.conversation_id.clone(),
+        tools: Vec::new(),
+        tool_rounds: Vec::new(),
+    }
+}
+
+/// Streams `chat` into `artifact`, reporting it as it starts and ends.
+async fn speak(
+    app: &AppHandle,
+    providers: &Providers,
+    client: &Client,
+    ids: (&str, &str),
+    chat: &ChatRequest,
+    mut artifact: Artifact,
+    on_delta: &mut DeltaSink<'_>,
+) -> Result<String> {
+    let (request_id, run_id) = ids;
+    orchestration::report(app, run_id, request_id, StageStatus::Started, &artifact);
+    let started = Instant::now();
+    let outcome = match providers.get(&chat.provider) {
+        Ok(provider) => provider.stream(client, chat, on_delta).await,
+        Err(err) => Err(err),
+    };
+    artifact.latency_ms = started.elapsed().as_millis() as u64;
+    match outcome {
+        Ok(completion) => {
+            usage::record(app, chat, &completion.content, completion.usage);
+            artifact.content = Some(completion.content.clone());
+            orchestration::report(app, run_id, request_id, StageStatus::Done, &artifact);
+            Ok(completion.content)
+        }
+        Err(err) => {
+            artifact.error = Some(err.to_string());
+            orchestration::report(app, run_id, request_id, StageStatus::Failed, &artifact);
+            Err(err)
+        }
+    }
+}
+
+async fn run_rounds(
+    app: &AppHandle,
+    providers: &Providers,
+    client: &Client,
+    ids: (&str, &str),
+    request: &DebateRequest,
+    lineup: &Lineup,
+) -> Result<Vec<RoundVerdict>> {
+    let (request_id, run_id) = ids;
+    let Lineup {
+        seats,
+        judge,
+        rounds,
+    } = lineup;
+    let rounds = *rounds;
+    let mut turns: Vec<Turn> = Vec::new();
+    let mut verdicts = Vec::with_capacity(rounds as usize);
+    for round in 1..=rounds {
+        let step = (round as usize - 1) * 2;
+        for (debater, seat) in seats.iter().enumerate() {
+            let messages = turn_messages(&request.motion, seats, &turns, debater, round, rounds);
+            let turn = chat(&seat.target, messages, request);
+            let mut on_delta = |delta: &str| {
+                let _ = app.emit(
+                    "debate-token",
+                    DebateToken {
+                        request_id: request_id.to_string(),
+                        run_id: run_id.to_string(),
+                        round,
+                        debater,
+                        provider: seat.target.0.clone(),
+                        delta: delta.to_string(),
+                    },
+                );
+            };
+            let artifact = Artifact::new(step, debater, StageKind::Argument, &seat.target);
+            let content =
+                speak(app, providers, client, ids, &turn, artifact, &mut on_delta).await?;
+            turns.push(Turn {
+                round,
+                debater,
+                content,
+            });
+        }
+
+        let prompt = judge_prompt(&request.motion, seats, &turns, round);
+        let mut scoring = chat(
+            judge,
+            vec![ChatMessage {
+                role: Role::User,
+                content: prompt,
+            }],
+            request,
+        );
+        scoring.temperature = Some(JUDGE_TEMPERATURE);
+        scoring.max_tokens = None;
+        let artifact = Artifact::new(step + 1, 0, StageKind::Verdict, judge);
+        let content = speak(app, providers, client, ids, &scoring, artifact, &mut |_| {}).await?;
+        let verdict = parse_verdict(&judge.0, &content, round, seats.len())?;
+        let _ = app.emit(
+            "debate-round",
+            RoundProgress {
+                request_id: request_id.to_string(),
+                run_id: run_id.to_string(),
+                verdict: verdict.clone(),
+            },
+        );
+        verdicts.push(verdict);
+    }
+    Ok(verdicts)
+}
+
+/// Runs a debate to the end, or until `request_id` is cancelled; the turns
+/// and verdicts so far are kept either way.
+#[tauri::command]
+pub async fn run_debate(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    requests: State<'_, Requests>,
+    db: State<'_, Database>,
+    request_id: String,
+    request: DebateRequest,
+) -> Result<Debate> {
+    if request.motion.trim().is_empty() {
+        return Err(Error::InvalidSetting("a debate needs a motion".into()));
+    }
+    if request.debaters.len() < 2 {
+        return Err(Error::InvalidSetting(
+            "a debate needs at least two debaters".into(),
+        ));
+    }
+    let rounds = request.rounds.unwrap_or(DEFAULT_ROUNDS);
+    if !(1..=MAX_ROUNDS).contains(&rounds) {
+        return Err(Error::InvalidSetting(format!(
+            "a debate runs for 1 to {MAX_ROUNDS} rounds"
+        )));
+    }
+    let seats = request
+        .debaters
+        .iter()
+        .enumerate()
+        .map(|(index, debater)| {
+            let choice = ModelChoice {
+                provider: debater.provider.clone(),
+                model: debater.model.clone(),
+            };
+            Ok(Seat {
+                target: arbiter::resolve(&providers, &choice)?,
+                stance: stance(index, debater),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let lineup = Lineup {
+        seats,
+        judge: arbiter::resolve(&providers, &request.judge)?,
+        rounds,
+    };
+
+    let run = orchestration::begin(&db, DEBATE, request.conversation_id.clone(), &request)?;
+    let guard = requests.register(&request_id);
+    let outcome = cancellable(
+        guard.token(),
+        run_rounds(
+            &app,
+            &providers,
+            &client,
+            (&request_id, &run.id),
+            &request,
+            &lineup,
+        ),
+    )
+    .await;
+    orchestration::finish(&db, &run.id, &outcome)?;
+    let verdicts = outcome?;
+
+    let mut totals = vec![0.0; lineup.seats.len()];
+    for verdict in &verdicts {
+        for score in &verdict.scores {
+            totals[score.debater] += score.score;
+        }
+    }
+    let best = totals.iter().copied().fold(f32::MIN, f32::max);
+    let leaders: Vec<usize> = (0..totals.len()).filter(|&i| totals[i] == best).collect();
+    Ok(Debate {
+        run: db.get_orchestration_run(&run.id)?,
+        rounds: verdicts,
+        winner: (leaders.len() == 1).then(|| leaders[0]),
+        totals,
+    })
+}
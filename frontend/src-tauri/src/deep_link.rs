@@ -0,0 +1,193 @@
+//! `pentamind://` links, which open the app at a given place:
+//!
+//! - `pentamind://new?prompt=...` starts a chat, optionally with
+//!   `provider` and `model`
+//! - `pentamind://conversation/<id>` opens a saved conversation
+//! - `pentamind://settings` opens the settings
+//!
+//! Links arrive as launch arguments (Windows, Linux; forwarded by
+//! `instance` when the app is already running) or as an open-URL event
+//! (macOS). Each one shows the main window and is emitted as `deep-link`.
+//! Links that arrive before the frontend is listening, such as the one the
+//! app was launched with, wait for `take_pending_deep_links`. Links to
+//! `pentamind://notification/...` are clicks on Windows notifications and
+//! go to `notifications` instead.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, State, Url};
+
+use crate::error::{Error, Result};
+use crate::notifications;
+use crate::windows;
+
+pub const SCHEME: &str = "pentamind";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum DeepLink {
+    New {
+        prompt: Option<String>,
+        provider: Option<String>,
+        model: Option<String>,
+    },
+    Conversation {
+        id: String,
+    },
+    Settings,
+}
+
+/// Payload of the `deep-link` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeepLinkEvent {
+    pub url: String,
+    #[serde(flatten)]
+    pub link: DeepLink,
+}
+
+/// Links held until the frontend asks for them. Managed as Tauri state.
+#[derive(Default)]
+pub struct DeepLinks {
+    frontend_ready: AtomicBool,
+    pending: Mutex<Vec<DeepLinkEvent>>,
+}
+
+fn invalid(url: &str, reason: &str) -> Error {
+    Error::InvalidDeepLink(format!("{url}: {reason}"))
+}
+
+pub fn parse(url: &str) -> Result<DeepLink> {
+    let parsed = Url::parse(url).map_err(|e| invalid(url, &e.to_string()))?;
+    if parsed.scheme() != SCHEME {
+        return Err(invalid(url, "not a pentamind:// link"));
+    }
+    let query = |key: &str| {
+        parsed
+            .query_pairs()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.into_owned())
+            .filter(|v| !v.is_empty())
+    };
+    let action = parsed.host_str().unwrap_or_default().to_ascii_lowercase();
+    match action.as_str() {
+        "new" => Ok(DeepLink::New {
+            prompt: query("prompt"),
+            provider: query("provider"),
+            model: query("model"),
+        }),
+        "conversation" => {
+            let id = parsed.path().trim_matches('/');
+            if id.is_empty() || id.contains('/') {
+                return Err(invalid(url, "expected pentamind://conversation/<id>"));
+            }
+            Ok(DeepLink::Conversation { id: id.to_string() })
+        }
+        "settings" => Ok(DeepLink::Settings),
+        _ => Err(invalid(url, "unknown action")),
+    }
+}
+
+/// Opens the main window and hands `url` to the frontend. Malformed links
+/// are ignored.
+pub fn handle_url(app: &AppHandle, url: &str) {
+    if notifications::handle_link(app, url) {
+        return;
+    }
+    let Ok(link) = parse(url) else {
+        return;
+    };
+    let event = DeepLinkEvent {
+        url: url.to_string(),
+        link,
+    };
+    windows::show_main_window(app);
+    let links = app.state::<DeepLinks>();
+    // Checked under the lock so a link can't slip between the frontend
+    // draining the queue and marking itself ready.
+    let mut pending = links.pending.lock().unwrap();
+    if links.frontend_ready.load(Ordering::SeqCst) {
+        drop(pending);
+        let _ = app.emit("deep-link", event);
+    } else {
+        pending.push(event);
+    }
+}
+
+/// Handles any links among a launch's arguments.
+pub fn handle_args(app: &AppHandle, args: &[String]) {
+    let prefix = format!("{SCHEME}:");
+    for arg in args.iter().filter(|a| a.starts_with(&prefix)) {
+        handle_url(app, arg);
+    }
+}
+
+/// Makes the OS send `pentamind://` links to this executable. On macOS the
+/// scheme is declared in the bundle's Info.plist instead.
+pub fn register(app: &AppHandle) {
+    let Ok(exe) = std::env::current_exe() else {
+        return;
+    };
+    #[cfg(target_os = "linux")]
+    if let Ok(data_dir) = app.path().data_dir() {
+        std::thread::spawn(move || register_linux(&data_dir.join("applications"), &exe));
+    }
+    #[cfg(windows)]
+    std::thread::spawn(move || register_windows(&exe));
+    #[cfg(not(target_os = "linux"))]
+    let _ = app;
+    #[cfg(target_os = "macos")]
+    let _ = exe;
+}
+
+#[cfg(target_os = "linux")]
+fn register_linux(applications: &std::path::Path, exe: &std::path::Path) {
+    const DESKTOP_FILE: &str = "pentamind-url-handler.desktop";
+    let entry = format!(
+        "[Desktop Entry]\nType=Application\nName=Pentamind\nExec=\"{}\" %u\n\
+         NoDisplay=true\nMimeType=x-scheme-handler/{SCHEME};\n",
+        exe.display()
+    );
+    let path = applications.join(DESKTOP_FILE);
+    if std::fs::read_to_string(&path).is_ok_and(|existing| existing == entry) {
+        return;
+    }
+    if std::fs::create_dir_all(applications).is_err() || std::fs::write(&path, entry).is_err() {
+        return;
+    }
+    let _ = crate::process::command("xdg-mime")
+        .args(["default", DESKTOP_FILE])
+        .arg(format!("x-scheme-handler/{SCHEME}"))
+        .status();
+}
+
+#[cfg(windows)]
+fn register_windows(exe: &std::path::Path) {
+    let key = format!(r"HKCU\Software\Classes\{SCHEME}");
+    let command = format!("\"{}\" \"%1\"", exe.display());
+    let entries: [(String, &[&str]); 3] = [
+        (key.clone(), &["/ve", "/d", "URL:Pentamind"]),
+        (key.clone(), &["/v", "URL Protocol", "/d", ""]),
+        (
+            format!(r"{key}\shell\open\command"),
+            &["/ve", "/d", &command],
+        ),
+    ];
+    for (key, values) in entries {
+        let _ = crate::process::command("reg")
+            .args(["add", &key])
+            .args(values)
+            .arg("/f")
+            .status();
+    }
+}
+
+/// Links that arrived before the frontend was listening. After this call
+/// every link is emitted as `deep-link` instead.
+#[tauri::command]
+pub fn take_pending_deep_links(links: State<'_, DeepLinks>) -> Vec<DeepLinkEvent> {
+    let mut pending = links.pending.lock().unwrap();
+    links.frontend_ready.store(true, Ordering::SeqCst);
+    std::mem::take(&mut *pending)
+}
@@ -0,0 +1,260 @@
+//! Compares the answers of a fan-out pairwise: a word or sentence diff the
+//! UI can render span by span, a lexical similarity score, and optionally
+//! the cosine similarity of their embeddings.
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::error::Result;
+use crate::providers::Providers;
+use crate::rag::{self, Embedder, EmbeddingOptions};
+
+/// Edit distance past which a pair is reported as rewritten outright, which
+/// keeps the diff's memory bounded for answers that share little.
+const MAX_EDIT_DISTANCE: usize = 2048;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiffInput {
+    /// Echoed back to tell the pairs apart, usually the provider id.
+    pub id: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    #[default]
+    Word,
+    Sentence,
+    Line,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffOp {
+    Equal,
+    /// Only in the left text.
+    Delete,
+    /// Only in the right text.
+    Insert,
+}
+
+/// A run of text with the same op. The equal and deleted spans in order
+/// spell out the left text exactly, the equal and inserted ones the right
+/// text apart from whitespace inside equal runs.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffSpan {
+    pub op: DiffOp,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PairDiff {
+    pub left: String,
+    pub right: String,
+    /// Share of tokens the texts have in common, from 0 to 1.
+    pub similarity: f32,
+    /// Cosine similarity of the embeddings, when they were requested.
+    pub semantic_similarity: Option<f32>,
+    pub spans: Vec<DiffSpan>,
+}
+
+/// A word or sentence with the byte range it covers in its text, trailing
+/// whitespace included.
+struct Token<'a> {
+    key: String,
+    extent: &'a str,
+}
+
+fn tokenize(text: &str, granularity: Granularity) -> Vec<Token<'_>> {
+    let pieces: Vec<(usize, &str)> = match granularity {
+        Granularity::Word => text.split_word_bound_indices().collect(),
+        Granularity::Sentence => text.split_sentence_bound_indices().collect(),
+        Granularity::Line => text
+            .split_inclusive('\n')
+            .scan(0, |start, line| {
+                let piece = (*start, line);
+                *start += line.len();
+                Some(piece)
+            })
+            .collect(),
+    };
+    let starts: Vec<usize> = pieces
+        .iter()
+        .filter(|(_, piece)| !piece.trim().is_empty())
+        .map(|(start, _)| *start)
+        .collect();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| {
+            // Leading whitespace belongs to the first token.
+            let start = if i == 0 { 0 } else { start };
+            let end = starts.get(i + 1).copied().unwrap_or(text.len());
+            let extent = &text[start..end];
+            Token {
+                key: extent.split_whitespace().collect::<Vec<_>>().join(" "),
+                extent,
+            }
+        })
+        .collect()
+}
+
+/// The shortest edit script turning `a` into `b` (Myers' algorithm), or
+/// `None` if it would take more than `MAX_EDIT_DISTANCE` edits.
+fn edit_script(a: &[&str], b: &[&str]) -> Option<Vec<DiffOp>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let offset = max + 1;
+    let index = |k: isize| (k + offset) as usize;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let mut trace = Vec::new();
+    'search: for d in 0..=max {
+        if d as usize > MAX_EDIT_DISTANCE {
+            return None;
+        }
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let down = k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]);
+            let mut x = if down {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let (mut x, mut y) = (n, m);
+    let mut ops = Vec::new();
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let down = k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]);
+        let prev_k = if down { k + 1 } else { k - 1 };
+        let prev_x = v[index(prev_k)];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            ops.push(DiffOp::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            ops.push(if down { DiffOp::Insert } else { DiffOp::Delete });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    ops.reverse();
+    Some(ops)
+}
+
+/// Diffs two texts, returning their spans and lexical similarity.
+pub(crate) fn diff_pair(left: &str, right: &str, granularity: Granularity) -> (Vec<DiffSpan>, f32) {
+    let left = tokenize(left, granularity);
+    let right = tokenize(right, granularity);
+    let a: Vec<&str> = left.iter().map(|t| t.key.as_str()).collect();
+    let b: Vec<&str> = right.iter().map(|t| t.key.as_str()).collect();
+
+    // Common ends are cheap to match and shrink what the search has to cover.
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (middle_a, middle_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let middle = edit_script(middle_a, middle_b).unwrap_or_else(|| {
+        let mut ops = vec![DiffOp::Delete; middle_a.len()];
+        ops.resize(middle_a.len() + middle_b.len(), DiffOp::Insert);
+        ops
+    });
+    let ops = std::iter::repeat_n(DiffOp::Equal, prefix)
+        .chain(middle)
+        .chain(std::iter::repeat_n(DiffOp::Equal, suffix));
+
+    let mut spans: Vec<DiffSpan> = Vec::new();
+    let (mut i, mut j, mut equal) = (0, 0, 0);
+    for op in ops {
+        let text = match op {
+            DiffOp::Equal => {
+                equal += 1;
+                j += 1;
+                i += 1;
+                left[i - 1].extent
+            }
+            DiffOp::Delete => {
+                i += 1;
+                left[i - 1].extent
+            }
+            DiffOp::Insert => {
+                j += 1;
+                right[j - 1].extent
+            }
+        };
+        match spans.last_mut() {
+            Some(last) if last.op == op => last.text.push_str(text),
+            _ => spans.push(DiffSpan {
+                op,
+                text: text.to_string(),
+            }),
+        }
+    }
+    let total = left.len() + right.len();
+    let similarity = if total == 0 {
+        1.0
+    } else {
+        2.0 * equal as f32 / total as f32
+    };
+    (spans, similarity)
+}
+
+/// Diffs every pair of `responses`. Passing `embedding` also scores each
+/// pair by meaning, at the cost of one embeddings call.
+#[tauri::command]
+pub async fn diff_responses(
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    responses: Vec<DiffInput>,
+    granularity: Option<Granularity>,
+    embedding: Option<EmbeddingOptions>,
+) -> Result<Vec<PairDiff>> {
+    let vectors = match embedding {
+        Some(options) => {
+            let embedder = Embedder::resolve(&providers, &options)?;
+            let texts: Vec<String> = responses.iter().map(|r| r.text.clone()).collect();
+            Some(embedder.embed(&client, &texts).await?)
+        }
+        None => None,
+    };
+    let granularity = granularity.unwrap_or_default();
+    let pairs = tauri::async_runtime::spawn_blocking(move || {
+        let mut pairs = Vec::new();
+        for (i, left) in responses.iter().enumerate() {
+            for (j, right) in responses.iter().enumerate().skip(i + 1) {
+                let (spans, similarity) = diff_pair(&left.text, &right.text, granularity);
+                pairs.push(PairDiff {
+                    left: left.id.clone(),
+                    right: right.id.clone(),
+                    similarity,
+                    semantic_similarity: vectors.as_ref().map(|v| rag::cosine(&v[i], &v[j])),
+                    spans,
+                });
+            }
+        }
+        pairs
+    })
+    .await?;
+    Ok(pairs)
+}
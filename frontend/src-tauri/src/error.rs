@@ -0,0 +1,114 @@
+use serde::{Serialize, Serializer};
+
+/// Errors returned from Tauri commands. Serialized as a plain message so the
+/// frontend can show it directly.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Tauri(#[from] tauri::Error),
+    #[error(transparent)]
+    Keyring(#[from] keyring::Error),
+    #[error(transparent)]
+    Database(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Shortcut(#[from] tauri_plugin_global_shortcut::Error),
+    #[error(transparent)]
+    Opener(#[from] tauri_plugin_opener::Error),
+    #[error(transparent)]
+    Pdf(#[from] lopdf::Error),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    #[error("{provider} returned {status}: {body}")]
+    Api {
+        provider: String,
+        status: u16,
+        body: String,
+        /// How long the provider asked to be left alone, if it said.
+        retry_after: Option<std::time::Duration>,
+    },
+    #[error("{0}")]
+    Provider(String),
+    #[error("no API key configured for {0}")]
+    MissingApiKey(String),
+    #[error("unknown provider `{0}`")]
+    UnknownProvider(String),
+    #[error("request cancelled")]
+    Cancelled,
+    #[error("offline; the prompt will be sent when the connection is back")]
+    Queued,
+    #[error("the prompt was held back because it contains: {0}")]
+    Scrubbed(String),
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("could not extract text from {0}")]
+    Extraction(String),
+    #[error("invalid shortcut `{0}`")]
+    InvalidShortcut(String),
+    #[error("{0} is not supported")]
+    Unsupported(String),
+    #[error("the database is locked")]
+    Locked,
+    #[error("the database couldn't be opened: {0}")]
+    Unavailable(String),
+    #[error("incorrect passphrase")]
+    WrongPassphrase,
+    #[error("{0}")]
+    Encryption(String),
+    #[error("invalid backup: {0}")]
+    InvalidBackup(String),
+    #[error("screen capture failed: {0}")]
+    Capture(String),
+    #[error("OCR failed: {0}")]
+    Ocr(String),
+    #[error("{0}")]
+    Audio(String),
+    #[error("invalid setting: {0}")]
+    InvalidSetting(String),
+    #[error("invalid template: {0}")]
+    InvalidTemplate(String),
+    #[error("invalid note: {0}")]
+    InvalidNote(String),
+    #[error("invalid preset: {0}")]
+    InvalidPreset(String),
+    #[error("can't attach {0}")]
+    InvalidAttachment(String),
+    #[error("MCP: {0}")]
+    Mcp(String),
+    #[error("plugin: {0}")]
+    Plugin(String),
+    #[error("connector: {0}")]
+    Connector(String),
+    #[error("{0}")]
+    Tool(String),
+    #[error("invalid patch: {0}")]
+    Patch(String),
+    #[error("OS search: {0}")]
+    #[cfg_attr(not(any(target_os = "macos", windows)), allow(dead_code))]
+    OsSearch(String),
+    #[error("couldn't fetch the page: {0}")]
+    Fetch(String),
+    #[error("invalid link {0}")]
+    InvalidDeepLink(String),
+    #[error("update failed: {0}")]
+    Update(String),
+    #[error("couldn't show the notification: {0}")]
+    Notification(String),
+    #[error("sync failed: {0}")]
+    Sync(String),
+    #[error("realtime session: {0}")]
+    Realtime(String),
+}
+
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,710 @@
+//! Benchmarks models against a dataset. A dataset is a JSONL file of
+//! prompts, one case per line; each case is fanned out to the chosen
+//! providers and every answer is scored by the graders: exact match with
+//! the expected answer, a regular expression, or a judge model. Runs go in
+//! the background as jobs, report each finished case as `eval-progress`,
+//! and are kept in SQLite for `get_eval_report`.
+//!
+//! A case looks like
+//! `{"id": "capital-fr", "prompt": "Capital of France?", "expected": "Paris"}`,
+//! and may carry `system` and `graders` of its own.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use regex::Regex;
+use reqwest::Client;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, OptionalExtension, Row, ToSql};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::arbiter::{self, ModelChoice};
+use crate::error::{Error, Result};
+use crate::fanout::{self, FanoutRequest, FanoutResult, FanoutTarget};
+use crate::jobs::{self, JobContext, JobInfo, JobKind};
+use crate::llm::{ChatMessage, ChatRequest, Role};
+use crate::providers::Providers;
+use crate::storage::{new_id, now_ms, Database};
+use crate::usage;
+
+const JUDGE_TEMPERATURE: f32 = 0.0;
+const DEFAULT_RUBRIC: &str = "Score it for correctness, completeness and clarity";
+
+/// Scores one answer between 0 and 1.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Grader {
+    /// 1 when the answer is the case's `expected`, give or take whitespace
+    /// and, unless `case_sensitive`, case. Skipped for cases without one.
+    ExactMatch {
+        #[serde(default)]
+        case_sensitive: bool,
+    },
+    /// 1 when `pattern` matches anywhere in the answer.
+    Regex { pattern: String },
+    /// A judge model's score out of 10, against `rubric` and the expected
+    /// answer when there is one.
+    Judge {
+        judge: ModelChoice,
+        #[serde(default)]
+        rubric: Option<String>,
+    },
+}
+
+impl Grader {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::ExactMatch { .. } => "exact_match",
+            Self::Regex { .. } => "regex",
+            Self::Judge { .. } => "judge",
+        }
+    }
+}
+
+/// One line of a dataset.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EvalCase {
+    #[serde(default)]
+    pub id: Option<String>,
+    pub prompt: String,
+    #[serde(default)]
+    pub system: Option<String>,
+    #[serde(default)]
+    pub expected: Option<String>,
+    /// Used instead of the run's graders for this case.
+    #[serde(default)]
+    pub graders: Vec<Grader>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvalRequest {
+    /// Defaults to the dataset's file name.
+    #[serde(default)]
+    pub name: Option<String>,
+    pub dataset: PathBuf,
+    /// Empty means every configured provider.
+    #[serde(default)]
+    pub targets: Vec<FanoutTarget>,
+    pub graders: Vec<Grader>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    /// Ask every provider afresh instead of reusing cached answers, so
+    /// latencies mean something.
+    #[serde(default)]
+    pub bypass_cache: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraderScore {
+    /// The grader's kind, numbered from the second of a kind on
+    /// (`regex`, `regex#2`).
+    pub grader: String,
+    pub score: f32,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalResult {
+    pub case_index: usize,
+    pub case_id: Option<String>,
+    pub provider: String,
+    pub model: String,
+    pub response: Option<String>,
+    pub error: Option<String>,
+    pub scores: Vec<GraderScore>,
+    /// Mean of `scores`; `None` when no grader applied. A failed request
+    /// scores 0.
+    pub score: Option<f32>,
+    pub latency_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvalStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl ToSql for EvalStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let status = match self {
+            EvalStatus::Running => "running",
+            EvalStatus::Completed => "completed",
+            EvalStatus::Failed => "failed",
+            EvalStatus::Cancelled => "cancelled",
+        };
+        Ok(status.into())
+    }
+}
+
+impl FromSql for EvalStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "running" => Ok(EvalStatus::Running),
+            "completed" => Ok(EvalStatus::Completed),
+            "failed" => Ok(EvalStatus::Failed),
+            "cancelled" => Ok(EvalStatus::Cancelled),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalRun {
+    pub id: String,
+    pub name: String,
+    pub dataset: String,
+    pub cases: usize,
+    pub status: EvalStatus,
+    pub error: Option<String>,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+}
+
+/// What `run_eval` returns: the stored run, and the job to follow or
+/// cancel it by.
+#[derive(Debug, Clone, Serialize)]
+pub struct StartedEval {
+    pub run: EvalRun,
+    pub job: JobInfo,
+}
+
+/// Payload of the `eval-progress` event, one per finished case.
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalProgress {
+    pub run_id: String,
+    pub completed: usize,
+    pub total: usize,
+    pub results: Vec<EvalResult>,
+}
+
+/// How one model did over a run.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelSummary {
+    pub provider: String,
+    pub model: String,
+    pub cases: usize,
+    pub errors: usize,
+    /// Mean over the graded cases.
+    pub mean_score: Option<f32>,
+    /// Mean per grader.
+    pub graders: BTreeMap<String, f32>,
+    pub mean_latency_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalReport {
+    pub run: EvalRun,
+    /// Best mean score first.
+    pub models: Vec<ModelSummary>,
+    pub results: Vec<EvalResult>,
+}
+
+impl EvalRun {
+    const COLUMNS: &'static str =
+        "id, name, dataset, cases, status, error, started_at, finished_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            dataset: row.get(2)?,
+            cases: row.get(3)?,
+            status: row.get(4)?,
+            error: row.get(5)?,
+            started_at: row.get(6)?,
+            finished_at: row.get(7)?,
+        })
+    }
+}
+
+impl EvalResult {
+    const COLUMNS: &'static str =
+        "case_index, case_id, provider, model, response, error, scores, score, latency_ms";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let scores: String = row.get(6)?;
+        Ok(Self {
+            case_index: row.get(0)?,
+            case_id: row.get(1)?,
+            provider: row.get(2)?,
+            model: row.get(3)?,
+            response: row.get(4)?,
+            error: row.get(5)?,
+            scores: serde_json::from_str(&scores).unwrap_or_default(),
+            score: row.get(7)?,
+            latency_ms: row.get(8)?,
+        })
+    }
+}
+
+impl Database {
+    fn insert_eval_run(&self, run: &EvalRun, request: &EvalRequest) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO eval_runs
+                 (id, name, dataset, request, cases, status, started_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                run.id,
+                run.name,
+                run.dataset,
+                serde_json::to_string(request)?,
+                run.cases,
+                run.status,
+                run.started_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn finish_eval_run(&self, id: &str, status: EvalStatus, error: Option<&str>) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE eval_runs SET status = ?2, error = ?3, finished_at = ?4 WHERE id = ?1",
+            params![id, status, error, now_ms()],
+        )?;
+        Ok(())
+    }
+
+    fn save_eval_result(&self, run_id: &str, result: &EvalResult) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO eval_results
+                 (run_id, case_index, case_id, provider, model, response, error, scores,
+                  score, latency_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                run_id,
+                result.case_index,
+                result.case_id,
+                result.provider,
+                result.model,
+                result.response,
+                result.error,
+                serde_json::to_string(&result.scores)?,
+                result.score,
+                result.latency_ms
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_eval_run(&self, id: &str) -> Result<EvalRun> {
+        self.conn()?
+            .query_row(
+                &format!("SELECT {} FROM eval_runs WHERE id = ?1", EvalRun::COLUMNS),
+                [id],
+                EvalRun::from_row,
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("eval run {id}")))
+    }
+
+    fn list_eval_runs(&self, limit: usize) -> Result<Vec<EvalRun>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM eval_runs ORDER BY started_at DESC LIMIT ?1",
+            EvalRun::COLUMNS
+        ))?;
+        let rows = stmt.query_map([limit], EvalRun::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn eval_results(&self, run_id: &str) -> Result<Vec<EvalResult>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM eval_results WHERE run_id = ?1
+             ORDER BY case_index, provider, model",
+            EvalResult::COLUMNS
+        ))?;
+        let rows = stmt.query_map([run_id], EvalResult::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+fn read_dataset(path: &std::path::Path) -> Result<Vec<EvalCase>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut cases = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let case: EvalCase = serde_json::from_str(line).map_err(|e| {
+            Error::InvalidSetting(format!("{} line {}: {e}", path.display(), number + 1))
+        })?;
+        cases.push(case);
+    }
+    if cases.is_empty() {
+        return Err(Error::InvalidSetting(format!(
+            "{} has no cases",
+            path.display()
+        )));
+    }
+    Ok(cases)
+}
+
+/// Fails on graders that could never run: a bad pattern or an unknown
+/// judge.
+fn check_graders(providers: &Providers, graders: &[Grader]) -> Result<()> {
+    for grader in graders {
+        match grader {
+            Grader::ExactMatch { .. } => {}
+            Grader::Regex { pattern } => {
+                Regex::new(pattern)
+                    .map_err(|e| Error::InvalidSetting(format!("grader pattern: {e}")))?;
+            }
+            Grader::Judge { judge, .. } => {
+                arbiter::resolve(providers, judge)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn normalize(text: &str, case_sensitive: bool) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if case_sensitive {
+        text
+    } else {
+        text.to_lowercase()
+    }
+}
+
+async fn judge(
+    app: &AppHandle,
+    case: &EvalCase,
+    answer: &str,
+    choice: &ModelChoice,
+    rubric: Option<&str>,
+) -> Result<(f32, Option<String>)> {
+    let providers = app.state::<Providers>();
+    let (provider_id, model) = arbiter::resolve(&providers, choice)?;
+    let reference = case
+        .expected
+        .as_deref()
+        .map(|expected| format!("## Reference answer\n{expected}\n\n"))
+        .unwrap_or_default();
+    let prompt = format!(
+        "You are grading an answer to a question. {rubric}, from 0 to 10.\n\n\
+         ## Question\n{question}\n\n{reference}## Answer\n{answer}\n\n\
+         Reply with JSON only, in the form {{\"score\": 7, \"reason\": \"...\"}}.",
+        rubric = rubric.unwrap_or(DEFAULT_RUBRIC),
+        question = case.prompt,
+    );
+    let request = ChatRequest {
+        provider: provider_id,
+        model,
+        messages: vec![ChatMessage {
+            role: Role::User,
+            content: prompt,
+        }],
+        temperature: Some(JUDGE_TEMPERATURE),
+        top_p: None,
+        max_tokens: None,
+        use_tools: false,
+        bypass_cache: false,
+        response_schema: None,
+        conversation_id: None,
+        tools: Vec::new(),
+        tool_rounds: Vec::new(),
+    };
+    let completion = providers
+        .get(&request.provider)?
+        .stream(&app.state::<Client>(), &request, &mut |_| {})
+        .await?;
+    usage::record(app, &request, &completion.content, completion.usage);
+
+    let verdict: Value =
+        serde_json::from_str(arbiter::strip_fence(&completion.content)).map_err(|_| {
+            Error::Provider(format!("{} returned an unreadable grade", request.provider))
+        })?;
+    let score = verdict["score"]
+        .as_f64()
+        .unwrap_or_default()
+        .clamp(0.0, 10.0) as f32;
+    let reason = verdict["reason"].as_str().map(str::to_string);
+    Ok((score / 10.0, reason))
+}
+
+/// Every applicable grader's score for `answer`. A judge that fails is left
+/// out rather than scoring 0.
+async fn grade(
+    app: &AppHandle,
+    case: &EvalCase,
+    graders: &[Grader],
+    answer: &str,
+) -> Vec<GraderScore> {
+    let mut seen: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut scores = Vec::new();
+    for grader in graders {
+        let count = seen.entry(grader.kind()).or_default();
+        *count += 1;
+        let label = match *count {
+            1 => grader.kind().to_string(),
+            n => format!("{}#{n}", grader.kind()),
+        };
+        let graded = match grader {
+            Grader::ExactMatch { case_sensitive } => case.expected.as_ref().map(|expected| {
+                let matched =
+                    normalize(answer, *case_sensitive) == normalize(expected, *case_sensitive);
+                (if matched { 1.0 } else { 0.0 }, None)
+            }),
+            Grader::Regex { pattern } => Regex::new(pattern)
+                .ok()
+                .map(|re| (if re.is_match(answer) { 1.0 } else { 0.0 }, None)),
+            Grader::Judge {
+                judge: choice,
+                rubric,
+            } => match judge(app, case, answer, choice, rubric.as_deref()).await {
+                Ok(graded) => Some(graded),
+                Err(err) => {
+                    tracing::warn!("eval judge {} failed: {err}", choice.provider);
+                    None
+                }
+            },
+        };
+        if let Some((score, reason)) = graded {
+            scores.push(GraderScore {
+                grader: label,
+                score,
+                reason,
+            });
+        }
+    }
+    scores
+}
+
+async fn grade_result(
+    app: &AppHandle,
+    index: usize,
+    case: &EvalCase,
+    graders: &[Grader],
+    result: FanoutResult,
+) -> EvalResult {
+    let (scores, score) = match &result.content {
+        Some(answer) => {
+            let scores = grade(app, case, graders, answer).await;
+            let score = (!scores.is_empty())
+                .then(|| scores.iter().map(|s| s.score).sum::<f32>() / scores.len() as f32);
+            (scores, score)
+        }
+        None => (Vec::new(), Some(0.0)),
+    };
+    EvalResult {
+        case_index: index,
+        case_id: case.id.clone(),
+        provider: result.provider,
+        model: result.model,
+        response: result.content,
+        error: result.error,
+        scores,
+        score,
+        latency_ms: result.latency_ms,
+    }
+}
+
+async fn evaluate(
+    ctx: &JobContext,
+    run_id: &str,
+    request: &EvalRequest,
+    cases: &[EvalCase],
+) -> Result<()> {
+    let app = ctx.app();
+    let total = cases.len();
+    for (index, case) in cases.iter().enumerate() {
+        ctx.progress(
+            Some(index as f32 / total as f32),
+            format!("{} of {total} prompts", index + 1),
+        );
+        let mut messages = Vec::new();
+        if let Some(system) = &case.system {
+            messages.push(ChatMessage {
+                role: Role::System,
+                content: system.clone(),
+            });
+        }
+        messages.push(ChatMessage {
+            role: Role::User,
+            content: case.prompt.clone(),
+        });
+        let fanout = FanoutRequest {
+            targets: request.targets.clone(),
+            messages,
+            temperature: request.temperature,
+            top_p: None,
+            max_tokens: request.max_tokens,
+            preset_id: None,
+            use_tools: false,
+            bypass_cache: request.bypass_cache,
+            conversation_id: None,
+        };
+        let answers = fanout::fan_out(app, &new_id(), &fanout).await?;
+
+        let graders = if case.graders.is_empty() {
+            &request.graders
+        } else {
+            &case.graders
+        };
+        let mut results = Vec::with_capacity(answers.len());
+        for answer in answers {
+            let result = grade_result(app, index, case, graders, answer).await;
+            app.state::<Database>().save_eval_result(run_id, &result)?;
+            results.push(result);
+        }
+        let _ = app.emit(
+            "eval-progress",
+            EvalProgress {
+                run_id: run_id.to_string(),
+                completed: index + 1,
+                total,
+                results,
+            },
+        );
+    }
+    Ok(())
+}
+
+/// Marks the run cancelled if the job is dropped before it finishes.
+struct Unfinished {
+    app: AppHandle,
+    run_id: String,
+    finished: bool,
+}
+
+impl Drop for Unfinished {
+    fn drop(&mut self) {
+        if !self.finished {
+            let db = self.app.state::<Database>();
+            let _ = db.finish_eval_run(&self.run_id, EvalStatus::Cancelled, None);
+        }
+    }
+}
+
+fn summarize(results: &[EvalResult]) -> Vec<ModelSummary> {
+    let mut by_model: BTreeMap<(&str, &str), Vec<&EvalResult>> = BTreeMap::new();
+    for result in results {
+        by_model
+            .entry((&result.provider, &result.model))
+            .or_default()
+            .push(result);
+    }
+    let mean = |values: &[f32]| {
+        (!values.is_empty()).then(|| values.iter().sum::<f32>() / values.len() as f32)
+    };
+    let mut models: Vec<ModelSummary> = by_model
+        .into_iter()
+        .map(|((provider, model), results)| {
+            let scores: Vec<f32> = results.iter().filter_map(|r| r.score).collect();
+            let mut per_grader: BTreeMap<String, Vec<f32>> = BTreeMap::new();
+            for score in results.iter().flat_map(|r| &r.scores) {
+                per_grader
+                    .entry(score.grader.clone())
+                    .or_default()
+                    .push(score.score);
+            }
+            let latency: u64 = results.iter().map(|r| r.latency_ms).sum();
+            ModelSummary {
+                provider: provider.to_string(),
+                model: model.to_string(),
+                cases: results.len(),
+                errors: results.iter().filter(|r| r.error.is_some()).count(),
+                mean_score: mean(&scores),
+                graders: per_grader
+                    .into_iter()
+                    .filter_map(|(grader, scores)| Some((grader, mean(&scores)?)))
+                    .collect(),
+                mean_latency_ms: latency / results.len() as u64,
+            }
+        })
+        .collect();
+    models.sort_by(|a, b| {
+        b.mean_score
+            .unwrap_or(-1.0)
+            .total_cmp(&a.mean_score.unwrap_or(-1.0))
+    });
+    models
+}
+
+/// Starts an eval of `request.dataset` in the background. A dataset that
+/// doesn't parse, an unknown provider or a bad grader fails here; the run's
+/// progress comes as `eval-progress` and `job-progress` events.
+#[tauri::command]
+pub async fn run_eval(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    request: EvalRequest,
+) -> Result<StartedEval> {
+    let cases = read_dataset(&request.dataset)?;
+    for target in &request.targets {
+        providers.get(&target.provider)?;
+    }
+    check_graders(&providers, &request.graders)?;
+    for case in &cases {
+        check_graders(&providers, &case.graders)?;
+    }
+    if request.graders.is_empty() && cases.iter().any(|case| case.graders.is_empty()) {
+        return Err(Error::InvalidSetting(
+            "an eval needs at least one grader".into(),
+        ));
+    }
+
+    let name = request.name.clone().unwrap_or_else(|| {
+        request
+            .dataset
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "Eval".into())
+    });
+    let run = EvalRun {
+        id: new_id(),
+        name,
+        dataset: request.dataset.to_string_lossy().into_owned(),
+        cases: cases.len(),
+        status: EvalStatus::Running,
+        error: None,
+        started_at: now_ms(),
+        finished_at: None,
+    };
+    app.state::<Database>().insert_eval_run(&run, &request)?;
+
+    let run_id = run.id.clone();
+    let label = format!("Eval {}", run.name);
+    let job = jobs::spawn(&app, JobKind::Evaluation, label, |ctx| async move {
+        let mut guard = Unfinished {
+            app: ctx.app().clone(),
+            run_id: run_id.clone(),
+            finished: false,
+        };
+        let outcome = evaluate(&ctx, &run_id, &request, &cases).await;
+        guard.finished = true;
+        let db = ctx.app().state::<Database>();
+        match &outcome {
+            Ok(()) => db.finish_eval_run(&run_id, EvalStatus::Completed, None)?,
+            Err(err) => db.finish_eval_run(&run_id, EvalStatus::Failed, Some(&err.to_string()))?,
+        }
+        outcome.map(|()| run_id)
+    });
+    Ok(StartedEval { run, job })
+}
+
+/// A run with every result and a summary per model.
+#[tauri::command]
+pub fn get_eval_report(db: State<'_, Database>, run_id: String) -> Result<EvalReport> {
+    let run = db.get_eval_run(&run_id)?;
+    let results = db.eval_results(&run_id)?;
+    Ok(EvalReport {
+        run,
+        models: summarize(&results),
+        results,
+    })
+}
+
+/// Recent runs, newest first.
+#[tauri::command]
+pub fn list_eval_runs(db: State<'_, Database>, limit: Option<usize>) -> Result<Vec<EvalRun>> {
+    db.list_eval_runs(limit.unwrap_or(50))
+}
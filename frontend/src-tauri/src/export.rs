@@ -0,0 +1,395 @@
+//! Conversation transcripts as Markdown, JSON or PDF. Runs of assistant
+//! replies from different models (a fan-out) are laid out side by side, one
+//! column per model, so comparisons survive the export.
+
+use std::path::PathBuf;
+
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Document, Object, Stream};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::error::Result;
+use crate::llm::Role;
+use crate::storage::conversations::{ConversationDetail, Message};
+use crate::storage::Database;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Pdf,
+}
+
+/// A message on its own, or a fan-out shown as columns.
+pub(crate) enum Block<'a> {
+    Single(&'a Message),
+    Columns(Vec<&'a Message>),
+}
+
+fn same_source(a: &Message, b: &Message) -> bool {
+    a.provider == b.provider && a.model == b.model
+}
+
+pub(crate) fn blocks(messages: &[Message]) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < messages.len() {
+        let run = messages[i..]
+            .iter()
+            .take_while(|m| m.role == Role::Assistant)
+            .count();
+        let fanout = run > 1
+            && !messages[i..i + run]
+                .iter()
+                .all(|m| same_source(m, &messages[i]));
+        if fanout {
+            blocks.push(Block::Columns(messages[i..i + run].iter().collect()));
+            i += run;
+        } else {
+            blocks.push(Block::Single(&messages[i]));
+            i += 1;
+        }
+    }
+    blocks
+}
+
+pub(crate) fn speaker(message: &Message) -> String {
+    match message.role {
+        Role::User => "You".to_string(),
+        Role::System => "System".to_string(),
+        Role::Assistant => match (&message.provider, &message.model) {
+            (Some(provider), Some(model)) => format!("{provider} · {model}"),
+            (Some(provider), None) => provider.clone(),
+            _ => "Assistant".to_string(),
+        },
+    }
+}
+
+fn table_cell(text: &str) -> String {
+    text.trim()
+        .replace('|', "\\|")
+        .lines()
+        .collect::<Vec<_>>()
+        .join("<br>")
+}
+
+fn render_markdown(detail: &ConversationDetail) -> String {
+    let mut out = format!("# {}\n", detail.conversation.title);
+    for block in blocks(&detail.messages) {
+        match block {
+            Block::Single(message) => {
+                out.push_str(&format!(
+                    "\n## {}\n\n{}\n",
+                    speaker(message),
+                    message.content.trim()
+                ));
+            }
+            Block::Columns(messages) => {
+                let header: Vec<String> =
+                    messages.iter().map(|m| table_cell(&speaker(m))).collect();
+                let row: Vec<String> = messages.iter().map(|m| table_cell(&m.content)).collect();
+                out.push_str(&format!(
+                    "\n| {} |\n|{}|\n| {} |\n",
+                    header.join(" | "),
+                    " --- |".repeat(messages.len()),
+                    row.join(" | ")
+                ));
+            }
+        }
+    }
+    out
+}
+
+#[derive(Serialize)]
+struct JsonResponse<'a> {
+    provider: Option<&'a str>,
+    model: Option<&'a str>,
+    content: &'a str,
+    created_at: i64,
+}
+
+/// Every turn carries a list of responses; fan-outs simply have several.
+#[derive(Serialize)]
+struct JsonTurn<'a> {
+    role: Role,
+    responses: Vec<JsonResponse<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonExport<'a> {
+    id: &'a str,
+    title: &'a str,
+    created_at: i64,
+    updated_at: i64,
+    turns: Vec<JsonTurn<'a>>,
+}
+
+impl<'a> From<&'a Message> for JsonResponse<'a> {
+    fn from(m: &'a Message) -> Self {
+        Self {
+            provider: m.provider.as_deref(),
+            model: m.model.as_deref(),
+            content: &m.content,
+            created_at: m.created_at,
+        }
+    }
+}
+
+fn render_json(detail: &ConversationDetail) -> Result<Vec<u8>> {
+    let turns = blocks(&detail.messages)
+        .into_iter()
+        .map(|block| match block {
+            Block::Single(m) => JsonTurn {
+                role: m.role,
+                responses: vec![m.into()],
+            },
+            Block::Columns(ms) => JsonTurn {
+                role: Role::Assistant,
+                responses: ms.into_iter().map(JsonResponse::from).collect(),
+            },
+        })
+        .collect();
+    let conversation = &detail.conversation;
+    Ok(serde_json::to_vec_pretty(&JsonExport {
+        id: &conversation.id,
+        title: &conversation.title,
+        created_at: conversation.created_at,
+        updated_at: conversation.updated_at,
+        turns,
+    })?)
+}
+
+// A4 in points, with the built-in Helvetica faces so no font is embedded.
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+const BODY_SIZE: f32 = 10.0;
+const HEADING_SIZE: f32 = 12.0;
+const TITLE_SIZE: f32 = 18.0;
+const LEADING: f32 = 1.4;
+const COLUMN_GAP: f32 = 12.0;
+/// Helvetica's average glyph width as a fraction of the font size.
+const AVERAGE_GLYPH_WIDTH: f32 = 0.52;
+
+/// Maps text onto WinAnsiEncoding, which is all the standard fonts offer.
+fn win_ansi(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\u{2018}' | '\u{2019}' => bytes.push(b'\''),
+            '\u{201c}' | '\u{201d}' => bytes.push(b'"'),
+            '\u{2013}' | '\u{2014}' => bytes.push(b'-'),
+            '\u{2026}' => bytes.extend_from_slice(b"..."),
+            '\u{2022}' => bytes.push(0x95),
+            '\t' => bytes.extend_from_slice(b"    "),
+            c if (c as u32) < 0x80 || (0xa0..=0xff).contains(&(c as u32)) => bytes.push(c as u8),
+            _ => bytes.push(b'?'),
+        }
+    }
+    bytes
+}
+
+/// Word-wraps to at most `width` characters per line, splitting words that
+/// are longer than a whole line.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            while word.len() > width {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                lines.push(word.drain(..width).collect());
+            }
+            if word.is_empty() {
+                continue;
+            }
+            let word: String = word.into_iter().collect();
+            let len = line.chars().count();
+            if len > 0 && len + 1 + word.chars().count() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+fn chars_for(width: f32, size: f32) -> usize {
+    (width / (size * AVERAGE_GLYPH_WIDTH)) as usize
+}
+
+/// Lays text out top to bottom, starting a new page when one fills up.
+struct PdfLayout {
+    pages: Vec<Vec<Operation>>,
+    y: f32,
+}
+
+impl PdfLayout {
+    fn new() -> Self {
+        Self {
+            pages: vec![Vec::new()],
+            y: PAGE_HEIGHT - MARGIN,
+        }
+    }
+
+    fn reserve(&mut self, height: f32) {
+        if self.y - height < MARGIN {
+            self.pages.push(Vec::new());
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+    }
+
+    fn text_at(&mut self, x: f32, font: &str, size: f32, text: &str) {
+        let page = self.pages.last_mut().expect("layout always has a page");
+        page.extend([
+            Operation::new("BT", vec![]),
+            Operation::new("Tf", vec![font.into(), size.into()]),
+            Operation::new("Td", vec![x.into(), self.y.into()]),
+            Operation::new("Tj", vec![Object::string_literal(win_ansi(text))]),
+            Operation::new("ET", vec![]),
+        ]);
+    }
+
+    fn line(&mut self, font: &str, size: f32, text: &str) {
+        self.reserve(size * LEADING);
+        self.y -= size * LEADING;
+        self.text_at(MARGIN, font, size, text);
+    }
+
+    fn paragraph(&mut self, font: &str, size: f32, text: &str) {
+        for line in wrap(text, chars_for(PAGE_WIDTH - 2.0 * MARGIN, size)) {
+            self.line(font, size, &line);
+        }
+    }
+
+    /// Columns advance together line by line, so a page break splits every
+    /// column at the same height.
+    fn columns(&mut self, columns: &[(String, String)]) {
+        let count = columns.len() as f32;
+        let width = (PAGE_WIDTH - 2.0 * MARGIN - COLUMN_GAP * (count - 1.0)) / count;
+        let x = |i: usize| MARGIN + i as f32 * (width + COLUMN_GAP);
+        let heading_lines: Vec<Vec<String>> = columns
+            .iter()
+            .map(|(heading, _)| wrap(heading, chars_for(width, BODY_SIZE)))
+            .collect();
+        let body_lines: Vec<Vec<String>> = columns
+            .iter()
+            .map(|(_, body)| wrap(body.trim(), chars_for(width, BODY_SIZE)))
+            .collect();
+        for (lines, font) in [(heading_lines, "F2"), (body_lines, "F1")] {
+            let rows = lines.iter().map(Vec::len).max().unwrap_or_default();
+            for row in 0..rows {
+                self.reserve(BODY_SIZE * LEADING);
+                self.y -= BODY_SIZE * LEADING;
+                for (i, column) in lines.iter().enumerate() {
+                    if let Some(text) = column.get(row) {
+                        self.text_at(x(i), font, BODY_SIZE, text);
+                    }
+                }
+            }
+        }
+    }
+
+    fn gap(&mut self) {
+        self.y -= BODY_SIZE;
+    }
+}
+
+fn render_pdf(detail: &ConversationDetail) -> Result<Vec<u8>> {
+    let mut layout = PdfLayout::new();
+    layout.paragraph("F2", TITLE_SIZE, &detail.conversation.title);
+    for block in blocks(&detail.messages) {
+        layout.gap();
+        match block {
+            Block::Single(message) => {
+                layout.paragraph("F2", HEADING_SIZE, &speaker(message));
+                layout.paragraph("F1", BODY_SIZE, message.content.trim());
+            }
+            Block::Columns(messages) => {
+                let columns: Vec<(String, String)> = messages
+                    .iter()
+                    .map(|m| (speaker(m), m.content.clone()))
+                    .collect();
+                layout.columns(&columns);
+            }
+        }
+    }
+
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let font = |name: &str| {
+        dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => Object::Name(name.as_bytes().to_vec()),
+            "Encoding" => "WinAnsiEncoding",
+        }
+    };
+    let regular = doc.add_object(font("Helvetica"));
+    let bold = doc.add_object(font("Helvetica-Bold"));
+    let resources_id = doc.add_object(dictionary! {
+        "Font" => dictionary! { "F1" => regular, "F2" => bold },
+    });
+    let mut kids = Vec::with_capacity(layout.pages.len());
+    for operations in layout.pages {
+        let content = Content { operations }.encode()?;
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content));
+        kids.push(Object::from(doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+        })));
+    }
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as i64,
+            "Kids" => kids,
+            "Resources" => resources_id,
+            "MediaBox" => vec![0.into(), 0.into(), PAGE_WIDTH.into(), PAGE_HEIGHT.into()],
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+    doc.compress();
+
+    let mut bytes = Vec::new();
+    doc.save_to(&mut bytes)?;
+    Ok(bytes)
+}
+
+pub fn render(detail: &ConversationDetail, format: ExportFormat) -> Result<Vec<u8>> {
+    match format {
+        ExportFormat::Markdown => Ok(render_markdown(detail).into_bytes()),
+        ExportFormat::Json => render_json(detail),
+        ExportFormat::Pdf => render_pdf(detail),
+    }
+}
+
+/// Writes the conversation to `path`, which the frontend picks with a save
+/// dialog.
+#[tauri::command]
+pub async fn export_conversation(
+    db: State<'_, Database>,
+    id: String,
+    format: ExportFormat,
+    path: PathBuf,
+) -> Result<PathBuf> {
+    let detail = db.get_conversation(&id)?;
+    std::fs::write(&path, render(&detail, format)?)?;
+    Ok(path)
+}
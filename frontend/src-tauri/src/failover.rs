@@ -0,0 +1,389 @@
+//! Keeps a fan-out answering when one of its providers doesn't. Each
+//! configured provider is probed in the background by listing its models;
+//! the results are kept and announced as `provider-health` when they
+//! change. When a target fails during a fan-out, runs over its latency
+//! budget, or is already known to be down, the configured fallbacks for
+//! its provider are tried in order. The result names the fallback that
+//! answered and says what happened to the original, and the switch is
+//! announced as `provider-failover` so the UI can relabel the column.
+//!
+//! Nothing fails over for a provider without fallbacks, and the budgets
+//! only cut a call short when there is somewhere else to go.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::Notify;
+
+use crate::arbiter::{self, ModelChoice};
+use crate::config;
+use crate::error::{Error, Result};
+use crate::llm::{self, ChatRequest};
+use crate::offline;
+use crate::providers::priority::{self, Priority};
+use crate::providers::{Completion, DeltaSink, Provider, Providers};
+use crate::storage::now_ms;
+
+const CONFIG_FILE: &str = "failover.json";
+const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FailoverConfig {
+    pub enabled: bool,
+    /// By provider id, the models to try in order when it fails.
+    pub fallbacks: BTreeMap<String, Vec<ModelChoice>>,
+    /// Give up on a target that hasn't streamed anything by then.
+    pub first_token_budget_ms: Option<u64>,
+    /// Give up on a target that hasn't finished by then.
+    pub total_budget_ms: Option<u64>,
+    /// Seconds between health probes; 0 turns them off.
+    pub probe_interval_secs: u64,
+}
+
+impl Default for FailoverConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            fallbacks: BTreeMap::new(),
+            first_token_budget_ms: Some(20_000),
+            total_budget_ms: None,
+            probe_interval_secs: 300,
+        }
+    }
+}
+
+/// The latest probe of one provider. Also the payload of the
+/// `provider-health` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderHealth {
+    pub provider: String,
+    pub healthy: bool,
+    pub latency_ms: u64,
+    pub error: Option<String>,
+    pub checked_at: i64,
+}
+
+/// What happened to a target's own model when a fallback answered for it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Failover {
+    pub provider: String,
+    pub model: String,
+    pub reason: String,
+    /// It had streamed part of an answer, which the fallback's replaces.
+    pub partial_discarded: bool,
+}
+
+/// Payload of the `provider-failover` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct FailoverEvent {
+    pub request_id: String,
+    /// The fallback about to answer.
+    pub fallback_provider: String,
+    pub fallback_model: String,
+    #[serde(flatten)]
+    pub failover: Failover,
+}
+
+/// A finished completion and who gave it.
+pub(crate) struct Answer {
+    pub completion: Completion,
+    pub cached: bool,
+    /// The target's own request, or the fallback's that answered it.
+    pub request: ChatRequest,
+    pub failover: Option<Failover>,
+}
+
+/// Managed as Tauri state.
+pub struct FailoverState {
+    config: Mutex<FailoverConfig>,
+    health: Mutex<BTreeMap<String, ProviderHealth>>,
+    /// Wakes the prober to run right away.
+    wake: Notify,
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<FailoverConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(FailoverState {
+        config: Mutex::new(config),
+        health: Mutex::default(),
+        wake: Notify::new(),
+    });
+}
+
+/// Starts the background health probes.
+pub fn watch(app: &AppHandle) {
+    tauri::async_runtime::spawn(priority::scope(Priority::Background, prober(app.clone())));
+}
+
+async fn probe(provider: &dyn Provider, client: &Client) -> ProviderHealth {
+    let started = Instant::now();
+    let outcome = tokio::time::timeout(PROBE_TIMEOUT, provider.list_models(client)).await;
+    let error = match outcome {
+        Ok(Ok(_)) => None,
+        Ok(Err(err)) => Some(err.to_string()),
+        Err(_) => Some(format!("no answer within {}s", PROBE_TIMEOUT.as_secs())),
+    };
+    ProviderHealth {
+        provider: provider.id().to_string(),
+        healthy: error.is_none(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        error,
+        checked_at: now_ms(),
+    }
+}
+
+/// Probes every configured provider, emitting `provider-health` for those
+/// whose health changed.
+async fn probe_all(app: &AppHandle) -> Vec<ProviderHealth> {
+    let providers = app.state::<Providers>();
+    let client = app.state::<Client>();
+    let configured: Vec<_> = providers.all().filter(|p| p.configured()).collect();
+    let probes = configured.iter().map(|p| probe(p.as_ref(), &client));
+    let results = futures_util::future::join_all(probes).await;
+
+    let state = app.state::<FailoverState>();
+    let mut health = state.health.lock().unwrap();
+    for result in &results {
+        let changed = health
+            .get(&result.provider)
+            .is_none_or(|previous| previous.healthy != result.healthy);
+        if changed {
+            if !result.healthy {
+                tracing::warn!(
+                    "{} failed its health check: {}",
+                    result.provider,
+                    result.error.as_deref().unwrap_or_default()
+                );
+            }
+            let _ = app.emit("provider-health", result.clone());
+        }
+        health.insert(result.provider.clone(), result.clone());
+    }
+    results
+}
+
+async fn prober(app: AppHandle) {
+    let state = app.state::<FailoverState>();
+    loop {
+        let interval = state.config.lock().unwrap().probe_interval_secs;
+        // Everything would look down; `offline` has that covered.
+        if interval > 0 && !offline::is_offline(&app) {
+            probe_all(&app).await;
+        }
+        let sleep = Duration::from_secs(if interval == 0 { 3600 } else { interval });
+        tokio::select! {
+            _ = state.wake.notified() => {}
+            _ = tokio::time::sleep(sleep) => {}
+        }
+    }
+}
+
+/// Whether another model might do better after `err`. Cancellation and
+/// the user's own decisions are final.
+fn worth_failing_over(err: &Error) -> bool {
+    !matches!(err, Error::Cancelled | Error::Scrubbed(_) | Error::Queued)
+}
+
+/// Resolves when `config`'s budgets run out, with the reason.
+async fn budget(config: &FailoverConfig, streamed: &AtomicBool) -> String {
+    let started = Instant::now();
+    if let Some(ms) = config.first_token_budget_ms {
+        tokio::time::sleep(Duration::from_millis(ms)).await;
+        if !streamed.load(Ordering::Relaxed) {
+            return format!("nothing streamed within {:.1}s", ms as f64 / 1000.0);
+        }
+    }
+    match config.total_budget_ms {
+        Some(ms) => {
+            let total = Duration::from_millis(ms);
+            tokio::time::sleep(total.saturating_sub(started.elapsed())).await;
+            format!("not finished within {:.1}s", ms as f64 / 1000.0)
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// `llm::complete_cached`, going to the provider's fallbacks when it fails
+/// or runs over budget. Gives the original error if every fallback fails
+/// too.
+pub(crate) async fn complete(
+    app: &AppHandle,
+    provider: &dyn Provider,
+    client: &Client,
+    request_id: &str,
+    request: &ChatRequest,
+    on_delta: &mut DeltaSink<'_>,
+) -> Result<Answer> {
+    let (config, known_down) = match app.try_state::<FailoverState>() {
+        Some(state) => {
+            let config = state.config.lock().unwrap().clone();
+            let down = state
+                .health
+                .lock()
+                .unwrap()
+                .get(&request.provider)
+                .filter(|h| !h.healthy)
+                .map(|h| h.error.clone().unwrap_or_default());
+            (config, down)
+        }
+        None => (FailoverConfig::default(), None),
+    };
+    let fallbacks = match config.fallbacks.get(&request.provider) {
+        Some(fallbacks) if config.enabled && !offline::is_offline(app) => fallbacks.as_slice(),
+        _ => &[],
+    };
+    if fallbacks.is_empty() {
+        let (completion, cached) =
+            llm::complete_cached(app, provider, client, request_id, request, on_delta).await?;
+        return Ok(Answer {
+            completion,
+            cached,
+            request: request.clone(),
+            failover: None,
+        });
+    }
+
+    let (reason, original_error, partial_discarded) = match known_down {
+        Some(error) => (format!("failed its health check: {error}"), None, false),
+        None => {
+            let streamed = AtomicBool::new(false);
+            let mut forward = |delta: &str| {
+                streamed.store(true, Ordering::Relaxed);
+                on_delta(delta);
+            };
+            let call =
+                llm::complete_cached(app, provider, client, request_id, request, &mut forward);
+            let outcome = tokio::select! {
+                outcome = call => outcome,
+                reason = budget(&config, &streamed) => {
+                    Err(Error::Provider(format!("{}: {reason}", request.provider)))
+                }
+            };
+            match outcome {
+                Ok((completion, cached)) => {
+                    return Ok(Answer {
+                        completion,
+                        cached,
+                        request: request.clone(),
+                        failover: None,
+                    })
+                }
+                Err(err) if !worth_failing_over(&err) => return Err(err),
+                Err(err) => {
+                    offline::observe(app, &request.provider, &err);
+                    let streamed = streamed.load(Ordering::Relaxed);
+                    (err.to_string(), Some(err), streamed)
+                }
+            }
+        }
+    };
+
+    let providers = app.state::<Providers>();
+    let health = app.try_state::<FailoverState>();
+    for choice in fallbacks {
+        let Ok((provider_id, model)) = arbiter::resolve(&providers, choice) else {
+            tracing::warn!("unknown fallback provider {}", choice.provider);
+            continue;
+        };
+        let down = health.as_ref().is_some_and(|state| {
+            state
+                .health
+                .lock()
+                .unwrap()
+                .get(&provider_id)
+                .is_some_and(|h| !h.healthy)
+        });
+        if down || (provider_id == request.provider && model == request.model) {
+            continue;
+        }
+        let Ok(fallback_provider) = providers.get(&provider_id) else {
+            continue;
+        };
+        let failover = Failover {
+            provider: request.provider.clone(),
+            model: request.model.clone(),
+            reason: reason.clone(),
+            partial_discarded,
+        };
+        let _ = app.emit(
+            "provider-failover",
+            FailoverEvent {
+                request_id: request_id.to_string(),
+                fallback_provider: provider_id.clone(),
+                fallback_model: model.clone(),
+                failover: failover.clone(),
+            },
+        );
+        let mut fallback = request.clone();
+        fallback.provider = provider_id;
+        fallback.model = model;
+        let outcome = llm::complete_cached(
+            app,
+            fallback_provider.as_ref(),
+            client,
+            request_id,
+            &fallback,
+            &mut *on_delta,
+        )
+        .await;
+        match outcome {
+            Ok((completion, cached)) => {
+                return Ok(Answer {
+                    completion,
+                    cached,
+                    request: fallback,
+                    failover: Some(failover),
+                })
+            }
+            Err(err) if !worth_failing_over(&err) => return Err(err),
+            Err(err) => tracing::warn!("fallback {} failed: {err}", fallback.provider),
+        }
+    }
+    Err(original_error
+        .unwrap_or_else(|| Error::Provider(format!("{}: {reason}", request.provider))))
+}
+
+#[tauri::command]
+pub fn get_failover_config(failover: State<'_, FailoverState>) -> FailoverConfig {
+    failover.config.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn set_failover_config(
+    app: AppHandle,
+    failover: State<'_, FailoverState>,
+    providers: State<'_, Providers>,
+    config: FailoverConfig,
+) -> Result<FailoverConfig> {
+    for (provider, fallbacks) in &config.fallbacks {
+        providers.get(provider)?;
+        for choice in fallbacks {
+            arbiter::resolve(&providers, choice)?;
+        }
+    }
+    config::write(&app, CONFIG_FILE, &config)?;
+    *failover.config.lock().unwrap() = config.clone();
+    failover.wake.notify_one();
+    Ok(config)
+}
+
+/// The latest probe of each provider that has been probed.
+#[tauri::command]
+pub fn get_provider_health(failover: State<'_, FailoverState>) -> Vec<ProviderHealth> {
+    failover.health.lock().unwrap().values().cloned().collect()
+}
+
+/// Probes every configured provider now.
+#[tauri::command]
+pub async fn check_provider_health(app: AppHandle) -> Vec<ProviderHealth> {
+    probe_all(&app).await
+}
@@ -0,0 +1,334 @@
+//! Sends one prompt to several providers at once. Each provider's deltas are
+//! emitted as `chat-token` events tagged with its id, and a `fanout-result`
+//! event fires as soon as that provider finishes. A long fan-out that ends
+//! while the window is hidden also shows a notification. While offline it
+//! goes to a local model or waits in the queue; see `offline`. A target
+//! that fails or runs slow can be answered by a fallback; see `failover`.
+
+use std::time::Instant;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::task::JoinSet;
+
+use crate::error::Result;
+use crate::failover::{self, Failover};
+use crate::llm::{ChatMessage, ChatRequest, ChatToken, Role, Usage};
+use crate::memory;
+use crate::mini_window;
+use crate::notifications;
+use crate::offline;
+use crate::pipeline;
+use crate::providers::priority;
+use crate::providers::Providers;
+use crate::requests::{cancellable, RequestGuard, Requests};
+use crate::routing;
+use crate::scrub;
+use crate::settings::SettingsStore;
+use crate::storage::Database;
+use crate::translate;
+use crate::usage;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FanoutTarget {
+    pub provider: String,
+    /// Falls back to the provider's default model.
+    pub model: Option<String>,
+    /// Parameters for this target alone, over the request's own.
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    /// Sent ahead of the messages to this target only.
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+}
+
+impl FanoutTarget {
+    pub fn new(provider: &str) -> Self {
+        Self {
+            provider: provider.to_string(),
+            model: None,
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            system_prompt: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FanoutRequest {
+    /// Empty means every configured provider.
+    #[serde(default)]
+    pub targets: Vec<FanoutTarget>,
+    pub messages: Vec<ChatMessage>,
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    /// Preset whose parameters fill in what the targets leave unset.
+    #[serde(default)]
+    pub preset_id: Option<String>,
+    /// Let every model call the enabled tools, built-in and from MCP servers.
+    #[serde(default)]
+    pub use_tools: bool,
+    /// Ask every provider afresh instead of reusing cached answers.
+    #[serde(default)]
+    pub bypass_cache: bool,
+    /// Picks the conversation's post-processing over the default.
+    #[serde(default)]
+    pub conversation_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FanoutResult {
+    pub provider: String,
+    pub model: String,
+    pub content: Option<String>,
+    /// The answer before post-processing, when that changed it.
+    #[serde(default)]
+    pub original_content: Option<String>,
+    pub error: Option<String>,
+    pub usage: Option<Usage>,
+    pub latency_ms: u64,
+    pub cached: bool,
+    /// Set when a fallback answered for the target; `provider` and
+    /// `model` then name the fallback.
+    #[serde(default)]
+    pub failover: Option<Failover>,
+}
+
+/// Payload of the `fanout-result` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct FanoutProgress {
+    pub request_id: String,
+    #[serde(flatten)]
+    pub result: FanoutResult,
+}
+
+#[tauri::command]
+pub async fn fanout_prompt(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    requests: State<'_, Requests>,
+    request_id: String,
+    request: FanoutRequest,
+) -> Result<Vec<FanoutResult>> {
+    // Registered before the steps ahead of the fan-out, which call models
+    // too, so cancelling stops them as well.
+    let guard = requests.register(&request_id);
+    let (request, prompt_language) = cancellable(guard.token(), async {
+        let mut request = request;
+        request.messages = scrub::check(&app, &request_id, request.messages).await?;
+        let mut request = if offline::is_offline(&app) {
+            offline::route(&app, &providers, &client, &request_id, request).await?
+        } else {
+            request
+        };
+        let prompt_language = translate::incoming(&app, &request_id, &mut request).await;
+        routing::apply(&app, &request_id, &mut request).await;
+        memory::inject(&app, &request_id, &mut request).await;
+        Ok((request, prompt_language))
+    })
+    .await?;
+    let start = Instant::now();
+    let mut results = run(&app, &providers, &client, &guard, &request_id, &request).await?;
+    if let Some(prompt_language) = &prompt_language {
+        translate::outgoing(&app, &request_id, prompt_language, &mut results).await;
+    }
+    notifications::fanout_finished(&app, &request_id, &results, start.elapsed());
+    Ok(results)
+}
+
+/// Runs a fan-out for callers without the command's state, such as the
+/// scheduler, which sends its own notification.
+pub(crate) async fn fan_out(
+    app: &AppHandle,
+    request_id: &str,
+    request: &FanoutRequest,
+) -> Result<Vec<FanoutResult>> {
+    let requests = app.state::<Requests>();
+    let guard = requests.register(request_id);
+    run(app, &app.state(), &app.state(), &guard, request_id, request).await
+}
+
+/// The request's targets, or every configured provider not turned off in
+/// the settings when it names none.
+pub(crate) fn targets(
+    app: &AppHandle,
+    request: &FanoutRequest,
+    providers: &Providers,
+) -> Vec<FanoutTarget> {
+    if request.targets.is_empty() {
+        let disabled = app.state::<SettingsStore>().get().disabled_providers;
+        providers
+            .all()
+            .filter(|p| p.configured() && !disabled.iter().any(|d| d == p.id()))
+            .map(|p| FanoutTarget::new(p.id()))
+            .collect()
+    } else {
+        request.targets.clone()
+    }
+}
+
+async fn run(
+    app: &AppHandle,
+    providers: &Providers,
+    client: &Client,
+    // One id covers the whole fan-out; cancelling it stops every provider.
+    guard: &RequestGuard<'_>,
+    request_id: &str,
+    request: &FanoutRequest,
+) -> Result<Vec<FanoutResult>> {
+    let targets = match &request.preset_id {
+        Some(id) => {
+            let preset = app.state::<Database>().get_preset(id)?;
+            preset.apply(app, request, providers).targets
+        }
+        None => targets(app, request, providers),
+    };
+
+    let mut tasks = JoinSet::new();
+    let mut results = Vec::new();
+    for (index, target) in targets.into_iter().enumerate() {
+        // A provider that is gone fails its own column, not the fan-out.
+        let provider = match providers.get(&target.provider) {
+            Ok(provider) => provider,
+            Err(err) => {
+                let result = FanoutResult {
+                    provider: target.provider,
+                    model: target.model.unwrap_or_default(),
+                    content: None,
+                    original_content: None,
+                    error: Some(err.to_string()),
+                    usage: None,
+                    latency_ms: 0,
+                    cached: false,
+                    failover: None,
+                };
+                let _ = app.emit(
+                    "fanout-result",
+                    FanoutProgress {
+                        request_id: request_id.to_string(),
+                        result: result.clone(),
+                    },
+                );
+                results.push((index, result));
+                continue;
+            }
+        };
+        let mut messages = request.messages.clone();
+        if let Some(system_prompt) = target.system_prompt {
+            messages.insert(
+                0,
+                ChatMessage {
+                    role: Role::System,
+                    content: system_prompt,
+                },
+            );
+        }
+        let chat = ChatRequest {
+            provider: target.provider,
+            model: target
+                .model
+                .unwrap_or_else(|| provider.default_model().to_string()),
+            messages,
+            temperature: target.temperature.or(request.temperature),
+            top_p: target.top_p.or(request.top_p),
+            max_tokens: target.max_tokens.or(request.max_tokens),
+            use_tools: request.use_tools,
+            bypass_cache: request.bypass_cache,
+            response_schema: None,
+            conversation_id: request.conversation_id.clone(),
+            tools: Vec::new(),
+            tool_rounds: Vec::new(),
+        };
+        let app = app.clone();
+        let client = client.clone();
+        let request_id = request_id.to_string();
+        let token = guard.token().clone();
+        tasks.spawn(priority::inherit(async move {
+            let started = Instant::now();
+            let mut on_delta = |delta: &str| {
+                let _ = app.emit(
+                    "chat-token",
+                    ChatToken {
+                        request_id: request_id.clone(),
+                        provider: chat.provider.clone(),
+                        delta: delta.to_string(),
+                    },
+                );
+            };
+            let stream = failover::complete(
+                &app,
+                provider.as_ref(),
+                &client,
+                &request_id,
+                &chat,
+                &mut on_delta,
+            );
+            let outcome = cancellable(&token, stream).await;
+            let latency_ms = started.elapsed().as_millis() as u64;
+            let result = match outcome {
+                Ok(answer) => {
+                    let mut completion = answer.completion;
+                    let answered = answer.request;
+                    if !answer.cached {
+                        usage::record(&app, &answered, &completion.content, completion.usage);
+                    }
+                    let conversation_id = answered.conversation_id.as_deref();
+                    let original_content =
+                        pipeline::apply(&app, conversation_id, &mut completion.content).await;
+                    FanoutResult {
+                        provider: answered.provider,
+                        model: answered.model,
+                        content: Some(completion.content),
+                        original_content,
+                        error: None,
+                        usage: completion.usage,
+                        latency_ms,
+                        cached: answer.cached,
+                        failover: answer.failover,
+                    }
+                }
+                Err(err) => {
+                    offline::observe(&app, &chat.provider, &err);
+                    FanoutResult {
+                        provider: chat.provider,
+                        model: chat.model,
+                        content: None,
+                        original_content: None,
+                        error: Some(err.to_string()),
+                        usage: None,
+                        latency_ms,
+                        cached: false,
+                        failover: None,
+                    }
+                }
+            };
+            let _ = app.emit(
+                "fanout-result",
+                FanoutProgress {
+                    request_id,
+                    result: result.clone(),
+                },
+            );
+            (index, result)
+        }));
+    }
+
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok(entry) = joined {
+            results.push(entry);
+        }
+    }
+    results.sort_by_key(|(index, _)| *index);
+    let results: Vec<FanoutResult> = results.into_iter().map(|(_, result)| result).collect();
+    mini_window::record(app, request_id, request, &results);
+    Ok(results)
+}
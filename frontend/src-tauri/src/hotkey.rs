@@ -0,0 +1,77 @@
+//! The quick-prompt window, summoned from anywhere by a global shortcut
+//! (see `shortcuts`), so a prompt can go to every model without switching
+//! to the main window.
+
+use tauri::{AppHandle, Manager, State, WebviewUrl, WebviewWindowBuilder, Window, WindowEvent};
+
+use crate::error::Result;
+use crate::screen_context;
+use crate::shortcuts::{self, Shortcuts};
+
+pub const QUICK_LABEL: &str = "quick";
+/// The quick prompt's id in the shortcut registry.
+const SHORTCUT_ID: &str = "quick_prompt";
+
+pub fn toggle_quick_window(app: &AppHandle) -> Result<()> {
+    match app.get_webview_window(QUICK_LABEL) {
+        Some(window) if window.is_visible()? => Ok(window.hide()?),
+        _ => screen_context::summon_quick_prompt(app),
+    }
+}
+
+pub fn show_quick_window(app: &AppHandle) -> Result<()> {
+    if let Some(window) = app.get_webview_window(QUICK_LABEL) {
+        window.show()?;
+        window.set_focus()?;
+        return Ok(());
+    }
+    let _window = WebviewWindowBuilder::new(
+        app,
+        QUICK_LABEL,
+        WebviewUrl::App("index.html?view=quick".into()),
+    )
+    .title("Pentamind")
+    .inner_size(640.0, 120.0)
+    .decorations(false)
+    .transparent(true)
+    .always_on_top(true)
+    .resizable(false)
+    .skip_taskbar(true)
+    .center()
+    .focused(true)
+    .build()?;
+    // Undecorated windows get no menu bar on Windows and Linux.
+    #[cfg(not(target_os = "macos"))]
+    let _ = _window.remove_menu();
+    Ok(())
+}
+
+/// Hides the quick-prompt window when it loses focus, spotlight-style.
+pub fn on_window_event(window: &Window, event: &WindowEvent) {
+    if window.label() == QUICK_LABEL && matches!(event, WindowEvent::Focused(false)) {
+        let _ = window.hide();
+    }
+}
+
+/// The quick-prompt binding; empty if it has been cleared.
+#[tauri::command]
+pub fn get_quick_prompt_shortcut(shortcuts: State<'_, Shortcuts>) -> String {
+    shortcuts::list_shortcuts(shortcuts)
+        .into_iter()
+        .find(|s| s.id == SHORTCUT_ID)
+        .and_then(|s| s.binding)
+        .unwrap_or_default()
+}
+
+/// Rebinds the quick prompt through the shortcut registry, which keeps the
+/// old binding if the new one can't be registered (e.g. another app
+/// already owns it).
+#[tauri::command]
+pub fn set_quick_prompt_shortcut(
+    app: AppHandle,
+    shortcuts: State<'_, Shortcuts>,
+    shortcut: String,
+) -> Result<String> {
+    let info = shortcuts::rebind_shortcut(app, shortcuts, SHORTCUT_ID.to_string(), Some(shortcut))?;
+    Ok(info.binding.unwrap_or_default())
+}
@@ -0,0 +1,248 @@
+//! Image generation on top of the backends in `providers::images`. Each
+//! image is stored in the attachments directory like a dropped file and
+//! recorded with the prompt it came from, so it can be shown in its
+//! conversation and made again.
+
+use std::path::PathBuf;
+
+use reqwest::Client;
+use rusqlite::{params, Row};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::attachments;
+use crate::error::Result;
+use crate::providers::images::ImageRequest;
+use crate::providers::Providers;
+use crate::requests::{cancellable, Requests};
+use crate::storage::{new_id, now_ms, Database};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImageGeneration {
+    #[serde(flatten)]
+    pub request: ImageRequest,
+    /// Where the images belong, if anywhere.
+    pub conversation_id: Option<String>,
+    pub message_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GeneratedImage {
+    pub id: String,
+    /// The attachment holding the image.
+    pub attachment_id: String,
+    pub path: PathBuf,
+    pub provider: String,
+    pub model: String,
+    pub prompt: String,
+    pub revised_prompt: Option<String>,
+    pub negative_prompt: Option<String>,
+    pub size: Option<String>,
+    pub conversation_id: Option<String>,
+    pub message_id: Option<String>,
+    pub created_at: i64,
+}
+
+impl GeneratedImage {
+    const COLUMNS: &'static str = "id, attachment_id, path, provider, model, prompt, \
+                                   revised_prompt, negative_prompt, size, conversation_id, \
+                                   message_id, created_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            attachment_id: row.get(1)?,
+            path: PathBuf::from(row.get::<_, String>(2)?),
+            provider: row.get(3)?,
+            model: row.get(4)?,
+            prompt: row.get(5)?,
+            revised_prompt: row.get(6)?,
+            negative_prompt: row.get(7)?,
+            size: row.get(8)?,
+            conversation_id: row.get(9)?,
+            message_id: row.get(10)?,
+            created_at: row.get(11)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageStage {
+    Generating,
+    Saving,
+}
+
+/// Payload of the `image-progress` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageProgress {
+    pub request_id: String,
+    pub provider: String,
+    pub stage: ImageStage,
+    /// From 0 to 1, when the backend reports it.
+    pub progress: Option<f32>,
+}
+
+/// Payload of the `image-done` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageDone {
+    pub request_id: String,
+    pub images: Vec<GeneratedImage>,
+}
+
+/// Payload of the `image-error` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageError {
+    pub request_id: String,
+    pub error: String,
+}
+
+impl Database {
+    fn insert_generated_image(&self, image: &GeneratedImage) -> Result<()> {
+        self.conn()?.execute(
+            &format!(
+                "INSERT INTO generated_images ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                GeneratedImage::COLUMNS
+            ),
+            params![
+                image.id,
+                image.attachment_id,
+                image.path.to_string_lossy(),
+                image.provider,
+                image.model,
+                image.prompt,
+                image.revised_prompt,
+                image.negative_prompt,
+                image.size,
+                image.conversation_id,
+                image.message_id,
+                image.created_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn list_generated_images(
+        &self,
+        conversation_id: Option<&str>,
+    ) -> Result<Vec<GeneratedImage>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM generated_images
+             WHERE ?1 IS NULL OR conversation_id = ?1
+             ORDER BY created_at DESC",
+            GeneratedImage::COLUMNS
+        ))?;
+        let images = stmt
+            .query_map([conversation_id], GeneratedImage::from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(images)
+    }
+}
+
+async fn generate(
+    app: &AppHandle,
+    providers: &Providers,
+    client: &Client,
+    request_id: &str,
+    generation: &ImageGeneration,
+) -> Result<Vec<GeneratedImage>> {
+    let request = &generation.request;
+    let generator = providers.image_generator(&request.provider)?;
+    let progress = |stage, progress| {
+        let _ = app.emit(
+            "image-progress",
+            ImageProgress {
+                request_id: request_id.to_string(),
+                provider: request.provider.clone(),
+                stage,
+                progress,
+            },
+        );
+    };
+    progress(ImageStage::Generating, None);
+    let data = generator
+        .generate(client, request, &mut |fraction| {
+            progress(ImageStage::Generating, Some(fraction))
+        })
+        .await?;
+    progress(ImageStage::Saving, None);
+
+    let model = request
+        .model
+        .clone()
+        .unwrap_or_else(|| generator.default_model().to_string());
+    let db = app.state::<Database>();
+    let mut images = Vec::new();
+    for image in data {
+        let (attachment_id, path) = attachments::store_bytes(app, &image.bytes, image.extension)?;
+        let image = GeneratedImage {
+            id: new_id(),
+            attachment_id,
+            path,
+            provider: request.provider.clone(),
+            model: model.clone(),
+            prompt: request.prompt.clone(),
+            revised_prompt: image.revised_prompt,
+            negative_prompt: request.negative_prompt.clone(),
+            size: request.size.clone(),
+            conversation_id: generation.conversation_id.clone(),
+            message_id: generation.message_id.clone(),
+            created_at: now_ms(),
+        };
+        db.insert_generated_image(&image)?;
+        images.push(image);
+    }
+    Ok(images)
+}
+
+/// Generates images, emitting `image-progress` while it runs and
+/// `image-done` (or `image-error`) at the end. `request_id` can be passed
+/// to `cancel_request`.
+#[tauri::command]
+pub async fn generate_image(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    requests: State<'_, Requests>,
+    request_id: String,
+    request: ImageGeneration,
+) -> Result<Vec<GeneratedImage>> {
+    let guard = requests.register(&request_id);
+    let outcome = cancellable(
+        guard.token(),
+        generate(&app, &providers, &client, &request_id, &request),
+    )
+    .await;
+    match outcome {
+        Ok(images) => {
+            app.emit(
+                "image-done",
+                ImageDone {
+                    request_id,
+                    images: images.clone(),
+                },
+            )?;
+            Ok(images)
+        }
+        Err(err) => {
+            app.emit(
+                "image-error",
+                ImageError {
+                    request_id,
+                    error: err.to_string(),
+                },
+            )?;
+            Err(err)
+        }
+    }
+}
+
+/// Generated images, newest first; only those of `conversation_id` if set.
+#[tauri::command]
+pub fn list_generated_images(
+    db: State<'_, Database>,
+    conversation_id: Option<String>,
+) -> Result<Vec<GeneratedImage>> {
+    db.list_generated_images(conversation_id.as_deref())
+}
@@ -0,0 +1,361 @@
+//! Imports chat history exported from ChatGPT or Claude. Both vendors ship a
+//! zip containing `conversations.json`; either the zip or the bare JSON file
+//! is accepted, and the format is detected from its shape.
+//!
+//! Imported conversations remember where they came from, so importing the
+//! same export twice skips what is already there.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use rusqlite::params;
+use serde::Serialize;
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, Manager};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{Error, Result};
+use crate::llm::Role;
+use crate::storage::{new_id, Database};
+
+const ARCHIVE_ENTRY: &str = "conversations.json";
+/// Emit progress every this many conversations rather than every one.
+const PROGRESS_EVERY: usize = 25;
+const UNTITLED: &str = "Imported conversation";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    Openai,
+    Anthropic,
+}
+
+impl ArchiveFormat {
+    fn key(self) -> &'static str {
+        match self {
+            ArchiveFormat::Openai => "openai",
+            ArchiveFormat::Anthropic => "anthropic",
+        }
+    }
+}
+
+struct ImportedMessage {
+    role: Role,
+    content: String,
+    model: Option<String>,
+    created_at: i64,
+}
+
+struct ImportedConversation {
+    /// The vendor's conversation id.
+    source_id: String,
+    title: String,
+    created_at: i64,
+    updated_at: i64,
+    messages: Vec<ImportedMessage>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportSummary {
+    pub format: ArchiveFormat,
+    pub total: usize,
+    pub imported: usize,
+    /// Already imported earlier, or empty.
+    pub skipped: usize,
+    pub messages: usize,
+}
+
+/// Payload of the `import-progress` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportProgress {
+    pub path: PathBuf,
+    pub processed: usize,
+    pub total: usize,
+}
+
+fn archive_error(path: &Path, err: impl std::fmt::Display) -> Error {
+    Error::Extraction(format!("{}: {err}", path.display()))
+}
+
+fn read_export(path: &Path) -> Result<Value> {
+    let bytes = std::fs::read(path)?;
+    if bytes.starts_with(b"PK") {
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))
+            .map_err(|e| archive_error(path, e))?;
+        let mut json = Vec::new();
+        archive
+            .by_name(ARCHIVE_ENTRY)
+            .map_err(|e| archive_error(path, e))?
+            .read_to_end(&mut json)?;
+        return Ok(serde_json::from_slice(&json)?);
+    }
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+fn detect(export: &Value) -> Option<ArchiveFormat> {
+    let first = export.as_array()?.first()?;
+    if first.get("mapping").is_some() {
+        Some(ArchiveFormat::Openai)
+    } else if first.get("chat_messages").is_some() {
+        Some(ArchiveFormat::Anthropic)
+    } else {
+        None
+    }
+}
+
+/// OpenAI timestamps are fractional Unix seconds.
+fn seconds_to_ms(value: &Value) -> Option<i64> {
+    value.as_f64().map(|secs| (secs * 1000.0) as i64)
+}
+
+/// Anthropic timestamps are RFC 3339 strings.
+fn rfc3339_to_ms(value: &Value) -> Option<i64> {
+    let parsed = chrono::DateTime::parse_from_rfc3339(value.as_str()?).ok()?;
+    Some(parsed.timestamp_millis())
+}
+
+fn title_or_default(value: &Value) -> String {
+    value
+        .as_str()
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .unwrap_or(UNTITLED)
+        .to_string()
+}
+
+/// ChatGPT stores each conversation as a tree of edits and regenerations.
+/// The visible thread is the path from `current_node` back to the root.
+fn parse_openai(conversation: &Value) -> Option<ImportedConversation> {
+    let mapping = conversation["mapping"].as_object()?;
+    let created_at = seconds_to_ms(&conversation["create_time"]).unwrap_or_default();
+    let mut node_id = conversation["current_node"].as_str();
+    let mut thread = Vec::new();
+    while let Some(id) = node_id {
+        let Some(node) = mapping.get(id) else { break };
+        thread.push(node);
+        node_id = node["parent"].as_str();
+        if thread.len() > mapping.len() {
+            // A cycle in a malformed export; stop rather than spin.
+            break;
+        }
+    }
+    thread.reverse();
+
+    let messages = thread
+        .into_iter()
+        .filter_map(|node| {
+            let message = &node["message"];
+            let role = match message["author"]["role"].as_str()? {
+                "user" => Role::User,
+                "assistant" => Role::Assistant,
+                "system" => Role::System,
+                // Tool calls and their output aren't part of the readable thread.
+                _ => return None,
+            };
+            let content: Vec<&str> = message["content"]["parts"]
+                .as_array()?
+                .iter()
+                .filter_map(Value::as_str)
+                .collect();
+            let content = content.join("\n").trim().to_string();
+            if content.is_empty() {
+                return None;
+            }
+            Some(ImportedMessage {
+                role,
+                content,
+                model: message["metadata"]["model_slug"]
+                    .as_str()
+                    .map(str::to_string),
+                created_at: seconds_to_ms(&message["create_time"]).unwrap_or(created_at),
+            })
+        })
+        .collect();
+
+    Some(ImportedConversation {
+        source_id: conversation["conversation_id"]
+            .as_str()
+            .or_else(|| conversation["id"].as_str())?
+            .to_string(),
+        title: title_or_default(&conversation["title"]),
+        created_at,
+        updated_at: seconds_to_ms(&conversation["update_time"]).unwrap_or(created_at),
+        messages,
+    })
+}
+
+fn parse_anthropic(conversation: &Value) -> Option<ImportedConversation> {
+    let created_at = rfc3339_to_ms(&conversation["created_at"]).unwrap_or_default();
+    let messages = conversation["chat_messages"]
+        .as_array()?
+        .iter()
+        .filter_map(|message| {
+            let role = match message["sender"].as_str()? {
+                "human" => Role::User,
+                "assistant" => Role::Assistant,
+                _ => return None,
+            };
+            // Newer exports split text into content blocks; older ones only
+            // have the flat `text` field.
+            let blocks: Vec<&str> = message["content"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|block| block["type"] == "text")
+                .filter_map(|block| block["text"].as_str())
+                .collect();
+            let content = if blocks.is_empty() {
+                message["text"].as_str()?.trim().to_string()
+            } else {
+                blocks.join("\n").trim().to_string()
+            };
+            if content.is_empty() {
+                return None;
+            }
+            Some(ImportedMessage {
+                role,
+                content,
+                model: None,
+                created_at: rfc3339_to_ms(&message["created_at"]).unwrap_or(created_at),
+            })
+        })
+        .collect();
+
+    Some(ImportedConversation {
+        source_id: conversation["uuid"].as_str()?.to_string(),
+        title: title_or_default(&conversation["name"]),
+        created_at,
+        updated_at: rfc3339_to_ms(&conversation["updated_at"]).unwrap_or(created_at),
+        messages,
+    })
+}
+
+impl Database {
+    /// Inserts an imported conversation unless one with the same source key
+    /// exists. Returns whether it was inserted.
+    fn import_conversation(
+        &self,
+        format: ArchiveFormat,
+        conversation: &ImportedConversation,
+    ) -> Result<bool> {
+        let source = format!("{}:{}", format.key(), conversation.source_id);
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let id = new_id();
+        let inserted = tx.execute(
+            "INSERT OR IGNORE INTO conversations (id, title, created_at, updated_at, import_source)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                id,
+                conversation.title,
+                conversation.created_at,
+                conversation.updated_at,
+                source
+            ],
+        )?;
+        if inserted == 0 {
+            return Ok(false);
+        }
+        let mut parent_id: Option<String> = None;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO messages
+                     (id, conversation_id, parent_id, role, content, provider, model, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for message in &conversation.messages {
+                let message_id = new_id();
+                let provider = (message.role == Role::Assistant).then_some(format.key());
+                insert.execute(params![
+                    message_id,
+                    id,
+                    parent_id,
+                    message.role,
+                    message.content,
+                    provider,
+                    message.model,
+                    message.created_at
+                ])?;
+                parent_id = Some(message_id);
+            }
+        }
+        tx.execute(
+            "UPDATE conversations SET head_id = ?2 WHERE id = ?1",
+            params![id, parent_id],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+}
+
+/// Imports conversation by conversation, calling `on_progress` with the
+/// processed and total counts. Each conversation is its own transaction, so
+/// stopping on `token` keeps what was imported so far.
+pub fn import(
+    app: &AppHandle,
+    path: &Path,
+    token: &CancellationToken,
+    mut on_progress: impl FnMut(usize, usize),
+) -> Result<ImportSummary> {
+    let export = read_export(path)?;
+    let format = detect(&export)
+        .ok_or_else(|| archive_error(path, "not a ChatGPT or Claude conversations export"))?;
+    let conversations = export.as_array().map(Vec::as_slice).unwrap_or_default();
+    let db = app.state::<Database>();
+    let mut summary = ImportSummary {
+        format,
+        total: conversations.len(),
+        imported: 0,
+        skipped: 0,
+        messages: 0,
+    };
+    for (i, raw) in conversations.iter().enumerate() {
+        if token.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let parsed = match format {
+            ArchiveFormat::Openai => parse_openai(raw),
+            ArchiveFormat::Anthropic => parse_anthropic(raw),
+        };
+        match parsed {
+            Some(conversation)
+                if !conversation.messages.is_empty()
+                    && db.import_conversation(format, &conversation)? =>
+            {
+                summary.imported += 1;
+                summary.messages += conversation.messages.len();
+            }
+            _ => summary.skipped += 1,
+        }
+        let processed = i + 1;
+        if processed % PROGRESS_EVERY == 0 || processed == summary.total {
+            on_progress(processed, summary.total);
+        }
+    }
+    Ok(summary)
+}
+
+/// Imports a ChatGPT or Claude data export, emitting `import-progress` as
+/// conversations are processed.
+#[tauri::command]
+pub async fn import_archive(app: AppHandle, path: PathBuf) -> Result<ImportSummary> {
+    tauri::async_runtime::spawn_blocking(move || {
+        import(
+            &app,
+            &path,
+            &CancellationToken::new(),
+            |processed, total| {
+                let _ = app.emit(
+                    "import-progress",
+                    ImportProgress {
+                        path: path.clone(),
+                        processed,
+                        total,
+                    },
+                );
+            },
+        )
+    })
+    .await
+    .map_err(|e| Error::Extraction(e.to_string()))?
+}
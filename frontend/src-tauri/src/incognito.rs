@@ -0,0 +1,259 @@
+//! Incognito conversations. They live in memory only, owned by the window
+//! that started them: nothing about them is written to the database, so
+//! they stay out of search, the OS index, sync and backups, and the
+//! request paths that would keep something of them on disk (usage, the
+//! response cache, autosave logs, context summaries, saved runs, the
+//! offline queue) leave them out. Closing the owning window wipes them.
+//!
+//! Their ids, and those of their messages, start with [`ID_PREFIX`], which
+//! is how the conversation commands know to come here instead. Branches
+//! can be started by editing a message, but not listed or switched
+//! between.
+//!
+//! Events: `ephemeral-conversation-created` when one starts, and
+//! `ephemeral-conversation-wiped` when it is deleted or its window closes.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, State, Window, WindowEvent};
+
+use crate::error::{Error, Result};
+use crate::storage::conversations::{
+    Conversation, ConversationDetail, Message, NewMessage, DEFAULT_TITLE,
+};
+use crate::storage::{new_id, now_ms};
+
+pub const ID_PREFIX: &str = "incognito-";
+
+/// Whether `id` names an incognito conversation or one of its messages.
+pub fn is_ephemeral(id: &str) -> bool {
+    id.starts_with(ID_PREFIX)
+}
+
+/// Whether a request belongs to an incognito conversation.
+pub fn is_ephemeral_request(conversation_id: Option<&str>) -> bool {
+    conversation_id.is_some_and(is_ephemeral)
+}
+
+fn ephemeral_id() -> String {
+    format!("{ID_PREFIX}{}", new_id())
+}
+
+struct Ephemeral {
+    /// Label of the window that owns it.
+    window: String,
+    conversation: Conversation,
+    /// Every message, branches included, oldest first.
+    messages: Vec<Message>,
+}
+
+impl Ephemeral {
+    fn find(&self, message_id: &str) -> Option<&Message> {
+        self.messages.iter().find(|m| m.id == message_id)
+    }
+
+    fn push(&mut self, message: Message) {
+        self.conversation.head_id = Some(message.id.clone());
+        self.conversation.updated_at = message.created_at;
+        self.messages.push(message);
+    }
+
+    /// The conversation with the messages from the top down to the head.
+    fn detail(&self) -> ConversationDetail {
+        let mut messages = Vec::new();
+        let mut next = self.conversation.head_id.as_deref();
+        while let Some(message) = next.and_then(|id| self.find(id)) {
+            messages.push(message.clone());
+            next = message.parent_id.as_deref();
+        }
+        messages.reverse();
+        ConversationDetail {
+            conversation: self.conversation.clone(),
+            messages,
+        }
+    }
+}
+
+/// Incognito conversations by id. Managed as Tauri state.
+#[derive(Default)]
+pub struct Incognito(Mutex<HashMap<String, Ephemeral>>);
+
+/// Payload of `ephemeral-conversation-created`.
+#[derive(Debug, Clone, Serialize)]
+pub struct EphemeralCreated {
+    pub window: String,
+    pub conversation: Conversation,
+}
+
+/// Payload of `ephemeral-conversation-wiped`.
+#[derive(Debug, Clone, Serialize)]
+pub struct EphemeralWiped {
+    pub window: String,
+    pub conversation_id: String,
+    /// Gone with its window rather than deleted.
+    pub window_closed: bool,
+}
+
+fn not_found(id: &str) -> Error {
+    Error::NotFound(format!("conversation {id}"))
+}
+
+impl Incognito {
+    fn create(&self, window: &str, title: Option<String>) -> Conversation {
+        let now = now_ms();
+        let conversation = Conversation {
+            id: ephemeral_id(),
+            title: title
+                .filter(|t| !t.trim().is_empty())
+                .unwrap_or_else(|| DEFAULT_TITLE.to_string()),
+            created_at: now,
+            updated_at: now,
+            head_id: None,
+            summary: None,
+            archived_at: None,
+        };
+        self.0.lock().unwrap().insert(
+            conversation.id.clone(),
+            Ephemeral {
+                window: window.to_string(),
+                conversation: conversation.clone(),
+                messages: Vec::new(),
+            },
+        );
+        conversation
+    }
+
+    /// Adds a message the way `Database::append_message` does.
+    pub(crate) fn append(&self, conversation_id: &str, message: NewMessage) -> Result<Message> {
+        let mut conversations = self.0.lock().unwrap();
+        let ephemeral = conversations
+            .get_mut(conversation_id)
+            .ok_or_else(|| not_found(conversation_id))?;
+        let parent_id = match message.parent_id {
+            Some(parent_id) => {
+                if ephemeral.find(&parent_id).is_none() {
+                    return Err(Error::NotFound(format!(
+                        "message {parent_id} in conversation {conversation_id}"
+                    )));
+                }
+                Some(parent_id)
+            }
+            None => ephemeral.conversation.head_id.clone(),
+        };
+        let message = Message {
+            id: ephemeral_id(),
+            conversation_id: conversation_id.to_string(),
+            parent_id,
+            role: message.role,
+            content: message.content,
+            provider: message.provider,
+            model: message.model,
+            created_at: now_ms(),
+        };
+        ephemeral.push(message.clone());
+        Ok(message)
+    }
+
+    /// Adds the edit of `message_id` as its sibling, the way
+    /// `Database::fork_conversation` does.
+    pub(crate) fn fork(&self, message_id: &str, content: String) -> Result<Message> {
+        let mut conversations = self.0.lock().unwrap();
+        let (ephemeral, original) = conversations
+            .values_mut()
+            .find_map(|e| e.find(message_id).cloned().map(|m| (e, m)))
+            .ok_or_else(|| Error::NotFound(format!("message {message_id}")))?;
+        let message = Message {
+            id: ephemeral_id(),
+            conversation_id: original.conversation_id,
+            parent_id: original.parent_id,
+            role: original.role,
+            content,
+            provider: None,
+            model: None,
+            created_at: now_ms(),
+        };
+        ephemeral.push(message.clone());
+        Ok(message)
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Result<ConversationDetail> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(Ephemeral::detail)
+            .ok_or_else(|| not_found(id))
+    }
+}
+
+/// Deletes an incognito conversation from memory.
+pub(crate) fn wipe(app: &AppHandle, id: &str) -> Result<()> {
+    let removed = app.state::<Incognito>().0.lock().unwrap().remove(id);
+    let ephemeral = removed.ok_or_else(|| not_found(id))?;
+    let _ = app.emit(
+        "ephemeral-conversation-wiped",
+        EphemeralWiped {
+            window: ephemeral.window,
+            conversation_id: id.to_string(),
+            window_closed: false,
+        },
+    );
+    Ok(())
+}
+
+/// Wipes a window's incognito conversations once it is asked to close, even
+/// if it is only hidden, and again when it is destroyed in case it went
+/// without asking.
+pub fn on_window_event(window: &Window, event: &WindowEvent) {
+    if !matches!(
+        event,
+        WindowEvent::CloseRequested { .. } | WindowEvent::Destroyed
+    ) {
+        return;
+    }
+    let Some(incognito) = window.try_state::<Incognito>() else {
+        return;
+    };
+    let label = window.label();
+    let mut wiped = Vec::new();
+    incognito.0.lock().unwrap().retain(|id, ephemeral| {
+        if ephemeral.window != label {
+            return true;
+        }
+        wiped.push(id.clone());
+        false
+    });
+    for conversation_id in wiped {
+        let _ = window.app_handle().emit(
+            "ephemeral-conversation-wiped",
+            EphemeralWiped {
+                window: label.to_string(),
+                conversation_id,
+                window_closed: true,
+            },
+        );
+    }
+}
+
+/// Starts an incognito conversation owned by the calling window. Use the
+/// usual conversation commands with its id; requests for it should pass the
+/// id as their `conversation_id` so nothing of them is kept.
+#[tauri::command]
+pub fn create_ephemeral_conversation(
+    app: AppHandle,
+    window: Window,
+    incognito: State<'_, Incognito>,
+    title: Option<String>,
+) -> Result<Conversation> {
+    let conversation = incognito.create(window.label(), title);
+    app.emit(
+        "ephemeral-conversation-created",
+        EphemeralCreated {
+            window: window.label().to_string(),
+            conversation: conversation.clone(),
+        },
+    )?;
+    Ok(conversation)
+}
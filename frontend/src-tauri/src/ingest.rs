@@ -0,0 +1,362 @@
+//! Text extraction for attached files. Each format is turned into a list of
+//! sections (pages, headings, row groups) that the frontend can preview or
+//! that can be fed straight into the RAG index.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::Client;
+use serde::Serialize;
+use tauri::State;
+
+use crate::error::{Error, Result};
+use crate::providers::Providers;
+use crate::rag::{self, Embedder, EmbeddingOptions, IndexedDocument};
+use crate::storage::Database;
+
+pub(crate) const MAX_FILE_SIZE: u64 = 50 * 1024 * 1024;
+const CSV_ROWS_PER_SECTION: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentKind {
+    Pdf,
+    Docx,
+    Markdown,
+    Csv,
+    Code,
+    Text,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Section {
+    pub heading: Option<String>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtractedDocument {
+    pub path: PathBuf,
+    pub title: String,
+    pub kind: DocumentKind,
+    /// Language hint for source files, e.g. `rust`.
+    pub language: Option<&'static str>,
+    pub sections: Vec<Section>,
+}
+
+impl ExtractedDocument {
+    /// All sections joined as plain text, headings included.
+    pub fn full_text(&self) -> String {
+        let mut text = String::new();
+        for section in &self.sections {
+            if let Some(heading) = &section.heading {
+                text.push_str(heading);
+                text.push_str("\n\n");
+            }
+            text.push_str(&section.text);
+            text.push_str("\n\n");
+        }
+        text.truncate(text.trim_end().len());
+        text
+    }
+}
+
+pub(crate) fn code_language(extension: &str) -> Option<&'static str> {
+    Some(match extension {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" => "typescript",
+        "tsx" => "tsx",
+        "jsx" => "jsx",
+        "go" => "go",
+        "java" => "java",
+        "kt" => "kotlin",
+        "swift" => "swift",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "hpp" => "cpp",
+        "cs" => "csharp",
+        "rb" => "ruby",
+        "php" => "php",
+        "sh" | "bash" | "zsh" => "bash",
+        "sql" => "sql",
+        "html" => "html",
+        "css" => "css",
+        "json" => "json",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        _ => return None,
+    })
+}
+
+fn classify(path: &Path) -> (DocumentKind, Option<&'static str>) {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    match extension.as_str() {
+        "pdf" => (DocumentKind::Pdf, None),
+        "docx" => (DocumentKind::Docx, None),
+        "md" | "markdown" => (DocumentKind::Markdown, None),
+        "csv" => (DocumentKind::Csv, None),
+        other => match code_language(other) {
+            Some(language) => (DocumentKind::Code, Some(language)),
+            None => (DocumentKind::Text, None),
+        },
+    }
+}
+
+fn extraction_error(path: &Path, err: impl std::fmt::Display) -> Error {
+    Error::Extraction(format!("{}: {err}", path.display()))
+}
+
+fn extract_pdf(path: &Path) -> Result<Vec<Section>> {
+    let pages = pdf_extract::extract_text_by_pages(path).map_err(|e| extraction_error(path, e))?;
+    Ok(pages
+        .into_iter()
+        .enumerate()
+        .filter(|(_, text)| !text.trim().is_empty())
+        .map(|(i, text)| Section {
+            heading: Some(format!("Page {}", i + 1)),
+            text: text.trim().to_string(),
+        })
+        .collect())
+}
+
+/// Reads `word/document.xml` and groups paragraphs under their nearest
+/// heading-styled paragraph.
+fn extract_docx(path: &Path) -> Result<Vec<Section>> {
+    let file = std::fs::File::open(path)?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| extraction_error(path, e))?;
+    let mut xml = String::new();
+    archive
+        .by_name("word/document.xml")
+        .map_err(|e| extraction_error(path, e))?
+        .read_to_string(&mut xml)?;
+
+    let mut reader = Reader::from_str(&xml);
+    let mut sections = Vec::new();
+    let mut current = Section {
+        heading: None,
+        text: String::new(),
+    };
+    let mut paragraph = String::new();
+    let mut is_heading = false;
+    let mut in_text = false;
+    loop {
+        match reader.read_event().map_err(|e| extraction_error(path, e))? {
+            Event::Start(e) if e.name().as_ref() == b"w:t" => in_text = true,
+            Event::End(e) if e.name().as_ref() == b"w:t" => in_text = false,
+            Event::Text(t) if in_text => {
+                paragraph.push_str(&t.unescape().map_err(|e| extraction_error(path, e))?);
+            }
+            Event::Empty(e) => match e.name().as_ref() {
+                b"w:pStyle" => {
+                    if let Some(style) = e
+                        .try_get_attribute("w:val")
+                        .map_err(|e| extraction_error(path, e))?
+                    {
+                        let style = style
+                            .unescape_value()
+                            .map_err(|e| extraction_error(path, e))?;
+                        is_heading = style.starts_with("Heading") || style == "Title";
+                    }
+                }
+                b"w:tab" => paragraph.push('\t'),
+                b"w:br" => paragraph.push('\n'),
+                _ => {}
+            },
+            Event::End(e) if e.name().as_ref() == b"w:p" => {
+                let text = std::mem::take(&mut paragraph);
+                let text = text.trim();
+                if is_heading && !text.is_empty() {
+                    if current.heading.is_some() || !current.text.is_empty() {
+                        sections.push(std::mem::replace(
+                            &mut current,
+                            Section {
+                                heading: None,
+                                text: String::new(),
+                            },
+                        ));
+                    }
+                    current.heading = Some(text.to_string());
+                } else if !text.is_empty() {
+                    if !current.text.is_empty() {
+                        current.text.push('\n');
+                    }
+                    current.text.push_str(text);
+                }
+                is_heading = false;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if current.heading.is_some() || !current.text.is_empty() {
+        sections.push(current);
+    }
+    Ok(sections)
+}
+
+/// Splits on ATX headings (`#`, `##`, ...), ignoring `#` lines inside fences.
+fn extract_markdown(text: &str) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut current = Section {
+        heading: None,
+        text: String::new(),
+    };
+    let mut in_fence = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        let heading = (!in_fence)
+            .then(|| line.strip_prefix('#'))
+            .flatten()
+            .map(|rest| rest.trim_start_matches('#'))
+            .filter(|rest| rest.starts_with(' '))
+            .map(str::trim);
+        if let Some(heading) = heading {
+            if current.heading.is_some() || !current.text.trim().is_empty() {
+                current.text.truncate(current.text.trim_end().len());
+                sections.push(current);
+            }
+            current = Section {
+                heading: Some(heading.to_string()),
+                text: String::new(),
+            };
+        } else {
+            current.text.push_str(line);
+            current.text.push('\n');
+        }
+    }
+    if current.heading.is_some() || !current.text.trim().is_empty() {
+        current.text.truncate(current.text.trim_end().len());
+        sections.push(current);
+    }
+    sections
+}
+
+/// Renders rows as `column: value` lines so each chunk is self-describing,
+/// grouped into sections of [`CSV_ROWS_PER_SECTION`] rows.
+fn extract_csv(path: &Path) -> Result<Vec<Section>> {
+    let mut reader = csv::Reader::from_path(path).map_err(|e| extraction_error(path, e))?;
+    let headers = reader
+        .headers()
+        .map_err(|e| extraction_error(path, e))?
+        .clone();
+    let mut sections = Vec::new();
+    let mut text = String::new();
+    let mut first_row = 1;
+    let mut rows = 0;
+    for (i, record) in reader.records().enumerate() {
+        rows = i + 1;
+        let record = record.map_err(|e| extraction_error(path, e))?;
+        let line: Vec<String> = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(column, value)| format!("{column}: {value}"))
+            .collect();
+        text.push_str(&line.join(" | "));
+        text.push('\n');
+        if rows % CSV_ROWS_PER_SECTION == 0 {
+            sections.push(Section {
+                heading: Some(format!("Rows {first_row}-{rows}")),
+                text: std::mem::take(&mut text),
+            });
+            first_row = rows + 1;
+        }
+    }
+    if !text.is_empty() {
+        sections.push(Section {
+            heading: Some(format!("Rows {first_row}-{rows}")),
+            text,
+        });
+    }
+    Ok(sections)
+}
+
+pub fn extract(path: &Path) -> Result<ExtractedDocument> {
+    let size = std::fs::metadata(path)?.len();
+    if size > MAX_FILE_SIZE {
+        return Err(extraction_error(
+            path,
+            format!("file is larger than {} MB", MAX_FILE_SIZE / 1024 / 1024),
+        ));
+    }
+    let (kind, language) = classify(path);
+    let sections = match kind {
+        DocumentKind::Pdf => extract_pdf(path)?,
+        DocumentKind::Docx => extract_docx(path)?,
+        DocumentKind::Csv => extract_csv(path)?,
+        DocumentKind::Markdown | DocumentKind::Code | DocumentKind::Text => {
+            let bytes = std::fs::read(path)?;
+            let text = String::from_utf8(bytes)
+                .map_err(|_| extraction_error(path, "not a UTF-8 text file"))?;
+            if kind == DocumentKind::Markdown {
+                extract_markdown(&text)
+            } else {
+                vec![Section {
+                    heading: None,
+                    text,
+                }]
+            }
+        }
+    };
+    Ok(ExtractedDocument {
+        path: path.to_path_buf(),
+        title: path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        kind,
+        language,
+        sections,
+    })
+}
+
+async fn extract_blocking(path: PathBuf) -> Result<ExtractedDocument> {
+    tauri::async_runtime::spawn_blocking(move || extract(&path))
+        .await
+        .map_err(|e| Error::Extraction(e.to_string()))?
+}
+
+pub async fn ingest(
+    db: &Database,
+    embedder: &Embedder,
+    client: &Client,
+    path: PathBuf,
+) -> Result<IndexedDocument> {
+    let document = extract_blocking(path).await?;
+    rag::index_text(
+        db,
+        embedder,
+        client,
+        &document.path.to_string_lossy(),
+        &document.title,
+        &document.full_text(),
+    )
+    .await
+}
+
+/// Extracts a file into sections for preview without indexing it.
+#[tauri::command]
+pub async fn extract_document(path: PathBuf) -> Result<ExtractedDocument> {
+    extract_blocking(path).await
+}
+
+/// Extracts a file and adds it to the RAG index, replacing earlier versions.
+#[tauri::command]
+pub async fn ingest_document(
+    db: State<'_, Database>,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    path: PathBuf,
+    options: Option<EmbeddingOptions>,
+) -> Result<IndexedDocument> {
+    let embedder = Embedder::resolve(&providers, &options.unwrap_or_default())?;
+    ingest(&db, &embedder, &client, path).await
+}
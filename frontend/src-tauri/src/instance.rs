@@ -0,0 +1,184 @@
+//! Keeps one copy of the app running. A second launch (opening a file with
+//! the app, a `pentamind://` link on Windows and Linux, or just starting
+//! it again) hands its command line to the first over a local socket (a
+//! named pipe on Windows) and exits. The running copy brings its main
+//! window forward, handles any links and emits `second-instance` with the
+//! arguments and the files among them.
+//!
+//! On unix the socket lives in `$XDG_RUNTIME_DIR`, or else in a directory
+//! of the user's own under the temp directory; either is refused unless
+//! only this user can get at it, since whoever could bind the socket first
+//! would be sent every later launch's arguments.
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+use crate::deep_link;
+use crate::profile;
+use crate::windows;
+
+/// Names the socket; per user, so accounts don't see each other's app.
+const SOCKET_NAME: &str = "com.pentamind.app";
+
+/// A launch's command line. Payload of the `second-instance` event, and
+/// what `get_launch_args` returns.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LaunchArgs {
+    pub args: Vec<String>,
+    /// Where it was started from, which relative paths are relative to.
+    pub cwd: Option<PathBuf>,
+    /// Arguments naming existing files or directories, made absolute.
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+}
+
+impl LaunchArgs {
+    fn current() -> Self {
+        let mut launch = Self {
+            args: std::env::args().skip(1).collect(),
+            cwd: std::env::current_dir().ok(),
+            files: Vec::new(),
+        };
+        launch.resolve_files();
+        launch
+    }
+
+    fn resolve_files(&mut self) {
+        let cwd = self.cwd.clone().unwrap_or_default();
+        self.files = self
+            .args
+            .iter()
+            .filter(|arg| !arg.starts_with('-') && !arg.contains("://"))
+            .map(|arg| cwd.join(arg))
+            .filter(|path| path.exists())
+            .collect();
+    }
+}
+
+#[cfg(unix)]
+fn socket_path() -> Option<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    let uid = unsafe { libc::getuid() };
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir,
+        _ => {
+            let dir = std::env::temp_dir().join(format!("{SOCKET_NAME}-{uid}"));
+            let _ = std::fs::DirBuilder::new().mode(0o700).create(&dir);
+            dir
+        }
+    };
+    let meta = std::fs::symlink_metadata(&dir).ok()?;
+    (meta.is_dir() && meta.uid() == uid && meta.mode() & 0o077 == 0)
+        .then(|| dir.join(format!("{SOCKET_NAME}.sock")))
+}
+
+#[cfg(windows)]
+fn pipe_name() -> String {
+    let user = std::env::var("USERNAME").unwrap_or_default();
+    format!(r"\\.\pipe\{SOCKET_NAME}-{user}")
+}
+
+/// Sends this process's arguments to an instance that is already running.
+/// Returns whether one took them, in which case this process should exit.
+pub fn forward_to_running() -> bool {
+    let Ok(message) = serde_json::to_string(&LaunchArgs::current()) else {
+        return false;
+    };
+    #[cfg(unix)]
+    let stream = match socket_path() {
+        Some(path) => std::os::unix::net::UnixStream::connect(path),
+        None => return false,
+    };
+    #[cfg(windows)]
+    let stream = std::fs::OpenOptions::new().write(true).open(pipe_name());
+    match stream {
+        Ok(mut stream) => writeln!(stream, "{message}").is_ok(),
+        Err(_) => false,
+    }
+}
+
+async fn receive(app: AppHandle, stream: impl AsyncRead + Unpin) {
+    let mut line = String::new();
+    if BufReader::new(stream).read_line(&mut line).await.is_err() {
+        return;
+    }
+    if let Ok(mut launch) = serde_json::from_str::<LaunchArgs>(&line) {
+        // Files are checked here too: the sender may be an older build.
+        launch.resolve_files();
+        profile::handle_args(&app, &launch.args);
+        windows::show_main_window(&app);
+        deep_link::handle_args(&app, &launch.args);
+        let _ = app.emit("second-instance", launch);
+    }
+}
+
+/// Files the OS asked the running app to open (macOS delivers these as an
+/// event rather than a new launch), reported like a second launch.
+#[cfg(target_os = "macos")]
+pub fn open_files(app: &AppHandle, files: Vec<PathBuf>) {
+    if files.is_empty() {
+        return;
+    }
+    windows::show_main_window(app);
+    let launch = LaunchArgs {
+        args: files.iter().map(|f| f.display().to_string()).collect(),
+        cwd: None,
+        files,
+    };
+    let _ = app.emit("second-instance", launch);
+}
+
+/// Accepts forwarded launches for the rest of the app's life.
+pub fn listen(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(err) = accept_loop(app).await {
+            tracing::warn!("not taking launches from other copies: {err}");
+        }
+    });
+}
+
+#[cfg(unix)]
+async fn accept_loop(app: AppHandle) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = socket_path().ok_or_else(|| {
+        std::io::Error::other("no directory only this user can reach for the socket")
+    })?;
+    // Nothing answered `forward_to_running`, so any file there is stale.
+    let _ = std::fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path)?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tauri::async_runtime::spawn(receive(app.clone(), stream));
+    }
+}
+
+#[cfg(windows)]
+async fn accept_loop(app: AppHandle) -> std::io::Result<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let name = pipe_name();
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&name)?;
+    loop {
+        server.connect().await?;
+        let connected = server;
+        server = ServerOptions::new().create(&name)?;
+        tauri::async_runtime::spawn(receive(app.clone(), connected));
+    }
+}
+
+/// This process's own command line, so the frontend can act on a file the
+/// app was launched to open.
+#[tauri::command]
+pub fn get_launch_args() -> LaunchArgs {
+    LaunchArgs::current()
+}
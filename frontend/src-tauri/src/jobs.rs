@@ -0,0 +1,374 @@
+//! Background jobs for operations too long to hold a command open: indexing,
+//! exports, imports, model downloads and evals. Jobs are tasks on the async
+//! runtime rather than tied to a window, so they keep going when the window
+//! is closed (closing only hides it while any are running). Every change is
+//! broadcast as a `job-progress` event carrying the job's full state.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, Manager, State, Window, WindowEvent};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{Error, Result};
+use crate::export::{self, ExportFormat};
+use crate::import;
+use crate::ingest;
+use crate::providers::priority::{self, Priority};
+use crate::providers::Providers;
+use crate::rag::{self, Embedder, EmbeddingOptions};
+use crate::requests::cancellable;
+use crate::storage::{new_id, now_ms, Database};
+use crate::windows;
+
+/// Finished jobs kept for `list_jobs`; older ones are forgotten.
+const MAX_FINISHED: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Indexing,
+    Export,
+    Import,
+    ModelDownload,
+    Evaluation,
+}
+
+impl JobKind {
+    /// The class a job's provider calls queue in.
+    fn priority(self) -> Priority {
+        match self {
+            JobKind::Evaluation => Priority::Eval,
+            _ => Priority::Background,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Payload of the `job-progress` event and what `list_jobs` returns.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub kind: JobKind,
+    pub label: String,
+    pub status: JobStatus,
+    /// From 0 to 1, or `None` while the amount of work isn't known.
+    pub progress: Option<f32>,
+    pub message: Option<String>,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    /// What the operation returned, once completed.
+    pub result: Option<Value>,
+    pub error: Option<String>,
+}
+
+struct Job {
+    info: JobInfo,
+    token: CancellationToken,
+}
+
+/// Running and recently finished jobs. Managed as Tauri state.
+#[derive(Default)]
+pub struct Jobs(Mutex<HashMap<String, Job>>);
+
+impl Jobs {
+    fn update(&self, id: &str, f: impl FnOnce(&mut JobInfo)) -> Option<JobInfo> {
+        let mut jobs = self.0.lock().unwrap();
+        let job = jobs.get_mut(id)?;
+        f(&mut job.info);
+        Some(job.info.clone())
+    }
+
+    pub fn running(&self) -> usize {
+        self.0
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|job| job.info.status == JobStatus::Running)
+            .count()
+    }
+
+    fn prune(jobs: &mut HashMap<String, Job>) {
+        let mut finished: Vec<(i64, String)> = jobs
+            .values()
+            .filter_map(|job| job.info.finished_at.map(|at| (at, job.info.id.clone())))
+            .collect();
+        if finished.len() <= MAX_FINISHED {
+            return;
+        }
+        finished.sort_unstable();
+        for (_, id) in &finished[..finished.len() - MAX_FINISHED] {
+            jobs.remove(id);
+        }
+    }
+}
+
+/// Handed to a job's work for reporting progress.
+#[derive(Clone)]
+pub struct JobContext {
+    app: AppHandle,
+    id: String,
+    token: CancellationToken,
+}
+
+impl JobContext {
+    pub fn app(&self) -> &AppHandle {
+        &self.app
+    }
+
+    /// Cancelled by `cancel_job`. Async work is dropped on cancellation;
+    /// blocking work should check this between steps.
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    pub fn progress(&self, progress: Option<f32>, message: impl Into<String>) {
+        let message = message.into();
+        let updated = self.app.state::<Jobs>().update(&self.id, |info| {
+            if info.status == JobStatus::Running {
+                info.progress = progress.map(|p| p.clamp(0.0, 1.0));
+                info.message = Some(message);
+            }
+        });
+        if let Some(info) = updated {
+            let _ = self.app.emit("job-progress", info);
+        }
+    }
+}
+
+/// Starts `run` as a background job and returns its initial state. The
+/// job's result is serialized into `JobInfo::result` when it completes.
+pub fn spawn<F, Fut, T>(app: &AppHandle, kind: JobKind, label: String, run: F) -> JobInfo
+where
+    F: FnOnce(JobContext) -> Fut,
+    Fut: Future<Output = Result<T>> + Send + 'static,
+    T: Serialize,
+{
+    let info = JobInfo {
+        id: new_id(),
+        kind,
+        label,
+        status: JobStatus::Running,
+        progress: None,
+        message: None,
+        started_at: now_ms(),
+        finished_at: None,
+        result: None,
+        error: None,
+    };
+    let context = JobContext {
+        app: app.clone(),
+        id: info.id.clone(),
+        token: CancellationToken::new(),
+    };
+    app.state::<Jobs>().0.lock().unwrap().insert(
+        info.id.clone(),
+        Job {
+            info: info.clone(),
+            token: context.token.clone(),
+        },
+    );
+    let _ = app.emit("job-progress", info.clone());
+
+    let work = priority::scope(kind.priority(), run(context.clone()));
+    tauri::async_runtime::spawn(async move {
+        let result = cancellable(&context.token, work)
+            .await
+            .and_then(|value| Ok(serde_json::to_value(value)?));
+        let jobs = context.app.state::<Jobs>();
+        let finished = jobs.update(&context.id, |info| {
+            info.finished_at = Some(now_ms());
+            match result {
+                Ok(value) => {
+                    info.status = JobStatus::Completed;
+                    info.progress = Some(1.0);
+                    info.result = Some(value);
+                }
+                Err(Error::Cancelled) => info.status = JobStatus::Cancelled,
+                Err(err) => {
+                    info.status = JobStatus::Failed;
+                    info.error = Some(err.to_string());
+                }
+            }
+        });
+        Jobs::prune(&mut jobs.0.lock().unwrap());
+        if let Some(info) = finished {
+            let _ = context.app.emit("job-progress", info);
+        }
+    });
+    info
+}
+
+/// Keeps the main window's close button from taking running jobs down with
+/// it: the window is hidden to the tray instead.
+pub fn on_window_event(window: &Window, event: &WindowEvent) {
+    if let WindowEvent::CloseRequested { api, .. } = event {
+        if window.label() == windows::MAIN_LABEL && window.state::<Jobs>().running() > 0 {
+            api.prevent_close();
+            let _ = window.hide();
+        }
+    }
+}
+
+/// An operation to run as a job, tagged by `kind`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobRequest {
+    /// Extract a file and add it to the RAG index.
+    IngestDocument {
+        path: PathBuf,
+        options: Option<EmbeddingOptions>,
+    },
+    /// Add text to the RAG index.
+    IndexDocument {
+        source: String,
+        title: String,
+        text: String,
+        options: Option<EmbeddingOptions>,
+    },
+    ExportConversation {
+        id: String,
+        format: ExportFormat,
+        path: PathBuf,
+    },
+    /// Import a ChatGPT or Claude data export.
+    ImportArchive {
+        path: PathBuf,
+    },
+    PullOllamaModel {
+        model: String,
+    },
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn blocking_error(err: tauri::Error) -> Error {
+    Error::Io(std::io::Error::other(err))
+}
+
+/// Starts a background job. Bad options (an unknown provider, say) fail
+/// here; everything after that is reported through `job-progress`.
+#[tauri::command]
+pub async fn start_job(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    request: JobRequest,
+) -> Result<JobInfo> {
+    let info = match request {
+        JobRequest::IngestDocument { path, options } => {
+            let embedder = Embedder::resolve(&providers, &options.unwrap_or_default())?;
+            let label = format!("Index {}", file_name(&path));
+            spawn(&app, JobKind::Indexing, label, |ctx| async move {
+                ctx.progress(None, "Extracting and embedding");
+                let app = ctx.app();
+                let (db, client) = (app.state::<Database>(), app.state::<Client>());
+                ingest::ingest(&db, &embedder, &client, path).await
+            })
+        }
+        JobRequest::IndexDocument {
+            source,
+            title,
+            text,
+            options,
+        } => {
+            let embedder = Embedder::resolve(&providers, &options.unwrap_or_default())?;
+            let label = format!("Index {title}");
+            spawn(&app, JobKind::Indexing, label, |ctx| async move {
+                ctx.progress(None, "Embedding");
+                let app = ctx.app();
+                let (db, client) = (app.state::<Database>(), app.state::<Client>());
+                rag::index_text(&db, &embedder, &client, &source, &title, &text).await
+            })
+        }
+        JobRequest::ExportConversation { id, format, path } => {
+            let label = format!("Export to {}", file_name(&path));
+            spawn(&app, JobKind::Export, label, |ctx| async move {
+                tauri::async_runtime::spawn_blocking(move || {
+                    let detail = ctx.app().state::<Database>().get_conversation(&id)?;
+                    std::fs::write(&path, export::render(&detail, format)?)?;
+                    Ok(path)
+                })
+                .await
+                .map_err(blocking_error)?
+            })
+        }
+        JobRequest::ImportArchive { path } => {
+            let label = format!("Import {}", file_name(&path));
+            spawn(&app, JobKind::Import, label, |ctx| async move {
+                tauri::async_runtime::spawn_blocking(move || {
+                    import::import(ctx.app(), &path, ctx.token(), |processed, total| {
+                        ctx.progress(
+                            Some(processed as f32 / total.max(1) as f32),
+                            format!("{processed} of {total} conversations"),
+                        );
+                    })
+                })
+                .await
+                .map_err(blocking_error)?
+            })
+        }
+        JobRequest::PullOllamaModel { model } => {
+            let label = format!("Download {model}");
+            spawn(&app, JobKind::ModelDownload, label, |ctx| async move {
+                let app = ctx.app();
+                let (providers, client) = (app.state::<Providers>(), app.state::<Client>());
+                providers
+                    .ollama()
+                    .pull(&client, &model, |value| {
+                        let fraction = value["completed"]
+                            .as_u64()
+                            .zip(value["total"].as_u64())
+                            .filter(|&(_, total)| total > 0)
+                            .map(|(completed, total)| completed as f32 / total as f32);
+                        ctx.progress(fraction, value["status"].as_str().unwrap_or_default());
+                    })
+                    .await
+            })
+        }
+    };
+    Ok(info)
+}
+
+/// All running jobs and recently finished ones, newest first.
+#[tauri::command]
+pub fn list_jobs(jobs: State<'_, Jobs>) -> Vec<JobInfo> {
+    let mut list: Vec<JobInfo> = jobs
+        .0
+        .lock()
+        .unwrap()
+        .values()
+        .map(|job| job.info.clone())
+        .collect();
+    list.sort_by_key(|info| std::cmp::Reverse(info.started_at));
+    list
+}
+
+/// Stops a running job. Returns whether it was still running.
+#[tauri::command]
+pub fn cancel_job(jobs: State<'_, Jobs>, job_id: String) -> bool {
+    match jobs.0.lock().unwrap().get(&job_id) {
+        Some(job) if job.info.status == JobStatus::Running => {
+            job.token.cancel();
+            true
+        }
+        _ => false,
+    }
+}
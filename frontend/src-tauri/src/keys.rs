@@ -0,0 +1,49 @@
+//! Provider API keys kept in the OS credential store (Keychain, Credential
+//! Manager, Secret Service) instead of the webview's localStorage. Each
+//! profile has entries of its own.
+
+use keyring::Entry;
+
+use crate::error::Result;
+use crate::profile;
+
+const SERVICE: &str = "com.pentamind.app";
+
+fn entry(provider: &str) -> Result<Entry> {
+    Ok(Entry::new(&profile::key_service(SERVICE), provider)?)
+}
+
+/// Returns the stored key for `provider`, if any.
+pub fn load(provider: &str) -> Result<Option<String>> {
+    match entry(provider)?.get_password() {
+        Ok(key) => Ok(Some(key)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub fn store(provider: &str, key: &str) -> Result<()> {
+    Ok(entry(provider)?.set_password(key.trim())?)
+}
+
+pub fn delete(provider: &str) -> Result<()> {
+    match entry(provider)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tauri::command]
+pub fn store_api_key(provider: String, key: String) -> Result<()> {
+    store(&provider, &key)
+}
+
+#[tauri::command]
+pub fn get_api_key(provider: String) -> Result<Option<String>> {
+    load(&provider)
+}
+
+#[tauri::command]
+pub fn delete_api_key(provider: String) -> Result<()> {
+    delete(&provider)
+}
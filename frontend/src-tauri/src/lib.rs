@@ -1,28 +1,584 @@
 use tauri::Manager;
 
+mod accessibility;
+mod api_server;
+mod app_menu;
+mod arbiter;
+mod attachments;
+mod audio;
+mod audit;
+mod autosave;
+mod backup;
+mod bookmarks;
+mod bundle;
+mod cache;
+mod citations;
+mod cli;
+mod clipboard;
+mod code_files;
+mod config;
+mod connectors;
+mod context_manager;
+mod debate;
+mod deep_link;
+mod diff;
+mod error;
+mod evals;
+mod export;
+mod failover;
+mod fanout;
+mod hotkey;
+mod images;
+mod import;
+mod incognito;
+mod ingest;
+mod instance;
+mod jobs;
+mod keys;
+mod llm;
+#[cfg(feature = "local-llm")]
+mod local_llm;
+mod logging;
+mod maintenance;
+mod markdown;
+mod mcp;
+mod media;
+mod memory;
+mod mini_window;
+mod network;
+mod notes;
+mod notifications;
+mod ocr;
+mod offline;
+mod orchestration;
+mod os_search;
+mod palette;
+mod paste;
+mod patch;
+mod pipeline;
+mod plugins;
+mod presets;
+mod process;
+mod profile;
+mod project;
+mod providers;
+mod rag;
+mod realtime;
+mod relocate;
+mod requests;
+mod retention;
+mod routing;
+mod scheduler;
+mod screen_context;
+mod screenshot;
+mod scrub;
+mod search;
+mod session;
+mod settings;
+mod share;
+mod shortcuts;
+mod sources;
+mod speech;
+mod startup;
+mod storage;
+mod structured;
+mod sync;
+mod system_stats;
+mod template_versions;
+mod templates;
+mod thumbnails;
+mod titling;
+mod tools;
+mod translate;
+mod tray;
+mod updater;
+mod usage;
+mod watch;
+mod web;
+mod window_state;
+mod windows;
+
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 #[tauri::command]
 fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
+/// Subsystems the headless CLI needs as well as the GUI.
+fn init_core(app: &tauri::App) -> Result<(), Box<dyn std::error::Error>> {
+    profile::init(app.handle())?;
+    logging::init(app.handle());
+    network::init(app.handle());
+    plugins::init(app.handle());
+    app.manage(providers::Providers::new(app.handle()));
+    let db = storage::Database::open(&profile::data_dir(app.handle())?)?;
+    app.manage(db);
+    audit::init(app.handle());
+    settings::init(app.handle());
+    scrub::init(app.handle());
+    usage::init(app.handle());
+    cache::init(app.handle());
+    context_manager::init(app.handle());
+    titling::init(app.handle());
+    notifications::init(app.handle());
+    providers::ollama::init(app.handle());
+    pipeline::init(app.handle());
+    translate::init(app.handle());
+    memory::init(app.handle());
+    failover::init(app.handle());
+    Ok(())
+}
+
+fn context() -> tauri::Context {
+    tauri::generate_context!()
+}
+
+/// Runs a CLI command without opening a window and returns the exit code;
+/// see `cli`.
+pub fn run_headless(args: Vec<String>) -> i32 {
+    let command = match cli::parse(&args) {
+        Ok(cli::Command::Help) => {
+            println!("{}", cli::USAGE);
+            return 0;
+        }
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("pentamind: {message}\n\n{}", cli::USAGE);
+            return 2;
+        }
+    };
+    let mut context = context();
+    // Any window would load the frontend, which starts requests of its own.
+    context.config_mut().app.windows.clear();
+    let app = tauri::Builder::default()
+        .manage(requests::Requests::default())
+        .setup(|app| init_core(app))
+        .build(context)
+        .expect("error while building tauri application");
+    tauri::async_runtime::block_on(cli::execute(app.handle(), command))
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    startup::begin();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == cli::HEADLESS_FLAG) {
+        std::process::exit(run_headless(args[1..].to_vec()));
+    }
+    if instance::forward_to_running() {
+        return;
+    }
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![greet])
-        .build(tauri::generate_context!())
+        .manage(requests::Requests::default())
+        .manage(audio::Recorder::default())
+        .manage(realtime::Sessions::default())
+        .manage(jobs::Jobs::default())
+        .manage(deep_link::DeepLinks::default())
+        .manage(windows::ConversationWindows::default())
+        .manage(incognito::Incognito::default())
+        .register_asynchronous_uri_scheme_protocol(thumbnails::SCHEME, thumbnails::protocol)
+        .register_asynchronous_uri_scheme_protocol(media::SCHEME, media::protocol)
+        .setup(move |app| {
+            startup::phase("core", || init_core(app))?;
+            startup::phase("services", || {
+                session::init(app.handle());
+                mini_window::init(app.handle());
+                backup::init(app.handle());
+                scheduler::init(app.handle());
+                sync::init(app.handle());
+                clipboard::init(app.handle());
+                watch::init(app.handle());
+                speech::init(app.handle());
+                offline::init(app.handle());
+                os_search::init(app.handle());
+                screen_context::init(app.handle());
+                system_stats::init(app.handle());
+                accessibility::init(app.handle());
+                retention::init(app.handle());
+                maintenance::init(app.handle());
+                failover::watch(app.handle());
+                mcp::init(app.handle());
+                connectors::init(app.handle());
+                sources::init(app.handle());
+                tools::init(app.handle());
+                api_server::init(app.handle());
+                updater::init(app.handle())
+            })?;
+            startup::phase("interface", || {
+                instance::listen(app.handle());
+                deep_link::register(app.handle());
+                shortcuts::init(app.handle())?;
+                audio::wake::init(app.handle());
+                tray::init(app)?;
+                app_menu::init(app.handle())
+            })?;
+            app.manage(window_state::WindowStates::default());
+            if let Some(window) = app.get_webview_window(windows::MAIN_LABEL) {
+                // The main window starts hidden so restoring doesn't flash the default position.
+                startup::phase("window", || {
+                    let _ = window_state::restore(app.handle(), &window);
+                    window.show()
+                })?;
+                startup::window_shown();
+            }
+            startup::warm(app.handle());
+            deep_link::handle_args(app.handle(), &args);
+            Ok(())
+        })
+        .on_menu_event(app_menu::on_menu_event)
+        .on_window_event(|window, event| {
+            hotkey::on_window_event(window, event);
+            mini_window::on_window_event(window, event);
+            accessibility::on_window_event(window, event);
+            windows::on_window_event(window, event);
+            incognito::on_window_event(window, event);
+            window_state::on_window_event(window, event);
+            jobs::on_window_event(window, event);
+            attachments::on_window_event(window, event);
+        })
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            llm::stream_chat,
+            structured::structured_output,
+            llm::tokens::count_tokens,
+            llm::tokens::fit_to_context,
+            fanout::fanout_prompt,
+            routing::classify_and_route,
+            routing::set_conversation_route,
+            attachments::attach_files,
+            paste::paste,
+            paste::paste_clipboard_image,
+            images::generate_image,
+            images::list_generated_images,
+            profile::list_profiles,
+            profile::create_profile,
+            profile::switch_profile,
+            relocate::get_data_directory,
+            relocate::set_data_directory,
+            startup::get_startup_metrics,
+            scheduler::list_schedules,
+            scheduler::create_schedule,
+            scheduler::update_schedule,
+            scheduler::delete_schedule,
+            scheduler::run_schedule_now,
+            scheduler::list_scheduled_runs,
+            sync::get_sync_config,
+            sync::set_sync_config,
+            sync::sync_now,
+            sync::get_sync_status,
+            diff::diff_responses,
+            arbiter::rank_responses,
+            arbiter::synthesize_answer,
+            keys::store_api_key,
+            keys::get_api_key,
+            keys::delete_api_key,
+            storage::conversations::create_conversation,
+            incognito::create_ephemeral_conversation,
+            storage::conversations::append_message,
+            storage::conversations::list_conversations,
+            storage::conversations::get_conversation,
+            storage::conversations::get_conversation_tree,
+            storage::conversations::fork_conversation,
+            storage::conversations::list_branches,
+            storage::conversations::switch_branch,
+            titling::regenerate_title,
+            titling::summarize_conversation,
+            titling::get_conversation_context,
+            titling::get_titling_config,
+            titling::set_titling_config,
+            memory::list_memories,
+            memory::add_memory,
+            memory::update_memory,
+            memory::delete_memory,
+            memory::clear_memories,
+            memory::find_memories,
+            memory::get_memory_config,
+            memory::set_memory_config,
+            storage::conversations::delete_conversation,
+            storage::conversations::archive_conversation,
+            storage::conversations::unarchive_conversation,
+            storage::encryption::get_database_status,
+            storage::encryption::set_encryption_passphrase,
+            storage::encryption::unlock_database,
+            storage::encryption::change_passphrase,
+            backup::get_backup_config,
+            backup::set_backup_config,
+            backup::list_backups,
+            backup::create_backup_now,
+            backup::restore_from_backup,
+            bundle::export_app_bundle,
+            bundle::import_app_bundle,
+            clipboard::copy_response,
+            clipboard::copy_as_markdown,
+            clipboard::copy_as_code,
+            code_files::save_code_blocks,
+            patch::apply_patch,
+            patch::undo_patch,
+            clipboard::get_clipboard_history,
+            clipboard::clear_clipboard_history,
+            clipboard::set_clipboard_history,
+            screenshot::capture_screen,
+            ocr::ocr_image,
+            ocr::list_ocr_languages,
+            audio::start_recording,
+            audio::stop_recording,
+            audio::cancel_recording,
+            audio::wake::get_wake_config,
+            audio::wake::set_wake_config,
+            audio::wake::get_wake_status,
+            audio::wake::pause_wake_word,
+            audio::wake::resume_wake_word,
+            speech::speak_text,
+            speech::pause_speech,
+            speech::resume_speech,
+            speech::stop_speech,
+            speech::list_voices,
+            speech::get_speech_settings,
+            speech::set_speech_settings,
+            settings::get_settings,
+            settings::update_settings,
+            presets::create_preset,
+            presets::update_preset,
+            presets::get_preset,
+            presets::list_presets,
+            presets::delete_preset,
+            presets::apply_preset,
+            templates::create_template,
+            templates::update_template,
+            templates::get_template,
+            templates::list_templates,
+            templates::delete_template,
+            templates::list_template_tags,
+            templates::render_template,
+            template_versions::get_prompt_history,
+            template_versions::diff_prompt_versions,
+            template_versions::rollback_prompt,
+            mcp::list_mcp_servers,
+            mcp::add_mcp_server,
+            mcp::remove_mcp_server,
+            mcp::set_mcp_server_enabled,
+            mcp::list_mcp_tools,
+            mcp::list_mcp_resources,
+            mcp::read_mcp_resource,
+            mcp::call_mcp_tool,
+            connectors::list_connectors,
+            connectors::save_connector,
+            connectors::delete_connector,
+            connectors::sync_connector,
+            sources::get_sources_config,
+            sources::set_sources_config,
+            sources::list_sources,
+            sources::add_source,
+            sources::update_source,
+            sources::delete_source,
+            sources::refresh_source,
+            sources::list_source_items,
+            sources::summarize_new_items,
+            tools::list_tools,
+            tools::set_tool_enabled,
+            tools::approve_tool_call,
+            tools::get_shell_policy,
+            tools::set_shell_policy,
+            tools::run_code,
+            web::fetch_url,
+            api_server::start_api_server,
+            api_server::stop_api_server,
+            api_server::get_api_server_status,
+            api_server::regenerate_api_token,
+            deep_link::take_pending_deep_links,
+            network::get_network_config,
+            network::set_network_config,
+            audit::get_audit_config,
+            audit::set_audit_config,
+            audit::list_audit_log,
+            audit::export_audit_log,
+            scrub::scrub_prompt,
+            scrub::confirm_scrubbed_prompt,
+            scrub::get_scrub_config,
+            scrub::set_scrub_config,
+            offline::get_offline_status,
+            offline::get_offline_config,
+            offline::set_offline_config,
+            offline::list_queued_requests,
+            offline::discard_queued_request,
+            offline::retry_queued_requests,
+            os_search::get_os_search_config,
+            os_search::set_os_search_config,
+            os_search::reindex_os_search,
+            notifications::notify,
+            notifications::get_notification_config,
+            notifications::set_notification_config,
+            notifications::set_provider_muted,
+            logging::get_recent_logs,
+            logging::open_log_folder,
+            logging::set_log_level,
+            updater::check_for_updates,
+            updater::download_update,
+            updater::install_and_restart,
+            instance::get_launch_args,
+            search::search_messages,
+            bookmarks::bookmark_message,
+            bookmarks::remove_bookmark,
+            bookmarks::list_bookmarks,
+            bookmarks::tag_message,
+            bookmarks::untag_message,
+            bookmarks::list_message_tags,
+            notes::create_note,
+            notes::update_note,
+            notes::get_note,
+            notes::list_notes,
+            notes::delete_note,
+            notes::get_backlinks,
+            notes::link_note,
+            notes::unlink_note,
+            notes::list_conversation_notes,
+            palette::palette_query,
+            pipeline::get_pipeline_config,
+            pipeline::configure_pipeline,
+            translate::translate,
+            translate::detect_language,
+            translate::get_translate_config,
+            translate::set_translate_config,
+            translate::set_conversation_language,
+            pipeline::run_pipeline,
+            plugins::list_plugins,
+            plugins::install_plugin,
+            plugins::remove_plugin,
+            plugins::set_plugin_permissions,
+            export::export_conversation,
+            share::export_share_html,
+            import::import_archive,
+            rag::index_document,
+            rag::semantic_search,
+            rag::build_context,
+            citations::get_response_sources,
+            ingest::extract_document,
+            ingest::ingest_document,
+            project::project_context,
+            watch::watch_path,
+            watch::unwatch_path,
+            watch::list_watches,
+            jobs::start_job,
+            jobs::list_jobs,
+            jobs::cancel_job,
+            evals::run_eval,
+            evals::get_eval_report,
+            evals::list_eval_runs,
+            hotkey::get_quick_prompt_shortcut,
+            hotkey::set_quick_prompt_shortcut,
+            shortcuts::list_shortcuts,
+            shortcuts::rebind_shortcut,
+            shortcuts::reset_shortcuts,
+            screen_context::capture_screen_context,
+            screen_context::get_screen_context,
+            screen_context::clear_screen_context,
+            screen_context::get_screen_context_permission,
+            screen_context::request_screen_context_permission,
+            screen_context::get_screen_context_config,
+            screen_context::set_screen_context_config,
+            thumbnails::get_thumbnail,
+            realtime::start_realtime_session,
+            realtime::send_audio_chunk,
+            realtime::end_session,
+            system_stats::system_stats,
+            system_stats::get_system_monitor_config,
+            system_stats::set_system_monitor_config,
+            accessibility::get_accessibility_preferences,
+            retention::get_retention_policy,
+            retention::set_retention_policy,
+            retention::apply_retention_policy,
+            maintenance::check_database_integrity,
+            maintenance::vacuum_database,
+            maintenance::get_storage_stats,
+            orchestration::run_draft_critique,
+            orchestration::get_orchestration_run,
+            orchestration::list_orchestration_runs,
+            debate::run_debate,
+            tray::set_tray_status,
+            windows::open_conversation_window,
+            windows::list_conversation_windows,
+            mini_window::toggle_mini_window,
+            mini_window::pin_mini_window,
+            mini_window::snap_mini_window,
+            mini_window::get_mini_window_config,
+            mini_window::get_mini_summary,
+            window_state::reset_window_state,
+            session::restore_session,
+            session::update_session,
+            session::clear_session,
+            autosave::recover_partial_responses,
+            requests::cancel_request,
+            usage::get_usage_summary,
+            usage::set_budget_alert,
+            cache::get_cache_config,
+            cache::set_cache_config,
+            cache::clear_cache,
+            context_manager::get_context_config,
+            context_manager::set_context_config,
+            providers::list_providers,
+            providers::list_image_providers,
+            providers::list_models,
+            providers::custom::list_custom_providers,
+            providers::custom::add_custom_provider,
+            providers::custom::remove_custom_provider,
+            providers::custom::test_provider_connection,
+            providers::send_prompt,
+            providers::get_rate_limits,
+            providers::set_rate_limit,
+            providers::get_concurrency_limits,
+            providers::set_concurrency_limits,
+            providers::get_provider_stats,
+            failover::get_failover_config,
+            failover::set_failover_config,
+            failover::get_provider_health,
+            failover::check_provider_health,
+            providers::ollama::detect_ollama,
+            providers::ollama::list_ollama_models,
+            providers::ollama::pull_ollama_model,
+            providers::ollama::delete_ollama_model,
+            #[cfg(feature = "local-llm")]
+            local_llm::load_local_model,
+            #[cfg(feature = "local-llm")]
+            local_llm::unload_local_model,
+            #[cfg(feature = "local-llm")]
+            local_llm::get_local_model,
+            #[cfg(feature = "local-llm")]
+            local_llm::estimate_local_model_memory,
+            #[cfg(feature = "local-llm")]
+            local_llm::check_local_model_fit,
+        ])
+        .build(context())
         .expect("error while building tauri application")
         .run(|app_handle, event| {
+            if let tauri::RunEvent::ExitRequested { .. } = event {
+                window_state::save_all(app_handle);
+                session::flush(app_handle);
+            }
+            if let tauri::RunEvent::Exit = event {
+                updater::install_on_exit(app_handle);
+            }
+            #[cfg(target_os = "macos")]
+            if let tauri::RunEvent::Opened { urls } = &event {
+                let files = urls
+                    .iter()
+                    .filter_map(|url| url.to_file_path().ok())
+                    .collect();
+                instance::open_files(app_handle, files);
+                for url in urls.iter().filter(|url| url.scheme() != "file") {
+                    deep_link::handle_url(app_handle, url.as_str());
+                }
+            }
             // Handle macOS dock icon click when app is hidden
             #[cfg(target_os = "macos")]
-            if let tauri::RunEvent::Reopen { has_visible_windows, .. } = event {
+            if let tauri::RunEvent::Reopen {
+                has_visible_windows,
+                ..
+            } = event
+            {
                 if !has_visible_windows {
                     // Show the main window when dock icon is clicked
-                    if let Some(window) = app_handle.get_webview_window("main") {
-                        let _ = window.show();
-                        let _ = window.set_focus();
-                    }
+                    windows::show_main_window(app_handle);
                 }
             }
         });
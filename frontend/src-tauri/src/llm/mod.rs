@@ -0,0 +1,336 @@
+//! Model calls made from the Rust side, so a generation survives webview
+//! reloads and can be stopped from the backend.
+
+pub mod tokens;
+
+use std::time::Instant;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, State};
+
+use crate::autosave;
+use crate::cache;
+use crate::citations;
+use crate::context_manager;
+use crate::error::Result;
+use crate::pipeline;
+use crate::providers::{Completion, DeltaSink, Provider, Providers};
+use crate::requests::{cancellable, Requests};
+use crate::scrub;
+use crate::tools;
+use crate::usage;
+
+/// Model turns allowed to end in tool calls before the answer is cut off.
+const MAX_TOOL_ROUNDS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatRequest {
+    pub provider: String,
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    /// Let the model call the enabled tools, built-in and from MCP servers.
+    #[serde(default)]
+    pub use_tools: bool,
+    /// Send the request even if a cached answer exists, and cache the new one.
+    #[serde(default)]
+    pub bypass_cache: bool,
+    /// JSON Schema the answer must follow, passed to the provider's native
+    /// JSON mode where it has one.
+    #[serde(default)]
+    pub response_schema: Option<Value>,
+    /// Picks the conversation's post-processing over the default; see
+    /// `pipeline`.
+    #[serde(default)]
+    pub conversation_id: Option<String>,
+    /// Filled in by the backend from `use_tools`.
+    #[serde(skip)]
+    pub tools: Vec<ToolSpec>,
+    /// Tool use so far in this turn, replayed after `messages`.
+    #[serde(skip)]
+    pub tool_rounds: Vec<ToolRound>,
+}
+
+/// A function the model may call, with its parameters as JSON Schema.
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolCall {
+    /// Generated locally for providers that don't assign ids.
+    pub id: String,
+    pub name: String,
+    /// Normally an object; left as the raw string if the model sent
+    /// something that doesn't parse.
+    pub arguments: Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolResult {
+    pub call_id: String,
+    pub name: String,
+    pub content: String,
+    pub is_error: bool,
+}
+
+/// A model response that called tools, and what the tools returned.
+#[derive(Debug, Clone)]
+pub struct ToolRound {
+    pub text: String,
+    pub calls: Vec<ToolCall>,
+    pub results: Vec<ToolResult>,
+}
+
+/// Token usage as reported by the provider.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatResponse {
+    pub provider: String,
+    pub model: String,
+    pub content: String,
+    /// The answer before post-processing, when that changed it.
+    pub original_content: Option<String>,
+    pub usage: Option<Usage>,
+    pub latency_ms: u64,
+    /// Answered from the response cache, so nothing was billed.
+    pub cached: bool,
+}
+
+/// Payload of the `chat-token` event, one per streamed delta.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatToken {
+    pub request_id: String,
+    pub provider: String,
+    pub delta: String,
+}
+
+/// Payload of the `chat-tool-call` event, sent after each tool runs.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatToolCall {
+    pub request_id: String,
+    pub provider: String,
+    pub call: ToolCall,
+    pub result: ToolResult,
+}
+
+/// Payload of the `chat-done` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatDone {
+    pub request_id: String,
+    #[serde(flatten)]
+    pub response: ChatResponse,
+}
+
+/// Payload of the `chat-error` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatError {
+    pub request_id: String,
+    pub error: String,
+}
+
+/// Streams a chat completion, emitting `chat-token` for each delta and
+/// `chat-done` (or `chat-error`) at the end. `request_id` is chosen by the
+/// caller so it can match events and pass it to `cancel_request`.
+#[tauri::command]
+pub async fn stream_chat(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    requests: State<'_, Requests>,
+    request_id: String,
+    request: ChatRequest,
+) -> Result<ChatResponse> {
+    let guard = requests.register(&request_id);
+    let outcome = cancellable(guard.token(), async {
+        let mut request = request;
+        request.messages = scrub::check(&app, &request_id, request.messages).await?;
+        let mut response = run_stream(&app, &providers, &client, &request_id, &request).await?;
+        let conversation_id = request.conversation_id.as_deref();
+        response.original_content =
+            pipeline::apply(&app, conversation_id, &mut response.content).await;
+        Ok(response)
+    })
+    .await;
+    match outcome {
+        Ok(response) => {
+            app.emit(
+                "chat-done",
+                ChatDone {
+                    request_id,
+                    response: response.clone(),
+                },
+            )?;
+            Ok(response)
+        }
+        Err(err) => {
+            app.emit(
+                "chat-error",
+                ChatError {
+                    request_id,
+                    error: err.to_string(),
+                },
+            )?;
+            Err(err)
+        }
+    }
+}
+
+/// Streams `request`, emitting `chat-token` events, and records its usage.
+pub(crate) async fn run_stream(
+    app: &AppHandle,
+    providers: &Providers,
+    client: &Client,
+    request_id: &str,
+    request: &ChatRequest,
+) -> Result<ChatResponse> {
+    let started = Instant::now();
+    let provider = providers.get(&request.provider)?;
+    let (completion, cached) = complete_cached(
+        app,
+        provider.as_ref(),
+        client,
+        request_id,
+        request,
+        &mut |delta| {
+            let _ = app.emit(
+                "chat-token",
+                ChatToken {
+                    request_id: request_id.to_string(),
+                    provider: request.provider.clone(),
+                    delta: delta.to_string(),
+                },
+            );
+        },
+    )
+    .await?;
+
+    let response = ChatResponse {
+        provider: request.provider.clone(),
+        model: request.model.clone(),
+        content: completion.content,
+        original_content: None,
+        usage: completion.usage,
+        latency_ms: started.elapsed().as_millis() as u64,
+        cached,
+    };
+    if !cached {
+        usage::record(app, request, &response.content, response.usage);
+    }
+    Ok(response)
+}
+
+pub(crate) fn add_usage(total: Option<Usage>, more: Option<Usage>) -> Option<Usage> {
+    match (total, more) {
+        (Some(a), Some(b)) => Some(Usage {
+            prompt_tokens: a.prompt_tokens + b.prompt_tokens,
+            completion_tokens: a.completion_tokens + b.completion_tokens,
+        }),
+        (a, b) => a.or(b),
+    }
+}
+
+/// `complete`, answered from the response cache when possible. A cached
+/// answer reaches `on_delta` in one piece. Returns whether it was cached.
+/// Anything else is written to an autosave log as it streams.
+pub(crate) async fn complete_cached(
+    app: &AppHandle,
+    provider: &dyn Provider,
+    client: &Client,
+    request_id: &str,
+    request: &ChatRequest,
+    on_delta: &mut DeltaSink<'_>,
+) -> Result<(Completion, bool)> {
+    let compressed = context_manager::compress(app, request_id, request).await;
+    let request = compressed.as_ref().unwrap_or(request);
+    let mut lookup = cache::lookup(app, client, request).await;
+    if let Some(hit) = lookup.as_mut().and_then(|l| l.hit.take()) {
+        on_delta(&hit.content);
+        return Ok((hit, true));
+    }
+    let mut log = autosave::Log::begin(app, request_id, request);
+    let mut logged = |delta: &str| {
+        if let Some(log) = log.as_mut() {
+            log.append(delta);
+        }
+        on_delta(delta);
+    };
+    let completion = complete(app, provider, client, request_id, request, &mut logged).await?;
+    if let Some(lookup) = lookup {
+        cache::store(app, request, lookup, &completion);
+    }
+    Ok((completion, false))
+}
+
+/// Streams a completion, and with `use_tools` runs the tools the model
+/// calls and sends it the results until it answers without calling any.
+/// The returned completion holds the text of every round and their
+/// combined usage.
+pub(crate) async fn complete(
+    app: &AppHandle,
+    provider: &dyn Provider,
+    client: &Client,
+    request_id: &str,
+    request: &ChatRequest,
+    on_delta: &mut DeltaSink<'_>,
+) -> Result<Completion> {
+    if !request.use_tools {
+        return provider.stream(client, request, on_delta).await;
+    }
+    let mut request = request.clone();
+    request.tools = tools::specs(app);
+    let mut total = Completion::default();
+    loop {
+        let completion = provider.stream(client, &request, &mut *on_delta).await?;
+        total.content.push_str(&completion.content);
+        total.usage = add_usage(total.usage, completion.usage);
+        if completion.tool_calls.is_empty() || request.tool_rounds.len() >= MAX_TOOL_ROUNDS {
+            return Ok(total);
+        }
+        let mut results = Vec::with_capacity(completion.tool_calls.len());
+        for call in &completion.tool_calls {
+            let result = citations::for_request(&request, tools::run(app, request_id, call)).await;
+            let _ = app.emit(
+                "chat-tool-call",
+                ChatToolCall {
+                    request_id: request_id.to_string(),
+                    provider: request.provider.clone(),
+                    call: call.clone(),
+                    result: result.clone(),
+                },
+            );
+            results.push(result);
+        }
+        request.tool_rounds.push(ToolRound {
+            text: completion.content,
+            calls: completion.tool_calls,
+            results,
+        });
+    }
+}
@@ -0,0 +1,252 @@
+//! Token counting and context-window budgeting. OpenAI models are counted
+//! exactly with their tiktoken encodings; other vendors don't publish their
+//! tokenizers, so they are estimated with `cl100k_base` plus a safety margin.
+
+use serde::{Deserialize, Serialize};
+use tiktoken_rs::CoreBPE;
+
+use super::{ChatMessage, Role};
+
+/// Tokens added per message for role markers and separators.
+const MESSAGE_OVERHEAD: usize = 4;
+/// Tokens reserved for priming the assistant's reply.
+const REPLY_OVERHEAD: usize = 3;
+/// Headroom for tokenizers we can only approximate.
+const ESTIMATE_MARGIN: f32 = 1.1;
+const DEFAULT_CONTEXT_WINDOW: usize = 8192;
+const DEFAULT_OUTPUT_RESERVE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tokenizer {
+    O200kBase,
+    Cl100kBase,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenCount {
+    pub tokens: usize,
+    pub tokenizer: Tokenizer,
+    /// False when the count is an estimate for a non-OpenAI model.
+    pub exact: bool,
+    pub context_window: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationStrategy {
+    /// Drop the oldest turns first.
+    #[default]
+    DropOldest,
+    /// Keep the first user message for grounding and drop the turns after it.
+    DropMiddle,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FitResult {
+    pub messages: Vec<ChatMessage>,
+    pub tokens: usize,
+    pub dropped: usize,
+    /// Whether the newest message itself had to be shortened.
+    pub truncated: bool,
+    pub context_window: usize,
+}
+
+/// Picks the encoding for a model and whether it is the model's real one.
+fn tokenizer_for(provider: &str, model: &str) -> (Tokenizer, bool) {
+    if provider != "openai" {
+        return (Tokenizer::Cl100kBase, false);
+    }
+    let legacy = model.starts_with("gpt-4-") || model == "gpt-4" || model.starts_with("gpt-3.5");
+    if legacy {
+        (Tokenizer::Cl100kBase, true)
+    } else {
+        (Tokenizer::O200kBase, true)
+    }
+}
+
+fn bpe(tokenizer: Tokenizer) -> &'static CoreBPE {
+    match tokenizer {
+        Tokenizer::O200kBase => tiktoken_rs::o200k_base_singleton(),
+        Tokenizer::Cl100kBase => tiktoken_rs::cl100k_base_singleton(),
+    }
+}
+
+/// Context window, in tokens, for well-known model families.
+pub fn context_window(provider: &str, model: &str) -> usize {
+    let model = model.to_ascii_lowercase();
+    let known: &[(&str, usize)] = match provider {
+        "openai" => &[
+            ("gpt-4.1", 1_047_576),
+            ("gpt-4o", 128_000),
+            ("gpt-4-turbo", 128_000),
+            ("o1", 200_000),
+            ("o3", 200_000),
+            ("o4", 200_000),
+            ("gpt-4", 8_192),
+            ("gpt-3.5", 16_385),
+        ],
+        "anthropic" => &[("claude", 200_000)],
+        "google" => &[("gemini-1.5-pro", 2_097_152), ("gemini", 1_048_576)],
+        "mistral" => &[("codestral", 256_000), ("", 128_000)],
+        _ => &[],
+    };
+    known
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map_or(DEFAULT_CONTEXT_WINDOW, |(_, window)| *window)
+}
+
+struct Counter {
+    bpe: &'static CoreBPE,
+    tokenizer: Tokenizer,
+    exact: bool,
+}
+
+impl Counter {
+    fn new(provider: &str, model: &str) -> Self {
+        let (tokenizer, exact) = tokenizer_for(provider, model);
+        Self {
+            bpe: bpe(tokenizer),
+            tokenizer,
+            exact,
+        }
+    }
+
+    fn text(&self, text: &str) -> usize {
+        let tokens = self.bpe.encode_with_special_tokens(text).len();
+        if self.exact {
+            tokens
+        } else {
+            (tokens as f32 * ESTIMATE_MARGIN).ceil() as usize
+        }
+    }
+
+    fn message(&self, message: &ChatMessage) -> usize {
+        self.text(&message.content) + MESSAGE_OVERHEAD
+    }
+
+    fn messages(&self, messages: &[ChatMessage]) -> usize {
+        messages.iter().map(|m| self.message(m)).sum::<usize>() + REPLY_OVERHEAD
+    }
+}
+
+pub fn count(provider: &str, model: &str, messages: &[ChatMessage]) -> TokenCount {
+    let counter = Counter::new(provider, model);
+    TokenCount {
+        tokens: counter.messages(messages),
+        tokenizer: counter.tokenizer,
+        exact: counter.exact,
+        context_window: context_window(provider, model),
+    }
+}
+
+/// Tokens in a bare piece of text, e.g. a completion with no usage report.
+pub fn count_text(provider: &str, model: &str, text: &str) -> usize {
+    Counter::new(provider, model).text(text)
+}
+
+/// Shortens `text` to roughly `budget` tokens, keeping the beginning.
+fn truncate_to(counter: &Counter, text: &str, budget: usize) -> String {
+    let mut end = text.len();
+    loop {
+        let candidate = &text[..end];
+        let tokens = counter.text(candidate);
+        if tokens <= budget || end == 0 {
+            return candidate.to_string();
+        }
+        // Scale down proportionally, always making progress.
+        let target = (end as f64 * budget as f64 / tokens as f64 * 0.95) as usize;
+        end = target.min(end - 1);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+    }
+}
+
+/// Trims `messages` so they fit in the model's context window, leaving
+/// `reserve` tokens for the reply. System messages and the newest message are
+/// always kept; if those alone overflow, the newest message is shortened.
+pub fn fit(
+    provider: &str,
+    model: &str,
+    messages: Vec<ChatMessage>,
+    reserve: Option<usize>,
+    strategy: TruncationStrategy,
+) -> FitResult {
+    let counter = Counter::new(provider, model);
+    let window = context_window(provider, model);
+    let budget = window.saturating_sub(reserve.unwrap_or(DEFAULT_OUTPUT_RESERVE));
+
+    let original = messages.len();
+    let mut tokens = counter.messages(&messages);
+    let last = original.saturating_sub(1);
+    let anchor = match strategy {
+        TruncationStrategy::DropOldest => None,
+        TruncationStrategy::DropMiddle => messages.iter().position(|m| m.role == Role::User),
+    };
+    // Oldest first, with the anchored first message only as a last resort.
+    let mut candidates: Vec<usize> = (0..last)
+        .filter(|&i| messages[i].role != Role::System && Some(i) != anchor)
+        .collect();
+    candidates.extend(anchor.filter(|&i| i < last));
+
+    let mut keep = vec![true; original];
+    for index in candidates {
+        if tokens <= budget {
+            break;
+        }
+        tokens -= counter.message(&messages[index]);
+        keep[index] = false;
+    }
+    let mut messages: Vec<ChatMessage> = messages
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(message, keep)| keep.then_some(message))
+        .collect();
+
+    let mut truncated = false;
+    if tokens > budget {
+        if let Some(last) = messages.last_mut() {
+            let others = tokens - counter.message(last);
+            let allowed = budget.saturating_sub(others + MESSAGE_OVERHEAD);
+            last.content = truncate_to(&counter, &last.content, allowed);
+            truncated = true;
+        }
+        tokens = counter.messages(&messages);
+    }
+
+    FitResult {
+        dropped: original - messages.len(),
+        messages,
+        tokens,
+        truncated,
+        context_window: window,
+    }
+}
+
+#[tauri::command]
+pub async fn count_tokens(
+    provider: String,
+    model: String,
+    messages: Vec<ChatMessage>,
+) -> TokenCount {
+    count(&provider, &model, &messages)
+}
+
+#[tauri::command]
+pub async fn fit_to_context(
+    provider: String,
+    model: String,
+    messages: Vec<ChatMessage>,
+    reserve: Option<usize>,
+    strategy: Option<TruncationStrategy>,
+) -> FitResult {
+    fit(
+        &provider,
+        &model,
+        messages,
+        reserve,
+        strategy.unwrap_or_default(),
+    )
+}
@@ -0,0 +1,163 @@
+//! Minimal GGUF header reader: just enough metadata to size a model's KV
+//! cache before committing memory to loading it.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+const MAGIC: &[u8; 4] = b"GGUF";
+
+#[derive(Debug, Clone)]
+enum Value {
+    Int(u64),
+    Float(f64),
+    Str(String),
+    /// Booleans and arrays (the tokenizer vocabulary, mostly) aren't needed,
+    /// so they are skipped rather than kept.
+    Other,
+}
+
+/// The metadata keys the estimate needs, resolved for the file's architecture.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    pub architecture: Option<String>,
+    pub name: Option<String>,
+    pub context_length: Option<u64>,
+    pub block_count: Option<u64>,
+    pub embedding_length: Option<u64>,
+    pub head_count: Option<u64>,
+    pub head_count_kv: Option<u64>,
+}
+
+fn invalid(path: &Path, reason: &str) -> Error {
+    Error::Extraction(format!("{}: {reason}", path.display()))
+}
+
+struct Reader<R> {
+    inner: R,
+}
+
+impl<R: Read + Seek> Reader<R> {
+    fn bytes<const N: usize>(&mut self) -> std::io::Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.inner.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn u32(&mut self) -> std::io::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> std::io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn string(&mut self) -> std::io::Result<String> {
+        let len = self.u64()?;
+        let mut buf = Vec::new();
+        (&mut self.inner).take(len).read_to_end(&mut buf)?;
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+
+    fn skip(&mut self, len: u64) -> std::io::Result<()> {
+        self.inner.seek(SeekFrom::Current(len as i64))?;
+        Ok(())
+    }
+
+    /// Byte width of fixed-size value types, `None` for strings and arrays.
+    fn fixed_width(kind: u32) -> Option<u64> {
+        match kind {
+            0 | 1 | 7 => Some(1),
+            2 | 3 => Some(2),
+            4..=6 => Some(4),
+            10..=12 => Some(8),
+            _ => None,
+        }
+    }
+
+    fn value(&mut self, kind: u32) -> std::io::Result<Value> {
+        Ok(match kind {
+            0 => Value::Int(self.bytes::<1>()?[0] as u64),
+            1 => Value::Int(self.bytes::<1>()?[0] as i8 as u64),
+            2 => Value::Int(u16::from_le_bytes(self.bytes()?) as u64),
+            3 => Value::Int(i16::from_le_bytes(self.bytes()?) as u64),
+            4 => Value::Int(self.u32()? as u64),
+            5 => Value::Int(i32::from_le_bytes(self.bytes()?) as u64),
+            6 => Value::Float(f32::from_le_bytes(self.bytes()?) as f64),
+            7 => {
+                self.skip(1)?;
+                Value::Other
+            }
+            8 => Value::Str(self.string()?),
+            9 => {
+                let item = self.u32()?;
+                let len = self.u64()?;
+                match Self::fixed_width(item) {
+                    Some(width) => self.skip(width * len)?,
+                    None => {
+                        for _ in 0..len {
+                            self.value(item)?;
+                        }
+                    }
+                }
+                Value::Other
+            }
+            10 | 11 => Value::Int(self.u64()?),
+            12 => Value::Float(f64::from_le_bytes(self.bytes()?)),
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("unknown GGUF value type {kind}"),
+                ))
+            }
+        })
+    }
+}
+
+/// Reads the key/value header of a GGUF file (version 2 or later).
+pub fn read_metadata(path: &Path) -> Result<Metadata> {
+    let mut reader = Reader {
+        inner: BufReader::new(File::open(path)?),
+    };
+    if &reader.bytes::<4>()? != MAGIC {
+        return Err(invalid(path, "not a GGUF file"));
+    }
+    if reader.u32()? < 2 {
+        return Err(invalid(path, "GGUF version 1 is not supported"));
+    }
+    let _tensor_count = reader.u64()?;
+    let kv_count = reader.u64()?;
+
+    let mut values = HashMap::new();
+    for _ in 0..kv_count {
+        let key = reader.string()?;
+        let kind = reader.u32()?;
+        values.insert(key, reader.value(kind)?);
+    }
+
+    let string = |key: &str| match values.get(key) {
+        Some(Value::Str(s)) => Some(s.clone()),
+        _ => None,
+    };
+    let architecture = string("general.architecture");
+    let int = |suffix: &str| {
+        let key = format!("{}.{suffix}", architecture.as_deref()?);
+        match values.get(&key) {
+            Some(Value::Int(n)) => Some(*n),
+            Some(Value::Float(n)) => Some(*n as u64),
+            _ => None,
+        }
+    };
+    Ok(Metadata {
+        name: string("general.name"),
+        context_length: int("context_length"),
+        block_count: int("block_count"),
+        embedding_length: int("embedding_length"),
+        head_count: int("attention.head_count"),
+        head_count_kv: int("attention.head_count_kv").or_else(|| int("attention.head_count")),
+        architecture,
+    })
+}
@@ -0,0 +1,412 @@
+//! In-process inference over GGUF models through llama.cpp, for machines
+//! without an Ollama server. Built only with the `local-llm` cargo feature,
+//! since it links a native library.
+//!
+//! The loaded model is registered as the `local` provider, so `stream_chat`
+//! and `fanout_prompt` drive generation like any other mind; the request's
+//! model name is ignored in favour of whichever file is loaded.
+
+mod gguf;
+
+use std::fmt::Display;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use async_trait::async_trait;
+use llama_cpp_2::context::params::LlamaContextParams;
+use llama_cpp_2::llama_backend::LlamaBackend;
+use llama_cpp_2::llama_batch::LlamaBatch;
+use llama_cpp_2::model::params::LlamaModelParams;
+use llama_cpp_2::model::{AddBos, LlamaChatMessage, LlamaModel, Special};
+use llama_cpp_2::sampling::LlamaSampler;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::error::{Error, Result};
+use crate::llm::{ChatMessage, ChatRequest, Role, Usage};
+use crate::providers::{Completion, DeltaSink, ModelInfo, Provider, Providers};
+use crate::storage::now_ms;
+use crate::system_stats;
+
+const DEFAULT_CONTEXT_LENGTH: u32 = 4096;
+const DEFAULT_MAX_TOKENS: u32 = 1024;
+const DEFAULT_TEMPERATURE: f32 = 0.8;
+/// llama.cpp's convention for "offload every layer"; ignored on CPU builds.
+const ALL_GPU_LAYERS: u32 = 999;
+/// Bytes per KV cache element, which llama.cpp stores as f16 by default.
+const KV_ELEMENT_BYTES: u64 = 2;
+
+/// llama.cpp's backend is process-global and may only be initialised once.
+static BACKEND: OnceLock<LlamaBackend> = OnceLock::new();
+
+fn llama_error(err: impl Display) -> Error {
+    Error::Provider(format!("llama.cpp: {err}"))
+}
+
+fn backend() -> Result<&'static LlamaBackend> {
+    if let Some(backend) = BACKEND.get() {
+        return Ok(backend);
+    }
+    let backend = LlamaBackend::init().map_err(llama_error)?;
+    Ok(BACKEND.get_or_init(|| backend))
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LoadOptions {
+    /// Layers to offload to the GPU; defaults to all of them.
+    pub gpu_layers: Option<u32>,
+    /// Capped at the length the model was trained with.
+    pub context_length: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LocalModelInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub context_length: u32,
+    pub trained_context_length: u32,
+    pub size_bytes: u64,
+}
+
+/// Whether a model would fit, from `check_local_model_fit`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelFit {
+    pub estimate: MemoryEstimate,
+    pub available_bytes: u64,
+    /// Free memory on the roomiest GPU, if one could be read.
+    pub gpu_available_bytes: Option<u64>,
+    /// Everything fits in GPU memory, so all layers can be offloaded.
+    pub fits_gpu: bool,
+    pub fits: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryEstimate {
+    pub architecture: Option<String>,
+    pub name: Option<String>,
+    /// The weights are memory-mapped, so this is the file size.
+    pub weights_bytes: u64,
+    /// `None` when the file lacks the metadata needed to size the cache.
+    pub kv_cache_bytes: Option<u64>,
+    pub total_bytes: u64,
+    pub context_length: u64,
+    pub trained_context_length: Option<u64>,
+}
+
+struct LoadedModel {
+    info: LocalModelInfo,
+    model: LlamaModel,
+}
+
+/// Holds at most one loaded model. Generations keep their own handle, so
+/// unloading mid-stream frees the memory once that stream finishes.
+#[derive(Default)]
+pub struct LocalLlm {
+    loaded: Mutex<Option<Arc<LoadedModel>>>,
+}
+
+impl LocalLlm {
+    fn current(&self) -> Option<Arc<LoadedModel>> {
+        self.loaded.lock().unwrap().clone()
+    }
+
+    pub fn info(&self) -> Option<LocalModelInfo> {
+        self.current().map(|loaded| loaded.info.clone())
+    }
+
+    pub async fn load(&self, path: PathBuf, options: LoadOptions) -> Result<LocalModelInfo> {
+        let loaded = tauri::async_runtime::spawn_blocking(move || load_model(&path, &options))
+            .await
+            .map_err(llama_error)??;
+        let info = loaded.info.clone();
+        *self.loaded.lock().unwrap() = Some(Arc::new(loaded));
+        Ok(info)
+    }
+
+    pub fn unload(&self) -> bool {
+        self.loaded.lock().unwrap().take().is_some()
+    }
+}
+
+fn load_model(path: &Path, options: &LoadOptions) -> Result<LoadedModel> {
+    let params =
+        LlamaModelParams::default().with_n_gpu_layers(options.gpu_layers.unwrap_or(ALL_GPU_LAYERS));
+    let model = LlamaModel::load_from_file(backend()?, path, &params).map_err(llama_error)?;
+    let trained = model.n_ctx_train();
+    let context_length = options
+        .context_length
+        .unwrap_or(DEFAULT_CONTEXT_LENGTH)
+        .min(trained)
+        .max(1);
+    let name = gguf::read_metadata(path)
+        .ok()
+        .and_then(|metadata| metadata.name)
+        .or_else(|| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default();
+    Ok(LoadedModel {
+        info: LocalModelInfo {
+            name,
+            path: path.to_path_buf(),
+            context_length,
+            trained_context_length: trained,
+            size_bytes: std::fs::metadata(path)?.len(),
+        },
+        model,
+    })
+}
+
+/// Sizes weights plus KV cache from the GGUF header, without loading anything.
+pub fn estimate_memory(path: &Path, context_length: Option<u32>) -> Result<MemoryEstimate> {
+    let metadata = gguf::read_metadata(path)?;
+    let weights_bytes = std::fs::metadata(path)?.len();
+    let context_length = context_length
+        .map(u64::from)
+        .unwrap_or(DEFAULT_CONTEXT_LENGTH as u64);
+    let context_length = metadata
+        .context_length
+        .map_or(context_length, |trained| context_length.min(trained));
+    // Keys and values for every layer and position, over the KV heads only.
+    let kv_cache_bytes = (|| {
+        let per_head = metadata.embedding_length? / metadata.head_count?.max(1);
+        let kv_width = per_head * metadata.head_count_kv?;
+        Some(2 * metadata.block_count? * context_length * kv_width * KV_ELEMENT_BYTES)
+    })();
+    Ok(MemoryEstimate {
+        architecture: metadata.architecture,
+        name: metadata.name,
+        weights_bytes,
+        kv_cache_bytes,
+        total_bytes: weights_bytes + kv_cache_bytes.unwrap_or_default(),
+        context_length,
+        trained_context_length: metadata.context_length,
+    })
+}
+
+fn role_name(role: Role) -> &'static str {
+    match role {
+        Role::System => "system",
+        Role::User => "user",
+        Role::Assistant => "assistant",
+    }
+}
+
+/// Runs on a blocking thread. Each decoded piece goes to `tx`; a closed
+/// channel means the caller was cancelled, which stops generation.
+fn generate(
+    loaded: &LoadedModel,
+    messages: &[ChatMessage],
+    max_tokens: u32,
+    temperature: Option<f32>,
+    tx: &UnboundedSender<String>,
+) -> Result<Usage> {
+    let model = &loaded.model;
+    let template = model.chat_template(None).map_err(llama_error)?;
+    let chat = messages
+        .iter()
+        .map(|m| LlamaChatMessage::new(role_name(m.role).to_string(), m.content.clone()))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(llama_error)?;
+    let prompt = model
+        .apply_chat_template(&template, &chat, true)
+        .map_err(llama_error)?;
+    let tokens = model
+        .str_to_token(&prompt, AddBos::Always)
+        .map_err(llama_error)?;
+
+    let n_ctx = loaded.info.context_length;
+    if tokens.is_empty() || tokens.len() as u32 >= n_ctx {
+        return Err(Error::Provider(format!(
+            "prompt is {} tokens but the local context holds {n_ctx}",
+            tokens.len()
+        )));
+    }
+    let params = LlamaContextParams::default()
+        .with_n_ctx(NonZeroU32::new(n_ctx))
+        .with_n_batch(n_ctx);
+    let mut ctx = model.new_context(backend()?, params).map_err(llama_error)?;
+
+    let mut batch = LlamaBatch::new(n_ctx as usize, 1);
+    let last = tokens.len() - 1;
+    for (i, token) in tokens.iter().enumerate() {
+        batch
+            .add(*token, i as i32, &[0], i == last)
+            .map_err(llama_error)?;
+    }
+    ctx.decode(&mut batch).map_err(llama_error)?;
+
+    let mut sampler = match temperature {
+        Some(t) if t <= 0.0 => LlamaSampler::greedy(),
+        t => LlamaSampler::chain_simple([
+            LlamaSampler::temp(t.unwrap_or(DEFAULT_TEMPERATURE)),
+            LlamaSampler::dist(now_ms() as u32),
+        ]),
+    };
+    let mut position = tokens.len() as i32;
+    let mut completion_tokens = 0;
+    // Tokens can split a UTF-8 character; hold bytes until they decode.
+    let mut pending = Vec::new();
+    while completion_tokens < max_tokens && (position as u32) < n_ctx {
+        let token = sampler.sample(&ctx, batch.n_tokens() - 1);
+        sampler.accept(token);
+        if model.is_eog_token(token) {
+            break;
+        }
+        completion_tokens += 1;
+
+        pending.extend(
+            model
+                .token_to_bytes(token, Special::Tokenize)
+                .map_err(llama_error)?,
+        );
+        let valid = match std::str::from_utf8(&pending) {
+            Ok(text) => text.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            // Genuinely invalid bytes; flush them lossily instead of stalling.
+            Err(_) => pending.len(),
+        };
+        if valid > 0 {
+            let text = String::from_utf8_lossy(&pending[..valid]).into_owned();
+            pending.drain(..valid);
+            if tx.send(text).is_err() {
+                break;
+            }
+        }
+
+        batch.clear();
+        batch
+            .add(token, position, &[0], true)
+            .map_err(llama_error)?;
+        position += 1;
+        ctx.decode(&mut batch).map_err(llama_error)?;
+    }
+
+    Ok(Usage {
+        prompt_tokens: tokens.len() as u32,
+        completion_tokens,
+    })
+}
+
+#[async_trait]
+impl Provider for LocalLlm {
+    fn id(&self) -> &'static str {
+        "local"
+    }
+
+    fn name(&self) -> &'static str {
+        "Local (llama.cpp)"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "loaded"
+    }
+
+    fn configured(&self) -> bool {
+        self.loaded.lock().unwrap().is_some()
+    }
+
+    async fn list_models(&self, _client: &Client) -> Result<Vec<ModelInfo>> {
+        Ok(self
+            .info()
+            .map(|info| ModelInfo {
+                id: info.name.clone(),
+                name: info.name,
+            })
+            .into_iter()
+            .collect())
+    }
+
+    async fn stream(
+        &self,
+        _client: &Client,
+        request: &ChatRequest,
+        on_delta: &mut DeltaSink<'_>,
+    ) -> Result<Completion> {
+        let loaded = self
+            .current()
+            .ok_or_else(|| Error::Provider("no local model is loaded".to_string()))?;
+        let messages = request.messages.clone();
+        let max_tokens = request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS);
+        let temperature = request.temperature;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let worker = tauri::async_runtime::spawn_blocking(move || {
+            generate(&loaded, &messages, max_tokens, temperature, &tx)
+        });
+
+        let mut content = String::new();
+        while let Some(delta) = rx.recv().await {
+            on_delta(&delta);
+            content.push_str(&delta);
+        }
+        let usage = worker.await.map_err(llama_error)??;
+        Ok(Completion {
+            content,
+            usage: Some(usage),
+            tool_calls: Vec::new(),
+        })
+    }
+}
+
+#[tauri::command]
+pub async fn load_local_model(
+    providers: State<'_, Providers>,
+    path: PathBuf,
+    options: Option<LoadOptions>,
+) -> Result<LocalModelInfo> {
+    providers
+        .local()
+        .load(path, options.unwrap_or_default())
+        .await
+}
+
+#[tauri::command]
+pub fn unload_local_model(providers: State<'_, Providers>) -> bool {
+    providers.local().unload()
+}
+
+#[tauri::command]
+pub fn get_local_model(providers: State<'_, Providers>) -> Option<LocalModelInfo> {
+    providers.local().info()
+}
+
+/// Compares a model's estimated footprint with the memory free right now.
+#[tauri::command]
+pub async fn check_local_model_fit(
+    app: AppHandle,
+    path: PathBuf,
+    context_length: Option<u32>,
+) -> Result<ModelFit> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let estimate = estimate_memory(&path, context_length)?;
+        let stats = system_stats::sample(&app)?;
+        let gpu_available_bytes = stats
+            .gpus
+            .iter()
+            .map(|gpu| gpu.total_bytes.saturating_sub(gpu.used_bytes))
+            .max();
+        Ok(ModelFit {
+            fits_gpu: gpu_available_bytes.is_some_and(|free| free >= estimate.total_bytes),
+            fits: stats.memory.available_bytes >= estimate.total_bytes,
+            available_bytes: stats.memory.available_bytes,
+            gpu_available_bytes,
+            estimate,
+        })
+    })
+    .await
+    .map_err(llama_error)?
+}
+
+#[tauri::command]
+pub async fn estimate_local_model_memory(
+    path: PathBuf,
+    context_length: Option<u32>,
+) -> Result<MemoryEstimate> {
+    tauri::async_runtime::spawn_blocking(move || estimate_memory(&path, context_length))
+        .await
+        .map_err(llama_error)?
+}
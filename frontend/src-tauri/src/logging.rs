@@ -0,0 +1,417 @@
+//! Structured logs and crash reports, kept in the app log directory so a
+//! user can attach them to a bug report.
+//!
+//! `tracing` events, and records from dependencies that use `log`, are
+//! written as JSON lines to `pentamind.log`. The file is rotated at
+//! `MAX_FILE_BYTES`, keeping `KEEP_FILES` old ones. A panic writes
+//! `crashes/crash-<time>.txt` with a backtrace before the process goes
+//! down. The level is a setting kept in `logging.json`.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tauri::{AppHandle, Manager};
+use tauri_plugin_opener::OpenerExt;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Subscriber};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::storage::now_ms;
+
+const CONFIG_FILE: &str = "logging.json";
+const LOG_FILE: &str = "pentamind.log";
+const MAX_FILE_BYTES: u64 = 5 * 1024 * 1024;
+/// Rotated files kept besides the current one.
+const KEEP_FILES: usize = 4;
+const DEFAULT_RECENT: usize = 500;
+
+/// Levels from least to most verbose, indexed by `FileLogger::level`.
+const LEVELS: [Level; 5] = [
+    Level::ERROR,
+    Level::WARN,
+    Level::INFO,
+    Level::DEBUG,
+    Level::TRACE,
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct LoggingConfig {
+    level: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".into(),
+        }
+    }
+}
+
+/// One line of the log file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    /// RFC 3339, UTC.
+    pub time: String,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub fields: Map<String, Value>,
+}
+
+/// The log file being written, and the directory it rotates within.
+struct LogFile {
+    dir: PathBuf,
+    file: Option<File>,
+    size: u64,
+}
+
+impl LogFile {
+    fn open(dir: PathBuf) -> Self {
+        let path = dir.join(LOG_FILE);
+        let file = std::fs::create_dir_all(&dir)
+            .and_then(|_| OpenOptions::new().create(true).append(true).open(&path))
+            .ok();
+        let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        Self { dir, file, size }
+    }
+
+    /// Shifts `pentamind.log` to `pentamind.1.log` and so on, dropping the
+    /// oldest, and starts a fresh file.
+    fn rotate(&mut self) {
+        self.file = None;
+        let _ = std::fs::remove_file(rotated(&self.dir, KEEP_FILES));
+        for n in (1..KEEP_FILES).rev() {
+            let _ = std::fs::rename(rotated(&self.dir, n), rotated(&self.dir, n + 1));
+        }
+        let _ = std::fs::rename(self.dir.join(LOG_FILE), rotated(&self.dir, 1));
+        *self = Self::open(std::mem::take(&mut self.dir));
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.size + line.len() as u64 + 1 > MAX_FILE_BYTES {
+            self.rotate();
+        }
+        if let Some(file) = self.file.as_mut() {
+            if writeln!(file, "{line}").is_ok() {
+                self.size += line.len() as u64 + 1;
+            }
+        }
+    }
+}
+
+fn rotated(dir: &Path, n: usize) -> PathBuf {
+    dir.join(format!("pentamind.{n}.log"))
+}
+
+/// The process-wide subscriber.
+struct FileLogger {
+    /// Index into `LEVELS` of the most verbose level written.
+    level: AtomicUsize,
+    file: Mutex<LogFile>,
+    next_span: AtomicU64,
+}
+
+static LOGGER: OnceLock<FileLogger> = OnceLock::new();
+
+impl FileLogger {
+    fn allows(&self, level: &Level) -> bool {
+        LEVELS
+            .iter()
+            .position(|l| l == level)
+            .is_some_and(|i| i <= self.level.load(Ordering::Relaxed))
+    }
+
+    fn write(&self, entry: &LogEntry) {
+        if let Ok(line) = serde_json::to_string(entry) {
+            if let Ok(mut file) = self.file.lock() {
+                file.write_line(&line);
+            }
+        }
+    }
+}
+
+fn timestamp() -> String {
+    chrono::DateTime::from_timestamp_millis(now_ms())
+        .unwrap_or_default()
+        .to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+/// Collects an event's fields, pulling out `message`.
+#[derive(Default)]
+struct Fields {
+    message: String,
+    fields: Map<String, Value>,
+}
+
+impl Fields {
+    fn insert(&mut self, field: &Field, value: Value) {
+        if field.name() == "message" {
+            self.message = match value {
+                Value::String(s) => s,
+                other => other.to_string(),
+            };
+        } else {
+            self.fields.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, Value::String(format!("{value:?}")));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, Value::String(value.to_string()));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.insert(field, Value::String(value.to_string()));
+    }
+}
+
+/// Spans are given ids so instrumented code works, but only events are
+/// written.
+impl Subscriber for &'static FileLogger {
+    fn register_callsite(&self, _metadata: &'static Metadata<'static>) -> Interest {
+        // Decided per event, so `set_log_level` takes effect at once.
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.allows(metadata.level())
+    }
+
+    fn new_span(&self, _span: &Attributes<'_>) -> Id {
+        Id::from_u64(self.next_span.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let metadata = event.metadata();
+        self.write(&LogEntry {
+            time: timestamp(),
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            message: fields.message,
+            fields: fields.fields,
+        });
+    }
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+/// Sends `log` records from dependencies to the same file.
+struct LogBridge;
+
+impl log::Log for LogBridge {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        LOGGER
+            .get()
+            .is_some_and(|logger| logger.allows(&level_from_log(metadata.level())))
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        let Some(logger) = LOGGER.get() else {
+            return;
+        };
+        let level = level_from_log(record.level());
+        if logger.allows(&level) {
+            logger.write(&LogEntry {
+                time: timestamp(),
+                level: level.to_string(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+                fields: Map::new(),
+            });
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn level_from_log(level: log::Level) -> Level {
+    match level {
+        log::Level::Error => Level::ERROR,
+        log::Level::Warn => Level::WARN,
+        log::Level::Info => Level::INFO,
+        log::Level::Debug => Level::DEBUG,
+        log::Level::Trace => Level::TRACE,
+    }
+}
+
+fn parse_level(level: &str) -> Result<usize> {
+    LEVELS
+        .iter()
+        .position(|l| l.as_str().eq_ignore_ascii_case(level))
+        .ok_or_else(|| Error::InvalidSetting(format!("unknown log level {level}")))
+}
+
+fn log_dir(app: &AppHandle) -> Result<PathBuf> {
+    Ok(app.path().app_log_dir()?)
+}
+
+/// Writes a crash report, then lets the default hook print as usual.
+fn install_panic_hook(dir: PathBuf, version: String) {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let message = info.payload_as_str().unwrap_or("(no message)");
+        let location = info
+            .location()
+            .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()))
+            .unwrap_or_default();
+        let thread = std::thread::current();
+        let thread = thread.name().unwrap_or("unnamed");
+        tracing::error!(thread, location, "panic: {message}");
+        let report = format!(
+            "Pentamind {version} crashed\n\
+             time: {}\nos: {} {}\nthread: {thread}\nlocation: {location}\n\
+             message: {message}\n\nbacktrace:\n{}\n",
+            timestamp(),
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+            std::backtrace::Backtrace::force_capture(),
+        );
+        let crashes = dir.join("crashes");
+        let stamp = chrono::DateTime::from_timestamp_millis(now_ms())
+            .unwrap_or_default()
+            .format("%Y%m%d-%H%M%S%.3f");
+        let _ = std::fs::create_dir_all(&crashes)
+            .and_then(|_| std::fs::write(crashes.join(format!("crash-{stamp}.txt")), report));
+        previous(info);
+    }));
+}
+
+/// Starts logging at the saved level and installs the panic hook. Runs
+/// first in setup so the other subsystems' startup is logged.
+pub fn init(app: &AppHandle) {
+    let Ok(dir) = log_dir(app) else {
+        return;
+    };
+    let saved: LoggingConfig = config::read(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    let level = parse_level(&saved.level).unwrap_or(2);
+    let logger = LOGGER.get_or_init(|| FileLogger {
+        level: AtomicUsize::new(level),
+        file: Mutex::new(LogFile::open(dir.clone())),
+        next_span: AtomicU64::new(1),
+    });
+    let _ = tracing::subscriber::set_global_default(logger);
+    if log::set_logger(&LogBridge).is_ok() {
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+    install_panic_hook(dir, app.package_info().version.to_string());
+    tracing::info!(
+        version = %app.package_info().version,
+        os = std::env::consts::OS,
+        "starting"
+    );
+}
+
+/// Reads the last `limit` lines across the current and rotated files,
+/// oldest first, keeping those at `min_level` or more severe.
+fn read_recent(dir: &Path, limit: usize, min_level: usize) -> Vec<LogEntry> {
+    let mut recent = Vec::new();
+    let files =
+        std::iter::once(dir.join(LOG_FILE)).chain((1..=KEEP_FILES).map(|n| rotated(dir, n)));
+    for path in files {
+        let Ok(file) = File::open(&path) else {
+            break;
+        };
+        let mut entries: Vec<LogEntry> = BufReader::new(file)
+            .lines()
+            .map_while(|line| line.ok())
+            .filter_map(|line| serde_json::from_str::<LogEntry>(&line).ok())
+            .filter(|entry| parse_level(&entry.level).is_ok_and(|l| l <= min_level))
+            .collect();
+        let wanted = limit - recent.len();
+        let skip = entries.len().saturating_sub(wanted);
+        entries.drain(..skip);
+        entries.append(&mut recent);
+        recent = entries;
+        if recent.len() >= limit {
+            break;
+        }
+    }
+    recent
+}
+
+/// The most recent log entries, oldest first. `level` keeps only entries
+/// that severe or more (default: everything written).
+#[tauri::command]
+pub async fn get_recent_logs(
+    app: AppHandle,
+    limit: Option<usize>,
+    level: Option<String>,
+) -> Result<Vec<LogEntry>> {
+    let dir = log_dir(&app)?;
+    let min_level = level.as_deref().map(parse_level).transpose()?;
+    let limit = limit.unwrap_or(DEFAULT_RECENT).max(1);
+    let min_level = min_level.unwrap_or(LEVELS.len() - 1);
+    Ok(tauri::async_runtime::spawn_blocking(move || read_recent(&dir, limit, min_level)).await?)
+}
+
+/// Opens the log directory, with the crash reports, in the file manager.
+#[tauri::command]
+pub fn open_log_folder(app: AppHandle) -> Result<()> {
+    let dir = log_dir(&app)?;
+    std::fs::create_dir_all(&dir)?;
+    app.opener()
+        .open_path(dir.to_string_lossy(), None::<&str>)?;
+    Ok(())
+}
+
+/// Sets the most verbose level written (`error`, `warn`, `info`, `debug`
+/// or `trace`) and keeps it for later launches.
+#[tauri::command]
+pub fn set_log_level(app: AppHandle, level: String) -> Result<()> {
+    let index = parse_level(&level)?;
+    if let Some(logger) = LOGGER.get() {
+        logger.level.store(index, Ordering::Relaxed);
+    }
+    config::write(
+        &app,
+        CONFIG_FILE,
+        &LoggingConfig {
+            level: LEVELS[index].as_str().to_ascii_lowercase(),
+        },
+    )?;
+    tracing::info!(level = LEVELS[index].as_str(), "log level changed");
+    Ok(())
+}
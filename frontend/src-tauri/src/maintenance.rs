@@ -0,0 +1,328 @@
+//! Upkeep of the database: integrity checks, compaction and a breakdown of
+//! where the disk space goes. A quick check runs at every launch; a broken
+//! full-text index, the usual casualty of a crash mid-write, is rebuilt
+//! from the messages on the spot, since nothing is lost by doing so. Other
+//! damage is only reported, with a `database-integrity` event, and left to
+//! a restore from backup.
+
+use std::path::Path;
+
+use rusqlite::Connection;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::attachments;
+use crate::error::{Error, Result};
+use crate::profile;
+use crate::startup;
+use crate::storage::{now_ms, Database, DB_FILE};
+use crate::thumbnails;
+
+/// Problems listed beyond this are dropped from the report.
+const MAX_PROBLEMS: usize = 100;
+/// Full-text indexes, all of which can be rebuilt from the table they index.
+const FTS_TABLES: [&str; 1] = ["messages_fts"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IntegrityReport {
+    pub ok: bool,
+    /// What SQLite found, one line per problem.
+    pub problems: Vec<String>,
+    pub foreign_key_violations: usize,
+    /// Full-text indexes that didn't match their table.
+    pub broken_indexes: Vec<String>,
+    /// The broken indexes were rebuilt, and now check out.
+    pub repaired: bool,
+    /// Only the quicker checks were run, as at startup.
+    pub quick: bool,
+    pub checked_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VacuumReport {
+    pub before_bytes: u64,
+    pub after_bytes: u64,
+    pub duration_ms: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TableStats {
+    pub name: String,
+    /// Rows, for ordinary tables.
+    pub rows: Option<u64>,
+    /// Pages of the table and its indexes.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DirectoryStats {
+    pub name: &'static str,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageStats {
+    /// The database file plus its write-ahead log.
+    pub database_bytes: u64,
+    /// Pages free inside the file, which `vacuum_database` would give back.
+    pub free_bytes: u64,
+    /// Largest first.
+    pub tables: Vec<TableStats>,
+    pub directories: Vec<DirectoryStats>,
+}
+
+fn database_bytes(app: &AppHandle) -> Result<u64> {
+    let path = profile::data_dir(app)?.join(DB_FILE);
+    let size = |suffix: &str| {
+        let mut name = path.clone().into_os_string();
+        name.push(suffix);
+        std::fs::metadata(name).map_or(0, |m| m.len())
+    };
+    Ok(size("") + size("-wal"))
+}
+
+fn broken_indexes(conn: &Connection) -> Vec<String> {
+    FTS_TABLES
+        .into_iter()
+        .filter(|table| {
+            // With rank 1 the index is also compared against its table.
+            conn.execute(
+                &format!("INSERT INTO {table} ({table}, rank) VALUES ('integrity-check', 1)"),
+                [],
+            )
+            .is_err()
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+fn rebuild(conn: &Connection, table: &str) -> Result<()> {
+    conn.execute(
+        &format!("INSERT INTO {table} ({table}) VALUES ('rebuild')"),
+        [],
+    )?;
+    Ok(())
+}
+
+impl Database {
+    /// Checks the file, foreign keys and full-text indexes; `quick` skips
+    /// the slow part of SQLite's own check. With `repair`, broken
+    /// full-text indexes are rebuilt.
+    pub fn check_integrity(&self, quick: bool, repair: bool) -> Result<IntegrityReport> {
+        if self.is_locked() {
+            return Err(Error::Locked);
+        }
+        let conn = self.conn()?;
+        let pragma = if quick {
+            "quick_check"
+        } else {
+            "integrity_check"
+        };
+        let mut stmt = conn.prepare(&format!("PRAGMA {pragma}({MAX_PROBLEMS})"))?;
+        let problems: Vec<String> = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|line| line != "ok")
+            .collect();
+        let foreign_key_violations = conn
+            .prepare("PRAGMA foreign_key_check")?
+            .query_map([], |_| Ok(()))?
+            .count();
+        let mut broken = broken_indexes(&conn);
+        let mut repaired = false;
+        if repair && !broken.is_empty() {
+            for table in &broken {
+                rebuild(&conn, table)?;
+            }
+            let still_broken = broken_indexes(&conn);
+            repaired = still_broken.is_empty();
+            if !repaired {
+                broken = still_broken;
+            }
+        }
+        Ok(IntegrityReport {
+            ok: problems.is_empty()
+                && foreign_key_violations == 0
+                && (broken.is_empty() || repaired),
+            problems,
+            foreign_key_violations,
+            broken_indexes: broken,
+            repaired,
+            quick,
+            checked_at: now_ms(),
+        })
+    }
+
+    /// Page usage by table, indexes counted with the table they belong to.
+    fn table_stats(&self) -> Result<(Vec<TableStats>, u64)> {
+        if self.is_locked() {
+            return Err(Error::Locked);
+        }
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT COALESCE(m.tbl_name, d.name), SUM(d.pgsize),
+                    MAX(m.type = 'table' AND m.sql NOT LIKE 'CREATE VIRTUAL%')
+             FROM dbstat d LEFT JOIN sqlite_master m ON m.name = d.name
+             GROUP BY 1 ORDER BY 2 DESC",
+        )?;
+        let sized = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Option<bool>>(2)?.unwrap_or(false),
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut tables = Vec::with_capacity(sized.len());
+        for (name, bytes, ordinary) in sized {
+            let rows = if ordinary {
+                conn.query_row(
+                    &format!("SELECT count(*) FROM \"{}\"", name.replace('"', "\"\"")),
+                    [],
+                    |row| row.get::<_, i64>(0),
+                )
+                .ok()
+                .map(|n| n as u64)
+            } else {
+                None
+            };
+            tables.push(TableStats {
+                name,
+                rows,
+                bytes: bytes as u64,
+            });
+        }
+        let free_pages: i64 = conn.pragma_query_value(None, "freelist_count", |row| row.get(0))?;
+        let page_size: i64 = conn.pragma_query_value(None, "page_size", |row| row.get(0))?;
+        Ok((tables, (free_pages * page_size) as u64))
+    }
+}
+
+/// Files and bytes under `dir`, following no symlinks.
+fn directory_usage(dir: &Path) -> (u64, u64) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return (0, 0);
+    };
+    let (mut files, mut bytes) = (0, 0);
+    for entry in entries.flatten() {
+        let Ok(kind) = entry.file_type() else {
+            continue;
+        };
+        if kind.is_dir() {
+            let (f, b) = directory_usage(&entry.path());
+            files += f;
+            bytes += b;
+        } else if kind.is_file() {
+            files += 1;
+            bytes += entry.metadata().map_or(0, |m| m.len());
+        }
+    }
+    (files, bytes)
+}
+
+/// Runs the quick check once the app is up. Skipped while the database is
+/// locked, since nothing can be read yet.
+pub fn init(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        startup::warmed().await;
+        let _ = tauri::async_runtime::spawn_blocking(move || check_on_startup(&app)).await;
+    });
+}
+
+/// Problems found that couldn't be fixed go out as `database-integrity`.
+fn check_on_startup(app: &AppHandle) {
+    let db = app.state::<Database>();
+    if db.is_locked() {
+        return;
+    }
+    match db.check_integrity(true, true) {
+        Ok(report) if report.ok => {
+            if report.repaired {
+                tracing::warn!(
+                    "rebuilt broken full-text indexes: {}",
+                    report.broken_indexes.join(", ")
+                );
+            }
+        }
+        Ok(report) => {
+            tracing::error!(
+                problems = report.problems.len(),
+                foreign_key_violations = report.foreign_key_violations,
+                "the database failed its integrity check"
+            );
+            let _ = app.emit("database-integrity", &report);
+        }
+        Err(err) => tracing::warn!("couldn't check the database: {err}"),
+    }
+}
+
+/// A full integrity check. With `repair`, broken full-text indexes are
+/// rebuilt from the messages.
+#[tauri::command]
+pub async fn check_database_integrity(
+    app: AppHandle,
+    repair: Option<bool>,
+) -> Result<IntegrityReport> {
+    tauri::async_runtime::spawn_blocking(move || {
+        app.state::<Database>()
+            .check_integrity(false, repair.unwrap_or(false))
+    })
+    .await?
+}
+
+/// Compacts the database file and tidies the full-text index. Everything
+/// else waits while it runs, which on a large history can take a while.
+#[tauri::command]
+pub async fn vacuum_database(app: AppHandle) -> Result<VacuumReport> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let db = app.state::<Database>();
+        if db.is_locked() {
+            return Err(Error::Locked);
+        }
+        let before_bytes = database_bytes(&app)?;
+        let started = now_ms();
+        {
+            let conn = db.conn()?;
+            for table in FTS_TABLES {
+                conn.execute(
+                    &format!("INSERT INTO {table} ({table}) VALUES ('optimize')"),
+                    [],
+                )?;
+            }
+            conn.execute_batch("VACUUM; PRAGMA wal_checkpoint(TRUNCATE);")?;
+        }
+        Ok(VacuumReport {
+            before_bytes,
+            after_bytes: database_bytes(&app)?,
+            duration_ms: now_ms() - started,
+        })
+    })
+    .await?
+}
+
+/// Where the space goes: the database by table, and the files kept beside
+/// it.
+#[tauri::command]
+pub async fn get_storage_stats(app: AppHandle, db: State<'_, Database>) -> Result<StorageStats> {
+    let (tables, free_bytes) = db.table_stats()?;
+    let directories = [
+        ("attachments", attachments::dir(&app)?),
+        ("thumbnails", thumbnails::dir(&app)?),
+    ]
+    .into_iter()
+    .map(|(name, dir)| {
+        let (files, bytes) = directory_usage(&dir);
+        DirectoryStats { name, files, bytes }
+    })
+    .collect();
+    Ok(StorageStats {
+        database_bytes: database_bytes(&app)?,
+        free_bytes,
+        tables,
+        directories,
+    })
+}
@@ -0,0 +1,431 @@
+//! Just enough Markdown handling for what the backend does with responses:
+//! pulling out fenced code blocks, flattening to plain text, and rendering
+//! the common subset to HTML for shared pages.
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CodeBlock {
+    /// The first word of the info string after the opening fence, if any.
+    pub language: Option<String>,
+    /// The whole info string, which sometimes names a file after the
+    /// language.
+    pub info: String,
+    /// The last non-blank line before the fence, often a file name.
+    pub caption: Option<String>,
+    pub code: String,
+}
+
+/// The fence a line opens or closes with: its character and length.
+pub(crate) fn fence(line: &str) -> Option<(char, usize)> {
+    let trimmed = line.trim_start();
+    let ch = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = trimmed.chars().take_while(|c| *c == ch).count();
+    (len >= 3).then_some((ch, len))
+}
+
+/// Fenced code blocks in document order. An unclosed fence runs to the end,
+/// as it renders.
+pub fn code_blocks(markdown: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut open: Option<((char, usize), CodeBlock)> = None;
+    let mut caption: Option<&str> = None;
+    for line in markdown.lines() {
+        let marker = fence(line);
+        if let Some(((ch, len), block)) = &mut open {
+            let closes = marker
+                .is_some_and(|(c, l)| c == *ch && l >= *len && line.trim().chars().all(|x| x == c));
+            if !closes {
+                block.code.push_str(line);
+                block.code.push('\n');
+                continue;
+            }
+            blocks.extend(open.take().map(|(_, block)| block));
+            caption = None;
+        } else if let Some(opening) = marker {
+            let info = line.trim_start().trim_start_matches(opening.0).trim();
+            open = Some((
+                opening,
+                CodeBlock {
+                    language: info.split_whitespace().next().map(str::to_string),
+                    info: info.to_string(),
+                    caption: caption.take().map(str::to_string),
+                    code: String::new(),
+                },
+            ));
+        } else if !line.trim().is_empty() {
+            caption = Some(line.trim());
+        }
+    }
+    blocks.extend(open.map(|(_, block)| block));
+    for block in &mut blocks {
+        block.code.truncate(block.code.trim_end().len());
+    }
+    blocks
+}
+
+/// `[text](url)` and `![alt](url)` become their text.
+fn strip_links(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(open) = rest.find('[') {
+        let Some(close) = rest[open..].find("](").map(|i| open + i) else {
+            break;
+        };
+        let Some(end) = rest[close..].find(')').map(|i| close + i) else {
+            break;
+        };
+        out.push_str(rest[..open].strip_suffix('!').unwrap_or(&rest[..open]));
+        out.push_str(&rest[open + 1..close]);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Markdown reduced to readable plain text: fences, heading and quote markers,
+/// emphasis and link syntax are dropped; code keeps its content.
+pub fn plain_text(markdown: &str) -> String {
+    flatten(markdown, true)
+}
+
+/// Like [`plain_text`] but without code blocks, for reading aloud.
+pub fn prose(markdown: &str) -> String {
+    flatten(markdown, false)
+}
+
+fn flatten(markdown: &str, keep_code: bool) -> String {
+    let mut lines = Vec::new();
+    let mut in_code: Option<(char, usize)> = None;
+    for line in markdown.lines() {
+        if let Some((ch, len)) = fence(line) {
+            match in_code {
+                None => {
+                    in_code = Some((ch, len));
+                    continue;
+                }
+                Some((open, open_len)) if ch == open && len >= open_len => {
+                    in_code = None;
+                    continue;
+                }
+                Some(_) => {}
+            }
+        }
+        if in_code.is_some() {
+            if keep_code {
+                lines.push(line.to_string());
+            }
+            continue;
+        }
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let mut text = line.trim_start();
+        while let Some(rest) = text.strip_prefix('>') {
+            text = rest.trim_start();
+        }
+        let heading = text.trim_start_matches('#');
+        if heading.len() < text.len() && (heading.is_empty() || heading.starts_with(' ')) {
+            text = heading.trim_start();
+        }
+        let text = ["**", "__", "~~", "`"]
+            .iter()
+            .fold(strip_links(text), |text, marker| text.replace(marker, ""));
+        lines.push(format!("{indent}{text}"));
+    }
+    lines.join("\n").trim().to_string()
+}
+
+/// Escapes text for HTML content and attribute values.
+pub fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+/// How [`to_html`] renders what it can't decide on its own.
+pub trait HtmlHooks {
+    /// A fenced code block, already inside `<pre>`.
+    fn code(&self, language: Option<&str>, code: &str) -> String {
+        let _ = language;
+        escape_html(code)
+    }
+
+    /// The `src` for an image written as `url`; `None` leaves only its alt
+    /// text.
+    fn image_src(&self, url: &str) -> Option<String> {
+        safe_url(url).then(|| url.to_string())
+    }
+}
+
+/// Links that can't run script when clicked.
+fn safe_url(url: &str) -> bool {
+    let lower = url.trim().to_ascii_lowercase();
+    ["http://", "https://", "mailto:", "#"]
+        .iter()
+        .any(|scheme| lower.starts_with(scheme))
+}
+
+/// `[text](url)` at the start of `text`: its text, url and length.
+fn link_at(text: &str) -> Option<(&str, &str, usize)> {
+    let rest = text.strip_prefix('[')?;
+    let close = rest.find("](")?;
+    let end = rest[close + 2..].find(')')? + close + 2;
+    let url = rest[close + 2..end].split_whitespace().next().unwrap_or("");
+    Some((&rest[..close], url, end + 2))
+}
+
+/// Where a run delimited by `marker` closes in `text`, if it does.
+fn closing(text: &str, marker: &str) -> Option<usize> {
+    let found = text.find(marker)?;
+    (found > 0 && !text[..found].ends_with(' ')).then_some(found)
+}
+
+/// Inline Markdown to HTML: code spans, emphasis, strikethrough, links
+/// and images. Anything else is escaped.
+fn inline_html(text: &str, hooks: &dyn HtmlHooks) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut previous: Option<char> = None;
+    while let Some(ch) = rest.chars().next() {
+        if ch == '`' {
+            if let Some(end) = rest[1..].find('`') {
+                out.push_str(&format!("<code>{}</code>", escape_html(&rest[1..=end])));
+                rest = &rest[end + 2..];
+                previous = Some('`');
+                continue;
+            }
+        }
+        if ch == '!' && rest[1..].starts_with('[') {
+            if let Some((alt, url, len)) = link_at(&rest[1..]) {
+                match hooks.image_src(url) {
+                    Some(src) => out.push_str(&format!(
+                        "<img src=\"{}\" alt=\"{}\">",
+                        escape_html(&src),
+                        escape_html(alt)
+                    )),
+                    None => out.push_str(&escape_html(alt)),
+                }
+                rest = &rest[len + 1..];
+                previous = Some(')');
+                continue;
+            }
+        }
+        if ch == '[' {
+            if let Some((label, url, len)) = link_at(rest) {
+                let label = inline_html(label, hooks);
+                if safe_url(url) {
+                    out.push_str(&format!("<a href=\"{}\">{label}</a>", escape_html(url)));
+                } else {
+                    out.push_str(&label);
+                }
+                rest = &rest[len..];
+                previous = Some(')');
+                continue;
+            }
+        }
+        let pair = [("**", "strong"), ("__", "strong"), ("~~", "del")]
+            .into_iter()
+            .find(|(marker, _)| rest.starts_with(marker));
+        if let Some((marker, tag)) = pair {
+            if let Some(end) = closing(&rest[2..], marker) {
+                let inner = inline_html(&rest[2..2 + end], hooks);
+                out.push_str(&format!("<{tag}>{inner}</{tag}>"));
+                rest = &rest[4 + end..];
+                previous = Some(ch);
+                continue;
+            }
+        }
+        // `_` only counts between words, so snake_case stays as it is.
+        let emphasis = ch == '*' || (ch == '_' && !previous.is_some_and(char::is_alphanumeric));
+        if emphasis && !rest[1..].starts_with(' ') {
+            if let Some(end) = closing(&rest[1..], &ch.to_string()) {
+                let after = rest[1 + end + 1..].chars().next();
+                if ch == '*' || !after.is_some_and(char::is_alphanumeric) {
+                    let inner = inline_html(&rest[1..1 + end], hooks);
+                    out.push_str(&format!("<em>{inner}</em>"));
+                    rest = &rest[end + 2..];
+                    previous = Some(ch);
+                    continue;
+                }
+            }
+        }
+        out.push_str(&escape_html(&ch.to_string()));
+        rest = &rest[ch.len_utf8()..];
+        previous = Some(ch);
+    }
+    out
+}
+
+/// The item text if `line` is a list item, and whether it is numbered.
+fn list_item(line: &str) -> Option<(bool, &str)> {
+    let trimmed = line.trim_start();
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(item) = trimmed.strip_prefix(bullet) {
+            return Some((false, item));
+        }
+    }
+    let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+    let rest = &trimmed[digits..];
+    (digits > 0)
+        .then(|| rest.strip_prefix(". ").or_else(|| rest.strip_prefix(") ")))
+        .flatten()
+        .map(|item| (true, item))
+}
+
+fn is_rule(line: &str) -> bool {
+    let compact: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    compact.len() >= 3
+        && ['-', '*', '_']
+            .iter()
+            .any(|&c| compact.chars().all(|x| x == c))
+}
+
+fn table_cells(line: &str) -> Vec<&str> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').unwrap_or(line);
+    line.split('|').map(str::trim).collect()
+}
+
+fn is_table_separator(line: &str) -> bool {
+    line.contains('-')
+        && table_cells(line)
+            .iter()
+            .all(|cell| !cell.is_empty() && cell.chars().all(|c| matches!(c, '-' | ':')))
+}
+
+/// Markdown to an HTML fragment: headings, paragraphs, lists, quotes,
+/// rules, tables and fenced code, with the inline syntax inside them. Raw
+/// HTML is escaped rather than passed through.
+pub fn to_html(markdown: &str, hooks: &dyn HtmlHooks) -> String {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut out = String::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let flush = |paragraph: &mut Vec<&str>, out: &mut String| {
+        if !paragraph.is_empty() {
+            let text = paragraph
+                .iter()
+                .map(|l| l.trim())
+                .collect::<Vec<_>>()
+                .join("\n");
+            out.push_str(&format!("<p>{}</p>\n", inline_html(&text, hooks)));
+            paragraph.clear();
+        }
+    };
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim();
+        if let Some((ch, len)) = fence(line) {
+            flush(&mut paragraph, &mut out);
+            let info = trimmed.trim_start_matches(ch).trim();
+            let language = info.split_whitespace().next();
+            let mut code = Vec::new();
+            i += 1;
+            while i < lines.len() {
+                let closes = fence(lines[i]).is_some_and(|(c, l)| {
+                    c == ch && l >= len && lines[i].trim().chars().all(|x| x == c)
+                });
+                if closes {
+                    break;
+                }
+                code.push(lines[i]);
+                i += 1;
+            }
+            let class = language
+                .map(|l| format!(" class=\"language-{}\"", escape_html(l)))
+                .unwrap_or_default();
+            out.push_str(&format!(
+                "<pre><code{class}>{}</code></pre>\n",
+                hooks.code(language, &code.join("\n"))
+            ));
+            i += 1;
+            continue;
+        }
+        if trimmed.is_empty() {
+            flush(&mut paragraph, &mut out);
+            i += 1;
+            continue;
+        }
+        let level = trimmed.chars().take_while(|c| *c == '#').count();
+        if (1..=6).contains(&level) && trimmed[level..].starts_with(' ') {
+            flush(&mut paragraph, &mut out);
+            let text = trimmed[level..].trim().trim_end_matches('#').trim_end();
+            out.push_str(&format!(
+                "<h{level}>{}</h{level}>\n",
+                inline_html(text, hooks)
+            ));
+            i += 1;
+            continue;
+        }
+        if is_rule(trimmed) && paragraph.is_empty() {
+            out.push_str("<hr>\n");
+            i += 1;
+            continue;
+        }
+        if trimmed.starts_with('>') {
+            flush(&mut paragraph, &mut out);
+            let mut quoted = Vec::new();
+            while i < lines.len() && lines[i].trim_start().starts_with('>') {
+                let inner = lines[i].trim_start()[1..].strip_prefix(' ');
+                quoted.push(inner.unwrap_or(&lines[i].trim_start()[1..]));
+                i += 1;
+            }
+            out.push_str(&format!(
+                "<blockquote>\n{}</blockquote>\n",
+                to_html(&quoted.join("\n"), hooks)
+            ));
+            continue;
+        }
+        if let Some((ordered, _)) = list_item(line) {
+            flush(&mut paragraph, &mut out);
+            let tag = if ordered { "ol" } else { "ul" };
+            out.push_str(&format!("<{tag}>\n"));
+            while let Some((same, item)) = lines.get(i).and_then(|l| list_item(l)) {
+                if same != ordered {
+                    break;
+                }
+                out.push_str(&format!("<li>{}</li>\n", inline_html(item.trim(), hooks)));
+                i += 1;
+            }
+            out.push_str(&format!("</{tag}>\n"));
+            continue;
+        }
+        let table = trimmed.contains('|')
+            && paragraph.is_empty()
+            && lines
+                .get(i + 1)
+                .is_some_and(|next| is_table_separator(next));
+        if table {
+            let header = table_cells(trimmed);
+            out.push_str("<table>\n<thead><tr>");
+            for cell in &header {
+                out.push_str(&format!("<th>{}</th>", inline_html(cell, hooks)));
+            }
+            out.push_str("</tr></thead>\n<tbody>\n");
+            i += 2;
+            while i < lines.len() && lines[i].contains('|') && !lines[i].trim().is_empty() {
+                out.push_str("<tr>");
+                for cell in table_cells(lines[i]) {
+                    out.push_str(&format!("<td>{}</td>", inline_html(cell, hooks)));
+                }
+                out.push_str("</tr>\n");
+                i += 1;
+            }
+            out.push_str("</tbody>\n</table>\n");
+            continue;
+        }
+        paragraph.push(line);
+        i += 1;
+    }
+    flush(&mut paragraph, &mut out);
+    out
+}
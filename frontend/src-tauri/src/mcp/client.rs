@@ -0,0 +1,513 @@
+//! JSON-RPC sessions with MCP servers, over a child process's stdio (one
+//! message per line) or the HTTP + server-sent events transport, where
+//! replies arrive on an event stream and requests are POSTed to an endpoint
+//! the server announces when the stream opens.
+
+use std::collections::{BTreeMap, HashMap};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT};
+use reqwest::{Client, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::async_runtime::JoinHandle;
+use tauri::Url;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout};
+use tokio::sync::oneshot;
+
+use crate::error::{Error, Result};
+use crate::providers::sse::SseDecoder;
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Tools can do real work (searches, builds), so calls get longer.
+const TOOL_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(rename(deserialize = "inputSchema"), default = "empty_schema")]
+    pub input_schema: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Resource {
+    pub uri: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(rename(deserialize = "mimeType"), default)]
+    pub mime_type: Option<String>,
+}
+
+/// A resource's contents: `text`, or `blob` as base64 for binary data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceContents {
+    pub uri: String,
+    #[serde(rename(deserialize = "mimeType"), default)]
+    pub mime_type: Option<String>,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub blob: Option<String>,
+}
+
+/// A tool's result flattened to text, which is all models take back.
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolOutput {
+    pub content: String,
+    pub is_error: bool,
+}
+
+fn empty_schema() -> Value {
+    json!({ "type": "object", "properties": {} })
+}
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Value>>>>>;
+
+/// Sends messages to the server. For SSE it only exists once the server
+/// has said where to POST.
+enum Writer {
+    Stdio(tokio::sync::Mutex<ChildStdin>),
+    Http {
+        client: Client,
+        endpoint: Url,
+        headers: HeaderMap,
+    },
+}
+
+type WriterSlot = Arc<OnceLock<Writer>>;
+
+impl Writer {
+    async fn send(&self, message: &Value) -> Result<()> {
+        match self {
+            Writer::Stdio(stdin) => {
+                let mut line = serde_json::to_vec(message)?;
+                line.push(b'\n');
+                let mut stdin = stdin.lock().await;
+                stdin.write_all(&line).await?;
+                stdin.flush().await?;
+            }
+            Writer::Http {
+                client,
+                endpoint,
+                headers,
+            } => {
+                let response = client
+                    .post(endpoint.clone())
+                    .headers(headers.clone())
+                    .json(message)
+                    .send()
+                    .await?;
+                if !response.status().is_success() {
+                    return Err(Error::Mcp(format!(
+                        "the server returned {}",
+                        response.status()
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Routes one incoming message: replies complete a pending request, and
+/// requests from the server get an answer (only `ping` is supported).
+async fn dispatch(message: Value, pending: &Pending, writer: Option<&Writer>) {
+    let id = message.get("id").cloned();
+    if message.get("method").is_some() {
+        let (Some(id), Some(writer)) = (id, writer) else {
+            // A notification; nothing needs them yet.
+            return;
+        };
+        let reply = if message["method"] == "ping" {
+            json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+        } else {
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": -32601, "message": "method not supported" },
+            })
+        };
+        let _ = writer.send(&reply).await;
+        return;
+    }
+    let Some(sender) = id
+        .and_then(|id| id.as_u64())
+        .and_then(|id| pending.lock().unwrap().remove(&id))
+    else {
+        return;
+    };
+    let result = match message.get("error") {
+        Some(error) => Err(Error::Mcp(
+            error["message"]
+                .as_str()
+                .unwrap_or("request failed")
+                .to_string(),
+        )),
+        None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+    };
+    let _ = sender.send(result);
+}
+
+/// Marks the session closed and fails every request still waiting.
+fn close(pending: &Pending, alive: &AtomicBool) {
+    alive.store(false, Ordering::Relaxed);
+    for (_, sender) in pending.lock().unwrap().drain() {
+        let _ = sender.send(Err(Error::Mcp("the server disconnected".into())));
+    }
+}
+
+async fn read_stdio(
+    stdout: ChildStdout,
+    pending: Pending,
+    writer: WriterSlot,
+    alive: Arc<AtomicBool>,
+) {
+    let mut lines = BufReader::new(stdout).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        // Servers sometimes log to stdout; anything that isn't JSON is noise.
+        if let Ok(message) = serde_json::from_str::<Value>(&line) {
+            dispatch(message, &pending, writer.get()).await;
+        }
+    }
+    close(&pending, &alive);
+}
+
+/// Where and how an SSE server's messages are POSTed.
+struct HttpTarget {
+    client: Client,
+    /// The event stream's URL, which the announced endpoint is relative to.
+    base: Url,
+    headers: HeaderMap,
+}
+
+async fn read_events(
+    response: Response,
+    target: HttpTarget,
+    pending: Pending,
+    writer: WriterSlot,
+    alive: Arc<AtomicBool>,
+    endpoint_ready: oneshot::Sender<Result<()>>,
+) {
+    let mut endpoint_ready = Some(endpoint_ready);
+    let mut decoder = SseDecoder::default();
+    let mut chunks = response.bytes_stream();
+    while let Some(Ok(chunk)) = chunks.next().await {
+        for event in decoder.push(&chunk) {
+            match event.event.as_deref() {
+                Some("endpoint") => {
+                    let result = target
+                        .base
+                        .join(event.data.trim())
+                        .map_err(|e| Error::Mcp(format!("bad endpoint {}: {e}", event.data)))
+                        .map(|endpoint| {
+                            let _ = writer.set(Writer::Http {
+                                client: target.client.clone(),
+                                endpoint,
+                                headers: target.headers.clone(),
+                            });
+                        });
+                    if let Some(ready) = endpoint_ready.take() {
+                        let _ = ready.send(result);
+                    }
+                }
+                Some("message") | None => {
+                    if let Ok(message) = serde_json::from_str::<Value>(&event.data) {
+                        dispatch(message, &pending, writer.get()).await;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    close(&pending, &alive);
+}
+
+fn header_map(headers: &BTreeMap<String, String>) -> Result<HeaderMap> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let invalid = || Error::Mcp(format!("invalid header {name}"));
+            Ok((
+                HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?,
+                HeaderValue::from_str(value).map_err(|_| invalid())?,
+            ))
+        })
+        .collect()
+}
+
+/// Text of a `tools/call` result. Non-text content is noted rather than
+/// dropped, so the model knows it was there.
+fn tool_text(result: &Value) -> String {
+    let mut parts = Vec::new();
+    for item in result["content"].as_array().into_iter().flatten() {
+        match item["type"].as_str() {
+            Some("text") => parts.push(item["text"].as_str().unwrap_or_default().to_string()),
+            Some("resource") => {
+                let resource = &item["resource"];
+                parts.push(match resource["text"].as_str() {
+                    Some(text) => text.to_string(),
+                    None => format!("[resource {}]", resource["uri"].as_str().unwrap_or("")),
+                });
+            }
+            Some(kind) => parts.push(format!(
+                "[{kind} {}]",
+                item["mimeType"].as_str().unwrap_or_default()
+            )),
+            None => {}
+        }
+    }
+    if parts.is_empty() {
+        if let Some(structured) = result.get("structuredContent") {
+            return structured.to_string();
+        }
+    }
+    parts.join("\n")
+}
+
+/// An initialized connection to one server. Dropping it stops the reader
+/// and, for stdio servers, kills the process.
+pub struct Session {
+    writer: WriterSlot,
+    pending: Pending,
+    next_id: AtomicU64,
+    alive: Arc<AtomicBool>,
+    reader: JoinHandle<()>,
+    _child: Option<Child>,
+    capabilities: Value,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl Session {
+    pub async fn stdio(
+        command: &str,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<Self> {
+        let mut child = tokio::process::Command::from(crate::process::command(command))
+            .args(args)
+            .envs(env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| Error::Mcp(format!("couldn't start {command}: {e}")))?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| Error::Mcp("no stdin".into()))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| Error::Mcp("no stdout".into()))?;
+
+        let writer: WriterSlot = Arc::default();
+        let _ = writer.set(Writer::Stdio(tokio::sync::Mutex::new(stdin)));
+        let pending = Pending::default();
+        let alive = Arc::new(AtomicBool::new(true));
+        let reader = tauri::async_runtime::spawn(read_stdio(
+            stdout,
+            pending.clone(),
+            writer.clone(),
+            alive.clone(),
+        ));
+        Self::initialize(Self {
+            writer,
+            pending,
+            next_id: AtomicU64::new(1),
+            alive,
+            reader,
+            _child: Some(child),
+            capabilities: Value::Null,
+        })
+        .await
+    }
+
+    pub async fn sse(
+        client: &Client,
+        url: &str,
+        headers: &BTreeMap<String, String>,
+    ) -> Result<Self> {
+        let base = Url::parse(url).map_err(|e| Error::Mcp(format!("invalid URL {url}: {e}")))?;
+        let headers = header_map(headers)?;
+        let response = client
+            .get(base.clone())
+            .headers(headers.clone())
+            .header(ACCEPT, "text/event-stream")
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(Error::Mcp(format!(
+                "the server returned {}",
+                response.status()
+            )));
+        }
+
+        let writer: WriterSlot = Arc::default();
+        let pending = Pending::default();
+        let alive = Arc::new(AtomicBool::new(true));
+        let (ready, endpoint) = oneshot::channel();
+        let target = HttpTarget {
+            client: client.clone(),
+            base,
+            headers,
+        };
+        let reader = tauri::async_runtime::spawn(read_events(
+            response,
+            target,
+            pending.clone(),
+            writer.clone(),
+            alive.clone(),
+            ready,
+        ));
+        let session = Self {
+            writer,
+            pending,
+            next_id: AtomicU64::new(1),
+            alive,
+            reader,
+            _child: None,
+            capabilities: Value::Null,
+        };
+        match tokio::time::timeout(REQUEST_TIMEOUT, endpoint).await {
+            Ok(Ok(result)) => result?,
+            Ok(Err(_)) => return Err(Error::Mcp("the event stream closed".into())),
+            Err(_) => return Err(Error::Mcp("the server sent no endpoint".into())),
+        }
+        Self::initialize(session).await
+    }
+
+    async fn initialize(mut self) -> Result<Self> {
+        let result = self
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": { "name": "pentamind", "version": env!("CARGO_PKG_VERSION") },
+                }),
+                REQUEST_TIMEOUT,
+            )
+            .await?;
+        self.capabilities = result["capabilities"].clone();
+        self.send(&json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }))
+            .await?;
+        Ok(self)
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::Relaxed)
+    }
+
+    async fn send(&self, message: &Value) -> Result<()> {
+        let writer = self
+            .writer
+            .get()
+            .ok_or_else(|| Error::Mcp("not connected".into()))?;
+        writer.send(message).await
+    }
+
+    async fn request(&self, method: &str, params: Value, timeout: Duration) -> Result<Value> {
+        if !self.is_alive() {
+            return Err(Error::Mcp("the server disconnected".into()));
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, reply) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, sender);
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        if let Err(err) = self.send(&message).await {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(err);
+        }
+        match tokio::time::timeout(timeout, reply).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(Error::Mcp("the server disconnected".into())),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                let _ = self
+                    .send(&json!({
+                        "jsonrpc": "2.0",
+                        "method": "notifications/cancelled",
+                        "params": { "requestId": id, "reason": "timed out" },
+                    }))
+                    .await;
+                Err(Error::Mcp(format!("{method} timed out")))
+            }
+        }
+    }
+
+    /// Collects every page of a `*/list` method.
+    async fn list<T: serde::de::DeserializeOwned>(
+        &self,
+        method: &str,
+        key: &str,
+    ) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let mut page = self.request(method, params, REQUEST_TIMEOUT).await?;
+            let batch: Vec<T> = serde_json::from_value(page[key].take())?;
+            items.extend(batch);
+            cursor = page["nextCursor"].as_str().map(str::to_string);
+            if cursor.is_none() {
+                return Ok(items);
+            }
+        }
+    }
+
+    pub async fn list_tools(&self) -> Result<Vec<RemoteTool>> {
+        if self.capabilities.get("tools").is_none() {
+            return Ok(Vec::new());
+        }
+        self.list("tools/list", "tools").await
+    }
+
+    pub async fn list_resources(&self) -> Result<Vec<Resource>> {
+        if self.capabilities.get("resources").is_none() {
+            return Ok(Vec::new());
+        }
+        self.list("resources/list", "resources").await
+    }
+
+    pub async fn read_resource(&self, uri: &str) -> Result<Vec<ResourceContents>> {
+        let mut result = self
+            .request("resources/read", json!({ "uri": uri }), REQUEST_TIMEOUT)
+            .await?;
+        Ok(serde_json::from_value(result["contents"].take())?)
+    }
+
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<ToolOutput> {
+        if !arguments.is_object() {
+            return Err(Error::Mcp("tool arguments must be a JSON object".into()));
+        }
+        let result = self
+            .request(
+                "tools/call",
+                json!({ "name": name, "arguments": arguments }),
+                TOOL_TIMEOUT,
+            )
+            .await?;
+        Ok(ToolOutput {
+            content: tool_text(&result),
+            is_error: result["isError"].as_bool().unwrap_or(false),
+        })
+    }
+}
@@ -0,0 +1,391 @@
+//! Model Context Protocol client. Configured servers are launched (stdio)
+//! or connected to (SSE) when enabled, and their tools are offered to
+//! whichever model a chat goes to when it asks for tools, through the
+//! `tools` registry, which asks the user before every call a model makes.
+//!
+//! Server definitions, including any header values such as bearer tokens,
+//! are kept in `mcp.json` in the config directory.
+
+mod client;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use client::{Resource, ResourceContents, Session, ToolOutput};
+
+const CONFIG_FILE: &str = "mcp.json";
+/// Joins server id and tool name in the names models see, since tools on
+/// different servers may share a name.
+const NAME_SEPARATOR: &str = "__";
+/// The longest function name the provider APIs accept.
+const MAX_TOOL_NAME: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum McpTransport {
+    Stdio {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+    },
+    Sse {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct McpServerConfig {
+    /// Letters, digits, `-` and `_`; it prefixes the server's tool names.
+    pub id: String,
+    pub name: Option<String>,
+    pub transport: McpTransport,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct McpConfig {
+    servers: Vec<McpServerConfig>,
+}
+
+/// Payload of the `mcp-server-changed` event and what `list_mcp_servers`
+/// returns.
+#[derive(Debug, Clone, Serialize)]
+pub struct McpServerStatus {
+    #[serde(flatten)]
+    pub config: McpServerConfig,
+    pub connected: bool,
+    pub tools: usize,
+    /// Why the last connection attempt failed.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct McpTool {
+    pub server: String,
+    pub name: String,
+    /// The name models call it by.
+    pub qualified_name: String,
+    pub description: Option<String>,
+    pub input_schema: Value,
+}
+
+struct Connection {
+    session: Session,
+    tools: Vec<McpTool>,
+}
+
+/// Configured servers and live connections. Managed as Tauri state.
+#[derive(Default)]
+pub struct Mcp {
+    config: Mutex<McpConfig>,
+    connections: Mutex<HashMap<String, Arc<Connection>>>,
+    errors: Mutex<HashMap<String, String>>,
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn qualified_name(server: &str, tool: &str) -> String {
+    let mut name = format!("{server}{NAME_SEPARATOR}{}", sanitize(tool));
+    name.truncate(MAX_TOOL_NAME);
+    name
+}
+
+impl Mcp {
+    fn server(&self, id: &str) -> Result<McpServerConfig> {
+        self.config
+            .lock()
+            .unwrap()
+            .servers
+            .iter()
+            .find(|s| s.id == id)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("MCP server {id}")))
+    }
+
+    fn connection(&self, id: &str) -> Result<Arc<Connection>> {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|c| c.session.is_alive())
+            .cloned()
+            .ok_or_else(|| Error::Mcp(format!("{id} is not connected")))
+    }
+
+    fn status(&self, config: McpServerConfig) -> McpServerStatus {
+        let connection = self.connections.lock().unwrap().get(&config.id).cloned();
+        let alive = connection.as_ref().filter(|c| c.session.is_alive());
+        McpServerStatus {
+            connected: alive.is_some(),
+            tools: alive.map_or(0, |c| c.tools.len()),
+            error: self.errors.lock().unwrap().get(&config.id).cloned(),
+            config,
+        }
+    }
+
+    fn save(&self, app: &AppHandle) -> Result<()> {
+        config::write(app, CONFIG_FILE, &*self.config.lock().unwrap())
+    }
+
+    /// The tool a model-facing name refers to.
+    fn resolve(&self, qualified: &str) -> Option<(Arc<Connection>, McpTool)> {
+        self.connections
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|c| c.session.is_alive())
+            .find_map(|connection| {
+                let tool = connection
+                    .tools
+                    .iter()
+                    .find(|t| t.qualified_name == qualified)?;
+                Some((connection.clone(), tool.clone()))
+            })
+    }
+}
+
+async fn open(app: &AppHandle, server: &McpServerConfig) -> Result<Connection> {
+    let session = match &server.transport {
+        McpTransport::Stdio { command, args, env } => Session::stdio(command, args, env).await?,
+        McpTransport::Sse { url, headers } => {
+            Session::sse(&app.state::<Client>(), url, headers).await?
+        }
+    };
+    let tools = session
+        .list_tools()
+        .await?
+        .into_iter()
+        .map(|tool| McpTool {
+            server: server.id.clone(),
+            qualified_name: qualified_name(&server.id, &tool.name),
+            name: tool.name,
+            description: tool.description,
+            // Providers reject function parameters that aren't an object schema.
+            input_schema: if tool.input_schema.is_object() {
+                tool.input_schema
+            } else {
+                json!({ "type": "object", "properties": {} })
+            },
+        })
+        .collect();
+    Ok(Connection { session, tools })
+}
+
+/// Connects to `server`, replacing any earlier connection, and reports the
+/// outcome as `mcp-server-changed`.
+async fn connect(app: &AppHandle, server: McpServerConfig) {
+    let mcp = app.state::<Mcp>();
+    mcp.connections.lock().unwrap().remove(&server.id);
+    match open(app, &server).await {
+        // Disabled or removed while connecting.
+        Ok(_) if !mcp.server(&server.id).is_ok_and(|s| s.enabled) => return,
+        Ok(connection) => {
+            mcp.errors.lock().unwrap().remove(&server.id);
+            mcp.connections
+                .lock()
+                .unwrap()
+                .insert(server.id.clone(), Arc::new(connection));
+        }
+        Err(err) => {
+            tracing::warn!(server = %server.id, "MCP server failed to connect: {err}");
+            mcp.errors
+                .lock()
+                .unwrap()
+                .insert(server.id.clone(), err.to_string());
+        }
+    }
+    let _ = app.emit("mcp-server-changed", mcp.status(server));
+}
+
+fn disconnect(mcp: &Mcp, id: &str) {
+    mcp.connections.lock().unwrap().remove(id);
+    mcp.errors.lock().unwrap().remove(id);
+}
+
+/// Loads the server list and connects to the enabled servers in the
+/// background.
+pub fn init(app: &AppHandle) {
+    let config = config::read::<McpConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    let enabled: Vec<McpServerConfig> = config
+        .servers
+        .iter()
+        .filter(|s| s.enabled)
+        .cloned()
+        .collect();
+    app.manage(Mcp {
+        config: Mutex::new(config),
+        ..Default::default()
+    });
+    for server in enabled {
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move { connect(&app, server).await });
+    }
+}
+
+/// Tools of every connected server.
+pub fn tools(app: &AppHandle) -> Vec<McpTool> {
+    let Some(mcp) = app.try_state::<Mcp>() else {
+        return Vec::new();
+    };
+    let connections = mcp.connections.lock().unwrap();
+    connections
+        .values()
+        .filter(|c| c.session.is_alive())
+        .flat_map(|c| c.tools.clone())
+        .collect()
+}
+
+/// The connected tool models know as `qualified_name`.
+pub fn find_tool(app: &AppHandle, qualified_name: &str) -> Option<McpTool> {
+    let mcp = app.try_state::<Mcp>()?;
+    mcp.resolve(qualified_name).map(|(_, tool)| tool)
+}
+
+pub async fn call_tool(app: &AppHandle, tool: &McpTool, arguments: Value) -> Result<ToolOutput> {
+    let (connection, _) = app
+        .state::<Mcp>()
+        .resolve(&tool.qualified_name)
+        .ok_or_else(|| Error::Mcp(format!("{} is not connected", tool.server)))?;
+    connection.session.call_tool(&tool.name, arguments).await
+}
+
+#[tauri::command]
+pub fn list_mcp_servers(mcp: State<'_, Mcp>) -> Vec<McpServerStatus> {
+    let servers = mcp.config.lock().unwrap().servers.clone();
+    servers.into_iter().map(|s| mcp.status(s)).collect()
+}
+
+/// Adds a server, or replaces the one with the same id, and connects to it
+/// if enabled. The connection result follows as `mcp-server-changed`.
+#[tauri::command]
+pub async fn add_mcp_server(
+    app: AppHandle,
+    mcp: State<'_, Mcp>,
+    server: McpServerConfig,
+) -> Result<McpServerStatus> {
+    if server.id.is_empty() || sanitize(&server.id) != server.id {
+        return Err(Error::Mcp(format!(
+            "server id `{}` may only use letters, digits, - and _",
+            server.id
+        )));
+    }
+    {
+        let mut config = mcp.config.lock().unwrap();
+        config.servers.retain(|s| s.id != server.id);
+        config.servers.push(server.clone());
+    }
+    mcp.save(&app)?;
+    disconnect(&mcp, &server.id);
+    if server.enabled {
+        let app = app.clone();
+        let server = server.clone();
+        tauri::async_runtime::spawn(async move { connect(&app, server).await });
+    }
+    Ok(mcp.status(server))
+}
+
+#[tauri::command]
+pub async fn remove_mcp_server(app: AppHandle, mcp: State<'_, Mcp>, id: String) -> Result<()> {
+    mcp.config.lock().unwrap().servers.retain(|s| s.id != id);
+    mcp.save(&app)?;
+    disconnect(&mcp, &id);
+    Ok(())
+}
+
+/// Connects or disconnects a server and remembers the choice.
+#[tauri::command]
+pub async fn set_mcp_server_enabled(
+    app: AppHandle,
+    mcp: State<'_, Mcp>,
+    id: String,
+    enabled: bool,
+) -> Result<McpServerStatus> {
+    let server = {
+        let mut config = mcp.config.lock().unwrap();
+        let server = config
+            .servers
+            .iter_mut()
+            .find(|s| s.id == id)
+            .ok_or_else(|| Error::NotFound(format!("MCP server {id}")))?;
+        server.enabled = enabled;
+        server.clone()
+    };
+    mcp.save(&app)?;
+    if enabled {
+        connect(&app, server.clone()).await;
+    } else {
+        disconnect(&mcp, &id);
+    }
+    Ok(mcp.status(server))
+}
+
+/// Tools of one connected server, or of all of them.
+#[tauri::command]
+pub fn list_mcp_tools(mcp: State<'_, Mcp>, server: Option<String>) -> Vec<McpTool> {
+    mcp.connections
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(id, c)| server.as_ref().is_none_or(|s| s == *id) && c.session.is_alive())
+        .flat_map(|(_, c)| c.tools.clone())
+        .collect()
+}
+
+#[tauri::command]
+pub async fn list_mcp_resources(mcp: State<'_, Mcp>, server: String) -> Result<Vec<Resource>> {
+    let connection = mcp.connection(&server)?;
+    connection.session.list_resources().await
+}
+
+#[tauri::command]
+pub async fn read_mcp_resource(
+    mcp: State<'_, Mcp>,
+    server: String,
+    uri: String,
+) -> Result<Vec<ResourceContents>> {
+    let connection = mcp.connection(&server)?;
+    connection.session.read_resource(&uri).await
+}
+
+/// Runs a tool directly, on the user's own request, so without asking.
+#[tauri::command]
+pub async fn call_mcp_tool(
+    mcp: State<'_, Mcp>,
+    server: String,
+    tool: String,
+    arguments: Option<Value>,
+) -> Result<ToolOutput> {
+    mcp.server(&server)?;
+    let connection = mcp.connection(&server)?;
+    let arguments = arguments.unwrap_or_else(|| Value::Object(Default::default()));
+    connection.session.call_tool(&tool, arguments).await
+}
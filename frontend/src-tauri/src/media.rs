@@ -0,0 +1,328 @@
+//! The `pm-media://` protocol, which lets the webview load stored files
+//! (attachments and their previews) straight from disk instead of having
+//! them base64-encoded through a command. Byte ranges are honoured, so
+//! `<audio>` and `<video>` can seek in a long recording without the
+//! whole file being read first.
+//!
+//! URLs name a file relative to the data directory,
+//! `pm-media://localhost/attachments/<file>`, and only the directories in
+//! `ROOTS` can be reached: the path is resolved and checked to still be
+//! inside one of them, so neither `..` nor a symlink leads elsewhere.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use tauri::http::{header, Method, Request, Response, StatusCode};
+use tauri::{AppHandle, UriSchemeContext, UriSchemeResponder, Wry};
+
+use crate::error::{Error, Result};
+use crate::profile;
+
+pub const SCHEME: &str = "pm-media";
+/// Directories under the data directory the protocol may serve from.
+const ROOTS: [&str; 2] = ["attachments", "thumbnails"];
+/// Most a single response carries when the range asked for is open-ended,
+/// so a player asking for `bytes=0-` doesn't pull a whole film into memory.
+const MAX_CHUNK: u64 = 4 * 1024 * 1024;
+
+fn mime(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        "wav" => "audio/wav",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "ogg" | "oga" => "audio/ogg",
+        "flac" => "audio/flac",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "txt" | "md" | "csv" | "log" => "text/plain; charset=utf-8",
+        "json" => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The file a request path names, if it is inside one of `ROOTS`.
+fn resolve(app: &AppHandle, request_path: &str) -> Result<PathBuf> {
+    resolve_under(&profile::data_dir(app)?, request_path)
+}
+
+fn resolve_under(data_dir: &Path, request_path: &str) -> Result<PathBuf> {
+    let decoded = percent_decode(request_path.trim_start_matches('/'))
+        .ok_or_else(|| Error::NotFound(request_path.to_string()))?;
+    let relative = Path::new(&decoded);
+    let mut components = relative.components();
+    let root = match components.next() {
+        Some(Component::Normal(root)) => root.to_str().unwrap_or_default(),
+        _ => return Err(Error::NotFound(decoded)),
+    };
+    if !ROOTS.contains(&root) || !components.all(|c| matches!(c, Component::Normal(_))) {
+        return Err(Error::NotFound(decoded));
+    }
+    let not_found = || Error::NotFound(decoded.clone());
+    let root = data_dir
+        .join(root)
+        .canonicalize()
+        .map_err(|_| not_found())?;
+    let path = data_dir
+        .join(relative)
+        .canonicalize()
+        .map_err(|_| not_found())?;
+    if !path.starts_with(&root) || !path.is_file() {
+        return Err(not_found());
+    }
+    Ok(path)
+}
+
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// The inclusive byte span a `Range` header asks for in a file of `len`
+/// bytes. `Ok(None)` means the header is absent or not one this handles,
+/// so the whole file is sent; `Err` that the range lies past the end.
+fn parse_range(value: Option<&str>, len: u64) -> std::result::Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = value.and_then(|v| v.trim().strip_prefix("bytes=")) else {
+        return Ok(None);
+    };
+    // Multipart responses aren't worth it for media; players ask for one.
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return Ok(None);
+    };
+    let (start, end) = (start.trim(), end.trim());
+    let span = if start.is_empty() {
+        let Ok(suffix) = end.parse::<u64>() else {
+            return Ok(None);
+        };
+        if suffix == 0 || len == 0 {
+            return Err(());
+        }
+        (len.saturating_sub(suffix), len - 1)
+    } else {
+        let Ok(start) = start.parse::<u64>() else {
+            return Ok(None);
+        };
+        if start >= len {
+            return Err(());
+        }
+        let end = if end.is_empty() {
+            (start + MAX_CHUNK - 1).min(len - 1)
+        } else {
+            match end.parse::<u64>() {
+                Ok(end) if end >= start => end.min(len - 1),
+                _ => return Ok(None),
+            }
+        };
+        (start, end)
+    };
+    Ok(Some(span))
+}
+
+fn plain(status: StatusCode, body: String) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(body.into_bytes())
+        .unwrap_or_default()
+}
+
+fn serve(app: &AppHandle, request: &Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return Ok(plain(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "only GET and HEAD".into(),
+        ));
+    }
+    let path = resolve(app, request.uri().path())?;
+    let mut file = File::open(&path)?;
+    let len = file.metadata()?.len();
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok());
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, mime(&path))
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .header(header::CACHE_CONTROL, "private, max-age=3600");
+    let (response, start, count) = match parse_range(range, len) {
+        Err(()) => {
+            return Ok(Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{len}"))
+                .body(Vec::new())
+                .unwrap_or_default())
+        }
+        Ok(Some((start, end))) => (
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}")),
+            start,
+            end - start + 1,
+        ),
+        Ok(None) => (response.status(StatusCode::OK), 0, len),
+    };
+    let mut body = Vec::new();
+    if request.method() == Method::GET {
+        file.seek(SeekFrom::Start(start))?;
+        body.reserve(count as usize);
+        file.take(count).read_to_end(&mut body)?;
+    }
+    Ok(response
+        .header(header::CONTENT_LENGTH, count)
+        .body(body)
+        .unwrap_or_default())
+}
+
+/// Handler for the `pm-media://` protocol.
+pub fn protocol(
+    ctx: UriSchemeContext<'_, Wry>,
+    request: Request<Vec<u8>>,
+    responder: UriSchemeResponder,
+) {
+    let app = ctx.app_handle().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let response = match serve(&app, &request) {
+            Ok(response) => response,
+            Err(Error::NotFound(what)) => plain(StatusCode::NOT_FOUND, what),
+            Err(err) => plain(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        };
+        responder.respond(response);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_ranges() {
+        let range = |value: &str, len| parse_range(Some(value), len);
+        assert_eq!(parse_range(None, 1000), Ok(None));
+        assert_eq!(range("bytes=0-99", 1000), Ok(Some((0, 99))));
+        assert_eq!(range(" bytes=10-19 ", 1000), Ok(Some((10, 19))));
+        // Past the end is cut to it.
+        assert_eq!(range("bytes=900-5000", 1000), Ok(Some((900, 999))));
+    }
+
+    #[test]
+    fn reads_suffix_ranges() {
+        let range = |value: &str, len| parse_range(Some(value), len);
+        assert_eq!(range("bytes=-100", 1000), Ok(Some((900, 999))));
+        assert_eq!(range("bytes=-5000", 1000), Ok(Some((0, 999))));
+    }
+
+    #[test]
+    fn caps_open_ended_ranges() {
+        let range = |value: &str, len| parse_range(Some(value), len);
+        assert_eq!(range("bytes=500-", 1000), Ok(Some((500, 999))));
+        assert_eq!(
+            range("bytes=0-", 10 * MAX_CHUNK),
+            Ok(Some((0, MAX_CHUNK - 1)))
+        );
+    }
+
+    #[test]
+    fn ignores_ranges_it_cant_read() {
+        for value in [
+            "items=0-1",
+            "bytes=5",
+            "bytes=abc-",
+            "bytes=0-xyz",
+            "bytes=5-2",
+            "bytes=-",
+            "bytes=0-1,5-6",
+        ] {
+            assert_eq!(parse_range(Some(value), 1000), Ok(None), "{value}");
+        }
+    }
+
+    #[test]
+    fn refuses_unsatisfiable_ranges() {
+        let range = |value: &str, len| parse_range(Some(value), len);
+        assert_eq!(range("bytes=1000-", 1000), Err(()));
+        assert_eq!(range("bytes=2000-3000", 1000), Err(()));
+        assert_eq!(range("bytes=-0", 1000), Err(()));
+        assert_eq!(range("bytes=-5", 0), Err(()));
+        assert_eq!(range("bytes=0-", 0), Err(()));
+    }
+
+    /// A data directory with one attachment and a file outside the roots.
+    fn data_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("pentamind-media-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("attachments")).unwrap();
+        std::fs::write(dir.join("attachments").join("my file.png"), b"png").unwrap();
+        std::fs::write(dir.join("secret.txt"), b"secret").unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn serves_files_inside_the_roots() {
+        let dir = data_dir("inside");
+        let expected = dir.join("attachments").join("my file.png");
+        assert_eq!(
+            resolve_under(&dir, "/attachments/my%20file.png").unwrap(),
+            expected
+        );
+        assert_eq!(
+            resolve_under(&dir, "/attachments/./my file.png").unwrap(),
+            expected
+        );
+        assert!(resolve_under(&dir, "/attachments").is_err());
+        assert!(resolve_under(&dir, "/attachments/missing.png").is_err());
+        assert!(resolve_under(&dir, "/secret.txt").is_err());
+        assert!(resolve_under(&dir, "/attachments/%zz").is_err());
+    }
+
+    #[test]
+    fn refuses_traversal() {
+        let dir = data_dir("traversal");
+        for path in [
+            "/attachments/../secret.txt",
+            "/attachments/%2e%2e/secret.txt",
+            "/attachments%2F..%2Fsecret.txt",
+            "/%2E%2E/secret.txt",
+            "/attachments/..%5csecret.txt",
+            "//etc/passwd",
+        ] {
+            assert!(resolve_under(&dir, path).is_err(), "{path}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_out_of_the_roots() {
+        let dir = data_dir("symlink");
+        let link = dir.join("attachments").join("link.txt");
+        std::os::unix::fs::symlink(dir.join("secret.txt"), &link).unwrap();
+        assert!(resolve_under(&dir, "/attachments/link.txt").is_err());
+    }
+}
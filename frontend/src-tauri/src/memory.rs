@@ -0,0 +1,543 @@
+//! Long-term memory: durable facts about the user, such as where they work
+//! or which language they write code in, carried from one conversation to
+//! the next. Extraction is opt-in; once it is on, a cheap model reads each
+//! message the user sends and picks out anything worth keeping. Every
+//! memory records where it came from and can be edited or deleted, and
+//! memories can be added by hand as well.
+//!
+//! Before a fan-out, the memories closest to the prompt, found by embedding
+//! both through the RAG layer, go in front of it as a system message.
+
+use std::sync::Mutex;
+
+use reqwest::Client;
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::fanout::FanoutRequest;
+use crate::llm::{ChatMessage, ChatRequest, Role};
+use crate::providers::priority::{self, Priority};
+use crate::providers::Providers;
+use crate::rag::{self, Embedder, EmbeddingOptions};
+use crate::scrub;
+use crate::storage::conversations::Message;
+use crate::storage::{new_id, now_ms, Database};
+use crate::titling;
+use crate::usage;
+
+const CONFIG_FILE: &str = "memory.json";
+/// Messages shorter than this rarely say anything about the user.
+const MIN_MESSAGE_CHARS: usize = 20;
+/// Known memories shown to the extraction model so it doesn't repeat them.
+const MAX_KNOWN: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemoryConfig {
+    /// Send the user's messages to the extraction model. Off by default.
+    pub enabled: bool,
+    /// Add relevant memories to fan-out prompts.
+    pub inject: bool,
+    /// Falls back to the first configured of a few cheap models.
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    /// Used to find the memories relevant to a prompt.
+    pub embedding_provider: Option<String>,
+    pub embedding_model: Option<String>,
+    /// Most memories added to one prompt.
+    pub limit: usize,
+    /// Least cosine similarity for a memory to count as relevant.
+    pub min_score: f32,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            inject: true,
+            provider: None,
+            model: None,
+            embedding_provider: None,
+            embedding_model: None,
+            limit: 5,
+            min_score: 0.3,
+        }
+    }
+}
+
+impl MemoryConfig {
+    fn embedding(&self) -> EmbeddingOptions {
+        EmbeddingOptions {
+            provider: self.embedding_provider.clone(),
+            model: self.embedding_model.clone(),
+        }
+    }
+}
+
+/// Managed as Tauri state.
+pub struct MemoryStore {
+    config: Mutex<MemoryConfig>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Memory {
+    pub id: String,
+    pub content: String,
+    /// Where it was taken from; `None` for memories added by hand, or once
+    /// the conversation is deleted.
+    pub conversation_id: Option<String>,
+    pub conversation_title: Option<String>,
+    pub message_id: Option<String>,
+    /// The model that extracted it.
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoredMemory {
+    #[serde(flatten)]
+    pub memory: Memory,
+    pub score: f32,
+}
+
+/// Payload of the `memories-added` event.
+#[derive(Debug, Clone, Serialize)]
+struct MemoriesAdded {
+    memories: Vec<Memory>,
+}
+
+/// Payload of the `memories-used` event, sent when memories go into a
+/// fan-out.
+#[derive(Debug, Clone, Serialize)]
+struct MemoriesUsed {
+    request_id: String,
+    memories: Vec<ScoredMemory>,
+}
+
+/// Provenance of an extracted memory.
+struct Source<'a> {
+    message: &'a Message,
+    provider: &'a str,
+    model: &'a str,
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<MemoryConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(MemoryStore {
+        config: Mutex::new(config),
+    });
+}
+
+impl Memory {
+    const COLUMNS: &'static str = "m.id, m.content, m.conversation_id, c.title, m.message_id,
+         m.provider, m.model, m.created_at, m.updated_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            content: row.get(1)?,
+            conversation_id: row.get(2)?,
+            conversation_title: row.get(3)?,
+            message_id: row.get(4)?,
+            provider: row.get(5)?,
+            model: row.get(6)?,
+            created_at: row.get(7)?,
+            updated_at: row.get(8)?,
+        })
+    }
+}
+
+fn clean(content: &str) -> Result<String> {
+    let content = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    if content.is_empty() {
+        return Err(Error::InvalidSetting("a memory can't be empty".into()));
+    }
+    Ok(content)
+}
+
+impl Database {
+    fn insert_memory(&self, content: &str, source: Option<&Source<'_>>) -> Result<Memory> {
+        let id = new_id();
+        self.conn()?.execute(
+            "INSERT INTO memories
+                 (id, content, conversation_id, message_id, provider, model, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)",
+            params![
+                id,
+                content,
+                source.map(|s| &s.message.conversation_id),
+                source.map(|s| &s.message.id),
+                source.map(|s| s.provider),
+                source.map(|s| s.model),
+                now_ms()
+            ],
+        )?;
+        self.get_memory(&id)
+    }
+
+    pub fn get_memory(&self, id: &str) -> Result<Memory> {
+        self.conn()?
+            .query_row(
+                &format!(
+                    "SELECT {} FROM memories m LEFT JOIN conversations c ON c.id = m.conversation_id
+                     WHERE m.id = ?1",
+                    Memory::COLUMNS
+                ),
+                [id],
+                Memory::from_row,
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("memory {id}")))
+    }
+
+    /// Memories, newest first.
+    pub fn list_memories(&self) -> Result<Vec<Memory>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM memories m LEFT JOIN conversations c ON c.id = m.conversation_id
+             ORDER BY m.created_at DESC",
+            Memory::COLUMNS
+        ))?;
+        let memories = stmt
+            .query_map([], Memory::from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(memories)
+    }
+
+    /// Rewrites a memory. Its embedding is dropped and made again when next
+    /// needed.
+    pub fn update_memory(&self, id: &str, content: &str) -> Result<Memory> {
+        let updated = self.conn()?.execute(
+            "UPDATE memories
+             SET content = ?2, embedding = NULL, embedding_model = NULL, updated_at = ?3
+             WHERE id = ?1",
+            params![id, content, now_ms()],
+        )?;
+        if updated == 0 {
+            return Err(Error::NotFound(format!("memory {id}")));
+        }
+        self.get_memory(id)
+    }
+
+    pub fn delete_memory(&self, id: &str) -> Result<()> {
+        let deleted = self
+            .conn()?
+            .execute("DELETE FROM memories WHERE id = ?1", [id])?;
+        if deleted == 0 {
+            return Err(Error::NotFound(format!("memory {id}")));
+        }
+        Ok(())
+    }
+
+    pub fn clear_memories(&self) -> Result<()> {
+        self.conn()?.execute("DELETE FROM memories", [])?;
+        Ok(())
+    }
+
+    fn has_memories(&self) -> Result<bool> {
+        Ok(self
+            .conn()?
+            .query_row("SELECT EXISTS (SELECT 1 FROM memories)", [], |row| {
+                row.get(0)
+            })?)
+    }
+
+    /// Memories with no embedding from `model` yet.
+    fn unembedded_memories(&self, model: &str) -> Result<Vec<(String, String)>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, content FROM memories
+             WHERE embedding IS NULL OR embedding_model IS NOT ?1",
+        )?;
+        let rows = stmt
+            .query_map([model], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(rows)
+    }
+
+    fn set_memory_embedding(&self, id: &str, model: &str, vector: &[f32]) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE memories SET embedding_model = ?2, embedding = ?3 WHERE id = ?1",
+            params![id, model, rag::encode_vector(vector)],
+        )?;
+        Ok(())
+    }
+
+    fn nearest_memories(&self, model: &str, query: &[f32]) -> Result<Vec<ScoredMemory>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}, m.embedding FROM memories m
+             LEFT JOIN conversations c ON c.id = m.conversation_id
+             WHERE m.embedding_model = ?1",
+            Memory::COLUMNS
+        ))?;
+        let rows = stmt.query_map([model], |row| {
+            let embedding: Vec<u8> = row.get(9)?;
+            Ok(ScoredMemory {
+                memory: Memory::from_row(row)?,
+                score: rag::cosine(query, &rag::decode_vector(&embedding)),
+            })
+        })?;
+        let mut scored = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Ok(scored)
+    }
+}
+
+/// Memories relevant to `query`, best first, embedding any memory that
+/// hasn't been for this model yet.
+async fn relevant(
+    app: &AppHandle,
+    config: &MemoryConfig,
+    query: &str,
+) -> Result<Vec<ScoredMemory>> {
+    let db = app.state::<Database>();
+    let client = app.state::<Client>();
+    let embedder = Embedder::resolve(&app.state::<Providers>(), &config.embedding())?;
+    let key = embedder.key();
+    let missing = db.unembedded_memories(&key)?;
+    if !missing.is_empty() {
+        let contents: Vec<String> = missing.iter().map(|(_, c)| c.clone()).collect();
+        let vectors = embedder.embed(&client, &contents).await?;
+        for ((id, _), vector) in missing.iter().zip(&vectors) {
+            db.set_memory_embedding(id, &key, vector)?;
+        }
+    }
+    let vector = embedder
+        .embed(&client, &[query.to_string()])
+        .await?
+        .pop()
+        .unwrap_or_default();
+    let mut scored = db.nearest_memories(&key, &vector)?;
+    scored.retain(|m| m.score >= config.min_score);
+    scored.truncate(config.limit);
+    Ok(scored)
+}
+
+/// Puts the memories relevant to the last user message in front of the
+/// fan-out's messages, and tells the frontend which ones. Best effort: a
+/// fan-out goes ahead without them if they can't be looked up or the
+/// scrubber holds them back.
+pub(crate) async fn inject(app: &AppHandle, request_id: &str, request: &mut FanoutRequest) {
+    let Some(store) = app.try_state::<MemoryStore>() else {
+        return;
+    };
+    let config = store.config.lock().unwrap().clone();
+    if !config.inject || config.limit == 0 {
+        return;
+    }
+    let db = app.state::<Database>();
+    if !db.has_memories().unwrap_or(false) {
+        return;
+    }
+    let Some(query) = request
+        .messages
+        .iter()
+        .rev()
+        .find(|m| m.role == Role::User)
+        .map(|m| m.content.clone())
+    else {
+        return;
+    };
+    let memories = match relevant(app, &config, &query).await {
+        Ok(memories) if !memories.is_empty() => memories,
+        Ok(_) => return,
+        Err(err) => {
+            tracing::debug!("couldn't look up memories: {err}");
+            return;
+        }
+    };
+    let mut content = String::from(
+        "What you know about the user from earlier conversations. \
+         Use it where it helps and don't mention it otherwise:\n",
+    );
+    for memory in &memories {
+        content.push_str(&format!("\n- {}", memory.memory.content));
+    }
+    let message = ChatMessage {
+        role: Role::System,
+        content,
+    };
+    // Memories come from earlier prompts, so they get the same checks the
+    // prompt did; held back, the fan-out goes without them.
+    let message = match scrub::check(app, request_id, vec![message]).await {
+        Ok(mut checked) => checked.remove(0),
+        Err(err) => {
+            tracing::debug!("memories not sent: {err}");
+            return;
+        }
+    };
+    request.messages.insert(0, message);
+    let _ = app.emit(
+        "memories-used",
+        MemoriesUsed {
+            request_id: request_id.to_string(),
+            memories,
+        },
+    );
+}
+
+/// The strings of the first JSON array in `reply`, which models tend to
+/// wrap in prose or a code fence.
+fn parse_facts(reply: &str) -> Vec<String> {
+    let (Some(start), Some(end)) = (reply.find('['), reply.rfind(']')) else {
+        return Vec::new();
+    };
+    if end < start {
+        return Vec::new();
+    }
+    serde_json::from_str::<Vec<String>>(&reply[start..=end]).unwrap_or_default()
+}
+
+async fn extract(app: &AppHandle, config: &MemoryConfig, message: &Message) -> Result<()> {
+    let db = app.state::<Database>();
+    let known: Vec<String> = db
+        .list_memories()?
+        .into_iter()
+        .take(MAX_KNOWN)
+        .map(|m| m.content)
+        .collect();
+    let (provider, model) =
+        titling::pick_model(app, config.provider.as_deref(), config.model.as_deref())?;
+    let mut prompt = String::from(
+        "Pick out durable facts about the user from their message below: who they \
+         are, their work, preferences, tools, projects and circumstances that will \
+         still hold in later conversations. Leave out anything about the task at \
+         hand only, guesses, and anything already known. Write each fact as a short \
+         sentence about \"the user\". Reply with a JSON array of strings, [] if \
+         there are none.\n",
+    );
+    if !known.is_empty() {
+        prompt.push_str("\n## Already known\n");
+        for fact in &known {
+            prompt.push_str(&format!("- {fact}\n"));
+        }
+    }
+    prompt.push_str(&format!("\n## Message\n{}", message.content.trim()));
+    let request = ChatRequest {
+        provider: provider.id().to_string(),
+        model,
+        messages: vec![ChatMessage {
+            role: Role::User,
+            content: prompt,
+        }],
+        temperature: Some(0.0),
+        top_p: None,
+        max_tokens: Some(400),
+        use_tools: false,
+        bypass_cache: false,
+        response_schema: None,
+        conversation_id: None,
+        tools: Vec::new(),
+        tool_rounds: Vec::new(),
+    };
+    let client = app.state::<Client>();
+    let completion = provider.stream(&client, &request, &mut |_| {}).await?;
+    usage::record(app, &request, &completion.content, completion.usage);
+
+    let source = Source {
+        message,
+        provider: &request.provider,
+        model: &request.model,
+    };
+    let mut added = Vec::new();
+    for fact in parse_facts(&completion.content) {
+        let Ok(fact) = clean(&fact) else {
+            continue;
+        };
+        if known.iter().any(|k| k.eq_ignore_ascii_case(&fact)) {
+            continue;
+        }
+        added.push(db.insert_memory(&fact, Some(&source))?);
+    }
+    if !added.is_empty() {
+        let _ = app.emit("memories-added", MemoriesAdded { memories: added });
+    }
+    Ok(())
+}
+
+/// Called after a message is added; has a user message read for memories
+/// in the background when extraction is on.
+pub fn after_append(app: &AppHandle, message: &Message) {
+    let Some(store) = app.try_state::<MemoryStore>() else {
+        return;
+    };
+    let config = store.config.lock().unwrap().clone();
+    if !config.enabled
+        || message.role != Role::User
+        || message.content.trim().chars().count() < MIN_MESSAGE_CHARS
+    {
+        return;
+    }
+    let app = app.clone();
+    let message = message.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(err) =
+            priority::scope(Priority::Background, extract(&app, &config, &message)).await
+        {
+            tracing::warn!("couldn't extract memories from {}: {err}", message.id);
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn list_memories(db: State<'_, Database>) -> Result<Vec<Memory>> {
+    db.list_memories()
+}
+
+/// Adds a memory by hand; it has no source.
+#[tauri::command]
+pub async fn add_memory(db: State<'_, Database>, content: String) -> Result<Memory> {
+    db.insert_memory(&clean(&content)?, None)
+}
+
+#[tauri::command]
+pub async fn update_memory(db: State<'_, Database>, id: String, content: String) -> Result<Memory> {
+    db.update_memory(&id, &clean(&content)?)
+}
+
+#[tauri::command]
+pub async fn delete_memory(db: State<'_, Database>, id: String) -> Result<()> {
+    db.delete_memory(&id)
+}
+
+#[tauri::command]
+pub async fn clear_memories(db: State<'_, Database>) -> Result<()> {
+    db.clear_memories()
+}
+
+/// The memories that would go into a prompt of `query`, for checking what
+/// the models will be told.
+#[tauri::command]
+pub async fn find_memories(
+    app: AppHandle,
+    store: State<'_, MemoryStore>,
+    query: String,
+) -> Result<Vec<ScoredMemory>> {
+    let config = store.config.lock().unwrap().clone();
+    relevant(&app, &config, &query).await
+}
+
+#[tauri::command]
+pub fn get_memory_config(store: State<'_, MemoryStore>) -> MemoryConfig {
+    store.config.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn set_memory_config(
+    app: AppHandle,
+    store: State<'_, MemoryStore>,
+    config: MemoryConfig,
+) -> Result<MemoryConfig> {
+    config::write(&app, CONFIG_FILE, &config)?;
+    *store.config.lock().unwrap() = config.clone();
+    Ok(config)
+}
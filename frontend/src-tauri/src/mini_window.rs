@@ -0,0 +1,322 @@
+//! A small borderless window that floats above everything with the latest
+//! fan-out at a glance: one line per model. It sits in a corner of the
+//! screen and snaps back to the nearest one after being dragged. Unpinned
+//! it hides when it loses focus like the quick prompt; pinned it stays up,
+//! on every desktop.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tauri::{
+    AppHandle, Emitter, Manager, PhysicalPosition, State, WebviewUrl, WebviewWindow,
+    WebviewWindowBuilder, Window, WindowEvent,
+};
+
+use crate::config;
+use crate::error::Result;
+use crate::fanout::{FanoutRequest, FanoutResult};
+use crate::llm::Role;
+use crate::markdown;
+use crate::storage::now_ms;
+
+pub const MINI_LABEL: &str = "mini";
+const CONFIG_FILE: &str = "mini_window.json";
+const WIDTH: f64 = 360.0;
+const HEIGHT: f64 = 220.0;
+/// Gap to the screen edges, in logical pixels.
+const MARGIN: f64 = 16.0;
+/// How long a drag has to be still before the window snaps.
+const SNAP_DELAY: Duration = Duration::from_millis(400);
+const PROMPT_LEN: usize = 120;
+const EXCERPT_LEN: usize = 280;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Corner {
+    TopLeft,
+    #[default]
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MiniWindowConfig {
+    pub pinned: bool,
+    pub corner: Corner,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MiniResponse {
+    pub provider: String,
+    pub model: String,
+    /// The start of the answer as plain text.
+    pub excerpt: Option<String>,
+    pub error: Option<String>,
+    pub latency_ms: u64,
+}
+
+/// Payload of the `mini-summary` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct FanoutSummary {
+    pub request_id: String,
+    /// The last user message, shortened.
+    pub prompt: String,
+    pub responses: Vec<MiniResponse>,
+    pub finished_at: i64,
+}
+
+/// Managed as Tauri state.
+pub struct MiniWindow {
+    config: Mutex<MiniWindowConfig>,
+    latest: Mutex<Option<FanoutSummary>>,
+    /// Bumped on every move so only the last one of a drag snaps.
+    moves: AtomicU64,
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<MiniWindowConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(MiniWindow {
+        config: Mutex::new(config),
+        latest: Mutex::default(),
+        moves: AtomicU64::new(0),
+    });
+}
+
+fn shorten(text: &str, max: usize) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(max) {
+        Some((end, _)) => format!("{}…", text[..end].trim_end()),
+        None => text,
+    }
+}
+
+/// Keeps the fan-out that just finished for the mini window.
+pub fn record(
+    app: &AppHandle,
+    request_id: &str,
+    request: &FanoutRequest,
+    results: &[FanoutResult],
+) {
+    let Some(mini) = app.try_state::<MiniWindow>() else {
+        return;
+    };
+    let prompt = request
+        .messages
+        .iter()
+        .rev()
+        .find(|m| m.role == Role::User)
+        .map(|m| shorten(&m.content, PROMPT_LEN))
+        .unwrap_or_default();
+    let summary = FanoutSummary {
+        request_id: request_id.to_string(),
+        prompt,
+        responses: results
+            .iter()
+            .map(|result| MiniResponse {
+                provider: result.provider.clone(),
+                model: result.model.clone(),
+                excerpt: result
+                    .content
+                    .as_deref()
+                    .map(|content| shorten(&markdown::plain_text(content), EXCERPT_LEN)),
+                error: result.error.clone(),
+                latency_ms: result.latency_ms,
+            })
+            .collect(),
+        finished_at: now_ms(),
+    };
+    *mini.latest.lock().unwrap() = Some(summary.clone());
+    let _ = app.emit_to(MINI_LABEL, "mini-summary", summary);
+}
+
+/// Where the window goes in `corner` of the monitor it is on, keeping clear
+/// of the menu bar, taskbar and dock.
+fn corner_position(
+    window: &WebviewWindow,
+    corner: Corner,
+) -> Result<Option<PhysicalPosition<i32>>> {
+    let Some(monitor) = window.current_monitor()?.or(window.primary_monitor()?) else {
+        return Ok(None);
+    };
+    let area = monitor.work_area();
+    let size = window.outer_size()?;
+    let margin = (MARGIN * monitor.scale_factor()) as i32;
+    let left = area.position.x + margin;
+    let top = area.position.y + margin;
+    let right = area.position.x + area.size.width as i32 - size.width as i32 - margin;
+    let bottom = area.position.y + area.size.height as i32 - size.height as i32 - margin;
+    Ok(Some(match corner {
+        Corner::TopLeft => PhysicalPosition::new(left, top),
+        Corner::TopRight => PhysicalPosition::new(right, top),
+        Corner::BottomLeft => PhysicalPosition::new(left, bottom),
+        Corner::BottomRight => PhysicalPosition::new(right, bottom),
+    }))
+}
+
+/// The corner the window's center is closest to.
+fn nearest_corner(window: &WebviewWindow) -> Result<Option<Corner>> {
+    let Some(monitor) = window.current_monitor()? else {
+        return Ok(None);
+    };
+    let area = monitor.work_area();
+    let position = window.outer_position()?;
+    let size = window.outer_size()?;
+    let center_x = position.x + size.width as i32 / 2;
+    let center_y = position.y + size.height as i32 / 2;
+    let left = center_x < area.position.x + area.size.width as i32 / 2;
+    let top = center_y < area.position.y + area.size.height as i32 / 2;
+    Ok(Some(match (top, left) {
+        (true, true) => Corner::TopLeft,
+        (true, false) => Corner::TopRight,
+        (false, true) => Corner::BottomLeft,
+        (false, false) => Corner::BottomRight,
+    }))
+}
+
+fn place(window: &WebviewWindow, corner: Corner) -> Result<()> {
+    if let Some(position) = corner_position(window, corner)? {
+        if window.outer_position()? != position {
+            window.set_position(position)?;
+        }
+    }
+    Ok(())
+}
+
+fn save(app: &AppHandle, config: MiniWindowConfig) -> Result<()> {
+    config::write(app, CONFIG_FILE, &config)?;
+    *app.state::<MiniWindow>().config.lock().unwrap() = config;
+    Ok(())
+}
+
+fn show(app: &AppHandle) -> Result<()> {
+    let config = app.state::<MiniWindow>().config.lock().unwrap().clone();
+    if let Some(window) = app.get_webview_window(MINI_LABEL) {
+        window.show()?;
+        window.set_focus()?;
+        return Ok(());
+    }
+    let window = WebviewWindowBuilder::new(
+        app,
+        MINI_LABEL,
+        WebviewUrl::App("index.html?view=mini".into()),
+    )
+    .title("Pentamind")
+    .inner_size(WIDTH, HEIGHT)
+    .decorations(false)
+    .transparent(true)
+    .always_on_top(true)
+    .visible_on_all_workspaces(config.pinned)
+    .resizable(false)
+    .skip_taskbar(true)
+    .visible(false)
+    .build()?;
+    #[cfg(not(target_os = "macos"))]
+    let _ = window.remove_menu();
+    place(&window, config.corner)?;
+    window.show()?;
+    window.set_focus()?;
+    Ok(())
+}
+
+/// Hides an unpinned window when it loses focus and snaps a dragged one to
+/// the nearest corner once it stops moving.
+pub fn on_window_event(window: &Window, event: &WindowEvent) {
+    if window.label() != MINI_LABEL {
+        return;
+    }
+    let app = window.app_handle();
+    let mini = app.state::<MiniWindow>();
+    match event {
+        WindowEvent::Focused(false) if !mini.config.lock().unwrap().pinned => {
+            let _ = window.hide();
+        }
+        WindowEvent::Moved(_) => {
+            let generation = mini.moves.fetch_add(1, Ordering::Relaxed) + 1;
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                tokio::time::sleep(SNAP_DELAY).await;
+                if app.state::<MiniWindow>().moves.load(Ordering::Relaxed) != generation {
+                    return;
+                }
+                if let Err(err) = snap(&app, None) {
+                    tracing::warn!("couldn't snap the mini window: {err}");
+                }
+            });
+        }
+        _ => {}
+    }
+}
+
+/// Moves the window to `corner`, or the nearest one, and remembers it.
+fn snap(app: &AppHandle, corner: Option<Corner>) -> Result<Corner> {
+    let mut config = app.state::<MiniWindow>().config.lock().unwrap().clone();
+    let Some(window) = app.get_webview_window(MINI_LABEL) else {
+        if let Some(corner) = corner {
+            config.corner = corner;
+            save(app, config)?;
+        }
+        return Ok(corner.unwrap_or_default());
+    };
+    let corner = match corner {
+        Some(corner) => corner,
+        None => nearest_corner(&window)?.unwrap_or(config.corner),
+    };
+    place(&window, corner)?;
+    if config.corner != corner {
+        config.corner = corner;
+        save(app, config)?;
+    }
+    Ok(corner)
+}
+
+/// Shows or hides the mini window, returning whether it is now visible.
+#[tauri::command]
+pub fn toggle_mini_window(app: AppHandle) -> Result<bool> {
+    match app.get_webview_window(MINI_LABEL) {
+        Some(window) if window.is_visible()? => {
+            window.hide()?;
+            Ok(false)
+        }
+        _ => {
+            show(&app)?;
+            Ok(true)
+        }
+    }
+}
+
+/// Keeps the window up when it loses focus and on every desktop.
+#[tauri::command]
+pub fn pin_mini_window(app: AppHandle, pinned: bool) -> Result<MiniWindowConfig> {
+    let mut config = app.state::<MiniWindow>().config.lock().unwrap().clone();
+    config.pinned = pinned;
+    save(&app, config.clone())?;
+    if let Some(window) = app.get_webview_window(MINI_LABEL) {
+        window.set_visible_on_all_workspaces(pinned)?;
+    }
+    Ok(config)
+}
+
+/// Moves the window to `corner`, or to the one nearest where it is now.
+#[tauri::command]
+pub fn snap_mini_window(app: AppHandle, corner: Option<Corner>) -> Result<Corner> {
+    snap(&app, corner)
+}
+
+#[tauri::command]
+pub fn get_mini_window_config(mini: State<'_, MiniWindow>) -> MiniWindowConfig {
+    mini.config.lock().unwrap().clone()
+}
+
+/// The latest fan-out, for the window to show when it opens; later ones
+/// arrive as `mini-summary` events.
+#[tauri::command]
+pub fn get_mini_summary(mini: State<'_, MiniWindow>) -> Option<FanoutSummary> {
+    mini.latest.lock().unwrap().clone()
+}
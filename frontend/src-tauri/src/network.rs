@@ -0,0 +1,398 @@
+//! Network settings for the shared HTTP client: a proxy (HTTP, HTTPS or
+//! SOCKS5, optionally with a login), extra CA certificates for gateways
+//! that intercept TLS, and base URLs that replace a provider's own, for
+//! Azure OpenAI deployments or self-hosted gateways.
+//!
+//! The proxy and base URLs apply to the next request. The client only
+//! reads the CA bundle when it is built, so a new bundle needs a restart.
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client, ClientBuilder, Proxy, Url};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::keys;
+
+const CONFIG_FILE: &str = "network.json";
+/// Credential store entry holding the proxy password.
+pub(crate) const PROXY_PASSWORD_KEY: &str = "network-proxy";
+const PROXY_SCHEMES: [&str; 4] = ["http", "https", "socks5", "socks5h"];
+/// Never proxied, whatever the settings say: local servers such as Ollama.
+const ALWAYS_DIRECT: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+/// As many as reqwest follows by default.
+const MAX_REDIRECTS: usize = 10;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// `http://`, `https://`, `socks5://`, or `socks5h://` to resolve names
+    /// through the proxy. `None` falls back to the `HTTPS_PROXY` family of
+    /// environment variables.
+    pub proxy_url: Option<String>,
+    /// The password is kept in the OS credential store.
+    pub proxy_username: Option<String>,
+    /// Hosts reached directly, comma-separated like `NO_PROXY`; a leading
+    /// dot or none both match subdomains.
+    pub no_proxy: Option<String>,
+    /// PEM file of certificates to trust besides the built-in roots.
+    pub ca_bundle: Option<PathBuf>,
+    /// Provider id to the base URL used instead of its own. A query string
+    /// (say Azure's `api-version`) is kept on every request.
+    pub base_urls: BTreeMap<String, String>,
+}
+
+/// What requests consult as they are sent.
+struct Active {
+    proxy: Option<Url>,
+    no_proxy: Vec<String>,
+    base_urls: BTreeMap<String, String>,
+}
+
+/// Shared with the client's proxy callback and providers, which have no
+/// app handle of their own.
+static ACTIVE: RwLock<Active> = RwLock::new(Active {
+    proxy: None,
+    no_proxy: Vec::new(),
+    base_urls: BTreeMap::new(),
+});
+
+/// Managed as Tauri state.
+pub struct Network {
+    config: Mutex<NetworkConfig>,
+    /// The bundle the running client was built with.
+    loaded_ca_bundle: Option<PathBuf>,
+}
+
+/// What `get_network_config` and `set_network_config` return.
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkStatus {
+    #[serde(flatten)]
+    pub config: NetworkConfig,
+    pub has_proxy_password: bool,
+    /// The CA bundle changed since launch.
+    pub restart_required: bool,
+}
+
+fn invalid(message: String) -> Error {
+    Error::InvalidSetting(message)
+}
+
+fn proxy_url(config: &NetworkConfig, password: Option<&str>) -> Result<Option<Url>> {
+    let Some(raw) = config.proxy_url.as_deref().filter(|u| !u.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let mut url = Url::parse(raw.trim()).map_err(|e| invalid(format!("proxy URL {raw}: {e}")))?;
+    if !PROXY_SCHEMES.contains(&url.scheme()) || url.host_str().is_none() {
+        return Err(invalid(format!(
+            "proxy URL {raw} must be http://, https://, socks5:// or socks5h:// with a host"
+        )));
+    }
+    if let Some(username) = config.proxy_username.as_deref().filter(|u| !u.is_empty()) {
+        let _ = url.set_username(username);
+        let _ = url.set_password(password);
+    }
+    Ok(Some(url))
+}
+
+fn check_base_urls(base_urls: &BTreeMap<String, String>) -> Result<()> {
+    for (provider, base) in base_urls {
+        let url = Url::parse(base).map_err(|e| invalid(format!("base URL for {provider}: {e}")))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(invalid(format!("base URL for {provider} must be http(s)")));
+        }
+    }
+    Ok(())
+}
+
+fn read_ca_bundle(path: &Path) -> Result<Vec<Certificate>> {
+    let pem = std::fs::read(path)?;
+    let certificates = Certificate::from_pem_bundle(&pem)
+        .map_err(|e| invalid(format!("CA bundle {}: {e}", path.display())))?;
+    if certificates.is_empty() {
+        return Err(invalid(format!(
+            "CA bundle {} has no certificates",
+            path.display()
+        )));
+    }
+    Ok(certificates)
+}
+
+fn activate(config: &NetworkConfig, password: Option<&str>) -> Result<()> {
+    let proxy = proxy_url(config, password)?;
+    check_base_urls(&config.base_urls)?;
+    let no_proxy = config
+        .no_proxy
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(|host| host.trim().trim_start_matches('.').to_ascii_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect();
+    let base_urls = config
+        .base_urls
+        .iter()
+        .map(|(provider, base)| (provider.clone(), base.trim_end_matches('/').to_string()))
+        .collect();
+    *ACTIVE.write().unwrap() = Active {
+        proxy,
+        no_proxy,
+        base_urls,
+    };
+    Ok(())
+}
+
+/// The proxy from the environment, for when none is configured. Adding
+/// any proxy to the client turns off its own environment lookup.
+fn env_proxy(url: &Url) -> Option<Url> {
+    let names: &[&str] = if url.scheme() == "https" {
+        &["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+    } else {
+        &["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"]
+    };
+    let proxy = names.iter().find_map(|name| std::env::var(name).ok())?;
+    let no_proxy = std::env::var("NO_PROXY")
+        .or_else(|_| std::env::var("no_proxy"))
+        .unwrap_or_default();
+    let host = url.host_str()?;
+    let excluded = no_proxy
+        .split(',')
+        .map(|h| h.trim().trim_start_matches('.'))
+        .any(|h| h == "*" || host_matches(host, h));
+    (!excluded).then(|| Url::parse(&proxy).ok()).flatten()
+}
+
+fn host_matches(host: &str, pattern: &str) -> bool {
+    !pattern.is_empty()
+        && (host.eq_ignore_ascii_case(pattern)
+            || host
+                .to_ascii_lowercase()
+                .ends_with(&format!(".{}", pattern.to_ascii_lowercase())))
+}
+
+/// Picks the proxy for each request from the current settings.
+fn choose_proxy(url: &Url) -> Option<Url> {
+    let host = url
+        .host_str()?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    if ALWAYS_DIRECT.contains(&host) {
+        return None;
+    }
+    let active = ACTIVE.read().unwrap();
+    let Some(proxy) = active.proxy.clone() else {
+        drop(active);
+        return env_proxy(url);
+    };
+    let excluded = active
+        .no_proxy
+        .iter()
+        .any(|pattern| pattern == "*" || host_matches(host, pattern));
+    (!excluded).then_some(proxy)
+}
+
+fn client_builder(ca_bundle: Option<&Path>) -> Result<ClientBuilder> {
+    let mut builder = Client::builder().proxy(Proxy::custom(choose_proxy));
+    if let Some(path) = ca_bundle {
+        for certificate in read_ca_bundle(path)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    Ok(builder)
+}
+
+fn build_client(ca_bundle: Option<&Path>) -> Result<Client> {
+    Ok(client_builder(ca_bundle)?.build()?)
+}
+
+/// A client like the shared one but limited to HTTP/1.1, for connections
+/// upgraded to WebSockets, which HTTP/2 can't carry.
+pub fn upgrade_client(app: &AppHandle) -> Result<Client> {
+    let ca_bundle = app
+        .try_state::<Network>()
+        .and_then(|network| network.loaded_ca_bundle.clone());
+    Ok(client_builder(ca_bundle.as_deref())?.http1_only().build()?)
+}
+
+/// Whether `ip` is reachable from anywhere, as opposed to this machine or
+/// its local network.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10.
+                || (a == 100 && b & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    // Unique local, fc00::/7, and link-local, fe80::/10.
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Refuses a URL naming a loopback, private or link-local address outright.
+/// Names are checked when they resolve, by [`PublicOnly`].
+pub fn check_public(url: &Url) -> Result<()> {
+    let host = url
+        .host_str()
+        .unwrap_or_default()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let Ok(ip) = host.parse::<IpAddr>() else {
+        return Ok(());
+    };
+    if is_public(ip) {
+        Ok(())
+    } else {
+        Err(Error::Fetch(format!(
+            "{ip} is on this computer or its local network"
+        )))
+    }
+}
+
+/// Resolves names as usual but drops addresses that aren't public, failing
+/// if none are left.
+struct PublicOnly;
+
+impl Resolve for PublicOnly {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{host} is on this computer or its local network").into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// A client like the shared one that won't reach this computer or its
+/// local network, redirects included, for fetches a model asks for. Hosts
+/// sent through a proxy are resolved by the proxy, so only addresses
+/// written into their URLs are checked.
+pub fn public_client(app: &AppHandle) -> Result<Client> {
+    let ca_bundle = app
+        .try_state::<Network>()
+        .and_then(|network| network.loaded_ca_bundle.clone());
+    let redirects = Policy::custom(|attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+        match check_public(attempt.url()) {
+            Ok(()) => attempt.follow(),
+            Err(err) => attempt.error(err.to_string()),
+        }
+    });
+    Ok(client_builder(ca_bundle.as_deref())?
+        .dns_resolver(Arc::new(PublicOnly))
+        .redirect(redirects)
+        .build()?)
+}
+
+/// `path` under `provider`'s base URL: its override if one is set,
+/// otherwise `default`.
+pub fn endpoint(provider: &str, default: &str, path: &str) -> String {
+    let active = ACTIVE.read().unwrap();
+    let base = active
+        .base_urls
+        .get(provider)
+        .map(String::as_str)
+        .unwrap_or(default);
+    match base.split_once('?') {
+        Some((base, query)) => format!("{}{path}?{query}", base.trim_end_matches('/')),
+        None => format!("{base}{path}"),
+    }
+}
+
+/// Loads the settings and manages the shared client built from them. A
+/// proxy or bundle that no longer works is dropped rather than keeping the
+/// app offline.
+pub fn init(app: &AppHandle) {
+    let config = config::read::<NetworkConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    let password = keys::load(PROXY_PASSWORD_KEY).ok().flatten();
+    if let Err(err) = activate(&config, password.as_deref()) {
+        tracing::warn!("ignoring network settings: {err}");
+    }
+    let client = match build_client(config.ca_bundle.as_deref()) {
+        Ok(client) => client,
+        Err(err) => {
+            tracing::warn!("ignoring the CA bundle: {err}");
+            build_client(None).unwrap_or_default()
+        }
+    };
+    app.manage(client);
+    app.manage(Network {
+        loaded_ca_bundle: config.ca_bundle.clone(),
+        config: Mutex::new(config),
+    });
+}
+
+fn status(network: &Network, config: &NetworkConfig) -> NetworkStatus {
+    NetworkStatus {
+        config: config.clone(),
+        has_proxy_password: keys::load(PROXY_PASSWORD_KEY).ok().flatten().is_some(),
+        restart_required: config.ca_bundle != network.loaded_ca_bundle,
+    }
+}
+
+#[tauri::command]
+pub fn get_network_config(network: State<'_, Network>) -> NetworkStatus {
+    status(&network, &network.config.lock().unwrap())
+}
+
+/// Replaces the network settings. `proxy_password` is stored when given;
+/// an empty one removes the stored password.
+#[tauri::command]
+pub fn set_network_config(
+    app: AppHandle,
+    network: State<'_, Network>,
+    config: NetworkConfig,
+    proxy_password: Option<String>,
+) -> Result<NetworkStatus> {
+    if let Some(path) = &config.ca_bundle {
+        read_ca_bundle(path)?;
+    }
+    let password = match proxy_password.as_deref() {
+        Some("") => None,
+        Some(password) => Some(password.to_string()),
+        None => keys::load(PROXY_PASSWORD_KEY)?,
+    };
+    activate(&config, password.as_deref())?;
+    match proxy_password.as_deref() {
+        Some("") => keys::delete(PROXY_PASSWORD_KEY)?,
+        Some(password) => keys::store(PROXY_PASSWORD_KEY, password)?,
+        None => {}
+    }
+    config::write(&app, CONFIG_FILE, &config)?;
+    let mut current = network.config.lock().unwrap();
+    *current = config;
+    Ok(status(&network, &current))
+}
@@ -0,0 +1,520 @@
+//! Notes: Markdown pages for keeping what came out of conversations.
+//! Notes link to each other with `[[Title]]` (also `[[Title|shown text]]`
+//! and `[[Title#heading]]`), and can be attached to a conversation or one
+//! of its messages.
+//!
+//! Links are parsed out of the body whenever a note is saved and kept by
+//! the key of the title they name, so a link to a note that doesn't exist
+//! yet starts working once it is written, and backlinks are a lookup.
+//! Renaming a note rewrites the links to it in every other note. Text in
+//! code is never a link.
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::error::{Error, Result};
+use crate::markdown::fence;
+use crate::storage::{new_id, now_ms, Database};
+
+/// Characters of the linking line shown with a backlink.
+const CONTEXT_LEN: usize = 160;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NoteLink {
+    /// The title as written in the link.
+    pub title: String,
+    /// `None` while no note has that title.
+    pub note_id: Option<String>,
+}
+
+/// A conversation, or one of its messages, a note is attached to.
+#[derive(Debug, Clone, Serialize)]
+pub struct NoteReference {
+    pub conversation_id: String,
+    pub conversation_title: String,
+    pub message_id: Option<String>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Note {
+    pub id: String,
+    pub title: String,
+    pub body: String,
+    /// Notes this one links to, in the order they first appear.
+    pub links: Vec<NoteLink>,
+    pub references: Vec<NoteReference>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NoteInput {
+    pub title: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Backlink {
+    pub note_id: String,
+    pub title: String,
+    /// The line the link is on.
+    pub context: String,
+    pub updated_at: i64,
+}
+
+/// A `[[link]]` in a body; `start..end` is the title part.
+#[derive(Debug, Clone, PartialEq)]
+struct WikiLink {
+    start: usize,
+    end: usize,
+    key: String,
+}
+
+/// What links and titles are matched by: case and spacing don't count.
+fn key(title: &str) -> String {
+    title
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Links in `line`, which starts at `offset` in the body, outside inline
+/// code.
+fn line_links(line: &str, offset: usize, links: &mut Vec<WikiLink>) {
+    let mut in_code = false;
+    let mut i = 0;
+    while i < line.len() {
+        let rest = &line[i..];
+        if rest.starts_with('`') {
+            in_code = !in_code;
+            i += 1;
+            continue;
+        }
+        if in_code || !rest.starts_with("[[") {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+            continue;
+        }
+        let Some(close) = rest[2..].find("]]") else {
+            return;
+        };
+        let inner = &rest[2..2 + close];
+        let title = inner.split(['|', '#']).next().unwrap_or_default();
+        let leading = title.len() - title.trim_start().len();
+        let title = title.trim();
+        if !title.is_empty() && !inner.contains("[[") {
+            let start = offset + i + 2 + leading;
+            links.push(WikiLink {
+                start,
+                end: start + title.len(),
+                key: key(title),
+            });
+        }
+        i += 2 + close + 2;
+    }
+}
+
+/// Every link in a body, outside fenced and inline code.
+fn wiki_links(body: &str) -> Vec<WikiLink> {
+    let mut links = Vec::new();
+    let mut open: Option<(char, usize)> = None;
+    let mut offset = 0;
+    for line in body.split_inclusive('\n') {
+        let marker = fence(line);
+        match (open, marker) {
+            (Some((ch, len)), Some((c, l)))
+                if c == ch && l >= len && line.trim().chars().all(|x| x == c) =>
+            {
+                open = None
+            }
+            (Some(_), _) => {}
+            (None, Some(opening)) => open = Some(opening),
+            (None, None) => line_links(line, offset, &mut links),
+        }
+        offset += line.len();
+    }
+    links
+}
+
+/// `body` with the links to `from` pointing at `to` instead, keeping any
+/// `|shown text` or `#heading`.
+fn retarget(body: &str, from: &str, to: &str) -> String {
+    let mut rewritten = String::with_capacity(body.len());
+    let mut last = 0;
+    for link in wiki_links(body).into_iter().filter(|l| l.key == from) {
+        rewritten.push_str(&body[last..link.start]);
+        rewritten.push_str(to);
+        last = link.end;
+    }
+    rewritten.push_str(&body[last..]);
+    rewritten
+}
+
+/// The line around `at`, trimmed and cut to `CONTEXT_LEN` characters.
+fn context(body: &str, at: usize) -> String {
+    let start = body[..at].rfind('\n').map_or(0, |i| i + 1);
+    let end = body[at..].find('\n').map_or(body.len(), |i| at + i);
+    let line = body[start..end].trim();
+    match line.char_indices().nth(CONTEXT_LEN) {
+        Some((cut, _)) => format!("{}…", &line[..cut]),
+        None => line.to_string(),
+    }
+}
+
+impl NoteInput {
+    fn normalize(mut self) -> Result<Self> {
+        self.title = self.title.split_whitespace().collect::<Vec<_>>().join(" ");
+        if self.title.is_empty() {
+            return Err(Error::InvalidNote("the title is empty".into()));
+        }
+        if self.title.contains(['[', ']', '|', '#']) {
+            return Err(Error::InvalidNote(
+                "a title can't contain [, ], | or #".into(),
+            ));
+        }
+        Ok(self)
+    }
+}
+
+impl Note {
+    const COLUMNS: &'static str = "id, title, body, created_at, updated_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            body: row.get(2)?,
+            links: Vec::new(),
+            references: Vec::new(),
+            created_at: row.get(3)?,
+            updated_at: row.get(4)?,
+        })
+    }
+
+    /// Loads the links and references.
+    fn complete(mut self, conn: &Connection) -> Result<Self> {
+        let mut seen = Vec::new();
+        let mut stmt = conn.prepare_cached("SELECT id FROM notes WHERE key = ?1")?;
+        for link in wiki_links(&self.body) {
+            if seen.contains(&link.key) {
+                continue;
+            }
+            self.links.push(NoteLink {
+                title: self.body[link.start..link.end].to_string(),
+                note_id: stmt.query_row([&link.key], |row| row.get(0)).optional()?,
+            });
+            seen.push(link.key);
+        }
+        let mut stmt = conn.prepare_cached(
+            "SELECT r.conversation_id, c.title, r.message_id, r.created_at
+             FROM note_references r JOIN conversations c ON c.id = r.conversation_id
+             WHERE r.note_id = ?1 ORDER BY r.created_at",
+        )?;
+        self.references = stmt
+            .query_map([&self.id], |row| {
+                Ok(NoteReference {
+                    conversation_id: row.get(0)?,
+                    conversation_title: row.get(1)?,
+                    message_id: row.get(2)?,
+                    created_at: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(self)
+    }
+}
+
+fn find_note(conn: &Connection, id: &str) -> Result<Note> {
+    conn.query_row(
+        &format!("SELECT {} FROM notes WHERE id = ?1", Note::COLUMNS),
+        [id],
+        Note::from_row,
+    )
+    .optional()?
+    .ok_or_else(|| Error::NotFound(format!("note {id}")))
+}
+
+fn ensure_unique(conn: &Connection, key: &str, title: &str, id: Option<&str>) -> Result<()> {
+    let taken: Option<String> = conn
+        .query_row(
+            "SELECT id FROM notes WHERE key = ?1 AND (?2 IS NULL OR id != ?2)",
+            params![key, id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match taken {
+        Some(_) => Err(Error::InvalidNote(format!(
+            "there is already a note called {title}"
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Replaces the stored links of a note with those in `body`.
+fn write_links(conn: &Connection, id: &str, body: &str) -> Result<()> {
+    conn.execute("DELETE FROM note_links WHERE source_id = ?1", [id])?;
+    for link in wiki_links(body) {
+        conn.execute(
+            "INSERT OR IGNORE INTO note_links (source_id, target_key, position)
+             VALUES (?1, ?2, ?3)",
+            params![id, link.key, link.start],
+        )?;
+    }
+    Ok(())
+}
+
+impl Database {
+    pub fn create_note(&self, input: NoteInput) -> Result<Note> {
+        let input = input.normalize()?;
+        let id = new_id();
+        let key = key(&input.title);
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        ensure_unique(&tx, &key, &input.title, None)?;
+        tx.execute(
+            "INSERT INTO notes (id, title, key, body, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+            params![id, input.title, key, input.body, now_ms()],
+        )?;
+        write_links(&tx, &id, &input.body)?;
+        tx.commit()?;
+        drop(conn);
+        self.get_note(&id)
+    }
+
+    /// Saves a note. A new title is carried into every link to the old one.
+    pub fn update_note(&self, id: &str, input: NoteInput) -> Result<Note> {
+        let input = input.normalize()?;
+        let new_key = key(&input.title);
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let old_key: String = tx
+            .query_row("SELECT key FROM notes WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("note {id}")))?;
+        ensure_unique(&tx, &new_key, &input.title, Some(id))?;
+        let now = now_ms();
+        tx.execute(
+            "UPDATE notes SET title = ?2, key = ?3, body = ?4, updated_at = ?5 WHERE id = ?1",
+            params![id, input.title, new_key, input.body, now],
+        )?;
+        write_links(&tx, id, &input.body)?;
+        if new_key != old_key {
+            let linking: Vec<(String, String)> = {
+                let mut stmt = tx.prepare(
+                    "SELECT n.id, n.body FROM notes n
+                     WHERE n.id != ?1 AND EXISTS (
+                         SELECT 1 FROM note_links l
+                         WHERE l.source_id = n.id AND l.target_key = ?2)",
+                )?;
+                let rows =
+                    stmt.query_map(params![id, old_key], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect::<rusqlite::Result<_>>()?
+            };
+            for (other, body) in linking {
+                let body = retarget(&body, &old_key, &input.title);
+                tx.execute(
+                    "UPDATE notes SET body = ?2, updated_at = ?3 WHERE id = ?1",
+                    params![other, body, now],
+                )?;
+                write_links(&tx, &other, &body)?;
+            }
+        }
+        tx.commit()?;
+        drop(conn);
+        self.get_note(id)
+    }
+
+    pub fn get_note(&self, id: &str) -> Result<Note> {
+        let conn = self.conn()?;
+        find_note(&conn, id)?.complete(&conn)
+    }
+
+    /// Notes, most recently edited first; with `query`, only those whose
+    /// title or body contains it.
+    pub fn list_notes(&self, query: Option<&str>) -> Result<Vec<Note>> {
+        let query = query.map(str::trim).filter(|q| !q.is_empty());
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM notes
+             WHERE ?1 IS NULL OR instr(lower(title), lower(?1)) OR instr(lower(body), lower(?1))
+             ORDER BY updated_at DESC",
+            Note::COLUMNS
+        ))?;
+        let notes = stmt
+            .query_map([query], Note::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        notes.into_iter().map(|n| n.complete(&conn)).collect()
+    }
+
+    /// Deletes a note. Links to it stay in other notes and work again if a
+    /// note with the same title is written.
+    pub fn delete_note(&self, id: &str) -> Result<()> {
+        self.conn()?
+            .execute("DELETE FROM notes WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    /// Notes linking to `id`, most recently edited first, each with the
+    /// line of its first link.
+    pub fn backlinks(&self, id: &str) -> Result<Vec<Backlink>> {
+        let conn = self.conn()?;
+        let key: String = conn
+            .query_row("SELECT key FROM notes WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("note {id}")))?;
+        let mut stmt = conn.prepare(
+            "SELECT n.id, n.title, n.body, MIN(l.position), n.updated_at
+             FROM note_links l JOIN notes n ON n.id = l.source_id
+             WHERE l.target_key = ?1 AND n.id != ?2
+             GROUP BY n.id ORDER BY n.updated_at DESC",
+        )?;
+        let rows = stmt.query_map(params![key, id], |row| {
+            let body: String = row.get(2)?;
+            let position: usize = row.get(3)?;
+            Ok(Backlink {
+                note_id: row.get(0)?,
+                title: row.get(1)?,
+                context: context(&body, position.min(body.len())),
+                updated_at: row.get(4)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Attaches a note to a conversation, or to one of its messages.
+    pub fn link_note(
+        &self,
+        note_id: &str,
+        conversation_id: &str,
+        message_id: Option<&str>,
+    ) -> Result<Note> {
+        let conn = self.conn()?;
+        find_note(&conn, note_id)?;
+        let found: Option<String> = match message_id {
+            Some(message_id) => conn
+                .query_row(
+                    "SELECT conversation_id FROM messages WHERE id = ?1",
+                    [message_id],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .filter(|c: &String| c == conversation_id),
+            None => conn
+                .query_row(
+                    "SELECT id FROM conversations WHERE id = ?1",
+                    [conversation_id],
+                    |row| row.get(0),
+                )
+                .optional()?,
+        };
+        if found.is_none() {
+            let missing = match message_id {
+                Some(message_id) => format!("message {message_id} in {conversation_id}"),
+                None => format!("conversation {conversation_id}"),
+            };
+            return Err(Error::NotFound(missing));
+        }
+        conn.execute(
+            "INSERT OR IGNORE INTO note_references (note_id, conversation_id, message_id, created_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![note_id, conversation_id, message_id, now_ms()],
+        )?;
+        find_note(&conn, note_id)?.complete(&conn)
+    }
+
+    pub fn unlink_note(
+        &self,
+        note_id: &str,
+        conversation_id: &str,
+        message_id: Option<&str>,
+    ) -> Result<Note> {
+        let conn = self.conn()?;
+        conn.execute(
+            "DELETE FROM note_references
+             WHERE note_id = ?1 AND conversation_id = ?2 AND message_id IS ?3",
+            params![note_id, conversation_id, message_id],
+        )?;
+        find_note(&conn, note_id)?.complete(&conn)
+    }
+
+    /// Notes attached to a conversation or any of its messages.
+    pub fn conversation_notes(&self, conversation_id: &str) -> Result<Vec<Note>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM notes WHERE id IN (
+                 SELECT note_id FROM note_references WHERE conversation_id = ?1)
+             ORDER BY updated_at DESC",
+            Note::COLUMNS
+        ))?;
+        let notes = stmt
+            .query_map([conversation_id], Note::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        notes.into_iter().map(|n| n.complete(&conn)).collect()
+    }
+}
+
+#[tauri::command]
+pub async fn create_note(db: State<'_, Database>, note: NoteInput) -> Result<Note> {
+    db.create_note(note)
+}
+
+/// Saves a note; renaming it updates the links to it in other notes.
+#[tauri::command]
+pub async fn update_note(db: State<'_, Database>, id: String, note: NoteInput) -> Result<Note> {
+    db.update_note(&id, note)
+}
+
+#[tauri::command]
+pub async fn get_note(db: State<'_, Database>, id: String) -> Result<Note> {
+    db.get_note(&id)
+}
+
+#[tauri::command]
+pub async fn list_notes(db: State<'_, Database>, query: Option<String>) -> Result<Vec<Note>> {
+    db.list_notes(query.as_deref())
+}
+
+#[tauri::command]
+pub async fn delete_note(db: State<'_, Database>, id: String) -> Result<()> {
+    db.delete_note(&id)
+}
+
+#[tauri::command]
+pub async fn get_backlinks(db: State<'_, Database>, id: String) -> Result<Vec<Backlink>> {
+    db.backlinks(&id)
+}
+
+#[tauri::command]
+pub async fn link_note(
+    db: State<'_, Database>,
+    note_id: String,
+    conversation_id: String,
+    message_id: Option<String>,
+) -> Result<Note> {
+    db.link_note(&note_id, &conversation_id, message_id.as_deref())
+}
+
+#[tauri::command]
+pub async fn unlink_note(
+    db: State<'_, Database>,
+    note_id: String,
+    conversation_id: String,
+    message_id: Option<String>,
+) -> Result<Note> {
+    db.unlink_note(&note_id, &conversation_id, message_id.as_deref())
+}
+
+#[tauri::command]
+pub async fn list_conversation_notes(
+    db: State<'_, Database>,
+    conversation_id: String,
+) -> Result<Vec<Note>> {
+    db.conversation_notes(&conversation_id)
+}
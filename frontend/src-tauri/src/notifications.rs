@@ -0,0 +1,401 @@
+//! Desktop notifications: for fan-outs that finish while the window is
+//! hidden, and for whatever the frontend sends through `notify`.
+//!
+//! Each platform goes through its own facility: the freedesktop
+//! notification service on Linux, a toast from PowerShell on Windows, and
+//! `osascript` on macOS, which can't show buttons. Clicking a notification
+//! or one of its buttons emits `notification-action`; the `default` (the
+//! notification itself) and `open` actions also bring the main window up.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::fanout::FanoutResult;
+use crate::storage::new_id;
+use crate::windows;
+
+const CONFIG_FILE: &str = "notifications.json";
+/// Sent notifications remembered so a late click still finds its data.
+const MAX_SENT: usize = 50;
+/// Actions that bring the main window up as well as being emitted.
+const FOCUS_ACTIONS: [&str; 2] = ["default", "open"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationConfig {
+    pub enabled: bool,
+    /// Fan-outs quicker than this don't notify.
+    pub min_duration_secs: u64,
+    /// Providers whose results never notify on their own.
+    pub muted_providers: BTreeSet<String>,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_duration_secs: 10,
+            muted_providers: BTreeSet::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationAction {
+    pub id: String,
+    pub label: String,
+}
+
+/// What `notify` shows.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Notification {
+    pub title: String,
+    #[serde(default)]
+    pub body: String,
+    /// Buttons, where the platform shows them.
+    #[serde(default)]
+    pub actions: Vec<NotificationAction>,
+    /// Handed back in `notification-action`.
+    pub data: Option<Value>,
+}
+
+/// Payload of the `notification-action` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct NotificationActionEvent {
+    pub id: String,
+    /// `default` when the notification itself was clicked.
+    pub action: String,
+    pub data: Option<Value>,
+}
+
+/// Managed as Tauri state.
+pub struct Notifications {
+    config: Mutex<NotificationConfig>,
+    sent: Mutex<VecDeque<(String, Option<Value>)>>,
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<NotificationConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(Notifications {
+        config: Mutex::new(config),
+        sent: Mutex::new(VecDeque::new()),
+    });
+}
+
+/// Emits a click on notification `id`.
+fn activated(app: &AppHandle, id: &str, action: &str) {
+    if FOCUS_ACTIONS.contains(&action) {
+        windows::show_main_window(app);
+    }
+    let data = app
+        .state::<Notifications>()
+        .sent
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(sent, _)| sent == id)
+        .and_then(|(_, data)| data.clone());
+    let _ = app.emit(
+        "notification-action",
+        NotificationActionEvent {
+            id: id.to_string(),
+            action: action.to_string(),
+            data,
+        },
+    );
+}
+
+/// Handles `pentamind://notification/<id>/<action>`, which Windows toast
+/// buttons open. Returns whether `url` was one.
+pub fn handle_link(app: &AppHandle, url: &str) -> bool {
+    let Some(rest) = url.strip_prefix(&format!("{}://notification/", crate::deep_link::SCHEME))
+    else {
+        return false;
+    };
+    if let Some((id, action)) = rest.split_once('/') {
+        activated(app, id, action.trim_end_matches('/'));
+    }
+    true
+}
+
+#[cfg(target_os = "linux")]
+fn show(app: &AppHandle, id: &str, notification: &Notification) -> Result<()> {
+    use dbus::arg::PropMap;
+    use dbus::blocking::Connection;
+    use dbus::message::MatchRule;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    const SERVICE: &str = "org.freedesktop.Notifications";
+    const PATH: &str = "/org/freedesktop/Notifications";
+    /// How long clicks are listened for.
+    const LISTEN: Duration = Duration::from_secs(30 * 60);
+
+    let dbus_error = |e: dbus::Error| Error::Notification(e.to_string());
+    let conn = Connection::new_session().map_err(dbus_error)?;
+    let mut actions = vec!["default".to_string(), String::new()];
+    for action in &notification.actions {
+        actions.extend([action.id.clone(), action.label.clone()]);
+    }
+    let proxy = conn.with_proxy(SERVICE, PATH, Duration::from_secs(5));
+    let (handle,): (u32,) = proxy
+        .method_call(
+            SERVICE,
+            "Notify",
+            (
+                "Pentamind",
+                0u32,
+                "",
+                notification.title.as_str(),
+                notification.body.as_str(),
+                actions,
+                PropMap::new(),
+                -1i32,
+            ),
+        )
+        .map_err(dbus_error)?;
+
+    let done = Arc::new(AtomicBool::new(false));
+    let (app, id) = (app.clone(), id.to_string());
+    let clicked = done.clone();
+    conn.add_match(
+        MatchRule::new_signal(SERVICE, "ActionInvoked"),
+        move |(notification, action): (u32, String), _, _| {
+            if notification == handle {
+                activated(&app, &id, &action);
+                clicked.store(true, Ordering::SeqCst);
+            }
+            true
+        },
+    )
+    .map_err(dbus_error)?;
+    let closed = done.clone();
+    conn.add_match(
+        MatchRule::new_signal(SERVICE, "NotificationClosed"),
+        move |(notification, _reason): (u32, u32), _, _| {
+            if notification == handle {
+                closed.store(true, Ordering::SeqCst);
+            }
+            true
+        },
+    )
+    .map_err(dbus_error)?;
+    std::thread::spawn(move || {
+        let deadline = std::time::Instant::now() + LISTEN;
+        while !done.load(Ordering::SeqCst) && std::time::Instant::now() < deadline {
+            if conn.process(Duration::from_secs(1)).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(windows)]
+fn show(app: &AppHandle, id: &str, notification: &Notification) -> Result<()> {
+    /// Unpackaged debug builds borrow PowerShell's id; Windows drops toasts
+    /// from ids without a Start menu shortcut.
+    const POWERSHELL_APP_ID: &str =
+        r"{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}\WindowsPowerShell\v1.0\powershell.exe";
+    const SCRIPT: &str = r#"
+[Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] | Out-Null
+[Windows.Data.Xml.Dom.XmlDocument, Windows.Data.Xml.Dom.XmlDocument, ContentType = WindowsRuntime] | Out-Null
+$xml = New-Object Windows.Data.Xml.Dom.XmlDocument
+$xml.LoadXml($env:PENTAMIND_TOAST)
+$toast = New-Object Windows.UI.Notifications.ToastNotification $xml
+[Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier($env:PENTAMIND_APP_ID).Show($toast)"#;
+
+    let escape = |s: &str| {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    };
+    let link = |action: &str| {
+        escape(&format!(
+            "{}://notification/{id}/{action}",
+            crate::deep_link::SCHEME
+        ))
+    };
+    let buttons: String = notification
+        .actions
+        .iter()
+        .map(|action| {
+            format!(
+                r#"<action content="{}" activationType="protocol" arguments="{}"/>"#,
+                escape(&action.label),
+                link(&action.id)
+            )
+        })
+        .collect();
+    let toast = format!(
+        r#"<toast activationType="protocol" launch="{}"><visual><binding template="ToastGeneric"><text>{}</text><text>{}</text></binding></visual><actions>{buttons}</actions></toast>"#,
+        link("default"),
+        escape(&notification.title),
+        escape(&notification.body)
+    );
+    let app_id = if cfg!(debug_assertions) {
+        POWERSHELL_APP_ID.to_string()
+    } else {
+        app.config().identifier.clone()
+    };
+    let output = crate::process::command("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", SCRIPT])
+        .env("PENTAMIND_TOAST", toast)
+        .env("PENTAMIND_APP_ID", app_id)
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::Notification(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn show(_app: &AppHandle, _id: &str, notification: &Notification) -> Result<()> {
+    let output = crate::process::command("osascript")
+        .args([
+            "-e",
+            "on run argv",
+            "-e",
+            "display notification (item 2 of argv) with title (item 1 of argv)",
+            "-e",
+            "end run",
+            &notification.title,
+            &notification.body,
+        ])
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::Notification(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", windows)))]
+fn show(_app: &AppHandle, _id: &str, _notification: &Notification) -> Result<()> {
+    Err(Error::Unsupported("notifications on this platform".into()))
+}
+
+/// Shows `notification` and returns its id, which `notification-action`
+/// carries.
+pub async fn send(app: &AppHandle, notification: Notification) -> Result<String> {
+    let id = new_id();
+    {
+        let notifications = app.state::<Notifications>();
+        let mut sent = notifications.sent.lock().unwrap();
+        if sent.len() == MAX_SENT {
+            sent.pop_front();
+        }
+        sent.push_back((id.clone(), notification.data.clone()));
+    }
+    let (app, shown) = (app.clone(), id.clone());
+    tauri::async_runtime::spawn_blocking(move || show(&app, &shown, &notification)).await??;
+    Ok(id)
+}
+
+/// Whether notifications are turned on at all.
+pub fn enabled(app: &AppHandle) -> bool {
+    app.state::<Notifications>().config.lock().unwrap().enabled
+}
+
+/// Notifies that a fan-out finished, if it took long enough, the window is
+/// hidden and not every provider in it is muted.
+pub fn fanout_finished(
+    app: &AppHandle,
+    request_id: &str,
+    results: &[FanoutResult],
+    elapsed: Duration,
+) {
+    let config = app.state::<Notifications>().config.lock().unwrap().clone();
+    let visible = app
+        .get_webview_window(windows::MAIN_LABEL)
+        .is_some_and(|w| w.is_visible().unwrap_or(false));
+    let audible = results
+        .iter()
+        .any(|r| !config.muted_providers.contains(&r.provider));
+    if !config.enabled || visible || !audible || elapsed.as_secs() < config.min_duration_secs {
+        return;
+    }
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+    let answered = results.len() - failed;
+    let mut body = format!(
+        "{answered} response{} ready",
+        if answered == 1 { "" } else { "s" }
+    );
+    if failed > 0 {
+        body.push_str(&format!(", {failed} failed"));
+    }
+    let notification = Notification {
+        title: "Fan-out finished".into(),
+        body,
+        actions: vec![NotificationAction {
+            id: "open".into(),
+            label: "Show".into(),
+        }],
+        data: Some(serde_json::json!({ "request_id": request_id })),
+    };
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(err) = send(&app, notification).await {
+            tracing::warn!("couldn't show a notification: {err}");
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn notify(app: AppHandle, notification: Notification) -> Result<String> {
+    send(&app, notification).await
+}
+
+#[tauri::command]
+pub async fn get_notification_config(
+    notifications: State<'_, Notifications>,
+) -> Result<NotificationConfig> {
+    Ok(notifications.config.lock().unwrap().clone())
+}
+
+#[tauri::command]
+pub async fn set_notification_config(
+    app: AppHandle,
+    notifications: State<'_, Notifications>,
+    enabled: bool,
+    min_duration_secs: u64,
+) -> Result<NotificationConfig> {
+    let mut config = notifications.config.lock().unwrap();
+    config.enabled = enabled;
+    config.min_duration_secs = min_duration_secs;
+    config::write(&app, CONFIG_FILE, &*config)?;
+    Ok(config.clone())
+}
+
+/// Stops (or resumes) notifications for fan-outs that only involve
+/// `provider`.
+#[tauri::command]
+pub async fn set_provider_muted(
+    app: AppHandle,
+    notifications: State<'_, Notifications>,
+    provider: String,
+    muted: bool,
+) -> Result<NotificationConfig> {
+    let mut config = notifications.config.lock().unwrap();
+    if muted {
+        config.muted_providers.insert(provider);
+    } else {
+        config.muted_providers.remove(&provider);
+    }
+    config::write(&app, CONFIG_FILE, &*config)?;
+    Ok(config.clone())
+}
@@ -0,0 +1,125 @@
+//! Text extraction from screenshots and pasted images, so models without
+//! vision can still answer questions about what an image says. Recognition
+//! is done by the `tesseract` command-line tool, which must be installed.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::process::command;
+
+const DEFAULT_LANGUAGE: &str = "eng";
+
+/// The image to read: a file, or base64 data (with or without a `data:`
+/// URL prefix) as the webview has it for pasted images.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OcrSource {
+    Path(PathBuf),
+    Base64(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OcrResult {
+    pub text: String,
+    pub languages: Vec<String>,
+}
+
+fn unavailable(err: std::io::Error) -> Error {
+    if err.kind() == std::io::ErrorKind::NotFound {
+        Error::Unsupported("OCR without tesseract installed".into())
+    } else {
+        err.into()
+    }
+}
+
+fn image_bytes(source: OcrSource) -> Result<Vec<u8>> {
+    match source {
+        OcrSource::Path(path) => Ok(std::fs::read(path)?),
+        OcrSource::Base64(data) => {
+            let data = data
+                .split_once(";base64,")
+                .map_or(data.as_str(), |(_, d)| d);
+            base64::engine::general_purpose::STANDARD
+                .decode(data.trim())
+                .map_err(|e| Error::Ocr(format!("invalid image data: {e}")))
+        }
+    }
+}
+
+/// Collapses the blank-line runs tesseract leaves between blocks.
+fn tidy(text: &str) -> String {
+    let mut out = Vec::new();
+    for line in text.lines().map(str::trim_end) {
+        if line.is_empty() && out.last().is_none_or(|l: &&str| l.is_empty()) {
+            continue;
+        }
+        out.push(line);
+    }
+    out.join("\n").trim().to_string()
+}
+
+fn recognize(image: &[u8], languages: &[String]) -> Result<String> {
+    let mut child = command("tesseract")
+        .args(["stdin", "stdout", "-l", &languages.join("+")])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(unavailable)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(image)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // Progress chatter comes first; the reason is on the last line.
+        let message = stderr
+            .lines()
+            .rfind(|l| !l.trim().is_empty())
+            .unwrap_or_default();
+        return Err(Error::Ocr(message.to_string()));
+    }
+    Ok(tidy(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Extracts the text in an image. `languages` are tesseract language codes
+/// (`eng`, `deu`, ...) and default to English.
+#[tauri::command]
+pub async fn ocr_image(source: OcrSource, languages: Option<Vec<String>>) -> Result<OcrResult> {
+    let languages = languages
+        .filter(|l| !l.is_empty())
+        .unwrap_or_else(|| vec![DEFAULT_LANGUAGE.to_string()]);
+    tauri::async_runtime::spawn_blocking(move || {
+        let text = recognize(&image_bytes(source)?, &languages)?;
+        Ok(OcrResult { text, languages })
+    })
+    .await
+    .map_err(|e| Error::Ocr(e.to_string()))?
+}
+
+/// The language packs the installed tesseract has.
+#[tauri::command]
+pub async fn list_ocr_languages() -> Result<Vec<String>> {
+    let output = tauri::async_runtime::spawn_blocking(|| {
+        command("tesseract")
+            .arg("--list-langs")
+            .stdin(Stdio::null())
+            .output()
+            .map_err(unavailable)
+    })
+    .await
+    .map_err(|e| Error::Ocr(e.to_string()))??;
+    // The first line is a header naming the tessdata directory.
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && *l != "osd")
+        .map(str::to_string)
+        .collect())
+}
@@ -0,0 +1,426 @@
+//! Keeps prompting usable while the network is down. Connectivity is probed
+//! in the background and marked lost as soon as a request can't connect.
+//! While offline a fan-out goes to a local model if one is loaded or Ollama
+//! is running; otherwise it is queued in the database and replayed once the
+//! connection is back.
+//!
+//! The UI follows along through `offline` (the status, whenever it
+//! changes), `queued` (a prompt was put aside) and `replayed` (a queued
+//! prompt was sent; its tokens and results arrive under its request id
+//! like any fan-out).
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use reqwest::Client;
+use rusqlite::types::Type;
+use rusqlite::{params, Row};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::Notify;
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::fanout::{self, FanoutRequest, FanoutResult, FanoutTarget};
+use crate::incognito;
+use crate::providers::Providers;
+use crate::startup;
+use crate::storage::{new_id, now_ms, Database};
+
+const CONFIG_FILE: &str = "offline.json";
+/// How often connectivity is checked while online, and while offline.
+const ONLINE_INTERVAL: Duration = Duration::from_secs(30);
+const OFFLINE_INTERVAL: Duration = Duration::from_secs(5);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Providers that run on this machine, in order of preference.
+const LOCAL_PROVIDERS: [&str; 2] = ["local", "ollama"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OfflineConfig {
+    /// Fetched to tell whether the internet is reachable; any response
+    /// counts. Goes through the proxy like every other request.
+    pub probe_url: String,
+    /// Answer with a local model while offline instead of queueing.
+    pub local_fallback: bool,
+}
+
+impl Default for OfflineConfig {
+    fn default() -> Self {
+        Self {
+            probe_url: "https://www.gstatic.com/generate_204".into(),
+            local_fallback: true,
+        }
+    }
+}
+
+/// Payload of the `offline` event.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OfflineStatus {
+    pub offline: bool,
+    /// When the connection was lost.
+    pub since: Option<i64>,
+    /// Prompts waiting for the connection.
+    pub queued: usize,
+}
+
+/// A prompt put aside until the connection is back. Also the payload of
+/// the `queued` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueuedRequest {
+    pub id: String,
+    /// The fan-out's request id, which the replay keeps.
+    pub request_id: String,
+    pub request: FanoutRequest,
+    /// Replays that were cut short by the connection dropping again.
+    pub attempts: u32,
+    pub created_at: i64,
+}
+
+impl QueuedRequest {
+    const COLUMNS: &'static str = "id, request_id, request, attempts, created_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let request: String = row.get(2)?;
+        Ok(Self {
+            id: row.get(0)?,
+            request_id: row.get(1)?,
+            request: serde_json::from_str(&request).map_err(|err| {
+                rusqlite::Error::FromSqlConversionFailure(2, Type::Text, Box::new(err))
+            })?,
+            attempts: row.get(3)?,
+            created_at: row.get(4)?,
+        })
+    }
+}
+
+/// Payload of the `replayed` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct Replayed {
+    pub id: String,
+    pub request_id: String,
+    pub results: Vec<FanoutResult>,
+    /// Set when the fan-out couldn't start at all.
+    pub error: Option<String>,
+}
+
+impl Database {
+    fn queue_request(&self, queued: &QueuedRequest) -> Result<()> {
+        self.conn()?.execute(
+            &format!(
+                "INSERT INTO queued_requests ({}) VALUES (?1, ?2, ?3, ?4, ?5)",
+                QueuedRequest::COLUMNS
+            ),
+            params![
+                queued.id,
+                queued.request_id,
+                serde_json::to_string(&queued.request)?,
+                queued.attempts,
+                queued.created_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn list_queued_requests(&self) -> Result<Vec<QueuedRequest>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM queued_requests ORDER BY created_at",
+            QueuedRequest::COLUMNS
+        ))?;
+        let queued = stmt
+            .query_map([], QueuedRequest::from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(queued)
+    }
+
+    fn count_queued_requests(&self) -> Result<usize> {
+        let count: i64 =
+            self.conn()?
+                .query_row("SELECT COUNT(*) FROM queued_requests", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    fn bump_queued_request(&self, id: &str) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE queued_requests SET attempts = attempts + 1 WHERE id = ?1",
+            [id],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_queued_request(&self, id: &str) -> Result<()> {
+        let deleted = self
+            .conn()?
+            .execute("DELETE FROM queued_requests WHERE id = ?1", [id])?;
+        if deleted == 0 {
+            return Err(Error::NotFound(format!("queued request {id}")));
+        }
+        Ok(())
+    }
+}
+
+/// Managed as Tauri state.
+pub struct Offline {
+    config: Mutex<OfflineConfig>,
+    status: Mutex<OfflineStatus>,
+    /// Wakes the monitor to probe right away.
+    changed: Notify,
+    replaying: AtomicBool,
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<OfflineConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(Offline {
+        config: Mutex::new(config),
+        status: Mutex::new(OfflineStatus::default()),
+        changed: Notify::new(),
+        replaying: AtomicBool::new(false),
+    });
+    tauri::async_runtime::spawn(monitor(app.clone()));
+}
+
+pub fn is_offline(app: &AppHandle) -> bool {
+    app.try_state::<Offline>()
+        .is_some_and(|offline| offline.status.lock().unwrap().offline)
+}
+
+fn update_status(app: &AppHandle, update: impl FnOnce(&mut OfflineStatus)) {
+    let offline = app.state::<Offline>();
+    let (before, after) = {
+        let mut status = offline.status.lock().unwrap();
+        let before = (status.offline, status.queued);
+        update(&mut status);
+        (before, status.clone())
+    };
+    if before != (after.offline, after.queued) {
+        let _ = app.emit("offline", after);
+    }
+}
+
+fn set_offline(app: &AppHandle, offline: bool) {
+    update_status(app, |status| {
+        if status.offline != offline {
+            status.offline = offline;
+            status.since = offline.then(now_ms);
+        }
+    });
+}
+
+fn refresh_queued(app: &AppHandle) {
+    let queued = app
+        .state::<Database>()
+        .count_queued_requests()
+        .unwrap_or_default();
+    update_status(app, |status| status.queued = queued);
+}
+
+/// Marks the connection lost when `err` is a request to a remote provider
+/// that never reached its server. Called for every failed provider call.
+pub fn observe(app: &AppHandle, provider: &str, err: &Error) {
+    let unreachable = !LOCAL_PROVIDERS.contains(&provider)
+        && matches!(err, Error::Http(e) if e.is_connect() || e.is_timeout());
+    // Not tracked at all in headless runs.
+    let Some(offline) = app.try_state::<Offline>() else {
+        return;
+    };
+    if unreachable && !is_offline(app) {
+        tracing::info!("connection lost: {err}");
+        set_offline(app, true);
+        offline.changed.notify_one();
+    }
+}
+
+async fn probe(client: &Client, url: &str) -> bool {
+    client.head(url).timeout(PROBE_TIMEOUT).send().await.is_ok()
+}
+
+/// Probes connectivity on a timer, and replays the queue whenever the
+/// connection is up and something is waiting.
+async fn monitor(app: AppHandle) {
+    let offline = app.state::<Offline>();
+    let client = app.state::<Client>();
+    startup::warmed().await;
+    refresh_queued(&app);
+    loop {
+        let url = offline.config.lock().unwrap().probe_url.clone();
+        let reachable = probe(&client, &url).await;
+        if reachable == is_offline(&app) {
+            tracing::info!("connection {}", if reachable { "restored" } else { "lost" });
+        }
+        set_offline(&app, !reachable);
+        if reachable && offline.status.lock().unwrap().queued > 0 {
+            tauri::async_runtime::spawn(replay(app.clone()));
+        }
+        let interval = if reachable {
+            ONLINE_INTERVAL
+        } else {
+            OFFLINE_INTERVAL
+        };
+        tokio::select! {
+            _ = offline.changed.notified() => {}
+            _ = tokio::time::sleep(interval) => {}
+        }
+    }
+}
+
+/// Sends the queued prompts in the order they were asked, stopping if the
+/// connection drops again.
+async fn replay(app: AppHandle) {
+    let offline = app.state::<Offline>();
+    if offline.replaying.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let db = app.state::<Database>();
+    let queued = db.list_queued_requests().unwrap_or_default();
+    for item in queued {
+        if is_offline(&app) {
+            break;
+        }
+        let outcome = fanout::fan_out(&app, &item.request_id, &item.request).await;
+        if is_offline(&app) {
+            let _ = db.bump_queued_request(&item.id);
+            break;
+        }
+        let _ = db.delete_queued_request(&item.id);
+        let (results, error) = match outcome {
+            Ok(results) => (results, None),
+            Err(err) => (Vec::new(), Some(err.to_string())),
+        };
+        let _ = app.emit(
+            "replayed",
+            Replayed {
+                id: item.id,
+                request_id: item.request_id,
+                results,
+                error,
+            },
+        );
+        refresh_queued(&app);
+    }
+    offline.replaying.store(false, Ordering::SeqCst);
+}
+
+/// The local provider to answer with: a loaded model, else a running
+/// Ollama server.
+async fn local_provider(providers: &Providers, client: &Client) -> Option<&'static str> {
+    for id in LOCAL_PROVIDERS {
+        let Ok(provider) = providers.get(id) else {
+            continue;
+        };
+        if id == "ollama" {
+            providers.ollama().detect(client).await;
+        }
+        if provider.configured() {
+            return Some(provider.id());
+        }
+    }
+    None
+}
+
+/// `request` narrowed to the local providers it names, or sent to `local`
+/// when it names none.
+fn localize(
+    app: &AppHandle,
+    providers: &Providers,
+    request: FanoutRequest,
+    local: &str,
+) -> Result<FanoutRequest> {
+    let mut request = match &request.preset_id {
+        Some(id) => app
+            .state::<Database>()
+            .get_preset(id)?
+            .apply(app, &request, providers),
+        None => request,
+    };
+    let targets: Vec<FanoutTarget> = fanout::targets(app, &request, providers)
+        .into_iter()
+        .filter(|target| LOCAL_PROVIDERS.contains(&target.provider.as_str()))
+        .collect();
+    request.targets = if targets.is_empty() {
+        vec![FanoutTarget::new(local)]
+    } else {
+        targets
+    };
+    Ok(request)
+}
+
+/// What to do with a fan-out while offline: the request to run locally, or
+/// `Error::Queued` once it has been put aside for later.
+pub(crate) async fn route(
+    app: &AppHandle,
+    providers: &Providers,
+    client: &Client,
+    request_id: &str,
+    request: FanoutRequest,
+) -> Result<FanoutRequest> {
+    let fallback = app.state::<Offline>().config.lock().unwrap().local_fallback;
+    if fallback {
+        if let Some(local) = local_provider(providers, client).await {
+            return localize(app, providers, request, local);
+        }
+    }
+    if incognito::is_ephemeral_request(request.conversation_id.as_deref()) {
+        return Err(Error::Unsupported(
+            "queueing an incognito prompt while offline".to_string(),
+        ));
+    }
+    let queued = QueuedRequest {
+        id: new_id(),
+        request_id: request_id.to_string(),
+        request,
+        attempts: 0,
+        created_at: now_ms(),
+    };
+    app.state::<Database>().queue_request(&queued)?;
+    let _ = app.emit("queued", &queued);
+    refresh_queued(app);
+    Err(Error::Queued)
+}
+
+#[tauri::command]
+pub fn get_offline_status(offline: State<'_, Offline>) -> OfflineStatus {
+    offline.status.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn get_offline_config(offline: State<'_, Offline>) -> OfflineConfig {
+    offline.config.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn set_offline_config(
+    app: AppHandle,
+    offline: State<'_, Offline>,
+    config: OfflineConfig,
+) -> Result<OfflineConfig> {
+    reqwest::Url::parse(&config.probe_url)
+        .map_err(|e| Error::InvalidSetting(format!("probe URL {}: {e}", config.probe_url)))?;
+    config::write(&app, CONFIG_FILE, &config)?;
+    *offline.config.lock().unwrap() = config.clone();
+    offline.changed.notify_one();
+    Ok(config)
+}
+
+#[tauri::command]
+pub fn list_queued_requests(db: State<'_, Database>) -> Result<Vec<QueuedRequest>> {
+    db.list_queued_requests()
+}
+
+/// Drops a queued prompt without sending it.
+#[tauri::command]
+pub fn discard_queued_request(app: AppHandle, db: State<'_, Database>, id: String) -> Result<()> {
+    db.delete_queued_request(&id)?;
+    refresh_queued(&app);
+    Ok(())
+}
+
+/// Checks the connection now, replaying the queue if it is back.
+#[tauri::command]
+pub fn retry_queued_requests(offline: State<'_, Offline>) {
+    offline.changed.notify_one();
+}
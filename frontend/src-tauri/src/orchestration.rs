@@ -0,0 +1,662 @@
+//! Answers built in several passes. A pipeline is a list of stages, each
+//! run by one or more models in parallel and each working from what the
+//! stages before it wrote. The one mode so far is draft then critique: one
+//! model drafts, the others critique the draft, and a last pass merges the
+//! feedback into the final answer, which streams as `chat-token` events
+//! like `stream_chat`. Every model's start and finish is reported with an
+//! `orchestration-progress` event, and what each wrote is kept with the run.
+//! Debates are runs too, with their own turn order; see `debate`.
+
+use std::time::Instant;
+
+use reqwest::Client;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, OptionalExtension, Row, ToSql};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::task::JoinSet;
+
+use crate::arbiter::{self, ModelChoice};
+use crate::error::{Error, Result};
+use crate::incognito;
+use crate::llm::{self, ChatMessage, ChatRequest, Role};
+use crate::providers::priority;
+use crate::providers::Providers;
+use crate::requests::{cancellable, Requests};
+use crate::settings::SettingsStore;
+use crate::storage::{new_id, now_ms, Database};
+use crate::usage;
+
+const DRAFT_CRITIQUE: &str = "draft_critique";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageKind {
+    /// Answers the conversation as it stands.
+    Draft,
+    /// Reviews the latest draft without rewriting it.
+    Critique,
+    /// Rewrites the latest draft with the critiques that followed it.
+    Synthesis,
+    /// One debater's turn; see `debate`.
+    Argument,
+    /// A judge's scores for a round of a debate.
+    Verdict,
+}
+
+impl StageKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            StageKind::Draft => "draft",
+            StageKind::Critique => "critique",
+            StageKind::Synthesis => "synthesis",
+            StageKind::Argument => "argument",
+            StageKind::Verdict => "verdict",
+        }
+    }
+}
+
+impl ToSql for StageKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for StageKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "draft" => Ok(StageKind::Draft),
+            "critique" => Ok(StageKind::Critique),
+            "synthesis" => Ok(StageKind::Synthesis),
+            "argument" => Ok(StageKind::Argument),
+            "verdict" => Ok(StageKind::Verdict),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// One step of a pipeline, with the provider and model of everyone taking
+/// part in it.
+pub(crate) struct Stage {
+    pub kind: StageKind,
+    pub models: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl ToSql for RunStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let status = match self {
+            RunStatus::Running => "running",
+            RunStatus::Completed => "completed",
+            RunStatus::Failed => "failed",
+            RunStatus::Cancelled => "cancelled",
+        };
+        Ok(status.into())
+    }
+}
+
+impl FromSql for RunStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "running" => Ok(RunStatus::Running),
+            "completed" => Ok(RunStatus::Completed),
+            "failed" => Ok(RunStatus::Failed),
+            "cancelled" => Ok(RunStatus::Cancelled),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// What one model wrote at one stage.
+#[derive(Debug, Clone, Serialize)]
+pub struct Artifact {
+    /// Which stage, counted from 0 in the order they ran.
+    pub step: usize,
+    /// Which of the stage's models.
+    pub position: usize,
+    pub stage: StageKind,
+    pub provider: String,
+    pub model: String,
+    pub content: Option<String>,
+    pub error: Option<String>,
+    pub latency_ms: u64,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrchestrationRun {
+    pub id: String,
+    pub mode: String,
+    pub conversation_id: Option<String>,
+    pub status: RunStatus,
+    pub error: Option<String>,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    /// In the order they were written; empty in listings.
+    pub artifacts: Vec<Artifact>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageStatus {
+    Started,
+    Done,
+    Failed,
+}
+
+/// Payload of the `orchestration-progress` event. A started model has no
+/// content yet.
+#[derive(Debug, Clone, Serialize)]
+pub struct StageProgress {
+    pub run_id: String,
+    pub request_id: String,
+    pub status: StageStatus,
+    #[serde(flatten)]
+    pub artifact: Artifact,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DraftCritiqueRequest {
+    pub messages: Vec<ChatMessage>,
+    pub drafter: ModelChoice,
+    /// Empty means every other configured provider not turned off in the
+    /// settings.
+    #[serde(default)]
+    pub critics: Vec<ModelChoice>,
+    /// Merges the feedback; the drafter when unset.
+    #[serde(default)]
+    pub synthesizer: Option<ModelChoice>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub conversation_id: Option<String>,
+}
+
+impl OrchestrationRun {
+    const COLUMNS: &'static str =
+        "id, mode, conversation_id, status, error, started_at, finished_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            mode: row.get(1)?,
+            conversation_id: row.get(2)?,
+            status: row.get(3)?,
+            error: row.get(4)?,
+            started_at: row.get(5)?,
+            finished_at: row.get(6)?,
+            artifacts: Vec::new(),
+        })
+    }
+}
+
+impl Artifact {
+    /// An entry for a model that is about to start.
+    pub(crate) fn new(
+        step: usize,
+        position: usize,
+        stage: StageKind,
+        target: &(String, String),
+    ) -> Self {
+        Self {
+            step,
+            position,
+            stage,
+            provider: target.0.clone(),
+            model: target.1.clone(),
+            content: None,
+            error: None,
+            latency_ms: 0,
+            created_at: now_ms(),
+        }
+    }
+
+    const COLUMNS: &'static str =
+        "step, position, stage, provider, model, content, error, latency_ms, created_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            step: row.get(0)?,
+            position: row.get(1)?,
+            stage: row.get(2)?,
+            provider: row.get(3)?,
+            model: row.get(4)?,
+            content: row.get(5)?,
+            error: row.get(6)?,
+            latency_ms: row.get(7)?,
+            created_at: row.get(8)?,
+        })
+    }
+}
+
+impl Database {
+    fn insert_orchestration_run(
+        &self,
+        run: &OrchestrationRun,
+        request: &impl Serialize,
+    ) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO orchestration_runs
+                 (id, mode, conversation_id, request, status, started_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                run.id,
+                run.mode,
+                run.conversation_id,
+                serde_json::to_string(request)?,
+                run.status,
+                run.started_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn finish_orchestration_run(
+        &self,
+        id: &str,
+        status: RunStatus,
+        error: Option<&str>,
+    ) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE orchestration_runs SET status = ?2, error = ?3, finished_at = ?4
+             WHERE id = ?1",
+            params![id, status, error, now_ms()],
+        )?;
+        Ok(())
+    }
+
+    fn save_artifact(&self, run_id: &str, artifact: &Artifact) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO orchestration_artifacts
+                 (run_id, step, position, stage, provider, model, content, error,
+                  latency_ms, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                run_id,
+                artifact.step,
+                artifact.position,
+                artifact.stage,
+                artifact.provider,
+                artifact.model,
+                artifact.content,
+                artifact.error,
+                artifact.latency_ms,
+                artifact.created_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn get_orchestration_run(&self, id: &str) -> Result<OrchestrationRun> {
+        let conn = self.conn()?;
+        let mut run = conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM orchestration_runs WHERE id = ?1",
+                    OrchestrationRun::COLUMNS
+                ),
+                [id],
+                OrchestrationRun::from_row,
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("orchestration run {id}")))?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM orchestration_artifacts WHERE run_id = ?1 ORDER BY step, position",
+            Artifact::COLUMNS
+        ))?;
+        run.artifacts = stmt
+            .query_map([id], Artifact::from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(run)
+    }
+
+    fn list_orchestration_runs(
+        &self,
+        conversation_id: Option<&str>,
+    ) -> Result<Vec<OrchestrationRun>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM orchestration_runs
+             WHERE ?1 IS NULL OR conversation_id = ?1
+             ORDER BY started_at DESC",
+            OrchestrationRun::COLUMNS
+        ))?;
+        let rows = stmt.query_map([conversation_id], OrchestrationRun::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+/// The prompt added after the conversation for a stage, given what the
+/// earlier stages wrote. A draft needs none.
+fn stage_prompt(kind: StageKind, done: &[Artifact]) -> Option<String> {
+    // The text being worked on is the latest draft or synthesis.
+    let (at, draft) = done.iter().enumerate().rev().find_map(|(i, a)| {
+        let content = a.content.as_deref()?;
+        (a.stage != StageKind::Critique).then_some((i, content.trim()))
+    })?;
+    match kind {
+        StageKind::Draft | StageKind::Argument | StageKind::Verdict => None,
+        StageKind::Critique => Some(format!(
+            "An assistant drafted the answer below to the last message above. \
+             Critique it: point out mistakes, gaps and anything unclear, and say \
+             how to fix each. Don't rewrite the answer.\n\n## Draft\n{draft}"
+        )),
+        StageKind::Synthesis => {
+            let critiques = done[at + 1..]
+                .iter()
+                .filter(|a| a.stage == StageKind::Critique)
+                .filter_map(|a| a.content.as_deref())
+                .enumerate()
+                .map(|(i, c)| format!("### Critique {}\n{}\n", i + 1, c.trim()))
+                .collect::<Vec<_>>()
+                .join("\n");
+            Some(format!(
+                "An assistant drafted the answer below to the last message above, \
+                 and reviewers critiqued it. Write the final answer: keep what the \
+                 draft gets right, fix what the critiques rightly point out, and \
+                 ignore criticism that doesn't hold up. Reply with the answer alone, \
+                 without mentioning the draft or the reviewers.\n\n\
+                 ## Draft\n{draft}\n\n## Critiques\n{critiques}"
+            ))
+        }
+    }
+}
+
+/// Starts a run of `mode`, keeping `request` with it.
+pub(crate) fn begin(
+    db: &Database,
+    mode: &str,
+    conversation_id: Option<String>,
+    request: &impl Serialize,
+) -> Result<OrchestrationRun> {
+    if incognito::is_ephemeral_request(conversation_id.as_deref()) {
+        return Err(Error::Unsupported(
+            "keeping a run of an incognito conversation".to_string(),
+        ));
+    }
+    let run = OrchestrationRun {
+        id: new_id(),
+        mode: mode.to_string(),
+        conversation_id,
+        status: RunStatus::Running,
+        error: None,
+        started_at: now_ms(),
+        finished_at: None,
+        artifacts: Vec::new(),
+    };
+    db.insert_orchestration_run(&run, request)?;
+    Ok(run)
+}
+
+/// Records how a run ended.
+pub(crate) fn finish<T>(db: &Database, run_id: &str, outcome: &Result<T>) -> Result<()> {
+    let (status, error) = match outcome {
+        Ok(_) => (RunStatus::Completed, None),
+        Err(Error::Cancelled) => (RunStatus::Cancelled, None),
+        Err(err) => (RunStatus::Failed, Some(err.to_string())),
+    };
+    db.finish_orchestration_run(run_id, status, error.as_deref())
+}
+
+/// Emits `orchestration-progress` for `artifact`, saving it once its model
+/// is done.
+pub(crate) fn report(
+    app: &AppHandle,
+    run_id: &str,
+    request_id: &str,
+    status: StageStatus,
+    artifact: &Artifact,
+) {
+    if status != StageStatus::Started {
+        if let Err(err) = app.state::<Database>().save_artifact(run_id, artifact) {
+            tracing::warn!("couldn't save an orchestration artifact: {err}");
+        }
+    }
+    let _ = app.emit(
+        "orchestration-progress",
+        StageProgress {
+            run_id: run_id.to_string(),
+            request_id: request_id.to_string(),
+            status,
+            artifact: artifact.clone(),
+        },
+    );
+}
+
+/// Runs the stages in order and returns everything they wrote. A stage
+/// fails only when all its models do. The last stage streams under
+/// `request_id` when a single model runs it.
+async fn execute(
+    app: &AppHandle,
+    providers: &Providers,
+    client: &Client,
+    request_id: &str,
+    run_id: &str,
+    stages: &[Stage],
+    template: &ChatRequest,
+) -> Result<Vec<Artifact>> {
+    let progress = |status: StageStatus, artifact: &Artifact| {
+        report(app, run_id, request_id, status, artifact);
+    };
+    let mut done: Vec<Artifact> = Vec::new();
+    for (step, stage) in stages.iter().enumerate() {
+        let mut messages = template.messages.clone();
+        if let Some(prompt) = stage_prompt(stage.kind, &done) {
+            messages.push(ChatMessage {
+                role: Role::User,
+                content: prompt,
+            });
+        }
+        let artifact = |position: usize, target: &(String, String)| {
+            Artifact::new(step, position, stage.kind, target)
+        };
+        let chat = |(provider, model): &(String, String)| ChatRequest {
+            provider: provider.clone(),
+            model: model.clone(),
+            messages: messages.clone(),
+            ..template.clone()
+        };
+
+        let streamed = step + 1 == stages.len() && stage.models.len() == 1;
+        let mut finished: Vec<(Artifact, Option<Error>)> = Vec::new();
+        if streamed {
+            let target = &stage.models[0];
+            let mut entry = artifact(0, target);
+            progress(StageStatus::Started, &entry);
+            let started = Instant::now();
+            let outcome = llm::run_stream(app, providers, client, request_id, &chat(target)).await;
+            entry.latency_ms = started.elapsed().as_millis() as u64;
+            finished.push(match outcome {
+                Ok(response) => {
+                    entry.content = Some(response.content);
+                    (entry, None)
+                }
+                Err(err) => (entry, Some(err)),
+            });
+        } else {
+            let mut tasks = JoinSet::new();
+            for (position, target) in stage.models.iter().enumerate() {
+                let entry = artifact(position, target);
+                progress(StageStatus::Started, &entry);
+                let provider = providers.get(&target.0)?;
+                let request = chat(target);
+                let app = app.clone();
+                let client = client.clone();
+                tasks.spawn(priority::inherit(async move {
+                    let started = Instant::now();
+                    let outcome = provider.stream(&client, &request, &mut |_| {}).await;
+                    if let Ok(completion) = &outcome {
+                        usage::record(&app, &request, &completion.content, completion.usage);
+                    }
+                    let mut entry = entry;
+                    entry.latency_ms = started.elapsed().as_millis() as u64;
+                    match outcome {
+                        Ok(completion) => {
+                            entry.content = Some(completion.content);
+                            (entry, None)
+                        }
+                        Err(err) => (entry, Some(err)),
+                    }
+                }));
+            }
+            while let Some(joined) = tasks.join_next().await {
+                if let Ok(result) = joined {
+                    finished.push(result);
+                }
+            }
+            finished.sort_by_key(|(entry, _)| entry.position);
+        }
+
+        let mut first_error = None;
+        for (mut entry, err) in finished {
+            match err {
+                None => progress(StageStatus::Done, &entry),
+                Some(err) => {
+                    entry.error = Some(err.to_string());
+                    progress(StageStatus::Failed, &entry);
+                    first_error.get_or_insert(err);
+                }
+            }
+            done.push(entry);
+        }
+        let answered = done.iter().any(|a| a.step == step && a.content.is_some());
+        if !answered {
+            return Err(first_error.unwrap_or_else(|| {
+                Error::Provider(format!(
+                    "no model finished the {} stage",
+                    stage.kind.as_str()
+                ))
+            }));
+        }
+    }
+    Ok(done)
+}
+
+/// Every configured provider not turned off in the settings, other than
+/// `except`, each with its default model.
+fn other_providers(app: &AppHandle, providers: &Providers, except: &str) -> Vec<(String, String)> {
+    let disabled = app.state::<SettingsStore>().get().disabled_providers;
+    providers
+        .all()
+        .filter(|p| p.configured() && p.id() != except && !disabled.iter().any(|d| d == p.id()))
+        .map(|p| (p.id().to_string(), p.default_model().to_string()))
+        .collect()
+}
+
+/// The stages of a draft then critique run.
+pub(crate) fn draft_critique(
+    app: &AppHandle,
+    providers: &Providers,
+    request: &DraftCritiqueRequest,
+) -> Result<Vec<Stage>> {
+    let drafter = arbiter::resolve(providers, &request.drafter)?;
+    let critics = if request.critics.is_empty() {
+        other_providers(app, providers, &drafter.0)
+    } else {
+        request
+            .critics
+            .iter()
+            .map(|choice| arbiter::resolve(providers, choice))
+            .collect::<Result<_>>()?
+    };
+    if critics.is_empty() {
+        return Err(Error::InvalidSetting(
+            "a draft needs at least one other configured provider to critique it".into(),
+        ));
+    }
+    let synthesizer = match &request.synthesizer {
+        Some(choice) => arbiter::resolve(providers, choice)?,
+        None => drafter.clone(),
+    };
+    Ok(vec![
+        Stage {
+            kind: StageKind::Draft,
+            models: vec![drafter],
+        },
+        Stage {
+            kind: StageKind::Critique,
+            models: critics,
+        },
+        Stage {
+            kind: StageKind::Synthesis,
+            models: vec![synthesizer],
+        },
+    ])
+}
+
+/// Drafts, critiques and synthesises an answer, streaming the final pass
+/// under `request_id`. Cancelling the request stops whichever stage is
+/// running; what finished before is kept.
+#[tauri::command]
+pub async fn run_draft_critique(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    requests: State<'_, Requests>,
+    db: State<'_, Database>,
+    request_id: String,
+    request: DraftCritiqueRequest,
+) -> Result<OrchestrationRun> {
+    let stages = draft_critique(&app, &providers, &request)?;
+    let run = begin(
+        &db,
+        DRAFT_CRITIQUE,
+        request.conversation_id.clone(),
+        &request,
+    )?;
+
+    let template = ChatRequest {
+        provider: String::new(),
+        model: String::new(),
+        messages: request.messages,
+        temperature: request.temperature,
+        top_p: None,
+        max_tokens: request.max_tokens,
+        use_tools: false,
+        bypass_cache: false,
+        response_schema: None,
+        conversation_id: request.conversation_id,
+        tools: Vec::new(),
+        tool_rounds: Vec::new(),
+    };
+    let guard = requests.register(&request_id);
+    let outcome = cancellable(
+        guard.token(),
+        execute(
+            &app,
+            &providers,
+            &client,
+            &request_id,
+            &run.id,
+            &stages,
+            &template,
+        ),
+    )
+    .await;
+    finish(&db, &run.id, &outcome)?;
+    outcome?;
+    db.get_orchestration_run(&run.id)
+}
+
+/// A run with everything its stages wrote.
+#[tauri::command]
+pub fn get_orchestration_run(db: State<'_, Database>, run_id: String) -> Result<OrchestrationRun> {
+    db.get_orchestration_run(&run_id)
+}
+
+/// Runs newest first, all of them or one conversation's.
+#[tauri::command]
+pub fn list_orchestration_runs(
+    db: State<'_, Database>,
+    conversation_id: Option<String>,
+) -> Result<Vec<OrchestrationRun>> {
+    db.list_orchestration_runs(conversation_id.as_deref())
+}
@@ -0,0 +1,426 @@
+//! Makes conversations findable from the OS search: Spotlight on macOS,
+//! Windows Search on Windows. Each conversation is indexed by its title and
+//! summary as they change, and removed when it is deleted.
+//!
+//! On macOS the items go to Core Spotlight's default index under one
+//! domain. On Windows every conversation becomes a small HTML page in
+//! `search/` in the data directory, which is added to the indexer's crawl
+//! scope; opening one follows a `pentamind://` link back to the
+//! conversation. Nothing is indexed while the database is encrypted, since
+//! that would leave the titles readable outside it, and turning the
+//! setting off takes everything back out.
+
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::startup;
+use crate::storage::conversations::{Conversation, DEFAULT_TITLE};
+use crate::storage::Database;
+
+const CONFIG_FILE: &str = "os_search.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OsSearchConfig {
+    pub enabled: bool,
+}
+
+impl Default for OsSearchConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// What the OS gets to see of a conversation.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(any(target_os = "macos", windows)), allow(dead_code))]
+struct Entry {
+    id: String,
+    title: String,
+    summary: Option<String>,
+}
+
+impl Entry {
+    /// `None` for a conversation with nothing worth finding yet.
+    fn of(conversation: &Conversation) -> Option<Self> {
+        let untitled = conversation.title == DEFAULT_TITLE;
+        (!untitled || conversation.summary.is_some()).then(|| Self {
+            id: conversation.id.clone(),
+            title: conversation.title.clone(),
+            summary: conversation.summary.clone(),
+        })
+    }
+}
+
+/// Managed as Tauri state.
+pub struct OsSearch {
+    config: Mutex<OsSearchConfig>,
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<OsSearchConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    let enabled = config.enabled;
+    app.manage(OsSearch {
+        config: Mutex::new(config),
+    });
+    // Catches up on whatever changed while the app wasn't running.
+    if enabled && platform::SUPPORTED {
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            startup::warmed().await;
+            let indexed = tauri::async_runtime::spawn_blocking(move || reindex(&app)).await;
+            if let Ok(Err(err)) = indexed {
+                tracing::warn!("couldn't index conversations for OS search: {err}");
+            }
+        });
+    }
+}
+
+fn active(app: &AppHandle) -> bool {
+    platform::SUPPORTED
+        && app
+            .try_state::<OsSearch>()
+            .is_some_and(|s| s.config.lock().unwrap().enabled)
+        && !app.state::<Database>().is_encrypted()
+}
+
+/// Replaces everything in the OS index with the current conversations,
+/// returning how many went in.
+fn reindex(app: &AppHandle) -> Result<usize> {
+    let db = app.state::<Database>();
+    if db.is_encrypted() {
+        platform::clear(app)?;
+        return Ok(0);
+    }
+    let entries: Vec<Entry> = db
+        .list_conversations()?
+        .iter()
+        .filter_map(Entry::of)
+        .collect();
+    platform::clear(app)?;
+    platform::index(app, &entries)?;
+    Ok(entries.len())
+}
+
+/// Brings a conversation's entry up to date after its title or summary
+/// changed.
+pub fn conversation_updated(app: &AppHandle, conversation: &Conversation) {
+    if !active(app) {
+        return;
+    }
+    let (app, conversation) = (app.clone(), conversation.clone());
+    tauri::async_runtime::spawn_blocking(move || {
+        let outcome = match Entry::of(&conversation) {
+            Some(entry) => platform::index(&app, &[entry]),
+            None => platform::remove(&app, std::slice::from_ref(&conversation.id)),
+        };
+        if let Err(err) = outcome {
+            tracing::warn!("couldn't index {} for OS search: {err}", conversation.id);
+        }
+    });
+}
+
+pub fn conversation_deleted(app: &AppHandle, conversation_id: &str) {
+    if !active(app) {
+        return;
+    }
+    let (app, id) = (app.clone(), conversation_id.to_string());
+    tauri::async_runtime::spawn_blocking(move || {
+        if let Err(err) = platform::remove(&app, std::slice::from_ref(&id)) {
+            tracing::warn!("couldn't remove {id} from OS search: {err}");
+        }
+    });
+}
+
+/// Takes everything out of the OS index once the database is encrypted.
+pub fn database_encrypted(app: &AppHandle) {
+    if !platform::SUPPORTED {
+        return;
+    }
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        if let Err(err) = platform::clear(&app) {
+            tracing::warn!("couldn't clear the OS search index: {err}");
+        }
+    });
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use objc2::msg_send;
+    use objc2::rc::{autoreleasepool, Allocated, Retained};
+    use objc2::runtime::{AnyClass, AnyObject};
+    use objc2_foundation::{NSArray, NSError, NSString};
+    use tauri::AppHandle;
+
+    use super::Entry;
+    use crate::error::{Error, Result};
+
+    pub const SUPPORTED: bool = true;
+    const DOMAIN: &str = "conversations";
+
+    #[link(name = "CoreSpotlight", kind = "framework")]
+    extern "C" {}
+
+    type Completion = block2::DynBlock<dyn Fn(*mut NSError)>;
+
+    fn class(name: &std::ffi::CStr) -> Result<&'static AnyClass> {
+        AnyClass::get(name).ok_or_else(|| Error::OsSearch("Core Spotlight isn't available".into()))
+    }
+
+    fn default_index() -> Result<Retained<AnyObject>> {
+        let index: Option<Retained<AnyObject>> =
+            unsafe { msg_send![class(c"CSSearchableIndex")?, defaultSearchableIndex] };
+        index.ok_or_else(|| Error::OsSearch("no Spotlight index".into()))
+    }
+
+    pub fn index(_app: &AppHandle, entries: &[Entry]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        autoreleasepool(|_| {
+            let attribute_set = class(c"CSSearchableItemAttributeSet")?;
+            let item = class(c"CSSearchableItem")?;
+            let domain = NSString::from_str(DOMAIN);
+            let content_type = NSString::from_str("public.text");
+            let items: Vec<Retained<AnyObject>> = entries
+                .iter()
+                .map(|entry| unsafe {
+                    let allocated: Allocated<AnyObject> = msg_send![attribute_set, alloc];
+                    let attributes: Retained<AnyObject> =
+                        msg_send![allocated, initWithItemContentType: &*content_type];
+                    let _: () =
+                        msg_send![&attributes, setTitle: &*NSString::from_str(&entry.title)];
+                    if let Some(summary) = &entry.summary {
+                        let _: () = msg_send![
+                            &attributes,
+                            setContentDescription: &*NSString::from_str(summary)
+                        ];
+                    }
+                    let allocated: Allocated<AnyObject> = msg_send![item, alloc];
+                    msg_send![
+                        allocated,
+                        initWithUniqueIdentifier: &*NSString::from_str(&entry.id),
+                        domainIdentifier: &*domain,
+                        attributeSet: &*attributes
+                    ]
+                })
+                .collect();
+            let items = NSArray::from_retained_slice(&items);
+            let index = default_index()?;
+            unsafe {
+                let _: () = msg_send![
+                    &index,
+                    indexSearchableItems: &*items,
+                    completionHandler: None::<&Completion>
+                ];
+            }
+            Ok(())
+        })
+    }
+
+    pub fn remove(_app: &AppHandle, ids: &[String]) -> Result<()> {
+        autoreleasepool(|_| {
+            let ids: Vec<Retained<NSString>> =
+                ids.iter().map(|id| NSString::from_str(id)).collect();
+            let ids = NSArray::from_retained_slice(&ids);
+            let index = default_index()?;
+            unsafe {
+                let _: () = msg_send![
+                    &index,
+                    deleteSearchableItemsWithIdentifiers: &*ids,
+                    completionHandler: None::<&Completion>
+                ];
+            }
+            Ok(())
+        })
+    }
+
+    pub fn clear(_app: &AppHandle) -> Result<()> {
+        autoreleasepool(|_| {
+            let domains = NSArray::from_retained_slice(&[NSString::from_str(DOMAIN)]);
+            let index = default_index()?;
+            unsafe {
+                let _: () = msg_send![
+                    &index,
+                    deleteSearchableItemsWithDomainIdentifiers: &*domains,
+                    completionHandler: None::<&Completion>
+                ];
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::path::{Path, PathBuf};
+
+    use tauri::AppHandle;
+    use windows::core::{w, HSTRING};
+    use windows::Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, CLSCTX_LOCAL_SERVER, COINIT_MULTITHREADED,
+    };
+    use windows::Win32::System::Search::{CSearchManager, ISearchManager};
+
+    use super::Entry;
+    use crate::deep_link;
+    use crate::error::{Error, Result};
+    use crate::markdown::escape_html;
+    use crate::profile;
+
+    pub const SUPPORTED: bool = true;
+    const DIR: &str = "search";
+
+    fn dir(app: &AppHandle) -> Result<PathBuf> {
+        Ok(profile::data_dir(app)?.join(DIR))
+    }
+
+    fn page(dir: &Path, id: &str) -> PathBuf {
+        dir.join(format!("{id}.html"))
+    }
+
+    /// Adds `dir` to the indexer's crawl scope and asks for it to be read
+    /// again.
+    fn register(dir: &Path) -> Result<()> {
+        let os_error = |err: windows::core::Error| Error::OsSearch(err.message());
+        let url = HSTRING::from(format!("file:///{}\\", dir.display()));
+        unsafe {
+            // Already initialised on this thread is fine.
+            let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+            let manager: ISearchManager =
+                CoCreateInstance(&CSearchManager, None, CLSCTX_LOCAL_SERVER).map_err(os_error)?;
+            let catalog = manager.GetCatalog(w!("SystemIndex")).map_err(os_error)?;
+            let scope = catalog.GetCrawlScopeManager().map_err(os_error)?;
+            scope
+                .AddUserScopeRule(&url, true, false, 0)
+                .map_err(os_error)?;
+            scope.SaveAll().map_err(os_error)?;
+            catalog.ReindexSearchRoot(&url).map_err(os_error)?;
+        }
+        Ok(())
+    }
+
+    pub fn index(app: &AppHandle, entries: &[Entry]) -> Result<()> {
+        let dir = dir(app)?;
+        let fresh = !dir.exists();
+        std::fs::create_dir_all(&dir)?;
+        for entry in entries {
+            let link = format!("{}://conversation/{}", deep_link::SCHEME, entry.id);
+            let summary = entry.summary.as_deref().unwrap_or_default();
+            let html = format!(
+                "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+                 <meta http-equiv=\"refresh\" content=\"0; url={link}\">\
+                 <meta name=\"description\" content=\"{description}\">\
+                 <title>{title}</title></head>\
+                 <body><h1>{title}</h1><p>{description}</p><a href=\"{link}\">Open in Pentamind</a></body></html>\n",
+                title = escape_html(&entry.title),
+                description = escape_html(summary),
+            );
+            std::fs::write(page(&dir, &entry.id), html)?;
+        }
+        if fresh {
+            if let Err(err) = register(&dir) {
+                tracing::warn!("couldn't add {} to Windows Search: {err}", dir.display());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(app: &AppHandle, ids: &[String]) -> Result<()> {
+        let dir = dir(app)?;
+        for id in ids {
+            match std::fs::remove_file(page(&dir, id)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clear(app: &AppHandle) -> Result<()> {
+        let dir = dir(app)?;
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            return Ok(());
+        };
+        for entry in entries.flatten() {
+            if entry.path().extension().is_some_and(|e| e == "html") {
+                std::fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+mod platform {
+    use tauri::AppHandle;
+
+    use super::Entry;
+    use crate::error::Result;
+
+    pub const SUPPORTED: bool = false;
+
+    pub fn index(_app: &AppHandle, _entries: &[Entry]) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn remove(_app: &AppHandle, _ids: &[String]) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn clear(_app: &AppHandle) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[tauri::command]
+pub fn get_os_search_config(search: State<'_, OsSearch>) -> OsSearchConfig {
+    search.config.lock().unwrap().clone()
+}
+
+/// Turning it off removes every conversation from the OS index; turning it
+/// on indexes them all.
+#[tauri::command]
+pub async fn set_os_search_config(
+    app: AppHandle,
+    search: State<'_, OsSearch>,
+    config: OsSearchConfig,
+) -> Result<OsSearchConfig> {
+    config::write(&app, CONFIG_FILE, &config)?;
+    let was = std::mem::replace(&mut *search.config.lock().unwrap(), config.clone());
+    if platform::SUPPORTED && was.enabled != config.enabled {
+        let enabled = config.enabled;
+        tauri::async_runtime::spawn_blocking(move || {
+            if enabled {
+                reindex(&app).map(drop)
+            } else {
+                platform::clear(&app)
+            }
+        })
+        .await??;
+    }
+    Ok(config)
+}
+
+/// Rebuilds the OS index from scratch, returning how many conversations
+/// are in it.
+#[tauri::command]
+pub async fn reindex_os_search(app: AppHandle) -> Result<usize> {
+    if !platform::SUPPORTED {
+        return Err(Error::Unsupported("OS search on this platform".into()));
+    }
+    if !app.state::<OsSearch>().config.lock().unwrap().enabled {
+        return Err(Error::InvalidSetting(
+            "OS search indexing is turned off".into(),
+        ));
+    }
+    tauri::async_runtime::spawn_blocking(move || reindex(&app)).await?
+}
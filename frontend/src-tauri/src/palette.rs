@@ -0,0 +1,366 @@
+//! The command palette's search. Conversations, templates, settings and
+//! actions are matched against what was typed with a subsequence matcher in
+//! the spirit of fzf: every query character has to appear in order, and
+//! matches at word starts, in runs and early in the text rank higher. The
+//! ranked list comes back with the matched positions for highlighting.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, State};
+
+use crate::error::Result;
+use crate::settings::Settings;
+use crate::shortcuts;
+use crate::storage::Database;
+
+const DEFAULT_LIMIT: usize = 50;
+/// Shown for an empty query.
+const RECENT_CONVERSATIONS: usize = 10;
+
+const SCORE_MATCH: i64 = 16;
+const BONUS_BOUNDARY: i64 = 10;
+const BONUS_CAMEL: i64 = 8;
+const BONUS_CONSECUTIVE: i64 = 6;
+const BONUS_FIRST_CHAR: i64 = 12;
+const PENALTY_GAP_START: i64 = 3;
+const PENALTY_GAP: i64 = 1;
+/// Matching only a secondary field (a description or keyword) counts for
+/// this fraction of the score.
+const SECONDARY_DIVISOR: i64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaletteKind {
+    Action,
+    Conversation,
+    Template,
+    Setting,
+}
+
+impl PaletteKind {
+    /// Breaks ties between kinds: actions first, as in most palettes.
+    fn weight(self) -> i64 {
+        match self {
+            Self::Action => 3,
+            Self::Conversation => 2,
+            Self::Template => 1,
+            Self::Setting => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PaletteResult {
+    pub kind: PaletteKind,
+    /// A conversation or template id, a setting's key, or an action name.
+    pub id: String,
+    pub title: String,
+    pub subtitle: Option<String>,
+    /// Binding for actions that have one.
+    pub shortcut: Option<String>,
+    pub score: i64,
+    /// Character positions in `title` that matched.
+    pub matches: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PaletteQuery {
+    pub query: String,
+    /// Only these kinds; all when empty.
+    pub kinds: Vec<PaletteKind>,
+    pub limit: Option<usize>,
+}
+
+struct Action {
+    id: &'static str,
+    title: &'static str,
+    keywords: &'static str,
+}
+
+/// Actions the frontend carries out, by the names `menu-action` and the
+/// shortcut registry use.
+const ACTIONS: &[Action] = &[
+    Action {
+        id: "new_conversation",
+        title: "New Conversation",
+        keywords: "chat create start",
+    },
+    Action {
+        id: "open_in_window",
+        title: "Open Conversation in New Window",
+        keywords: "detach window",
+    },
+    Action {
+        id: "export",
+        title: "Export Conversation",
+        keywords: "save markdown pdf json",
+    },
+    Action {
+        id: "share_html",
+        title: "Share as HTML",
+        keywords: "export page email",
+    },
+    Action {
+        id: "find",
+        title: "Find in Conversation",
+        keywords: "search",
+    },
+    Action {
+        id: "search_messages",
+        title: "Search All Messages",
+        keywords: "find history",
+    },
+    Action {
+        id: "toggle_sidebar",
+        title: "Toggle Sidebar",
+        keywords: "hide show panel",
+    },
+    Action {
+        id: "toggle_mini_window",
+        title: "Toggle Mini Window",
+        keywords: "floating compact",
+    },
+    Action {
+        id: "open_settings",
+        title: "Open Settings",
+        keywords: "preferences options",
+    },
+    Action {
+        id: "compare_models",
+        title: "Compare Models",
+        keywords: "fan out fanout all providers",
+    },
+    Action {
+        id: "generate_image",
+        title: "Generate Image",
+        keywords: "picture dall-e stable diffusion",
+    },
+    Action {
+        id: "start_recording",
+        title: "Start Dictation",
+        keywords: "voice record microphone transcribe",
+    },
+    Action {
+        id: "capture_screen",
+        title: "Capture Screen",
+        keywords: "screenshot ocr",
+    },
+    Action {
+        id: "open_logs",
+        title: "Show Logs",
+        keywords: "debug troubleshoot",
+    },
+];
+
+/// How well `pattern` (lowercase) matches `text`, and where. `None`
+/// unless every pattern character appears in order.
+pub(crate) fn fuzzy_match(pattern: &[char], text: &str) -> Option<(i64, Vec<usize>)> {
+    if pattern.is_empty() {
+        return Some((0, Vec::new()));
+    }
+    let chars: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+
+    // First find where the earliest full match ends, then walk back from
+    // there for the shortest window ending at it.
+    let mut p = 0;
+    let mut end = None;
+    for (i, c) in lower.iter().enumerate() {
+        if *c == pattern[p] {
+            p += 1;
+            if p == pattern.len() {
+                end = Some(i);
+                break;
+            }
+        }
+    }
+    let end = end?;
+    let mut p = pattern.len();
+    let mut start = end;
+    for i in (0..=end).rev() {
+        if lower[i] == pattern[p - 1] {
+            p -= 1;
+            if p == 0 {
+                start = i;
+                break;
+            }
+        }
+    }
+
+    let mut positions = Vec::with_capacity(pattern.len());
+    let mut p = 0;
+    for (i, c) in lower.iter().enumerate().take(end + 1).skip(start) {
+        if p < pattern.len() && *c == pattern[p] {
+            positions.push(i);
+            p += 1;
+        }
+    }
+
+    let mut score = 0;
+    let mut previous: Option<usize> = None;
+    let mut run = 0;
+    for &i in &positions {
+        score += SCORE_MATCH;
+        let before = i.checked_sub(1).map(|j| chars[j]);
+        match before {
+            None => score += BONUS_FIRST_CHAR + BONUS_BOUNDARY,
+            Some(b) if !b.is_alphanumeric() => score += BONUS_BOUNDARY,
+            Some(b) if b.is_lowercase() && chars[i].is_uppercase() => score += BONUS_CAMEL,
+            _ => {}
+        }
+        match previous {
+            Some(prev) if prev + 1 == i => {
+                run += 1;
+                score += BONUS_CONSECUTIVE * run.min(4);
+            }
+            Some(prev) => {
+                run = 0;
+                score -= PENALTY_GAP_START + PENALTY_GAP * (i - prev - 2) as i64;
+            }
+            None => score -= PENALTY_GAP * i.min(16) as i64,
+        }
+        previous = Some(i);
+    }
+    // Shorter texts win among otherwise equal matches.
+    score -= (chars.len() / 16) as i64;
+    Some((score, positions))
+}
+
+/// Matches `title` first, then the secondary fields at a discount.
+fn score(pattern: &[char], title: &str, secondary: &[&str]) -> Option<(i64, Vec<usize>)> {
+    if let Some(found) = fuzzy_match(pattern, title) {
+        return Some(found);
+    }
+    secondary
+        .iter()
+        .filter_map(|text| fuzzy_match(pattern, text))
+        .map(|(score, _)| (score / SECONDARY_DIVISOR, Vec::new()))
+        .max_by_key(|(score, _)| *score)
+}
+
+/// `font_size` as "Font size".
+fn humanize(key: &str) -> String {
+    let mut title = key.replace('_', " ");
+    if let Some(first) = title.get(..1) {
+        title.replace_range(..1, &first.to_uppercase());
+    }
+    title
+}
+
+impl Database {
+    /// Id and title of every conversation, most recently updated first;
+    /// lighter than listing them when there are many.
+    fn palette_conversations(&self) -> Result<Vec<(String, String)>> {
+        let conn = self.conn()?;
+        let mut stmt =
+            conn.prepare("SELECT id, title FROM conversations ORDER BY updated_at DESC")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(rows)
+    }
+}
+
+/// Ranked matches for `query`. An empty query lists the actions and the
+/// most recent conversations.
+#[tauri::command]
+pub fn palette_query(
+    app: AppHandle,
+    db: State<'_, Database>,
+    query: PaletteQuery,
+) -> Result<Vec<PaletteResult>> {
+    let pattern: Vec<char> = query
+        .query
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    let wanted = |kind| query.kinds.is_empty() || query.kinds.contains(&kind);
+    let mut results = Vec::new();
+
+    if wanted(PaletteKind::Action) {
+        for action in ACTIONS {
+            if let Some((score, matches)) = score(&pattern, action.title, &[action.keywords]) {
+                results.push(PaletteResult {
+                    kind: PaletteKind::Action,
+                    id: action.id.to_string(),
+                    title: action.title.to_string(),
+                    subtitle: None,
+                    shortcut: shortcuts::binding(&app, action.id),
+                    score,
+                    matches,
+                });
+            }
+        }
+    }
+
+    if wanted(PaletteKind::Conversation) {
+        let conversations = db.palette_conversations()?;
+        let take = if pattern.is_empty() {
+            RECENT_CONVERSATIONS
+        } else {
+            usize::MAX
+        };
+        for (id, title) in conversations.into_iter().take(take) {
+            if let Some((score, matches)) = fuzzy_match(&pattern, &title) {
+                results.push(PaletteResult {
+                    kind: PaletteKind::Conversation,
+                    id,
+                    title,
+                    subtitle: None,
+                    shortcut: None,
+                    score,
+                    matches,
+                });
+            }
+        }
+    }
+
+    if wanted(PaletteKind::Template) && !pattern.is_empty() {
+        for template in db.list_templates(None)? {
+            let tags = template.tags.join(" ");
+            let description = template.description.clone().unwrap_or_default();
+            if let Some((score, matches)) = score(&pattern, &template.name, &[&tags, &description])
+            {
+                results.push(PaletteResult {
+                    kind: PaletteKind::Template,
+                    id: template.id,
+                    title: template.name,
+                    subtitle: template.description,
+                    shortcut: None,
+                    score,
+                    matches,
+                });
+            }
+        }
+    }
+
+    if wanted(PaletteKind::Setting) && !pattern.is_empty() {
+        if let Value::Object(settings) = serde_json::to_value(Settings::default())? {
+            for key in settings.keys() {
+                let title = humanize(key);
+                if let Some((score, matches)) = score(&pattern, &title, &[key]) {
+                    results.push(PaletteResult {
+                        kind: PaletteKind::Setting,
+                        id: key.clone(),
+                        title,
+                        subtitle: Some("Settings".into()),
+                        shortcut: None,
+                        score,
+                        matches,
+                    });
+                }
+            }
+        }
+    }
+
+    // A stable sort keeps conversations newest first among equal scores.
+    results.sort_by_key(|r| std::cmp::Reverse((r.score, r.kind.weight())));
+    results.truncate(query.limit.unwrap_or(DEFAULT_LIMIT));
+    Ok(results)
+}
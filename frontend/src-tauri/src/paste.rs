@@ -0,0 +1,211 @@
+//! Pastes handled in the backend. The webview hands over what was pasted as
+//! raw bytes, or asks for the clipboard's image directly, so a screenshot
+//! never makes the trip through the JS bridge as a multi-megabyte base64
+//! string. Images are decoded, scaled down when huge and re-encoded as
+//! whichever of PNG and lossless WebP comes out smaller, then stored as
+//! attachments; what comes back is the attachment, not the pixels.
+//!
+//! Pasted HTML is turned into Markdown, and any images embedded in it as
+//! `data:` URLs are stored the same way and left out of the text.
+
+use std::io::Cursor;
+
+use base64::Engine;
+use dom_query::Document;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{DynamicImage, ImageFormat};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tauri::ipc::{InvokeBody, Request};
+use tauri::{AppHandle, Emitter, Window};
+
+use crate::attachments::{self, image_mime, Attachment, AttachmentKind};
+use crate::backup::hex;
+use crate::clipboard;
+use crate::error::{Error, Result};
+use crate::ingest::MAX_FILE_SIZE;
+use crate::web;
+
+/// Longest side an image is stored at; larger ones are scaled down, which
+/// no model needs more than.
+const MAX_DIMENSION: u32 = 2048;
+
+/// What a paste turned into.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Pasted {
+    /// Images, stored and announced with `attachment-added` like dropped
+    /// files.
+    pub attachments: Vec<Attachment>,
+    /// Text to insert at the cursor, for text and HTML pastes.
+    pub text: Option<String>,
+}
+
+fn invalid(reason: impl std::fmt::Display) -> Error {
+    Error::InvalidAttachment(format!("the pasted image: {reason}"))
+}
+
+/// `image` encoded as PNG and, where it is smaller, lossless WebP, with
+/// the extension to store it under.
+fn encode(image: &DynamicImage) -> Result<(Vec<u8>, &'static str)> {
+    let mut png = Vec::new();
+    image
+        .write_with_encoder(PngEncoder::new_with_quality(
+            &mut png,
+            CompressionType::Best,
+            FilterType::Adaptive,
+        ))
+        .map_err(invalid)?;
+    let mut webp = Cursor::new(Vec::new());
+    // Without WebP support this fails and PNG is what gets stored.
+    match image.write_to(&mut webp, ImageFormat::WebP) {
+        Ok(()) if webp.get_ref().len() < png.len() => Ok((webp.into_inner(), "webp")),
+        _ => Ok((png, "png")),
+    }
+}
+
+/// Decodes, shrinks and re-encodes `bytes`.
+fn compress(bytes: &[u8]) -> Result<(Vec<u8>, &'static str)> {
+    let image = image::load_from_memory(bytes).map_err(invalid)?;
+    let image = if image.width().max(image.height()) > MAX_DIMENSION {
+        image.resize(
+            MAX_DIMENSION,
+            MAX_DIMENSION,
+            image::imageops::FilterType::Lanczos3,
+        )
+    } else {
+        image
+    };
+    // Screenshots usually come with an alpha channel nobody uses.
+    let image = match image {
+        DynamicImage::ImageRgba8(rgba) if rgba.pixels().all(|p| p[3] == u8::MAX) => {
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).to_rgb8())
+        }
+        image => image,
+    };
+    encode(&image)
+}
+
+/// Stores a pasted image as an attachment and announces it.
+fn store_image(app: &AppHandle, bytes: &[u8], window: &str) -> Result<Attachment> {
+    if bytes.len() as u64 > MAX_FILE_SIZE {
+        return Err(invalid(format!(
+            "it is larger than {} MB",
+            MAX_FILE_SIZE / 1024 / 1024
+        )));
+    }
+    let (bytes, extension) = compress(bytes)?;
+    let duplicate = attachments::dir(app)?
+        .join(format!("{}.{extension}", hex(&Sha256::digest(&bytes))))
+        .exists();
+    let (id, path) = attachments::store_bytes(app, &bytes, extension)?;
+    let attachment = Attachment {
+        id,
+        name: format!("Pasted image.{extension}"),
+        source: path.clone(),
+        path,
+        size: bytes.len() as u64,
+        kind: AttachmentKind::Image,
+        mime: image_mime(extension).unwrap_or("image/png"),
+        duplicate,
+        window: window.to_string(),
+    };
+    let _ = app.emit("attachment-added", &attachment);
+    Ok(attachment)
+}
+
+/// The bytes of an image embedded as a base64 `data:` URL.
+fn data_image(src: &str) -> Option<Vec<u8>> {
+    let (_, data) = src
+        .trim()
+        .strip_prefix("data:image/")?
+        .split_once(";base64,")?;
+    base64::engine::general_purpose::STANDARD
+        .decode(data.trim())
+        .ok()
+}
+
+/// Pasted HTML as Markdown, with the bytes of the images embedded in it.
+fn html_to_markdown(html: &str) -> (String, Vec<Vec<u8>>) {
+    let doc = Document::from(html);
+    let embedded = doc.select(r#"img[src^="data:"]"#);
+    let images = embedded
+        .nodes()
+        .iter()
+        .filter_map(|img| data_image(&img.attr("src")?))
+        .collect();
+    embedded.remove();
+    (web::fragment_markdown(&doc.html()), images)
+}
+
+fn paste_bytes(app: &AppHandle, mime: &str, bytes: &[u8], window: &str) -> Result<Pasted> {
+    let text = || String::from_utf8_lossy(bytes).into_owned();
+    if mime.starts_with("image/") || (mime.is_empty() && image::guess_format(bytes).is_ok()) {
+        return Ok(Pasted {
+            attachments: vec![store_image(app, bytes, window)?],
+            text: None,
+        });
+    }
+    if mime == "text/html" {
+        let (markdown, images) = html_to_markdown(&text());
+        let mut attachments = Vec::new();
+        for bytes in images {
+            match store_image(app, &bytes, window) {
+                Ok(attachment) => attachments.push(attachment),
+                Err(err) => tracing::info!("left out an image from pasted HTML: {err}"),
+            }
+        }
+        return Ok(Pasted {
+            attachments,
+            text: Some(markdown).filter(|t| !t.is_empty()),
+        });
+    }
+    if mime.starts_with("text/") || mime.is_empty() {
+        return Ok(Pasted {
+            attachments: Vec::new(),
+            text: Some(text()),
+        });
+    }
+    Err(Error::InvalidAttachment(format!(
+        "pasted {mime}: the type isn't supported"
+    )))
+}
+
+/// Handles a paste sent as the raw request body, with its MIME type in the
+/// `Content-Type` header (`image/png`, `text/html`, `text/plain`).
+#[tauri::command]
+pub async fn paste(app: AppHandle, window: Window, request: Request<'_>) -> Result<Pasted> {
+    let InvokeBody::Raw(bytes) = request.body() else {
+        return Err(Error::InvalidAttachment(
+            "a paste must be sent as raw bytes".into(),
+        ));
+    };
+    let mime = request
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let bytes = bytes.clone();
+    let label = window.label().to_string();
+    tauri::async_runtime::spawn_blocking(move || paste_bytes(&app, &mime, &bytes, &label)).await?
+}
+
+/// Attaches the image on the system clipboard, if it holds one, without it
+/// passing through the webview at all.
+#[tauri::command]
+pub async fn paste_clipboard_image(app: AppHandle, window: Window) -> Result<Option<Pasted>> {
+    let label = window.label().to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let Some(bytes) = clipboard::read_image()? else {
+            return Ok(None);
+        };
+        let attachment = store_image(&app, &bytes, &label)?;
+        Ok(Some(Pasted {
+            attachments: vec![attachment],
+            text: None,
+        }))
+    })
+    .await?
+}
@@ -0,0 +1,876 @@
+//! Applies unified diffs, as models write them, to files under a folder.
+//! Hunks are placed by their context rather than by trusting their line
+//! numbers: at the stated line first, then at the nearest match, then at
+//! one that differs only in whitespace. Hunk counts are often wrong in
+//! model output, so they only settle what is a header and what isn't.
+//!
+//! Every file is checked before any is written, and by default a patch is
+//! applied whole or not at all. The files it touches are copied to
+//! `patch-backups/<id>` in the data directory first, so a write that
+//! fails part way is rolled back and `undo_patch` can put them back later.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+
+use crate::code_files;
+use crate::config;
+use crate::error::{Error, Result};
+use crate::markdown;
+use crate::profile;
+use crate::storage::{new_id, now_ms};
+
+const BACKUP_DIR: &str = "patch-backups";
+const MANIFEST_FILE: &str = "manifest.json";
+const MAX_BACKUPS: usize = 20;
+
+#[derive(Debug, Default)]
+struct Hunk {
+    /// The 1-based line it says it starts at in the old file.
+    old_start: Option<usize>,
+    /// Each line with its marker: ` `, `-` or `+`.
+    lines: Vec<(char, String)>,
+    /// `\ No newline at end of file` followed the new side's last line.
+    no_newline_new: bool,
+    /// The same, after the old side's.
+    no_newline_old: bool,
+}
+
+impl Hunk {
+    fn before(&self) -> Vec<&str> {
+        self.side('+')
+    }
+
+    fn after(&self) -> Vec<&str> {
+        self.side('-')
+    }
+
+    fn side(&self, without: char) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|(marker, _)| *marker != without)
+            .map(|(_, line)| line.as_str())
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+struct FilePatch {
+    /// `None` for a file the patch creates.
+    old_path: Option<String>,
+    /// `None` for a file the patch deletes.
+    new_path: Option<String>,
+    hunks: Vec<Hunk>,
+    /// Started by a `diff --git` line, whose `---`/`+++` lines follow.
+    from_git: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PatchOptions {
+    /// Check the patch and report, but write nothing.
+    pub dry_run: bool,
+    /// Apply the hunks that fit even when others don't.
+    pub partial: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HunkStatus {
+    Applied,
+    /// The file already reads as the hunk would leave it.
+    AlreadyApplied,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HunkResult {
+    pub index: usize,
+    pub status: HunkStatus,
+    /// 1-based line it was placed at.
+    pub line: Option<usize>,
+    /// Lines between where it said it goes and where it went.
+    pub offset: isize,
+    /// Placed by a match that ignores whitespace.
+    pub fuzzy: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileAction {
+    Modify,
+    Create,
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileResult {
+    /// Relative to the folder, as the patch names it.
+    pub path: String,
+    pub renamed_from: Option<String>,
+    pub action: FileAction,
+    pub ok: bool,
+    /// What kept the whole file from being patched, as opposed to a hunk.
+    pub error: Option<String>,
+    pub hunks: Vec<HunkResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PatchOutcome {
+    /// Something was written.
+    pub applied: bool,
+    /// Pass to `undo_patch` to put the files back.
+    pub backup_id: Option<String>,
+    pub files: Vec<FileResult>,
+}
+
+impl PatchOutcome {
+    /// What happened, for a model that asked for the patch.
+    pub(crate) fn summary(&self) -> String {
+        let mut lines = vec![if self.applied {
+            "The patch was applied.".to_string()
+        } else {
+            "Nothing was written.".to_string()
+        }];
+        for file in &self.files {
+            let applied = file
+                .hunks
+                .iter()
+                .filter(|h| h.status != HunkStatus::Failed)
+                .count();
+            lines.push(format!(
+                "{}: {applied} of {} hunks fit",
+                file.path,
+                file.hunks.len()
+            ));
+            if let Some(error) = &file.error {
+                lines.push(format!("  {error}"));
+            }
+            for hunk in file.hunks.iter().filter(|h| h.status == HunkStatus::Failed) {
+                lines.push(format!(
+                    "  hunk {} failed: {}",
+                    hunk.index + 1,
+                    hunk.reason.as_deref().unwrap_or_default()
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// A file a backup can restore: its copy, or `None` if it didn't exist.
+#[derive(Debug, Serialize, Deserialize)]
+struct BackedUp {
+    path: PathBuf,
+    copy: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    root: PathBuf,
+    created_at: i64,
+    files: Vec<BackedUp>,
+}
+
+/// What to write to one file; `None` deletes it.
+struct Change {
+    path: PathBuf,
+    contents: Option<String>,
+    /// The old name of a renamed file, removed once the new one is written.
+    remove: Option<PathBuf>,
+}
+
+/// The diff in `text`: the `diff` or `patch` code blocks of a response if
+/// it has any, or the text itself.
+fn extract(text: &str) -> String {
+    if !text.contains("```") && !text.contains("~~~") {
+        return text.to_string();
+    }
+    let blocks = markdown::code_blocks(text);
+    let diffs: Vec<&str> = blocks
+        .iter()
+        .filter(|b| matches!(b.language.as_deref(), Some("diff" | "patch" | "udiff")))
+        .map(|b| b.code.as_str())
+        .collect();
+    match (diffs.is_empty(), blocks.is_empty()) {
+        (false, _) => diffs.join("\n"),
+        (true, false) => blocks
+            .iter()
+            .map(|b| b.code.as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        (true, true) => text.to_string(),
+    }
+}
+
+fn header_path(rest: &str, prefix: &str) -> Option<String> {
+    let path = rest
+        .split('\t')
+        .next()
+        .unwrap_or(rest)
+        .trim()
+        .trim_matches('"');
+    if path == "/dev/null" || path.is_empty() {
+        return None;
+    }
+    Some(path.strip_prefix(prefix).unwrap_or(path).to_string())
+}
+
+/// The start line and counts of a `@@ -12,5 +12,7 @@` header, when it has
+/// them.
+fn hunk_header(line: &str) -> (Option<usize>, Option<(usize, usize)>) {
+    let mut ranges = line.trim_start_matches('@').split_whitespace();
+    let parse = |range: Option<&str>, sign: char| {
+        let range = range?.strip_prefix(sign)?;
+        let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+        Some((start.parse::<usize>().ok()?, count.parse::<usize>().ok()?))
+    };
+    let old = parse(ranges.next(), '-');
+    let new = parse(ranges.next(), '+');
+    match (old, new) {
+        (Some((start, old)), Some((_, new))) => (Some(start), Some((old, new))),
+        (Some((start, _)), None) => (Some(start), None),
+        _ => (None, None),
+    }
+}
+
+fn is_body(line: &str) -> bool {
+    line.starts_with([' ', '-', '+']) && !line.starts_with("--- ") && !line.starts_with("+++ ")
+}
+
+fn parse(text: &str) -> Result<Vec<FilePatch>> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut files: Vec<FilePatch> = Vec::new();
+    // Lines the current hunk's header says are still to come, old and new.
+    let mut remaining: Option<(usize, usize)> = None;
+    let mut in_hunk = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_end_matches('\r');
+        let next = lines.get(i + 1).map(|l| l.trim_end_matches('\r'));
+        i += 1;
+        let expecting = remaining.is_some_and(|(old, new)| old > 0 || new > 0);
+
+        if in_hunk {
+            let hunk = files
+                .last_mut()
+                .and_then(|f| f.hunks.last_mut())
+                .expect("a hunk is open");
+            let marker = line.chars().next();
+            let body = match marker {
+                Some(' ' | '-' | '+') => expecting || is_body(line),
+                // Editors and models drop the space of blank context lines.
+                None => expecting || next.is_some_and(is_body),
+                Some('\\') => {
+                    match hunk.lines.last().map(|(m, _)| *m) {
+                        Some('+') => hunk.no_newline_new = true,
+                        Some('-') => hunk.no_newline_old = true,
+                        _ => {
+                            hunk.no_newline_new = true;
+                            hunk.no_newline_old = true;
+                        }
+                    }
+                    continue;
+                }
+                _ => false,
+            };
+            if body {
+                let marker = marker.unwrap_or(' ');
+                let content = line.get(1..).unwrap_or_default().to_string();
+                if let Some((old, new)) = &mut remaining {
+                    if marker != '+' {
+                        *old = old.saturating_sub(1);
+                    }
+                    if marker != '-' {
+                        *new = new.saturating_sub(1);
+                    }
+                }
+                hunk.lines.push((marker, content));
+                continue;
+            }
+            in_hunk = false;
+        }
+
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            let (old, new) = rest.split_once(" b/").unwrap_or((rest, rest));
+            files.push(FilePatch {
+                old_path: header_path(old, "a/"),
+                new_path: header_path(new, ""),
+                from_git: true,
+                ..Default::default()
+            });
+        } else if let (Some(rest), Some(plus)) = (
+            line.strip_prefix("--- "),
+            next.and_then(|n| n.strip_prefix("+++ ")),
+        ) {
+            i += 1;
+            let (old_path, new_path) = (header_path(rest, "a/"), header_path(plus, "b/"));
+            match files.last_mut() {
+                Some(file) if file.from_git && file.hunks.is_empty() => {
+                    file.old_path = old_path;
+                    file.new_path = new_path;
+                    file.from_git = false;
+                }
+                _ => files.push(FilePatch {
+                    old_path,
+                    new_path,
+                    ..Default::default()
+                }),
+            }
+        } else if line.starts_with("@@") {
+            let file = files
+                .last_mut()
+                .ok_or_else(|| Error::Patch("a hunk comes before any file header".into()))?;
+            let (old_start, counts) = hunk_header(line);
+            file.hunks.push(Hunk {
+                old_start,
+                ..Default::default()
+            });
+            remaining = counts;
+            in_hunk = true;
+        } else if let Some(file) = files.last_mut().filter(|f| f.hunks.is_empty()) {
+            if line.starts_with("new file mode") {
+                file.old_path = None;
+            } else if line.starts_with("deleted file mode") {
+                file.new_path = None;
+            } else if let Some(path) = line.strip_prefix("rename from ") {
+                file.old_path = Some(path.trim().to_string());
+            } else if let Some(path) = line.strip_prefix("rename to ") {
+                file.new_path = Some(path.trim().to_string());
+            }
+        }
+    }
+    files.retain(|f| !f.hunks.is_empty() || f.old_path != f.new_path);
+    if files.is_empty() {
+        return Err(Error::Patch("it changes no files".into()));
+    }
+    Ok(files)
+}
+
+/// Where `wanted` occurs in `lines` at or after `from`, nearest to
+/// `expected`, and whether only a whitespace-blind match found it.
+fn find(lines: &[String], from: usize, expected: usize, wanted: &[&str]) -> Option<(usize, bool)> {
+    let last = lines.len().checked_sub(wanted.len())?;
+    if from > last {
+        return None;
+    }
+    let expected = expected.clamp(from, last);
+    let levels: [fn(&str, &str) -> bool; 3] = [
+        |a, b| a == b,
+        |a, b| a.trim_end() == b.trim_end(),
+        |a, b| a.split_whitespace().eq(b.split_whitespace()),
+    ];
+    for (level, same) in levels.iter().enumerate() {
+        let fits = |at: usize| {
+            lines[at..at + wanted.len()]
+                .iter()
+                .zip(wanted)
+                .all(|(line, want)| same(line, want))
+        };
+        for distance in 0..=(last - from) {
+            let candidates = [
+                expected.checked_sub(distance),
+                expected.checked_add(distance),
+            ];
+            for at in candidates.into_iter().flatten() {
+                if (from..=last).contains(&at) && fits(at) {
+                    return Some((at, level == 2));
+                }
+            }
+            if expected.saturating_sub(distance) <= from && expected + distance >= last {
+                break;
+            }
+        }
+    }
+    None
+}
+
+/// `original` with `hunks` applied, leaving out those that don't fit.
+fn apply_hunks(original: &str, hunks: &[Hunk]) -> (String, Vec<HunkResult>) {
+    let crlf = original.contains("\r\n");
+    let mut ends_with_newline = original.is_empty() || original.ends_with('\n');
+    let mut lines: Vec<String> = original.lines().map(str::to_string).collect();
+    let mut results = Vec::new();
+    let mut delta: isize = 0;
+    let mut from = 0;
+    for (index, hunk) in hunks.iter().enumerate() {
+        let (old, new) = (hunk.before(), hunk.after());
+        let expected = match hunk.old_start {
+            // A hunk that only adds says it follows line `n`.
+            Some(start) if old.is_empty() => start as isize + delta,
+            Some(start) => start.saturating_sub(1) as isize + delta,
+            None => from as isize,
+        }
+        .max(0) as usize;
+        let mut result = HunkResult {
+            index,
+            status: HunkStatus::Applied,
+            line: None,
+            offset: 0,
+            fuzzy: false,
+            reason: None,
+        };
+        // After the hunks placed so far, unless the patch has them out of
+        // order.
+        let locate = |wanted: &[&str]| {
+            find(&lines, from, expected, wanted).or_else(|| find(&lines, 0, expected, wanted))
+        };
+        // Lines it only adds would fit anywhere; check first that they
+        // aren't there already.
+        let already = || {
+            (!new.is_empty())
+                .then(|| locate(&new))
+                .flatten()
+                .filter(|(_, fuzzy)| !fuzzy)
+        };
+        let placed = if old.iter().all(|l| new.contains(l)) {
+            already()
+                .map(|found| (found, HunkStatus::AlreadyApplied))
+                .or_else(|| locate(&old).map(|f| (f, HunkStatus::Applied)))
+        } else {
+            locate(&old)
+                .map(|f| (f, HunkStatus::Applied))
+                .or_else(|| already().map(|f| (f, HunkStatus::AlreadyApplied)))
+        };
+        match placed {
+            Some(((at, fuzzy), status)) => {
+                result.status = status;
+                result.line = Some(at + 1);
+                result.offset = at as isize - expected as isize;
+                result.fuzzy = fuzzy;
+                if status == HunkStatus::Applied {
+                    let touches_end = at + old.len() == lines.len();
+                    lines.splice(at..at + old.len(), new.iter().map(|l| l.to_string()));
+                    delta += new.len() as isize - old.len() as isize;
+                    if touches_end && hunk.no_newline_new {
+                        ends_with_newline = false;
+                    } else if touches_end && hunk.no_newline_old {
+                        ends_with_newline = true;
+                    }
+                }
+                from = from.max(at + new.len());
+            }
+            None => {
+                result.status = HunkStatus::Failed;
+                result.reason = Some(if old.is_empty() {
+                    "there is nothing to place it by".into()
+                } else {
+                    format!("the {} lines it replaces aren't in the file", old.len())
+                });
+            }
+        }
+        results.push(result);
+    }
+    let eol = if crlf { "\r\n" } else { "\n" };
+    let mut text = lines.join(eol);
+    if ends_with_newline && !lines.is_empty() {
+        text.push_str(eol);
+    }
+    (text, results)
+}
+
+/// Checks `file` against the folder and works out what to write.
+fn plan(root: &Path, file: &FilePatch) -> (FileResult, Option<Change>) {
+    let path = file.new_path.clone().or_else(|| file.old_path.clone());
+    let mut result = FileResult {
+        path: path.clone().unwrap_or_default(),
+        renamed_from: file
+            .old_path
+            .clone()
+            .filter(|old| file.new_path.as_ref().is_some_and(|new| new != old)),
+        action: match (&file.old_path, &file.new_path) {
+            (None, _) => FileAction::Create,
+            (_, None) => FileAction::Delete,
+            _ => FileAction::Modify,
+        },
+        ok: false,
+        error: None,
+        hunks: Vec::new(),
+    };
+    let resolve = |relative: &str| {
+        code_files::resolve(root, relative).map_err(|reason| format!("{relative}: {reason}"))
+    };
+    let (source, target) = match (&file.old_path, &file.new_path) {
+        (None, None) => {
+            result.error = Some("it names no file".into());
+            return (result, None);
+        }
+        (old, new) => (old.as_deref().map(resolve), new.as_deref().map(resolve)),
+    };
+    let (source, target) = match (source.transpose(), target.transpose()) {
+        (Ok(source), Ok(target)) => (source, target),
+        (Err(err), _) | (_, Err(err)) => {
+            result.error = Some(err);
+            return (result, None);
+        }
+    };
+
+    let read = |path: &Path| match std::fs::read(path) {
+        Ok(bytes) => String::from_utf8(bytes)
+            .map(Some)
+            .map_err(|_| "it isn't a text file".to_string()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.to_string()),
+    };
+    let original = match source.as_deref().or(target.as_deref()).map(read) {
+        Some(Ok(text)) => text,
+        Some(Err(err)) => {
+            result.error = Some(err);
+            return (result, None);
+        }
+        None => None,
+    };
+    let creates_only = file.hunks.iter().all(|h| h.before().is_empty());
+    let original = match (original, result.action) {
+        (Some(text), FileAction::Create) if !text.is_empty() => {
+            // Fine if an earlier run of the same patch wrote it.
+            let (created, hunks) = apply_hunks("", &file.hunks);
+            if created == text {
+                result.ok = true;
+                result.hunks = hunks
+                    .into_iter()
+                    .map(|h| HunkResult {
+                        status: HunkStatus::AlreadyApplied,
+                        ..h
+                    })
+                    .collect();
+            } else {
+                result.error = Some("the file already exists".into());
+            }
+            return (result, None);
+        }
+        (Some(text), _) => text,
+        (None, FileAction::Delete) => {
+            result.ok = true;
+            return (result, None);
+        }
+        (None, _) if creates_only => {
+            result.action = FileAction::Create;
+            String::new()
+        }
+        (None, _) => {
+            result.error = Some("the file doesn't exist".into());
+            return (result, None);
+        }
+    };
+
+    let (patched, hunks) = apply_hunks(&original, &file.hunks);
+    result.ok = hunks.iter().all(|h| h.status != HunkStatus::Failed);
+    result.hunks = hunks;
+    let change = match result.action {
+        FileAction::Delete if !patched.trim().is_empty() => {
+            result.ok = false;
+            result.error = Some("the file has lines the patch doesn't remove".into());
+            None
+        }
+        FileAction::Delete => source.map(|path| Change {
+            path,
+            contents: None,
+            remove: None,
+        }),
+        _ if patched == original && result.renamed_from.is_none() => None,
+        _ => target.map(|path| Change {
+            remove: source.filter(|source| *source != path),
+            path,
+            contents: Some(patched),
+        }),
+    };
+    (result, change)
+}
+
+fn backups_dir(app: &AppHandle) -> Result<PathBuf> {
+    Ok(profile::data_dir(app)?.join(BACKUP_DIR))
+}
+
+fn restore(dir: &Path, manifest: &Manifest) -> Result<()> {
+    for file in &manifest.files {
+        match &file.copy {
+            Some(copy) => {
+                if let Some(parent) = file.path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::copy(dir.join("files").join(copy), &file.path)?;
+            }
+            None => match std::fs::remove_file(&file.path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Drops all but the newest backups.
+fn prune(backups: &Path) {
+    let Ok(entries) = std::fs::read_dir(backups) else {
+        return;
+    };
+    let mut dirs: Vec<(i64, PathBuf)> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .map(|dir| {
+            let manifest = config::read_in::<Manifest>(&dir, MANIFEST_FILE)
+                .ok()
+                .flatten();
+            (manifest.map_or(0, |m| m.created_at), dir)
+        })
+        .collect();
+    dirs.sort_by_key(|(created_at, _)| std::cmp::Reverse(*created_at));
+    for (_, dir) in dirs.into_iter().skip(MAX_BACKUPS) {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
+
+/// Backs up what `changes` touch, then writes them, putting everything
+/// back if any write fails.
+fn write(backups: &Path, root: &Path, changes: &[Change]) -> Result<String> {
+    let id = new_id();
+    let dir = backups.join(&id);
+    std::fs::create_dir_all(dir.join("files"))?;
+    let mut manifest = Manifest {
+        root: root.to_path_buf(),
+        created_at: now_ms(),
+        files: Vec::new(),
+    };
+    let touched = changes
+        .iter()
+        .flat_map(|c| std::iter::once(&c.path).chain(&c.remove));
+    for (n, path) in touched.enumerate() {
+        let copy = path.exists().then(|| n.to_string());
+        if let Some(copy) = &copy {
+            std::fs::copy(path, dir.join("files").join(copy))?;
+        }
+        manifest.files.push(BackedUp {
+            path: path.clone(),
+            copy,
+        });
+    }
+    config::write_in(&dir, MANIFEST_FILE, &manifest)?;
+
+    let outcome = changes.iter().try_for_each(|change| -> Result<()> {
+        match &change.contents {
+            Some(contents) => {
+                let parent = change.path.parent().unwrap_or(root);
+                std::fs::create_dir_all(parent)?;
+                let name = change
+                    .path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy();
+                let tmp = parent.join(format!(".{name}.patch-tmp"));
+                std::fs::write(&tmp, contents)?;
+                std::fs::rename(&tmp, &change.path)?;
+            }
+            None => std::fs::remove_file(&change.path)?,
+        }
+        if let Some(old) = &change.remove {
+            std::fs::remove_file(old)?;
+        }
+        Ok(())
+    });
+    if let Err(err) = outcome {
+        if let Err(restore_err) = restore(&dir, &manifest) {
+            tracing::warn!("couldn't roll back a failed patch from {id}: {restore_err}");
+        }
+        return Err(err);
+    }
+    prune(backups);
+    Ok(id)
+}
+
+/// Applies `patch` under `root`. Also what the `apply_patch` tool runs.
+pub(crate) async fn apply(
+    app: &AppHandle,
+    root: &Path,
+    patch: &str,
+    options: PatchOptions,
+) -> Result<PatchOutcome> {
+    let root = std::fs::canonicalize(root)
+        .ok()
+        .filter(|root| root.is_dir())
+        .ok_or_else(|| Error::NotFound(format!("folder {}", root.display())))?;
+    let files = parse(&extract(patch))?;
+    let backups = backups_dir(app)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let (results, changes): (Vec<_>, Vec<_>) = files.iter().map(|f| plan(&root, f)).unzip();
+        let ok = results.iter().all(|r| r.ok);
+        let changes: Vec<Change> = changes.into_iter().flatten().collect();
+        let mut outcome = PatchOutcome {
+            applied: false,
+            backup_id: None,
+            files: results,
+        };
+        if options.dry_run || changes.is_empty() || !(ok || options.partial) {
+            return Ok(outcome);
+        }
+        outcome.backup_id = Some(write(&backups, &root, &changes)?);
+        outcome.applied = true;
+        Ok(outcome)
+    })
+    .await?
+}
+
+/// Applies a unified diff, or a response holding one in a code block, to
+/// the files under `root`.
+#[tauri::command]
+pub async fn apply_patch(
+    app: AppHandle,
+    root: PathBuf,
+    patch: String,
+    options: Option<PatchOptions>,
+) -> Result<PatchOutcome> {
+    apply(&app, &root, &patch, options.unwrap_or_default()).await
+}
+
+/// Puts back the files a patch changed, returning their paths.
+#[tauri::command]
+pub async fn undo_patch(app: AppHandle, backup_id: String) -> Result<Vec<PathBuf>> {
+    let not_found = || Error::NotFound(format!("patch backup {backup_id}"));
+    if !backup_id.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+        return Err(not_found());
+    }
+    let dir = backups_dir(&app)?.join(&backup_id);
+    let manifest = config::read_in::<Manifest>(&dir, MANIFEST_FILE)?.ok_or_else(not_found)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        restore(&dir, &manifest)?;
+        std::fs::remove_dir_all(&dir)?;
+        Ok(manifest.files.into_iter().map(|f| f.path).collect())
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hunks(diff: &str) -> Vec<Hunk> {
+        parse(diff).unwrap().remove(0).hunks
+    }
+
+    /// An empty folder of the test's own under the temp directory.
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("pentamind-patch-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::canonicalize(dir).unwrap()
+    }
+
+    const FIVE: &str = "--- a/f.txt\n+++ b/f.txt\n@@ -1,3 +1,3 @@\n four\n-five\n+FIVE\n six\n";
+
+    #[test]
+    fn places_a_hunk_away_from_its_line() {
+        let original = "one\ntwo\nthree\nfour\nfive\nsix\nseven\n";
+        let (patched, results) = apply_hunks(original, &hunks(FIVE));
+        assert_eq!(patched, "one\ntwo\nthree\nfour\nFIVE\nsix\nseven\n");
+        assert_eq!(results[0].status, HunkStatus::Applied);
+        assert_eq!(results[0].line, Some(4));
+        assert_eq!(results[0].offset, 3);
+        assert!(!results[0].fuzzy);
+    }
+
+    #[test]
+    fn places_a_hunk_by_whitespace_blind_match() {
+        let diff = "--- a/m.rs\n+++ b/m.rs\n@@ -1,3 +1,3 @@\n fn main() {\n-  let x = 1;\n+    let x = 2;\n }\n";
+        let (patched, results) = apply_hunks("fn main() {\n    let x = 1;\n}\n", &hunks(diff));
+        assert_eq!(patched, "fn main() {\n    let x = 2;\n}\n");
+        assert_eq!(results[0].status, HunkStatus::Applied);
+        assert!(results[0].fuzzy);
+    }
+
+    #[test]
+    fn fails_a_hunk_that_fits_nowhere() {
+        let (patched, results) = apply_hunks("one\ntwo\n", &hunks(FIVE));
+        assert_eq!(patched, "one\ntwo\n");
+        assert_eq!(results[0].status, HunkStatus::Failed);
+    }
+
+    #[test]
+    fn notices_a_hunk_already_applied() {
+        let original = "one\ntwo\nthree\nfour\nFIVE\nsix\nseven\n";
+        let (patched, results) = apply_hunks(original, &hunks(FIVE));
+        assert_eq!(patched, original);
+        assert_eq!(results[0].status, HunkStatus::AlreadyApplied);
+        assert_eq!(results[0].line, Some(4));
+    }
+
+    #[test]
+    fn follows_no_newline_markers() {
+        let drop = "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n\\ No newline at end of file\n";
+        assert_eq!(apply_hunks("a\nb\n", &hunks(drop)).0, "a\nc");
+        let add = "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n";
+        assert_eq!(apply_hunks("a\nb", &hunks(add)).0, "a\nc\n");
+        let both = "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n\\ No newline at end of file\n";
+        assert_eq!(apply_hunks("a\nb", &hunks(both)).0, "a\nc");
+    }
+
+    #[test]
+    fn keeps_crlf_line_endings() {
+        let diff = "--- a/f\r\n+++ b/f\r\n@@ -1,3 +1,3 @@\r\n one\r\n-two\r\n+TWO\r\n three\r\n";
+        let (patched, results) = apply_hunks("one\r\ntwo\r\nthree\r\n", &hunks(diff));
+        assert_eq!(patched, "one\r\nTWO\r\nthree\r\n");
+        assert_eq!(results[0].status, HunkStatus::Applied);
+    }
+
+    #[test]
+    fn creates_a_file() {
+        let root = scratch("create");
+        let diff = "diff --git a/new.txt b/new.txt\nnew file mode 100644\n--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1,2 @@\n+hello\n+world\n";
+        let (result, change) = plan(&root, &parse(diff).unwrap()[0]);
+        assert!(result.ok, "{:?}", result.error);
+        assert_eq!(result.action, FileAction::Create);
+        let change = change.unwrap();
+        assert_eq!(change.path, root.join("new.txt"));
+        assert_eq!(change.contents.as_deref(), Some("hello\nworld\n"));
+        assert!(change.remove.is_none());
+
+        // Running the same patch again finds it done.
+        std::fs::write(root.join("new.txt"), "hello\nworld\n").unwrap();
+        let (result, change) = plan(&root, &parse(diff).unwrap()[0]);
+        assert!(result.ok);
+        assert!(change.is_none());
+        assert_eq!(result.hunks[0].status, HunkStatus::AlreadyApplied);
+    }
+
+    #[test]
+    fn deletes_a_file() {
+        let root = scratch("delete");
+        std::fs::write(root.join("old.txt"), "bye\n").unwrap();
+        let diff = "diff --git a/old.txt b/old.txt\ndeleted file mode 100644\n--- a/old.txt\n+++ /dev/null\n@@ -1 +0,0 @@\n-bye\n";
+        let (result, change) = plan(&root, &parse(diff).unwrap()[0]);
+        assert!(result.ok, "{:?}", result.error);
+        assert_eq!(result.action, FileAction::Delete);
+        let change = change.unwrap();
+        assert_eq!(change.path, root.join("old.txt"));
+        assert!(change.contents.is_none());
+
+        // Not when the file has lines the patch doesn't remove.
+        std::fs::write(root.join("old.txt"), "bye\nand more\n").unwrap();
+        let (result, change) = plan(&root, &parse(diff).unwrap()[0]);
+        assert!(!result.ok);
+        assert!(change.is_none());
+    }
+
+    #[test]
+    fn renames_a_file() {
+        let root = scratch("rename");
+        std::fs::write(root.join("a.txt"), "x\ny\n").unwrap();
+        let diff = "diff --git a/a.txt b/b.txt\nsimilarity index 90%\nrename from a.txt\nrename to b.txt\n--- a/a.txt\n+++ b/b.txt\n@@ -1,2 +1,2 @@\n x\n-y\n+z\n";
+        let (result, change) = plan(&root, &parse(diff).unwrap()[0]);
+        assert!(result.ok, "{:?}", result.error);
+        assert_eq!(result.path, "b.txt");
+        assert_eq!(result.renamed_from.as_deref(), Some("a.txt"));
+        let change = change.unwrap();
+        assert_eq!(change.path, root.join("b.txt"));
+        assert_eq!(change.contents.as_deref(), Some("x\nz\n"));
+        assert_eq!(change.remove, Some(root.join("a.txt")));
+
+        // A rename without hunks still moves the file.
+        let bare = "diff --git a/a.txt b/c.txt\nsimilarity index 100%\nrename from a.txt\nrename to c.txt\n";
+        let (result, change) = plan(&root, &parse(bare).unwrap()[0]);
+        assert!(result.ok);
+        assert_eq!(change.unwrap().contents.as_deref(), Some("x\ny\n"));
+    }
+}
@@ -0,0 +1,518 @@
+//! Transforms run over each finished answer before it is returned: dropping
+//! reasoning blocks, labelling and tidying code blocks, converting units,
+//! translating, regex replacements of the user's own and those plugins
+//! add. The steps run in
+//! the configured order, each on the previous one's output. A conversation
+//! can have its own list instead of the default one.
+//!
+//! Streamed tokens are the model's raw output; the processed text arrives
+//! with `chat-done` and `fanout-result`, next to the original.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::markdown;
+use crate::plugins;
+use crate::translate;
+
+const CONFIG_FILE: &str = "pipeline.json";
+/// Tags reasoning models wrap their thinking in.
+const REASONING_TAGS: &[&str] = &["think", "thinking", "reasoning", "reflection"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnitSystem {
+    Metric,
+    Imperial,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Transform {
+    /// Removes `<think>…</think>` and similar blocks; `tags` replaces the
+    /// usual set when given.
+    StripReasoning {
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+    /// Labels unlabelled code blocks with a detected language, pretty-prints
+    /// JSON, and drops trailing whitespace and common indentation.
+    FormatCode,
+    /// Adds the equivalent in `to` after each quantity in the other system,
+    /// as in "5 miles (8.05 km)". Code is left alone.
+    ConvertUnits { to: UnitSystem },
+    /// Has a model translate the answer. Falls back to a cheap configured
+    /// model, as titles do.
+    Translate {
+        language: String,
+        #[serde(default)]
+        provider: Option<String>,
+        #[serde(default)]
+        model: Option<String>,
+    },
+    /// A regex replacement; `replacement` may refer to groups as `$1`.
+    Replace {
+        pattern: String,
+        #[serde(default)]
+        replacement: String,
+    },
+    /// A transform an installed plugin provides.
+    Plugin { plugin: String, name: String },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PipelineConfig {
+    /// Run over every answer without an override.
+    pub transforms: Vec<Transform>,
+    /// Per conversation id, used instead of `transforms`. An empty list
+    /// turns processing off there.
+    pub conversations: BTreeMap<String, Vec<Transform>>,
+}
+
+impl PipelineConfig {
+    fn for_conversation(&self, conversation_id: Option<&str>) -> &[Transform] {
+        conversation_id
+            .and_then(|id| self.conversations.get(id))
+            .unwrap_or(&self.transforms)
+    }
+}
+
+/// Managed as Tauri state.
+pub struct Pipeline {
+    config: Mutex<PipelineConfig>,
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<PipelineConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(Pipeline {
+        config: Mutex::new(config),
+    });
+}
+
+fn validate(transforms: &[Transform]) -> Result<()> {
+    for transform in transforms {
+        match transform {
+            Transform::Replace { pattern, .. } => {
+                Regex::new(pattern)
+                    .map_err(|e| Error::InvalidSetting(format!("replace pattern: {e}")))?;
+            }
+            Transform::Translate { language, .. } if language.trim().is_empty() => {
+                return Err(Error::InvalidSetting(
+                    "translate needs a target language".into(),
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Runs the pipeline for `conversation_id` over `content` in place. Returns
+/// the original text if anything changed. A step that fails is skipped.
+pub(crate) async fn apply(
+    app: &AppHandle,
+    conversation_id: Option<&str>,
+    content: &mut String,
+) -> Option<String> {
+    let transforms = app
+        .state::<Pipeline>()
+        .config
+        .lock()
+        .unwrap()
+        .for_conversation(conversation_id)
+        .to_vec();
+    let mut text = content.clone();
+    for transform in &transforms {
+        match run(app, transform, &text).await {
+            Ok(output) => text = output,
+            Err(err) => tracing::warn!("skipping a post-processing step: {err}"),
+        }
+    }
+    if text == *content {
+        return None;
+    }
+    Some(std::mem::replace(content, text))
+}
+
+async fn run(app: &AppHandle, transform: &Transform, text: &str) -> Result<String> {
+    Ok(match transform {
+        Transform::StripReasoning { tags } => strip_reasoning(text, tags)?,
+        Transform::FormatCode => format_code(text),
+        Transform::ConvertUnits { to } => outside_code(text, |prose| convert_units(prose, *to)),
+        Transform::Translate {
+            language,
+            provider,
+            model,
+        } => {
+            translate::translate_text(app, text, language, provider.as_deref(), model.as_deref())
+                .await?
+                .0
+        }
+        Transform::Replace {
+            pattern,
+            replacement,
+        } => Regex::new(pattern)
+            .map_err(|e| Error::InvalidSetting(format!("replace pattern: {e}")))?
+            .replace_all(text, replacement.as_str())
+            .into_owned(),
+        Transform::Plugin { plugin, name } => plugins::transform(app, plugin, name, text).await?,
+    })
+}
+
+fn strip_reasoning(text: &str, tags: &[String]) -> Result<String> {
+    let tags: Vec<String> = if tags.is_empty() {
+        REASONING_TAGS.iter().map(|t| t.to_string()).collect()
+    } else {
+        tags.iter().map(|t| regex::escape(t.trim())).collect()
+    };
+    let mut text = text.to_string();
+    for tag in tags {
+        let block = Regex::new(&format!(r"(?is)<{tag}(?:\s[^>]*)?>.*?</{tag}>\s*"))
+            .map_err(|e| Error::InvalidSetting(format!("reasoning tag {tag}: {e}")))?;
+        text = block.replace_all(&text, "").into_owned();
+    }
+    Ok(text.trim_start().to_string())
+}
+
+/// `f` applied to the text between fenced code blocks and inline code.
+fn outside_code(text: &str, f: impl Fn(&str) -> String) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut prose = String::new();
+    let mut open: Option<(char, usize)> = None;
+    for line in text.split_inclusive('\n') {
+        let marker = markdown::fence(line);
+        match open {
+            Some((ch, len)) => {
+                if marker.is_some_and(|(c, l)| c == ch && l >= len) {
+                    open = None;
+                }
+                out.push_str(line);
+            }
+            None if marker.is_some() => {
+                out.push_str(&outside_inline_code(&prose, &f));
+                prose.clear();
+                open = marker;
+                out.push_str(line);
+            }
+            None => prose.push_str(line),
+        }
+    }
+    out.push_str(&outside_inline_code(&prose, &f));
+    out
+}
+
+fn outside_inline_code(text: &str, f: &impl Fn(&str) -> String) -> String {
+    text.split('`')
+        .enumerate()
+        .map(|(i, part)| {
+            if i % 2 == 0 {
+                f(part)
+            } else {
+                part.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("`")
+}
+
+fn format_code(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    // The opening fence, its line and the lines since.
+    let mut open: Option<((char, usize), &str, Vec<&str>)> = None;
+    for line in text.split_inclusive('\n') {
+        let marker = markdown::fence(line);
+        match &mut open {
+            Some((fence, opening, body)) => {
+                if marker.is_some_and(|(c, l)| c == fence.0 && l >= fence.1) {
+                    out.push_str(&format_block(*fence, opening, body));
+                    out.push_str(line);
+                    open = None;
+                } else {
+                    body.push(line);
+                }
+            }
+            None => match marker {
+                Some(fence) => open = Some((fence, line, Vec::new())),
+                None => out.push_str(line),
+            },
+        }
+    }
+    // An unclosed block is left as it came.
+    if let Some((_, opening, body)) = open {
+        out.push_str(opening);
+        body.iter().for_each(|line| out.push_str(line));
+    }
+    out
+}
+
+/// A closed block's opening fence and code, tidied up.
+fn format_block(fence: (char, usize), opening: &str, body: &[&str]) -> String {
+    let indent = &opening[..opening.len() - opening.trim_start().len()];
+    let marker: String = std::iter::repeat_n(fence.0, fence.1).collect();
+    let info = opening.trim_start().trim_start_matches(fence.0).trim();
+
+    let lines: Vec<&str> = body.iter().map(|l| l.trim_end()).collect();
+    let dedent = lines
+        .iter()
+        .filter(|l| !l.is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let code = lines
+        .iter()
+        .map(|l| l.get(dedent..).unwrap_or(""))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let code = code.trim_matches('\n');
+
+    let language = match info {
+        "" => detect_language(code).unwrap_or_default(),
+        info => info,
+    };
+    let code = match language {
+        "json" => serde_json::from_str::<serde_json::Value>(code)
+            .and_then(|v| serde_json::to_string_pretty(&v))
+            .unwrap_or_else(|_| code.to_string()),
+        _ => code.to_string(),
+    };
+    let mut block = format!("{indent}{marker}{language}\n");
+    for line in code.lines() {
+        // Blocks inside list items keep their place in the list.
+        if !line.is_empty() {
+            block.push_str(indent);
+        }
+        block.push_str(line);
+        block.push('\n');
+    }
+    block
+}
+
+/// A best guess from telltale keywords; `None` when nothing stands out.
+fn detect_language(code: &str) -> Option<&'static str> {
+    let trimmed = code.trim_start();
+    let has = |needle: &str| code.contains(needle);
+    let starts = |prefix: &str| code.lines().any(|l| l.trim_start().starts_with(prefix));
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(code).is_ok()
+    {
+        return Some("json");
+    }
+    if trimmed.starts_with("<!DOCTYPE") || trimmed.starts_with("<html") {
+        return Some("html");
+    }
+    if (has("fn ") && (has("let ") || has("->") || has("::"))) || starts("use std::") {
+        return Some("rust");
+    }
+    if starts("package ") && has("func ") {
+        return Some("go");
+    }
+    if starts("#include") {
+        return Some(if has("std::") || has("class ") {
+            "cpp"
+        } else {
+            "c"
+        });
+    }
+    if starts("def ")
+        || (starts("from ") && has(" import "))
+        || has("elif ")
+        || (has("print(") && !has(";"))
+    {
+        return Some("python");
+    }
+    if (has("interface ") && has(": ")) || has(": string") || has(": number") {
+        return Some("typescript");
+    }
+    if starts("const ") || starts("function ") || has("=>") || has("console.log") {
+        return Some("javascript");
+    }
+    if starts("public class ") || has("System.out.println") {
+        return Some("java");
+    }
+    let upper = trimmed.to_uppercase();
+    if [
+        "SELECT ",
+        "INSERT INTO",
+        "CREATE TABLE",
+        "UPDATE ",
+        "DELETE FROM",
+        "WITH ",
+    ]
+    .iter()
+    .any(|k| upper.starts_with(k))
+    {
+        return Some("sql");
+    }
+    if trimmed.starts_with("#!/bin/")
+        || starts("$ ")
+        || starts("sudo ")
+        || starts("npm ")
+        || starts("cargo ")
+        || starts("git ")
+        || starts("pip ")
+        || starts("brew ")
+    {
+        return Some("bash");
+    }
+    if trimmed.starts_with('<') && trimmed.ends_with('>') {
+        return Some("html");
+    }
+    None
+}
+
+/// Unit names matched case-insensitively, longest first, with their
+/// system, the other system's unit and how many of it make one. Units that
+/// another's names start with, like `km/h` and `km`, come first.
+const UNITS: &[(&str, UnitSystem, &str, f64)] = &[
+    ("miles|mile|mi", UnitSystem::Imperial, "km", 1.609344),
+    ("feet|foot|ft", UnitSystem::Imperial, "m", 0.3048),
+    ("inches|inch", UnitSystem::Imperial, "cm", 2.54),
+    (
+        "pounds|pound|lbs|lb",
+        UnitSystem::Imperial,
+        "kg",
+        0.453_592_37,
+    ),
+    ("ounces|ounce|oz", UnitSystem::Imperial, "g", 28.349_523),
+    (
+        "gallons|gallon|gal",
+        UnitSystem::Imperial,
+        "L",
+        3.785_411_784,
+    ),
+    ("mph", UnitSystem::Imperial, "km/h", 1.609344),
+    ("km/h|kph", UnitSystem::Metric, "mph", 0.621_371),
+    (
+        "kilometers|kilometres|kilometer|kilometre|km",
+        UnitSystem::Metric,
+        "mi",
+        0.621_371,
+    ),
+    (
+        "meters|metres|meter|metre|m",
+        UnitSystem::Metric,
+        "ft",
+        3.280_84,
+    ),
+    (
+        "centimeters|centimetres|centimeter|centimetre|cm",
+        UnitSystem::Metric,
+        "in",
+        0.393_701,
+    ),
+    ("kilograms|kilogram|kg", UnitSystem::Metric, "lb", 2.204_62),
+    ("grams|gram|g", UnitSystem::Metric, "oz", 0.035_274),
+    (
+        "liters|litres|liter|litre",
+        UnitSystem::Metric,
+        "gal",
+        0.264_172,
+    ),
+];
+
+/// Three significant figures or so, without trailing zeros.
+fn round(value: f64) -> String {
+    let decimals = match value.abs() {
+        v if v >= 100.0 => 0,
+        v if v >= 10.0 => 1,
+        _ => 2,
+    };
+    let text = format!("{value:.decimals$}");
+    if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        text
+    }
+}
+
+fn convert_units(text: &str, to: UnitSystem) -> String {
+    let from: Vec<_> = UNITS.iter().filter(|unit| unit.1 != to).collect();
+    let names = from.iter().map(|unit| unit.0).collect::<Vec<_>>().join("|");
+    // Amounts already followed by a parenthesis were converted before.
+    let quantity = Regex::new(&format!(r"(?i)\b(\d+(?:\.\d+)?)(\s?)({names})\b(\s*\()?")).unwrap();
+    let text = quantity.replace_all(text, |caps: &Captures| {
+        let unit = from.iter().find(|unit| {
+            unit.0
+                .split('|')
+                .any(|name| name.eq_ignore_ascii_case(&caps[3]))
+        });
+        match (unit, caps.get(4)) {
+            (Some((_, _, target, factor)), None) => {
+                let value: f64 = caps[1].parse().unwrap_or(0.0);
+                format!("{} ({} {target})", &caps[0], round(value * factor))
+            }
+            _ => caps[0].to_string(),
+        }
+    });
+
+    let temperature = Regex::new(r"(-?\d+(?:\.\d+)?)\s?°\s?([CF])\b(\s*\()?").unwrap();
+    temperature
+        .replace_all(&text, |caps: &Captures| {
+            let value: f64 = caps[1].parse().unwrap_or(0.0);
+            match (&caps[2], to, caps.get(3)) {
+                ("F", UnitSystem::Metric, None) => {
+                    format!("{} ({} °C)", &caps[0], round((value - 32.0) / 1.8))
+                }
+                ("C", UnitSystem::Imperial, None) => {
+                    format!("{} ({} °F)", &caps[0], round(value * 1.8 + 32.0))
+                }
+                _ => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+#[tauri::command]
+pub fn get_pipeline_config(pipeline: State<'_, Pipeline>) -> PipelineConfig {
+    pipeline.config.lock().unwrap().clone()
+}
+
+/// Sets the default transforms, or with `conversation_id` that
+/// conversation's override; `None` for `transforms` clears it.
+#[tauri::command]
+pub fn configure_pipeline(
+    app: AppHandle,
+    pipeline: State<'_, Pipeline>,
+    conversation_id: Option<String>,
+    transforms: Option<Vec<Transform>>,
+) -> Result<PipelineConfig> {
+    if let Some(transforms) = &transforms {
+        validate(transforms)?;
+    }
+    let mut config = pipeline.config.lock().unwrap().clone();
+    match (conversation_id, transforms) {
+        (Some(id), Some(transforms)) => {
+            config.conversations.insert(id, transforms);
+        }
+        (Some(id), None) => {
+            config.conversations.remove(&id);
+        }
+        (None, transforms) => config.transforms = transforms.unwrap_or_default(),
+    }
+    config::write(&app, CONFIG_FILE, &config)?;
+    *pipeline.config.lock().unwrap() = config.clone();
+    Ok(config)
+}
+
+/// Runs the pipeline over `content`, to preview a configuration or
+/// reprocess an older answer.
+#[tauri::command]
+pub async fn run_pipeline(
+    app: AppHandle,
+    conversation_id: Option<String>,
+    content: String,
+) -> Result<String> {
+    let mut content = content;
+    apply(&app, conversation_id.as_deref(), &mut content).await;
+    Ok(content)
+}
@@ -0,0 +1,154 @@
+//! `plugin.json`: who a plugin is, what it adds and what it would like to
+//! be allowed.
+
+use std::path::{Component, Path};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+
+const MAX_ID_LEN: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginManifest {
+    /// Lowercase letters, digits, `-` and `_`; also names its directory
+    /// and prefixes its tools.
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    /// Path of the WASM module inside the plugin.
+    #[serde(default = "default_module")]
+    pub module: String,
+    #[serde(default)]
+    pub tools: Vec<ToolSpec>,
+    #[serde(default)]
+    pub transforms: Vec<TransformSpec>,
+    #[serde(default)]
+    pub providers: Vec<ProviderSpec>,
+    /// What the plugin asks for; it gets only what the user grants.
+    #[serde(default)]
+    pub permissions: PluginPermissions,
+}
+
+fn default_module() -> String {
+    "plugin.wasm".into()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    /// JSON Schema of the arguments.
+    #[serde(default = "empty_schema")]
+    pub parameters: Value,
+}
+
+fn empty_schema() -> Value {
+    serde_json::json!({ "type": "object", "properties": {} })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransformSpec {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderSpec {
+    pub id: String,
+    pub name: String,
+    /// The first is the default.
+    pub models: Vec<String>,
+}
+
+/// Capabilities beyond computing on its inputs. In a manifest, what is
+/// asked for; in the plugin settings, what was granted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PluginPermissions {
+    /// Hosts it may send HTTP requests to, subdomains included; `*` is any.
+    pub network: Vec<String>,
+    /// A small key-value store of its own that persists between calls.
+    pub storage: bool,
+}
+
+impl PluginPermissions {
+    /// What of `self` was also asked for in `requested`.
+    pub fn within(self, requested: &PluginPermissions) -> Self {
+        let any_host = requested.network.iter().any(|h| h == "*");
+        Self {
+            network: self
+                .network
+                .into_iter()
+                .filter(|host| {
+                    any_host
+                        || requested
+                            .network
+                            .iter()
+                            .any(|r| r.eq_ignore_ascii_case(host))
+                })
+                .collect(),
+            storage: self.storage && requested.storage,
+        }
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::Plugin(format!("invalid manifest: {}", reason.into()))
+}
+
+impl PluginManifest {
+    fn validate(&self) -> Result<()> {
+        if !valid_name(&self.id) || self.id.len() > MAX_ID_LEN {
+            return Err(invalid(format!(
+                "the id `{}` must be up to {MAX_ID_LEN} lowercase letters, digits, - and _",
+                self.id
+            )));
+        }
+        if self.name.trim().is_empty() {
+            return Err(invalid("the name is empty"));
+        }
+        // The module must stay inside the plugin's directory.
+        let module = Path::new(&self.module);
+        if !module
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(invalid(format!("the module path `{}`", self.module)));
+        }
+        if let Some(tool) = self.tools.iter().find(|t| !valid_name(&t.name)) {
+            return Err(invalid(format!("the tool name `{}`", tool.name)));
+        }
+        if let Some(provider) = self.providers.iter().find(|p| !valid_name(&p.id)) {
+            return Err(invalid(format!("the provider id `{}`", provider.id)));
+        }
+        if let Some(provider) = self.providers.iter().find(|p| p.models.is_empty()) {
+            return Err(invalid(format!(
+                "provider `{}` lists no models",
+                provider.id
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub fn read(path: &Path) -> Result<PluginManifest> {
+    let bytes = std::fs::read(path)?;
+    let manifest: PluginManifest =
+        serde_json::from_slice(&bytes).map_err(|e| invalid(e.to_string()))?;
+    manifest.validate()?;
+    Ok(manifest)
+}
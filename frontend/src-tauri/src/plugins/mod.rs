@@ -0,0 +1,628 @@
+//! User-installed WebAssembly plugins. A plugin is a directory (or a zip of
+//! one) holding a `plugin.json` manifest and a WASM module, and can add
+//! tools models may call, post-processing transforms and chat providers.
+//! Installed plugins live under `plugins/` in the data directory.
+//!
+//! Modules run sandboxed with a memory cap and a fuel budget per call, and
+//! reach nothing outside themselves except through host functions. Those
+//! that touch the network or keep data refuse unless the user granted the
+//! matching permission, and only for the hosts granted; the manifest only
+//! says what a plugin would like.
+//!
+//! The guest interface is plain core WASM. A module exports `memory`,
+//! `alloc(len: i32) -> i32`, and for each kind of contribution it makes one
+//! of `tool`, `transform` or `chat`, each `(ptr: i32, len: i32) -> i64`.
+//! Inputs and outputs are UTF-8 JSON; a result is returned as
+//! `ptr << 32 | len`, and `{"error": "..."}` reports a failure. Host
+//! functions are imported from the `pentamind` module; see `runtime`.
+//!
+//! Plugin providers are registered at startup, so installing one that
+//! adds a provider takes effect after a restart.
+
+mod manifest;
+#[cfg(feature = "plugins")]
+mod runtime;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::llm::{ChatRequest, Usage};
+use crate::profile;
+use crate::providers::{Completion, DeltaSink, ModelInfo, Provider};
+
+pub use manifest::{PluginManifest, PluginPermissions};
+
+const CONFIG_FILE: &str = "plugins.json";
+const DIR: &str = "plugins";
+/// What plugins store, kept apart so upgrades don't lose it.
+const DATA_DIR: &str = "plugin-data";
+const MANIFEST_FILE: &str = "plugin.json";
+/// Separates a plugin's id from its tool's name in what models call it.
+const NAME_SEPARATOR: &str = "__";
+
+#[cfg(feature = "plugins")]
+use runtime::Runtime;
+
+#[cfg(not(feature = "plugins"))]
+struct Runtime;
+
+#[cfg(not(feature = "plugins"))]
+impl Runtime {
+    fn new() -> Result<Self> {
+        Ok(Self)
+    }
+
+    fn check(&self, _wasm: &[u8]) -> Result<()> {
+        Err(Error::Unsupported("WASM plugins in this build".into()))
+    }
+
+    fn forget(&self, _plugin: &str) {}
+
+    fn call(
+        &self,
+        _client: &Client,
+        _plugin: &Installed,
+        _export: &str,
+        _input: &[u8],
+    ) -> Result<Vec<u8>> {
+        Err(Error::Unsupported("WASM plugins in this build".into()))
+    }
+}
+
+/// What the user allowed an installed plugin.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct PluginGrant {
+    enabled: bool,
+    granted: PluginPermissions,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct PluginsConfig {
+    plugins: BTreeMap<String, PluginGrant>,
+}
+
+/// An installed plugin as the runtime sees it.
+#[derive(Debug, Clone)]
+pub(crate) struct Installed {
+    pub manifest: PluginManifest,
+    pub dir: PathBuf,
+    /// Holds `<id>.json` with its stored values.
+    pub data_dir: PathBuf,
+    pub granted: PluginPermissions,
+}
+
+/// What `list_plugins` returns.
+#[derive(Debug, Clone, Serialize)]
+pub struct PluginInfo {
+    #[serde(flatten)]
+    pub manifest: PluginManifest,
+    pub enabled: bool,
+    /// The subset of `manifest.permissions` the user allowed.
+    pub granted: PluginPermissions,
+}
+
+/// A plugin tool as offered to models.
+#[derive(Debug, Clone)]
+pub struct PluginTool {
+    pub plugin: String,
+    pub name: String,
+    /// The name models call it by.
+    pub qualified_name: String,
+    pub description: String,
+    pub parameters: Value,
+    /// Plugins that may reach the network ask before each call.
+    pub requires_approval: bool,
+}
+
+/// Installed plugins and the runtime. Managed as Tauri state.
+pub struct Plugins {
+    config: Mutex<PluginsConfig>,
+    installed: Mutex<BTreeMap<String, Installed>>,
+    runtime: Arc<Runtime>,
+}
+
+impl Plugins {
+    fn get(&self, id: &str) -> Result<Installed> {
+        let enabled = self
+            .config
+            .lock()
+            .unwrap()
+            .plugins
+            .get(id)
+            .is_some_and(|grant| grant.enabled);
+        self.installed
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|_| enabled)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("plugin {id}")))
+    }
+
+    fn enabled(&self) -> Vec<Installed> {
+        let config = self.config.lock().unwrap();
+        self.installed
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|p| {
+                config
+                    .plugins
+                    .get(&p.manifest.id)
+                    .is_some_and(|g| g.enabled)
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn save(&self, app: &AppHandle) -> Result<()> {
+        config::write(app, CONFIG_FILE, &*self.config.lock().unwrap())
+    }
+}
+
+fn plugins_dir(app: &AppHandle) -> Result<PathBuf> {
+    Ok(profile::data_dir(app)?.join(DIR))
+}
+
+fn data_dir(app: &AppHandle) -> Result<PathBuf> {
+    Ok(profile::data_dir(app)?.join(DATA_DIR))
+}
+
+/// Every plugin with a readable manifest, by id. Broken ones are logged
+/// and skipped.
+fn load_installed(
+    dir: &Path,
+    data_dir: &Path,
+    config: &PluginsConfig,
+) -> BTreeMap<String, Installed> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return BTreeMap::new();
+    };
+    entries
+        .flatten()
+        // Dot directories are installs that never finished.
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|entry| {
+            let dir = entry.path();
+            match manifest::read(&dir.join(MANIFEST_FILE)) {
+                Ok(manifest) => {
+                    let granted = config
+                        .plugins
+                        .get(&manifest.id)
+                        .map(|grant| grant.granted.clone())
+                        .unwrap_or_default();
+                    Some((
+                        manifest.id.clone(),
+                        Installed {
+                            manifest,
+                            dir,
+                            data_dir: data_dir.to_path_buf(),
+                            granted,
+                        },
+                    ))
+                }
+                Err(err) => {
+                    tracing::warn!("skipping the plugin in {}: {err}", dir.display());
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Must run before the providers are set up, which include those of
+/// plugins.
+pub fn init(app: &AppHandle) {
+    let config = config::read::<PluginsConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    let installed = plugins_dir(app)
+        .and_then(|dir| Ok(load_installed(&dir, &data_dir(app)?, &config)))
+        .unwrap_or_default();
+    let runtime = match Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            tracing::warn!("plugins are unavailable: {err}");
+            return;
+        }
+    };
+    app.manage(Plugins {
+        config: Mutex::new(config),
+        installed: Mutex::new(installed),
+        runtime: Arc::new(runtime),
+    });
+}
+
+/// Calls `export` in `plugin` with `input` off the async runtime, as WASM
+/// runs synchronously.
+async fn invoke(
+    app: &AppHandle,
+    plugin: &str,
+    export: &'static str,
+    input: Value,
+) -> Result<Value> {
+    let plugins = app
+        .try_state::<Plugins>()
+        .ok_or_else(|| Error::Unsupported("plugins".into()))?;
+    let installed = plugins.get(plugin)?;
+    let runtime = plugins.runtime.clone();
+    let client = app.state::<Client>().inner().clone();
+    let input = serde_json::to_vec(&input)?;
+    let output = tauri::async_runtime::spawn_blocking(move || {
+        runtime.call(&client, &installed, export, &input)
+    })
+    .await
+    .map_err(|e| Error::Plugin(format!("{plugin}: {e}")))??;
+    let output: Value = serde_json::from_slice(&output)
+        .map_err(|e| Error::Plugin(format!("{plugin} returned invalid JSON: {e}")))?;
+    match output.get("error").and_then(Value::as_str) {
+        Some(error) => Err(Error::Plugin(format!("{plugin}: {error}"))),
+        None => Ok(output),
+    }
+}
+
+/// The tools of every enabled plugin.
+pub fn tools(app: &AppHandle) -> Vec<PluginTool> {
+    let Some(plugins) = app.try_state::<Plugins>() else {
+        return Vec::new();
+    };
+    plugins
+        .enabled()
+        .into_iter()
+        .flat_map(|plugin| {
+            let requires_approval = !plugin.granted.network.is_empty();
+            plugin
+                .manifest
+                .tools
+                .into_iter()
+                .map(move |tool| PluginTool {
+                    qualified_name: format!("{}{NAME_SEPARATOR}{}", plugin.manifest.id, tool.name),
+                    plugin: plugin.manifest.id.clone(),
+                    name: tool.name,
+                    description: tool.description,
+                    parameters: tool.parameters,
+                    requires_approval,
+                })
+        })
+        .collect()
+}
+
+pub fn find_tool(app: &AppHandle, qualified_name: &str) -> Option<PluginTool> {
+    tools(app)
+        .into_iter()
+        .find(|tool| tool.qualified_name == qualified_name)
+}
+
+/// Runs a plugin tool; the content and whether it is an error, as MCP
+/// tools report.
+pub async fn call_tool(
+    app: &AppHandle,
+    tool: &PluginTool,
+    arguments: Value,
+) -> Result<(String, bool)> {
+    let output = invoke(
+        app,
+        &tool.plugin,
+        "tool",
+        json!({ "name": tool.name, "arguments": arguments }),
+    )
+    .await?;
+    let content = match &output["content"] {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    };
+    Ok((content, output["is_error"].as_bool().unwrap_or(false)))
+}
+
+/// Runs the transform `name` of `plugin` over `text`.
+pub async fn transform(app: &AppHandle, plugin: &str, name: &str, text: &str) -> Result<String> {
+    let output = invoke(
+        app,
+        plugin,
+        "transform",
+        json!({ "name": name, "text": text }),
+    )
+    .await?;
+    output["text"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| Error::Plugin(format!("{plugin}: the transform returned no text")))
+}
+
+/// A chat provider implemented by a plugin. Answers arrive in one piece.
+struct PluginProvider {
+    app: AppHandle,
+    plugin: String,
+    // Leaked once at startup; the provider registry wants static strings.
+    id: &'static str,
+    name: &'static str,
+    default_model: &'static str,
+    models: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatOutput {
+    content: String,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[async_trait]
+impl Provider for PluginProvider {
+    fn id(&self) -> &'static str {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn default_model(&self) -> &'static str {
+        self.default_model
+    }
+
+    fn configured(&self) -> bool {
+        self.app
+            .try_state::<Plugins>()
+            .is_some_and(|plugins| plugins.get(&self.plugin).is_ok())
+    }
+
+    async fn list_models(&self, _client: &Client) -> Result<Vec<ModelInfo>> {
+        Ok(self
+            .models
+            .iter()
+            .map(|model| ModelInfo {
+                id: model.clone(),
+                name: model.clone(),
+            })
+            .collect())
+    }
+
+    async fn stream(
+        &self,
+        _client: &Client,
+        request: &ChatRequest,
+        on_delta: &mut DeltaSink<'_>,
+    ) -> Result<Completion> {
+        let input = json!({
+            "provider": self.id,
+            "model": request.model,
+            "messages": request.messages,
+            "temperature": request.temperature,
+            "top_p": request.top_p,
+            "max_tokens": request.max_tokens,
+        });
+        let output = invoke(&self.app, &self.plugin, "chat", input).await?;
+        let output: ChatOutput = serde_json::from_value(output)
+            .map_err(|e| Error::Plugin(format!("{}: {e}", self.plugin)))?;
+        on_delta(&output.content);
+        Ok(Completion {
+            content: output.content,
+            usage: output.usage,
+            tool_calls: Vec::new(),
+        })
+    }
+}
+
+/// Providers of the enabled plugins, for the registry. `taken` holds the
+/// ids already in use; a plugin can't replace a built-in provider.
+pub fn providers(app: &AppHandle, taken: &[&str]) -> Vec<Arc<dyn Provider>> {
+    let Some(plugins) = app.try_state::<Plugins>() else {
+        return Vec::new();
+    };
+    let mut providers: Vec<Arc<dyn Provider>> = Vec::new();
+    for plugin in plugins.enabled() {
+        for spec in plugin.manifest.providers {
+            if taken.contains(&spec.id.as_str()) {
+                tracing::warn!(
+                    "plugin {} can't add provider {}: the id is taken",
+                    plugin.manifest.id,
+                    spec.id
+                );
+                continue;
+            }
+            let default_model = spec.models.first().cloned().unwrap_or_default();
+            providers.push(Arc::new(PluginProvider {
+                app: app.clone(),
+                plugin: plugin.manifest.id.clone(),
+                id: spec.id.leak(),
+                name: spec.name.leak(),
+                default_model: default_model.leak(),
+                models: spec.models,
+            }));
+        }
+    }
+    providers
+}
+
+/// Copies a plugin directory into place.
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Unpacks a zipped plugin. The manifest may sit at the top or in a single
+/// folder, as zipping a directory tends to produce.
+fn unzip(archive: &Path, to: &Path) -> Result<()> {
+    let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+    let prefix = (0..zip.len())
+        .filter_map(|i| zip.by_index(i).ok()?.enclosed_name())
+        .find(|name| name.file_name().is_some_and(|n| n == MANIFEST_FILE))
+        .and_then(|name| name.parent().map(Path::to_path_buf))
+        .ok_or_else(|| Error::Plugin(format!("no {MANIFEST_FILE} in the archive")))?;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        // Entries that would land outside the plugin are skipped.
+        let Some(name) = entry.enclosed_name() else {
+            continue;
+        };
+        let Ok(relative) = name.strip_prefix(&prefix) else {
+            continue;
+        };
+        let target = to.join(relative);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&target)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::io::copy(&mut entry, &mut File::create(target)?)?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn list_plugins(plugins: State<'_, Plugins>) -> Vec<PluginInfo> {
+    let config = plugins.config.lock().unwrap();
+    plugins
+        .installed
+        .lock()
+        .unwrap()
+        .values()
+        .map(|plugin| {
+            let grant = config
+                .plugins
+                .get(&plugin.manifest.id)
+                .cloned()
+                .unwrap_or_default();
+            PluginInfo {
+                manifest: plugin.manifest.clone(),
+                enabled: grant.enabled,
+                granted: grant.granted,
+            }
+        })
+        .collect()
+}
+
+/// Installs (or upgrades) the plugin at `path`, a directory or a `.zip`,
+/// with what of its requested permissions the user allowed in `granted`.
+#[tauri::command]
+pub async fn install_plugin(
+    app: AppHandle,
+    plugins: State<'_, Plugins>,
+    path: PathBuf,
+    granted: Option<PluginPermissions>,
+) -> Result<PluginInfo> {
+    let root = plugins_dir(&app)?;
+    let staging = root.join(format!(".install-{}", crate::storage::new_id()));
+    let unpacked = if path.is_dir() {
+        copy_dir(&path, &staging)
+    } else {
+        unzip(&path, &staging)
+    };
+    let installed = unpacked.and_then(|()| {
+        let manifest = manifest::read(&staging.join(MANIFEST_FILE))?;
+        let wasm = std::fs::read(staging.join(&manifest.module))?;
+        plugins.runtime.check(&wasm)?;
+        Ok(manifest)
+    });
+    let manifest = match installed {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            let _ = std::fs::remove_dir_all(&staging);
+            return Err(err);
+        }
+    };
+
+    let dir = root.join(&manifest.id);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::rename(&staging, &dir)?;
+    plugins.runtime.forget(&manifest.id);
+
+    let granted = granted.unwrap_or_default().within(&manifest.permissions);
+    plugins.config.lock().unwrap().plugins.insert(
+        manifest.id.clone(),
+        PluginGrant {
+            enabled: true,
+            granted: granted.clone(),
+        },
+    );
+    plugins.save(&app)?;
+    plugins.installed.lock().unwrap().insert(
+        manifest.id.clone(),
+        Installed {
+            manifest: manifest.clone(),
+            dir,
+            data_dir: data_dir(&app)?,
+            granted: granted.clone(),
+        },
+    );
+    tracing::info!("installed plugin {} {}", manifest.id, manifest.version);
+    Ok(PluginInfo {
+        manifest,
+        enabled: true,
+        granted,
+    })
+}
+
+#[tauri::command]
+pub fn remove_plugin(app: AppHandle, plugins: State<'_, Plugins>, id: String) -> Result<()> {
+    let removed = plugins.installed.lock().unwrap().remove(&id);
+    let removed = removed.ok_or_else(|| Error::NotFound(format!("plugin {id}")))?;
+    plugins.runtime.forget(&id);
+    plugins.config.lock().unwrap().plugins.remove(&id);
+    plugins.save(&app)?;
+    std::fs::remove_dir_all(removed.dir)?;
+    match std::fs::remove_file(removed.data_dir.join(format!("{id}.json"))) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Changes what `id` may do; permissions it never asked for are ignored.
+#[tauri::command]
+pub fn set_plugin_permissions(
+    app: AppHandle,
+    plugins: State<'_, Plugins>,
+    id: String,
+    enabled: bool,
+    granted: PluginPermissions,
+) -> Result<PluginInfo> {
+    let mut installed = plugins.installed.lock().unwrap();
+    let plugin = installed
+        .get_mut(&id)
+        .ok_or_else(|| Error::NotFound(format!("plugin {id}")))?;
+    let granted = granted.within(&plugin.manifest.permissions);
+    plugin.granted = granted.clone();
+    let manifest = plugin.manifest.clone();
+    drop(installed);
+    plugins.config.lock().unwrap().plugins.insert(
+        id,
+        PluginGrant {
+            enabled,
+            granted: granted.clone(),
+        },
+    );
+    plugins.save(&app)?;
+    Ok(PluginInfo {
+        manifest,
+        enabled,
+        granted,
+    })
+}
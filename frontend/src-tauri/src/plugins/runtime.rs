@@ -0,0 +1,369 @@
+//! The wasmtime host. Every call gets a fresh instance, so a plugin keeps
+//! nothing between calls except through its storage.
+//!
+//! Host functions, imported from `pentamind`:
+//!
+//! - `log(level: i32, ptr: i32, len: i32)`, 0 to 3 for debug to error.
+//! - `http_request(ptr: i32, len: i32) -> i64` takes
+//!   `{method, url, headers, body}` and gives `{status, headers, body}`
+//!   or `{error}`. Only to granted hosts.
+//! - `storage_get(ptr: i32, len: i32) -> i64` gives the value under a key,
+//!   or 0 if there is none.
+//! - `storage_set(key_ptr: i32, key_len: i32, ptr: i32, len: i32) -> i32`
+//!   gives 0 once stored; an empty value deletes the key.
+//!
+//! Results are written into guest memory obtained from `alloc` and
+//! returned as `ptr << 32 | len`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use wasmtime::{
+    AsContext, AsContextMut, Caller, Config, Engine, Extern, Linker, Memory, Module, Store,
+    StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+use super::{Installed, PluginPermissions};
+use crate::config;
+use crate::error::{Error, Result};
+
+const MODULE: &str = "pentamind";
+/// Largest linear memory a plugin may grow to.
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+/// Instructions, roughly, before a call is stopped.
+const FUEL: u64 = 10_000_000_000;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_HTTP_BODY: usize = 4 * 1024 * 1024;
+const MAX_STORAGE_BYTES: usize = 1024 * 1024;
+
+/// Storage files are read, changed and written whole.
+static STORAGE: Mutex<()> = Mutex::new(());
+
+struct Host {
+    plugin: Installed,
+    client: Client,
+    limits: StoreLimits,
+}
+
+pub struct Runtime {
+    engine: Engine,
+    linker: Linker<Host>,
+    /// Compiled modules by plugin id.
+    modules: Mutex<HashMap<String, Module>>,
+}
+
+fn plugin_error(plugin: &str, err: impl std::fmt::Display) -> Error {
+    Error::Plugin(format!("{plugin}: {err}"))
+}
+
+impl Runtime {
+    pub fn new() -> Result<Self> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(|e| Error::Plugin(e.to_string()))?;
+        let linker = linker(&engine).map_err(|e| Error::Plugin(e.to_string()))?;
+        Ok(Self {
+            engine,
+            linker,
+            modules: Mutex::default(),
+        })
+    }
+
+    /// Fails unless `wasm` is a module this engine can run.
+    pub fn check(&self, wasm: &[u8]) -> Result<()> {
+        Module::validate(&self.engine, wasm)
+            .map_err(|e| Error::Plugin(format!("invalid module: {e}")))
+    }
+
+    /// Drops the compiled module, after the plugin was replaced or removed.
+    pub fn forget(&self, plugin: &str) {
+        self.modules.lock().unwrap().remove(plugin);
+    }
+
+    fn module(&self, plugin: &Installed) -> Result<Module> {
+        let id = &plugin.manifest.id;
+        if let Some(module) = self.modules.lock().unwrap().get(id) {
+            return Ok(module.clone());
+        }
+        let module = Module::from_file(&self.engine, plugin.dir.join(&plugin.manifest.module))
+            .map_err(|e| plugin_error(id, e))?;
+        self.modules
+            .lock()
+            .unwrap()
+            .insert(id.clone(), module.clone());
+        Ok(module)
+    }
+
+    /// Instantiates the plugin and calls `export` with `input`. Blocks.
+    pub fn call(
+        &self,
+        client: &Client,
+        plugin: &Installed,
+        export: &str,
+        input: &[u8],
+    ) -> Result<Vec<u8>> {
+        let id = plugin.manifest.id.clone();
+        let module = self.module(plugin)?;
+        let host = Host {
+            plugin: plugin.clone(),
+            client: client.clone(),
+            limits: StoreLimitsBuilder::new().memory_size(MEMORY_LIMIT).build(),
+        };
+        let mut store = Store::new(&self.engine, host);
+        store.limiter(|host| &mut host.limits);
+        store.set_fuel(FUEL).map_err(|e| plugin_error(&id, e))?;
+        run(&self.linker, &mut store, &module, export, input).map_err(|e| plugin_error(&id, e))
+    }
+}
+
+fn run(
+    linker: &Linker<Host>,
+    store: &mut Store<Host>,
+    module: &Module,
+    export: &str,
+    input: &[u8],
+) -> wasmtime::Result<Vec<u8>> {
+    let instance = linker.instantiate(&mut *store, module)?;
+    let memory = instance
+        .get_memory(&mut *store, "memory")
+        .ok_or_else(|| wasmtime::Error::msg("the module exports no memory"))?;
+    let alloc = instance.get_typed_func::<i32, i32>(&mut *store, "alloc")?;
+    let func = instance
+        .get_typed_func::<(i32, i32), i64>(&mut *store, export)
+        .map_err(|_| wasmtime::Error::msg(format!("the module doesn't export `{export}`")))?;
+    let (ptr, len) = unpack(write(&memory, &alloc, &mut *store, input)?);
+    let output = func.call(&mut *store, (ptr, len))?;
+    if output == 0 {
+        return Err(wasmtime::Error::msg(format!("`{export}` returned nothing")));
+    }
+    let (ptr, len) = unpack(output);
+    read(&memory, &*store, ptr, len)
+}
+
+fn pack(ptr: i32, len: i32) -> i64 {
+    ((ptr as u32 as i64) << 32) | len as u32 as i64
+}
+
+fn unpack(packed: i64) -> (i32, i32) {
+    ((packed >> 32) as u32 as i32, packed as u32 as i32)
+}
+
+fn read(memory: &Memory, store: impl AsContext, ptr: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+    let mut bytes = vec![0; usize::try_from(len)?];
+    memory.read(&store, usize::try_from(ptr)?, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// Copies `bytes` into memory the guest allocated.
+fn write(
+    memory: &Memory,
+    alloc: &TypedFunc<i32, i32>,
+    mut store: impl AsContextMut,
+    bytes: &[u8],
+) -> wasmtime::Result<i64> {
+    let len = i32::try_from(bytes.len())?;
+    let ptr = alloc.call(&mut store, len)?;
+    memory.write(&mut store, usize::try_from(ptr)?, bytes)?;
+    Ok(pack(ptr, len))
+}
+
+/// The calling guest's memory and allocator.
+fn guest(caller: &mut Caller<'_, Host>) -> wasmtime::Result<(Memory, TypedFunc<i32, i32>)> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmtime::Error::msg("the module exports no memory"))?;
+    let alloc = caller
+        .get_export("alloc")
+        .and_then(Extern::into_func)
+        .ok_or_else(|| wasmtime::Error::msg("the module exports no alloc"))?
+        .typed::<i32, i32>(&*caller)?;
+    Ok((memory, alloc))
+}
+
+fn linker(engine: &Engine) -> wasmtime::Result<Linker<Host>> {
+    let mut linker = Linker::new(engine);
+    linker.func_wrap(
+        MODULE,
+        "log",
+        |mut caller: Caller<'_, Host>, level: i32, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let (memory, _) = guest(&mut caller)?;
+            let message = String::from_utf8_lossy(&read(&memory, &caller, ptr, len)?).into_owned();
+            let plugin = &caller.data().plugin.manifest.id;
+            match level {
+                0 => tracing::debug!("plugin {plugin}: {message}"),
+                1 => tracing::info!("plugin {plugin}: {message}"),
+                2 => tracing::warn!("plugin {plugin}: {message}"),
+                _ => tracing::error!("plugin {plugin}: {message}"),
+            }
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        MODULE,
+        "http_request",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32| -> wasmtime::Result<i64> {
+            let (memory, alloc) = guest(&mut caller)?;
+            let input = read(&memory, &caller, ptr, len)?;
+            let output = match http_request(caller.data(), &input) {
+                Ok(response) => response,
+                Err(err) => json!({ "error": err.to_string() }),
+            };
+            write(&memory, &alloc, &mut caller, &serde_json::to_vec(&output)?)
+        },
+    )?;
+    linker.func_wrap(
+        MODULE,
+        "storage_get",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32| -> wasmtime::Result<i64> {
+            let (memory, alloc) = guest(&mut caller)?;
+            let key = String::from_utf8_lossy(&read(&memory, &caller, ptr, len)?).into_owned();
+            match stored_value(&caller.data().plugin, &key).ok().flatten() {
+                Some(value) => write(&memory, &alloc, &mut caller, value.as_bytes()),
+                None => Ok(0),
+            }
+        },
+    )?;
+    linker.func_wrap(
+        MODULE,
+        "storage_set",
+        |mut caller: Caller<'_, Host>,
+         key_ptr: i32,
+         key_len: i32,
+         ptr: i32,
+         len: i32|
+         -> wasmtime::Result<i32> {
+            let (memory, _) = guest(&mut caller)?;
+            let key =
+                String::from_utf8_lossy(&read(&memory, &caller, key_ptr, key_len)?).into_owned();
+            let value = String::from_utf8_lossy(&read(&memory, &caller, ptr, len)?).into_owned();
+            match store_value(&caller.data().plugin, key, value) {
+                Ok(()) => Ok(0),
+                Err(err) => {
+                    let plugin = &caller.data().plugin.manifest.id;
+                    tracing::warn!("plugin {plugin}: {err}");
+                    Ok(1)
+                }
+            }
+        },
+    )?;
+    Ok(linker)
+}
+
+/// Whether `host` is, or is under, one of the granted hosts.
+fn host_allowed(granted: &PluginPermissions, host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    granted.network.iter().any(|allowed| {
+        let allowed = allowed.to_ascii_lowercase();
+        allowed == "*" || host == allowed || host.ends_with(&format!(".{allowed}"))
+    })
+}
+
+#[derive(Deserialize)]
+struct HttpRequest {
+    #[serde(default = "default_method")]
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+fn default_method() -> String {
+    "GET".into()
+}
+
+fn http_request(host: &Host, input: &[u8]) -> Result<Value> {
+    let plugin = &host.plugin;
+    let request: HttpRequest = serde_json::from_slice(input)?;
+    let url = reqwest::Url::parse(&request.url)
+        .map_err(|e| Error::Plugin(format!("invalid URL: {e}")))?;
+    let allowed = |url: &reqwest::Url| {
+        matches!(url.scheme(), "http" | "https")
+            && url
+                .host_str()
+                .is_some_and(|h| host_allowed(&plugin.granted, h))
+    };
+    if !allowed(&url) {
+        return Err(Error::Plugin(format!(
+            "{} may not reach {}",
+            plugin.manifest.id,
+            url.host_str().unwrap_or(url.as_str())
+        )));
+    }
+    let method = reqwest::Method::from_bytes(request.method.to_uppercase().as_bytes())
+        .map_err(|_| Error::Plugin(format!("invalid method {}", request.method)))?;
+    let mut builder = host.client.request(method, url).timeout(HTTP_TIMEOUT);
+    for (name, value) in &request.headers {
+        builder = builder.header(name, value);
+    }
+    if let Some(body) = request.body {
+        builder = builder.body(body);
+    }
+    tauri::async_runtime::block_on(async move {
+        let response = builder.send().await?;
+        // A redirect elsewhere doesn't hand the plugin another host's reply.
+        if !allowed(response.url()) {
+            return Err(Error::Plugin(format!(
+                "redirected to {}, which is not allowed",
+                response.url().host_str().unwrap_or_default()
+            )));
+        }
+        let status = response.status().as_u16();
+        let headers: BTreeMap<String, String> = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = response.bytes().await?;
+        if body.len() > MAX_HTTP_BODY {
+            return Err(Error::Plugin(format!(
+                "the response is over {MAX_HTTP_BODY} bytes"
+            )));
+        }
+        Ok(json!({
+            "status": status,
+            "headers": headers,
+            "body": String::from_utf8_lossy(&body),
+        }))
+    })
+}
+
+fn storage_file(plugin: &Installed) -> String {
+    format!("{}.json", plugin.manifest.id)
+}
+
+/// The plugin's stored values. Callers hold `STORAGE`.
+fn load(plugin: &Installed) -> Result<BTreeMap<String, String>> {
+    if !plugin.granted.storage {
+        return Err(Error::Plugin("storage was not granted".into()));
+    }
+    Ok(config::read_in(&plugin.data_dir, &storage_file(plugin))?.unwrap_or_default())
+}
+
+fn stored_value(plugin: &Installed, key: &str) -> Result<Option<String>> {
+    let _guard = STORAGE.lock().unwrap();
+    Ok(load(plugin)?.remove(key))
+}
+
+fn store_value(plugin: &Installed, key: String, value: String) -> Result<()> {
+    let _guard = STORAGE.lock().unwrap();
+    let mut values = load(plugin)?;
+    if value.is_empty() {
+        values.remove(&key);
+    } else {
+        values.insert(key, value);
+    }
+    let size: usize = values.iter().map(|(k, v)| k.len() + v.len()).sum();
+    if size > MAX_STORAGE_BYTES {
+        return Err(Error::Plugin(format!(
+            "storage is limited to {MAX_STORAGE_BYTES} bytes"
+        )));
+    }
+    config::write_in(&plugin.data_dir, &storage_file(plugin), &values)
+}
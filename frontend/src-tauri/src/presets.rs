@@ -0,0 +1,326 @@
+//! Presets: named parameter sets such as "creative" or "precise", with
+//! values for every provider and overrides for particular ones, so a
+//! comparison can be run again the same way later. Applying one to a
+//! fan-out fills in whatever its targets leave unset.
+
+use std::collections::BTreeMap;
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+
+use crate::error::{Error, Result};
+use crate::fanout::{self, FanoutRequest};
+use crate::providers::Providers;
+use crate::storage::{new_id, now_ms, Database};
+
+/// `provider` value of the row holding the values for every provider.
+const ALL_PROVIDERS: &str = "";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresetParams {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub system_prompt: Option<String>,
+}
+
+impl PresetParams {
+    /// These values, with `fallback`'s wherever one is unset.
+    fn or(&self, fallback: &Self) -> Self {
+        Self {
+            temperature: self.temperature.or(fallback.temperature),
+            top_p: self.top_p.or(fallback.top_p),
+            max_tokens: self.max_tokens.or(fallback.max_tokens),
+            system_prompt: self
+                .system_prompt
+                .clone()
+                .or_else(|| fallback.system_prompt.clone()),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn normalize(mut self) -> Result<Self> {
+        if self.temperature.is_some_and(|t| !(0.0..=2.0).contains(&t)) {
+            return Err(Error::InvalidPreset(
+                "temperature must be between 0 and 2".into(),
+            ));
+        }
+        if self.top_p.is_some_and(|p| !(0.0..=1.0).contains(&p)) {
+            return Err(Error::InvalidPreset("top_p must be between 0 and 1".into()));
+        }
+        if self.max_tokens == Some(0) {
+            return Err(Error::InvalidPreset("max_tokens must be above 0".into()));
+        }
+        self.system_prompt = self.system_prompt.filter(|s| !s.trim().is_empty());
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Preset {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    /// Used for every provider without values of its own.
+    pub defaults: PresetParams,
+    /// Per provider id; unset fields fall back to `defaults`.
+    pub providers: BTreeMap<String, PresetParams>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PresetInput {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub defaults: PresetParams,
+    #[serde(default)]
+    pub providers: BTreeMap<String, PresetParams>,
+}
+
+impl Preset {
+    const COLUMNS: &'static str = "id, name, description, created_at, updated_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            description: row.get(2)?,
+            defaults: PresetParams::default(),
+            providers: BTreeMap::new(),
+            created_at: row.get(3)?,
+            updated_at: row.get(4)?,
+        })
+    }
+
+    /// Loads the parameter rows.
+    fn complete(mut self, conn: &Connection) -> Result<Self> {
+        let mut stmt = conn.prepare_cached(
+            "SELECT provider, temperature, top_p, max_tokens, system_prompt
+             FROM preset_params WHERE preset_id = ?1",
+        )?;
+        let rows = stmt.query_map([&self.id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                PresetParams {
+                    temperature: row.get(1)?,
+                    top_p: row.get(2)?,
+                    max_tokens: row.get(3)?,
+                    system_prompt: row.get(4)?,
+                },
+            ))
+        })?;
+        for row in rows {
+            let (provider, params) = row?;
+            match provider.as_str() {
+                ALL_PROVIDERS => self.defaults = params,
+                _ => {
+                    self.providers.insert(provider, params);
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// What the preset sets for `provider`.
+    pub fn params_for(&self, provider: &str) -> PresetParams {
+        match self.providers.get(provider) {
+            Some(params) => params.or(&self.defaults),
+            None => self.defaults.clone(),
+        }
+    }
+
+    /// `request` with the preset filled into each target. Values a target
+    /// sets itself are kept; an empty target list turns into every
+    /// configured provider first.
+    pub fn apply(
+        &self,
+        app: &AppHandle,
+        request: &FanoutRequest,
+        providers: &Providers,
+    ) -> FanoutRequest {
+        let mut applied = request.clone();
+        applied.targets = fanout::targets(app, request, providers)
+            .into_iter()
+            .map(|mut target| {
+                let params = self.params_for(&target.provider);
+                target.temperature = target.temperature.or(params.temperature);
+                target.top_p = target.top_p.or(params.top_p);
+                target.max_tokens = target.max_tokens.or(params.max_tokens);
+                target.system_prompt = target.system_prompt.or(params.system_prompt);
+                target
+            })
+            .collect();
+        applied.preset_id = None;
+        applied
+    }
+}
+
+impl PresetInput {
+    fn normalize(mut self) -> Result<Self> {
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() {
+            return Err(Error::InvalidPreset("the name is empty".into()));
+        }
+        self.description = self.description.filter(|d| !d.trim().is_empty());
+        self.defaults = self.defaults.normalize()?;
+        self.providers = std::mem::take(&mut self.providers)
+            .into_iter()
+            .map(|(provider, params)| Ok((provider.trim().to_string(), params.normalize()?)))
+            .filter(|entry| {
+                entry.as_ref().map_or(true, |(provider, params)| {
+                    !provider.is_empty() && !params.is_empty()
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(self)
+    }
+}
+
+fn write_params(conn: &Connection, id: &str, input: &PresetInput) -> Result<()> {
+    conn.execute("DELETE FROM preset_params WHERE preset_id = ?1", [id])?;
+    let rows = std::iter::once((ALL_PROVIDERS, &input.defaults)).chain(
+        input
+            .providers
+            .iter()
+            .map(|(p, params)| (p.as_str(), params)),
+    );
+    for (provider, params) in rows {
+        conn.execute(
+            "INSERT INTO preset_params
+                 (preset_id, provider, temperature, top_p, max_tokens, system_prompt)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                id,
+                provider,
+                params.temperature,
+                params.top_p,
+                params.max_tokens,
+                params.system_prompt
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+impl Database {
+    pub fn create_preset(&self, input: PresetInput) -> Result<Preset> {
+        let input = input.normalize()?;
+        let id = new_id();
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO presets (id, name, description, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?4)",
+            params![id, input.name, input.description, now_ms()],
+        )?;
+        write_params(&tx, &id, &input)?;
+        tx.commit()?;
+        drop(conn);
+        self.get_preset(&id)
+    }
+
+    pub fn update_preset(&self, id: &str, input: PresetInput) -> Result<Preset> {
+        let input = input.normalize()?;
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let updated = tx.execute(
+            "UPDATE presets SET name = ?2, description = ?3, updated_at = ?4 WHERE id = ?1",
+            params![id, input.name, input.description, now_ms()],
+        )?;
+        if updated == 0 {
+            return Err(Error::NotFound(format!("preset {id}")));
+        }
+        write_params(&tx, id, &input)?;
+        tx.commit()?;
+        drop(conn);
+        self.get_preset(id)
+    }
+
+    pub fn get_preset(&self, id: &str) -> Result<Preset> {
+        let conn = self.conn()?;
+        conn.query_row(
+            &format!("SELECT {} FROM presets WHERE id = ?1", Preset::COLUMNS),
+            [id],
+            Preset::from_row,
+        )
+        .optional()?
+        .ok_or_else(|| Error::NotFound(format!("preset {id}")))?
+        .complete(&conn)
+    }
+
+    pub fn list_presets(&self) -> Result<Vec<Preset>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM presets ORDER BY name COLLATE NOCASE",
+            Preset::COLUMNS
+        ))?;
+        let presets = stmt
+            .query_map([], Preset::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        presets
+            .into_iter()
+            .map(|preset| preset.complete(&conn))
+            .collect()
+    }
+
+    pub fn delete_preset(&self, id: &str) -> Result<()> {
+        let deleted = self
+            .conn()?
+            .execute("DELETE FROM presets WHERE id = ?1", [id])?;
+        if deleted == 0 {
+            return Err(Error::NotFound(format!("preset {id}")));
+        }
+        Ok(())
+    }
+}
+
+#[tauri::command]
+pub async fn create_preset(db: State<'_, Database>, preset: PresetInput) -> Result<Preset> {
+    db.create_preset(preset)
+}
+
+/// Replaces a preset's name, description and parameters.
+#[tauri::command]
+pub async fn update_preset(
+    db: State<'_, Database>,
+    id: String,
+    preset: PresetInput,
+) -> Result<Preset> {
+    db.update_preset(&id, preset)
+}
+
+#[tauri::command]
+pub async fn get_preset(db: State<'_, Database>, id: String) -> Result<Preset> {
+    db.get_preset(&id)
+}
+
+#[tauri::command]
+pub async fn list_presets(db: State<'_, Database>) -> Result<Vec<Preset>> {
+    db.list_presets()
+}
+
+#[tauri::command]
+pub async fn delete_preset(db: State<'_, Database>, id: String) -> Result<()> {
+    db.delete_preset(&id)
+}
+
+/// The fan-out request as it would run with preset `id`, one target per
+/// provider with its parameters spelled out. Passing `preset_id` to
+/// `fanout_prompt` does the same at send time.
+#[tauri::command]
+pub async fn apply_preset(
+    app: AppHandle,
+    db: State<'_, Database>,
+    providers: State<'_, Providers>,
+    id: String,
+    request: FanoutRequest,
+) -> Result<FanoutRequest> {
+    Ok(db.get_preset(&id)?.apply(&app, &request, &providers))
+}
@@ -0,0 +1,49 @@
+//! Spawning the platform tools some features lean on.
+
+use std::process::Command;
+
+/// A command for `program`, without the console window Windows would
+/// otherwise flash up.
+pub fn command(program: &str) -> Command {
+    #[allow(unused_mut)]
+    let mut command = Command::new(program);
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+    command
+}
+
+/// Starts `command` in a process group of its own, so [`kill_tree`] stops
+/// whatever it starts as well.
+pub fn own_group(command: &mut tokio::process::Command) {
+    #[cfg(unix)]
+    command.process_group(0);
+    #[cfg(windows)]
+    {
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        command.creation_flags(CREATE_NO_WINDOW | CREATE_NEW_PROCESS_GROUP);
+    }
+}
+
+/// Kills `child` and everything it started: its process group on unix,
+/// its process tree on Windows.
+pub async fn kill_tree(child: &mut tokio::process::Child) {
+    if let Some(pid) = child.id() {
+        #[cfg(unix)]
+        // The group was made by `own_group`, so its id is the child's.
+        unsafe {
+            libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+        }
+        #[cfg(windows)]
+        {
+            let mut taskkill = tokio::process::Command::from(command("taskkill"));
+            taskkill.args(["/T", "/F", "/PID", &pid.to_string()]);
+            let _ = taskkill.status().await;
+        }
+    }
+    let _ = child.kill().await;
+}
@@ -0,0 +1,280 @@
+//! Profiles such as Work and Personal, each with its own database, settings
+//! directory and credential store entries so contexts and billing keys
+//! never mix. The default profile uses the app's own directories, so an
+//! existing install carries on as it; others live under `profiles/<id>`.
+//!
+//! One profile is active per run. `--profile <name>` picks it at launch,
+//! creating it if needed; otherwise the last one used opens. Everything
+//! reads its state at startup, so switching saves the choice and restarts.
+//!
+//! A profile's data can be moved out of the default location; see
+//! `relocate`. Its settings stay in the config directory.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::storage::{now_ms, DB_FILE};
+
+pub const DEFAULT_ID: &str = "default";
+/// The profile list, kept in the app's own config directory.
+pub const REGISTRY_FILE: &str = "profiles.json";
+pub(crate) const PROFILES_DIR: &str = "profiles";
+const FLAG: &str = "--profile";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub id: String,
+    pub name: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileInfo {
+    #[serde(flatten)]
+    pub profile: Profile,
+    pub active: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Registry {
+    profiles: Vec<Profile>,
+    last: Option<String>,
+    /// Set by `switch_profile` for the restart. It wins over a `--profile`
+    /// flag the app may be relaunched with.
+    next: Option<String>,
+    /// Data directories of profiles kept somewhere other than the default.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    data_dirs: BTreeMap<String, PathBuf>,
+}
+
+static ACTIVE: OnceLock<String> = OnceLock::new();
+/// The active profile's data directory, when it isn't the default.
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+/// The chosen data directory, when it had no database at startup (a drive
+/// that isn't plugged in, say) and the default was used instead.
+static UNAVAILABLE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Id of the profile this run belongs to.
+pub fn active() -> &'static str {
+    ACTIVE.get().map_or(DEFAULT_ID, String::as_str)
+}
+
+fn scoped(dir: PathBuf) -> PathBuf {
+    match active() {
+        DEFAULT_ID => dir,
+        id => dir.join(PROFILES_DIR).join(id),
+    }
+}
+
+pub fn config_dir(app: &AppHandle) -> Result<PathBuf> {
+    Ok(scoped(app.path().app_config_dir()?))
+}
+
+pub fn data_dir(app: &AppHandle) -> Result<PathBuf> {
+    match DATA_DIR.get() {
+        Some(dir) => Ok(dir.clone()),
+        None => default_data_dir(app),
+    }
+}
+
+/// Where the active profile's data lives unless it was moved.
+pub fn default_data_dir(app: &AppHandle) -> Result<PathBuf> {
+    Ok(scoped(app.path().app_data_dir()?))
+}
+
+pub fn unavailable_data_dir() -> Option<&'static Path> {
+    UNAVAILABLE.get().map(PathBuf::as_path)
+}
+
+/// Points the active profile at `dir` from the next launch; `None` goes
+/// back to the default location.
+pub fn set_data_dir(app: &AppHandle, dir: Option<PathBuf>) -> Result<()> {
+    let mut registry = load(app)?;
+    match dir {
+        Some(dir) => registry.data_dirs.insert(active().to_string(), dir),
+        None => registry.data_dirs.remove(active()),
+    };
+    save(app, &registry)
+}
+
+/// Credential store service for the active profile's secrets.
+pub fn key_service(base: &str) -> String {
+    match active() {
+        DEFAULT_ID => base.to_string(),
+        id => format!("{base}.{id}"),
+    }
+}
+
+fn default_profile() -> Profile {
+    Profile {
+        id: DEFAULT_ID.into(),
+        name: "Default".into(),
+        created_at: 0,
+    }
+}
+
+fn load(app: &AppHandle) -> Result<Registry> {
+    let mut registry: Registry =
+        config::read_in(&app.path().app_config_dir()?, REGISTRY_FILE)?.unwrap_or_default();
+    if !registry.profiles.iter().any(|p| p.id == DEFAULT_ID) {
+        registry.profiles.insert(0, default_profile());
+    }
+    Ok(registry)
+}
+
+fn save(app: &AppHandle, registry: &Registry) -> Result<()> {
+    config::write_in(&app.path().app_config_dir()?, REGISTRY_FILE, registry)
+}
+
+/// Lowercase letters, digits and dashes, safe as a directory name.
+fn slug(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.trim().chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+fn find<'a>(registry: &'a Registry, name: &str) -> Option<&'a Profile> {
+    let id = slug(name);
+    registry
+        .profiles
+        .iter()
+        .find(|p| p.id == id || p.name.eq_ignore_ascii_case(name.trim()))
+}
+
+fn create(registry: &mut Registry, name: &str) -> Result<Profile> {
+    let id = slug(name);
+    if id.is_empty() {
+        return Err(Error::InvalidSetting(format!(
+            "profile name `{name}` needs a letter or digit"
+        )));
+    }
+    if find(registry, name).is_some() {
+        return Err(Error::InvalidSetting(format!(
+            "profile {name} already exists"
+        )));
+    }
+    let profile = Profile {
+        id,
+        name: name.trim().to_string(),
+        created_at: now_ms(),
+    };
+    registry.profiles.push(profile.clone());
+    Ok(profile)
+}
+
+/// The value of `--profile` in `args`, as `--profile work` or
+/// `--profile=work`.
+fn flag(args: &[String]) -> Option<&str> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == FLAG {
+            return args.next().map(String::as_str);
+        }
+        if let Some(value) = arg.strip_prefix(FLAG).and_then(|v| v.strip_prefix('=')) {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Picks the profile for this run. Called first in setup, before anything
+/// reads a config file or opens the database.
+pub fn init(app: &AppHandle) -> Result<()> {
+    let mut registry = load(app)?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let requested = registry
+        .next
+        .take()
+        .or_else(|| flag(&args).map(str::to_string));
+    let id = match requested {
+        Some(name) => match find(&registry, &name) {
+            Some(profile) => profile.id.clone(),
+            None => create(&mut registry, &name)?.id,
+        },
+        None => registry
+            .last
+            .clone()
+            .filter(|id| registry.profiles.iter().any(|p| &p.id == id))
+            .unwrap_or_else(|| DEFAULT_ID.to_string()),
+    };
+    registry.last = Some(id.clone());
+    save(app, &registry)?;
+    if let Some(dir) = registry.data_dirs.get(&id) {
+        if dir.join(DB_FILE).is_file() {
+            let _ = DATA_DIR.set(dir.clone());
+        } else {
+            let _ = UNAVAILABLE.set(dir.clone());
+        }
+    }
+    let _ = ACTIVE.set(id);
+    Ok(())
+}
+
+/// Switches to the profile a second launch asked for with `--profile`.
+pub fn handle_args(app: &AppHandle, args: &[String]) {
+    let Some(name) = flag(args) else {
+        return;
+    };
+    let switched = load(app).and_then(|mut registry| {
+        let id = match find(&registry, name) {
+            Some(profile) => profile.id.clone(),
+            None => create(&mut registry, name)?.id,
+        };
+        save(app, &registry)?;
+        Ok(id)
+    });
+    let result = match switched {
+        Ok(id) if id == active() => Ok(()),
+        Ok(id) => switch_profile(app.clone(), id),
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        tracing::warn!("couldn't switch to profile {name}: {err}");
+    }
+}
+
+#[tauri::command]
+pub fn list_profiles(app: AppHandle) -> Result<Vec<ProfileInfo>> {
+    Ok(load(&app)?
+        .profiles
+        .into_iter()
+        .map(|profile| ProfileInfo {
+            active: profile.id == active(),
+            profile,
+        })
+        .collect())
+}
+
+/// Adds a profile. Its directories are made the first time it is used.
+#[tauri::command]
+pub fn create_profile(app: AppHandle, name: String) -> Result<Profile> {
+    let mut registry = load(&app)?;
+    let profile = create(&mut registry, &name)?;
+    save(&app, &registry)?;
+    Ok(profile)
+}
+
+/// Restarts the app into profile `id`.
+#[tauri::command]
+pub fn switch_profile(app: AppHandle, id: String) -> Result<()> {
+    let mut registry = load(&app)?;
+    if !registry.profiles.iter().any(|p| p.id == id) {
+        return Err(Error::NotFound(format!("profile {id}")));
+    }
+    registry.next = Some(id);
+    save(&app, &registry)?;
+    app.restart()
+}
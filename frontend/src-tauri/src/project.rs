@@ -0,0 +1,497 @@
+//! Collects a folder as context for a prompt: a tree of what's in it and
+//! the contents of the files that matter, skipping whatever `.gitignore`
+//! leaves out of the repository. Files are picked by glob, by embedding
+//! similarity to a question, or else READMEs and manifests first.
+
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::error::{Error, Result};
+use crate::ingest;
+use crate::providers::Providers;
+use crate::rag::{self, Embedder, EmbeddingOptions};
+
+/// Entries visited before the walk gives up on a huge folder.
+const MAX_ENTRIES: usize = 20_000;
+/// Files larger than this are listed but never attached.
+const MAX_FILE_BYTES: u64 = 64 * 1024;
+/// Text files read as candidates for attaching.
+const MAX_CANDIDATES: usize = 500;
+/// Candidates embedded when ranking by a question.
+const MAX_RANKED: usize = 200;
+/// Leading characters of a file that stand in for it when ranking.
+const RANK_EXCERPT: usize = 2000;
+const MAX_TREE_LINES: usize = 400;
+/// Never attach part of a file when less than this much budget is left.
+const MIN_PARTIAL_BYTES: usize = 1024;
+const ALWAYS_SKIPPED: &[&str] = &[".git"];
+/// Opened first when nothing more specific was asked for.
+const KEY_FILES: &[&str] = &[
+    "readme",
+    "cargo.toml",
+    "package.json",
+    "pyproject.toml",
+    "go.mod",
+    "pom.xml",
+    "build.gradle",
+    "gemfile",
+    "composer.json",
+    "makefile",
+    "dockerfile",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ContextOptions {
+    /// Globs over paths relative to the folder, such as `src/**/*.rs`. One
+    /// without a slash matches the file name at any depth.
+    pub include: Vec<String>,
+    /// Picks the files closest to this text by embedding similarity.
+    pub query: Option<String>,
+    pub embedding: Option<EmbeddingOptions>,
+    pub max_files: usize,
+    /// Budget for file contents, in bytes.
+    pub max_bytes: usize,
+}
+
+impl Default for ContextOptions {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            query: None,
+            embedding: None,
+            max_files: 20,
+            max_bytes: 200_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProjectFile {
+    /// Relative to the folder, with `/` separators.
+    pub path: String,
+    pub language: Option<&'static str>,
+    pub content: String,
+    /// Cut short to stay within the byte budget.
+    pub truncated: bool,
+    /// Similarity to the query, when ranking by one.
+    pub score: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProjectContext {
+    pub root: PathBuf,
+    /// Checked-out branch, when the folder is a git repository.
+    pub branch: Option<String>,
+    pub tree: String,
+    pub files: Vec<ProjectFile>,
+    /// Files found, not counting ignored ones.
+    pub total_files: usize,
+    /// The folder was too big to walk completely.
+    pub incomplete: bool,
+    /// The tree and files as one Markdown block, ready to attach.
+    pub context: String,
+}
+
+/// One line of a `.gitignore`.
+struct Rule {
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+    /// Contains a slash, so it matches the path from the file's folder
+    /// rather than a name at any depth.
+    anchored: bool,
+}
+
+/// The rules of one ignore file and the folder they apply under.
+struct IgnoreFile {
+    base: String,
+    rules: Vec<Rule>,
+}
+
+const PATH_MATCH: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+fn parse_ignore(text: &str, base: &str) -> IgnoreFile {
+    let rules = text
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            let pattern = Pattern::new(line.trim_start_matches('/')).ok()?;
+            Some(Rule {
+                pattern,
+                negated,
+                dir_only,
+                anchored,
+            })
+        })
+        .collect();
+    IgnoreFile {
+        base: base.to_string(),
+        rules,
+    }
+}
+
+/// Whether `path` (relative to the root) is ignored; as in git, the last
+/// matching rule decides.
+fn ignored(stack: &[IgnoreFile], path: &str, is_dir: bool) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let mut ignored = false;
+    for file in stack {
+        let relative = match file.base.as_str() {
+            "" => path,
+            base => match path.strip_prefix(base).and_then(|p| p.strip_prefix('/')) {
+                Some(relative) => relative,
+                None => continue,
+            },
+        };
+        for rule in &file.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let subject = if rule.anchored { relative } else { name };
+            if rule.pattern.matches_with(subject, PATH_MATCH) {
+                ignored = !rule.negated;
+            }
+        }
+    }
+    ignored
+}
+
+struct Entry {
+    path: String,
+    depth: usize,
+    is_dir: bool,
+    size: u64,
+}
+
+struct Walk {
+    entries: Vec<Entry>,
+    incomplete: bool,
+}
+
+fn relative(parent: &str, name: &str) -> String {
+    match parent {
+        "" => name.to_string(),
+        parent => format!("{parent}/{name}"),
+    }
+}
+
+fn walk_dir(root: &Path, dir: &str, depth: usize, stack: &mut Vec<IgnoreFile>, walk: &mut Walk) {
+    let full = root.join(dir);
+    let own_rules = std::fs::read_to_string(full.join(".gitignore")).ok();
+    if let Some(text) = &own_rules {
+        stack.push(parse_ignore(text, dir));
+    }
+    let mut children: Vec<_> = match std::fs::read_dir(&full) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).collect(),
+        Err(_) => Vec::new(),
+    };
+    children.sort_by_key(|e| e.file_name());
+    for child in children {
+        if walk.entries.len() >= MAX_ENTRIES {
+            walk.incomplete = true;
+            break;
+        }
+        let name = child.file_name().to_string_lossy().into_owned();
+        // Symlinks are skipped so a link can't lead the walk out of the
+        // folder or round in circles.
+        let Ok(kind) = child.file_type() else {
+            continue;
+        };
+        if kind.is_symlink() || ALWAYS_SKIPPED.contains(&name.as_str()) {
+            continue;
+        }
+        let path = relative(dir, &name);
+        if ignored(stack, &path, kind.is_dir()) {
+            continue;
+        }
+        let size = child.metadata().map(|m| m.len()).unwrap_or_default();
+        walk.entries.push(Entry {
+            path: path.clone(),
+            depth,
+            is_dir: kind.is_dir(),
+            size,
+        });
+        if kind.is_dir() {
+            walk_dir(root, &path, depth + 1, stack, walk);
+        }
+    }
+    if own_rules.is_some() {
+        stack.pop();
+    }
+}
+
+fn walk(root: &Path) -> Walk {
+    let mut stack = Vec::new();
+    if let Ok(text) = std::fs::read_to_string(root.join(".git/info/exclude")) {
+        stack.push(parse_ignore(&text, ""));
+    }
+    let mut walk = Walk {
+        entries: Vec::new(),
+        incomplete: false,
+    };
+    walk_dir(root, "", 0, &mut stack, &mut walk);
+    walk
+}
+
+/// Files under `root` that `.gitignore` doesn't exclude, relative to it.
+pub(crate) fn list_files(root: &Path) -> Vec<String> {
+    walk(root)
+        .entries
+        .into_iter()
+        .filter(|e| !e.is_dir)
+        .map(|e| e.path)
+        .collect()
+}
+
+/// The checked-out branch from `.git/HEAD`; a detached head shows as its
+/// short commit id.
+fn branch(root: &Path) -> Option<String> {
+    let head = std::fs::read_to_string(root.join(".git/HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(reference.trim_start_matches("refs/heads/").to_string()),
+        None => Some(head.chars().take(12).collect()),
+    }
+}
+
+fn render_tree(name: &str, walk: &Walk) -> String {
+    let mut lines = vec![format!("{name}/")];
+    let mut hidden = 0;
+    for entry in &walk.entries {
+        if lines.len() >= MAX_TREE_LINES {
+            hidden += 1;
+            continue;
+        }
+        let file_name = entry.path.rsplit('/').next().unwrap_or(&entry.path);
+        let indent = "  ".repeat(entry.depth + 1);
+        lines.push(match entry.is_dir {
+            true => format!("{indent}{file_name}/"),
+            false => format!("{indent}{file_name}"),
+        });
+    }
+    if hidden > 0 {
+        lines.push(format!("… {hidden} more entries"));
+    }
+    if walk.incomplete {
+        lines.push("… folder too large to list in full".into());
+    }
+    lines.join("\n")
+}
+
+fn include_patterns(include: &[String]) -> Result<Vec<(Pattern, bool)>> {
+    include
+        .iter()
+        .map(|glob| {
+            let pattern = Pattern::new(glob.trim_start_matches('/'))
+                .map_err(|e| Error::InvalidSetting(format!("glob `{glob}`: {e}")))?;
+            Ok((pattern, glob.contains('/')))
+        })
+        .collect()
+}
+
+struct Candidate {
+    path: String,
+    depth: usize,
+    language: Option<&'static str>,
+    text: String,
+}
+
+/// Reads the text files worth considering; binaries and oversized files
+/// are left out.
+fn read_candidates(root: &Path, walk: &Walk, include: &[(Pattern, bool)]) -> Vec<Candidate> {
+    walk.entries
+        .iter()
+        .filter(|e| !e.is_dir && e.size <= MAX_FILE_BYTES)
+        .filter(|e| {
+            let name = e.path.rsplit('/').next().unwrap_or(&e.path);
+            include.is_empty()
+                || include.iter().any(|(pattern, anchored)| {
+                    pattern.matches_with(if *anchored { &e.path } else { name }, PATH_MATCH)
+                })
+        })
+        .filter_map(|e| {
+            let bytes = std::fs::read(root.join(&e.path)).ok()?;
+            if bytes.iter().take(8000).any(|&b| b == 0) {
+                return None;
+            }
+            let text = String::from_utf8(bytes).ok()?;
+            let extension = Path::new(&e.path)
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(str::to_ascii_lowercase)
+                .unwrap_or_default();
+            Some(Candidate {
+                path: e.path.clone(),
+                depth: e.depth,
+                language: ingest::code_language(&extension),
+                text,
+            })
+        })
+        .take(MAX_CANDIDATES)
+        .collect()
+}
+
+/// READMEs and manifests first, then shallower files before deeper ones.
+fn default_order(candidates: &[Candidate]) -> Vec<(usize, Option<f32>)> {
+    let mut order: Vec<usize> = (0..candidates.len()).collect();
+    order.sort_by_key(|&i| {
+        let candidate = &candidates[i];
+        let name = candidate
+            .path
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let key = KEY_FILES
+            .iter()
+            .any(|k| name == *k || name.starts_with(&format!("{k}.")));
+        (!key, candidate.depth)
+    });
+    order.into_iter().map(|i| (i, None)).collect()
+}
+
+async fn ranked_order(
+    embedder: &Embedder,
+    client: &Client,
+    query: &str,
+    candidates: &[Candidate],
+) -> Result<Vec<(usize, Option<f32>)>> {
+    let ranked = candidates.len().min(MAX_RANKED);
+    let mut inputs = vec![query.to_string()];
+    inputs.extend(candidates[..ranked].iter().map(|c| {
+        let excerpt: String = c.text.chars().take(RANK_EXCERPT).collect();
+        format!("{}\n{excerpt}", c.path)
+    }));
+    let vectors = embedder.embed(client, &inputs).await?;
+    let mut scored: Vec<(usize, Option<f32>)> = vectors[1..]
+        .iter()
+        .enumerate()
+        .map(|(i, vector)| (i, Some(rag::cosine(&vectors[0], vector))))
+        .collect();
+    scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    Ok(scored)
+}
+
+/// Takes files in `order` until the file count or byte budget runs out,
+/// cutting the last one short if a useful part of it fits.
+fn select(
+    candidates: Vec<Candidate>,
+    order: Vec<(usize, Option<f32>)>,
+    options: &ContextOptions,
+) -> Vec<ProjectFile> {
+    let mut candidates: Vec<Option<Candidate>> = candidates.into_iter().map(Some).collect();
+    let mut budget = options.max_bytes;
+    let mut files = Vec::new();
+    for (index, score) in order {
+        if files.len() >= options.max_files || budget < MIN_PARTIAL_BYTES {
+            break;
+        }
+        let Some(candidate) = candidates[index].take() else {
+            continue;
+        };
+        let mut content = candidate.text;
+        let truncated = content.len() > budget;
+        if truncated {
+            let mut end = budget;
+            while !content.is_char_boundary(end) {
+                end -= 1;
+            }
+            content.truncate(end);
+        }
+        budget -= content.len();
+        files.push(ProjectFile {
+            path: candidate.path,
+            language: candidate.language,
+            content,
+            truncated,
+            score,
+        });
+    }
+    files
+}
+
+fn render_context(name: &str, branch: Option<&str>, tree: &str, files: &[ProjectFile]) -> String {
+    let mut context = format!("# Project {name}\n\n");
+    if let Some(branch) = branch {
+        context.push_str(&format!("Branch: {branch}\n\n"));
+    }
+    context.push_str(&format!("## Files\n\n```\n{tree}\n```\n"));
+    for file in files {
+        let note = if file.truncated { " (truncated)" } else { "" };
+        context.push_str(&format!(
+            "\n## {}{note}\n\n```{}\n{}\n```\n",
+            file.path,
+            file.language.unwrap_or_default(),
+            file.content.trim_end()
+        ));
+    }
+    context
+}
+
+#[tauri::command]
+pub async fn project_context(
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    folder: PathBuf,
+    options: Option<ContextOptions>,
+) -> Result<ProjectContext> {
+    let options = options.unwrap_or_default();
+    if !folder.is_dir() {
+        return Err(Error::NotFound(format!("folder {}", folder.display())));
+    }
+    let include = include_patterns(&options.include)?;
+    let root = folder.clone();
+    let (walk, candidates) = tauri::async_runtime::spawn_blocking(move || {
+        let walk = walk(&root);
+        let candidates = read_candidates(&root, &walk, &include);
+        (walk, candidates)
+    })
+    .await
+    .map_err(|e| Error::Io(std::io::Error::other(e)))?;
+
+    let order = match options.query.as_deref().filter(|q| !q.trim().is_empty()) {
+        Some(query) if !candidates.is_empty() => {
+            let embedder =
+                Embedder::resolve(&providers, &options.embedding.clone().unwrap_or_default())?;
+            ranked_order(&embedder, &client, query, &candidates).await?
+        }
+        _ => default_order(&candidates),
+    };
+    let name = folder
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| folder.display().to_string());
+    let branch = branch(&folder);
+    let tree = render_tree(&name, &walk);
+    let files = select(candidates, order, &options);
+    Ok(ProjectContext {
+        context: render_context(&name, branch.as_deref(), &tree, &files),
+        root: folder,
+        branch,
+        tree,
+        files,
+        total_files: walk.entries.iter().filter(|e| !e.is_dir).count(),
+        incomplete: walk.incomplete,
+    })
+}
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+use super::{
+    api_key, arguments_object, check_status, read_sse, token_count, Completion, DeltaSink,
+    ModelInfo, PartialToolCall, Provider,
+};
+use crate::audit;
+use crate::error::Result;
+use crate::llm::{ChatRequest, Role, ToolRound};
+use crate::network;
+
+const BASE_URL: &str = "https://api.anthropic.com/v1";
+const API_VERSION: &str = "2023-06-01";
+const KEY_VAR: &str = "ANTHROPIC_API_KEY";
+// The Messages API requires max_tokens on every request.
+const DEFAULT_MAX_TOKENS: u32 = 1024;
+
+pub struct Anthropic;
+
+/// Each round as an assistant message of `tool_use` blocks followed by a
+/// user message carrying the `tool_result`s.
+fn tool_messages(rounds: &[ToolRound]) -> Vec<Value> {
+    let mut messages = Vec::new();
+    for round in rounds {
+        let mut content = Vec::new();
+        if !round.text.is_empty() {
+            content.push(json!({ "type": "text", "text": round.text }));
+        }
+        content.extend(round.calls.iter().map(|call| {
+            json!({
+                "type": "tool_use",
+                "id": call.id,
+                "name": call.name,
+                "input": arguments_object(call),
+            })
+        }));
+        messages.push(json!({ "role": "assistant", "content": content }));
+        let results: Vec<Value> = round
+            .results
+            .iter()
+            .map(|result| {
+                json!({
+                    "type": "tool_result",
+                    "tool_use_id": result.call_id,
+                    "content": result.content,
+                    "is_error": result.is_error,
+                })
+            })
+            .collect();
+        messages.push(json!({ "role": "user", "content": results }));
+    }
+    messages
+}
+
+#[async_trait]
+impl Provider for Anthropic {
+    fn id(&self) -> &'static str {
+        "anthropic"
+    }
+
+    fn name(&self) -> &'static str {
+        "Anthropic"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "claude-sonnet-4-20250514"
+    }
+
+    fn configured(&self) -> bool {
+        api_key(KEY_VAR, self.id()).is_ok()
+    }
+
+    async fn list_models(&self, client: &Client) -> Result<Vec<ModelInfo>> {
+        let key = api_key(KEY_VAR, self.id())?;
+        let response = audit::send(
+            self.id(),
+            None,
+            client
+                .get(network::endpoint(self.id(), BASE_URL, "/models"))
+                .header("x-api-key", key)
+                .header("anthropic-version", API_VERSION),
+        )
+        .await?;
+        let body: Value = check_status(self.id(), response).await?.json().await?;
+        Ok(body["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|m| {
+                let id = m["id"].as_str()?;
+                Some(ModelInfo {
+                    id: id.to_string(),
+                    name: m["display_name"].as_str().unwrap_or(id).to_string(),
+                })
+            })
+            .collect())
+    }
+
+    async fn stream(
+        &self,
+        client: &Client,
+        request: &ChatRequest,
+        on_delta: &mut DeltaSink<'_>,
+    ) -> Result<Completion> {
+        let key = api_key(KEY_VAR, self.id())?;
+        // Anthropic takes the system prompt as a top-level field.
+        let system: Vec<&str> = request
+            .messages
+            .iter()
+            .filter(|m| m.role == Role::System)
+            .map(|m| m.content.as_str())
+            .collect();
+        let mut messages: Vec<Value> = request
+            .messages
+            .iter()
+            .filter(|m| m.role != Role::System)
+            .map(|m| json!(m))
+            .collect();
+        messages.extend(tool_messages(&request.tool_rounds));
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "max_tokens": request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            "stream": true,
+        });
+        if !system.is_empty() {
+            body["system"] = json!(system.join("\n\n"));
+        }
+        if let Some(temperature) = request.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = request.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if !request.tools.is_empty() {
+            body["tools"] = request
+                .tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "name": tool.name,
+                        "description": tool.description,
+                        "input_schema": tool.parameters,
+                    })
+                })
+                .collect();
+        }
+        let response = audit::send(
+            self.id(),
+            Some(&request.model),
+            client
+                .post(network::endpoint(self.id(), BASE_URL, "/messages"))
+                .header("x-api-key", key)
+                .header("anthropic-version", API_VERSION)
+                .json(&body),
+        )
+        .await?;
+        let response = check_status(self.id(), response).await?;
+        // `tool_use` blocks open with the id and name; their input follows as
+        // JSON fragments on the same block index.
+        let mut calls: BTreeMap<u64, PartialToolCall> = BTreeMap::new();
+        let mut completion = read_sse(
+            response,
+            |event, usage| match event.event.as_deref() {
+                Some("content_block_start") => {
+                    let value: Value = serde_json::from_str(&event.data)?;
+                    let block = &value["content_block"];
+                    if block["type"] == "tool_use" {
+                        calls.insert(
+                            value["index"].as_u64().unwrap_or_default(),
+                            PartialToolCall {
+                                id: block["id"].as_str().unwrap_or_default().to_string(),
+                                name: block["name"].as_str().unwrap_or_default().to_string(),
+                                arguments: String::new(),
+                            },
+                        );
+                    }
+                    Ok(None)
+                }
+                Some("content_block_delta") => {
+                    let value: Value = serde_json::from_str(&event.data)?;
+                    if let Some(json) = value["delta"]["partial_json"].as_str() {
+                        if let Some(call) =
+                            calls.get_mut(&value["index"].as_u64().unwrap_or_default())
+                        {
+                            call.arguments.push_str(json);
+                        }
+                    }
+                    Ok(value["delta"]["text"].as_str().map(str::to_string))
+                }
+                Some("message_start") => {
+                    let value: Value = serde_json::from_str(&event.data)?;
+                    usage.prompt_tokens = token_count(&value["message"]["usage"]["input_tokens"]);
+                    Ok(None)
+                }
+                Some("message_delta") => {
+                    let value: Value = serde_json::from_str(&event.data)?;
+                    usage.completion_tokens = token_count(&value["usage"]["output_tokens"]);
+                    Ok(None)
+                }
+                _ => Ok(None),
+            },
+            on_delta,
+        )
+        .await?;
+        completion.tool_calls = calls.into_values().map(PartialToolCall::finish).collect();
+        Ok(completion)
+    }
+}
@@ -0,0 +1,295 @@
+//! OpenAI-compatible endpoints the user adds as providers of their own:
+//! gateways such as LiteLLM or OpenRouter, and servers such as vLLM or LM
+//! Studio. Each has a base URL, any extra headers it wants, and a model
+//! list that is asked of the endpoint (`GET /models`) with a list of its
+//! own to fall back on.
+//!
+//! They are kept in `custom_providers.json` apart from their keys, which go
+//! to the keychain under `custom:` and the provider's id, clear of the
+//! entries the app keeps for itself. Unlike plugin
+//! providers they take effect at once, without a restart.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+
+use super::openai::OpenAiCompatible;
+use super::{ModelInfo, Provider, ProviderInfo, Providers};
+use crate::config;
+use crate::error::{Error, Result};
+use crate::{api_server, keys, network, sync};
+
+const CONFIG_FILE: &str = "custom_providers.json";
+/// Keychain entries of the app's own, which a provider's id could be
+/// mistaken for wherever keys are looked up by provider.
+const RESERVED: [&str; 4] = [
+    sync::SECRET_KEY,
+    sync::PASSPHRASE_KEY,
+    network::PROXY_PASSWORD_KEY,
+    api_server::TOKEN_ENTRY,
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomProvider {
+    pub id: String,
+    pub name: String,
+    /// Up to and including the version, as in `http://localhost:1234/v1`.
+    pub base_url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub default_model: String,
+    /// Offered besides or, when it can't list them, instead of what the
+    /// endpoint lists.
+    #[serde(default)]
+    pub models: Vec<String>,
+    /// Ask for token counts with `stream_options`, which not every server
+    /// accepts.
+    #[serde(default)]
+    pub stream_usage: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CustomProviderInput {
+    /// Made from the name when not given.
+    #[serde(default)]
+    pub id: Option<String>,
+    pub name: String,
+    pub base_url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Stored in the keychain; `None` leaves a stored key as it is, an
+    /// empty string removes it.
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// The first listed model when not given.
+    #[serde(default)]
+    pub default_model: Option<String>,
+    #[serde(default)]
+    pub models: Vec<String>,
+    #[serde(default)]
+    pub stream_usage: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionTest {
+    pub ok: bool,
+    pub latency_ms: u64,
+    pub models: Vec<ModelInfo>,
+    pub error: Option<String>,
+}
+
+fn load(app: &AppHandle) -> Vec<CustomProvider> {
+    config::read::<Vec<CustomProvider>>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+/// The saved endpoints, ready to register.
+pub(super) fn providers(app: &AppHandle, taken: &[&str]) -> Vec<Arc<dyn Provider>> {
+    load(app)
+        .iter()
+        .filter(|custom| {
+            let free = !taken.contains(&custom.id.as_str());
+            if !free {
+                tracing::warn!("custom provider {} clashes with another id", custom.id);
+            }
+            free
+        })
+        .map(|custom| Arc::new(OpenAiCompatible::custom(custom, None)) as Arc<dyn Provider>)
+        .collect()
+}
+
+/// Where the key of the provider with this id is kept.
+pub fn key_entry(id: &str) -> String {
+    format!("custom:{id}")
+}
+
+/// Lowercase letters, digits and dashes, from the name.
+fn slug(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.trim().chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.ends_with('-') && !slug.is_empty() {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+impl CustomProviderInput {
+    /// Checks the input and turns it into what is saved, leaving the
+    /// default model empty when there is none yet.
+    fn validate(&self) -> Result<CustomProvider> {
+        let invalid = |reason: String| Error::InvalidSetting(format!("custom provider: {reason}"));
+        let name = self.name.trim().to_string();
+        if name.is_empty() {
+            return Err(invalid("the name is empty".into()));
+        }
+        let id = slug(self.id.as_deref().unwrap_or(&name));
+        if id.is_empty() {
+            return Err(invalid("the id needs a letter or digit".into()));
+        }
+        if RESERVED.contains(&id.as_str()) {
+            return Err(invalid(format!("the id {id} is reserved")));
+        }
+        let base_url = self.base_url.trim().trim_end_matches('/').to_string();
+        match Url::parse(&base_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            _ => return Err(invalid(format!("{base_url} isn't an http(s) URL"))),
+        }
+        for (header, value) in &self.headers {
+            HeaderName::from_bytes(header.as_bytes())
+                .map_err(|_| invalid(format!("{header} isn't a valid header name")))?;
+            HeaderValue::from_str(value)
+                .map_err(|_| invalid(format!("the value of {header} isn't valid")))?;
+        }
+        let models: Vec<String> = self
+            .models
+            .iter()
+            .map(|m| m.trim().to_string())
+            .filter(|m| !m.is_empty())
+            .collect();
+        let default_model = self
+            .default_model
+            .as_deref()
+            .map(str::trim)
+            .filter(|m| !m.is_empty())
+            .or(models.first().map(String::as_str))
+            .unwrap_or_default()
+            .to_string();
+        Ok(CustomProvider {
+            id,
+            name,
+            base_url,
+            headers: self.headers.clone(),
+            default_model,
+            models,
+            stream_usage: self.stream_usage,
+        })
+    }
+}
+
+async fn test(provider: &dyn Provider, client: &Client) -> ConnectionTest {
+    let started = Instant::now();
+    let listed = provider.list_models(client).await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+    match listed {
+        Ok(models) => ConnectionTest {
+            ok: true,
+            latency_ms,
+            models,
+            error: None,
+        },
+        Err(err) => ConnectionTest {
+            ok: false,
+            latency_ms,
+            models: Vec::new(),
+            error: Some(err.to_string()),
+        },
+    }
+}
+
+#[tauri::command]
+pub fn list_custom_providers(app: AppHandle) -> Vec<CustomProvider> {
+    load(&app)
+}
+
+/// Adds an endpoint, or replaces the one with the same id, and registers
+/// it right away. Without a default model, the first one the endpoint
+/// lists is used.
+#[tauri::command]
+pub async fn add_custom_provider(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    provider: CustomProviderInput,
+) -> Result<ProviderInfo> {
+    let mut custom = provider.validate()?;
+    let mut saved = load(&app);
+    let replacing = saved.iter().any(|c| c.id == custom.id);
+    if !replacing && providers.get(&custom.id).is_ok() {
+        return Err(Error::InvalidSetting(format!(
+            "custom provider: the id {} is taken",
+            custom.id
+        )));
+    }
+    let key = match provider.api_key.as_deref().map(str::trim) {
+        Some("") => None,
+        Some(key) => Some(key.to_string()),
+        None => keys::load(&key_entry(&custom.id))?,
+    };
+    if custom.default_model.is_empty() {
+        let models = OpenAiCompatible::custom(&custom, key.clone())
+            .list_models(&client)
+            .await?;
+        custom.default_model = models.first().map(|m| m.id.clone()).ok_or_else(|| {
+            Error::InvalidSetting(format!(
+                "custom provider: {} lists no models; name one",
+                custom.base_url
+            ))
+        })?;
+    }
+    match provider.api_key.as_deref().map(str::trim) {
+        Some("") => keys::delete(&key_entry(&custom.id))?,
+        Some(key) => keys::store(&key_entry(&custom.id), key)?,
+        None => {}
+    }
+    saved.retain(|c| c.id != custom.id);
+    saved.push(custom.clone());
+    config::write(&app, CONFIG_FILE, &saved)?;
+    let registered = providers.register(&app, Arc::new(OpenAiCompatible::custom(&custom, None)));
+    Ok(registered.info())
+}
+
+/// Removes an endpoint and its key.
+#[tauri::command]
+pub fn remove_custom_provider(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    id: String,
+) -> Result<()> {
+    let mut saved = load(&app);
+    let before = saved.len();
+    saved.retain(|c| c.id != id);
+    if saved.len() == before {
+        return Err(Error::NotFound(format!("custom provider {id}")));
+    }
+    config::write(&app, CONFIG_FILE, &saved)?;
+    keys::delete(&key_entry(&id))?;
+    providers.unregister(&id);
+    Ok(())
+}
+
+/// Lists the models of a registered provider to see that it answers, or
+/// of `custom` before it is saved.
+#[tauri::command]
+pub async fn test_provider_connection(
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    provider: Option<String>,
+    custom: Option<CustomProviderInput>,
+) -> Result<ConnectionTest> {
+    match (provider, custom) {
+        (_, Some(input)) => {
+            let custom = input.validate()?;
+            let key = input
+                .api_key
+                .as_deref()
+                .map(str::trim)
+                .filter(|k| !k.is_empty())
+                .map(str::to_string);
+            Ok(test(&OpenAiCompatible::custom(&custom, key), &client).await)
+        }
+        (Some(id), None) => Ok(test(providers.get(&id)?.as_ref(), &client).await),
+        (None, None) => Err(Error::InvalidSetting(
+            "name a provider or give an endpoint to test".into(),
+        )),
+    }
+}
@@ -0,0 +1,212 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+use super::{
+    api_key, arguments_object, check_status, read_sse, token_count, Completion, DeltaSink,
+    ModelInfo, Provider,
+};
+use crate::audit;
+use crate::error::Result;
+use crate::llm::{ChatRequest, Role, ToolCall, ToolSpec};
+use crate::network;
+
+const BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+const KEY_VAR: &str = "GEMINI_API_KEY";
+
+pub struct Google;
+
+/// Gemini accepts only a subset of JSON Schema and rejects requests using
+/// anything else, so the keywords it doesn't know are dropped.
+fn gemini_schema(schema: &Value) -> Value {
+    match schema {
+        Value::Object(map) => map
+            .iter()
+            .filter(|(key, _)| !matches!(key.as_str(), "$schema" | "additionalProperties" | "$id"))
+            .map(|(key, value)| (key.clone(), gemini_schema(value)))
+            .collect(),
+        Value::Array(items) => items.iter().map(gemini_schema).collect(),
+        other => other.clone(),
+    }
+}
+
+fn function_declaration(tool: &ToolSpec) -> Value {
+    let mut declaration = json!({ "name": tool.name, "description": tool.description });
+    // An object schema without properties is rejected; leave it out instead.
+    if tool.parameters["properties"]
+        .as_object()
+        .is_some_and(|p| !p.is_empty())
+    {
+        declaration["parameters"] = gemini_schema(&tool.parameters);
+    }
+    declaration
+}
+
+#[async_trait]
+impl Provider for Google {
+    fn id(&self) -> &'static str {
+        "google"
+    }
+
+    fn name(&self) -> &'static str {
+        "Google Gemini"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "gemini-2.5-pro"
+    }
+
+    fn configured(&self) -> bool {
+        api_key(KEY_VAR, self.id()).is_ok()
+    }
+
+    async fn list_models(&self, client: &Client) -> Result<Vec<ModelInfo>> {
+        let key = api_key(KEY_VAR, self.id())?;
+        let response = audit::send(
+            self.id(),
+            None,
+            client
+                .get(network::endpoint(self.id(), BASE_URL, "/models"))
+                .query(&[("key", key)]),
+        )
+        .await?;
+        let body: Value = check_status(self.id(), response).await?.json().await?;
+        Ok(body["models"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|m| {
+                m["supportedGenerationMethods"]
+                    .as_array()
+                    .is_some_and(|methods| methods.iter().any(|x| x == "generateContent"))
+            })
+            .filter_map(|m| {
+                let id = m["name"].as_str()?.trim_start_matches("models/");
+                Some(ModelInfo {
+                    id: id.to_string(),
+                    name: m["displayName"].as_str().unwrap_or(id).to_string(),
+                })
+            })
+            .collect())
+    }
+
+    async fn stream(
+        &self,
+        client: &Client,
+        request: &ChatRequest,
+        on_delta: &mut DeltaSink<'_>,
+    ) -> Result<Completion> {
+        let key = api_key(KEY_VAR, self.id())?;
+        let mut system = Vec::new();
+        let mut contents = Vec::new();
+        for message in &request.messages {
+            match message.role {
+                Role::System => system.push(json!({ "text": message.content })),
+                Role::User => contents.push(json!({
+                    "role": "user",
+                    "parts": [{ "text": message.content }],
+                })),
+                Role::Assistant => contents.push(json!({
+                    "role": "model",
+                    "parts": [{ "text": message.content }],
+                })),
+            }
+        }
+        for round in &request.tool_rounds {
+            let mut parts = Vec::new();
+            if !round.text.is_empty() {
+                parts.push(json!({ "text": round.text }));
+            }
+            parts.extend(round.calls.iter().map(|call| {
+                json!({ "functionCall": { "name": call.name, "args": arguments_object(call) } })
+            }));
+            contents.push(json!({ "role": "model", "parts": parts }));
+            let responses: Vec<Value> = round
+                .results
+                .iter()
+                .map(|result| {
+                    let key = if result.is_error { "error" } else { "content" };
+                    json!({
+                        "functionResponse": {
+                            "name": result.name,
+                            "response": { key: result.content },
+                        },
+                    })
+                })
+                .collect();
+            contents.push(json!({ "role": "user", "parts": responses }));
+        }
+        let mut body = json!({ "contents": contents, "generationConfig": {} });
+        if !request.tools.is_empty() {
+            let declarations: Vec<Value> = request.tools.iter().map(function_declaration).collect();
+            body["tools"] = json!([{ "functionDeclarations": declarations }]);
+        }
+        if !system.is_empty() {
+            body["systemInstruction"] = json!({ "parts": system });
+        }
+        if let Some(temperature) = request.temperature {
+            body["generationConfig"]["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = request.top_p {
+            body["generationConfig"]["topP"] = json!(top_p);
+        }
+        if let Some(max_tokens) = request.max_tokens {
+            body["generationConfig"]["maxOutputTokens"] = json!(max_tokens);
+        }
+        // Gemini's schema dialect rejects much of JSON Schema, so only JSON
+        // itself is asked for here; the schema travels in the prompt.
+        if request.response_schema.is_some() {
+            body["generationConfig"]["responseMimeType"] = json!("application/json");
+        }
+        let response = audit::send(
+            self.id(),
+            Some(&request.model),
+            client
+                .post(network::endpoint(
+                    self.id(),
+                    BASE_URL,
+                    &format!("/models/{}:streamGenerateContent", request.model),
+                ))
+                .query(&[("alt", "sse"), ("key", key.as_str())])
+                .json(&body),
+        )
+        .await?;
+        let response = check_status(self.id(), response).await?;
+        // Gemini sends each call whole and without an id.
+        let mut calls = Vec::new();
+        let mut completion = read_sse(
+            response,
+            |event, usage| {
+                if event.data.is_empty() {
+                    return Ok(None);
+                }
+                let value: Value = serde_json::from_str(&event.data)?;
+                // Counts are cumulative, so the last chunk's figures win.
+                if let Some(metadata) = value["usageMetadata"].as_object() {
+                    usage.prompt_tokens = token_count(&metadata["promptTokenCount"]);
+                    usage.completion_tokens = token_count(&metadata["candidatesTokenCount"]);
+                }
+                let parts = value["candidates"][0]["content"]["parts"].as_array();
+                for call in parts.into_iter().flatten().map(|p| &p["functionCall"]) {
+                    if let Some(name) = call["name"].as_str() {
+                        calls.push(ToolCall {
+                            id: format!("call_{}", calls.len()),
+                            name: name.to_string(),
+                            arguments: call["args"].clone(),
+                        });
+                    }
+                }
+                let text: String = parts
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|part| part["text"].as_str())
+                    .collect();
+                Ok((!text.is_empty()).then_some(text))
+            },
+            on_delta,
+        )
+        .await?;
+        completion.tool_calls = calls;
+        Ok(completion)
+    }
+}
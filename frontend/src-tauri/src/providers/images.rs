@@ -0,0 +1,417 @@
+//! Image generation backends: OpenAI's image API (DALL·E and gpt-image),
+//! Stability's hosted models and a local Stable Diffusion server speaking
+//! the AUTOMATIC1111 web UI API. They are kept apart from [`Provider`]
+//! since two of them have no chat models at all.
+//!
+//! [`Provider`]: super::Provider
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::Engine;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{api_key, check_status, ProviderInfo};
+use crate::audit;
+use crate::error::{Error, Result};
+use crate::network;
+
+/// How often the local server is asked how far along it is.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(750);
+const BOUNDARY: &str = "pentamind-form-boundary-9b2e4c";
+const STABILITY_ASPECT_RATIOS: &[(u32, u32)] = &[
+    (1, 1),
+    (16, 9),
+    (9, 16),
+    (21, 9),
+    (9, 21),
+    (2, 3),
+    (3, 2),
+    (4, 5),
+    (5, 4),
+];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImageRequest {
+    pub provider: String,
+    /// The backend's default when unset.
+    pub model: Option<String>,
+    pub prompt: String,
+    /// Things to keep out of the image. OpenAI has no such parameter and
+    /// ignores it.
+    pub negative_prompt: Option<String>,
+    /// `WIDTHxHEIGHT`; Stability turns it into the closest aspect ratio.
+    pub size: Option<String>,
+    /// Images to make, 1 when unset.
+    pub count: Option<u32>,
+}
+
+impl ImageRequest {
+    fn count(&self) -> u32 {
+        self.count.unwrap_or(1).max(1)
+    }
+
+    fn dimensions(&self) -> Result<Option<(u32, u32)>> {
+        let Some(size) = &self.size else {
+            return Ok(None);
+        };
+        size.split_once('x')
+            .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)))
+            .filter(|&(w, h): &(u32, u32)| w > 0 && h > 0)
+            .map(Some)
+            .ok_or_else(|| {
+                Error::InvalidSetting(format!("image size `{size}`, expected WIDTHxHEIGHT"))
+            })
+    }
+}
+
+/// One generated image.
+#[derive(Debug, Clone)]
+pub struct ImageData {
+    pub bytes: Vec<u8>,
+    pub extension: &'static str,
+    /// The prompt as the backend rewrote it, when it does that.
+    pub revised_prompt: Option<String>,
+}
+
+/// Receives how far along a generation is, from 0 to 1.
+pub type ProgressSink<'a> = dyn FnMut(f32) + Send + 'a;
+
+#[async_trait]
+pub trait ImageGenerator: Send + Sync {
+    fn id(&self) -> &'static str;
+    fn name(&self) -> &'static str;
+    fn default_model(&self) -> &'static str;
+    fn configured(&self) -> bool;
+
+    async fn generate(
+        &self,
+        client: &Client,
+        request: &ImageRequest,
+        on_progress: &mut ProgressSink<'_>,
+    ) -> Result<Vec<ImageData>>;
+
+    fn info(&self) -> ProviderInfo {
+        ProviderInfo {
+            id: self.id(),
+            name: self.name(),
+            default_model: self.default_model(),
+            configured: self.configured(),
+        }
+    }
+}
+
+fn decode(b64: &str) -> Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(b64)
+        .map_err(|e| Error::Provider(format!("undecodable image: {e}")))
+}
+
+pub struct OpenAiImages;
+
+#[derive(Deserialize)]
+struct OpenAiImageResponse {
+    data: Vec<OpenAiImage>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiImage {
+    b64_json: String,
+    revised_prompt: Option<String>,
+}
+
+#[async_trait]
+impl ImageGenerator for OpenAiImages {
+    fn id(&self) -> &'static str {
+        "openai"
+    }
+
+    fn name(&self) -> &'static str {
+        "OpenAI"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "dall-e-3"
+    }
+
+    fn configured(&self) -> bool {
+        api_key("OPENAI_API_KEY", self.id()).is_ok()
+    }
+
+    async fn generate(
+        &self,
+        client: &Client,
+        request: &ImageRequest,
+        on_progress: &mut ProgressSink<'_>,
+    ) -> Result<Vec<ImageData>> {
+        let key = api_key("OPENAI_API_KEY", self.id())?;
+        let model = request.model.as_deref().unwrap_or(self.default_model());
+        let mut body = json!({
+            "model": model,
+            "prompt": request.prompt,
+            "n": request.count(),
+        });
+        if let Some(size) = &request.size {
+            body["size"] = json!(size);
+        }
+        // gpt-image models always answer in base64 and reject the option.
+        if model.starts_with("dall-e") {
+            body["response_format"] = json!("b64_json");
+        }
+        on_progress(0.0);
+        let response = audit::send(
+            self.id(),
+            Some(model),
+            client
+                .post(network::endpoint(
+                    self.id(),
+                    "https://api.openai.com/v1",
+                    "/images/generations",
+                ))
+                .bearer_auth(key)
+                .json(&body),
+        )
+        .await?;
+        let body: OpenAiImageResponse = check_status(self.id(), response).await?.json().await?;
+        body.data
+            .into_iter()
+            .map(|image| {
+                Ok(ImageData {
+                    bytes: decode(&image.b64_json)?,
+                    extension: "png",
+                    revised_prompt: image.revised_prompt,
+                })
+            })
+            .collect()
+    }
+}
+
+pub struct Stability;
+
+/// A `multipart/form-data` body of text fields, which Stability requires
+/// even without a file.
+fn form(fields: &[(&str, &str)]) -> String {
+    let mut body = String::new();
+    for (name, value) in fields {
+        body.push_str(&format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+        ));
+    }
+    body.push_str(&format!("--{BOUNDARY}--\r\n"));
+    body
+}
+
+/// The supported aspect ratio closest to `width` by `height`.
+fn aspect_ratio(width: u32, height: u32) -> String {
+    let target = width as f32 / height as f32;
+    let (w, h) = STABILITY_ASPECT_RATIOS
+        .iter()
+        .min_by(|a, b| {
+            let distance = |(w, h): &&(u32, u32)| (*w as f32 / *h as f32 - target).abs();
+            distance(a).total_cmp(&distance(b))
+        })
+        .unwrap_or(&(1, 1));
+    format!("{w}:{h}")
+}
+
+#[async_trait]
+impl ImageGenerator for Stability {
+    fn id(&self) -> &'static str {
+        "stability"
+    }
+
+    fn name(&self) -> &'static str {
+        "Stability AI"
+    }
+
+    /// `core`, `ultra` or an `sd3…` model.
+    fn default_model(&self) -> &'static str {
+        "core"
+    }
+
+    fn configured(&self) -> bool {
+        api_key("STABILITY_API_KEY", self.id()).is_ok()
+    }
+
+    async fn generate(
+        &self,
+        client: &Client,
+        request: &ImageRequest,
+        on_progress: &mut ProgressSink<'_>,
+    ) -> Result<Vec<ImageData>> {
+        let key = api_key("STABILITY_API_KEY", self.id())?;
+        let model = request.model.as_deref().unwrap_or(self.default_model());
+        // The SD3 models share one endpoint and are picked with a field.
+        let (path, sd3_model) = match model {
+            "core" | "ultra" => (format!("/stable-image/generate/{model}"), None),
+            model => ("/stable-image/generate/sd3".to_string(), Some(model)),
+        };
+        let ratio = request.dimensions()?.map(|(w, h)| aspect_ratio(w, h));
+        let mut fields = vec![
+            ("prompt", request.prompt.as_str()),
+            ("output_format", "png"),
+        ];
+        fields.extend(
+            request
+                .negative_prompt
+                .as_deref()
+                .map(|n| ("negative_prompt", n)),
+        );
+        fields.extend(ratio.as_deref().map(|r| ("aspect_ratio", r)));
+        fields.extend(sd3_model.map(|m| ("model", m)));
+        let body = form(&fields);
+
+        // One image per call.
+        let count = request.count();
+        let mut images = Vec::new();
+        for index in 0..count {
+            on_progress(index as f32 / count as f32);
+            let response = audit::send(
+                self.id(),
+                Some(model),
+                client
+                    .post(network::endpoint(
+                        self.id(),
+                        "https://api.stability.ai/v2beta",
+                        &path,
+                    ))
+                    .bearer_auth(&key)
+                    .header(reqwest::header::ACCEPT, "image/*")
+                    .header(
+                        reqwest::header::CONTENT_TYPE,
+                        format!("multipart/form-data; boundary={BOUNDARY}"),
+                    )
+                    .body(body.clone()),
+            )
+            .await?;
+            let bytes = check_status(self.id(), response).await?.bytes().await?;
+            images.push(ImageData {
+                bytes: bytes.to_vec(),
+                extension: "png",
+                revised_prompt: None,
+            });
+        }
+        Ok(images)
+    }
+}
+
+/// A Stable Diffusion web UI started with `--api`, at `SD_WEBUI_URL` or
+/// the default address unless the network settings say otherwise.
+pub struct LocalStableDiffusion {
+    base_url: String,
+}
+
+#[derive(Deserialize)]
+struct Txt2ImgResponse {
+    images: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ProgressResponse {
+    progress: f32,
+}
+
+impl LocalStableDiffusion {
+    pub fn from_env() -> Self {
+        let base_url =
+            std::env::var("SD_WEBUI_URL").unwrap_or_else(|_| "http://127.0.0.1:7860".to_string());
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        network::endpoint(self.id(), &self.base_url, path)
+    }
+
+    async fn progress(&self, client: &Client) -> Result<f32> {
+        let response = client
+            .get(self.url("/sdapi/v1/progress?skip_current_image=true"))
+            .send()
+            .await?;
+        let body: ProgressResponse = check_status(self.id(), response).await?.json().await?;
+        Ok(body.progress)
+    }
+}
+
+#[async_trait]
+impl ImageGenerator for LocalStableDiffusion {
+    fn id(&self) -> &'static str {
+        "local-sd"
+    }
+
+    fn name(&self) -> &'static str {
+        "Stable Diffusion (local)"
+    }
+
+    /// Whatever checkpoint the server has loaded.
+    fn default_model(&self) -> &'static str {
+        ""
+    }
+
+    /// Local servers need no key; whether one is running shows on the
+    /// first call.
+    fn configured(&self) -> bool {
+        true
+    }
+
+    async fn generate(
+        &self,
+        client: &Client,
+        request: &ImageRequest,
+        on_progress: &mut ProgressSink<'_>,
+    ) -> Result<Vec<ImageData>> {
+        let (width, height) = request.dimensions()?.unwrap_or((512, 512));
+        let mut body = json!({
+            "prompt": request.prompt,
+            "negative_prompt": request.negative_prompt.as_deref().unwrap_or(""),
+            "width": width,
+            "height": height,
+            "batch_size": request.count(),
+        });
+        if let Some(model) = request.model.as_deref().filter(|m| !m.is_empty()) {
+            body["override_settings"] = json!({ "sd_model_checkpoint": model });
+        }
+        let generate = audit::send(
+            self.id(),
+            request.model.as_deref(),
+            client.post(self.url("/sdapi/v1/txt2img")).json(&body),
+        );
+        tokio::pin!(generate);
+        let response = loop {
+            tokio::select! {
+                response = &mut generate => break response?,
+                _ = tokio::time::sleep(PROGRESS_INTERVAL) => {
+                    if let Ok(progress) = self.progress(client).await {
+                        on_progress(progress.clamp(0.0, 1.0));
+                    }
+                }
+            }
+        };
+        let body: Txt2ImgResponse = check_status(self.id(), response).await?.json().await?;
+        body.images
+            .iter()
+            .map(|image| {
+                // Some versions prefix a data URL header.
+                let b64 = image.split_once(',').map_or(image.as_str(), |(_, b)| b);
+                Ok(ImageData {
+                    bytes: decode(b64)?,
+                    extension: "png",
+                    revised_prompt: None,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Everything that can make images, in the order they are listed.
+pub fn all() -> Vec<Arc<dyn ImageGenerator>> {
+    vec![
+        Arc::new(OpenAiImages),
+        Arc::new(Stability),
+        Arc::new(LocalStableDiffusion::from_env()),
+    ]
+}
@@ -0,0 +1,508 @@
+//! One implementation per vendor behind a common trait, so the five minds can
+//! be fanned out from Rust without the frontend knowing any vendor's HTTP API.
+
+mod anthropic;
+pub mod custom;
+mod google;
+pub mod images;
+pub mod ollama;
+mod openai;
+pub mod priority;
+pub mod resilience;
+pub mod sse;
+pub mod stats;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use reqwest::{Client, Response};
+use serde::Serialize;
+use tauri::{AppHandle, State};
+
+use images::ImageGenerator;
+use resilience::{ConcurrencyLimits, RateLimit, RateLimits, Resilient};
+use stats::{ProviderStats, Stats};
+
+use crate::cache;
+use crate::error::{Error, Result};
+use crate::keys;
+use crate::llm::{ChatRequest, ChatResponse, ToolCall, Usage};
+use crate::pipeline;
+use crate::startup;
+use crate::storage::now_ms;
+use crate::usage;
+use sse::{SseDecoder, SseEvent};
+
+const DEFAULT_STATS_WINDOW_SECS: u64 = 15 * 60;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderInfo {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub default_model: &'static str,
+    pub configured: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelInfo {
+    pub id: String,
+    pub name: String,
+}
+
+/// The result of a finished stream.
+#[derive(Debug, Clone, Default)]
+pub struct Completion {
+    pub content: String,
+    /// `None` when the provider didn't report token counts.
+    pub usage: Option<Usage>,
+    /// Tools the model asked to call; only when the request offered some.
+    pub tool_calls: Vec<ToolCall>,
+}
+
+/// A tool call assembled from streamed fragments of its arguments.
+#[derive(Debug, Default)]
+struct PartialToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+impl PartialToolCall {
+    fn finish(self) -> ToolCall {
+        ToolCall {
+            id: self.id,
+            name: self.name,
+            arguments: parse_arguments(self.arguments),
+        }
+    }
+}
+
+/// Tool arguments sent as a JSON string. An empty string means no
+/// arguments; anything unparseable is passed on as the raw string so the
+/// tool can report it.
+fn parse_arguments(raw: String) -> serde_json::Value {
+    if raw.trim().is_empty() {
+        return serde_json::json!({});
+    }
+    serde_json::from_str(&raw).unwrap_or(serde_json::Value::String(raw))
+}
+
+/// Tool arguments as an object, for APIs that require one.
+fn arguments_object(call: &ToolCall) -> serde_json::Value {
+    if call.arguments.is_object() {
+        call.arguments.clone()
+    } else {
+        serde_json::json!({})
+    }
+}
+
+/// Audio to turn into text.
+#[derive(Debug, Clone, Copy)]
+pub struct TranscriptionRequest<'a> {
+    pub model: &'a str,
+    /// A WAV file.
+    pub audio: &'a [u8],
+    /// ISO-639-1 code; detected when `None`.
+    pub language: Option<&'a str>,
+    /// Preceding text, which helps keep spelling and context consistent
+    /// across chunks.
+    pub prompt: Option<&'a str>,
+}
+
+/// Receives each streamed text fragment.
+pub type DeltaSink<'a> = dyn FnMut(&str) + Send + 'a;
+
+#[async_trait]
+pub trait Provider: Send + Sync {
+    fn id(&self) -> &'static str;
+    fn name(&self) -> &'static str;
+    fn default_model(&self) -> &'static str;
+
+    /// Whether credentials (or a reachable local server) are available.
+    fn configured(&self) -> bool;
+
+    async fn list_models(&self, client: &Client) -> Result<Vec<ModelInfo>>;
+
+    /// Streams a completion, calling `on_delta` for every text fragment, and
+    /// returns the full content once the stream ends.
+    async fn stream(
+        &self,
+        client: &Client,
+        request: &ChatRequest,
+        on_delta: &mut DeltaSink<'_>,
+    ) -> Result<Completion>;
+
+    /// Model used for embeddings when the caller does not pick one. `None`
+    /// means the provider has no embeddings endpoint.
+    fn embedding_model(&self) -> Option<&'static str> {
+        None
+    }
+
+    async fn embed(
+        &self,
+        _client: &Client,
+        _model: &str,
+        _inputs: &[String],
+    ) -> Result<Vec<Vec<f32>>> {
+        Err(Error::Unsupported(format!("{} embeddings", self.name())))
+    }
+
+    /// Model used for speech-to-text when the caller does not pick one.
+    /// `None` means the provider has no transcription endpoint.
+    fn transcription_model(&self) -> Option<&'static str> {
+        None
+    }
+
+    async fn transcribe(
+        &self,
+        _client: &Client,
+        _request: &TranscriptionRequest<'_>,
+    ) -> Result<String> {
+        Err(Error::Unsupported(format!("{} transcription", self.name())))
+    }
+
+    fn info(&self) -> ProviderInfo {
+        ProviderInfo {
+            id: self.id(),
+            name: self.name(),
+            default_model: self.default_model(),
+            configured: self.configured(),
+        }
+    }
+}
+
+/// Registry of available providers, managed as Tauri state. Each one is
+/// reached through [`Resilient`], which queues and retries its calls.
+/// Custom endpoints come and go while the app runs, hence the lock. The
+/// registry is filled on first use, so reading plugins and custom
+/// endpoints doesn't hold up startup.
+pub struct Providers {
+    app: AppHandle,
+    registry: OnceLock<Registry>,
+}
+
+struct Registry {
+    providers: RwLock<BTreeMap<&'static str, Arc<dyn Provider>>>,
+    rate_limits: Arc<RateLimits>,
+    stats: Arc<Stats>,
+    images: BTreeMap<&'static str, Arc<dyn ImageGenerator>>,
+    /// Kept concretely as well for the Ollama-specific commands.
+    ollama: Arc<ollama::Ollama>,
+    #[cfg(feature = "local-llm")]
+    local: Arc<crate::local_llm::LocalLlm>,
+}
+
+impl Registry {
+    fn new(app: &AppHandle) -> Self {
+        let rate_limits = Arc::new(RateLimits::load(app));
+        let stats = Arc::new(Stats::default());
+        let ollama = Arc::new(ollama::Ollama::from_env());
+        let mut all: Vec<Arc<dyn Provider>> = vec![
+            Arc::new(openai::OpenAiCompatible::openai()),
+            Arc::new(anthropic::Anthropic),
+            Arc::new(google::Google),
+            Arc::new(openai::OpenAiCompatible::mistral()),
+            ollama.clone(),
+        ];
+        #[cfg(feature = "local-llm")]
+        let local = Arc::new(crate::local_llm::LocalLlm::default());
+        #[cfg(feature = "local-llm")]
+        all.push(local.clone());
+        let taken: Vec<&str> = all.iter().map(|p| p.id()).collect();
+        all.extend(crate::plugins::providers(app, &taken));
+        let taken: Vec<&str> = all.iter().map(|p| p.id()).collect();
+        all.extend(custom::providers(app, &taken));
+        Self {
+            providers: RwLock::new(
+                all.into_iter()
+                    .map(|p| {
+                        let wrapped: Arc<dyn Provider> =
+                            Arc::new(Resilient::new(app, rate_limits.clone(), stats.clone(), p));
+                        (wrapped.id(), wrapped)
+                    })
+                    .collect(),
+            ),
+            rate_limits,
+            stats,
+            images: images::all().into_iter().map(|g| (g.id(), g)).collect(),
+            ollama,
+            #[cfg(feature = "local-llm")]
+            local,
+        }
+    }
+}
+
+impl Providers {
+    pub fn new(app: &AppHandle) -> Self {
+        Self {
+            app: app.clone(),
+            registry: OnceLock::new(),
+        }
+    }
+
+    fn registry(&self) -> &Registry {
+        self.registry
+            .get_or_init(|| startup::deferred("providers", || Registry::new(&self.app)))
+    }
+
+    /// Fills the registry now if nothing has needed it yet.
+    pub fn ensure_loaded(&self) {
+        self.registry();
+    }
+
+    pub fn get(&self, id: &str) -> Result<Arc<dyn Provider>> {
+        self.registry()
+            .providers
+            .read()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| Error::UnknownProvider(id.to_string()))
+    }
+
+    pub fn image_generator(&self, id: &str) -> Result<Arc<dyn ImageGenerator>> {
+        self.registry()
+            .images
+            .get(id)
+            .cloned()
+            .ok_or_else(|| Error::UnknownProvider(id.to_string()))
+    }
+
+    pub fn ollama(&self) -> &ollama::Ollama {
+        &self.registry().ollama
+    }
+
+    #[cfg(feature = "local-llm")]
+    pub fn local(&self) -> &crate::local_llm::LocalLlm {
+        &self.registry().local
+    }
+
+    pub fn all(&self) -> impl Iterator<Item = Arc<dyn Provider>> {
+        let providers: Vec<_> = self
+            .registry()
+            .providers
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        providers.into_iter()
+    }
+
+    /// Adds a provider, or replaces the one with its id, behind the same
+    /// queueing and retries as the rest.
+    pub fn register(&self, app: &AppHandle, provider: Arc<dyn Provider>) -> Arc<dyn Provider> {
+        let registry = self.registry();
+        let wrapped: Arc<dyn Provider> = Arc::new(Resilient::new(
+            app,
+            registry.rate_limits.clone(),
+            registry.stats.clone(),
+            provider,
+        ));
+        registry
+            .providers
+            .write()
+            .unwrap()
+            .insert(wrapped.id(), wrapped.clone());
+        wrapped
+    }
+
+    pub fn unregister(&self, id: &str) {
+        self.registry().providers.write().unwrap().remove(id);
+    }
+}
+
+/// Looks up a key in the OS keychain first, then falls back to the
+/// environment variable `var` for development setups.
+pub(crate) fn api_key(var: &str, provider: &str) -> Result<String> {
+    if let Some(key) = keys::load(provider)? {
+        return Ok(key);
+    }
+    std::env::var(var).map_err(|_| Error::MissingApiKey(provider.to_string()))
+}
+
+/// Reads a token count from a JSON usage field, treating absence as zero.
+fn token_count(value: &serde_json::Value) -> u32 {
+    value.as_u64().unwrap_or_default() as u32
+}
+
+/// The wait a response asks for, from `retry-after-ms` or `Retry-After`
+/// (seconds or an HTTP date).
+fn retry_after(response: &Response) -> Option<Duration> {
+    let header = |name: &str| response.headers().get(name)?.to_str().ok().map(str::trim);
+    let seconds = |value: &str| {
+        value
+            .parse::<f64>()
+            .ok()
+            .filter(|s| s.is_finite())
+            .map(|s| Duration::from_secs_f64(s.max(0.0)))
+    };
+    if let Some(delay) = header("retry-after-ms").and_then(seconds) {
+        return Some(delay / 1000);
+    }
+    let value = header("retry-after")?;
+    if let Some(delay) = seconds(value) {
+        return Some(delay);
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(Duration::from_millis(
+        (date.timestamp_millis() - now_ms()).max(0) as u64,
+    ))
+}
+
+/// Turns a non-2xx response into an [`Error::Api`] carrying the body.
+async fn check_status(provider: &str, response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let retry_after = retry_after(&response);
+    Err(Error::Api {
+        provider: provider.to_string(),
+        status: status.as_u16(),
+        body: response.text().await.unwrap_or_default(),
+        retry_after,
+    })
+}
+
+/// Drives an SSE response body. `parse` extracts the text delta from each
+/// event and records any usage figures it carries.
+async fn read_sse(
+    response: Response,
+    mut parse: impl FnMut(&SseEvent, &mut Usage) -> Result<Option<String>>,
+    on_delta: &mut DeltaSink<'_>,
+) -> Result<Completion> {
+    let mut decoder = SseDecoder::default();
+    let mut content = String::new();
+    let mut usage = Usage::default();
+    let mut chunks = response.bytes_stream();
+    while let Some(chunk) = chunks.next().await {
+        for event in decoder.push(&chunk?) {
+            if let Some(delta) = parse(&event, &mut usage)? {
+                on_delta(&delta);
+                content.push_str(&delta);
+            }
+        }
+    }
+    Ok(Completion {
+        content,
+        usage: (usage != Usage::default()).then_some(usage),
+        tool_calls: Vec::new(),
+    })
+}
+
+#[tauri::command]
+pub fn get_rate_limits(providers: State<'_, Providers>) -> BTreeMap<String, RateLimit> {
+    providers.registry().rate_limits.get()
+}
+
+/// Sets how many calls `provider` may have in flight and start per minute.
+/// Both unset restores the defaults.
+#[tauri::command]
+pub fn set_rate_limit(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    provider: String,
+    limit: RateLimit,
+) -> Result<()> {
+    providers.get(&provider)?;
+    providers.registry().rate_limits.set(&app, &provider, limit)
+}
+
+#[tauri::command]
+pub fn get_concurrency_limits(providers: State<'_, Providers>) -> ConcurrencyLimits {
+    providers.registry().rate_limits.concurrency()
+}
+
+/// Sets how many calls may be in flight across all providers, and how many
+/// slots to keep for interactive calls.
+#[tauri::command]
+pub fn set_concurrency_limits(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    limits: ConcurrencyLimits,
+) -> Result<()> {
+    providers
+        .registry()
+        .rate_limits
+        .set_concurrency(&app, limits)
+}
+
+#[tauri::command]
+pub fn list_providers(providers: State<'_, Providers>) -> Vec<ProviderInfo> {
+    providers.all().map(|p| p.info()).collect()
+}
+
+#[tauri::command]
+pub fn list_image_providers(providers: State<'_, Providers>) -> Vec<ProviderInfo> {
+    providers
+        .registry()
+        .images
+        .values()
+        .map(|g| g.info())
+        .collect()
+}
+
+#[tauri::command]
+pub async fn list_models(
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    provider: String,
+) -> Result<Vec<ModelInfo>> {
+    providers.get(&provider)?.list_models(&client).await
+}
+
+/// Non-streaming counterpart of `stream_chat` for callers that only want the
+/// final text.
+#[tauri::command]
+pub async fn send_prompt(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    request: ChatRequest,
+) -> Result<ChatResponse> {
+    let started = Instant::now();
+    let provider = providers.get(&request.provider)?;
+    let mut lookup = cache::lookup(&app, &client, &request).await;
+    let (completion, cached) = match lookup.as_mut().and_then(|l| l.hit.take()) {
+        Some(hit) => (hit, true),
+        None => {
+            let completion = provider.stream(&client, &request, &mut |_| {}).await?;
+            usage::record(&app, &request, &completion.content, completion.usage);
+            if let Some(lookup) = lookup {
+                cache::store(&app, &request, lookup, &completion);
+            }
+            (completion, false)
+        }
+    };
+    let mut content = completion.content;
+    let original_content =
+        pipeline::apply(&app, request.conversation_id.as_deref(), &mut content).await;
+    Ok(ChatResponse {
+        provider: request.provider,
+        model: request.model,
+        content,
+        original_content,
+        usage: completion.usage,
+        latency_ms: started.elapsed().as_millis() as u64,
+        cached,
+    })
+}
+
+/// Time to first token, latency, errors and timeouts per provider over the
+/// last `window_secs` (15 minutes by default, at most an hour), with a
+/// verdict on whether each is fast, slow or down.
+#[tauri::command]
+pub fn get_provider_stats(
+    providers: State<'_, Providers>,
+    window_secs: Option<u64>,
+) -> Vec<ProviderStats> {
+    let window = Duration::from_secs(window_secs.unwrap_or(DEFAULT_STATS_WINDOW_SECS));
+    providers
+        .all()
+        .map(|p| providers.registry().stats.summary(p.id(), window))
+        .collect()
+}
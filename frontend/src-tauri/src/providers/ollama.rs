@@ -0,0 +1,422 @@
+//! A locally running Ollama server: detection, model management and
+//! streaming, so at least one mind works without a network connection.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use reqwest::{Client, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use super::{
+    arguments_object, check_status, token_count, Completion, DeltaSink, ModelInfo, Provider,
+    Providers,
+};
+use crate::audit;
+use crate::error::{Error, Result};
+use crate::llm::{ChatRequest, ToolCall, ToolRound, Usage};
+use crate::network;
+use crate::requests::{cancellable, Requests};
+
+const DEFAULT_HOST: &str = "http://localhost:11434";
+/// Probing a local port should be near-instant; anything slower is "not running".
+const DETECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OllamaStatus {
+    pub running: bool,
+    pub base_url: String,
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OllamaModelDetails {
+    pub family: Option<String>,
+    pub parameter_size: Option<String>,
+    pub quantization_level: Option<String>,
+}
+
+/// An installed model as reported by `/api/tags`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaModel {
+    pub name: String,
+    /// Size on disk in bytes.
+    pub size: u64,
+    pub modified_at: String,
+    pub digest: String,
+    #[serde(default)]
+    pub details: OllamaModelDetails,
+}
+
+#[derive(Deserialize)]
+struct TagsResponse {
+    #[serde(default)]
+    models: Vec<OllamaModel>,
+}
+
+/// Payload of the `ollama-pull-progress` event. `total` and `completed` are
+/// byte counts for the layer named by `digest`, present while downloading.
+#[derive(Debug, Clone, Serialize)]
+pub struct OllamaPullProgress {
+    pub request_id: String,
+    pub model: String,
+    pub status: String,
+    pub digest: Option<String>,
+    pub total: Option<u64>,
+    pub completed: Option<u64>,
+}
+
+/// A locally running Ollama server. It needs no key, so it counts as
+/// configured whenever the last probe found it running.
+pub struct Ollama {
+    base_url: String,
+    available: AtomicBool,
+}
+
+impl Ollama {
+    pub fn from_env() -> Self {
+        let base_url = std::env::var("OLLAMA_HOST").unwrap_or_else(|_| DEFAULT_HOST.to_string());
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            available: AtomicBool::new(false),
+        }
+    }
+
+    /// `path` on the server, or on the base URL set in the network settings.
+    fn url(&self, path: &str) -> String {
+        network::endpoint(self.id(), &self.base_url, path)
+    }
+
+    /// Probes `/api/version` and remembers the outcome for `configured()`.
+    pub async fn detect(&self, client: &Client) -> OllamaStatus {
+        let version = async {
+            let response = audit::send(
+                self.id(),
+                None,
+                client.get(self.url("/api/version")).timeout(DETECT_TIMEOUT),
+            )
+            .await?;
+            let body: Value = check_status(self.id(), response).await?.json().await?;
+            Ok::<_, Error>(body["version"].as_str().map(str::to_string))
+        }
+        .await;
+        self.available.store(version.is_ok(), Ordering::Relaxed);
+        OllamaStatus {
+            running: version.is_ok(),
+            base_url: self.url(""),
+            version: version.ok().flatten(),
+        }
+    }
+
+    pub async fn installed_models(&self, client: &Client) -> Result<Vec<OllamaModel>> {
+        let response = audit::send(self.id(), None, client.get(self.url("/api/tags"))).await?;
+        let body: TagsResponse = check_status(self.id(), response).await?.json().await?;
+        Ok(body.models)
+    }
+
+    /// Downloads `model`, calling `on_progress` for every status line.
+    pub async fn pull(
+        &self,
+        client: &Client,
+        model: &str,
+        mut on_progress: impl FnMut(&Value) + Send,
+    ) -> Result<()> {
+        let response = audit::send(
+            self.id(),
+            Some(model),
+            client
+                .post(self.url("/api/pull"))
+                .json(&json!({ "model": model, "stream": true })),
+        )
+        .await?;
+        let response = check_status(self.id(), response).await?;
+        read_ndjson(response, |value| {
+            on_progress(&value);
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn delete(&self, client: &Client, model: &str) -> Result<()> {
+        let response = audit::send(
+            self.id(),
+            Some(model),
+            client
+                .delete(self.url("/api/delete"))
+                .json(&json!({ "model": model })),
+        )
+        .await?;
+        check_status(self.id(), response).await?;
+        Ok(())
+    }
+}
+
+/// Each round as an assistant message with its `tool_calls`, then a `tool`
+/// message per result. Ollama matches results to calls by name.
+fn tool_messages(rounds: &[ToolRound]) -> Vec<Value> {
+    let mut messages = Vec::new();
+    for round in rounds {
+        let calls: Vec<Value> = round
+            .calls
+            .iter()
+            .map(|call| {
+                json!({
+                    "function": { "name": call.name, "arguments": arguments_object(call) },
+                })
+            })
+            .collect();
+        messages.push(json!({
+            "role": "assistant",
+            "content": round.text,
+            "tool_calls": calls,
+        }));
+        for result in &round.results {
+            messages.push(json!({
+                "role": "tool",
+                "tool_name": result.name,
+                "content": result.content,
+            }));
+        }
+    }
+    messages
+}
+
+/// Reads a newline-delimited JSON body, which Ollama streams instead of SSE.
+/// A line carrying an `error` field ends the stream with that error.
+async fn read_ndjson(
+    response: Response,
+    mut on_value: impl FnMut(Value) -> Result<()> + Send,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    let mut chunks = response.bytes_stream();
+    while let Some(chunk) = chunks.next().await {
+        buf.extend_from_slice(&chunk?);
+        while let Some(pos) = buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=pos).collect();
+            let line = &line[..pos];
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let value: Value = serde_json::from_slice(line)?;
+            if let Some(error) = value["error"].as_str() {
+                return Err(Error::Provider(format!("ollama: {error}")));
+            }
+            on_value(value)?;
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Provider for Ollama {
+    fn id(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn name(&self) -> &'static str {
+        "Ollama"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "llama3.2"
+    }
+
+    fn configured(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+
+    fn embedding_model(&self) -> Option<&'static str> {
+        Some("nomic-embed-text")
+    }
+
+    async fn embed(
+        &self,
+        client: &Client,
+        model: &str,
+        inputs: &[String],
+    ) -> Result<Vec<Vec<f32>>> {
+        let response = audit::send(
+            self.id(),
+            Some(model),
+            client
+                .post(self.url("/api/embed"))
+                .json(&json!({ "model": model, "input": inputs })),
+        )
+        .await?;
+        let body: EmbedResponse = check_status(self.id(), response).await?.json().await?;
+        Ok(body.embeddings)
+    }
+
+    async fn list_models(&self, client: &Client) -> Result<Vec<ModelInfo>> {
+        Ok(self
+            .installed_models(client)
+            .await?
+            .into_iter()
+            .map(|m| ModelInfo {
+                id: m.name.clone(),
+                name: m.name,
+            })
+            .collect())
+    }
+
+    async fn stream(
+        &self,
+        client: &Client,
+        request: &ChatRequest,
+        on_delta: &mut DeltaSink<'_>,
+    ) -> Result<Completion> {
+        let mut options = json!({});
+        if let Some(temperature) = request.temperature {
+            options["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = request.top_p {
+            options["top_p"] = json!(top_p);
+        }
+        if let Some(max_tokens) = request.max_tokens {
+            options["num_predict"] = json!(max_tokens);
+        }
+        let mut messages: Vec<Value> = request.messages.iter().map(|m| json!(m)).collect();
+        messages.extend(tool_messages(&request.tool_rounds));
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "stream": true,
+            "options": options,
+        });
+        if let Some(schema) = &request.response_schema {
+            body["format"] = schema.clone();
+        }
+        if !request.tools.is_empty() {
+            body["tools"] = request
+                .tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": tool.name,
+                            "description": tool.description,
+                            "parameters": tool.parameters,
+                        },
+                    })
+                })
+                .collect();
+        }
+        let response = audit::send(
+            self.id(),
+            Some(&request.model),
+            client.post(self.url("/api/chat")).json(&body),
+        )
+        .await?;
+        let response = check_status(self.id(), response).await?;
+
+        let mut content = String::new();
+        let mut usage = None;
+        let mut tool_calls = Vec::new();
+        read_ndjson(response, |value| {
+            if let Some(delta) = value["message"]["content"].as_str() {
+                if !delta.is_empty() {
+                    on_delta(delta);
+                    content.push_str(delta);
+                }
+            }
+            for call in value["message"]["tool_calls"]
+                .as_array()
+                .into_iter()
+                .flatten()
+            {
+                let function = &call["function"];
+                if let Some(name) = function["name"].as_str() {
+                    tool_calls.push(ToolCall {
+                        id: format!("call_{}", tool_calls.len()),
+                        name: name.to_string(),
+                        arguments: function["arguments"].clone(),
+                    });
+                }
+            }
+            if value["done"].as_bool() == Some(true) {
+                usage = Some(Usage {
+                    prompt_tokens: token_count(&value["prompt_eval_count"]),
+                    completion_tokens: token_count(&value["eval_count"]),
+                });
+            }
+            Ok(())
+        })
+        .await?;
+        Ok(Completion {
+            content,
+            usage,
+            tool_calls,
+        })
+    }
+}
+
+/// Probes for a local server in the background so startup isn't held up.
+pub fn init(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let client = app.state::<Client>();
+        app.state::<Providers>().ollama().detect(&client).await;
+    });
+}
+
+#[tauri::command]
+pub async fn detect_ollama(
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+) -> Result<OllamaStatus> {
+    Ok(providers.ollama().detect(&client).await)
+}
+
+#[tauri::command]
+pub async fn list_ollama_models(
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+) -> Result<Vec<OllamaModel>> {
+    providers.ollama().installed_models(&client).await
+}
+
+/// Pulls a model, emitting `ollama-pull-progress` as layers download.
+/// Cancel it with `cancel_request(request_id)`.
+#[tauri::command]
+pub async fn pull_ollama_model(
+    app: AppHandle,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    requests: State<'_, Requests>,
+    request_id: String,
+    model: String,
+) -> Result<()> {
+    let guard = requests.register(&request_id);
+    let pull = providers.ollama().pull(&client, &model, |value| {
+        let _ = app.emit(
+            "ollama-pull-progress",
+            OllamaPullProgress {
+                request_id: request_id.clone(),
+                model: model.clone(),
+                status: value["status"].as_str().unwrap_or_default().to_string(),
+                digest: value["digest"].as_str().map(str::to_string),
+                total: value["total"].as_u64(),
+                completed: value["completed"].as_u64(),
+            },
+        );
+    });
+    cancellable(guard.token(), pull).await
+}
+
+#[tauri::command]
+pub async fn delete_ollama_model(
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    model: String,
+) -> Result<()> {
+    providers.ollama().delete(&client, &model).await
+}
@@ -0,0 +1,435 @@
+use async_trait::async_trait;
+use reqwest::{Client, Method, RequestBuilder, Url};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use std::collections::BTreeMap;
+
+use super::custom::{self, CustomProvider};
+use super::{
+    api_key, check_status, read_sse, token_count, Completion, DeltaSink, ModelInfo,
+    PartialToolCall, Provider, TranscriptionRequest,
+};
+use crate::audit;
+use crate::error::{Error, Result};
+use crate::llm::{ChatRequest, ToolRound, ToolSpec};
+use crate::network;
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct TranscriptionResponse {
+    text: String,
+}
+
+const BOUNDARY: &str = "pentamind-form-boundary-7d3f1a";
+
+/// A `multipart/form-data` body with text `fields` and one WAV file.
+fn multipart(fields: &[(&str, &str)], audio: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(audio.len() + 512);
+    for (name, value) in fields {
+        body.extend_from_slice(
+            format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            )
+            .as_bytes(),
+        );
+    }
+    body.extend_from_slice(
+        format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"audio.wav\"\r\n\
+             Content-Type: audio/wav\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(audio);
+    body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+    body
+}
+
+fn tools(tools: &[ToolSpec]) -> Value {
+    tools
+        .iter()
+        .map(|tool| {
+            json!({
+                "type": "function",
+                "function": {
+                    "name": tool.name,
+                    "description": tool.description,
+                    "parameters": tool.parameters,
+                },
+            })
+        })
+        .collect()
+}
+
+/// The assistant's tool calls and a `tool` message per result.
+fn tool_messages(rounds: &[ToolRound]) -> Vec<Value> {
+    let mut messages = Vec::new();
+    for round in rounds {
+        let calls: Vec<Value> = round
+            .calls
+            .iter()
+            .map(|call| {
+                let arguments = match &call.arguments {
+                    Value::String(raw) => raw.clone(),
+                    arguments => arguments.to_string(),
+                };
+                json!({
+                    "id": call.id,
+                    "type": "function",
+                    "function": { "name": call.name, "arguments": arguments },
+                })
+            })
+            .collect();
+        messages.push(json!({
+            "role": "assistant",
+            "content": (!round.text.is_empty()).then_some(&round.text),
+            "tool_calls": calls,
+        }));
+        for result in &round.results {
+            messages.push(json!({
+                "role": "tool",
+                "tool_call_id": result.call_id,
+                "content": result.content,
+            }));
+        }
+    }
+    messages
+}
+
+/// Any vendor speaking the OpenAI chat completions API. Mistral's API is
+/// wire-compatible, so it shares this implementation, as do the endpoints
+/// users add themselves.
+pub struct OpenAiCompatible {
+    id: &'static str,
+    name: &'static str,
+    base_url: &'static str,
+    key_var: &'static str,
+    /// Keychain entry holding the key.
+    key_entry: &'static str,
+    default_model: &'static str,
+    embedding_model: Option<&'static str>,
+    transcription_model: Option<&'static str>,
+    /// OpenAI only reports usage on streams when asked via `stream_options`;
+    /// Mistral always sends it and rejects the option.
+    stream_usage: bool,
+    /// Sent with every request, for gateways that want more than a key.
+    headers: Vec<(String, String)>,
+    /// Offered when the endpoint can't list its own.
+    models: Vec<String>,
+    /// Local servers usually take no key at all.
+    key_required: bool,
+    /// Used instead of the keychain, to test an endpoint before saving it.
+    key: Option<String>,
+}
+
+impl OpenAiCompatible {
+    /// The key to send, if there is one.
+    fn key(&self) -> Result<Option<String>> {
+        if let Some(key) = &self.key {
+            return Ok(Some(key.clone()));
+        }
+        match api_key(self.key_var, self.key_entry) {
+            Ok(key) => Ok(Some(key)),
+            Err(Error::MissingApiKey(_)) if !self.key_required => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// A request to `path` under the base URL, or the one set in the network
+    /// settings, with the key and any extra headers attached.
+    fn request(
+        &self,
+        client: &Client,
+        method: Method,
+        path: &str,
+        key: Option<&str>,
+    ) -> RequestBuilder {
+        let url = network::endpoint(self.id, self.base_url, path);
+        // Azure OpenAI wants its keys in a header of its own.
+        let azure = Url::parse(&url)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.ends_with(".openai.azure.com")))
+            .unwrap_or(false);
+        let mut request = client.request(method, url);
+        request = match key {
+            Some(key) if azure => request.header("api-key", key),
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        };
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        request
+    }
+
+    pub fn openai() -> Self {
+        Self {
+            id: "openai",
+            name: "OpenAI",
+            base_url: "https://api.openai.com/v1",
+            key_var: "OPENAI_API_KEY",
+            key_entry: "openai",
+            default_model: "gpt-4o",
+            embedding_model: Some("text-embedding-3-small"),
+            transcription_model: Some("gpt-4o-mini-transcribe"),
+            stream_usage: true,
+            headers: Vec::new(),
+            models: Vec::new(),
+            key_required: true,
+            key: None,
+        }
+    }
+
+    pub fn mistral() -> Self {
+        Self {
+            id: "mistral",
+            name: "Mistral",
+            base_url: "https://api.mistral.ai/v1",
+            key_var: "MISTRAL_API_KEY",
+            key_entry: "mistral",
+            default_model: "mistral-small-latest",
+            embedding_model: Some("mistral-embed"),
+            transcription_model: Some("voxtral-mini-latest"),
+            stream_usage: false,
+            headers: Vec::new(),
+            models: Vec::new(),
+            key_required: true,
+            key: None,
+        }
+    }
+
+    /// An endpoint the user added. Its key, if it needs one, is in the
+    /// keychain under [`custom::key_entry`] unless `key` is given.
+    pub fn custom(provider: &CustomProvider, key: Option<String>) -> Self {
+        Self {
+            id: provider.id.clone().leak(),
+            name: provider.name.clone().leak(),
+            base_url: provider.base_url.trim_end_matches('/').to_string().leak(),
+            key_var: "",
+            key_entry: custom::key_entry(&provider.id).leak(),
+            default_model: provider.default_model.clone().leak(),
+            embedding_model: None,
+            transcription_model: None,
+            stream_usage: provider.stream_usage,
+            headers: provider
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            models: provider.models.clone(),
+            key_required: false,
+            key,
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for OpenAiCompatible {
+    fn id(&self) -> &'static str {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn default_model(&self) -> &'static str {
+        self.default_model
+    }
+
+    fn configured(&self) -> bool {
+        self.key().is_ok()
+    }
+
+    fn embedding_model(&self) -> Option<&'static str> {
+        self.embedding_model
+    }
+
+    async fn embed(
+        &self,
+        client: &Client,
+        model: &str,
+        inputs: &[String],
+    ) -> Result<Vec<Vec<f32>>> {
+        let key = self.key()?;
+        let response = audit::send(
+            self.id,
+            Some(model),
+            self.request(client, Method::POST, "/embeddings", key.as_deref())
+                .json(&json!({ "model": model, "input": inputs })),
+        )
+        .await?;
+        let body: EmbeddingResponse = check_status(self.id, response).await?.json().await?;
+        let mut data = body.data;
+        data.sort_by_key(|d| d.index);
+        Ok(data.into_iter().map(|d| d.embedding).collect())
+    }
+
+    fn transcription_model(&self) -> Option<&'static str> {
+        self.transcription_model
+    }
+
+    async fn transcribe(
+        &self,
+        client: &Client,
+        request: &TranscriptionRequest<'_>,
+    ) -> Result<String> {
+        let key = self.key()?;
+        let mut fields = vec![("model", request.model)];
+        fields.extend(request.language.map(|l| ("language", l)));
+        fields.extend(request.prompt.map(|p| ("prompt", p)));
+        let response = audit::send(
+            self.id,
+            Some(request.model),
+            self.request(
+                client,
+                Method::POST,
+                "/audio/transcriptions",
+                key.as_deref(),
+            )
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(multipart(&fields, request.audio)),
+        )
+        .await?;
+        let body: TranscriptionResponse = check_status(self.id, response).await?.json().await?;
+        Ok(body.text)
+    }
+
+    async fn list_models(&self, client: &Client) -> Result<Vec<ModelInfo>> {
+        let key = self.key()?;
+        let listed = async {
+            let response = audit::send(
+                self.id,
+                None,
+                self.request(client, Method::GET, "/models", key.as_deref()),
+            )
+            .await?;
+            let body: Value = check_status(self.id, response).await?.json().await?;
+            Ok::<_, Error>(
+                body["data"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|m| m["id"].as_str())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>(),
+            )
+        }
+        .await;
+        let mut ids = match listed {
+            Ok(ids) => ids,
+            Err(_) if !self.models.is_empty() => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        for model in &self.models {
+            if !ids.contains(model) {
+                ids.push(model.clone());
+            }
+        }
+        Ok(ids
+            .into_iter()
+            .map(|id| ModelInfo {
+                name: id.clone(),
+                id,
+            })
+            .collect())
+    }
+
+    async fn stream(
+        &self,
+        client: &Client,
+        request: &ChatRequest,
+        on_delta: &mut DeltaSink<'_>,
+    ) -> Result<Completion> {
+        let key = self.key()?;
+        let mut messages: Vec<Value> = request.messages.iter().map(|m| json!(m)).collect();
+        messages.extend(tool_messages(&request.tool_rounds));
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "stream": true,
+        });
+        if !request.tools.is_empty() {
+            body["tools"] = tools(&request.tools);
+        }
+        if self.stream_usage {
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+        if let Some(temperature) = request.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = request.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if let Some(max_tokens) = request.max_tokens {
+            body["max_tokens"] = json!(max_tokens);
+        }
+        if let Some(schema) = &request.response_schema {
+            body["response_format"] = json!({
+                "type": "json_schema",
+                "json_schema": { "name": "response", "schema": schema },
+            });
+        }
+        let response = audit::send(
+            self.id,
+            Some(&request.model),
+            self.request(client, Method::POST, "/chat/completions", key.as_deref())
+                .json(&body),
+        )
+        .await?;
+        let response = check_status(self.id, response).await?;
+        // Calls arrive in fragments keyed by index: the id and name first,
+        // then the arguments a piece at a time.
+        let mut calls: BTreeMap<u64, PartialToolCall> = BTreeMap::new();
+        let mut completion = read_sse(
+            response,
+            |event, usage| {
+                if event.data.is_empty() || event.data == "[DONE]" {
+                    return Ok(None);
+                }
+                let value: Value = serde_json::from_str(&event.data)?;
+                if let Some(reported) = value["usage"].as_object() {
+                    usage.prompt_tokens = token_count(&reported["prompt_tokens"]);
+                    usage.completion_tokens = token_count(&reported["completion_tokens"]);
+                }
+                let delta = &value["choices"][0]["delta"];
+                for fragment in delta["tool_calls"].as_array().into_iter().flatten() {
+                    let call = calls
+                        .entry(fragment["index"].as_u64().unwrap_or_default())
+                        .or_default();
+                    if let Some(id) = fragment["id"].as_str() {
+                        call.id = id.to_string();
+                    }
+                    if let Some(name) = fragment["function"]["name"].as_str() {
+                        call.name.push_str(name);
+                    }
+                    if let Some(arguments) = fragment["function"]["arguments"].as_str() {
+                        call.arguments.push_str(arguments);
+                    }
+                }
+                Ok(delta["content"]
+                    .as_str()
+                    .filter(|d| !d.is_empty())
+                    .map(str::to_string))
+            },
+            on_delta,
+        )
+        .await?;
+        completion.tool_calls = calls.into_values().map(PartialToolCall::finish).collect();
+        Ok(completion)
+    }
+}
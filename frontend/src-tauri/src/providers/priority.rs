@@ -0,0 +1,184 @@
+//! Priority classes for outbound calls, and the queue that hands out call
+//! slots by them.
+//!
+//! Work declares its class with [`scope`]; calls made outside any scope
+//! are interactive, which covers every command the user is waiting on.
+//! Background tasks and jobs run in their own scope, and work spawned onto
+//! another task keeps its class through [`inherit`]. A [`Gate`] serves its
+//! waiters most urgent first, and keeps some slots free of all but
+//! interactive calls, so a long eval can't take every connection from a
+//! chat.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+/// Most urgent first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    /// Someone is waiting on the answer: chats, fan-outs and the like.
+    Interactive,
+    /// Work nobody is watching, such as titles, summaries and memories.
+    Background,
+    /// Eval runs, which can be large and are never urgent.
+    Eval,
+}
+
+tokio::task_local! {
+    static PRIORITY: Priority;
+}
+
+/// The class of the calling task.
+pub fn current() -> Priority {
+    PRIORITY.try_with(|p| *p).unwrap_or(Priority::Interactive)
+}
+
+/// Runs `work` with its calls in `priority`.
+pub async fn scope<F: Future>(priority: Priority, work: F) -> F::Output {
+    PRIORITY.scope(priority, work).await
+}
+
+/// Wraps `work` for spawning so it keeps the current task's class.
+pub fn inherit<F: Future>(work: F) -> impl Future<Output = F::Output> {
+    PRIORITY.scope(current(), work)
+}
+
+struct GateState {
+    in_use: usize,
+    /// Keyed by class, then arrival, so the first entry goes next.
+    waiting: BTreeMap<(Priority, u64), oneshot::Sender<()>>,
+    arrivals: u64,
+}
+
+/// A limited number of slots, handed out by priority.
+pub struct Gate {
+    limit: usize,
+    /// Slots that only interactive calls may take.
+    reserved: usize,
+    state: Mutex<GateState>,
+}
+
+/// Holds a slot until dropped.
+pub struct GatePermit(Arc<Gate>);
+
+impl Drop for GatePermit {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// Takes the waiter out of the queue if it gives up, and hands back a slot
+/// it was granted but never took.
+struct Waiter<'a> {
+    gate: &'a Gate,
+    key: (Priority, u64),
+    receiver: oneshot::Receiver<()>,
+    granted: bool,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if self.granted {
+            return;
+        }
+        self.gate.state.lock().unwrap().waiting.remove(&self.key);
+        if self.receiver.try_recv().is_ok() {
+            self.gate.release();
+        }
+    }
+}
+
+impl Gate {
+    /// At least one slot, and at least one open to every class.
+    pub fn new(limit: u32, reserved: u32) -> Arc<Self> {
+        let limit = limit.max(1) as usize;
+        Arc::new(Self {
+            limit,
+            reserved: (reserved as usize).min(limit - 1),
+            state: Mutex::new(GateState {
+                in_use: 0,
+                waiting: BTreeMap::new(),
+                arrivals: 0,
+            }),
+        })
+    }
+
+    fn admits(&self, in_use: usize, priority: Priority) -> bool {
+        let limit = if priority == Priority::Interactive {
+            self.limit
+        } else {
+            self.limit - self.reserved
+        };
+        in_use < limit
+    }
+
+    fn grant(&self, state: &mut GateState) {
+        while let Some(&key) = state.waiting.keys().next() {
+            if !self.admits(state.in_use, key.0) {
+                break;
+            }
+            let sender = state.waiting.remove(&key).unwrap();
+            // A waiter that gave up has dropped its receiver.
+            if sender.send(()).is_ok() {
+                state.in_use += 1;
+            }
+        }
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.in_use -= 1;
+        self.grant(&mut state);
+    }
+
+    /// A slot now, if one is free and nobody as urgent is queued ahead.
+    pub fn try_acquire(self: &Arc<Self>, priority: Priority) -> Option<GatePermit> {
+        let mut state = self.state.lock().unwrap();
+        let queued_ahead = state
+            .waiting
+            .keys()
+            .next()
+            .is_some_and(|(p, _)| *p <= priority);
+        if queued_ahead || !self.admits(state.in_use, priority) {
+            return None;
+        }
+        state.in_use += 1;
+        Some(GatePermit(self.clone()))
+    }
+
+    /// Waits for a slot, behind everyone more urgent or queued earlier in
+    /// the same class.
+    pub async fn acquire(self: &Arc<Self>, priority: Priority) -> GatePermit {
+        let mut waiter = {
+            let mut state = self.state.lock().unwrap();
+            let queued_ahead = state
+                .waiting
+                .keys()
+                .next()
+                .is_some_and(|(p, _)| *p <= priority);
+            if !queued_ahead && self.admits(state.in_use, priority) {
+                state.in_use += 1;
+                return GatePermit(self.clone());
+            }
+            let (sender, receiver) = oneshot::channel();
+            let key = (priority, state.arrivals);
+            state.arrivals += 1;
+            state.waiting.insert(key, sender);
+            Waiter {
+                gate: self,
+                key,
+                receiver,
+                granted: false,
+            }
+        };
+        (&mut waiter.receiver)
+            .await
+            .expect("waiters only leave the queue by being granted or giving up");
+        waiter.granted = true;
+        GatePermit(self.clone())
+    }
+}
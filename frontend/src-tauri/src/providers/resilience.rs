@@ -0,0 +1,491 @@
+//! Retries, backoff and per-provider queueing around every provider call.
+//!
+//! Each registered provider is wrapped in a [`Resilient`]. Calls wait their
+//! turn under the provider's concurrency and per-minute limits and a cap on
+//! calls in flight across all providers, most urgent class first (see
+//! [`priority`](super::priority)); part of each budget is kept for
+//! interactive calls. Failures worth retrying (rate limits, overload,
+//! server errors, dropped connections) are tried again after the
+//! `Retry-After` the provider asked for, or with exponential backoff and
+//! jitter. A 429 also holds back the provider's queued calls. Every wait is
+//! emitted, as `provider-queued` or `provider-retry`, so the UI can say why
+//! a response is late rather than fail outright. Every chat attempt also
+//! goes into the provider's [`Stats`].
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::priority::{self, Gate, GatePermit, Priority};
+use super::stats::Stats;
+use super::{Completion, DeltaSink, ModelInfo, Provider, ProviderInfo, TranscriptionRequest};
+use crate::config;
+use crate::error::{Error, Result};
+use crate::llm::ChatRequest;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter};
+
+const CONFIG_FILE: &str = "rate_limits.json";
+const CONCURRENCY_FILE: &str = "concurrency.json";
+const MAX_ATTEMPTS: u32 = 4;
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+/// A provider asking for a longer wait than this gets an error instead.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+const DEFAULT_CONCURRENCY: u32 = 4;
+/// One in this many of a provider's calls per minute is kept for
+/// interactive calls.
+const INTERACTIVE_RPM_SHARE: u32 = 4;
+/// Statuses that mean "try again later": timeouts, rate limits, server
+/// errors and Anthropic's 529 overload.
+const RETRY_STATUSES: [u16; 8] = [408, 425, 429, 500, 502, 503, 504, 529];
+
+/// Limits for one provider; unset fields use the defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    /// Calls in flight at once. Defaults to 4.
+    pub max_concurrent: Option<u32>,
+    /// Calls started per rolling minute. Unlimited by default.
+    pub requests_per_minute: Option<u32>,
+}
+
+/// Limits across every provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConcurrencyLimits {
+    /// Calls in flight at once, whatever the provider.
+    pub max_concurrent: u32,
+    /// Slots of this limit, and of each provider's, that only interactive
+    /// calls may take.
+    pub interactive_reserve: u32,
+}
+
+impl Default for ConcurrencyLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 8,
+            interactive_reserve: 1,
+        }
+    }
+}
+
+/// Payload of the `provider-queued` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderQueued {
+    pub provider: String,
+    pub model: Option<String>,
+    /// `concurrency`, `global_concurrency`, `requests_per_minute` or
+    /// `rate_limited` (the provider returned 429 recently).
+    pub reason: &'static str,
+    pub priority: Priority,
+    /// Unknown while waiting for another call to finish.
+    pub wait_ms: Option<u64>,
+}
+
+/// Payload of the `provider-retry` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderRetry {
+    pub provider: String,
+    pub model: Option<String>,
+    /// The attempt about to be made, from 2.
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub delay_ms: u64,
+    pub reason: String,
+    /// The failed attempt had streamed text, which the retry replaces.
+    pub partial_discarded: bool,
+}
+
+/// One provider's queue.
+struct Limiter {
+    permits: Arc<Gate>,
+    requests_per_minute: Option<u32>,
+    /// Start times within the last minute.
+    started: Mutex<VecDeque<Instant>>,
+    /// Set by a 429: nothing starts before then.
+    cooldown_until: Mutex<Option<Instant>>,
+}
+
+impl Limiter {
+    fn new(limit: RateLimit, reserve: u32) -> Self {
+        let concurrency = limit.max_concurrent.unwrap_or(DEFAULT_CONCURRENCY);
+        Self {
+            permits: Gate::new(concurrency, reserve),
+            requests_per_minute: limit.requests_per_minute.filter(|n| *n > 0),
+            started: Mutex::new(VecDeque::new()),
+            cooldown_until: Mutex::new(None),
+        }
+    }
+
+    /// How long until a call may start, and why; records the start when
+    /// it may start now. Calls other than interactive ones leave part of
+    /// the per-minute budget unused.
+    fn wait(&self, now: Instant, priority: Priority) -> Option<(Duration, &'static str)> {
+        if let Some(until) = *self.cooldown_until.lock().unwrap() {
+            if until > now {
+                return Some((until - now, "rate_limited"));
+            }
+        }
+        let mut started = self.started.lock().unwrap();
+        while started
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= Duration::from_secs(60))
+        {
+            started.pop_front();
+        }
+        if let Some(limit) = self.requests_per_minute {
+            let limit = if priority == Priority::Interactive {
+                limit
+            } else {
+                limit - limit / INTERACTIVE_RPM_SHARE
+            };
+            if started.len() >= limit as usize {
+                // The start that has to leave the window for one more to fit.
+                let oldest = started[started.len() - limit as usize];
+                return Some((
+                    Duration::from_secs(60).saturating_sub(now.duration_since(oldest)),
+                    "requests_per_minute",
+                ));
+            }
+        }
+        started.push_back(now);
+        None
+    }
+
+    fn cool_down(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut cooldown = self.cooldown_until.lock().unwrap();
+        if cooldown.is_none_or(|current| current < until) {
+            *cooldown = Some(until);
+        }
+    }
+}
+
+/// Every provider's limits. Shared by the wrappers and the commands that
+/// change them.
+pub struct RateLimits {
+    limits: Mutex<BTreeMap<String, RateLimit>>,
+    limiters: Mutex<HashMap<String, Arc<Limiter>>>,
+    concurrency: Mutex<ConcurrencyLimits>,
+    global: Mutex<Arc<Gate>>,
+}
+
+impl RateLimits {
+    pub fn load(app: &AppHandle) -> Self {
+        let limits = config::read(app, CONFIG_FILE)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        let concurrency: ConcurrencyLimits = config::read(app, CONCURRENCY_FILE)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        Self {
+            limits: Mutex::new(limits),
+            limiters: Mutex::new(HashMap::new()),
+            global: Mutex::new(Gate::new(
+                concurrency.max_concurrent,
+                concurrency.interactive_reserve,
+            )),
+            concurrency: Mutex::new(concurrency),
+        }
+    }
+
+    fn limiter(&self, provider: &str) -> Arc<Limiter> {
+        let mut limiters = self.limiters.lock().unwrap();
+        limiters
+            .entry(provider.to_string())
+            .or_insert_with(|| {
+                let limit = self
+                    .limits
+                    .lock()
+                    .unwrap()
+                    .get(provider)
+                    .copied()
+                    .unwrap_or_default();
+                let reserve = self.concurrency.lock().unwrap().interactive_reserve;
+                Arc::new(Limiter::new(limit, reserve))
+            })
+            .clone()
+    }
+
+    pub fn get(&self) -> BTreeMap<String, RateLimit> {
+        self.limits.lock().unwrap().clone()
+    }
+
+    /// Replaces `provider`'s limits. Calls already queued finish under the
+    /// old ones.
+    pub fn set(&self, app: &AppHandle, provider: &str, limit: RateLimit) -> Result<()> {
+        let mut limits = self.limits.lock().unwrap();
+        if limit == RateLimit::default() {
+            limits.remove(provider);
+        } else {
+            limits.insert(provider.to_string(), limit);
+        }
+        config::write(app, CONFIG_FILE, &*limits)?;
+        self.limiters.lock().unwrap().remove(provider);
+        Ok(())
+    }
+
+    fn global(&self) -> Arc<Gate> {
+        self.global.lock().unwrap().clone()
+    }
+
+    pub fn concurrency(&self) -> ConcurrencyLimits {
+        *self.concurrency.lock().unwrap()
+    }
+
+    /// Replaces the global limits. Calls already queued finish under the
+    /// old ones.
+    pub fn set_concurrency(&self, app: &AppHandle, limits: ConcurrencyLimits) -> Result<()> {
+        if limits.max_concurrent == 0 {
+            return Err(Error::InvalidSetting(
+                "at least one call must be allowed at a time".into(),
+            ));
+        }
+        config::write(app, CONCURRENCY_FILE, &limits)?;
+        *self.concurrency.lock().unwrap() = limits;
+        *self.global.lock().unwrap() = Gate::new(limits.max_concurrent, limits.interactive_reserve);
+        // Provider queues take the new reserve when next made.
+        self.limiters.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+/// How long to wait before retrying after `err`, and a short reason, or
+/// `None` if it isn't worth retrying.
+fn retry_delay(err: &Error, attempt: u32) -> Option<(Duration, String)> {
+    let (asked, reason) = match err {
+        Error::Api {
+            status,
+            retry_after,
+            ..
+        } if RETRY_STATUSES.contains(status) => (*retry_after, format!("HTTP {status}")),
+        Error::Http(e) if e.is_timeout() => (None, "timed out".to_string()),
+        Error::Http(e) if e.is_connect() || e.is_request() || e.is_body() => {
+            (None, "connection error".to_string())
+        }
+        _ => return None,
+    };
+    match asked {
+        Some(delay) if delay > MAX_RETRY_AFTER => None,
+        Some(delay) => Some((delay, reason)),
+        None => Some((backoff(attempt), reason)),
+    }
+}
+
+/// Exponential backoff with jitter: somewhere in the upper half of
+/// `BASE_DELAY * 2^(attempt - 1)`, capped at `MAX_DELAY`.
+fn backoff(attempt: u32) -> Duration {
+    let ceiling = BASE_DELAY
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(MAX_DELAY);
+    let random = RandomState::new().build_hasher().finish();
+    let fraction = 0.5 + (random % 1000) as f64 / 2000.0;
+    ceiling.mul_f64(fraction)
+}
+
+/// A provider with queueing and retries in front of it.
+pub struct Resilient {
+    inner: Arc<dyn Provider>,
+    limits: Arc<RateLimits>,
+    stats: Arc<Stats>,
+    app: AppHandle,
+}
+
+impl Resilient {
+    pub fn new(
+        app: &AppHandle,
+        limits: Arc<RateLimits>,
+        stats: Arc<Stats>,
+        inner: Arc<dyn Provider>,
+    ) -> Self {
+        Self {
+            inner,
+            limits,
+            stats,
+            app: app.clone(),
+        }
+    }
+
+    /// Waits for a free slot under the provider's limits and then the
+    /// global one, in the calling task's priority class.
+    async fn admit(&self, model: Option<&str>) -> (GatePermit, GatePermit) {
+        let priority = priority::current();
+        let limiter = self.limits.limiter(self.inner.id());
+        let queued = |reason, wait: Option<Duration>| {
+            let _ = self.app.emit(
+                "provider-queued",
+                ProviderQueued {
+                    provider: self.inner.id().to_string(),
+                    model: model.map(str::to_string),
+                    reason,
+                    priority,
+                    wait_ms: wait.map(|w| w.as_millis() as u64),
+                },
+            );
+        };
+        let slot = |gate: Arc<Gate>, reason| async move {
+            match gate.try_acquire(priority) {
+                Some(permit) => permit,
+                None => {
+                    queued(reason, None);
+                    gate.acquire(priority).await
+                }
+            }
+        };
+        let permit = slot(limiter.permits.clone(), "concurrency").await;
+        while let Some((wait, reason)) = limiter.wait(Instant::now(), priority) {
+            queued(reason, Some(wait));
+            tokio::time::sleep(wait).await;
+        }
+        let global = slot(self.limits.global(), "global_concurrency").await;
+        (permit, global)
+    }
+
+    /// Whether to try again after attempt `attempt` failed with `err`.
+    /// Emits `provider-retry` and sleeps out the delay if so.
+    async fn should_retry(
+        &self,
+        err: &Error,
+        attempt: u32,
+        model: Option<&str>,
+        partial_discarded: bool,
+    ) -> bool {
+        if attempt >= MAX_ATTEMPTS {
+            return false;
+        }
+        let Some((delay, reason)) = retry_delay(err, attempt) else {
+            return false;
+        };
+        if matches!(err, Error::Api { status: 429, .. }) {
+            self.limits.limiter(self.inner.id()).cool_down(delay);
+        }
+        let _ = self.app.emit(
+            "provider-retry",
+            ProviderRetry {
+                provider: self.inner.id().to_string(),
+                model: model.map(str::to_string),
+                attempt: attempt + 1,
+                max_attempts: MAX_ATTEMPTS,
+                delay_ms: delay.as_millis() as u64,
+                reason,
+                partial_discarded,
+            },
+        );
+        tokio::time::sleep(delay).await;
+        true
+    }
+
+    /// Runs a call that streams nothing, retrying it as needed.
+    async fn call<T, F, Fut>(&self, model: Option<&str>, mut call: F) -> Result<T>
+    where
+        F: FnMut() -> Fut + Send,
+        Fut: Future<Output = Result<T>> + Send,
+        T: Send,
+    {
+        let mut attempt = 1;
+        loop {
+            let permit = self.admit(model).await;
+            let result = call().await;
+            drop(permit);
+            match result {
+                Err(err) if self.should_retry(&err, attempt, model, false).await => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for Resilient {
+    fn id(&self) -> &'static str {
+        self.inner.id()
+    }
+
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn default_model(&self) -> &'static str {
+        self.inner.default_model()
+    }
+
+    fn configured(&self) -> bool {
+        self.inner.configured()
+    }
+
+    async fn list_models(&self, client: &Client) -> Result<Vec<ModelInfo>> {
+        self.call(None, move || self.inner.list_models(client))
+            .await
+    }
+
+    async fn stream(
+        &self,
+        client: &Client,
+        request: &ChatRequest,
+        on_delta: &mut DeltaSink<'_>,
+    ) -> Result<Completion> {
+        let model = Some(request.model.as_str());
+        let mut attempt = 1;
+        loop {
+            let permit = self.admit(model).await;
+            let started = Instant::now();
+            let mut first_token = None;
+            let result = self
+                .inner
+                .stream(client, request, &mut |delta: &str| {
+                    first_token.get_or_insert_with(Instant::now);
+                    on_delta(delta);
+                })
+                .await;
+            drop(permit);
+            self.stats
+                .record(self.inner.id(), started, first_token, &result);
+            let streamed = first_token.is_some();
+            match result {
+                Err(err) if self.should_retry(&err, attempt, model, streamed).await => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    fn embedding_model(&self) -> Option<&'static str> {
+        self.inner.embedding_model()
+    }
+
+    async fn embed(
+        &self,
+        client: &Client,
+        model: &str,
+        inputs: &[String],
+    ) -> Result<Vec<Vec<f32>>> {
+        self.call(Some(model), move || self.inner.embed(client, model, inputs))
+            .await
+    }
+
+    fn transcription_model(&self) -> Option<&'static str> {
+        self.inner.transcription_model()
+    }
+
+    async fn transcribe(
+        &self,
+        client: &Client,
+        request: &TranscriptionRequest<'_>,
+    ) -> Result<String> {
+        self.call(Some(request.model), move || {
+            self.inner.transcribe(client, request)
+        })
+        .await
+    }
+
+    fn info(&self) -> ProviderInfo {
+        self.inner.info()
+    }
+}
@@ -0,0 +1,130 @@
+/// A single server-sent event as emitted by provider streaming endpoints.
+#[derive(Debug, Default, Clone)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// Incremental SSE parser. Network chunks can split events (and UTF-8
+/// sequences) anywhere, so bytes are buffered until a blank line ends an event.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buf: Vec<u8>,
+}
+
+impl SseDecoder {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buf.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some((end, sep)) = find_boundary(&self.buf) {
+            let block: Vec<u8> = self.buf.drain(..end + sep).collect();
+            if let Some(event) = parse_block(&String::from_utf8_lossy(&block[..end])) {
+                events.push(event);
+            }
+        }
+        events
+    }
+}
+
+fn find_boundary(buf: &[u8]) -> Option<(usize, usize)> {
+    let lf = buf.windows(2).position(|w| w == b"\n\n").map(|i| (i, 2));
+    let crlf = buf
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| (i, 4));
+    match (lf, crlf) {
+        (Some(a), Some(b)) => Some(if a.0 <= b.0 { a } else { b }),
+        (a, b) => a.or(b),
+    }
+}
+
+fn parse_block(block: &str) -> Option<SseEvent> {
+    let mut event = SseEvent::default();
+    let mut has_data = false;
+    for line in block.lines() {
+        if line.is_empty() || line.starts_with(':') {
+            continue;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => event.event = Some(value.to_string()),
+            "data" => {
+                if has_data {
+                    event.data.push('\n');
+                }
+                event.data.push_str(value);
+                has_data = true;
+            }
+            _ => {}
+        }
+    }
+    (has_data || event.event.is_some()).then_some(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(chunks: &[&[u8]]) -> Vec<(Option<String>, String)> {
+        let mut decoder = SseDecoder::default();
+        chunks
+            .iter()
+            .flat_map(|chunk| decoder.push(chunk))
+            .map(|event| (event.event, event.data))
+            .collect()
+    }
+
+    fn data(data: &str) -> (Option<String>, String) {
+        (None, data.to_string())
+    }
+
+    #[test]
+    fn joins_events_split_across_chunks() {
+        let mut decoder = SseDecoder::default();
+        assert!(decoder.push(b"data: hel").is_empty());
+        assert!(decoder.push(b"lo\n").is_empty());
+        let events = decoder.push(b"\ndata: next\n\n");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].data, "hello");
+        assert_eq!(events[1].data, "next");
+
+        let text = "data: héllo\n\n".as_bytes();
+        let split = text.iter().position(|&b| b >= 0x80).unwrap() + 1;
+        assert_eq!(decode(&[&text[..split], &text[split..]]), [data("héllo")]);
+    }
+
+    #[test]
+    fn ends_events_at_crlf_boundaries() {
+        assert_eq!(
+            decode(&[b"event: delta\r\ndata: 1\r\n\r\ndata: 2\r\n\r\n"]),
+            [(Some("delta".into()), "1".into()), data("2")]
+        );
+        assert_eq!(decode(&[b"data: 1\r\n\r", b"\n"]), [data("1")]);
+        assert_eq!(decode(&[b"data: 1\r\n", b"\r\n"]), [data("1")]);
+    }
+
+    #[test]
+    fn joins_multiline_data() {
+        assert_eq!(
+            decode(&[b"data: first\ndata:second\ndata\n\n"]),
+            [data("first\nsecond\n")]
+        );
+    }
+
+    #[test]
+    fn skips_comments() {
+        assert!(decode(&[b": ping\n\n"]).is_empty());
+        assert_eq!(decode(&[b": keep-alive\nid: 7\ndata: x\n\n"]), [data("x")]);
+    }
+
+    #[test]
+    fn keeps_events_without_data() {
+        assert_eq!(
+            decode(&[b"event: done\n\n"]),
+            [(Some("done".into()), String::new())]
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Rolling latency and reliability figures per provider. Every chat
+//! attempt through [`super::Resilient`] is recorded, retries included, with
+//! its time to first token, total time and outcome; the last hour is kept
+//! in memory and summarised on request, so the UI can show which providers
+//! are fast, slow or down right now.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::storage::now_ms;
+
+/// Samples older than this are dropped.
+const RETENTION: Duration = Duration::from_secs(60 * 60);
+const MAX_SAMPLES: usize = 1000;
+/// A median time to first token above this counts as slow.
+const SLOW_FIRST_TOKEN: Duration = Duration::from_secs(4);
+/// This many failures in a row, or an error rate this high, is down.
+const DOWN_STREAK: usize = 3;
+const DOWN_ERROR_RATE: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Ok,
+    Error,
+    Timeout,
+}
+
+#[derive(Debug, Clone)]
+struct Sample {
+    at: Instant,
+    at_ms: i64,
+    first_token: Option<Duration>,
+    total: Duration,
+    outcome: Outcome,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    Fast,
+    Slow,
+    Down,
+    /// Nothing was sent in the window.
+    Unknown,
+}
+
+/// Median and 95th percentile, in milliseconds.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Percentiles {
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderStats {
+    pub provider: String,
+    pub health: Health,
+    /// Attempts in the window, retries included.
+    pub requests: usize,
+    pub errors: usize,
+    /// Of `errors`, the ones that timed out.
+    pub timeouts: usize,
+    pub error_rate: f32,
+    pub first_token: Option<Percentiles>,
+    /// Of successful attempts.
+    pub latency: Option<Percentiles>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<i64>,
+    pub last_success_at: Option<i64>,
+}
+
+fn is_timeout(err: &Error) -> bool {
+    match err {
+        Error::Http(e) => e.is_timeout(),
+        Error::Api { status, .. } => matches!(status, 408 | 504),
+        _ => false,
+    }
+}
+
+fn percentiles(mut values: Vec<Duration>) -> Option<Percentiles> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+    let at = |q: f32| values[((values.len() - 1) as f32 * q).round() as usize].as_millis() as u64;
+    Some(Percentiles {
+        p50_ms: at(0.5),
+        p95_ms: at(0.95),
+    })
+}
+
+/// Shared by every provider's wrapper and `get_provider_stats`.
+#[derive(Default)]
+pub struct Stats(Mutex<HashMap<String, VecDeque<Sample>>>);
+
+impl Stats {
+    /// Records one attempt that started at `started`.
+    pub fn record<T>(
+        &self,
+        provider: &str,
+        started: Instant,
+        first_token: Option<Instant>,
+        result: &Result<T, Error>,
+    ) {
+        let now = Instant::now();
+        let (outcome, error) = match result {
+            Ok(_) => (Outcome::Ok, None),
+            Err(Error::Cancelled) => return,
+            Err(err) if is_timeout(err) => (Outcome::Timeout, Some(err.to_string())),
+            Err(err) => (Outcome::Error, Some(err.to_string())),
+        };
+        let mut all = self.0.lock().unwrap();
+        let samples = all.entry(provider.to_string()).or_default();
+        while samples
+            .front()
+            .is_some_and(|s| now.duration_since(s.at) > RETENTION)
+            || samples.len() >= MAX_SAMPLES
+        {
+            samples.pop_front();
+        }
+        samples.push_back(Sample {
+            at: now,
+            at_ms: now_ms(),
+            first_token: first_token.map(|t| t.duration_since(started)),
+            total: now.duration_since(started),
+            outcome,
+            error,
+        });
+    }
+
+    /// Figures for `provider` over the last `window`.
+    pub fn summary(&self, provider: &str, window: Duration) -> ProviderStats {
+        let now = Instant::now();
+        let all = self.0.lock().unwrap();
+        let samples: Vec<&Sample> = all
+            .get(provider)
+            .into_iter()
+            .flatten()
+            .filter(|s| now.duration_since(s.at) <= window)
+            .collect();
+
+        let requests = samples.len();
+        let errors = samples.iter().filter(|s| s.outcome != Outcome::Ok).count();
+        let timeouts = samples
+            .iter()
+            .filter(|s| s.outcome == Outcome::Timeout)
+            .count();
+        let error_rate = if requests == 0 {
+            0.0
+        } else {
+            errors as f32 / requests as f32
+        };
+        let ok = || samples.iter().filter(|s| s.outcome == Outcome::Ok);
+        let first_token = percentiles(ok().filter_map(|s| s.first_token).collect());
+        let latency = percentiles(ok().map(|s| s.total).collect());
+        let last_failure = samples.iter().rev().find(|s| s.outcome != Outcome::Ok);
+        let streak = samples
+            .iter()
+            .rev()
+            .take_while(|s| s.outcome != Outcome::Ok)
+            .count();
+
+        let health = if requests == 0 {
+            Health::Unknown
+        } else if (streak > 0 && streak >= DOWN_STREAK.min(requests))
+            || (requests >= DOWN_STREAK && error_rate >= DOWN_ERROR_RATE)
+        {
+            Health::Down
+        } else if first_token.is_some_and(|p| Duration::from_millis(p.p50_ms) > SLOW_FIRST_TOKEN) {
+            Health::Slow
+        } else {
+            Health::Fast
+        };
+
+        ProviderStats {
+            provider: provider.to_string(),
+            health,
+            requests,
+            errors,
+            timeouts,
+            error_rate,
+            first_token,
+            latency,
+            last_error: last_failure.and_then(|s| s.error.clone()),
+            last_error_at: last_failure.map(|s| s.at_ms),
+            last_success_at: ok().next_back().map(|s| s.at_ms),
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Retrieval-augmented generation: documents are chunked, embedded through a
+//! provider's embeddings endpoint, and stored in SQLite. Search is a brute-force
+//! cosine scan, which is plenty for a single user's history.
+
+use std::sync::Arc;
+
+use reqwest::Client;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::citations;
+use crate::error::{Error, Result};
+use crate::providers::{Provider, Providers};
+use crate::storage::{new_id, now_ms, Database};
+
+const DEFAULT_EMBEDDING_PROVIDER: &str = "openai";
+const EMBED_BATCH: usize = 64;
+const CHUNK_LEN: usize = 1500;
+const CHUNK_OVERLAP: usize = 200;
+const DEFAULT_LIMIT: usize = 8;
+const DEFAULT_CONTEXT_LEN: usize = 6000;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EmbeddingOptions {
+    pub provider: Option<String>,
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexedDocument {
+    pub document_id: String,
+    pub chunks: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoredChunk {
+    pub chunk_id: String,
+    pub document_id: String,
+    pub source: String,
+    pub title: String,
+    pub content: String,
+    /// Byte offsets of the chunk within the original document text.
+    pub start_offset: usize,
+    pub end_offset: usize,
+    pub score: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RagContext {
+    /// Ready to prepend to a prompt as a system message.
+    pub context: String,
+    pub sources: Vec<ScoredChunk>,
+}
+
+/// A slice of a document, with byte offsets into the original text.
+#[derive(Debug, Clone)]
+pub struct TextChunk {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+fn floor_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Splits `text` into chunks of at most `max_len` bytes, preferring to cut at
+/// paragraph, line, sentence, then word boundaries, with `overlap` bytes of
+/// shared context between neighbours.
+pub fn chunk_text(text: &str, max_len: usize, overlap: usize) -> Vec<TextChunk> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let mut end = floor_boundary(text, (start + max_len).min(text.len()));
+        if end < text.len() {
+            let window = &text[start..end];
+            let min_cut = window.len() / 2;
+            let cut = ["\n\n", "\n", ". ", " "].iter().find_map(|sep| {
+                window
+                    .rfind(sep)
+                    .filter(|&i| i > min_cut)
+                    .map(|i| i + sep.len())
+            });
+            if let Some(cut) = cut {
+                end = start + cut;
+            }
+        }
+        if end <= start {
+            // A single character wider than max_len; take it whole.
+            end = start + text[start..].chars().next().map_or(1, char::len_utf8);
+        }
+        let piece = text[start..end].trim();
+        if !piece.is_empty() {
+            chunks.push(TextChunk {
+                start,
+                end,
+                text: piece.to_string(),
+            });
+        }
+        if end >= text.len() {
+            break;
+        }
+        let next = floor_boundary(text, end.saturating_sub(overlap));
+        start = if next > start { next } else { end };
+    }
+    chunks
+}
+
+pub(crate) fn encode_vector(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub(crate) fn decode_vector(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+/// A provider/model pair used to embed text. Vectors from different models
+/// live in different spaces, so chunks record which one produced them.
+pub struct Embedder {
+    provider: Arc<dyn Provider>,
+    model: String,
+}
+
+impl Embedder {
+    pub fn resolve(providers: &Providers, options: &EmbeddingOptions) -> Result<Self> {
+        let provider = providers.get(
+            options
+                .provider
+                .as_deref()
+                .unwrap_or(DEFAULT_EMBEDDING_PROVIDER),
+        )?;
+        let model = match &options.model {
+            Some(model) => model.clone(),
+            None => provider
+                .embedding_model()
+                .ok_or_else(|| Error::Unsupported(format!("{} embeddings", provider.name())))?
+                .to_string(),
+        };
+        Ok(Self { provider, model })
+    }
+
+    /// Identifier stored alongside each vector.
+    pub fn key(&self) -> String {
+        format!("{}/{}", self.provider.id(), self.model)
+    }
+
+    pub async fn embed(&self, client: &Client, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut vectors = Vec::with_capacity(inputs.len());
+        for batch in inputs.chunks(EMBED_BATCH) {
+            vectors.extend(self.provider.embed(client, &self.model, batch).await?);
+        }
+        if vectors.len() != inputs.len() {
+            return Err(Error::Provider(format!(
+                "{} returned {} embeddings for {} inputs",
+                self.provider.id(),
+                vectors.len(),
+                inputs.len()
+            )));
+        }
+        Ok(vectors)
+    }
+}
+
+impl Database {
+    /// Replaces any previous index of `source` with the given chunks.
+    pub fn store_document(
+        &self,
+        source: &str,
+        title: &str,
+        model: &str,
+        chunks: &[TextChunk],
+        vectors: &[Vec<f32>],
+    ) -> Result<String> {
+        let document_id = new_id();
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM documents WHERE source = ?1", [source])?;
+        tx.execute(
+            "INSERT INTO documents (id, source, title, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![document_id, source, title, now_ms()],
+        )?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO chunks
+                     (id, document_id, ordinal, content, start_offset, end_offset, model, embedding)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for (ordinal, (chunk, vector)) in chunks.iter().zip(vectors).enumerate() {
+                insert.execute(params![
+                    new_id(),
+                    document_id,
+                    ordinal,
+                    chunk.text,
+                    chunk.start,
+                    chunk.end,
+                    model,
+                    encode_vector(vector)
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(document_id)
+    }
+
+    pub fn nearest_chunks(
+        &self,
+        model: &str,
+        query: &[f32],
+        limit: usize,
+    ) -> Result<Vec<ScoredChunk>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT c.id, c.document_id, d.source, d.title, c.content,
+                    c.start_offset, c.end_offset, c.embedding
+             FROM chunks c JOIN documents d ON d.id = c.document_id
+             WHERE c.model = ?1",
+        )?;
+        let rows = stmt.query_map([model], |row| {
+            let embedding: Vec<u8> = row.get(7)?;
+            Ok(ScoredChunk {
+                chunk_id: row.get(0)?,
+                document_id: row.get(1)?,
+                source: row.get(2)?,
+                title: row.get(3)?,
+                content: row.get(4)?,
+                start_offset: row.get(5)?,
+                end_offset: row.get(6)?,
+                score: cosine(query, &decode_vector(&embedding)),
+            })
+        })?;
+        let mut scored = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+        scored.truncate(limit);
+        Ok(scored)
+    }
+}
+
+/// Chunks, embeds and stores `text`. Shared by the command and by other
+/// subsystems that feed the index.
+pub async fn index_text(
+    db: &Database,
+    embedder: &Embedder,
+    client: &Client,
+    source: &str,
+    title: &str,
+    text: &str,
+) -> Result<IndexedDocument> {
+    let chunks = chunk_text(text, CHUNK_LEN, CHUNK_OVERLAP);
+    let inputs: Vec<String> = chunks.iter().map(|c| c.text.clone()).collect();
+    let vectors = embedder.embed(client, &inputs).await?;
+    let document_id = db.store_document(source, title, &embedder.key(), &chunks, &vectors)?;
+    Ok(IndexedDocument {
+        document_id,
+        chunks: chunks.len(),
+    })
+}
+
+pub async fn search(
+    db: &Database,
+    embedder: &Embedder,
+    client: &Client,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<ScoredChunk>> {
+    let vector = embedder
+        .embed(client, &[query.to_string()])
+        .await?
+        .pop()
+        .unwrap_or_default();
+    db.nearest_chunks(&embedder.key(), &vector, limit)
+}
+
+/// Formats retrieved chunks as excerpts numbered from `first`, stopping at
+/// `max_len` bytes.
+pub fn format_context(chunks: Vec<ScoredChunk>, max_len: usize, first: u32) -> RagContext {
+    let mut context =
+        String::from("Use the following excerpts if they are relevant. Cite them as [n].\n");
+    let mut sources = Vec::new();
+    for chunk in chunks {
+        let entry = format!(
+            "\n[{}] {} ({})\n{}\n",
+            first as usize + sources.len(),
+            chunk.title,
+            chunk.source,
+            chunk.content
+        );
+        if context.len() + entry.len() > max_len && !sources.is_empty() {
+            break;
+        }
+        context.push_str(&entry);
+        sources.push(chunk);
+    }
+    RagContext { context, sources }
+}
+
+#[tauri::command]
+pub async fn index_document(
+    db: State<'_, Database>,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    source: String,
+    title: String,
+    text: String,
+    options: Option<EmbeddingOptions>,
+) -> Result<IndexedDocument> {
+    let embedder = Embedder::resolve(&providers, &options.unwrap_or_default())?;
+    index_text(&db, &embedder, &client, &source, &title, &text).await
+}
+
+#[tauri::command]
+pub async fn semantic_search(
+    db: State<'_, Database>,
+    providers: State<'_, Providers>,
+    client: State<'_, Client>,
+    query: String,
+    limit: Option<usize>,
+    options: Option<EmbeddingOptions>,
+) -> Result<Vec<ScoredChunk>> {
+    let embedder = Embedder::resolve(&providers, &options.unwrap_or_default())?;
+    search(
+        &db,
+        &embedder,
+        &client,
+        &query,
+        limit.unwrap_or(DEFAULT_LIMIT),
+    )
+    .await
+}
+
+/// Retrieves excerpts for `query` as context. Under a `request_id` they
+/// are recorded as the request's sources, for citations, and numbered
+/// after any it already has; not for an incognito `conversation_id`.
+#[tauri::command]
+pub async fn build_context(
+    app: AppHandle,
+    query: String,
+    max_len: Option<usize>,
+    options: Option<EmbeddingOptions>,
+    request_id: Option<String>,
+    conversation_id: Option<String>,
+) -> Result<RagContext> {
+    let request_id = citations::recorded_under(request_id, conversation_id.as_deref());
+    let (db, providers, client) = (
+        app.state::<Database>(),
+        app.state::<Providers>(),
+        app.state::<Client>(),
+    );
+    let embedder = Embedder::resolve(&providers, &options.unwrap_or_default())?;
+    let chunks = search(&db, &embedder, &client, &query, DEFAULT_LIMIT).await?;
+    let first = match &request_id {
+        Some(request_id) => db.next_source_number(request_id)?,
+        None => 1,
+    };
+    let context = format_context(chunks, max_len.unwrap_or(DEFAULT_CONTEXT_LEN), first);
+    if let Some(request_id) = &request_id {
+        db.record_chunks(request_id, first, &context.sources)?;
+    }
+    Ok(context)
+}
@@ -0,0 +1,451 @@
+//! Spoken conversations with the realtime APIs, OpenAI Realtime and Gemini
+//! Live. The backend holds the WebSocket: the frontend records the
+//! microphone and hands over PCM chunks with `send_audio_chunk`, and what
+//! comes back (the model's voice, transcripts of both sides, turn
+//! boundaries) arrives as `realtime-event`s. Both APIs detect the end of a
+//! turn themselves, so there is nothing to press between sentences.
+
+mod socket;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::audio;
+use crate::error::{Error, Result};
+use crate::network;
+use crate::providers::api_key;
+use crate::storage::new_id;
+use socket::{Message, Reader, Writer};
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+const GEMINI_URL: &str = "https://generativelanguage.googleapis.com/ws/google.ai.generativelanguage.v1beta.GenerativeService.BidiGenerateContent";
+/// Both APIs speak back at this rate.
+const OUTPUT_RATE: u32 = 24_000;
+/// How long a hang-up waits for the server to close its side.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dialect {
+    OpenAi,
+    Gemini,
+}
+
+impl Dialect {
+    fn for_provider(provider: &str) -> Result<Self> {
+        match provider {
+            "openai" => Ok(Self::OpenAi),
+            "google" => Ok(Self::Gemini),
+            other => Err(Error::Unsupported(format!("realtime voice with {other}"))),
+        }
+    }
+
+    fn provider(self) -> &'static str {
+        match self {
+            Self::OpenAi => "openai",
+            Self::Gemini => "google",
+        }
+    }
+
+    fn default_model(self) -> &'static str {
+        match self {
+            Self::OpenAi => "gpt-4o-realtime-preview",
+            Self::Gemini => "gemini-2.0-flash-live-001",
+        }
+    }
+
+    fn default_voice(self) -> &'static str {
+        match self {
+            Self::OpenAi => "alloy",
+            Self::Gemini => "Puck",
+        }
+    }
+
+    /// The rate microphone audio is sent at.
+    fn input_rate(self) -> u32 {
+        match self {
+            Self::OpenAi => 24_000,
+            Self::Gemini => 16_000,
+        }
+    }
+
+    fn connection(self, model: &str) -> Result<(String, Vec<(&'static str, String)>)> {
+        Ok(match self {
+            Self::OpenAi => {
+                let key = api_key("OPENAI_API_KEY", "openai")?;
+                let url = network::endpoint("openai", OPENAI_BASE_URL, "/realtime");
+                let separator = if url.contains('?') { '&' } else { '?' };
+                (
+                    format!("{url}{separator}model={model}"),
+                    vec![
+                        ("Authorization", format!("Bearer {key}")),
+                        ("OpenAI-Beta", "realtime=v1".to_string()),
+                    ],
+                )
+            }
+            Self::Gemini => {
+                let key = api_key("GEMINI_API_KEY", "google")?;
+                (GEMINI_URL.to_string(), vec![("x-goog-api-key", key)])
+            }
+        })
+    }
+
+    /// The first message, which configures the session.
+    fn setup(self, model: &str, voice: &str, instructions: Option<&str>) -> Value {
+        match self {
+            Self::OpenAi => json!({
+                "type": "session.update",
+                "session": {
+                    "modalities": ["text", "audio"],
+                    "instructions": instructions.unwrap_or_default(),
+                    "voice": voice,
+                    "input_audio_format": "pcm16",
+                    "output_audio_format": "pcm16",
+                    "input_audio_transcription": { "model": "whisper-1" },
+                    "turn_detection": { "type": "server_vad" },
+                },
+            }),
+            Self::Gemini => {
+                let mut setup = json!({
+                    "model": format!("models/{model}"),
+                    "generationConfig": {
+                        "responseModalities": ["AUDIO"],
+                        "speechConfig": {
+                            "voiceConfig": { "prebuiltVoiceConfig": { "voiceName": voice } },
+                        },
+                    },
+                    "inputAudioTranscription": {},
+                    "outputAudioTranscription": {},
+                });
+                if let Some(instructions) = instructions {
+                    setup["systemInstruction"] = json!({ "parts": [{ "text": instructions }] });
+                }
+                json!({ "setup": setup })
+            }
+        }
+    }
+
+    /// The message carrying one chunk of microphone audio, base64 PCM16.
+    fn audio(self, data: String) -> Value {
+        match self {
+            Self::OpenAi => json!({ "type": "input_audio_buffer.append", "audio": data }),
+            Self::Gemini => json!({
+                "realtimeInput": {
+                    "audio": {
+                        "data": data,
+                        "mimeType": format!("audio/pcm;rate={}", self.input_rate()),
+                    },
+                },
+            }),
+        }
+    }
+
+    /// What a message from the server means for the frontend.
+    fn events(self, message: &Value) -> Vec<RealtimeDelta> {
+        let text = |value: &Value| value.as_str().unwrap_or_default().to_string();
+        let mut events = Vec::new();
+        match self {
+            Self::OpenAi => match message["type"].as_str().unwrap_or_default() {
+                "response.audio.delta" => events.push(RealtimeDelta::Audio {
+                    data: text(&message["delta"]),
+                    sample_rate: OUTPUT_RATE,
+                }),
+                "response.audio_transcript.delta" | "response.text.delta" => {
+                    events.push(RealtimeDelta::Transcript {
+                        role: Speaker::Assistant,
+                        text: text(&message["delta"]),
+                    })
+                }
+                "conversation.item.input_audio_transcription.completed" => {
+                    events.push(RealtimeDelta::Transcript {
+                        role: Speaker::User,
+                        text: text(&message["transcript"]),
+                    })
+                }
+                "input_audio_buffer.speech_started" => events.push(RealtimeDelta::SpeechStarted),
+                "response.done" => events.push(RealtimeDelta::TurnDone),
+                "error" => events.push(RealtimeDelta::Error {
+                    message: text(&message["error"]["message"]),
+                }),
+                _ => {}
+            },
+            Self::Gemini => {
+                let content = &message["serverContent"];
+                if let Some(parts) = content["modelTurn"]["parts"].as_array() {
+                    for part in parts {
+                        if let Some(data) = part["inlineData"]["data"].as_str() {
+                            events.push(RealtimeDelta::Audio {
+                                data: data.to_string(),
+                                sample_rate: OUTPUT_RATE,
+                            });
+                        }
+                    }
+                }
+                for (field, role) in [
+                    ("inputTranscription", Speaker::User),
+                    ("outputTranscription", Speaker::Assistant),
+                ] {
+                    if let Some(said) = content[field]["text"].as_str() {
+                        events.push(RealtimeDelta::Transcript {
+                            role,
+                            text: said.to_string(),
+                        });
+                    }
+                }
+                if content["interrupted"].as_bool() == Some(true) {
+                    events.push(RealtimeDelta::SpeechStarted);
+                }
+                if content["turnComplete"].as_bool() == Some(true) {
+                    events.push(RealtimeDelta::TurnDone);
+                }
+            }
+        }
+        events.retain(|event| match event {
+            RealtimeDelta::Audio { data, .. } => !data.is_empty(),
+            RealtimeDelta::Transcript { text, .. } => !text.is_empty(),
+            _ => true,
+        });
+        events
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RealtimeOptions {
+    /// `openai` or `google`; defaults to OpenAI.
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    /// One of the provider's built-in voices.
+    pub voice: Option<String>,
+    pub instructions: Option<String>,
+}
+
+/// What `start_realtime_session` returns.
+#[derive(Debug, Clone, Serialize)]
+pub struct RealtimeSession {
+    pub session_id: String,
+    pub provider: &'static str,
+    pub model: String,
+    /// The rate `send_audio_chunk` expects unless told otherwise.
+    pub input_sample_rate: u32,
+    pub output_sample_rate: u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Speaker {
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RealtimeDelta {
+    /// Base64 16-bit little-endian mono PCM of the model speaking.
+    Audio {
+        data: String,
+        sample_rate: u32,
+    },
+    /// More of what was said: the user's words once a turn of theirs is
+    /// transcribed, the model's as it speaks them.
+    Transcript {
+        role: Speaker,
+        text: String,
+    },
+    /// The user started talking; any of the model's audio still queued
+    /// should stop playing.
+    SpeechStarted,
+    /// The model finished its turn.
+    TurnDone,
+    Error {
+        message: String,
+    },
+    /// The session is over, from either end.
+    Closed {
+        reason: Option<String>,
+    },
+}
+
+/// Payload of the `realtime-event` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct RealtimeEvent {
+    pub session_id: String,
+    #[serde(flatten)]
+    pub delta: RealtimeDelta,
+}
+
+struct Session {
+    dialect: Dialect,
+    writer: Arc<tokio::sync::Mutex<Writer>>,
+    reader: JoinHandle<()>,
+}
+
+/// Open realtime sessions by id. Managed as Tauri state.
+#[derive(Default)]
+pub struct Sessions(Mutex<HashMap<String, Session>>);
+
+fn emit(app: &AppHandle, session_id: &str, delta: RealtimeDelta) {
+    let _ = app.emit(
+        "realtime-event",
+        RealtimeEvent {
+            session_id: session_id.to_string(),
+            delta,
+        },
+    );
+}
+
+/// Relays what the server sends until the connection ends.
+async fn read_loop(
+    app: AppHandle,
+    session_id: String,
+    dialect: Dialect,
+    mut reader: Reader,
+    writer: Arc<tokio::sync::Mutex<Writer>>,
+) {
+    let reason = loop {
+        let payload = match reader.next().await {
+            Ok(Message::Text(text)) => text,
+            // Gemini sends its JSON as binary frames.
+            Ok(Message::Binary(bytes)) => String::from_utf8_lossy(&bytes).into_owned(),
+            Ok(Message::Ping(payload)) => {
+                let _ = writer.lock().await.pong(&payload).await;
+                continue;
+            }
+            Ok(Message::Close(reason)) => break reason,
+            Err(err) => break Some(err.to_string()),
+        };
+        let Ok(message) = serde_json::from_str::<Value>(&payload) else {
+            continue;
+        };
+        for delta in dialect.events(&message) {
+            emit(&app, &session_id, delta);
+        }
+    };
+    app.state::<Sessions>()
+        .0
+        .lock()
+        .unwrap()
+        .remove(&session_id);
+    emit(&app, &session_id, RealtimeDelta::Closed { reason });
+}
+
+/// Connects to a realtime API and returns the session to send audio to.
+/// Events for it come as `realtime-event`s until it is ended.
+#[tauri::command]
+pub async fn start_realtime_session(
+    app: AppHandle,
+    sessions: State<'_, Sessions>,
+    options: Option<RealtimeOptions>,
+) -> Result<RealtimeSession> {
+    let options = options.unwrap_or_default();
+    let dialect = Dialect::for_provider(options.provider.as_deref().unwrap_or("openai"))?;
+    let model = options
+        .model
+        .unwrap_or_else(|| dialect.default_model().to_string());
+    let voice = options
+        .voice
+        .unwrap_or_else(|| dialect.default_voice().to_string());
+    let (url, headers) = dialect.connection(&model)?;
+    let client = network::upgrade_client(&app)?;
+    let (reader, mut writer) = socket::connect(&client, &url, &headers).await?;
+    let setup = dialect.setup(&model, &voice, options.instructions.as_deref());
+    writer.text(&setup.to_string()).await?;
+
+    let session_id = new_id();
+    let writer = Arc::new(tokio::sync::Mutex::new(writer));
+    let reader = tauri::async_runtime::spawn(read_loop(
+        app.clone(),
+        session_id.clone(),
+        dialect,
+        reader,
+        writer.clone(),
+    ));
+    sessions.0.lock().unwrap().insert(
+        session_id.clone(),
+        Session {
+            dialect,
+            writer,
+            reader,
+        },
+    );
+    Ok(RealtimeSession {
+        session_id,
+        provider: dialect.provider(),
+        model,
+        input_sample_rate: dialect.input_rate(),
+        output_sample_rate: OUTPUT_RATE,
+    })
+}
+
+/// Sends microphone audio: base64 16-bit little-endian mono PCM, at
+/// `sample_rate` if given, otherwise at the session's input rate.
+#[tauri::command]
+pub async fn send_audio_chunk(
+    sessions: State<'_, Sessions>,
+    session_id: String,
+    audio: String,
+    sample_rate: Option<u32>,
+) -> Result<()> {
+    let (dialect, writer) = {
+        let sessions = sessions.0.lock().unwrap();
+        let session = sessions
+            .get(&session_id)
+            .ok_or_else(|| Error::NotFound(format!("realtime session {session_id}")))?;
+        (session.dialect, session.writer.clone())
+    };
+    let target = dialect.input_rate();
+    let audio = match sample_rate {
+        Some(rate) if rate != target && rate > 0 => {
+            let pcm = STANDARD
+                .decode(&audio)
+                .map_err(|err| Error::Realtime(format!("invalid audio: {err}")))?;
+            let samples: Vec<f32> = pcm
+                .chunks_exact(2)
+                .map(|s| f32::from(i16::from_le_bytes([s[0], s[1]])) / f32::from(i16::MAX))
+                .collect();
+            let resampled: Vec<u8> = audio::resample(&samples, rate, target)
+                .into_iter()
+                .flat_map(|s| ((s.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16).to_le_bytes())
+                .collect();
+            STANDARD.encode(resampled)
+        }
+        _ => audio,
+    };
+    let message = dialect.audio(audio).to_string();
+    let sent = writer.lock().await.text(&message).await;
+    sent
+}
+
+/// Hangs up. The last event of the session is `closed`.
+#[tauri::command]
+pub async fn end_session(
+    app: AppHandle,
+    sessions: State<'_, Sessions>,
+    session_id: String,
+) -> Result<()> {
+    let session = sessions
+        .0
+        .lock()
+        .unwrap()
+        .remove(&session_id)
+        .ok_or_else(|| Error::NotFound(format!("realtime session {session_id}")))?;
+    let closed = session.writer.lock().await.close().await;
+    // The server answers a close with one of its own, which ends the read
+    // loop; don't wait forever for it.
+    let mut reader = session.reader;
+    if tokio::time::timeout(CLOSE_TIMEOUT, &mut reader)
+        .await
+        .is_err()
+    {
+        reader.abort();
+        emit(&app, &session_id, RealtimeDelta::Closed { reason: None });
+    }
+    closed
+}
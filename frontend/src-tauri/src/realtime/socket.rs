@@ -0,0 +1,198 @@
+//! Just enough of a WebSocket client (RFC 6455) for the realtime APIs: the
+//! handshake goes through reqwest, so proxies and extra CA certificates
+//! apply as they do to every other request, and the upgraded connection is
+//! framed here. No extensions, no subprotocols.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::header::{CONNECTION, UPGRADE};
+use reqwest::{Client, StatusCode, Upgraded};
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+
+use crate::error::{Error, Result};
+
+/// Appended to the key to make the accept value, per the RFC.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Larger messages are refused rather than buffered.
+const MAX_MESSAGE: u64 = 16 * 1024 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+pub(super) enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    /// The peer is closing, with the reason it gave.
+    Close(Option<String>),
+}
+
+pub(super) struct Reader(ReadHalf<Upgraded>);
+pub(super) struct Writer(WriteHalf<Upgraded>);
+
+fn socket_error(err: impl std::fmt::Display) -> Error {
+    Error::Realtime(err.to_string())
+}
+
+fn random<const N: usize>() -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| socket_error("no randomness available"))?;
+    Ok(bytes)
+}
+
+/// Opens a WebSocket to `url` (given as `https://`), sending `headers` with
+/// the handshake. `client` must be limited to HTTP/1.1.
+pub(super) async fn connect(
+    client: &Client,
+    url: &str,
+    headers: &[(&str, String)],
+) -> Result<(Reader, Writer)> {
+    let key = STANDARD.encode(random::<16>()?);
+    let mut request = client
+        .get(url)
+        .header(CONNECTION, "Upgrade")
+        .header(UPGRADE, "websocket")
+        .header("Sec-WebSocket-Version", "13")
+        .header("Sec-WebSocket-Key", &key);
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    let response = request.send().await?;
+    if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(socket_error(format!(
+            "the server answered {status}: {body}"
+        )));
+    }
+    let expected = STANDARD.encode(digest(
+        &SHA1_FOR_LEGACY_USE_ONLY,
+        format!("{key}{ACCEPT_GUID}").as_bytes(),
+    ));
+    let accepted = response
+        .headers()
+        .get("Sec-WebSocket-Accept")
+        .and_then(|v| v.to_str().ok());
+    if accepted != Some(expected.as_str()) {
+        return Err(socket_error("the server didn't accept the WebSocket"));
+    }
+    let (read, write) = tokio::io::split(response.upgrade().await?);
+    Ok((Reader(read), Writer(write)))
+}
+
+impl Reader {
+    /// One frame's opcode, FIN bit and payload.
+    async fn frame(&mut self) -> Result<(u8, bool, Vec<u8>)> {
+        let mut head = [0; 2];
+        self.0.read_exact(&mut head).await?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        let masked = head[1] & 0x80 != 0;
+        let len = match head[1] & 0x7F {
+            126 => u64::from(self.0.read_u16().await?),
+            127 => self.0.read_u64().await?,
+            len => u64::from(len),
+        };
+        if len > MAX_MESSAGE {
+            return Err(socket_error("message too large"));
+        }
+        let mut mask = [0; 4];
+        if masked {
+            self.0.read_exact(&mut mask).await?;
+        }
+        let mut payload = vec![0; len as usize];
+        self.0.read_exact(&mut payload).await?;
+        if masked {
+            payload
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, b)| *b ^= mask[i % 4]);
+        }
+        Ok((opcode, fin, payload))
+    }
+
+    /// The next message, with fragments put back together. Pongs are
+    /// skipped; pings are handed back to be answered.
+    pub(super) async fn next(&mut self) -> Result<Message> {
+        let mut message: Option<(u8, Vec<u8>)> = None;
+        loop {
+            let (opcode, fin, payload) = self.frame().await?;
+            match opcode {
+                OP_PING => return Ok(Message::Ping(payload)),
+                OP_PONG => continue,
+                OP_CLOSE => {
+                    let reason = payload
+                        .get(2..)
+                        .map(|r| String::from_utf8_lossy(r).into_owned())
+                        .filter(|r| !r.is_empty());
+                    return Ok(Message::Close(reason));
+                }
+                OP_TEXT | OP_BINARY if message.is_none() => message = Some((opcode, payload)),
+                OP_CONTINUATION if message.is_some() => {
+                    let (_, data) = message.as_mut().unwrap();
+                    if (data.len() + payload.len()) as u64 > MAX_MESSAGE {
+                        return Err(socket_error("message too large"));
+                    }
+                    data.extend_from_slice(&payload);
+                }
+                _ => return Err(socket_error(format!("unexpected frame {opcode:#x}"))),
+            }
+            if fin {
+                let (opcode, data) = message.take().unwrap();
+                return Ok(if opcode == OP_TEXT {
+                    Message::Text(String::from_utf8(data).map_err(socket_error)?)
+                } else {
+                    Message::Binary(data)
+                });
+            }
+        }
+    }
+}
+
+impl Writer {
+    async fn send(&mut self, opcode: u8, payload: &[u8]) -> Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(0x80 | opcode);
+        // Everything a client sends is masked.
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        let mask = random::<4>()?;
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        self.0.write_all(&frame).await?;
+        self.0.flush().await?;
+        Ok(())
+    }
+
+    pub(super) async fn text(&mut self, text: &str) -> Result<()> {
+        self.send(OP_TEXT, text.as_bytes()).await
+    }
+
+    pub(super) async fn pong(&mut self, payload: &[u8]) -> Result<()> {
+        self.send(OP_PONG, payload).await
+    }
+
+    /// Says goodbye with a normal closure.
+    pub(super) async fn close(&mut self) -> Result<()> {
+        self.send(OP_CLOSE, &1000u16.to_be_bytes()).await?;
+        self.0.shutdown().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+//! Moves the active profile's data directory, to an external drive or a
+//! synced folder say. The database is snapshotted and every other file
+//! copied over, each one read back and checked against the SHA-256 of what
+//! was sent. Only then does the profile point at the new directory, in one
+//! write of the profile list, and the app restarts into it. The database
+//! is held from the snapshot on, and closed once the copy is done, so
+//! nothing written in between is left behind.
+//!
+//! The old directory is left as it was, for the user to remove once the
+//! new one is in use. If the new one is missing at a later launch, the
+//! app falls back to the default location and says so.
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::backup::{self, copy_hashed};
+use crate::error::{Error, Result};
+use crate::profile;
+use crate::storage::{Database, DB_FILE};
+
+/// The database snapshot, written next to the live file before it is
+/// copied over.
+const SNAPSHOT_FILE: &str = "relocating.db";
+/// Staging files of restores and bundles, and the live database with its
+/// sidecars, which go over as the snapshot.
+const SKIPPED: &[&str] = &[
+    "restore",
+    "bundle-export.zip",
+    "bundle-import.zip",
+    SNAPSHOT_FILE,
+];
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DataDirectory {
+    pub path: PathBuf,
+    pub default_path: PathBuf,
+    pub is_default: bool,
+    /// The directory chosen earlier, when it couldn't be found at startup
+    /// and `path` is the default instead.
+    pub unavailable: Option<PathBuf>,
+}
+
+/// Payload of `data-directory-progress`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RelocationProgress {
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::InvalidSetting(message.into())
+}
+
+/// Files under `dir` to carry over, relative to it, with their sizes.
+fn plan(dir: &Path, shared_root: bool) -> Result<Vec<(PathBuf, u64)>> {
+    fn walk(root: &Path, dir: &Path, files: &mut Vec<(PathBuf, u64)>) -> Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let kind = entry.file_type()?;
+            if kind.is_dir() {
+                walk(root, &path, files)?;
+            } else if kind.is_file() {
+                let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+                files.push((relative, entry.metadata()?.len()));
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        // The default profile's directory holds the other profiles' too.
+        if SKIPPED.contains(&name.as_ref())
+            || name.starts_with(DB_FILE)
+            || (shared_root && name == profile::PROFILES_DIR)
+        {
+            continue;
+        }
+        let path = entry.path();
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            walk(dir, &path, &mut files)?;
+        } else if kind.is_file() {
+            files.push((PathBuf::from(name.as_ref()), entry.metadata()?.len()));
+        }
+    }
+    Ok(files)
+}
+
+/// Copies `from` to `to` and reads the copy back to check it.
+fn copy_verified(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut dest = File::create(to)?;
+    let (_, sent) = copy_hashed(File::open(from)?, &mut dest)?;
+    dest.sync_all()?;
+    drop(dest);
+    let (_, written) = copy_hashed(File::open(to)?, io::sink())?;
+    if sent != written {
+        return Err(Error::Io(io::Error::other(format!(
+            "{} didn't copy intact",
+            to.display()
+        ))));
+    }
+    Ok(())
+}
+
+fn is_empty_dir(dir: &Path) -> Result<bool> {
+    match std::fs::read_dir(dir) {
+        Ok(mut entries) => Ok(entries.next().is_none()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Checks `dest` can take the data in `source`.
+fn check_destination(source: &Path, dest: &Path, default: &Path) -> Result<()> {
+    if !dest.is_absolute() {
+        return Err(invalid("the data directory must be an absolute path"));
+    }
+    if dest == source {
+        return Err(invalid("the data is already there"));
+    }
+    if dest.starts_with(source) || source.starts_with(dest) {
+        return Err(invalid(
+            "the data directory can't be inside the current one, or hold it",
+        ));
+    }
+    // The default location may still hold the copy left there by an
+    // earlier move, which this one replaces.
+    if dest != default && !is_empty_dir(dest)? {
+        return Err(invalid(format!("{} isn't empty", dest.display())));
+    }
+    std::fs::create_dir_all(dest)?;
+    let probe = dest.join(".pentamind-probe");
+    std::fs::write(&probe, b"")?;
+    std::fs::remove_file(probe)?;
+    Ok(())
+}
+
+/// Copies everything to `dest` with the database held, then points the
+/// profile there. Returns with the database closed; the caller restarts.
+fn relocate(app: &AppHandle, source: &Path, dest: &Path, default: &Path) -> Result<()> {
+    check_destination(source, dest, default)?;
+    let db = app.state::<Database>();
+    if db.is_locked() {
+        return Err(Error::Locked);
+    }
+    let shared_root =
+        profile::active() == profile::DEFAULT_ID && source == app.path().app_data_dir()?.as_path();
+    let files = plan(source, shared_root)?;
+
+    let mut conn = db.conn()?;
+    let snapshot = source.join(SNAPSHOT_FILE);
+    let _ = std::fs::remove_file(&snapshot);
+    conn.execute("VACUUM INTO ?1", [snapshot.to_string_lossy()])?;
+    let mut progress = RelocationProgress {
+        files_done: 0,
+        files_total: files.len() + 1,
+        bytes_done: 0,
+        bytes_total: files.iter().map(|(_, size)| size).sum::<u64>()
+            + std::fs::metadata(&snapshot)?.len(),
+    };
+    let mut written = Vec::with_capacity(progress.files_total);
+    let mut last_report = Instant::now();
+    let copied = std::iter::once((snapshot.clone(), PathBuf::from(DB_FILE)))
+        .chain(
+            files
+                .iter()
+                .map(|(file, _)| (source.join(file), file.clone())),
+        )
+        .try_for_each(|(from, relative)| {
+            let to = dest.join(&relative);
+            copy_verified(&from, &to)?;
+            written.push(to);
+            progress.files_done += 1;
+            progress.bytes_done += std::fs::metadata(&from)?.len();
+            if last_report.elapsed() >= PROGRESS_INTERVAL {
+                last_report = Instant::now();
+                let _ = app.emit("data-directory-progress", &progress);
+            }
+            Ok::<_, Error>(())
+        });
+    let _ = std::fs::remove_file(&snapshot);
+    let result = copied.and_then(|()| {
+        let chosen = (dest != default).then(|| dest.to_path_buf());
+        profile::set_data_dir(app, chosen)
+    });
+    if let Err(err) = result {
+        for file in written {
+            let _ = std::fs::remove_file(file);
+        }
+        return Err(err);
+    }
+    let _ = app.emit("data-directory-progress", &progress);
+    tracing::info!(
+        "moved the data directory from {} to {}",
+        source.display(),
+        dest.display()
+    );
+    // Nothing more goes into the old copy before the restart.
+    *conn = Connection::open_in_memory()?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_data_directory(app: AppHandle) -> Result<DataDirectory> {
+    let path = profile::data_dir(&app)?;
+    let default_path = profile::default_data_dir(&app)?;
+    Ok(DataDirectory {
+        is_default: path == default_path,
+        path,
+        default_path,
+        unavailable: profile::unavailable_data_dir().map(Path::to_path_buf),
+    })
+}
+
+/// Moves the data to `path`, or back to the default location without
+/// one, and restarts into it. Progress comes as `data-directory-progress`.
+#[tauri::command]
+pub async fn set_data_directory(app: AppHandle, path: Option<PathBuf>) -> Result<()> {
+    let source = profile::data_dir(&app)?;
+    let default = profile::default_data_dir(&app)?;
+    let dest = path.unwrap_or_else(|| default.clone());
+    backup::blocking(app.clone(), move |app| {
+        relocate(app, &source, &dest, &default)
+    })
+    .await?;
+    app.restart()
+}
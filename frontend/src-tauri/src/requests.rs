@@ -0,0 +1,105 @@
+//! Registry of in-flight model requests so any of them can be stopped from
+//! the backend. Cancelling drops the request future, which closes the
+//! underlying HTTP stream.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use tauri::State;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{Error, Result};
+
+#[derive(Default)]
+pub struct Requests {
+    /// Token per request id, tagged with a generation so a guard only removes
+    /// its own registration if the id was reused.
+    active: Mutex<HashMap<String, (u64, CancellationToken)>>,
+    next_generation: AtomicU64,
+}
+
+impl Requests {
+    /// Registers `id`, cancelling any earlier request that reused it. The
+    /// returned guard unregisters the id when dropped.
+    pub fn register(&self, id: &str) -> RequestGuard<'_> {
+        let token = CancellationToken::new();
+        let generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+        if let Some((_, previous)) = self
+            .active
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), (generation, token.clone()))
+        {
+            previous.cancel();
+        }
+        RequestGuard {
+            requests: self,
+            id: id.to_string(),
+            generation,
+            token,
+        }
+    }
+
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.active.lock().unwrap().get(id) {
+            Some((_, token)) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn cancel_all(&self) -> usize {
+        let requests = self.active.lock().unwrap();
+        for (_, token) in requests.values() {
+            token.cancel();
+        }
+        requests.len()
+    }
+}
+
+pub struct RequestGuard<'a> {
+    requests: &'a Requests,
+    id: String,
+    generation: u64,
+    token: CancellationToken,
+}
+
+impl RequestGuard<'_> {
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+impl Drop for RequestGuard<'_> {
+    fn drop(&mut self) {
+        let mut requests = self.requests.active.lock().unwrap();
+        if requests
+            .get(&self.id)
+            .is_some_and(|(generation, _)| *generation == self.generation)
+        {
+            requests.remove(&self.id);
+        }
+    }
+}
+
+/// Runs `future` until it completes or `token` is cancelled.
+pub async fn cancellable<T>(
+    token: &CancellationToken,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::select! {
+        result = future => result,
+        _ = token.cancelled() => Err(Error::Cancelled),
+    }
+}
+
+/// Stops a running `stream_chat`, `fanout_prompt` or `generate_image` call.
+/// Returns whether a request with that id was in flight.
+#[tauri::command]
+pub fn cancel_request(requests: State<'_, Requests>, request_id: String) -> bool {
+    requests.cancel(&request_id)
+}
@@ -0,0 +1,226 @@
+//! Keeping the history from growing without end. The policy archives
+//! conversations nobody has touched for a while and, later, deletes them;
+//! conversations with a message tagged with one of the excluded tags (or,
+//! by default, a bookmarked message) are left alone. A background task
+//! applies it every few hours while it is on, and `apply_retention_policy`
+//! runs it on demand or previews what it would do.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::bookmarks::normalize_tag;
+use crate::config;
+use crate::error::{Error, Result};
+use crate::os_search;
+use crate::startup;
+use crate::storage::{now_ms, Database};
+use crate::windows;
+
+const CONFIG_FILE: &str = "retention.json";
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+/// How often the background task applies the policy.
+const INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+/// The first run waits this long, to stay out of the way of startup.
+const FIRST_RUN_DELAY: Duration = Duration::from_secs(2 * 60);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    pub enabled: bool,
+    /// Archive conversations idle for this many days.
+    pub archive_after_days: Option<u32>,
+    /// Delete conversations idle for this many days, archived or not.
+    pub delete_after_days: Option<u32>,
+    /// Conversations with a message carrying any of these tags are kept.
+    pub excluded_tags: Vec<String>,
+    pub keep_bookmarked: bool,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            archive_after_days: Some(90),
+            delete_after_days: None,
+            excluded_tags: Vec::new(),
+            keep_bookmarked: true,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    fn check(&mut self) -> Result<()> {
+        if self.archive_after_days == Some(0) || self.delete_after_days == Some(0) {
+            return Err(Error::InvalidSetting(
+                "retention periods must be at least a day".into(),
+            ));
+        }
+        if let (Some(archive), Some(delete)) = (self.archive_after_days, self.delete_after_days) {
+            if delete < archive {
+                return Err(Error::InvalidSetting(
+                    "conversations can't be deleted before they are archived".into(),
+                ));
+            }
+        }
+        let mut tags: Vec<String> = self
+            .excluded_tags
+            .iter()
+            .filter_map(|tag| normalize_tag(tag))
+            .collect();
+        tags.sort();
+        tags.dedup();
+        self.excluded_tags = tags;
+        Ok(())
+    }
+}
+
+/// What a run of the policy did, or would do.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetentionReport {
+    pub archived: Vec<String>,
+    pub deleted: Vec<String>,
+    pub dry_run: bool,
+}
+
+/// Managed as Tauri state.
+pub struct Retention {
+    policy: Mutex<RetentionPolicy>,
+}
+
+impl Database {
+    /// Conversations last active before `before` that the policy may touch,
+    /// oldest first.
+    fn retention_candidates(
+        &self,
+        before: i64,
+        archived: Option<bool>,
+        policy: &RetentionPolicy,
+    ) -> Result<Vec<String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT c.id FROM conversations c
+             WHERE c.updated_at < ?1
+               AND (?2 IS NULL OR (c.archived_at IS NOT NULL) = ?2)
+               AND NOT EXISTS (
+                   SELECT 1 FROM messages m JOIN message_tags t ON t.message_id = m.id
+                   WHERE m.conversation_id = c.id
+                     AND t.tag IN (SELECT value FROM json_each(?3)))
+               AND NOT (?4 AND EXISTS (
+                   SELECT 1 FROM messages m JOIN bookmarks b ON b.message_id = m.id
+                   WHERE m.conversation_id = c.id))
+             ORDER BY c.updated_at",
+        )?;
+        let ids = stmt
+            .query_map(
+                params![
+                    before,
+                    archived,
+                    serde_json::to_string(&policy.excluded_tags)?,
+                    policy.keep_bookmarked
+                ],
+                |row| row.get(0),
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(ids)
+    }
+}
+
+/// Applies `policy`, or with `dry_run` only works out what it would do.
+pub(crate) fn apply(
+    app: &AppHandle,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+) -> Result<RetentionReport> {
+    let db = app.state::<Database>();
+    let now = now_ms();
+    let cutoff = |days: u32| now - i64::from(days) * DAY_MS;
+    let mut report = RetentionReport {
+        dry_run,
+        ..RetentionReport::default()
+    };
+    if let Some(days) = policy.delete_after_days {
+        report.deleted = db.retention_candidates(cutoff(days), None, policy)?;
+    }
+    if let Some(days) = policy.archive_after_days {
+        report.archived = db
+            .retention_candidates(cutoff(days), Some(false), policy)?
+            .into_iter()
+            .filter(|id| !report.deleted.contains(id))
+            .collect();
+    }
+    if dry_run {
+        return Ok(report);
+    }
+    db.archive_conversations(&report.archived)?;
+    for id in &report.deleted {
+        db.delete_conversation(id)?;
+        windows::conversation_deleted(app, id);
+        os_search::conversation_deleted(app, id);
+    }
+    if !report.archived.is_empty() || !report.deleted.is_empty() {
+        tracing::info!(
+            archived = report.archived.len(),
+            deleted = report.deleted.len(),
+            "applied the retention policy"
+        );
+        let _ = app.emit("retention-applied", &report);
+    }
+    Ok(report)
+}
+
+pub fn init(app: &AppHandle) {
+    let policy = config::read::<RetentionPolicy>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(Retention {
+        policy: Mutex::new(policy),
+    });
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(FIRST_RUN_DELAY).await;
+        startup::warmed().await;
+        loop {
+            let policy = app.state::<Retention>().policy.lock().unwrap().clone();
+            if policy.enabled && !app.state::<Database>().is_locked() {
+                if let Err(err) = apply(&app, &policy, false) {
+                    tracing::warn!("couldn't apply the retention policy: {err}");
+                }
+            }
+            tokio::time::sleep(INTERVAL).await;
+        }
+    });
+}
+
+#[tauri::command]
+pub fn get_retention_policy(retention: State<'_, Retention>) -> RetentionPolicy {
+    retention.policy.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn set_retention_policy(
+    app: AppHandle,
+    retention: State<'_, Retention>,
+    mut policy: RetentionPolicy,
+) -> Result<RetentionPolicy> {
+    policy.check()?;
+    config::write(&app, CONFIG_FILE, &policy)?;
+    *retention.policy.lock().unwrap() = policy.clone();
+    Ok(policy)
+}
+
+/// Runs the saved policy now, whether or not it is enabled. With `dry_run`
+/// nothing changes and the report lists what would.
+#[tauri::command]
+pub async fn apply_retention_policy(
+    app: AppHandle,
+    retention: State<'_, Retention>,
+    dry_run: Option<bool>,
+) -> Result<RetentionReport> {
+    let policy = retention.policy.lock().unwrap().clone();
+    apply(&app, &policy, dry_run.unwrap_or(false))
+}
@@ -0,0 +1,572 @@
+//! Routing rules: which providers a fan-out goes to, picked from what the
+//! prompt is about. Each rule names a kind of prompt ("code questions",
+//! "creative writing") and the targets for it; a fan-out that names no
+//! targets and no preset goes to those of the rule its prompt matches, and
+//! to the usual providers when none does.
+//!
+//! Prompts are classified in Rust by default, from words and shapes typical
+//! of code, maths, creative writing and research, plus each rule's own
+//! keywords. A small model can be asked to pick the rule instead. A
+//! conversation can pin a rule, or turn routing on or off for itself.
+//!
+//! The rules live in the settings, under `routing`.
+
+use std::collections::BTreeMap;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::error::{Error, Result};
+use crate::fanout::{FanoutRequest, FanoutTarget};
+use crate::llm::{ChatMessage, ChatRequest, Role};
+use crate::providers::Providers;
+use crate::settings::{self, Settings, SettingsStore};
+use crate::titling;
+use crate::usage;
+
+/// Longest stretch of a prompt the classifier model is shown, in characters.
+const CLASSIFY_EXCERPT_LEN: usize = 1500;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Code,
+    Creative,
+    Math,
+    Research,
+    #[default]
+    General,
+}
+
+/// Words and phrases that point to each category. Phrases match anywhere,
+/// single words only whole.
+const LEXICON: &[(Category, &[&str])] = &[
+    (
+        Category::Code,
+        &[
+            "code",
+            "function",
+            "bug",
+            "debug",
+            "error",
+            "exception",
+            "traceback",
+            "stack trace",
+            "compile",
+            "compiler",
+            "regex",
+            "api",
+            "script",
+            "python",
+            "rust",
+            "javascript",
+            "typescript",
+            "java",
+            "golang",
+            "sql",
+            "query",
+            "refactor",
+            "implement",
+            "library",
+            "framework",
+            "class",
+            "method",
+            "variable",
+            "unit test",
+            "dockerfile",
+            "kubernetes",
+            "git",
+            "css",
+            "html",
+            "json",
+            "yaml",
+            "bash",
+            "shell",
+            "terminal",
+            "segfault",
+            "null pointer",
+        ],
+    ),
+    (
+        Category::Creative,
+        &[
+            "poem",
+            "poetry",
+            "story",
+            "short story",
+            "lyrics",
+            "song",
+            "haiku",
+            "fiction",
+            "character",
+            "plot",
+            "novel",
+            "screenplay",
+            "creative",
+            "imagine",
+            "slogan",
+            "tagline",
+            "joke",
+            "limerick",
+            "metaphor",
+            "brainstorm",
+            "fantasy",
+            "villain",
+            "dialogue",
+            "name ideas",
+        ],
+    ),
+    (
+        Category::Math,
+        &[
+            "equation",
+            "integral",
+            "derivative",
+            "prove",
+            "proof",
+            "theorem",
+            "lemma",
+            "calculate",
+            "solve",
+            "probability",
+            "matrix",
+            "algebra",
+            "geometry",
+            "calculus",
+            "statistics",
+            "factorial",
+            "prime",
+            "polynomial",
+            "vector",
+        ],
+    ),
+    (
+        Category::Research,
+        &[
+            "explain",
+            "compare",
+            "history",
+            "summarize",
+            "summary",
+            "sources",
+            "research",
+            "analysis",
+            "analyze",
+            "pros and cons",
+            "difference between",
+            "overview",
+            "evidence",
+            "study",
+            "studies",
+            "paper",
+            "why does",
+            "how does",
+        ],
+    ),
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Classifier {
+    /// Built-in rules in Rust; free and instant.
+    #[default]
+    Keywords,
+    /// A small model picks the rule, from their names and descriptions.
+    Model,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingRule {
+    pub name: String,
+    /// What the rule is for, in words, shown to the classifier model.
+    #[serde(default)]
+    pub description: String,
+    /// Prompts classified as this match the rule.
+    #[serde(default)]
+    pub category: Option<Category>,
+    /// Words or phrases that match the rule as well.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    pub targets: Vec<FanoutTarget>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConversationRoute {
+    /// Always use this rule in the conversation, without classifying.
+    pub rule: Option<String>,
+    /// Overrides `enabled` for the conversation.
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutingSettings {
+    pub enabled: bool,
+    pub classifier: Classifier,
+    /// For the model classifier; falls back to the first configured of a
+    /// few cheap models.
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    /// Tried in order; the first of equally good matches wins.
+    pub rules: Vec<RoutingRule>,
+    pub conversations: BTreeMap<String, ConversationRoute>,
+}
+
+impl RoutingSettings {
+    pub(crate) fn check(&self) -> Result<()> {
+        for (index, rule) in self.rules.iter().enumerate() {
+            if rule.name.trim().is_empty() {
+                return Err(Error::InvalidSetting(format!(
+                    "routing rule {} has no name",
+                    index + 1
+                )));
+            }
+            if self.rules[..index]
+                .iter()
+                .any(|r| r.name.eq_ignore_ascii_case(&rule.name))
+            {
+                return Err(Error::InvalidSetting(format!(
+                    "there are two routing rules named {}",
+                    rule.name
+                )));
+            }
+            if rule.targets.is_empty() {
+                return Err(Error::InvalidSetting(format!(
+                    "routing rule {} has no targets",
+                    rule.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn rule(&self, name: &str) -> Option<&RoutingRule> {
+        self.rules
+            .iter()
+            .find(|r| r.name.eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteMethod {
+    Keywords,
+    Model,
+    /// The conversation pins the rule.
+    Pinned,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Route {
+    /// `None` when no rule matched, and the fan-out goes to the usual
+    /// providers.
+    pub rule: Option<String>,
+    pub category: Category,
+    pub method: RouteMethod,
+    /// The rule's targets that are set up, or empty without a rule.
+    pub targets: Vec<FanoutTarget>,
+}
+
+/// Payload of the `fanout-routed` event.
+#[derive(Debug, Clone, Serialize)]
+struct FanoutRouted {
+    request_id: String,
+    #[serde(flatten)]
+    route: Route,
+}
+
+fn hits(text: &str, words: &[String], terms: &[&str]) -> usize {
+    terms
+        .iter()
+        .filter(|term| {
+            if term.contains(' ') {
+                text.contains(*term)
+            } else {
+                words.iter().any(|w| w == *term)
+            }
+        })
+        .count()
+}
+
+/// Lowercase words of `text`, keeping `+` and `#` for C++ and C#.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '+' || c == '#'))
+        .filter(|w| !w.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Scores of each category for `prompt`, from the lexicon and a few shapes:
+/// code fences and code-like punctuation, and arithmetic.
+fn category_scores(prompt: &str) -> Vec<(Category, usize)> {
+    let text = prompt.to_lowercase();
+    let words = words(&text);
+    LEXICON
+        .iter()
+        .map(|(category, terms)| {
+            let mut score = hits(&text, &words, terms);
+            match category {
+                Category::Code => {
+                    score += 3 * text.matches("```").count().min(2);
+                    score += ["();", "=>", "::", "#include", "fn ", "def ", "</"]
+                        .iter()
+                        .filter(|shape| text.contains(*shape))
+                        .count();
+                }
+                Category::Math => {
+                    score += prompt
+                        .as_bytes()
+                        .windows(3)
+                        .filter(|w| {
+                            w[0].is_ascii_digit()
+                                && b"+-*/^=".contains(&w[1])
+                                && w[2].is_ascii_digit()
+                        })
+                        .count()
+                        .min(3);
+                }
+                _ => {}
+            }
+            (*category, score)
+        })
+        .collect()
+}
+
+/// The category `prompt` most looks like; general when nothing stands out.
+pub fn classify_category(prompt: &str) -> Category {
+    category_scores(prompt)
+        .into_iter()
+        .filter(|(_, score)| *score > 0)
+        .max_by_key(|(_, score)| *score)
+        .map_or(Category::General, |(category, _)| category)
+}
+
+/// The best rule for `prompt` by category and keywords, if any matches.
+fn match_keywords<'a>(
+    rules: &'a [RoutingRule],
+    prompt: &str,
+    category: Category,
+) -> Option<&'a RoutingRule> {
+    let text = prompt.to_lowercase();
+    let words = words(&text);
+    let scores = category_scores(prompt);
+    let mut best: Option<(&RoutingRule, usize)> = None;
+    for rule in rules {
+        let keywords: Vec<String> = rule.keywords.iter().map(|k| k.to_lowercase()).collect();
+        let keywords: Vec<&str> = keywords.iter().map(|k| k.trim()).collect();
+        let mut score = 2 * hits(&text, &words, &keywords);
+        if rule.category == Some(category) && category != Category::General {
+            score += scores
+                .iter()
+                .find(|(c, _)| *c == category)
+                .map_or(0, |(_, s)| *s);
+        }
+        if score > 0 && best.is_none_or(|(_, top)| score > top) {
+            best = Some((rule, score));
+        }
+    }
+    best.map(|(rule, _)| rule)
+}
+
+fn excerpt(text: &str) -> &str {
+    let text = text.trim();
+    match text.char_indices().nth(CLASSIFY_EXCERPT_LEN) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+/// Asks a small model which rule fits `prompt`.
+async fn match_model<'a>(
+    app: &AppHandle,
+    routing: &'a RoutingSettings,
+    prompt: &str,
+) -> Result<Option<&'a RoutingRule>> {
+    let (provider, model) =
+        titling::pick_model(app, routing.provider.as_deref(), routing.model.as_deref())?;
+    let mut question = String::from(
+        "Which of these categories does the prompt below belong to? \
+         Reply with the category name only, or none if no category fits.\n\n",
+    );
+    for rule in &routing.rules {
+        question.push_str(&format!("- {}", rule.name));
+        if !rule.description.trim().is_empty() {
+            question.push_str(&format!(": {}", rule.description.trim()));
+        }
+        question.push('\n');
+    }
+    question.push_str(&format!("\n## Prompt\n{}", excerpt(prompt)));
+    let request = ChatRequest {
+        provider: provider.id().to_string(),
+        model,
+        messages: vec![ChatMessage {
+            role: Role::User,
+            content: question,
+        }],
+        temperature: Some(0.0),
+        top_p: None,
+        max_tokens: Some(20),
+        use_tools: false,
+        bypass_cache: false,
+        response_schema: None,
+        conversation_id: None,
+        tools: Vec::new(),
+        tool_rounds: Vec::new(),
+    };
+    let client = app.state::<Client>();
+    let completion = provider.stream(&client, &request, &mut |_| {}).await?;
+    usage::record(app, &request, &completion.content, completion.usage);
+    let reply = completion
+        .content
+        .lines()
+        .find(|l| !l.trim().is_empty())
+        .unwrap_or_default()
+        .trim()
+        .trim_start_matches("- ")
+        .trim_matches(|c| matches!(c, '"' | '\'' | '*' | '`' | '.'));
+    Ok(routing.rule(reply))
+}
+
+/// The targets of `rule` whose providers are set up.
+fn usable_targets(app: &AppHandle, rule: &RoutingRule) -> Vec<FanoutTarget> {
+    let providers = app.state::<Providers>();
+    rule.targets
+        .iter()
+        .filter(|t| {
+            providers
+                .get(&t.provider)
+                .is_ok_and(|provider| provider.configured())
+        })
+        .cloned()
+        .collect()
+}
+
+/// Picks the rule for `prompt` in `conversation_id`, whether or not routing
+/// is turned on.
+pub(crate) async fn route(
+    app: &AppHandle,
+    routing: &RoutingSettings,
+    conversation_id: Option<&str>,
+    prompt: &str,
+) -> Route {
+    let category = classify_category(prompt);
+    let pinned = conversation_id
+        .and_then(|id| routing.conversations.get(id))
+        .and_then(|c| c.rule.as_deref())
+        .and_then(|name| routing.rule(name));
+    let (rule, method) = match (pinned, routing.classifier) {
+        (Some(rule), _) => (Some(rule), RouteMethod::Pinned),
+        (None, Classifier::Model) if !routing.rules.is_empty() => {
+            match match_model(app, routing, prompt).await {
+                Ok(rule) => (rule, RouteMethod::Model),
+                Err(err) => {
+                    tracing::warn!("couldn't classify with a model, using keywords: {err}");
+                    (
+                        match_keywords(&routing.rules, prompt, category),
+                        RouteMethod::Keywords,
+                    )
+                }
+            }
+        }
+        (None, _) => (
+            match_keywords(&routing.rules, prompt, category),
+            RouteMethod::Keywords,
+        ),
+    };
+    Route {
+        rule: rule.map(|r| r.name.clone()),
+        category,
+        method,
+        targets: rule.map(|r| usable_targets(app, r)).unwrap_or_default(),
+    }
+}
+
+fn last_prompt(request: &FanoutRequest) -> Option<&str> {
+    request
+        .messages
+        .iter()
+        .rev()
+        .find(|m| m.role == Role::User)
+        .map(|m| m.content.as_str())
+}
+
+/// Sends the fan-out to the targets of the rule its prompt matches, when
+/// routing is on for the conversation and the request doesn't name its
+/// targets or a preset.
+pub(crate) async fn apply(app: &AppHandle, request_id: &str, request: &mut FanoutRequest) {
+    if !request.targets.is_empty() || request.preset_id.is_some() {
+        return;
+    }
+    let Some(store) = app.try_state::<SettingsStore>() else {
+        return;
+    };
+    let routing = store.get().routing;
+    let conversation = request
+        .conversation_id
+        .as_deref()
+        .and_then(|id| routing.conversations.get(id));
+    let enabled = conversation
+        .and_then(|c| c.enabled)
+        .unwrap_or(routing.enabled)
+        || conversation.is_some_and(|c| c.rule.is_some());
+    if !enabled || routing.rules.is_empty() {
+        return;
+    }
+    let Some(prompt) = last_prompt(request) else {
+        return;
+    };
+    let route = route(app, &routing, request.conversation_id.as_deref(), prompt).await;
+    if route.targets.is_empty() {
+        return;
+    }
+    request.targets = route.targets.clone();
+    let _ = app.emit(
+        "fanout-routed",
+        FanoutRouted {
+            request_id: request_id.to_string(),
+            route,
+        },
+    );
+}
+
+/// Classifies `prompt` and says where a fan-out of it would go, without
+/// sending it.
+#[tauri::command]
+pub async fn classify_and_route(
+    app: AppHandle,
+    store: State<'_, SettingsStore>,
+    prompt: String,
+    conversation_id: Option<String>,
+) -> Result<Route> {
+    let routing = store.get().routing;
+    Ok(route(&app, &routing, conversation_id.as_deref(), &prompt).await)
+}
+
+/// Pins a rule for a conversation or turns routing on or off there; `None`
+/// goes back to the defaults.
+#[tauri::command]
+pub fn set_conversation_route(
+    app: AppHandle,
+    conversation_id: String,
+    route: Option<ConversationRoute>,
+) -> Result<Settings> {
+    settings::modify(&app, |settings| {
+        let routing = &mut settings.routing;
+        match route.filter(|r| *r != ConversationRoute::default()) {
+            Some(route) => {
+                if let Some(rule) = &route.rule {
+                    if routing.rule(rule).is_none() {
+                        return Err(Error::NotFound(format!("routing rule {rule}")));
+                    }
+                }
+                routing.conversations.insert(conversation_id, route);
+            }
+            None => {
+                routing.conversations.remove(&conversation_id);
+            }
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,756 @@
+//! Recurring prompts. Each schedule is a prompt, the providers to fan it
+//! out to and a cron expression in local time; a background task sleeps
+//! until the next one is due, so runs happen with the window hidden. Every
+//! run is kept in SQLite with its answers, announced as
+//! `scheduled-run-finished` and, with notifications on, shown as one.
+//!
+//! A schedule that came due while the app was closed runs once at startup
+//! rather than once per missed slot.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Local, NaiveDate, TimeZone, Timelike};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, OptionalExtension, Row, ToSql};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::Notify;
+
+use crate::error::{Error, Result};
+use crate::fanout::{self, FanoutRequest, FanoutResult, FanoutTarget};
+use crate::llm::{ChatMessage, Role};
+use crate::notifications::{self, Notification, NotificationAction};
+use crate::providers::priority::{self, Priority};
+use crate::startup;
+use crate::storage::{new_id, now_ms, Database};
+
+/// Longest sleep between checks, so a changed clock or a resumed laptop is
+/// noticed soon.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+/// Runs kept per schedule; older ones are deleted.
+const MAX_RUNS: usize = 100;
+const PREVIEW_LEN: usize = 140;
+
+/// A parsed five-field cron expression: minute, hour, day of month, month
+/// and day of week, each a bit set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cron {
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    /// The day fields started with `*`, as in `*` or `*/2`. When neither
+    /// does, cron matches a day that satisfies either; otherwise both.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+fn invalid_cron(expression: &str, why: &str) -> Error {
+    Error::InvalidSetting(format!("cron expression `{expression}`: {why}"))
+}
+
+fn parse_value(value: &str, min: u32, names: &[&str]) -> Option<u32> {
+    value.parse().ok().or_else(|| {
+        let lower = value.to_ascii_lowercase();
+        names
+            .iter()
+            .position(|name| *name == lower)
+            .map(|i| i as u32 + min)
+    })
+}
+
+/// Parses one field into a bit set over `min..=max`.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Option<u64> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (parse_value(a, min, names)?, parse_value(b, min, names)?),
+                // `5/15` means from 5 to the end in steps of 15.
+                None if part.contains('/') => (parse_value(range, min, names)?, max),
+                None => {
+                    let value = parse_value(range, min, names)?;
+                    (value, value)
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Some(bits)
+}
+
+impl Cron {
+    fn parse(expression: &str) -> Result<Self> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(invalid_cron(expression, "expected five fields"));
+        };
+        let field = |value: &str, min, max, names: &[&str], what: &str| {
+            parse_field(value, min, max, names)
+                .ok_or_else(|| invalid_cron(expression, &format!("bad {what} field `{value}`")))
+        };
+        let weekdays = field(weekday, 0, 7, &WEEKDAY_NAMES, "day of week")?;
+        Ok(Self {
+            minutes: field(minute, 0, 59, &[], "minute")?,
+            hours: field(hour, 0, 23, &[], "hour")? as u32,
+            days: field(day, 1, 31, &[], "day of month")? as u32,
+            months: field(month, 1, 12, &MONTH_NAMES, "month")? as u16,
+            // Both 0 and 7 are Sunday.
+            weekdays: ((weekdays | weekdays >> 7) & 0x7f) as u8,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        self.months & (1 << date.month()) != 0
+            && match (self.any_day, self.any_weekday) {
+                (false, false) => day || weekday,
+                _ => day && weekday,
+            }
+    }
+
+    /// The first matching minute after `after`. Times skipped by a daylight
+    /// saving change don't match.
+    fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = after.naive_local() + chrono::Duration::minutes(1);
+        let mut date = start.date();
+        // Five years covers a February 29th.
+        for _ in 0..366 * 5 {
+            if self.matches_day(date) {
+                let first_hour = if date == start.date() {
+                    start.hour()
+                } else {
+                    0
+                };
+                for hour in (first_hour..24).filter(|h| self.hours & (1 << h) != 0) {
+                    let first_minute = if date == start.date() && hour == start.hour() {
+                        start.minute()
+                    } else {
+                        0
+                    };
+                    for minute in (first_minute..60).filter(|m| self.minutes & (1 << m) != 0) {
+                        let time = date.and_hms_opt(hour, minute, 0)?;
+                        if let Some(at) = Local.from_local_datetime(&time).earliest() {
+                            return Some(at);
+                        }
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+}
+
+/// When `cron` next fires after `after_ms`, in epoch milliseconds.
+fn next_run(cron: &str, after_ms: i64) -> Result<Option<i64>> {
+    let cron = Cron::parse(cron)?;
+    let after = Local
+        .timestamp_millis_opt(after_ms)
+        .earliest()
+        .unwrap_or_else(Local::now);
+    Ok(cron.next_after(after).map(|at| at.timestamp_millis()))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Schedule {
+    pub id: String,
+    pub name: String,
+    pub cron: String,
+    pub prompt: String,
+    pub targets: Vec<FanoutTarget>,
+    pub use_tools: bool,
+    pub enabled: bool,
+    pub next_run_at: Option<i64>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduleInput {
+    pub name: String,
+    /// Five fields in local time (`0 8 * * 1-5` is weekdays at 8:00), or
+    /// `@hourly`, `@daily`, `@weekly`, `@monthly` or `@yearly`.
+    pub cron: String,
+    pub prompt: String,
+    /// Empty means every configured provider.
+    #[serde(default)]
+    pub targets: Vec<FanoutTarget>,
+    #[serde(default)]
+    pub use_tools: bool,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduledRun {
+    pub id: String,
+    pub schedule_id: String,
+    pub status: RunStatus,
+    pub results: Vec<FanoutResult>,
+    pub error: Option<String>,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+}
+
+impl ToSql for RunStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let status = match self {
+            RunStatus::Running => "running",
+            RunStatus::Completed => "completed",
+            RunStatus::Failed => "failed",
+        };
+        Ok(status.into())
+    }
+}
+
+impl FromSql for RunStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "running" => Ok(RunStatus::Running),
+            "completed" => Ok(RunStatus::Completed),
+            "failed" => Ok(RunStatus::Failed),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl Schedule {
+    const COLUMNS: &'static str =
+        "id, name, cron, prompt, targets, use_tools, enabled, next_run_at, created_at, updated_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let targets: String = row.get(4)?;
+        Ok(Self {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            cron: row.get(2)?,
+            prompt: row.get(3)?,
+            targets: serde_json::from_str(&targets).unwrap_or_default(),
+            use_tools: row.get(5)?,
+            enabled: row.get(6)?,
+            next_run_at: row.get(7)?,
+            created_at: row.get(8)?,
+            updated_at: row.get(9)?,
+        })
+    }
+}
+
+impl ScheduledRun {
+    const COLUMNS: &'static str =
+        "id, schedule_id, status, results, error, started_at, finished_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let results: Option<String> = row.get(3)?;
+        Ok(Self {
+            id: row.get(0)?,
+            schedule_id: row.get(1)?,
+            status: row.get(2)?,
+            results: results
+                .and_then(|r| serde_json::from_str(&r).ok())
+                .unwrap_or_default(),
+            error: row.get(4)?,
+            started_at: row.get(5)?,
+            finished_at: row.get(6)?,
+        })
+    }
+}
+
+impl Database {
+    fn list_schedules(&self) -> Result<Vec<Schedule>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM schedules ORDER BY name",
+            Schedule::COLUMNS
+        ))?;
+        let rows = stmt.query_map([], Schedule::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn get_schedule(&self, id: &str) -> Result<Schedule> {
+        self.conn()?
+            .query_row(
+                &format!("SELECT {} FROM schedules WHERE id = ?1", Schedule::COLUMNS),
+                [id],
+                Schedule::from_row,
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("schedule {id}")))
+    }
+
+    fn save_schedule(&self, schedule: &Schedule) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO schedules
+                 (id, name, cron, prompt, targets, use_tools, enabled, next_run_at,
+                  created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                schedule.id,
+                schedule.name,
+                schedule.cron,
+                schedule.prompt,
+                serde_json::to_string(&schedule.targets)?,
+                schedule.use_tools,
+                schedule.enabled,
+                schedule.next_run_at,
+                schedule.created_at,
+                schedule.updated_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn set_next_run(&self, id: &str, next_run_at: Option<i64>) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE schedules SET next_run_at = ?2 WHERE id = ?1",
+            params![id, next_run_at],
+        )?;
+        Ok(())
+    }
+
+    fn due_schedules(&self, now: i64) -> Result<Vec<Schedule>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM schedules WHERE enabled AND next_run_at <= ?1",
+            Schedule::COLUMNS
+        ))?;
+        let rows = stmt.query_map([now], Schedule::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn next_due(&self) -> Result<Option<i64>> {
+        Ok(self.conn()?.query_row(
+            "SELECT MIN(next_run_at) FROM schedules WHERE enabled",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn start_run(&self, schedule_id: &str) -> Result<ScheduledRun> {
+        let run = ScheduledRun {
+            id: new_id(),
+            schedule_id: schedule_id.to_string(),
+            status: RunStatus::Running,
+            results: Vec::new(),
+            error: None,
+            started_at: now_ms(),
+            finished_at: None,
+        };
+        self.conn()?.execute(
+            "INSERT INTO scheduled_runs (id, schedule_id, status, started_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![run.id, run.schedule_id, run.status, run.started_at],
+        )?;
+        Ok(run)
+    }
+
+    fn finish_run(&self, run: &ScheduledRun) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute(
+            "UPDATE scheduled_runs SET status = ?2, results = ?3, error = ?4, finished_at = ?5
+             WHERE id = ?1",
+            params![
+                run.id,
+                run.status,
+                serde_json::to_string(&run.results)?,
+                run.error,
+                run.finished_at
+            ],
+        )?;
+        conn.execute(
+            "DELETE FROM scheduled_runs WHERE schedule_id = ?1 AND id NOT IN (
+                 SELECT id FROM scheduled_runs WHERE schedule_id = ?1
+                 ORDER BY started_at DESC LIMIT ?2
+             )",
+            params![run.schedule_id, MAX_RUNS],
+        )?;
+        Ok(())
+    }
+
+    /// Runs cut off by the app quitting are marked failed.
+    fn fail_interrupted_runs(&self) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE scheduled_runs SET status = ?1, error = 'interrupted', finished_at = ?2
+             WHERE status = ?3",
+            params![RunStatus::Failed, now_ms(), RunStatus::Running],
+        )?;
+        Ok(())
+    }
+
+    fn list_scheduled_runs(
+        &self,
+        schedule_id: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<ScheduledRun>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM scheduled_runs
+             WHERE ?1 IS NULL OR schedule_id = ?1
+             ORDER BY started_at DESC LIMIT ?2",
+            ScheduledRun::COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![schedule_id, limit], ScheduledRun::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+/// Managed as Tauri state; wakes the scheduler when schedules change.
+#[derive(Default)]
+pub struct Scheduler {
+    wake: Arc<Notify>,
+}
+
+fn preview(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(PREVIEW_LEN) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+async fn announce(app: &AppHandle, schedule: &Schedule, run: &ScheduledRun) {
+    let _ = app.emit("scheduled-run-finished", run);
+    if !notifications::enabled(app) {
+        return;
+    }
+    let body = match (
+        &run.error,
+        run.results.iter().find_map(|r| r.content.as_deref()),
+    ) {
+        (Some(error), _) => format!("Failed: {error}"),
+        (None, Some(content)) => preview(content),
+        (None, None) => "No provider answered".into(),
+    };
+    let notification = Notification {
+        title: schedule.name.clone(),
+        body,
+        actions: vec![NotificationAction {
+            id: "open".into(),
+            label: "Show".into(),
+        }],
+        data: Some(serde_json::json!({ "schedule_id": schedule.id, "run_id": run.id })),
+    };
+    if let Err(err) = notifications::send(app, notification).await {
+        tracing::warn!("couldn't show a notification: {err}");
+    }
+}
+
+/// Fans the schedule's prompt out and records the run. Answers are always
+/// fresh rather than cached.
+async fn run_schedule(app: &AppHandle, schedule: &Schedule) -> Result<ScheduledRun> {
+    let db = app.state::<Database>();
+    let mut run = db.start_run(&schedule.id)?;
+    let request = FanoutRequest {
+        targets: schedule.targets.clone(),
+        messages: vec![ChatMessage {
+            role: Role::User,
+            content: schedule.prompt.clone(),
+        }],
+        temperature: None,
+        top_p: None,
+        max_tokens: None,
+        preset_id: None,
+        use_tools: schedule.use_tools,
+        bypass_cache: true,
+        conversation_id: None,
+    };
+    match fanout::fan_out(app, &run.id, &request).await {
+        Ok(results) => {
+            let all_failed = !results.is_empty() && results.iter().all(|r| r.error.is_some());
+            run.status = if all_failed {
+                RunStatus::Failed
+            } else {
+                RunStatus::Completed
+            };
+            run.results = results;
+        }
+        Err(err) => {
+            run.status = RunStatus::Failed;
+            run.error = Some(err.to_string());
+        }
+    }
+    run.finished_at = Some(now_ms());
+    db.finish_run(&run)?;
+    announce(app, schedule, &run).await;
+    Ok(run)
+}
+
+/// Starts every due schedule and moves each on to its next time first, so a
+/// slow run is never started twice.
+fn start_due(app: &AppHandle) -> Result<()> {
+    let db = app.state::<Database>();
+    let now = now_ms();
+    for schedule in db.due_schedules(now)? {
+        db.set_next_run(&schedule.id, next_run(&schedule.cron, now)?)?;
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(err) =
+                priority::scope(Priority::Background, run_schedule(&app, &schedule)).await
+            {
+                tracing::warn!("scheduled prompt {} failed: {err}", schedule.name);
+            }
+        });
+    }
+    Ok(())
+}
+
+pub fn init(app: &AppHandle) {
+    let scheduler = Scheduler::default();
+    let wake = scheduler.wake.clone();
+    app.manage(scheduler);
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        startup::warmed().await;
+        let db = app.state::<Database>();
+        let mut recovered = false;
+        loop {
+            let mut sleep = MAX_SLEEP;
+            if !db.is_locked() {
+                if !recovered {
+                    recovered = db.fail_interrupted_runs().is_ok();
+                }
+                if let Err(err) = start_due(&app) {
+                    tracing::warn!("couldn't start scheduled prompts: {err}");
+                }
+                if let Ok(Some(next)) = db.next_due() {
+                    let wait = Duration::from_millis((next - now_ms()).max(0) as u64);
+                    sleep = sleep.min(wait);
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(sleep) => {}
+                _ = wake.notified() => {}
+            }
+        }
+    });
+}
+
+fn build(id: String, created_at: i64, input: ScheduleInput) -> Result<Schedule> {
+    if input.name.trim().is_empty() || input.prompt.trim().is_empty() {
+        return Err(Error::InvalidSetting(
+            "a schedule needs a name and a prompt".into(),
+        ));
+    }
+    let now = now_ms();
+    let next_run_at = next_run(&input.cron, now)?.filter(|_| input.enabled);
+    Ok(Schedule {
+        id,
+        name: input.name.trim().to_string(),
+        cron: input.cron.trim().to_string(),
+        prompt: input.prompt,
+        targets: input.targets,
+        use_tools: input.use_tools,
+        enabled: input.enabled,
+        next_run_at,
+        created_at,
+        updated_at: now,
+    })
+}
+
+#[tauri::command]
+pub fn list_schedules(db: State<'_, Database>) -> Result<Vec<Schedule>> {
+    db.list_schedules()
+}
+
+#[tauri::command]
+pub fn create_schedule(
+    db: State<'_, Database>,
+    scheduler: State<'_, Scheduler>,
+    schedule: ScheduleInput,
+) -> Result<Schedule> {
+    let schedule = build(new_id(), now_ms(), schedule)?;
+    db.save_schedule(&schedule)?;
+    scheduler.wake.notify_one();
+    Ok(schedule)
+}
+
+#[tauri::command]
+pub fn update_schedule(
+    db: State<'_, Database>,
+    scheduler: State<'_, Scheduler>,
+    id: String,
+    schedule: ScheduleInput,
+) -> Result<Schedule> {
+    let created_at = db.get_schedule(&id)?.created_at;
+    let schedule = build(id, created_at, schedule)?;
+    db.save_schedule(&schedule)?;
+    scheduler.wake.notify_one();
+    Ok(schedule)
+}
+
+/// Deletes the schedule and its runs.
+#[tauri::command]
+pub fn delete_schedule(db: State<'_, Database>, id: String) -> Result<()> {
+    db.conn()?
+        .execute("DELETE FROM schedules WHERE id = ?1", [id])?;
+    Ok(())
+}
+
+/// Runs a schedule now, outside its timetable, and returns the run.
+#[tauri::command]
+pub async fn run_schedule_now(app: AppHandle, id: String) -> Result<ScheduledRun> {
+    let schedule = app.state::<Database>().get_schedule(&id)?;
+    run_schedule(&app, &schedule).await
+}
+
+/// Recent runs, newest first, of one schedule or all of them.
+#[tauri::command]
+pub fn list_scheduled_runs(
+    db: State<'_, Database>,
+    schedule_id: Option<String>,
+    limit: Option<usize>,
+) -> Result<Vec<ScheduledRun>> {
+    db.list_scheduled_runs(schedule_id.as_deref(), limit.unwrap_or(50))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn next(expression: &str, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        Cron::parse(expression).unwrap().next_after(after)
+    }
+
+    #[test]
+    fn parses_fields() {
+        let cron = Cron::parse("*/15 9-17 * * mon-fri").unwrap();
+        assert_eq!(cron.minutes, 1 | 1 << 15 | 1 << 30 | 1 << 45);
+        assert_eq!(cron.hours, (9..=17).fold(0, |bits, h| bits | 1 << h));
+        assert_eq!(cron.weekdays, 0b0111110);
+        assert!(cron.any_day);
+        assert!(!cron.any_weekday);
+
+        assert_eq!(
+            Cron::parse("5/20 * * * *").unwrap().minutes,
+            1 << 5 | 1 << 25 | 1 << 45
+        );
+        assert_eq!(Cron::parse("0 0 1,15 * *").unwrap().days, 1 << 1 | 1 << 15);
+        assert_eq!(
+            Cron::parse("0 0 * JAN,jul *").unwrap().months,
+            1 << 1 | 1 << 7
+        );
+        assert_eq!(Cron::parse("0 0 * * 7").unwrap().weekdays, 1);
+        assert_eq!(Cron::parse("0 0 * * 0").unwrap().weekdays, 1);
+        assert_eq!(
+            Cron::parse(" @daily ").unwrap(),
+            Cron::parse("0 0 * * *").unwrap()
+        );
+        // A stepped `*` still counts as unrestricted for the day rule.
+        assert!(Cron::parse("0 0 */2 * 1").unwrap().any_day);
+    }
+
+    #[test]
+    fn refuses_bad_expressions() {
+        for expression in [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * 32 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "* * * foo *",
+            "@sometimes",
+        ] {
+            assert!(Cron::parse(expression).is_err(), "{expression}");
+        }
+    }
+
+    #[test]
+    fn finds_the_next_run() {
+        assert_eq!(
+            next("30 9 * * *", at(2025, 6, 10, 10, 0)),
+            Some(at(2025, 6, 11, 9, 30))
+        );
+        // Strictly after, never the minute given.
+        assert_eq!(
+            next("30 9 * * *", at(2025, 6, 10, 9, 30)),
+            Some(at(2025, 6, 11, 9, 30))
+        );
+        assert_eq!(
+            next("*/15 * * * *", at(2025, 6, 10, 9, 50)),
+            Some(at(2025, 6, 10, 10, 0))
+        );
+        assert_eq!(
+            next("0 0 31 * *", at(2025, 6, 1, 0, 0)),
+            Some(at(2025, 7, 31, 0, 0))
+        );
+        assert_eq!(
+            next("0 12 29 2 *", at(2025, 3, 1, 0, 0)),
+            Some(at(2028, 2, 29, 12, 0))
+        );
+        assert_eq!(next("0 0 30 2 *", at(2025, 3, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn combines_the_day_fields_like_cron() {
+        // June 1st 2025 is a Sunday. Both restricted: the 1st or a Monday.
+        assert_eq!(
+            next("0 0 1 * 1", at(2025, 6, 1, 12, 0)),
+            Some(at(2025, 6, 2, 0, 0))
+        );
+        assert_eq!(
+            next("0 0 1 * 1", at(2025, 6, 30, 12, 0)),
+            Some(at(2025, 7, 1, 0, 0))
+        );
+        // One starts with `*`: an odd day that is also a Monday.
+        assert_eq!(
+            next("0 0 */2 * 1", at(2025, 6, 1, 12, 0)),
+            Some(at(2025, 6, 9, 0, 0))
+        );
+        assert_eq!(
+            next("0 0 13 * */5", at(2025, 6, 1, 0, 0)),
+            Some(at(2025, 6, 13, 0, 0))
+        );
+    }
+}
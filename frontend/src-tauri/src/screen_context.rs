@@ -0,0 +1,369 @@
+//! What the user is looking at, for prompts about it: the frontmost app,
+//! its window title and the text selected in it. The quick prompt takes it
+//! when summoned, before its own window comes up and takes the focus, so it
+//! can offer "about this" without any copying and pasting.
+//!
+//! It is opt-in. macOS only tells apps granted Accessibility access, read
+//! through System Events; Windows goes through UI Automation from
+//! PowerShell; on Linux the window comes from `xdotool` under X11 and the
+//! selection from the primary selection, which Wayland has as well.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::hotkey;
+use crate::storage::now_ms;
+
+const CONFIG_FILE: &str = "screen_context.json";
+/// Between the fields in the capture scripts' output.
+const SEPARATOR: char = '\u{1f}';
+/// Longer selections are cut to this many characters.
+const MAX_SELECTION_CHARS: usize = 20_000;
+/// How long the quick prompt waits for a capture before showing without it.
+const CAPTURE_TIMEOUT: Duration = Duration::from_millis(1500);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScreenContextConfig {
+    pub enabled: bool,
+    pub include_selection: bool,
+    /// Capture whenever the quick prompt is summoned.
+    pub quick_prompt: bool,
+}
+
+impl Default for ScreenContextConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            include_selection: true,
+            quick_prompt: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScreenContext {
+    pub app_name: Option<String>,
+    pub window_title: Option<String>,
+    pub selection: Option<String>,
+    pub captured_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScreenContextPermission {
+    pub granted: bool,
+    /// The OS asks the user to grant it; only macOS does.
+    pub required: bool,
+}
+
+/// Managed as Tauri state.
+pub struct ScreenContextState {
+    config: Mutex<ScreenContextConfig>,
+    /// Taken when the quick prompt was last summoned.
+    last: Mutex<Option<ScreenContext>>,
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<ScreenContextConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(ScreenContextState {
+        config: Mutex::new(config),
+        last: Mutex::default(),
+    });
+}
+
+fn non_empty(text: &str) -> Option<String> {
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Splits a capture script's output into the app, title and selection.
+fn parse(output: &str) -> ScreenContext {
+    let mut fields = output.splitn(3, SEPARATOR);
+    let app_name = fields.next().and_then(non_empty);
+    let window_title = fields.next().and_then(non_empty);
+    let selection = fields.next().and_then(non_empty).map(|selection| {
+        match selection.char_indices().nth(MAX_SELECTION_CHARS) {
+            Some((end, _)) => selection[..end].to_string(),
+            None => selection,
+        }
+    });
+    ScreenContext {
+        app_name,
+        window_title,
+        selection,
+        captured_at: now_ms(),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn capture(include_selection: bool) -> Result<ScreenContext> {
+    const SCRIPT: &str = r#"
+set sep to character id 31
+tell application "System Events"
+  set p to first application process whose frontmost is true
+  set appName to name of p
+  set winTitle to ""
+  try
+    set winTitle to value of attribute "AXTitle" of (value of attribute "AXFocusedWindow" of p)
+  end try
+  set sel to ""
+  if (system attribute "PENTAMIND_SELECTION") is "1" then
+    try
+      set sel to value of attribute "AXSelectedText" of (value of attribute "AXFocusedUIElement" of p)
+    end try
+  end if
+end tell
+return appName & sep & winTitle & sep & sel"#;
+    let output = crate::process::command("osascript")
+        .args(["-e", SCRIPT])
+        .env(
+            "PENTAMIND_SELECTION",
+            if include_selection { "1" } else { "0" },
+        )
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::Capture(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    let output = String::from_utf8_lossy(&output.stdout);
+    Ok(parse(output.trim_end_matches('\n')))
+}
+
+#[cfg(windows)]
+fn capture(include_selection: bool) -> Result<ScreenContext> {
+    const SCRIPT: &str = r#"
+Add-Type @"
+using System; using System.Text; using System.Runtime.InteropServices;
+public static class F {
+  [DllImport("user32.dll")] public static extern IntPtr GetForegroundWindow();
+  [DllImport("user32.dll", CharSet = CharSet.Unicode)] public static extern int GetWindowText(IntPtr h, StringBuilder s, int n);
+  [DllImport("user32.dll")] public static extern uint GetWindowThreadProcessId(IntPtr h, out uint p);
+}
+"@
+$h = [F]::GetForegroundWindow()
+$t = New-Object System.Text.StringBuilder 1024
+[F]::GetWindowText($h, $t, 1024) | Out-Null
+$id = 0
+[F]::GetWindowThreadProcessId($h, [ref]$id) | Out-Null
+$name = ""
+try {
+  $proc = Get-Process -Id $id
+  $name = $proc.ProcessName
+  $description = $proc.MainModule.FileVersionInfo.FileDescription
+  if ($description) { $name = $description }
+} catch {}
+$sel = ""
+if ($env:PENTAMIND_SELECTION -eq "1") {
+  try {
+    Add-Type -AssemblyName UIAutomationClient, UIAutomationTypes
+    $e = [System.Windows.Automation.AutomationElement]::FocusedElement
+    $pattern = $null
+    if ($e.TryGetCurrentPattern([System.Windows.Automation.TextPattern]::Pattern, [ref]$pattern)) {
+      $sel = ($pattern.GetSelection() | ForEach-Object { $_.GetText(-1) }) -join "`n"
+    }
+  } catch {}
+}
+[Console]::OutputEncoding = [Text.Encoding]::UTF8
+$sep = [char]31
+[Console]::Out.Write("$name$sep$($t.ToString())$sep$sel")"#;
+    let output = crate::process::command("powershell")
+        .args(["-NoProfile", "-Command", SCRIPT])
+        .env(
+            "PENTAMIND_SELECTION",
+            if include_selection { "1" } else { "0" },
+        )
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::Capture(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The standard output of `program`, if it ran and succeeded.
+#[cfg(target_os = "linux")]
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = crate::process::command(program).args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(target_os = "linux")]
+fn capture(include_selection: bool) -> Result<ScreenContext> {
+    let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+    // Wayland doesn't let other apps see which window has the focus.
+    let (app_name, window_title) = if wayland {
+        (None, None)
+    } else {
+        let title = run("xdotool", &["getactivewindow", "getwindowname"]);
+        let app = run("xdotool", &["getactivewindow", "getwindowpid"])
+            .and_then(|pid| std::fs::read_to_string(format!("/proc/{}/comm", pid.trim())).ok());
+        (app, title)
+    };
+    let selection = if !include_selection {
+        None
+    } else if wayland {
+        run("wl-paste", &["--primary", "--no-newline"])
+    } else {
+        run("xclip", &["-o", "-selection", "primary"])
+            .or_else(|| run("xsel", &["--primary", "--output"]))
+    };
+    let field = |value: Option<String>| value.unwrap_or_default().replace(SEPARATOR, " ");
+    Ok(parse(&format!(
+        "{}{SEPARATOR}{}{SEPARATOR}{}",
+        field(app_name),
+        field(window_title),
+        field(selection)
+    )))
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", windows)))]
+fn capture(_include_selection: bool) -> Result<ScreenContext> {
+    Err(Error::Unsupported("screen context on this platform".into()))
+}
+
+fn permission() -> ScreenContextPermission {
+    if cfg!(target_os = "macos") {
+        let granted = crate::process::command("osascript")
+            .args([
+                "-e",
+                "tell application \"System Events\" to get UI elements enabled",
+            ])
+            .output()
+            .is_ok_and(|o| String::from_utf8_lossy(&o.stdout).trim() == "true");
+        ScreenContextPermission {
+            granted,
+            required: true,
+        }
+    } else {
+        ScreenContextPermission {
+            granted: true,
+            required: false,
+        }
+    }
+}
+
+/// Captures now, leaving out this app's own windows.
+async fn take(app: &AppHandle, config: &ScreenContextConfig) -> Result<ScreenContext> {
+    if !config.enabled {
+        return Err(Error::InvalidSetting("screen context is turned off".into()));
+    }
+    let include_selection = config.include_selection;
+    let mut context =
+        tauri::async_runtime::spawn_blocking(move || capture(include_selection)).await??;
+    let own = &app.package_info().name;
+    if context
+        .app_name
+        .as_deref()
+        .is_some_and(|name| name.eq_ignore_ascii_case(own))
+    {
+        context = ScreenContext {
+            captured_at: context.captured_at,
+            ..ScreenContext::default()
+        };
+    }
+    Ok(context)
+}
+
+/// Shows the quick prompt, first taking the screen context when it is
+/// wanted: the frontmost app has to be read before the prompt becomes it.
+pub fn summon_quick_prompt(app: &AppHandle) -> Result<()> {
+    let config = match app.try_state::<ScreenContextState>() {
+        Some(state) => state.config.lock().unwrap().clone(),
+        None => ScreenContextConfig::default(),
+    };
+    if !config.enabled || !config.quick_prompt {
+        return hotkey::show_quick_window(app);
+    }
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let context = match tokio::time::timeout(CAPTURE_TIMEOUT, take(&app, &config)).await {
+            Ok(Ok(context)) => Some(context),
+            Ok(Err(err)) => {
+                tracing::warn!("couldn't capture the screen context: {err}");
+                None
+            }
+            Err(_) => None,
+        };
+        *app.state::<ScreenContextState>().last.lock().unwrap() = context.clone();
+        if let Err(err) = hotkey::show_quick_window(&app) {
+            tracing::warn!("couldn't show the quick prompt: {err}");
+        }
+        if let Some(context) = context {
+            let _ = app.emit_to(hotkey::QUICK_LABEL, "screen-context", context);
+        }
+    });
+    Ok(())
+}
+
+/// Captures the frontmost app, its window title and the selected text now.
+/// Called from one of this app's windows that has the focus, it only sees
+/// that window; the quick prompt should use `get_screen_context`.
+#[tauri::command]
+pub async fn capture_screen_context(
+    app: AppHandle,
+    state: State<'_, ScreenContextState>,
+) -> Result<ScreenContext> {
+    let config = state.config.lock().unwrap().clone();
+    take(&app, &config).await
+}
+
+/// What was in front when the quick prompt was last summoned.
+#[tauri::command]
+pub fn get_screen_context(state: State<'_, ScreenContextState>) -> Option<ScreenContext> {
+    state.last.lock().unwrap().clone()
+}
+
+/// Forgets the captured context, once the prompt has used or dropped it.
+#[tauri::command]
+pub fn clear_screen_context(state: State<'_, ScreenContextState>) {
+    *state.last.lock().unwrap() = None;
+}
+
+#[tauri::command]
+pub async fn get_screen_context_permission() -> Result<ScreenContextPermission> {
+    Ok(tauri::async_runtime::spawn_blocking(permission).await?)
+}
+
+/// Opens the Accessibility pane of the system settings on macOS, where the
+/// app is granted access. Elsewhere there is nothing to grant.
+#[tauri::command]
+pub fn request_screen_context_permission() -> Result<()> {
+    if cfg!(target_os = "macos") {
+        crate::process::command("open")
+            .arg("x-apple.systempreferences:com.apple.preference.security?Privacy_Accessibility")
+            .spawn()?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_screen_context_config(state: State<'_, ScreenContextState>) -> ScreenContextConfig {
+    state.config.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn set_screen_context_config(
+    app: AppHandle,
+    state: State<'_, ScreenContextState>,
+    config: ScreenContextConfig,
+) -> Result<ScreenContextConfig> {
+    config::write(&app, CONFIG_FILE, &config)?;
+    *state.config.lock().unwrap() = config.clone();
+    if !config.enabled {
+        *state.last.lock().unwrap() = None;
+    }
+    Ok(config)
+}
@@ -0,0 +1,251 @@
+//! Screen capture for vision prompts. Each platform goes through its own
+//! facility: `screencapture` on macOS, the desktop portal on Linux (which
+//! also covers Wayland, where apps can't read the screen themselves), and a
+//! GDI copy driven from PowerShell on Windows.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::error::{Error, Result};
+use crate::storage::now_ms;
+use crate::windows;
+
+const DIR: &str = "screenshots";
+/// Time for the window to actually disappear before the screen is read.
+const HIDE_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureMode {
+    /// The whole (main) display.
+    Screen,
+    /// A single window: picked by the user on macOS and Linux, the
+    /// foreground window on Windows.
+    Window,
+    /// A region the user drags out.
+    Region,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Screenshot {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    /// The PNG itself, when asked for.
+    pub base64: Option<String>,
+}
+
+/// Width and height from a PNG's IHDR chunk.
+fn png_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    if bytes.len() < 24 || &bytes[..8] != b"\x89PNG\r\n\x1a\n" || &bytes[12..16] != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(bytes[16..20].try_into().ok()?);
+    let height = u32::from_be_bytes(bytes[20..24].try_into().ok()?);
+    Some((width, height))
+}
+
+#[cfg(target_os = "macos")]
+fn capture(mode: CaptureMode, dest: &Path) -> Result<()> {
+    let args: &[&str] = match mode {
+        CaptureMode::Screen => &["-x", "-m"],
+        CaptureMode::Window => &["-x", "-i", "-w", "-o"],
+        CaptureMode::Region => &["-x", "-i", "-s"],
+    };
+    let status = crate::process::command("screencapture")
+        .args(args)
+        .arg(dest)
+        .status()?;
+    if !status.success() {
+        return Err(Error::Capture(format!(
+            "screencapture exited with {status}"
+        )));
+    }
+    // Pressing Escape in interactive mode exits cleanly without a file.
+    if !dest.exists() {
+        return Err(Error::Cancelled);
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn capture(mode: CaptureMode, dest: &Path) -> Result<()> {
+    const SCREEN: &str = "$b = [System.Windows.Forms.SystemInformation]::VirtualScreen";
+    const WINDOW: &str = r#"
+Add-Type @"
+using System; using System.Runtime.InteropServices;
+public static class W {
+  [DllImport("user32.dll")] public static extern IntPtr GetForegroundWindow();
+  [DllImport("user32.dll")] public static extern bool GetWindowRect(IntPtr h, out RECT r);
+  public struct RECT { public int Left, Top, Right, Bottom; }
+}
+"@
+$r = New-Object W+RECT
+[W]::GetWindowRect([W]::GetForegroundWindow(), [ref]$r) | Out-Null
+$b = [System.Drawing.Rectangle]::FromLTRB($r.Left, $r.Top, $r.Right, $r.Bottom)"#;
+    const SAVE: &str = r#"
+$bmp = New-Object System.Drawing.Bitmap $b.Width, $b.Height
+$g = [System.Drawing.Graphics]::FromImage($bmp)
+$g.CopyFromScreen($b.Location, [System.Drawing.Point]::Empty, $b.Size)
+$bmp.Save($env:PENTAMIND_SCREENSHOT, [System.Drawing.Imaging.ImageFormat]::Png)"#;
+
+    let bounds = match mode {
+        CaptureMode::Screen => SCREEN,
+        CaptureMode::Window => WINDOW,
+        CaptureMode::Region => {
+            return Err(Error::Unsupported(
+                "interactive region capture on Windows".into(),
+            ))
+        }
+    };
+    let script =
+        format!("Add-Type -AssemblyName System.Windows.Forms, System.Drawing\n{bounds}\n{SAVE}");
+    let output = crate::process::command("powershell")
+        .args(["-NoProfile", "-Command", &script])
+        .env("PENTAMIND_SCREENSHOT", dest)
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::Capture(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Asks the desktop portal for a screenshot. Window and region modes open
+/// the portal's own picker; the portal decides where the file lands, so it
+/// is copied to `dest`.
+#[cfg(target_os = "linux")]
+fn capture(mode: CaptureMode, dest: &Path) -> Result<()> {
+    use std::sync::{Arc, Mutex};
+
+    use dbus::arg::{PropMap, RefArg, Variant};
+    use dbus::blocking::Connection;
+    use dbus::message::MatchRule;
+
+    const PORTAL: &str = "org.freedesktop.portal.Desktop";
+    const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+    /// Long enough for the user to pick a region.
+    const RESPONSE_TIMEOUT: Duration = Duration::from_secs(120);
+
+    /// The portal's response code and the screenshot's URI.
+    type Response = Option<(u32, Option<String>)>;
+
+    let portal_error = |e: dbus::Error| Error::Capture(e.to_string());
+    let conn = Connection::new_session().map_err(portal_error)?;
+
+    // Subscribe to the request's response before making it, at the handle
+    // path the portal derives from our bus name and token.
+    let token = format!("pentamind{}", now_ms());
+    let sender = conn.unique_name().trim_start_matches(':').replace('.', "_");
+    let handle = dbus::Path::new(format!("{PORTAL_PATH}/request/{sender}/{token}"))
+        .map_err(Error::Capture)?;
+    let response: Arc<Mutex<Response>> = Arc::default();
+    let slot = response.clone();
+    conn.add_match(
+        MatchRule::new_signal("org.freedesktop.portal.Request", "Response").with_path(handle),
+        move |(code, results): (u32, PropMap), _, _| {
+            let uri = results
+                .get("uri")
+                .and_then(|v| v.as_str())
+                .map(str::to_string);
+            *slot.lock().unwrap() = Some((code, uri));
+            false
+        },
+    )
+    .map_err(portal_error)?;
+
+    let mut options = PropMap::new();
+    options.insert("handle_token".into(), Variant(Box::new(token)));
+    options.insert(
+        "interactive".into(),
+        Variant(Box::new(mode != CaptureMode::Screen)),
+    );
+    let proxy = conn.with_proxy(PORTAL, PORTAL_PATH, Duration::from_secs(5));
+    let _: (dbus::Path<'static>,) = proxy
+        .method_call(
+            "org.freedesktop.portal.Screenshot",
+            "Screenshot",
+            ("", options),
+        )
+        .map_err(portal_error)?;
+
+    let deadline = std::time::Instant::now() + RESPONSE_TIMEOUT;
+    while response.lock().unwrap().is_none() {
+        if std::time::Instant::now() > deadline {
+            return Err(Error::Capture(
+                "the screenshot portal didn't respond".into(),
+            ));
+        }
+        conn.process(Duration::from_millis(200))
+            .map_err(portal_error)?;
+    }
+    let (code, uri) = response.lock().unwrap().take().unwrap_or_default();
+    match (code, uri) {
+        (0, Some(uri)) => {
+            let source = tauri::Url::parse(&uri)
+                .ok()
+                .and_then(|url| url.to_file_path().ok())
+                .ok_or_else(|| Error::Capture(format!("unexpected screenshot location {uri}")))?;
+            std::fs::copy(source, dest)?;
+            Ok(())
+        }
+        // 1 means the user dismissed the picker.
+        (1, _) => Err(Error::Cancelled),
+        _ => Err(Error::Capture("the screenshot portal refused".into())),
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", windows)))]
+fn capture(_mode: CaptureMode, _dest: &Path) -> Result<()> {
+    Err(Error::Unsupported("screen capture on this platform".into()))
+}
+
+/// Captures into the app cache and returns the PNG. The main window is
+/// hidden for the capture (unless `keep_window` is set) so it isn't in
+/// the shot, and shown again afterwards.
+#[tauri::command]
+pub async fn capture_screen(
+    app: AppHandle,
+    mode: CaptureMode,
+    include_base64: Option<bool>,
+    keep_window: Option<bool>,
+) -> Result<Screenshot> {
+    let dir = app.path().app_cache_dir()?.join(DIR);
+    std::fs::create_dir_all(&dir)?;
+    let dest = dir.join(format!("screenshot-{}.png", now_ms()));
+
+    let window = app
+        .get_webview_window(windows::MAIN_LABEL)
+        .filter(|w| !keep_window.unwrap_or(false) && w.is_visible().unwrap_or(false));
+    if let Some(window) = &window {
+        window.hide()?;
+        tokio::time::sleep(HIDE_DELAY).await;
+    }
+    let result = {
+        let dest = dest.clone();
+        tauri::async_runtime::spawn_blocking(move || capture(mode, &dest))
+            .await
+            .map_err(|e| Error::Capture(e.to_string()))
+    };
+    if window.is_some() {
+        windows::show_main_window(&app);
+    }
+    result??;
+
+    let bytes = std::fs::read(&dest)?;
+    let (width, height) =
+        png_size(&bytes).ok_or_else(|| Error::Capture("the capture isn't a PNG".into()))?;
+    Ok(Screenshot {
+        path: dest,
+        width,
+        height,
+        base64: include_base64
+            .unwrap_or(false)
+            .then(|| base64::engine::general_purpose::STANDARD.encode(&bytes)),
+    })
+}
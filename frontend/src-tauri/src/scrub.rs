@@ -0,0 +1,508 @@
+//! Pre-flight checks on outgoing prompts. When turned on, every chat and
+//! fan-out is scanned for email addresses, API keys, card numbers and any
+//! patterns of the user's own before it is sent. What is found is either
+//! replaced with a placeholder such as `[EMAIL]`, or held back until the
+//! user answers a `prompt-scrub-confirm` event through
+//! `confirm_scrubbed_prompt`. With no window to answer, as in a headless
+//! run, what would be held back is replaced instead.
+//!
+//! Only user and system messages are scanned; answers a model already
+//! gave are sent on as they are.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::oneshot;
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::llm::{ChatMessage, Role};
+use crate::storage::new_id;
+
+const CONFIG_FILE: &str = "scrub.json";
+/// How long a held-back prompt waits for an answer before it is dropped.
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(300);
+
+const EMAIL: &str = r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}";
+/// Key formats of the common providers and code hosts.
+const API_KEY: &str = r"\b(?:sk-(?:ant-|proj-)?[A-Za-z0-9_-]{20,}|AKIA[0-9A-Z]{16}|AIza[0-9A-Za-z_-]{35}|gh[pousr]_[A-Za-z0-9]{36,}|github_pat_[A-Za-z0-9_]{22,}|xox[abposr]-[A-Za-z0-9-]{10,}|glpat-[A-Za-z0-9_-]{20,})";
+/// Runs of 13 to 19 digits, optionally split by spaces or dashes; only
+/// those passing the Luhn check count.
+const CARD: &str = r"\b\d(?:[ -]?\d){12,18}\b";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrubMode {
+    /// Replace what is found and send.
+    #[default]
+    Redact,
+    /// Ask before sending.
+    Confirm,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomPattern {
+    /// Shown in findings and used for the placeholder, as `[NAME]`.
+    pub name: String,
+    pub pattern: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScrubConfig {
+    pub enabled: bool,
+    pub mode: ScrubMode,
+    pub emails: bool,
+    pub api_keys: bool,
+    pub credit_cards: bool,
+    pub custom: Vec<CustomPattern>,
+}
+
+impl Default for ScrubConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: ScrubMode::default(),
+            emails: true,
+            api_keys: true,
+            credit_cards: true,
+            custom: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    /// `email`, `api_key`, `credit_card` or a custom pattern's name.
+    pub kind: String,
+    /// Index into the messages; 0 for `scrub_prompt`.
+    pub message: usize,
+    /// Byte range of the match in that message.
+    pub start: usize,
+    pub end: usize,
+    /// The match with most of it masked, safe to show.
+    pub preview: String,
+}
+
+/// What `scrub_prompt` returns.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScrubReport {
+    pub findings: Vec<Finding>,
+    pub redacted: String,
+}
+
+/// Payload of the `prompt-scrubbed` event, sent when a prompt went out
+/// redacted.
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptScrubbed {
+    pub request_id: String,
+    pub findings: Vec<Finding>,
+}
+
+/// Payload of the `prompt-scrub-confirm` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScrubConfirmation {
+    pub confirmation_id: String,
+    pub request_id: String,
+    pub findings: Vec<Finding>,
+}
+
+/// The answer to a `prompt-scrub-confirm` event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrubDecision {
+    /// Send the prompt as written.
+    Send,
+    Redact,
+    Cancel,
+}
+
+struct Detector {
+    kind: String,
+    regex: Regex,
+    luhn: bool,
+}
+
+/// Managed as Tauri state.
+pub struct Scrubber {
+    config: Mutex<ScrubConfig>,
+    detectors: Mutex<Vec<Detector>>,
+    confirmations: Mutex<HashMap<String, oneshot::Sender<ScrubDecision>>>,
+}
+
+fn detectors(config: &ScrubConfig) -> Result<Vec<Detector>> {
+    let builtin = |kind: &str, pattern: &str, luhn| Detector {
+        kind: kind.to_string(),
+        regex: Regex::new(pattern).expect("built-in patterns are valid"),
+        luhn,
+    };
+    let mut detectors = Vec::new();
+    if config.emails {
+        detectors.push(builtin("email", EMAIL, false));
+    }
+    if config.api_keys {
+        detectors.push(builtin("api_key", API_KEY, false));
+    }
+    if config.credit_cards {
+        detectors.push(builtin("credit_card", CARD, true));
+    }
+    for custom in &config.custom {
+        if custom.name.trim().is_empty() {
+            return Err(Error::InvalidSetting("a scrub pattern has no name".into()));
+        }
+        let regex = Regex::new(&custom.pattern)
+            .map_err(|e| Error::InvalidSetting(format!("scrub pattern {}: {e}", custom.name)))?;
+        // It would match between every two characters.
+        if regex.is_match("") {
+            return Err(Error::InvalidSetting(format!(
+                "scrub pattern {} matches empty text",
+                custom.name
+            )));
+        }
+        detectors.push(Detector {
+            kind: custom.name.trim().to_string(),
+            regex,
+            luhn: false,
+        });
+    }
+    Ok(detectors)
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<ScrubConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    let detectors = detectors(&config).unwrap_or_else(|err| {
+        tracing::warn!("ignoring scrub patterns: {err}");
+        detectors(&ScrubConfig {
+            custom: Vec::new(),
+            ..config.clone()
+        })
+        .unwrap_or_default()
+    });
+    app.manage(Scrubber {
+        config: Mutex::new(config),
+        detectors: Mutex::new(detectors),
+        confirmations: Mutex::default(),
+    });
+}
+
+fn luhn(digits: &str) -> bool {
+    let digits: Vec<u32> = digits.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match (i % 2 == 1, d * 2) {
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => d,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Keeps the first and last few characters of `text`.
+fn mask(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let keep = (chars.len() / 6).min(4);
+    let start: String = chars[..keep].iter().collect();
+    let end: String = chars[chars.len() - keep..].iter().collect();
+    format!("{start}{}{end}", "•".repeat(chars.len() - keep * 2))
+}
+
+fn placeholder(kind: &str) -> String {
+    format!("[{}]", kind.to_uppercase().replace([' ', '-'], "_"))
+}
+
+/// Matches in `text`, earliest first. Overlapping matches keep the first.
+fn find(detectors: &[Detector], message: usize, text: &str) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = detectors
+        .iter()
+        .flat_map(|detector| {
+            detector
+                .regex
+                .find_iter(text)
+                .filter(|m| !m.is_empty() && (!detector.luhn || luhn(m.as_str())))
+                .map(|m| Finding {
+                    kind: detector.kind.clone(),
+                    message,
+                    start: m.start(),
+                    end: m.end(),
+                    preview: mask(m.as_str()),
+                })
+        })
+        .collect();
+    findings.sort_by_key(|f| (f.start, std::cmp::Reverse(f.end)));
+    let mut last_end = 0;
+    findings.retain(|f| {
+        let keep = f.start >= last_end;
+        if keep {
+            last_end = f.end;
+        }
+        keep
+    });
+    findings
+}
+
+fn redact(text: &str, findings: &[Finding]) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut at = 0;
+    for finding in findings {
+        redacted.push_str(&text[at..finding.start]);
+        redacted.push_str(&placeholder(&finding.kind));
+        at = finding.end;
+    }
+    redacted.push_str(&text[at..]);
+    redacted
+}
+
+fn scanned(message: &ChatMessage) -> bool {
+    matches!(message.role, Role::User | Role::System)
+}
+
+/// Runs the pipeline over the messages about to go out for `request_id`,
+/// returning them as they should be sent. Fails with `Error::Scrubbed`
+/// when the user cancels or doesn't answer in time.
+pub(crate) async fn check(
+    app: &AppHandle,
+    request_id: &str,
+    mut messages: Vec<ChatMessage>,
+) -> Result<Vec<ChatMessage>> {
+    let Some(scrubber) = app.try_state::<Scrubber>() else {
+        return Ok(messages);
+    };
+    let config = scrubber.config.lock().unwrap().clone();
+    if !config.enabled {
+        return Ok(messages);
+    }
+    let per_message: Vec<Vec<Finding>> = {
+        let detectors = scrubber.detectors.lock().unwrap();
+        messages
+            .iter()
+            .enumerate()
+            .map(|(index, message)| {
+                if scanned(message) {
+                    find(&detectors, index, &message.content)
+                } else {
+                    Vec::new()
+                }
+            })
+            .collect()
+    };
+    let findings: Vec<Finding> = per_message.iter().flatten().cloned().collect();
+    if findings.is_empty() {
+        return Ok(messages);
+    }
+
+    let decision = match config.mode {
+        ScrubMode::Redact => ScrubDecision::Redact,
+        ScrubMode::Confirm if app.webview_windows().is_empty() => ScrubDecision::Redact,
+        ScrubMode::Confirm => confirm(app, &scrubber, request_id, findings.clone()).await,
+    };
+    match decision {
+        ScrubDecision::Send => Ok(messages),
+        ScrubDecision::Cancel => {
+            let mut kinds: Vec<&str> = findings.iter().map(|f| f.kind.as_str()).collect();
+            kinds.sort_unstable();
+            kinds.dedup();
+            Err(Error::Scrubbed(kinds.join(", ")))
+        }
+        ScrubDecision::Redact => {
+            for (message, found) in messages.iter_mut().zip(&per_message) {
+                if !found.is_empty() {
+                    message.content = redact(&message.content, found);
+                }
+            }
+            let _ = app.emit(
+                "prompt-scrubbed",
+                PromptScrubbed {
+                    request_id: request_id.to_string(),
+                    findings,
+                },
+            );
+            Ok(messages)
+        }
+    }
+}
+
+/// Asks the user what to do with a prompt, waiting for
+/// `confirm_scrubbed_prompt`.
+async fn confirm(
+    app: &AppHandle,
+    scrubber: &Scrubber,
+    request_id: &str,
+    findings: Vec<Finding>,
+) -> ScrubDecision {
+    let confirmation_id = new_id();
+    let (sender, answer) = oneshot::channel();
+    scrubber
+        .confirmations
+        .lock()
+        .unwrap()
+        .insert(confirmation_id.clone(), sender);
+    let _ = app.emit(
+        "prompt-scrub-confirm",
+        ScrubConfirmation {
+            confirmation_id: confirmation_id.clone(),
+            request_id: request_id.to_string(),
+            findings,
+        },
+    );
+    let decision = match tokio::time::timeout(CONFIRM_TIMEOUT, answer).await {
+        Ok(Ok(decision)) => decision,
+        _ => ScrubDecision::Cancel,
+    };
+    scrubber
+        .confirmations
+        .lock()
+        .unwrap()
+        .remove(&confirmation_id);
+    decision
+}
+
+/// Checks `text` against the current patterns without sending anything,
+/// for previewing them in the settings.
+#[tauri::command]
+pub fn scrub_prompt(scrubber: State<'_, Scrubber>, text: String) -> ScrubReport {
+    let findings = find(&scrubber.detectors.lock().unwrap(), 0, &text);
+    let redacted = redact(&text, &findings);
+    ScrubReport { findings, redacted }
+}
+
+/// Answers a `prompt-scrub-confirm` event. Returns whether the prompt was
+/// still waiting.
+#[tauri::command]
+pub fn confirm_scrubbed_prompt(
+    scrubber: State<'_, Scrubber>,
+    confirmation_id: String,
+    decision: ScrubDecision,
+) -> bool {
+    match scrubber
+        .confirmations
+        .lock()
+        .unwrap()
+        .remove(&confirmation_id)
+    {
+        Some(sender) => sender.send(decision).is_ok(),
+        None => false,
+    }
+}
+
+#[tauri::command]
+pub fn get_scrub_config(scrubber: State<'_, Scrubber>) -> ScrubConfig {
+    scrubber.config.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn set_scrub_config(
+    app: AppHandle,
+    scrubber: State<'_, Scrubber>,
+    config: ScrubConfig,
+) -> Result<ScrubConfig> {
+    let compiled = detectors(&config)?;
+    config::write(&app, CONFIG_FILE, &config)?;
+    *scrubber.detectors.lock().unwrap() = compiled;
+    *scrubber.config.lock().unwrap() = config.clone();
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_custom(custom: &[(&str, &str)]) -> ScrubConfig {
+        ScrubConfig {
+            custom: custom
+                .iter()
+                .map(|(name, pattern)| CustomPattern {
+                    name: name.to_string(),
+                    pattern: pattern.to_string(),
+                })
+                .collect(),
+            ..ScrubConfig::default()
+        }
+    }
+
+    /// What is found in `text` and the text with it replaced.
+    fn scan(config: &ScrubConfig, text: &str) -> (Vec<Finding>, String) {
+        let findings = find(&detectors(config).unwrap(), 0, text);
+        let redacted = redact(text, &findings);
+        (findings, redacted)
+    }
+
+    fn kinds(findings: &[Finding]) -> Vec<&str> {
+        findings.iter().map(|f| f.kind.as_str()).collect()
+    }
+
+    #[test]
+    fn finds_the_builtin_kinds() {
+        let text = "mail bob@example.com the key sk-proj-abcdefghijklmnopqrstuvwx please";
+        let (findings, redacted) = scan(&ScrubConfig::default(), text);
+        assert_eq!(kinds(&findings), ["email", "api_key"]);
+        assert_eq!(redacted, "mail [EMAIL] the key [API_KEY] please");
+    }
+
+    #[test]
+    fn checks_card_numbers() {
+        assert!(luhn("4111 1111 1111 1111"));
+        assert!(luhn("79927398713"));
+        assert!(!luhn("4111111111111112"));
+        let text = "pay 4111-1111-1111-1111, not 1234 5678 9012 3456";
+        let (findings, redacted) = scan(&ScrubConfig::default(), text);
+        assert_eq!(kinds(&findings), ["credit_card"]);
+        assert_eq!(redacted, "pay [CREDIT_CARD], not 1234 5678 9012 3456");
+    }
+
+    #[test]
+    fn keeps_the_first_of_overlapping_matches() {
+        let config = with_custom(&[("person", "alice"), ("domain", r"example\.com now")]);
+        let text = "to alice@example.com now";
+        let (findings, redacted) = scan(&config, text);
+        assert_eq!(kinds(&findings), ["email"]);
+        assert_eq!(redacted, "to [EMAIL] now");
+
+        // The longer of two that start together.
+        let config = with_custom(&[("short", "abc"), ("long", "abcdef")]);
+        assert_eq!(scan(&config, "xabcdefx").1, "x[LONG]x");
+    }
+
+    #[test]
+    fn handles_multibyte_text() {
+        let config = with_custom(&[("word", "Grüße")]);
+        let text = "Grüße an bob@example.com — danke 🙂";
+        let (findings, redacted) = scan(&config, text);
+        assert_eq!(redacted, "[WORD] an [EMAIL] — danke 🙂");
+        for finding in &findings {
+            assert!(text.is_char_boundary(finding.start));
+            assert!(text.is_char_boundary(finding.end));
+        }
+        assert_eq!(mask("ñandú-secret-ñandú"), "ñan••••••••••••ndú");
+        assert_eq!(mask("ß"), "•");
+    }
+
+    #[test]
+    fn takes_custom_patterns() {
+        let config = ScrubConfig {
+            emails: false,
+            api_keys: false,
+            credit_cards: false,
+            ..with_custom(&[("project code", r"\bPRJ-\d{4}\b")])
+        };
+        let (findings, redacted) = scan(&config, "see PRJ-1234 and PRJ-12");
+        assert_eq!(kinds(&findings), ["project code"]);
+        assert_eq!(redacted, "see [PROJECT_CODE] and PRJ-12");
+    }
+
+    #[test]
+    fn refuses_bad_custom_patterns() {
+        assert!(detectors(&with_custom(&[(" ", "x")])).is_err());
+        assert!(detectors(&with_custom(&[("broken", "(")])).is_err());
+        assert!(detectors(&with_custom(&[("empty", "a*")])).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! Full-text search over stored messages, backed by the `messages_fts` FTS5
+//! table that storage keeps in sync. `#tag` terms in a query narrow it to
+//! messages with those tags; a query of tags alone lists them, newest
+//! first.
+
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::bookmarks::{message_tags, normalize_tag};
+use crate::error::Result;
+use crate::llm::Role;
+use crate::storage::Database;
+
+const DEFAULT_LIMIT: u32 = 50;
+// Control characters never appear in chat text, so they make safe snippet
+// delimiters that are split into spans before reaching the frontend.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+/// Characters of a message shown for a hit that matched on tags alone.
+const PREVIEW_LEN: u32 = 160;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SearchQuery {
+    pub query: String,
+    pub provider: Option<String>,
+    pub conversation_id: Option<String>,
+    /// Inclusive lower bound, in epoch milliseconds.
+    pub from: Option<i64>,
+    /// Inclusive upper bound, in epoch milliseconds.
+    pub to: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SnippetSpan {
+    pub text: String,
+    pub highlight: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub message_id: String,
+    pub conversation_id: String,
+    pub conversation_title: String,
+    pub role: Role,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub created_at: i64,
+    pub snippet: Vec<SnippetSpan>,
+    /// BM25 score; lower is more relevant. Zero for a search by tags
+    /// alone.
+    pub rank: f64,
+    pub tags: Vec<String>,
+    pub bookmarked: bool,
+}
+
+/// Splits the `#tag` terms off a query.
+fn split_tags(input: &str) -> (Vec<&str>, Vec<String>) {
+    let (tags, terms): (Vec<&str>, Vec<&str>) = input
+        .split_whitespace()
+        .partition(|term| term.len() > 1 && term.starts_with('#'));
+    let mut tags: Vec<String> = tags.into_iter().filter_map(normalize_tag).collect();
+    tags.sort();
+    tags.dedup();
+    (terms, tags)
+}
+
+/// Quotes every term so user input can't produce FTS5 syntax errors, and
+/// makes the last term a prefix match for search-as-you-type.
+fn fts_query(terms: &[&str]) -> Option<String> {
+    let terms: Vec<String> = terms
+        .iter()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+    if terms.is_empty() {
+        return None;
+    }
+    Some(format!("{}*", terms.join(" ")))
+}
+
+fn split_snippet(snippet: &str) -> Vec<SnippetSpan> {
+    let mut spans = Vec::new();
+    let mut rest = snippet;
+    while let Some(start) = rest.find(MATCH_START) {
+        if start > 0 {
+            spans.push(SnippetSpan {
+                text: rest[..start].to_string(),
+                highlight: false,
+            });
+        }
+        let after = &rest[start + MATCH_START.len_utf8()..];
+        let end = after.find(MATCH_END).unwrap_or(after.len());
+        spans.push(SnippetSpan {
+            text: after[..end].to_string(),
+            highlight: true,
+        });
+        rest = after.get(end + MATCH_END.len_utf8()..).unwrap_or("");
+    }
+    if !rest.is_empty() {
+        spans.push(SnippetSpan {
+            text: rest.to_string(),
+            highlight: false,
+        });
+    }
+    spans
+}
+
+impl Database {
+    /// Reads the index through once so the first search doesn't pay for
+    /// pulling it off disk.
+    pub(crate) fn warm_search_index(&self) -> Result<()> {
+        if self.is_locked() {
+            return Ok(());
+        }
+        let conn = self.conn()?;
+        conn.query_row(
+            "SELECT COUNT(*), SUM(LENGTH(block)) FROM messages_fts_data",
+            [],
+            |_| Ok(()),
+        )?;
+        conn.query_row("SELECT COUNT(*) FROM messages_fts_idx", [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    pub fn search_messages(&self, query: &SearchQuery) -> Result<Vec<SearchHit>> {
+        let (terms, tags) = split_tags(&query.query);
+        let fts = fts_query(&terms);
+        if fts.is_none() && tags.is_empty() {
+            return Ok(Vec::new());
+        }
+        let filters = "(?2 IS NULL OR m.provider = ?2)
+               AND (?3 IS NULL OR m.conversation_id = ?3)
+               AND (?4 IS NULL OR m.created_at >= ?4)
+               AND (?5 IS NULL OR m.created_at <= ?5)
+               AND (?7 = 0 OR m.id IN (
+                   SELECT message_id FROM message_tags
+                   WHERE tag IN (SELECT value FROM json_each(?8))
+                   GROUP BY message_id HAVING COUNT(*) = ?7))";
+        let bookmarked = "EXISTS (SELECT 1 FROM bookmarks b WHERE b.message_id = m.id)";
+        let sql = match fts {
+            Some(_) => format!(
+                "SELECT m.id, m.conversation_id, c.title, m.role, m.provider, m.model, m.created_at,
+                        snippet(messages_fts, 0, '{MATCH_START}', '{MATCH_END}', '…', 16),
+                        bm25(messages_fts) AS rank, {bookmarked}
+                 FROM messages_fts
+                 JOIN messages m ON m.rowid = messages_fts.rowid
+                 JOIN conversations c ON c.id = m.conversation_id
+                 WHERE messages_fts MATCH ?1 AND {filters}
+                 ORDER BY rank
+                 LIMIT ?6"
+            ),
+            None => format!(
+                "SELECT m.id, m.conversation_id, c.title, m.role, m.provider, m.model, m.created_at,
+                        substr(m.content, 1, {PREVIEW_LEN}), 0.0, {bookmarked}
+                 FROM messages m
+                 JOIN conversations c ON c.id = m.conversation_id
+                 WHERE (?1 IS NULL) AND {filters}
+                 ORDER BY m.created_at DESC
+                 LIMIT ?6"
+            ),
+        };
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(
+            params![
+                fts,
+                query.provider,
+                query.conversation_id,
+                query.from,
+                query.to,
+                query.limit.unwrap_or(DEFAULT_LIMIT),
+                tags.len(),
+                serde_json::to_string(&tags)?
+            ],
+            |row| {
+                let snippet: String = row.get(7)?;
+                Ok(SearchHit {
+                    message_id: row.get(0)?,
+                    conversation_id: row.get(1)?,
+                    conversation_title: row.get(2)?,
+                    role: row.get(3)?,
+                    provider: row.get(4)?,
+                    model: row.get(5)?,
+                    created_at: row.get(6)?,
+                    snippet: split_snippet(&snippet),
+                    rank: row.get(8)?,
+                    tags: Vec::new(),
+                    bookmarked: row.get(9)?,
+                })
+            },
+        )?;
+        let mut hits = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        for hit in &mut hits {
+            hit.tags = message_tags(&conn, &hit.message_id)?;
+        }
+        Ok(hits)
+    }
+}
+
+#[tauri::command]
+pub async fn search_messages(
+    db: State<'_, Database>,
+    query: SearchQuery,
+) -> Result<Vec<SearchHit>> {
+    db.search_messages(&query)
+}
@@ -0,0 +1,148 @@
+//! What the user had open, kept on disk so a crash, forced quit or update
+//! picks up where it left off: the open conversations, where each was
+//! scrolled to and any prompt not yet sent. The frontend reports changes as
+//! they happen; they are written a moment after the last one, and right
+//! away on a clean exit.
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::config;
+use crate::error::Result;
+use crate::incognito;
+use crate::storage::{now_ms, Database};
+
+const SESSION_FILE: &str = "session.json";
+/// How long changes have to settle before they are written.
+const SAVE_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    /// Conversations open as tabs, in tab order.
+    pub open_conversations: Vec<String>,
+    pub active_conversation: Option<String>,
+    /// Scroll offset of each open conversation, in pixels.
+    pub scroll_positions: BTreeMap<String, f64>,
+    /// Unsent prompts by conversation; `new` for one not started yet.
+    pub drafts: BTreeMap<String, String>,
+    pub saved_at: Option<i64>,
+}
+
+impl Session {
+    /// Drops what no longer applies: empty drafts, and conversations that
+    /// are gone unless a draft is still waiting in them.
+    fn prune(&mut self, exists: impl Fn(&str) -> bool) {
+        self.drafts.retain(|_, draft| !draft.trim().is_empty());
+        self.open_conversations.retain(|id| exists(id));
+        let open: HashSet<&String> = self.open_conversations.iter().collect();
+        self.scroll_positions.retain(|id, _| open.contains(id));
+        self.drafts.retain(|id, _| id == "new" || exists(id));
+        if self
+            .active_conversation
+            .as_ref()
+            .is_some_and(|id| !open.contains(id))
+        {
+            self.active_conversation = None;
+        }
+    }
+
+    /// Leaves out incognito conversations, which mustn't outlive their
+    /// window.
+    fn without_incognito(mut self) -> Self {
+        self.open_conversations
+            .retain(|id| !incognito::is_ephemeral(id));
+        self.scroll_positions
+            .retain(|id, _| !incognito::is_ephemeral(id));
+        self.drafts.retain(|id, _| !incognito::is_ephemeral(id));
+        if self
+            .active_conversation
+            .as_deref()
+            .is_some_and(incognito::is_ephemeral)
+        {
+            self.active_conversation = None;
+        }
+        self
+    }
+}
+
+/// Managed as Tauri state.
+#[derive(Default)]
+pub struct Sessions {
+    current: Mutex<Session>,
+    /// Bumped on every change so only the last of a burst is written.
+    changes: AtomicU64,
+    /// Changes not written yet.
+    dirty: Mutex<bool>,
+}
+
+pub fn init(app: &AppHandle) {
+    let session = config::read::<Session>(app, SESSION_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(Sessions {
+        current: Mutex::new(session),
+        ..Default::default()
+    });
+}
+
+/// Writes pending changes now. Called on exit.
+pub fn flush(app: &AppHandle) {
+    let Some(sessions) = app.try_state::<Sessions>() else {
+        return;
+    };
+    let mut dirty = sessions.dirty.lock().unwrap();
+    if !*dirty {
+        return;
+    }
+    let mut session = sessions.current.lock().unwrap().clone();
+    session.saved_at = Some(now_ms());
+    match config::write(app, SESSION_FILE, &session) {
+        Ok(()) => *dirty = false,
+        Err(err) => tracing::warn!("couldn't save the session: {err}"),
+    }
+}
+
+/// The session as it was last saved, less conversations deleted since. The
+/// frontend calls this once on startup to reopen its tabs and drafts.
+#[tauri::command]
+pub fn restore_session(db: State<'_, Database>, sessions: State<'_, Sessions>) -> Result<Session> {
+    let existing: HashSet<String> = db
+        .list_conversations()?
+        .into_iter()
+        .map(|conversation| conversation.id)
+        .collect();
+    let mut session = sessions.current.lock().unwrap().clone();
+    session.prune(|id| existing.contains(id));
+    Ok(session)
+}
+
+/// Replaces the session with what the frontend has open. Cheap to call on
+/// every keystroke or scroll; the write is debounced.
+#[tauri::command]
+pub fn update_session(app: AppHandle, sessions: State<'_, Sessions>, session: Session) {
+    *sessions.current.lock().unwrap() = session.without_incognito();
+    *sessions.dirty.lock().unwrap() = true;
+    let generation = sessions.changes.fetch_add(1, Ordering::Relaxed) + 1;
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(SAVE_DELAY).await;
+        if app.state::<Sessions>().changes.load(Ordering::Relaxed) == generation {
+            flush(&app);
+        }
+    });
+}
+
+/// Forgets the saved session, for starting over with nothing open.
+#[tauri::command]
+pub fn clear_session(app: AppHandle, sessions: State<'_, Sessions>) -> Result<()> {
+    *sessions.current.lock().unwrap() = Session::default();
+    *sessions.dirty.lock().unwrap() = false;
+    sessions.changes.fetch_add(1, Ordering::Relaxed);
+    config::remove(&app, SESSION_FILE)
+}
@@ -0,0 +1,240 @@
+//! App settings, kept in one typed file in the config directory so every
+//! window sees the same values. The file records its schema version; older
+//! files are brought up to date by `MIGRATIONS` when loaded.
+
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::app_menu;
+use crate::config;
+use crate::error::{Error, Result};
+use crate::routing::RoutingSettings;
+use crate::updater::UpdateChannel;
+
+const SETTINGS_FILE: &str = "settings.json";
+
+type Migration = fn(&mut Map<String, Value>);
+
+/// Upgrades from each schema version to the next, applied in order. A file
+/// without a version is version 0. Append new entries; never edit one that
+/// has shipped.
+const MIGRATIONS: &[Migration] = &[
+    // 0 -> 1: settings carried over from the webview used camelCase keys.
+    camel_to_snake_keys,
+];
+
+const SCHEMA_VERSION: usize = MIGRATIONS.len();
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Theme {
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub theme: Theme,
+    /// Base font size of the conversation view, in points.
+    pub font_size: u32,
+    /// Enter sends the prompt; otherwise it needs Ctrl/Cmd+Enter.
+    pub send_on_enter: bool,
+    pub show_token_counts: bool,
+    pub default_provider: Option<String>,
+    pub default_model: Option<String>,
+    /// Providers left out when a fan-out doesn't name its targets.
+    pub disabled_providers: Vec<String>,
+    /// Prepended to new conversations.
+    pub system_prompt: Option<String>,
+    pub temperature: Option<f32>,
+    /// UI language; `None` follows the system.
+    pub language: Option<String>,
+    pub update_channel: UpdateChannel,
+    /// Download updates in the background and install them on quit.
+    pub auto_update: bool,
+    /// Which providers a fan-out goes to by what the prompt is about.
+    pub routing: RoutingSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            theme: Theme::System,
+            font_size: 14,
+            send_on_enter: true,
+            show_token_counts: true,
+            default_provider: None,
+            default_model: None,
+            disabled_providers: Vec::new(),
+            system_prompt: None,
+            temperature: None,
+            language: None,
+            update_channel: UpdateChannel::Stable,
+            auto_update: false,
+            routing: RoutingSettings::default(),
+        }
+    }
+}
+
+impl Settings {
+    fn check(&self) -> Result<()> {
+        if !(8..=48).contains(&self.font_size) {
+            return Err(Error::InvalidSetting(format!(
+                "font size {} is outside 8-48",
+                self.font_size
+            )));
+        }
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                return Err(Error::InvalidSetting(format!(
+                    "temperature {temperature} is outside 0-2"
+                )));
+            }
+        }
+        self.routing.check()
+    }
+}
+
+/// The file's layout: the settings plus the schema version they follow.
+#[derive(Serialize)]
+struct Stored<'a> {
+    version: usize,
+    #[serde(flatten)]
+    settings: &'a Settings,
+}
+
+/// Current settings. Managed as Tauri state.
+pub struct SettingsStore(Mutex<Settings>);
+
+impl SettingsStore {
+    pub fn get(&self) -> Settings {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+fn camel_to_snake_keys(map: &mut Map<String, Value>) {
+    *map = std::mem::take(map)
+        .into_iter()
+        .map(|(key, value)| {
+            let mut snake = String::with_capacity(key.len() + 4);
+            for c in key.chars() {
+                if c.is_ascii_uppercase() {
+                    snake.push('_');
+                }
+                snake.push(c.to_ascii_lowercase());
+            }
+            (snake, value)
+        })
+        .collect();
+}
+
+fn save(app: &AppHandle, settings: &Settings) -> Result<()> {
+    config::write(
+        app,
+        SETTINGS_FILE,
+        &Stored {
+            version: SCHEMA_VERSION,
+            settings,
+        },
+    )
+}
+
+/// Reads the settings file, migrating and rewriting it if it is older than
+/// this build. Files from a newer build are read as far as they can be.
+fn load(app: &AppHandle) -> Result<Settings> {
+    let Some(mut map) = config::read::<Map<String, Value>>(app, SETTINGS_FILE)? else {
+        return Ok(Settings::default());
+    };
+    let version = map
+        .remove("version")
+        .and_then(|v| v.as_u64())
+        .unwrap_or_default() as usize;
+    for migration in MIGRATIONS.iter().skip(version) {
+        migration(&mut map);
+    }
+    let settings: Settings = serde_json::from_value(Value::Object(map))?;
+    if version < SCHEMA_VERSION {
+        save(app, &settings)?;
+    }
+    Ok(settings)
+}
+
+/// Loads the settings, falling back to defaults if the file is unreadable.
+pub fn init(app: &AppHandle) {
+    let settings = load(app).unwrap_or_default();
+    app.manage(SettingsStore(Mutex::new(settings)));
+}
+
+#[tauri::command]
+pub fn get_settings(store: State<'_, SettingsStore>) -> Settings {
+    store.get()
+}
+
+/// Applies the fields present in `changes` (any subset of the settings; a
+/// `null` clears an optional one), saves, and broadcasts the result to every
+/// window as `settings-changed`.
+#[tauri::command]
+pub fn update_settings(
+    app: AppHandle,
+    store: State<'_, SettingsStore>,
+    changes: Map<String, Value>,
+) -> Result<Settings> {
+    let mut current = store.0.lock().unwrap();
+    let Value::Object(mut merged) = serde_json::to_value(&*current)? else {
+        unreachable!("settings serialize to an object");
+    };
+    for (key, value) in changes {
+        if !merged.contains_key(&key) {
+            return Err(Error::InvalidSetting(format!("unknown setting {key}")));
+        }
+        merged.insert(key, value);
+    }
+    let updated: Settings = serde_json::from_value(Value::Object(merged))
+        .map_err(|e| Error::InvalidSetting(e.to_string()))?;
+    updated.check()?;
+    save(&app, &updated)?;
+    *current = updated.clone();
+    drop(current);
+    changed(&app, &updated);
+    Ok(updated)
+}
+
+fn changed(app: &AppHandle, settings: &Settings) {
+    app_menu::sync(app, settings);
+    let _ = app.emit("settings-changed", settings);
+}
+
+/// Includes `provider` in fan-outs or leaves it out, for the Model menu.
+pub fn set_provider_enabled(app: &AppHandle, provider: &str, enabled: bool) -> Result<Settings> {
+    modify(app, |settings| {
+        settings.disabled_providers.retain(|p| p != provider);
+        if !enabled {
+            settings.disabled_providers.push(provider.to_string());
+        }
+        Ok(())
+    })
+}
+
+/// Changes the settings from the backend, saving and broadcasting them like
+/// `update_settings`.
+pub(crate) fn modify(
+    app: &AppHandle,
+    change: impl FnOnce(&mut Settings) -> Result<()>,
+) -> Result<Settings> {
+    let store = app.state::<SettingsStore>();
+    let mut current = store.0.lock().unwrap();
+    let mut updated = current.clone();
+    change(&mut updated)?;
+    updated.check()?;
+    save(app, &updated)?;
+    *current = updated.clone();
+    drop(current);
+    changed(app, &updated);
+    Ok(updated)
+}
@@ -0,0 +1,427 @@
+//! Conversations as a single HTML file for sending to someone who doesn't
+//! have the app. Everything the page needs is inside it: styles, code
+//! highlighting done here rather than by a script, and images as data URLs,
+//! so it opens the same from an email attachment or a static host.
+//!
+//! The page is `share.html` filled in through the prompt template engine;
+//! fan-outs get the side-by-side columns the other exports use.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use serde::Deserialize;
+use tauri::{AppHandle, State};
+
+use crate::attachments;
+use crate::error::{Error, Result};
+use crate::export::{blocks, speaker, Block};
+use crate::images::GeneratedImage;
+use crate::llm::Role;
+use crate::markdown::{self, escape_html, HtmlHooks};
+use crate::storage::conversations::Message;
+use crate::storage::{now_ms, Database};
+use crate::templates;
+
+const PAGE: &str = include_str!("share.html");
+/// Larger images are left out so the page stays small enough to mail.
+const MAX_INLINE_IMAGE: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ShareOptions {
+    /// Leave out system prompts.
+    pub skip_system: bool,
+    /// Leave out generated and embedded images.
+    pub skip_images: bool,
+}
+
+/// Keywords worth coloring, by the languages a fence can name.
+fn keywords(language: &str) -> &'static [&'static str] {
+    match language {
+        "rust" | "rs" => &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+            "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true",
+            "type", "unsafe", "use", "where", "while",
+        ],
+        "js" | "javascript" | "jsx" | "ts" | "typescript" | "tsx" => &[
+            "async",
+            "await",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "delete",
+            "else",
+            "export",
+            "extends",
+            "false",
+            "finally",
+            "for",
+            "from",
+            "function",
+            "if",
+            "import",
+            "in",
+            "instanceof",
+            "interface",
+            "let",
+            "new",
+            "null",
+            "of",
+            "return",
+            "switch",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "type",
+            "typeof",
+            "undefined",
+            "var",
+            "void",
+            "while",
+            "yield",
+        ],
+        "py" | "python" => &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "False", "finally", "for", "from", "if", "import", "in",
+            "is", "lambda", "None", "not", "or", "pass", "raise", "return", "True", "try", "while",
+            "with", "yield",
+        ],
+        "go" => &[
+            "break",
+            "case",
+            "chan",
+            "const",
+            "continue",
+            "default",
+            "defer",
+            "else",
+            "false",
+            "for",
+            "func",
+            "go",
+            "if",
+            "import",
+            "interface",
+            "map",
+            "nil",
+            "package",
+            "range",
+            "return",
+            "select",
+            "struct",
+            "switch",
+            "true",
+            "type",
+            "var",
+        ],
+        "c" | "cpp" | "c++" | "h" | "java" | "kotlin" | "cs" | "csharp" | "swift" => &[
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "do",
+            "else",
+            "enum",
+            "false",
+            "final",
+            "for",
+            "if",
+            "import",
+            "include",
+            "let",
+            "namespace",
+            "new",
+            "null",
+            "nullptr",
+            "private",
+            "protected",
+            "public",
+            "return",
+            "static",
+            "struct",
+            "switch",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "using",
+            "var",
+            "void",
+            "while",
+        ],
+        "sh" | "bash" | "zsh" | "shell" | "console" => &[
+            "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+            "in", "local", "return", "then", "until", "while",
+        ],
+        "sql" => &[
+            "and", "as", "by", "create", "delete", "from", "group", "having", "insert", "into",
+            "join", "left", "limit", "not", "null", "on", "or", "order", "select", "set", "table",
+            "update", "values", "where",
+        ],
+        _ => &[],
+    }
+}
+
+/// What starts a line comment in `language`.
+fn line_comment(language: &str) -> Option<&'static str> {
+    match language {
+        "py" | "python" | "sh" | "bash" | "zsh" | "shell" | "rb" | "ruby" | "yaml" | "yml"
+        | "toml" => Some("#"),
+        "sql" | "lua" | "haskell" => Some("--"),
+        "" | "text" | "txt" | "markdown" | "md" | "json" => None,
+        _ => Some("//"),
+    }
+}
+
+fn span(class: &str, text: &str) -> String {
+    format!("<span class=\"tok-{class}\">{}</span>", escape_html(text))
+}
+
+/// Colors keywords, strings, comments and numbers. Good enough to read,
+/// with no grammar behind it.
+fn highlight(language: Option<&str>, code: &str) -> String {
+    let language = language.unwrap_or_default().to_ascii_lowercase();
+    let keywords = keywords(&language);
+    let case_insensitive = language == "sql";
+    let comment = line_comment(&language);
+    let block_comments = comment == Some("//");
+    let mut out = String::with_capacity(code.len() * 2);
+    let mut rest = code;
+    while let Some(ch) = rest.chars().next() {
+        if comment.is_some_and(|marker| rest.starts_with(marker)) {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            out.push_str(&span("c", &rest[..end]));
+            rest = &rest[end..];
+            continue;
+        }
+        if block_comments && rest.starts_with("/*") {
+            let end = rest[2..].find("*/").map_or(rest.len(), |i| i + 4);
+            out.push_str(&span("c", &rest[..end]));
+            rest = &rest[end..];
+            continue;
+        }
+        if matches!(ch, '"' | '\'' | '`') && !keywords.is_empty() {
+            let mut end = 1;
+            let mut escaped = false;
+            for (i, c) in rest.char_indices().skip(1) {
+                end = i + c.len_utf8();
+                if c == '\n' && ch != '`' {
+                    break;
+                }
+                if !escaped && c == ch {
+                    break;
+                }
+                escaped = c == '\\' && !escaped;
+            }
+            out.push_str(&span("s", &rest[..end]));
+            rest = &rest[end..];
+            continue;
+        }
+        if ch.is_alphanumeric() || ch == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            let keyword = keywords.iter().any(|k| {
+                if case_insensitive {
+                    k.eq_ignore_ascii_case(word)
+                } else {
+                    *k == word
+                }
+            });
+            if keyword {
+                out.push_str(&span("k", word));
+            } else if ch.is_ascii_digit() && !keywords.is_empty() {
+                out.push_str(&span("n", word));
+            } else {
+                out.push_str(&escape_html(word));
+            }
+            rest = &rest[end..];
+            continue;
+        }
+        out.push_str(&escape_html(&ch.to_string()));
+        rest = &rest[ch.len_utf8()..];
+    }
+    out
+}
+
+/// An image file as a data URL, if it is one we know and not too large.
+fn data_url(path: &Path) -> Option<String> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let mime = attachments::image_mime(&extension)?;
+    if std::fs::metadata(path).ok()?.len() > MAX_INLINE_IMAGE {
+        return None;
+    }
+    let bytes = std::fs::read(path).ok()?;
+    Some(format!(
+        "data:{mime};base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    ))
+}
+
+struct Hooks {
+    skip_images: bool,
+    /// Images may only be read from here, so a message can't pull in an
+    /// arbitrary file.
+    attachments: Option<PathBuf>,
+}
+
+impl HtmlHooks for Hooks {
+    fn code(&self, language: Option<&str>, code: &str) -> String {
+        highlight(language, code)
+    }
+
+    fn image_src(&self, url: &str) -> Option<String> {
+        if self.skip_images {
+            return None;
+        }
+        if url.starts_with("https://")
+            || url.starts_with("http://")
+            || url.starts_with("data:image/")
+        {
+            return Some(url.to_string());
+        }
+        let path = PathBuf::from(url.strip_prefix("file://").unwrap_or(url));
+        let path = std::fs::canonicalize(path).ok()?;
+        let allowed = self
+            .attachments
+            .as_ref()
+            .is_some_and(|dir| path.starts_with(dir));
+        allowed.then(|| data_url(&path)).flatten()
+    }
+}
+
+fn figure(image: &GeneratedImage) -> String {
+    match data_url(&image.path) {
+        Some(src) => format!(
+            "<figure><img src=\"{src}\" alt=\"{alt}\"><figcaption>{alt}</figcaption></figure>\n",
+            alt = escape_html(&image.prompt)
+        ),
+        None => String::new(),
+    }
+}
+
+fn message_html(
+    message: &Message,
+    hooks: &Hooks,
+    images: &HashMap<String, Vec<GeneratedImage>>,
+) -> String {
+    let role = match message.role {
+        Role::User => "user",
+        Role::Assistant => "assistant",
+        Role::System => "system",
+    };
+    let figures: String = images
+        .get(&message.id)
+        .into_iter()
+        .flatten()
+        .map(figure)
+        .collect();
+    format!(
+        "<div class=\"message {role}\">\n<div class=\"speaker\">{}</div>\n<div class=\"content\">\n{}{figures}</div>\n</div>\n",
+        escape_html(&speaker(message)),
+        markdown::to_html(&message.content, hooks)
+    )
+}
+
+fn format_date(ms: i64) -> String {
+    chrono::DateTime::from_timestamp_millis(ms)
+        .map(|date| date.format("%B %-d, %Y %H:%M UTC").to_string())
+        .unwrap_or_default()
+}
+
+/// Renders conversation `id` as a complete page.
+pub fn render(app: &AppHandle, db: &Database, id: &str, options: &ShareOptions) -> Result<String> {
+    let detail = db.get_conversation(id)?;
+    let hooks = Hooks {
+        skip_images: options.skip_images,
+        attachments: attachments::dir(app)
+            .ok()
+            .and_then(|dir| std::fs::canonicalize(dir).ok()),
+    };
+    let mut images: HashMap<String, Vec<GeneratedImage>> = HashMap::new();
+    if !options.skip_images {
+        for image in db.list_generated_images(Some(id))?.into_iter().rev() {
+            if let Some(message_id) = image.message_id.clone() {
+                images.entry(message_id).or_default().push(image);
+            }
+        }
+    }
+    let messages: Vec<Message> = detail
+        .messages
+        .iter()
+        .filter(|m| !(options.skip_system && m.role == Role::System))
+        .cloned()
+        .collect();
+
+    let mut body = String::new();
+    let mut models = HashSet::new();
+    for block in blocks(&messages) {
+        match block {
+            Block::Single(message) => {
+                models.extend(message.model.clone());
+                body.push_str("<section class=\"turn\">\n");
+                body.push_str(&message_html(message, &hooks, &images));
+                body.push_str("</section>\n");
+            }
+            Block::Columns(columns) => {
+                body.push_str("<section class=\"turn columns\">\n");
+                for message in columns {
+                    models.extend(message.model.clone());
+                    body.push_str(&message_html(message, &hooks, &images));
+                }
+                body.push_str("</section>\n");
+            }
+        }
+    }
+
+    let mut models: Vec<String> = models.into_iter().collect();
+    models.sort();
+    let mut meta = format!(
+        "{} messages · started {}",
+        messages.len(),
+        format_date(detail.conversation.created_at)
+    );
+    if !models.is_empty() {
+        meta.push_str(&format!(" · {}", models.join(", ")));
+    }
+    let values = HashMap::from([
+        ("title".to_string(), escape_html(&detail.conversation.title)),
+        ("meta".to_string(), escape_html(&meta)),
+        ("body".to_string(), body),
+        ("shared_at".to_string(), format_date(now_ms())),
+    ]);
+    let mut missing = Vec::new();
+    let page = templates::substitute(PAGE, &values, &mut missing);
+    if !missing.is_empty() {
+        return Err(Error::InvalidTemplate(format!(
+            "share page has no value for {}",
+            missing.join(", ")
+        )));
+    }
+    Ok(page)
+}
+
+/// Writes conversation `id` to `path` as one self-contained HTML file.
+#[tauri::command]
+pub async fn export_share_html(
+    app: AppHandle,
+    db: State<'_, Database>,
+    id: String,
+    path: PathBuf,
+    options: Option<ShareOptions>,
+) -> Result<PathBuf> {
+    let page = render(&app, &db, &id, &options.unwrap_or_default())?;
+    std::fs::write(&path, page)?;
+    Ok(path)
+}
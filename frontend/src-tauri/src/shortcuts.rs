@@ -0,0 +1,487 @@
+//! Every keyboard shortcut the app has, in one registry. Each has a default
+//! for macOS and one for Windows and Linux, which the user can rebind or
+//! clear; only the changes are saved. Global shortcuts are registered with
+//! the OS and handled here, menu ones become the menu's accelerators, and
+//! window ones are left to the frontend, which hears of every change as
+//! `shortcuts-changed`. No two shortcuts may share a binding, whatever
+//! their scope, since a global one fires in every window too.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+
+use crate::app_menu;
+use crate::config;
+use crate::error::{Error, Result};
+use crate::hotkey;
+use crate::windows;
+
+const CONFIG_FILE: &str = "shortcuts.json";
+/// Where the quick-prompt shortcut was kept before the registry.
+const LEGACY_CONFIG_FILE: &str = "hotkey.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShortcutScope {
+    /// Works from any app.
+    Global,
+    /// An accelerator of the app menu.
+    Menu,
+    /// Handled by the frontend while a window has focus.
+    Window,
+}
+
+struct Definition {
+    id: &'static str,
+    title: &'static str,
+    scope: ShortcutScope,
+    macos: Option<&'static str>,
+    other: Option<&'static str>,
+}
+
+impl Definition {
+    fn default_binding(&self) -> Option<&'static str> {
+        if cfg!(target_os = "macos") {
+            self.macos
+        } else {
+            self.other
+        }
+    }
+}
+
+const fn shortcut(
+    id: &'static str,
+    title: &'static str,
+    scope: ShortcutScope,
+    macos: Option<&'static str>,
+    other: Option<&'static str>,
+) -> Definition {
+    Definition {
+        id,
+        title,
+        scope,
+        macos,
+        other,
+    }
+}
+
+/// Ids of window shortcuts are the actions `menu-action` and the palette
+/// use.
+const DEFINITIONS: &[Definition] = &[
+    shortcut(
+        "quick_prompt",
+        "Show Quick Prompt",
+        ShortcutScope::Global,
+        Some("Alt+Space"),
+        Some("CommandOrControl+Shift+Space"),
+    ),
+    shortcut(
+        "toggle_main_window",
+        "Show or Hide Pentamind",
+        ShortcutScope::Global,
+        None,
+        None,
+    ),
+    shortcut(
+        "new_conversation",
+        "New Conversation",
+        ShortcutScope::Menu,
+        Some("Cmd+N"),
+        Some("Ctrl+N"),
+    ),
+    shortcut(
+        "open_in_window",
+        "Open Conversation in New Window",
+        ShortcutScope::Menu,
+        Some("Cmd+Shift+N"),
+        Some("Ctrl+Shift+N"),
+    ),
+    shortcut(
+        "export",
+        "Export Conversation",
+        ShortcutScope::Menu,
+        Some("Cmd+E"),
+        Some("Ctrl+E"),
+    ),
+    shortcut(
+        "open_settings",
+        "Open Settings",
+        ShortcutScope::Menu,
+        Some("Cmd+,"),
+        Some("Ctrl+,"),
+    ),
+    shortcut(
+        "find",
+        "Find in Conversation",
+        ShortcutScope::Menu,
+        Some("Cmd+F"),
+        Some("Ctrl+F"),
+    ),
+    shortcut(
+        "zoom_in",
+        "Zoom In",
+        ShortcutScope::Menu,
+        Some("Cmd+="),
+        Some("Ctrl+="),
+    ),
+    shortcut(
+        "zoom_out",
+        "Zoom Out",
+        ShortcutScope::Menu,
+        Some("Cmd+-"),
+        Some("Ctrl+-"),
+    ),
+    shortcut(
+        "zoom_reset",
+        "Actual Size",
+        ShortcutScope::Menu,
+        Some("Cmd+0"),
+        Some("Ctrl+0"),
+    ),
+    shortcut(
+        "toggle_sidebar",
+        "Toggle Sidebar",
+        ShortcutScope::Menu,
+        Some("Cmd+\\"),
+        Some("Ctrl+\\"),
+    ),
+    shortcut(
+        "toggle_mini_window",
+        "Toggle Mini Window",
+        ShortcutScope::Menu,
+        Some("Cmd+Shift+M"),
+        Some("Ctrl+Shift+M"),
+    ),
+    shortcut(
+        "command_palette",
+        "Command Palette",
+        ShortcutScope::Window,
+        Some("Cmd+K"),
+        Some("Ctrl+K"),
+    ),
+    shortcut(
+        "search_messages",
+        "Search All Messages",
+        ShortcutScope::Window,
+        Some("Cmd+Shift+F"),
+        Some("Ctrl+Shift+F"),
+    ),
+    shortcut(
+        "compare_models",
+        "Compare Models",
+        ShortcutScope::Window,
+        None,
+        None,
+    ),
+    shortcut(
+        "start_recording",
+        "Start Dictation",
+        ShortcutScope::Window,
+        None,
+        None,
+    ),
+    shortcut(
+        "capture_screen",
+        "Capture Screen",
+        ShortcutScope::Window,
+        None,
+        None,
+    ),
+];
+
+#[derive(Deserialize)]
+struct LegacyConfig {
+    shortcut: String,
+}
+
+/// Changes from the defaults, by id; `None` has the shortcut cleared.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ShortcutConfig {
+    bindings: BTreeMap<String, Option<String>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShortcutInfo {
+    pub id: &'static str,
+    pub title: &'static str,
+    pub scope: ShortcutScope,
+    pub binding: Option<String>,
+    /// This platform's default.
+    pub default: Option<&'static str>,
+    pub customized: bool,
+    /// A global binding another app already holds.
+    pub unavailable: bool,
+}
+
+struct Registry {
+    config: ShortcutConfig,
+    /// Global shortcuts registered with the OS, by id.
+    registered: Vec<(&'static str, Shortcut)>,
+}
+
+/// Managed as Tauri state.
+pub struct Shortcuts(Mutex<Registry>);
+
+fn parse(binding: &str) -> Result<Shortcut> {
+    binding
+        .parse()
+        .map_err(|_| Error::InvalidShortcut(binding.to_string()))
+}
+
+fn definition(id: &str) -> Result<&'static Definition> {
+    DEFINITIONS
+        .iter()
+        .find(|d| d.id == id)
+        .ok_or_else(|| Error::NotFound(format!("shortcut {id}")))
+}
+
+impl Registry {
+    fn binding(&self, definition: &Definition) -> Option<String> {
+        match self.config.bindings.get(definition.id) {
+            Some(binding) => binding.clone(),
+            None => definition.default_binding().map(str::to_string),
+        }
+    }
+
+    /// The shortcut other than `except` already bound to `shortcut`.
+    fn conflict(&self, shortcut: Shortcut, except: &str) -> Option<&'static Definition> {
+        DEFINITIONS.iter().find(|d| {
+            d.id != except
+                && self
+                    .binding(d)
+                    .and_then(|b| parse(&b).ok())
+                    .is_some_and(|bound| bound == shortcut)
+        })
+    }
+
+    fn info(&self, definition: &'static Definition) -> ShortcutInfo {
+        ShortcutInfo {
+            id: definition.id,
+            title: definition.title,
+            scope: definition.scope,
+            binding: self.binding(definition),
+            default: definition.default_binding(),
+            customized: self.config.bindings.contains_key(definition.id),
+            unavailable: definition.scope == ShortcutScope::Global
+                && self.binding(definition).is_some()
+                && !self.registered.iter().any(|(id, _)| *id == definition.id),
+        }
+    }
+
+    fn list(&self) -> Vec<ShortcutInfo> {
+        DEFINITIONS.iter().map(|d| self.info(d)).collect()
+    }
+
+    /// Swaps the OS registration of a global shortcut over to `next`,
+    /// putting the old one back if the OS refuses.
+    fn register(
+        &mut self,
+        app: &AppHandle,
+        id: &'static str,
+        next: Option<Shortcut>,
+    ) -> Result<()> {
+        let global = app.global_shortcut();
+        let previous = self
+            .registered
+            .iter()
+            .position(|(registered, _)| *registered == id)
+            .map(|i| self.registered.remove(i).1);
+        if previous == next {
+            self.registered.extend(previous.map(|s| (id, s)));
+            return Ok(());
+        }
+        if let Some(previous) = previous {
+            global.unregister(previous)?;
+        }
+        let Some(next) = next else {
+            return Ok(());
+        };
+        if let Err(err) = global.register(next) {
+            if let Some(previous) = previous {
+                if global.register(previous).is_ok() {
+                    self.registered.push((id, previous));
+                }
+            }
+            return Err(err.into());
+        }
+        self.registered.push((id, next));
+        Ok(())
+    }
+}
+
+fn run_global(app: &AppHandle, id: &str) {
+    let result = match id {
+        "quick_prompt" => hotkey::toggle_quick_window(app),
+        "toggle_main_window" => {
+            windows::toggle_main_window(app);
+            Ok(())
+        }
+        _ => Ok(()),
+    };
+    if let Err(err) = result {
+        tracing::warn!("shortcut {id} failed: {err}");
+    }
+}
+
+/// Installs the global-shortcut plugin and registers the global bindings.
+/// One that no longer parses or is held by another app is skipped, and
+/// shows as unavailable.
+pub fn init(app: &AppHandle) -> Result<()> {
+    let mut config = config::read::<ShortcutConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    if let Some(legacy) = config::read::<LegacyConfig>(app, LEGACY_CONFIG_FILE)
+        .ok()
+        .flatten()
+    {
+        config
+            .bindings
+            .entry("quick_prompt".to_string())
+            .or_insert(Some(legacy.shortcut));
+        if config::write(app, CONFIG_FILE, &config).is_ok() {
+            let _ = config::remove(app, LEGACY_CONFIG_FILE);
+        }
+    }
+
+    app.plugin(
+        tauri_plugin_global_shortcut::Builder::new()
+            .with_handler(|app, shortcut, event| {
+                if event.state() != ShortcutState::Pressed {
+                    return;
+                }
+                let id = app
+                    .state::<Shortcuts>()
+                    .0
+                    .lock()
+                    .unwrap()
+                    .registered
+                    .iter()
+                    .find(|(_, registered)| registered == shortcut)
+                    .map(|(id, _)| *id);
+                if let Some(id) = id {
+                    run_global(app, id);
+                }
+            })
+            .build(),
+    )?;
+
+    let mut registry = Registry {
+        config,
+        registered: Vec::new(),
+    };
+    for definition in DEFINITIONS {
+        if definition.scope != ShortcutScope::Global {
+            continue;
+        }
+        let Some(binding) = registry.binding(definition) else {
+            continue;
+        };
+        let registered =
+            parse(&binding).and_then(|s| registry.register(app, definition.id, Some(s)));
+        if let Err(err) = registered {
+            tracing::warn!("couldn't register the {} shortcut: {err}", definition.id);
+        }
+    }
+    app.manage(Shortcuts(Mutex::new(registry)));
+    Ok(())
+}
+
+/// The current binding of `id`, for the menu and the palette.
+pub fn binding(app: &AppHandle, id: &str) -> Option<String> {
+    let definition = definition(id).ok()?;
+    match app.try_state::<Shortcuts>() {
+        Some(shortcuts) => shortcuts.0.lock().unwrap().binding(definition),
+        None => definition.default_binding().map(str::to_string),
+    }
+}
+
+fn changed(app: &AppHandle, registry: &Registry) -> Result<()> {
+    config::write(app, CONFIG_FILE, &registry.config)?;
+    app_menu::shortcuts_changed(app);
+    let _ = app.emit("shortcuts-changed", registry.list());
+    Ok(())
+}
+
+#[tauri::command]
+pub fn list_shortcuts(shortcuts: State<'_, Shortcuts>) -> Vec<ShortcutInfo> {
+    shortcuts.0.lock().unwrap().list()
+}
+
+/// Binds `id` to `binding`, or clears it with `None`. Fails if another
+/// shortcut already has the binding, or, for a global one, if the OS won't
+/// give it up; the old binding stays in either case.
+#[tauri::command]
+pub fn rebind_shortcut(
+    app: AppHandle,
+    shortcuts: State<'_, Shortcuts>,
+    id: String,
+    binding: Option<String>,
+) -> Result<ShortcutInfo> {
+    let definition = definition(&id)?;
+    let binding = binding
+        .map(|b| b.trim().to_string())
+        .filter(|b| !b.is_empty());
+    let parsed = binding.as_deref().map(parse).transpose()?;
+    let mut registry = shortcuts.0.lock().unwrap();
+    if let Some(other) = parsed.and_then(|s| registry.conflict(s, definition.id)) {
+        return Err(Error::InvalidSetting(format!(
+            "{} is already the shortcut for {}",
+            binding.unwrap_or_default(),
+            other.title
+        )));
+    }
+    if definition.scope == ShortcutScope::Global {
+        registry.register(&app, definition.id, parsed)?;
+    }
+    if binding.as_deref() == definition.default_binding() {
+        registry.config.bindings.remove(definition.id);
+    } else {
+        registry
+            .config
+            .bindings
+            .insert(definition.id.to_string(), binding);
+    }
+    changed(&app, &registry)?;
+    Ok(registry.info(definition))
+}
+
+/// Puts the listed shortcuts, or all of them, back to their defaults. A
+/// default that clashes with a binding kept from before is cleared.
+#[tauri::command]
+pub fn reset_shortcuts(
+    app: AppHandle,
+    shortcuts: State<'_, Shortcuts>,
+    ids: Option<Vec<String>>,
+) -> Result<Vec<ShortcutInfo>> {
+    let reset: Vec<&'static Definition> = match ids {
+        Some(ids) => ids.iter().map(|id| definition(id)).collect::<Result<_>>()?,
+        None => DEFINITIONS.iter().collect(),
+    };
+    let mut registry = shortcuts.0.lock().unwrap();
+    for definition in &reset {
+        registry.config.bindings.remove(definition.id);
+    }
+    for definition in &reset {
+        let default = definition.default_binding().and_then(|b| parse(b).ok());
+        let clash = default.and_then(|s| registry.conflict(s, definition.id));
+        if clash.is_some() {
+            registry
+                .config
+                .bindings
+                .insert(definition.id.to_string(), None);
+        }
+        if definition.scope == ShortcutScope::Global {
+            let next = if clash.is_some() { None } else { default };
+            if let Err(err) = registry.register(&app, definition.id, next) {
+                tracing::warn!("couldn't register the {} shortcut: {err}", definition.id);
+            }
+        }
+    }
+    changed(&app, &registry)?;
+    Ok(registry.list())
+}
@@ -0,0 +1,1224 @@
+//! Subscriptions to RSS and Atom feeds and to plain web pages. A background
+//! task fetches each source on its own interval: feeds conditionally, with
+//! new entries stored as items, and pages through `web::fetch`, with a new
+//! item whenever the readable text changes. Items are added to the RAG
+//! index as they arrive, and `summarize_new_items` fans the ones not yet
+//! summarized out to the models as a digest.
+//!
+//! Each refresh is announced with `source-refreshed`. Nothing is fetched
+//! while offline or with the database locked.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, USER_AGENT};
+use reqwest::{Client, StatusCode, Url};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, OptionalExtension, Row, ToSql};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::Notify;
+
+use crate::backup::hex;
+use crate::config;
+use crate::error::{Error, Result};
+use crate::fanout::{self, FanoutRequest, FanoutResult, FanoutTarget};
+use crate::llm::{ChatMessage, Role};
+use crate::offline;
+use crate::providers::priority::{self, Priority};
+use crate::providers::Providers;
+use crate::rag::{self, Embedder, EmbeddingOptions};
+use crate::startup;
+use crate::storage::{new_id, now_ms, Database};
+use crate::web::{self, FetchOptions};
+
+const CONFIG_FILE: &str = "sources.json";
+/// Longest sleep between checks, as in the scheduler.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_FEED_BYTES: usize = 5 * 1024 * 1024;
+const MIN_INTERVAL_MINUTES: u32 = 15;
+const MAX_INTERVAL_MINUTES: u32 = 7 * 24 * 60;
+const DEFAULT_INTERVAL_MINUTES: u32 = 60;
+/// Entries read from a feed per fetch, newest first. Older ones are left
+/// alone, so subscribing to a long archive doesn't index all of it.
+const MAX_NEW_ENTRIES: usize = 25;
+/// Items embedded per refresh; the rest wait for the next one.
+const MAX_INDEX_PER_REFRESH: usize = 25;
+const MAX_CONTENT_CHARS: usize = 100_000;
+const PREVIEW_LEN: usize = 280;
+const DIGEST_EXCERPT_CHARS: usize = 1500;
+const DEFAULT_DIGEST_ITEMS: usize = 30;
+const DIGEST_PROMPT: &str = "Summarize these new items from my subscriptions. Group related \
+    items, lead with what matters most and say which source each point comes from.";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SourcesConfig {
+    /// Where items are embedded; the RAG defaults when unset.
+    pub embedding_provider: Option<String>,
+    pub embedding_model: Option<String>,
+    /// Items kept per source, newest first. Older ones leave the index.
+    pub max_items: usize,
+}
+
+impl Default for SourcesConfig {
+    fn default() -> Self {
+        Self {
+            embedding_provider: None,
+            embedding_model: None,
+            max_items: 200,
+        }
+    }
+}
+
+impl SourcesConfig {
+    fn embedding(&self) -> EmbeddingOptions {
+        EmbeddingOptions {
+            provider: self.embedding_provider.clone(),
+            model: self.embedding_model.clone(),
+        }
+    }
+}
+
+/// Managed as Tauri state; wakes the fetcher when sources change.
+pub struct Sources {
+    config: Mutex<SourcesConfig>,
+    wake: Arc<Notify>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    /// RSS or Atom; each entry is an item.
+    Feed,
+    /// Any other page; its readable text is the item.
+    Page,
+}
+
+impl ToSql for SourceKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let kind = match self {
+            SourceKind::Feed => "feed",
+            SourceKind::Page => "page",
+        };
+        Ok(kind.into())
+    }
+}
+
+impl FromSql for SourceKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "feed" => Ok(SourceKind::Feed),
+            "page" => Ok(SourceKind::Page),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Source {
+    pub id: String,
+    pub name: String,
+    pub url: String,
+    pub kind: SourceKind,
+    pub interval_minutes: u32,
+    pub enabled: bool,
+    /// Why the last fetch or indexing failed.
+    pub error: Option<String>,
+    pub last_fetched_at: Option<i64>,
+    pub next_fetch_at: Option<i64>,
+    /// Items not yet in a digest.
+    pub new_items: usize,
+    pub created_at: i64,
+    pub updated_at: i64,
+    #[serde(skip)]
+    etag: Option<String>,
+    #[serde(skip)]
+    last_modified: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SourceInput {
+    pub url: String,
+    /// Taken from the feed or page title when empty.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Worked out from the response when unset. An HTML page that links a
+    /// feed is subscribed to through the feed.
+    #[serde(default)]
+    pub kind: Option<SourceKind>,
+    #[serde(default)]
+    pub interval_minutes: Option<u32>,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceItem {
+    pub source_id: String,
+    pub key: String,
+    pub title: String,
+    pub url: Option<String>,
+    pub published_at: Option<i64>,
+    pub preview: String,
+    pub fetched_at: i64,
+    pub indexed_at: Option<i64>,
+    pub digested_at: Option<i64>,
+}
+
+/// Payload of `source-refreshed`, and what `refresh_source` returns.
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceRefresh {
+    pub source_id: String,
+    pub new_items: usize,
+    pub indexed: usize,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DigestRequest {
+    /// Empty means every configured provider.
+    pub targets: Vec<FanoutTarget>,
+    /// Empty means every source.
+    pub source_ids: Vec<String>,
+    /// Replaces the default instructions ahead of the items.
+    pub instructions: Option<String>,
+    pub max_items: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Digest {
+    /// The items summarized, newest first.
+    pub items: Vec<SourceItem>,
+    pub results: Vec<FanoutResult>,
+}
+
+/// An item as fetched, before it is stored.
+#[derive(Debug, Clone, Default)]
+struct NewItem {
+    key: String,
+    title: String,
+    url: Option<String>,
+    published_at: Option<i64>,
+    content: String,
+}
+
+/// The stored item with its full text, for indexing and digests.
+struct StoredItem {
+    item: SourceItem,
+    content: String,
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::InvalidSetting(message.into())
+}
+
+/// The item's document in the RAG index; the schema's delete trigger
+/// builds the same name.
+fn document_source(source_id: &str, key: &str) -> String {
+    format!("source:{source_id}/{key}")
+}
+
+fn item_key(value: &str) -> String {
+    hex(&Sha256::digest(value.as_bytes())[..16])
+}
+
+fn truncate_chars(text: &str, max: usize) -> &str {
+    match text.char_indices().nth(max) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+fn preview(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(PREVIEW_LEN) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+fn clean(text: &str) -> Option<String> {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+/// A feed's title and entries.
+#[derive(Debug, Default)]
+struct Feed {
+    title: Option<String>,
+    entries: Vec<NewItem>,
+}
+
+/// One entry's fields as they are read.
+#[derive(Default)]
+struct Entry {
+    id: Option<String>,
+    title: Option<String>,
+    link: Option<String>,
+    published: Option<i64>,
+    updated: Option<i64>,
+    summary: Option<String>,
+    content: Option<String>,
+}
+
+/// Entry text, which feeds often send as escaped HTML.
+fn entry_text(text: &str) -> String {
+    if text.contains('<') {
+        web::fragment_markdown(text)
+    } else {
+        text.trim().to_string()
+    }
+}
+
+impl Entry {
+    fn finish(self, base: &Url) -> NewItem {
+        let url = self
+            .link
+            .and_then(|link| base.join(link.trim()).ok())
+            .map(|url| url.to_string());
+        let content = self
+            .content
+            .or(self.summary)
+            .map(|text| entry_text(&text))
+            .unwrap_or_default();
+        let title = self
+            .title
+            .and_then(|title| clean(&entry_text(&title)))
+            .or_else(|| clean(truncate_chars(&content, 80)))
+            .unwrap_or_else(|| "(untitled)".into());
+        let published_at = self.published.or(self.updated);
+        let identity = self
+            .id
+            .or_else(|| url.clone())
+            .unwrap_or_else(|| format!("{title}\n{}", published_at.unwrap_or_default()));
+        NewItem {
+            key: item_key(&identity),
+            title,
+            url,
+            published_at,
+            content: truncate_chars(&content, MAX_CONTENT_CHARS).to_string(),
+        }
+    }
+}
+
+fn feed_date(text: &str) -> Option<i64> {
+    let text = text.trim();
+    DateTime::parse_from_rfc2822(text)
+        .or_else(|_| DateTime::parse_from_rfc3339(text))
+        .ok()
+        .map(|date| date.timestamp_millis())
+}
+
+fn attribute(element: &BytesStart<'_>, name: &str) -> Option<String> {
+    let value = element.try_get_attribute(name).ok()??;
+    value.unescape_value().ok().map(|v| v.into_owned())
+}
+
+fn local_name(name: &[u8]) -> String {
+    String::from_utf8_lossy(name).to_ascii_lowercase()
+}
+
+/// Reads RSS (0.9x, 1.0 and 2.0) and Atom. `None` if the document isn't
+/// a feed. A feed that turns malformed partway keeps the entries before.
+fn parse_feed(xml: &str, base: &Url) -> Option<Feed> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+    let mut feed = Feed::default();
+    let mut stack: Vec<String> = Vec::new();
+    // Where the current entry's element sits in `stack`.
+    let mut entry: Option<(usize, Entry)> = None;
+    let mut text = String::new();
+    let mut is_feed = false;
+    loop {
+        let event = match reader.read_event() {
+            Ok(event) => event,
+            Err(_) if is_feed => break,
+            Err(_) => return None,
+        };
+        match event {
+            Event::Start(element) | Event::Empty(element) if !is_feed => {
+                let name = local_name(element.local_name().as_ref());
+                if !matches!(name.as_str(), "rss" | "feed" | "rdf") {
+                    return None;
+                }
+                is_feed = true;
+                stack.push(name);
+            }
+            Event::Start(element) => {
+                let name = local_name(element.local_name().as_ref());
+                match &mut entry {
+                    None if matches!(name.as_str(), "item" | "entry") => {
+                        entry = Some((stack.len(), Entry::default()));
+                    }
+                    Some((depth, fields)) if stack.len() == *depth + 1 => {
+                        text.clear();
+                        if name == "link" {
+                            read_link(&element, fields);
+                        }
+                    }
+                    None => text.clear(),
+                    _ => {}
+                }
+                stack.push(name);
+            }
+            Event::Empty(element) => {
+                if let Some((depth, fields)) = &mut entry {
+                    if stack.len() == *depth + 1
+                        && local_name(element.local_name().as_ref()) == "link"
+                    {
+                        read_link(&element, fields);
+                    }
+                }
+            }
+            Event::Text(value) => {
+                let value = value
+                    .unescape()
+                    .map(|v| v.into_owned())
+                    .unwrap_or_else(|_| String::from_utf8_lossy(&value).into_owned());
+                if !text.is_empty() {
+                    text.push(' ');
+                }
+                text.push_str(&value);
+            }
+            Event::CData(data) => text.push_str(&String::from_utf8_lossy(&data.into_inner())),
+            Event::End(_) => {
+                let Some(name) = stack.pop() else {
+                    break;
+                };
+                match &mut entry {
+                    Some((depth, _)) if stack.len() == *depth => {
+                        if let Some((_, fields)) = entry.take() {
+                            feed.entries.push(fields.finish(base));
+                        }
+                    }
+                    Some((depth, fields)) if stack.len() == *depth + 1 => {
+                        let value = std::mem::take(&mut text);
+                        entry_field(fields, &name, value);
+                    }
+                    None if name == "title"
+                        && feed.title.is_none()
+                        && stack
+                            .last()
+                            .is_some_and(|parent| parent == "channel" || parent == "feed") =>
+                    {
+                        feed.title = clean(&std::mem::take(&mut text));
+                    }
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    is_feed.then_some(feed)
+}
+
+/// An Atom link; the first one without a `rel`, or with `alternate`, is
+/// the entry's page.
+fn read_link(element: &BytesStart<'_>, fields: &mut Entry) {
+    let rel = attribute(element, "rel");
+    if fields.link.is_none() && rel.as_deref().is_none_or(|rel| rel == "alternate") {
+        fields.link = attribute(element, "href");
+    }
+}
+
+fn entry_field(fields: &mut Entry, name: &str, value: String) {
+    if value.trim().is_empty() {
+        return;
+    }
+    match name {
+        "title" => fields.title = Some(value),
+        // RSS puts the address in the element; Atom's came from `href`.
+        "link" if fields.link.is_none() => fields.link = Some(value),
+        "guid" | "id" => fields.id = Some(value.trim().to_string()),
+        "pubdate" | "published" | "date" | "issued" => {
+            fields.published = fields.published.or(feed_date(&value));
+        }
+        "updated" | "modified" => fields.updated = feed_date(&value),
+        "description" | "summary" => fields.summary = Some(value),
+        // `content:encoded` as well as Atom's `content`.
+        "encoded" | "content" => fields.content = Some(value),
+        _ => {}
+    }
+}
+
+/// The title and linked feed of an HTML page.
+fn inspect_page(html: &str, base: &Url) -> (Option<String>, Option<Url>) {
+    let doc = dom_query::Document::from(html);
+    let title = clean(&doc.select("title").text());
+    let feed = doc
+        .select(
+            r#"link[rel~="alternate"][type="application/rss+xml"],
+               link[rel~="alternate"][type="application/atom+xml"]"#,
+        )
+        .nodes()
+        .first()
+        .and_then(|link| link.attr("href"))
+        .and_then(|href| base.join(href.trim()).ok());
+    (title, feed)
+}
+
+fn parse_url(url: &str) -> Result<Url> {
+    let url = Url::parse(url.trim()).map_err(|e| invalid(format!("bad source URL: {e}")))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(invalid("sources must be http or https URLs"));
+    }
+    Ok(url)
+}
+
+/// A response body and the validators for fetching it again.
+struct Fetched {
+    url: Url,
+    body: String,
+    is_html: bool,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// A conditional GET; `None` when the server says nothing changed.
+async fn get(
+    client: &Client,
+    url: &Url,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+) -> Result<Option<Fetched>> {
+    let mut request = client
+        .get(url.clone())
+        .header(USER_AGENT, web::AGENT)
+        .timeout(FETCH_TIMEOUT);
+    if let Some(etag) = etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+    let response = request.send().await?;
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        return Err(Error::Fetch(format!("the server answered {status}")));
+    }
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+    let is_html = header(reqwest::header::CONTENT_TYPE)
+        .is_some_and(|kind| kind.to_ascii_lowercase().contains("html"));
+    let url = response.url().clone();
+    if response
+        .content_length()
+        .is_some_and(|len| len > MAX_FEED_BYTES as u64)
+    {
+        return Err(Error::Fetch("the feed is too large".into()));
+    }
+    let bytes = response.bytes().await?;
+    let body = String::from_utf8_lossy(&bytes[..bytes.len().min(MAX_FEED_BYTES)]).into_owned();
+    Ok(Some(Fetched {
+        url,
+        body,
+        is_html,
+        etag,
+        last_modified,
+    }))
+}
+
+/// Works out what `input` subscribes to: its kind, the URL to fetch and a
+/// name.
+async fn detect(client: &Client, input: &SourceInput) -> Result<(SourceKind, Url, String)> {
+    let url = parse_url(&input.url)?;
+    let given_name = input.name.as_deref().and_then(clean);
+    let host = url.host_str().unwrap_or_default().to_string();
+    if input.kind == Some(SourceKind::Page) {
+        return Ok((SourceKind::Page, url, given_name.unwrap_or(host)));
+    }
+    let fetched = get(client, &url, None, None)
+        .await?
+        .ok_or_else(|| Error::Fetch("the server sent nothing".into()))?;
+    if let Some(feed) = parse_feed(&fetched.body, &fetched.url) {
+        let name = given_name.or(feed.title).unwrap_or(host);
+        return Ok((SourceKind::Feed, url, name));
+    }
+    let (title, linked) = if fetched.is_html {
+        inspect_page(&fetched.body, &fetched.url)
+    } else {
+        (None, None)
+    };
+    if let Some(linked) = linked {
+        if let Some(feed) = get(client, &linked, None, None)
+            .await?
+            .and_then(|f| parse_feed(&f.body, &f.url))
+        {
+            let name = given_name.or(feed.title).or(title).unwrap_or(host);
+            return Ok((SourceKind::Feed, linked, name));
+        }
+    }
+    if input.kind == Some(SourceKind::Feed) {
+        return Err(Error::Fetch(format!("{url} isn't an RSS or Atom feed")));
+    }
+    Ok((SourceKind::Page, url, given_name.or(title).unwrap_or(host)))
+}
+
+fn interval(input: &SourceInput) -> Result<u32> {
+    let minutes = input.interval_minutes.unwrap_or(DEFAULT_INTERVAL_MINUTES);
+    if !(MIN_INTERVAL_MINUTES..=MAX_INTERVAL_MINUTES).contains(&minutes) {
+        return Err(invalid(format!(
+            "sources are fetched every {MIN_INTERVAL_MINUTES} minutes to 7 days"
+        )));
+    }
+    Ok(minutes)
+}
+
+impl Source {
+    const COLUMNS: &'static str = "id, name, url, kind, interval_minutes, enabled, error,
+        last_fetched_at, next_fetch_at,
+        (SELECT count(*) FROM source_items i
+         WHERE i.source_id = sources.id AND i.digested_at IS NULL),
+        created_at, updated_at, etag, last_modified";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            url: row.get(2)?,
+            kind: row.get(3)?,
+            interval_minutes: row.get(4)?,
+            enabled: row.get(5)?,
+            error: row.get(6)?,
+            last_fetched_at: row.get(7)?,
+            next_fetch_at: row.get(8)?,
+            new_items: row.get(9)?,
+            created_at: row.get(10)?,
+            updated_at: row.get(11)?,
+            etag: row.get(12)?,
+            last_modified: row.get(13)?,
+        })
+    }
+}
+
+impl StoredItem {
+    const COLUMNS: &'static str =
+        "source_id, key, title, url, published_at, content, fetched_at, indexed_at, digested_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let content: String = row.get(5)?;
+        Ok(Self {
+            item: SourceItem {
+                source_id: row.get(0)?,
+                key: row.get(1)?,
+                title: row.get(2)?,
+                url: row.get(3)?,
+                published_at: row.get(4)?,
+                preview: preview(&content),
+                fetched_at: row.get(6)?,
+                indexed_at: row.get(7)?,
+                digested_at: row.get(8)?,
+            },
+            content,
+        })
+    }
+}
+
+impl Database {
+    fn list_sources(&self) -> Result<Vec<Source>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM sources ORDER BY name COLLATE NOCASE",
+            Source::COLUMNS
+        ))?;
+        let rows = stmt.query_map([], Source::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn get_source(&self, id: &str) -> Result<Source> {
+        self.conn()?
+            .query_row(
+                &format!("SELECT {} FROM sources WHERE id = ?1", Source::COLUMNS),
+                [id],
+                Source::from_row,
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("source {id}")))
+    }
+
+    fn source_subscribed(&self, url: &str, except: &str) -> Result<bool> {
+        Ok(self
+            .conn()?
+            .query_row(
+                "SELECT 1 FROM sources WHERE url = ?1 AND id != ?2",
+                params![url, except],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    fn save_source(&self, source: &Source) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO sources
+                 (id, name, url, kind, interval_minutes, enabled, next_fetch_at,
+                  created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+             ON CONFLICT (id) DO UPDATE SET
+                 name = excluded.name, url = excluded.url, kind = excluded.kind,
+                 interval_minutes = excluded.interval_minutes, enabled = excluded.enabled,
+                 next_fetch_at = excluded.next_fetch_at, updated_at = excluded.updated_at",
+            params![
+                source.id,
+                source.name,
+                source.url,
+                source.kind,
+                source.interval_minutes,
+                source.enabled,
+                source.next_fetch_at,
+                source.created_at,
+                source.updated_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// A new address starts over: the old one's items and validators go.
+    fn reset_source(&self, id: &str) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute("DELETE FROM source_items WHERE source_id = ?1", [id])?;
+        conn.execute(
+            "UPDATE sources SET etag = NULL, last_modified = NULL, error = NULL WHERE id = ?1",
+            [id],
+        )?;
+        Ok(())
+    }
+
+    fn set_next_fetch(&self, id: &str, next_fetch_at: Option<i64>) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE sources SET next_fetch_at = ?2 WHERE id = ?1",
+            params![id, next_fetch_at],
+        )?;
+        Ok(())
+    }
+
+    fn finish_fetch(&self, source: &Source, error: Option<&str>) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE sources SET etag = ?2, last_modified = ?3, error = ?4, last_fetched_at = ?5
+             WHERE id = ?1",
+            params![
+                source.id,
+                source.etag,
+                source.last_modified,
+                error,
+                now_ms()
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn due_sources(&self, now: i64) -> Result<Vec<Source>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM sources WHERE enabled AND next_fetch_at <= ?1",
+            Source::COLUMNS
+        ))?;
+        let rows = stmt.query_map([now], Source::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn next_source_due(&self) -> Result<Option<i64>> {
+        Ok(self.conn()?.query_row(
+            "SELECT MIN(next_fetch_at) FROM sources WHERE enabled",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Stores the items not seen before and trims the source to
+    /// `max_items`, returning how many were new. A page keeps only its
+    /// latest text.
+    fn add_source_items(
+        &self,
+        source: &Source,
+        items: &[NewItem],
+        max_items: usize,
+    ) -> Result<usize> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let now = now_ms();
+        let mut added = 0;
+        for item in items {
+            let inserted = tx.execute(
+                "INSERT OR IGNORE INTO source_items
+                     (source_id, key, title, url, published_at, content, fetched_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    source.id,
+                    item.key,
+                    item.title,
+                    item.url,
+                    item.published_at,
+                    item.content,
+                    now
+                ],
+            )?;
+            added += inserted;
+            if inserted > 0 && source.kind == SourceKind::Page {
+                tx.execute(
+                    "DELETE FROM source_items WHERE source_id = ?1 AND key != ?2",
+                    params![source.id, item.key],
+                )?;
+            }
+        }
+        tx.execute(
+            "DELETE FROM source_items WHERE source_id = ?1 AND key NOT IN (
+                 SELECT key FROM source_items WHERE source_id = ?1
+                 ORDER BY IFNULL(published_at, fetched_at) DESC LIMIT ?2
+             )",
+            params![source.id, max_items],
+        )?;
+        tx.commit()?;
+        Ok(added)
+    }
+
+    fn unindexed_items(&self, source_id: &str, limit: usize) -> Result<Vec<StoredItem>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM source_items WHERE source_id = ?1 AND indexed_at IS NULL
+             ORDER BY IFNULL(published_at, fetched_at) DESC LIMIT ?2",
+            StoredItem::COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![source_id, limit], StoredItem::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn mark_items(&self, column: &str, items: &[SourceItem]) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let now = now_ms();
+        for item in items {
+            tx.execute(
+                &format!("UPDATE source_items SET {column} = ?3 WHERE source_id = ?1 AND key = ?2"),
+                params![item.source_id, item.key, now],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Items from `source_ids`, or every source when it's empty.
+    fn list_source_items(
+        &self,
+        source_ids: &[String],
+        only_new: bool,
+        limit: usize,
+    ) -> Result<Vec<StoredItem>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM source_items
+             WHERE (?1 = '[]' OR source_id IN (SELECT value FROM json_each(?1)))
+               AND (NOT ?2 OR digested_at IS NULL)
+             ORDER BY IFNULL(published_at, fetched_at) DESC LIMIT ?3",
+            StoredItem::COLUMNS
+        ))?;
+        let ids = serde_json::to_string(source_ids)?;
+        let rows = stmt.query_map(params![ids, only_new, limit], StoredItem::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+/// The newest entries of a feed, or `None` if it hasn't changed. The
+/// source's validators are updated for the next fetch.
+async fn fetch_feed(client: &Client, source: &mut Source) -> Result<Option<Vec<NewItem>>> {
+    let url = parse_url(&source.url)?;
+    let Some(fetched) = get(
+        client,
+        &url,
+        source.etag.as_deref(),
+        source.last_modified.as_deref(),
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+    let mut feed = parse_feed(&fetched.body, &fetched.url)
+        .ok_or_else(|| Error::Fetch(format!("{} is no longer an RSS or Atom feed", source.url)))?;
+    source.etag = fetched.etag;
+    source.last_modified = fetched.last_modified;
+    // Feeds list newest first, but not all of them; undated entries keep
+    // their place.
+    if feed.entries.iter().all(|e| e.published_at.is_some()) {
+        feed.entries
+            .sort_by_key(|e| std::cmp::Reverse(e.published_at));
+    }
+    feed.entries.truncate(MAX_NEW_ENTRIES);
+    Ok(Some(feed.entries))
+}
+
+async fn fetch_page(client: &Client, source: &Source) -> Result<Vec<NewItem>> {
+    let page = web::fetch(client, &source.url, &FetchOptions::default()).await?;
+    let text = page.to_prompt();
+    Ok(vec![NewItem {
+        key: item_key(&page.text),
+        title: page.title.unwrap_or_else(|| source.name.clone()),
+        url: Some(page.url),
+        published_at: None,
+        content: truncate_chars(&text, MAX_CONTENT_CHARS).to_string(),
+    }])
+}
+
+fn document_text(source: &Source, item: &StoredItem) -> String {
+    if source.kind == SourceKind::Page {
+        return item.content.clone();
+    }
+    let mut text = format!("# {}\nFeed: {}\n", item.item.title, source.name);
+    if let Some(url) = &item.item.url {
+        text.push_str(&format!("Link: {url}\n"));
+    }
+    if let Some(published) = item
+        .item
+        .published_at
+        .and_then(DateTime::from_timestamp_millis)
+    {
+        text.push_str(&format!(
+            "Published: {}\n",
+            published
+                .with_timezone(&Local)
+                .format("%a %-d %b %Y, %H:%M")
+        ));
+    }
+    text.push('\n');
+    text.push_str(&item.content);
+    text
+}
+
+/// Embeds the source's items that aren't in the index yet.
+async fn index_pending(app: &AppHandle, source: &Source) -> Result<usize> {
+    let db = app.state::<Database>();
+    let pending = db.unindexed_items(&source.id, MAX_INDEX_PER_REFRESH)?;
+    if pending.is_empty() {
+        return Ok(0);
+    }
+    let options = app.state::<Sources>().config.lock().unwrap().embedding();
+    let embedder = Embedder::resolve(&app.state::<Providers>(), &options)?;
+    let client = app.state::<Client>();
+    let mut indexed = Vec::with_capacity(pending.len());
+    for item in &pending {
+        let result = rag::index_text(
+            &db,
+            &embedder,
+            &client,
+            &document_source(&source.id, &item.item.key),
+            &item.item.title,
+            &document_text(source, item),
+        )
+        .await;
+        match result {
+            Ok(_) => indexed.push(item.item.clone()),
+            Err(err) => {
+                db.mark_items("indexed_at", &indexed)?;
+                return Err(err);
+            }
+        }
+    }
+    db.mark_items("indexed_at", &indexed)?;
+    Ok(indexed.len())
+}
+
+/// Fetches a source, stores what's new and indexes it. Fetch and index
+/// failures are recorded on the source rather than returned.
+async fn refresh(app: &AppHandle, mut source: Source) -> Result<SourceRefresh> {
+    let db = app.state::<Database>();
+    let client = app.state::<Client>();
+    let fetched = match source.kind {
+        SourceKind::Feed => fetch_feed(&client, &mut source).await,
+        SourceKind::Page => fetch_page(&client, &source).await.map(Some),
+    };
+    let max_items = app
+        .state::<Sources>()
+        .config
+        .lock()
+        .unwrap()
+        .max_items
+        .max(1);
+    let mut report = SourceRefresh {
+        source_id: source.id.clone(),
+        new_items: 0,
+        indexed: 0,
+        error: None,
+    };
+    match fetched {
+        Ok(items) => {
+            let items = items.unwrap_or_default();
+            report.new_items = db.add_source_items(&source, &items, max_items)?;
+            match index_pending(app, &source).await {
+                Ok(indexed) => report.indexed = indexed,
+                Err(err) => report.error = Some(format!("couldn't index: {err}")),
+            }
+        }
+        Err(err) => report.error = Some(err.to_string()),
+    }
+    db.finish_fetch(&source, report.error.as_deref())?;
+    if let Some(error) = &report.error {
+        tracing::warn!("source {} failed: {error}", source.name);
+    }
+    let _ = app.emit("source-refreshed", &report);
+    Ok(report)
+}
+
+fn next_fetch(source: &Source, after: i64) -> Option<i64> {
+    source
+        .enabled
+        .then(|| after + i64::from(source.interval_minutes) * 60_000)
+}
+
+/// Starts every due source, moving each on to its next time first.
+fn start_due(app: &AppHandle) -> Result<()> {
+    let db = app.state::<Database>();
+    let now = now_ms();
+    for source in db.due_sources(now)? {
+        db.set_next_fetch(&source.id, next_fetch(&source, now))?;
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            let name = source.name.clone();
+            if let Err(err) = priority::scope(Priority::Background, refresh(&app, source)).await {
+                tracing::warn!("couldn't refresh source {name}: {err}");
+            }
+        });
+    }
+    Ok(())
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<SourcesConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    let wake = Arc::new(Notify::new());
+    app.manage(Sources {
+        config: Mutex::new(config),
+        wake: wake.clone(),
+    });
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        startup::warmed().await;
+        let db = app.state::<Database>();
+        loop {
+            let mut sleep = MAX_SLEEP;
+            if !db.is_locked() && !offline::is_offline(&app) {
+                if let Err(err) = start_due(&app) {
+                    tracing::warn!("couldn't start source fetches: {err}");
+                }
+                if let Ok(Some(next)) = db.next_source_due() {
+                    let wait = Duration::from_millis((next - now_ms()).max(0) as u64);
+                    sleep = sleep.min(wait);
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(sleep) => {}
+                _ = wake.notified() => {}
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub fn get_sources_config(sources: State<'_, Sources>) -> SourcesConfig {
+    sources.config.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn set_sources_config(
+    app: AppHandle,
+    sources: State<'_, Sources>,
+    config: SourcesConfig,
+) -> Result<SourcesConfig> {
+    config::write(&app, CONFIG_FILE, &config)?;
+    *sources.config.lock().unwrap() = config.clone();
+    Ok(config)
+}
+
+#[tauri::command]
+pub fn list_sources(db: State<'_, Database>) -> Result<Vec<Source>> {
+    db.list_sources()
+}
+
+/// Subscribes to a feed or page and fetches it straight away.
+#[tauri::command]
+pub async fn add_source(
+    db: State<'_, Database>,
+    sources: State<'_, Sources>,
+    client: State<'_, Client>,
+    source: SourceInput,
+) -> Result<Source> {
+    let interval_minutes = interval(&source)?;
+    let (kind, url, name) = detect(&client, &source).await?;
+    if db.source_subscribed(url.as_str(), "")? {
+        return Err(invalid(format!("already subscribed to {url}")));
+    }
+    let now = now_ms();
+    let source = Source {
+        id: new_id(),
+        name,
+        url: url.to_string(),
+        kind,
+        interval_minutes,
+        enabled: source.enabled,
+        error: None,
+        last_fetched_at: None,
+        next_fetch_at: source.enabled.then_some(now),
+        new_items: 0,
+        created_at: now,
+        updated_at: now,
+        etag: None,
+        last_modified: None,
+    };
+    db.save_source(&source)?;
+    sources.wake.notify_one();
+    Ok(source)
+}
+
+/// Changes a source's name, address, kind or schedule. A new address is
+/// fetched afresh; its kind isn't worked out again unless given.
+#[tauri::command]
+pub fn update_source(
+    db: State<'_, Database>,
+    sources: State<'_, Sources>,
+    id: String,
+    source: SourceInput,
+) -> Result<Source> {
+    let mut saved = db.get_source(&id)?;
+    let url = parse_url(&source.url)?.to_string();
+    if db.source_subscribed(&url, &id)? {
+        return Err(invalid(format!("already subscribed to {url}")));
+    }
+    let moved = url != saved.url || source.kind.is_some_and(|kind| kind != saved.kind);
+    let now = now_ms();
+    saved.interval_minutes = interval(&source)?;
+    saved.name = source.name.as_deref().and_then(clean).unwrap_or(saved.name);
+    saved.kind = source.kind.unwrap_or(saved.kind);
+    saved.url = url;
+    saved.enabled = source.enabled;
+    saved.next_fetch_at = if moved {
+        source.enabled.then_some(now)
+    } else {
+        next_fetch(&saved, saved.last_fetched_at.unwrap_or(now))
+    };
+    saved.updated_at = now;
+    if moved {
+        db.reset_source(&id)?;
+    }
+    db.save_source(&saved)?;
+    sources.wake.notify_one();
+    db.get_source(&id)
+}
+
+/// Unsubscribes, removing the source's items from the index too.
+#[tauri::command]
+pub fn delete_source(db: State<'_, Database>, id: String) -> Result<()> {
+    db.conn()?
+        .execute("DELETE FROM sources WHERE id = ?1", [id])?;
+    Ok(())
+}
+
+/// Fetches a source now, outside its schedule.
+#[tauri::command]
+pub async fn refresh_source(app: AppHandle, id: String) -> Result<SourceRefresh> {
+    let source = app.state::<Database>().get_source(&id)?;
+    refresh(&app, source).await
+}
+
+/// Items newest first, from one source or all of them; `only_new` keeps
+/// those not yet in a digest.
+#[tauri::command]
+pub fn list_source_items(
+    db: State<'_, Database>,
+    source_id: Option<String>,
+    only_new: Option<bool>,
+    limit: Option<usize>,
+) -> Result<Vec<SourceItem>> {
+    Ok(db
+        .list_source_items(
+            &Vec::from_iter(source_id),
+            only_new.unwrap_or(false),
+            limit.unwrap_or(100),
+        )?
+        .into_iter()
+        .map(|stored| stored.item)
+        .collect())
+}
+
+/// Fans the items not yet summarized out to the models as one digest.
+/// They count as summarized once any model answers.
+#[tauri::command]
+pub async fn summarize_new_items(
+    app: AppHandle,
+    request_id: String,
+    request: Option<DigestRequest>,
+) -> Result<Digest> {
+    let request = request.unwrap_or_default();
+    let db = app.state::<Database>();
+    let names: Vec<(String, String)> = db
+        .list_sources()?
+        .into_iter()
+        .map(|source| (source.id, source.name))
+        .collect();
+    let limit = request.max_items.unwrap_or(DEFAULT_DIGEST_ITEMS);
+    let items = db.list_source_items(&request.source_ids, true, limit)?;
+    if items.is_empty() {
+        return Ok(Digest {
+            items: Vec::new(),
+            results: Vec::new(),
+        });
+    }
+    let mut prompt = request
+        .instructions
+        .as_deref()
+        .and_then(clean)
+        .unwrap_or_else(|| DIGEST_PROMPT.to_string());
+    for stored in &items {
+        let item = &stored.item;
+        let source = names
+            .iter()
+            .find(|(id, _)| *id == item.source_id)
+            .map_or("", |(_, name)| name.as_str());
+        prompt.push_str(&format!("\n\n## {}\nSource: {source}\n", item.title));
+        if let Some(url) = &item.url {
+            prompt.push_str(&format!("Link: {url}\n"));
+        }
+        prompt.push('\n');
+        prompt.push_str(truncate_chars(&stored.content, DIGEST_EXCERPT_CHARS));
+    }
+    let fanout = FanoutRequest {
+        targets: request.targets,
+        messages: vec![ChatMessage {
+            role: Role::User,
+            content: prompt,
+        }],
+        temperature: None,
+        top_p: None,
+        max_tokens: None,
+        preset_id: None,
+        use_tools: false,
+        bypass_cache: true,
+        conversation_id: None,
+    };
+    let results = fanout::fan_out(&app, &request_id, &fanout).await?;
+    let items: Vec<SourceItem> = items.into_iter().map(|stored| stored.item).collect();
+    if results.iter().any(|r| r.content.is_some()) {
+        db.mark_items("digested_at", &items)?;
+    }
+    Ok(Digest { items, results })
+}
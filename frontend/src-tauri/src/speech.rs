@@ -0,0 +1,693 @@
+//! Reading responses aloud with the system's own voices: `say` on macOS,
+//! SAPI (driven from PowerShell) on Windows, and espeak-ng or
+//! speech-dispatcher on Linux. One utterance plays at a time.
+//!
+//! So the frontend can highlight the words as they are spoken,
+//! `speech-started` lists where each word of the spoken text lies, and a
+//! `speech-word` event fires as each one begins. SAPI reports its word
+//! boundaries as it reaches them; the other engines say nothing while they
+//! speak, so their timings are estimated from the speaking rate and the
+//! syllables in each word, and the estimate's clock stops while paused.
+
+use std::io::Write;
+use std::process::{Child, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::markdown;
+use crate::process::command;
+use crate::storage::new_id;
+
+const CONFIG_FILE: &str = "speech.json";
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Words per minute `say` and espeak use at rate 1.0.
+const BASE_WPM: f32 = 175.0;
+/// Syllables in an average English word, for turning a word rate into a
+/// syllable rate.
+const SYLLABLES_PER_WORD: f32 = 1.4;
+/// Pauses after punctuation, in syllables.
+const SENTENCE_PAUSE: f32 = 2.0;
+const CLAUSE_PAUSE: f32 = 1.0;
+/// How often an estimated timing checks whether it's time for the next word.
+const WORD_POLL: Duration = Duration::from_millis(20);
+
+/// Reads the text (base64, first line) and then `pause`, `resume` or `stop`
+/// lines from stdin, so playback can be controlled while it runs. Writes a
+/// `word <position> <length>` line as each word starts.
+#[cfg(windows)]
+const SAPI_SCRIPT: &str = r#"
+Add-Type -AssemblyName System.Speech
+$s = New-Object System.Speech.Synthesis.SpeechSynthesizer
+if ($env:PENTAMIND_VOICE) { $s.SelectVoice($env:PENTAMIND_VOICE) }
+$s.Rate = [int]$env:PENTAMIND_RATE
+$null = Register-ObjectEvent -InputObject $s -EventName SpeakProgress -SourceIdentifier word
+function Write-Words {
+  Get-Event -SourceIdentifier word -ErrorAction SilentlyContinue | ForEach-Object {
+    $a = $_.SourceEventArgs
+    [Console]::Out.WriteLine("word $($a.CharacterPosition) $($a.CharacterCount)")
+    Remove-Event -EventIdentifier $_.EventIdentifier
+  }
+  [Console]::Out.Flush()
+}
+$in = [Console]::In
+$text = [Text.Encoding]::UTF8.GetString([Convert]::FromBase64String($in.ReadLine()))
+$null = $s.SpeakAsync($text)
+$next = $in.ReadLineAsync()
+while ($s.State -ne 'Ready') {
+  Write-Words
+  if ($next.Wait(50)) {
+    switch ($next.Result) {
+      'pause' { $s.Pause() }
+      'resume' { $s.Resume() }
+      default { $s.Resume(); $s.SpeakAsyncCancelAll() }
+    }
+    $next = $in.ReadLineAsync()
+  }
+}"#;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpeechSettings {
+    /// A voice name from `list_voices`; the system default when unset.
+    pub voice: Option<String>,
+    /// 1.0 is normal speed.
+    pub rate: f32,
+}
+
+impl Default for SpeechSettings {
+    fn default() -> Self {
+        Self {
+            voice: None,
+            rate: 1.0,
+        }
+    }
+}
+
+/// Payload of the `speech-finished` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct SpeechFinished {
+    pub id: String,
+}
+
+/// Where a word lies in the spoken text, in UTF-16 code units as
+/// JavaScript counts them.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct WordSpan {
+    pub start: usize,
+    pub length: usize,
+}
+
+/// Payload of the `speech-started` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct SpeechStarted {
+    pub id: String,
+    /// What is being read: the text with its Markdown taken out.
+    pub text: String,
+    pub words: Vec<WordSpan>,
+    /// The word timings are estimates rather than the engine's own.
+    pub estimated: bool,
+}
+
+/// Payload of the `speech-word` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct SpeechWord {
+    pub id: String,
+    /// Position in `SpeechStarted::words`.
+    pub index: usize,
+    #[serde(flatten)]
+    pub span: WordSpan,
+}
+
+/// How a running utterance is paused.
+enum Control {
+    /// SIGSTOP/SIGCONT on the speaking process.
+    #[cfg(unix)]
+    Signal,
+    /// Commands written to the SAPI script.
+    #[cfg(windows)]
+    Stdin(std::process::ChildStdin),
+    /// speech-dispatcher's client hands off to a daemon, so there's no
+    /// process to pause.
+    #[allow(dead_code)]
+    None,
+}
+
+/// Where word timings come from.
+enum Boundaries {
+    /// Worked out from the words per minute.
+    #[allow(dead_code)]
+    Estimated(f32),
+    /// `word` lines from the SAPI script.
+    #[cfg(windows)]
+    Reported(std::process::ChildStdout),
+}
+
+/// Time spent speaking, not counting pauses.
+struct Clock {
+    started: Instant,
+    /// When the current pause began, and all earlier pauses together.
+    paused: Mutex<(Option<Instant>, Duration)>,
+}
+
+impl Clock {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            paused: Mutex::new((None, Duration::ZERO)),
+        }
+    }
+
+    fn pause(&self, paused: bool) {
+        let mut state = self.paused.lock().unwrap();
+        match (state.0, paused) {
+            (None, true) => state.0 = Some(Instant::now()),
+            (Some(since), false) => *state = (None, state.1 + since.elapsed()),
+            _ => {}
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        let (since, total) = *self.paused.lock().unwrap();
+        let now = since.unwrap_or_else(Instant::now);
+        now.duration_since(self.started).saturating_sub(total)
+    }
+}
+
+struct Utterance {
+    id: String,
+    child: Child,
+    control: Control,
+    clock: Arc<Clock>,
+}
+
+#[derive(Default)]
+pub struct Speech {
+    settings: Mutex<SpeechSettings>,
+    current: Arc<Mutex<Option<Utterance>>>,
+}
+
+fn unavailable() -> Error {
+    Error::Unsupported("speech without say, PowerShell, espeak-ng or spd-say".into())
+}
+
+fn wpm(settings: &SpeechSettings) -> f32 {
+    BASE_WPM * settings.rate
+}
+
+#[cfg(target_os = "macos")]
+fn spawn(text: &str, settings: &SpeechSettings) -> Result<(Child, Control, Boundaries)> {
+    let mut say = command("say");
+    if let Some(voice) = &settings.voice {
+        say.args(["-v", voice]);
+    }
+    let mut child = say
+        .args(["-r", &(wpm(settings).round() as u32).to_string()])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|_| unavailable())?;
+    write_input(&mut child, text)?;
+    Ok((child, Control::Signal, Boundaries::Estimated(wpm(settings))))
+}
+
+#[cfg(windows)]
+fn spawn(text: &str, settings: &SpeechSettings) -> Result<(Child, Control, Boundaries)> {
+    use base64::Engine;
+
+    let rate = ((settings.rate - 1.0) * 10.0).round().clamp(-10.0, 10.0) as i32;
+    let mut child = command("powershell")
+        .args(["-NoProfile", "-Command", SAPI_SCRIPT])
+        .env(
+            "PENTAMIND_VOICE",
+            settings.voice.as_deref().unwrap_or_default(),
+        )
+        .env("PENTAMIND_RATE", rate.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|_| unavailable())?;
+    let mut stdin = child.stdin.take().ok_or_else(unavailable)?;
+    let stdout = child.stdout.take().ok_or_else(unavailable)?;
+    let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+    writeln!(stdin, "{encoded}")?;
+    Ok((child, Control::Stdin(stdin), Boundaries::Reported(stdout)))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn spawn(text: &str, settings: &SpeechSettings) -> Result<(Child, Control, Boundaries)> {
+    let estimated = Boundaries::Estimated(wpm(settings));
+    let wpm = (wpm(settings).round() as u32).to_string();
+    for program in ["espeak-ng", "espeak"] {
+        let mut espeak = command(program);
+        if let Some(voice) = &settings.voice {
+            espeak.args(["-v", voice]);
+        }
+        let Ok(mut child) = espeak
+            .args(["-s", &wpm, "--stdin"])
+            .stdin(Stdio::piped())
+            .spawn()
+        else {
+            continue;
+        };
+        write_input(&mut child, text)?;
+        return Ok((child, Control::Signal, estimated));
+    }
+    let rate = ((settings.rate - 1.0) * 100.0).round().clamp(-100.0, 100.0) as i32;
+    let mut spd = command("spd-say");
+    if let Some(voice) = &settings.voice {
+        spd.args(["-y", voice]);
+    }
+    let child = spd
+        .args(["-w", "-r", &rate.to_string(), "--", text])
+        .spawn()
+        .map_err(|_| unavailable())?;
+    Ok((child, Control::None, estimated))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn spawn(_text: &str, _settings: &SpeechSettings) -> Result<(Child, Control, Boundaries)> {
+    Err(unavailable())
+}
+
+/// Feeds `text` to the child's stdin and closes it. espeak reads as it
+/// speaks, so this happens off the caller's thread.
+#[cfg(unix)]
+fn write_input(child: &mut Child, text: &str) -> Result<()> {
+    if let Some(mut stdin) = child.stdin.take() {
+        let text = text.to_string();
+        std::thread::spawn(move || stdin.write_all(text.as_bytes()));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn signal(child: &Child, signal: libc::c_int) -> Result<()> {
+    // The pid is our own child's, so the worst case is it has just exited.
+    let pid = child.id() as libc::pid_t;
+    if unsafe { libc::kill(pid, signal) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+impl Utterance {
+    fn pause(&mut self, paused: bool) -> Result<()> {
+        self.control(paused)?;
+        self.clock.pause(paused);
+        Ok(())
+    }
+
+    fn control(&mut self, paused: bool) -> Result<()> {
+        match &mut self.control {
+            #[cfg(unix)]
+            Control::Signal => signal(
+                &self.child,
+                if paused { libc::SIGSTOP } else { libc::SIGCONT },
+            ),
+            #[cfg(windows)]
+            Control::Stdin(stdin) => {
+                writeln!(stdin, "{}", if paused { "pause" } else { "resume" })?;
+                Ok(())
+            }
+            Control::None => Err(Error::Unsupported(
+                "pausing speech-dispatcher playback".into(),
+            )),
+        }
+    }
+
+    fn stop(mut self) {
+        // A stopped process can still be killed, but resume it first so the
+        // audio device is released cleanly.
+        let _ = self.pause(false);
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Waits for the utterance to end on its own and emits `speech-finished`.
+fn watch(app: AppHandle, current: Arc<Mutex<Option<Utterance>>>, id: String) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let mut current = current.lock().unwrap();
+        match current.as_mut() {
+            Some(utterance) if utterance.id == id => {
+                if matches!(utterance.child.try_wait(), Ok(None)) {
+                    continue;
+                }
+                current.take();
+            }
+            // Stopped, or replaced by a newer utterance.
+            _ => return,
+        }
+        let _ = app.emit("speech-finished", SpeechFinished { id });
+        return;
+    });
+}
+
+/// The whitespace-separated words of `text`, with their spans.
+fn words(text: &str) -> Vec<(&str, WordSpan)> {
+    let mut words = Vec::new();
+    let mut offset = 0;
+    let mut current: Option<(usize, usize)> = None;
+    for (byte, c) in text.char_indices() {
+        match (c.is_whitespace(), current) {
+            (false, None) => current = Some((byte, offset)),
+            (true, Some((from, start))) => {
+                words.push((
+                    &text[from..byte],
+                    WordSpan {
+                        start,
+                        length: offset - start,
+                    },
+                ));
+                current = None;
+            }
+            _ => {}
+        }
+        offset += c.len_utf16();
+    }
+    if let Some((from, start)) = current {
+        words.push((
+            &text[from..],
+            WordSpan {
+                start,
+                length: offset - start,
+            },
+        ));
+    }
+    words
+}
+
+/// Roughly how long a word takes to say, in syllables, counting the pause
+/// its punctuation calls for. Words without Latin vowels (numbers, or
+/// scripts without them) count a syllable a character.
+fn syllables(word: &str) -> f32 {
+    let mut groups = 0;
+    let mut in_vowel = false;
+    for c in word.chars().map(|c| c.to_ascii_lowercase()) {
+        // Accented Latin letters are mostly vowels.
+        let vowel = matches!(c, 'a' | 'e' | 'i' | 'o' | 'u' | 'y' | '\u{c0}'..='\u{24f}');
+        if vowel && !in_vowel {
+            groups += 1;
+        }
+        in_vowel = vowel;
+    }
+    let spoken = if groups > 0 {
+        groups
+    } else {
+        word.chars().filter(|c| c.is_alphanumeric()).count()
+    };
+    let pause = match word.trim_end_matches(['"', '\'', ')', ']']).chars().last() {
+        Some('.' | '!' | '?') => SENTENCE_PAUSE,
+        Some(',' | ';' | ':') => CLAUSE_PAUSE,
+        _ => 0.0,
+    };
+    spoken.max(1) as f32 + pause
+}
+
+/// When each word should start, at `wpm` words per minute.
+fn estimate(words: &[(&str, WordSpan)], wpm: f32) -> Vec<Duration> {
+    let per_syllable = 60.0 / (wpm.max(1.0) * SYLLABLES_PER_WORD);
+    let mut at = 0.0;
+    words
+        .iter()
+        .map(|(word, _)| {
+            let start = Duration::from_secs_f32(at);
+            at += syllables(word) * per_syllable;
+            start
+        })
+        .collect()
+}
+
+fn is_current(current: &Mutex<Option<Utterance>>, id: &str) -> bool {
+    current.lock().unwrap().as_ref().is_some_and(|u| u.id == id)
+}
+
+/// Emits `speech-word` as each word is reached, by the engine's reports or
+/// the estimate, until the utterance ends or is replaced.
+fn follow(
+    app: AppHandle,
+    current: Arc<Mutex<Option<Utterance>>>,
+    id: String,
+    spans: Vec<WordSpan>,
+    timings: Option<Vec<Duration>>,
+    boundaries: Boundaries,
+    clock: Arc<Clock>,
+) {
+    let word_id = id.clone();
+    let emit = move |index: usize, span: WordSpan| {
+        let _ = app.emit(
+            "speech-word",
+            SpeechWord {
+                id: word_id.clone(),
+                index,
+                span,
+            },
+        );
+    };
+    match boundaries {
+        Boundaries::Estimated(_) => {
+            let timings = timings.unwrap_or_default();
+            std::thread::spawn(move || {
+                for (index, at) in timings.into_iter().enumerate() {
+                    loop {
+                        if !is_current(&current, &id) {
+                            return;
+                        }
+                        let elapsed = clock.elapsed();
+                        if elapsed >= at {
+                            break;
+                        }
+                        std::thread::sleep((at - elapsed).min(WORD_POLL));
+                    }
+                    emit(index, spans[index]);
+                }
+            });
+        }
+        #[cfg(windows)]
+        Boundaries::Reported(stdout) => {
+            use std::io::BufRead;
+
+            let _ = (current, clock);
+            std::thread::spawn(move || {
+                let mut last = None;
+                for line in std::io::BufReader::new(stdout).lines() {
+                    let Ok(line) = line else { break };
+                    let Some(position) = line
+                        .strip_prefix("word ")
+                        .and_then(|rest| rest.split(' ').next())
+                        .and_then(|p| p.parse::<usize>().ok())
+                    else {
+                        continue;
+                    };
+                    // The word the reported position falls in.
+                    let index = spans
+                        .partition_point(|span| span.start <= position)
+                        .saturating_sub(1);
+                    if let Some(span) = spans.get(index) {
+                        if last != Some(index) {
+                            last = Some(index);
+                            emit(index, *span);
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Loads the voice settings into managed state.
+pub fn init(app: &AppHandle) {
+    let settings = config::read::<SpeechSettings>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(Speech {
+        settings: Mutex::new(settings),
+        ..Default::default()
+    });
+}
+
+/// Reads `text` aloud, stopping anything already playing. Markdown syntax
+/// and code blocks are left out. Returns an id echoed by `speech-finished`.
+#[tauri::command]
+pub async fn speak_text(
+    app: AppHandle,
+    speech: State<'_, Speech>,
+    text: String,
+    voice: Option<String>,
+    rate: Option<f32>,
+) -> Result<String> {
+    let mut settings = speech.settings.lock().unwrap().clone();
+    if voice.is_some() {
+        settings.voice = voice;
+    }
+    if let Some(rate) = rate {
+        settings.rate = rate;
+    }
+    let text = markdown::prose(&text);
+    if text.is_empty() {
+        return Err(Error::NotFound("text to speak".into()));
+    }
+
+    let mut current = speech.current.lock().unwrap();
+    if let Some(previous) = current.take() {
+        previous.stop();
+    }
+    let (child, control, boundaries) = spawn(&text, &settings)?;
+    let id = new_id();
+    let clock = Arc::new(Clock::new());
+    *current = Some(Utterance {
+        id: id.clone(),
+        child,
+        control,
+        clock: clock.clone(),
+    });
+    drop(current);
+
+    let words = words(&text);
+    let timings = match &boundaries {
+        Boundaries::Estimated(wpm) => Some(estimate(&words, *wpm)),
+        #[cfg(windows)]
+        Boundaries::Reported(_) => None,
+    };
+    let spans: Vec<WordSpan> = words.into_iter().map(|(_, span)| span).collect();
+    let _ = app.emit(
+        "speech-started",
+        SpeechStarted {
+            id: id.clone(),
+            text: text.clone(),
+            words: spans.clone(),
+            estimated: timings.is_some(),
+        },
+    );
+    follow(
+        app.clone(),
+        speech.current.clone(),
+        id.clone(),
+        spans,
+        timings,
+        boundaries,
+        clock,
+    );
+    watch(app, speech.current.clone(), id.clone());
+    Ok(id)
+}
+
+#[tauri::command]
+pub async fn pause_speech(speech: State<'_, Speech>) -> Result<()> {
+    match speech.current.lock().unwrap().as_mut() {
+        Some(utterance) => utterance.pause(true),
+        None => Ok(()),
+    }
+}
+
+#[tauri::command]
+pub async fn resume_speech(speech: State<'_, Speech>) -> Result<()> {
+    match speech.current.lock().unwrap().as_mut() {
+        Some(utterance) => utterance.pause(false),
+        None => Ok(()),
+    }
+}
+
+#[tauri::command]
+pub async fn stop_speech(speech: State<'_, Speech>) -> Result<()> {
+    if let Some(utterance) = speech.current.lock().unwrap().take() {
+        utterance.stop();
+    }
+    Ok(())
+}
+
+/// Names of the installed voices, as accepted by `speak_text`.
+#[tauri::command]
+pub async fn list_voices() -> Result<Vec<String>> {
+    tauri::async_runtime::spawn_blocking(installed_voices)
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?
+}
+
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = command(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn installed_voices() -> Result<Vec<String>> {
+    if cfg!(target_os = "macos") {
+        // "Name   en_US    # Sample sentence"; names may contain single spaces.
+        let listing = output("say", &["-v", "?"]).ok_or_else(unavailable)?;
+        return Ok(listing
+            .lines()
+            .filter_map(|line| line.split("  ").next())
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect());
+    }
+    if cfg!(windows) {
+        let listing = output(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "Add-Type -AssemblyName System.Speech; \
+                 (New-Object System.Speech.Synthesis.SpeechSynthesizer).GetInstalledVoices() \
+                 | ForEach-Object { $_.VoiceInfo.Name }",
+            ],
+        )
+        .ok_or_else(unavailable)?;
+        return Ok(listing
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(str::to_string)
+            .collect());
+    }
+    for program in ["espeak-ng", "espeak"] {
+        // A table whose second column is the voice's language code.
+        if let Some(listing) = output(program, &["--voices"]) {
+            return Ok(listing
+                .lines()
+                .skip(1)
+                .filter_map(|line| line.split_whitespace().nth(1))
+                .map(str::to_string)
+                .collect());
+        }
+    }
+    let listing = output("spd-say", &["-L"]).ok_or_else(unavailable)?;
+    Ok(listing
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .collect())
+}
+
+#[tauri::command]
+pub async fn get_speech_settings(speech: State<'_, Speech>) -> Result<SpeechSettings> {
+    Ok(speech.settings.lock().unwrap().clone())
+}
+
+#[tauri::command]
+pub async fn set_speech_settings(
+    app: AppHandle,
+    speech: State<'_, Speech>,
+    settings: SpeechSettings,
+) -> Result<SpeechSettings> {
+    let settings = SpeechSettings {
+        rate: settings.rate.clamp(0.25, 4.0),
+        voice: settings.voice.filter(|v| !v.trim().is_empty()),
+    };
+    config::write(&app, CONFIG_FILE, &settings)?;
+    *speech.settings.lock().unwrap() = settings.clone();
+    Ok(settings)
+}
@@ -0,0 +1,129 @@
+//! How long startup takes, for `get_startup_metrics`. The setup the window
+//! waits on is timed phase by phase. Opening the database and filling the
+//! provider registry happen on first use instead, and the search index is
+//! warmed once the window is up; those are timed as deferred phases
+//! whenever they run. Background tasks started during setup wait for the
+//! warm-up with [`warmed`] rather than opening the database themselves.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+use tokio::sync::Notify;
+
+use crate::error::Error;
+use crate::providers::Providers;
+use crate::storage::Database;
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static METRICS: Mutex<StartupMetrics> = Mutex::new(StartupMetrics {
+    phases: Vec::new(),
+    window_shown_ms: None,
+    warmed_ms: None,
+    database_error: None,
+});
+static WARMED: AtomicBool = AtomicBool::new(false);
+static WARM: Notify = Notify::const_new();
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Phase {
+    pub name: &'static str,
+    /// Milliseconds from launch.
+    pub started_ms: u64,
+    pub duration_ms: u64,
+    /// Ran on first use or after the window was shown, rather than
+    /// holding it up.
+    pub deferred: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StartupMetrics {
+    /// In the order they finished.
+    pub phases: Vec<Phase>,
+    /// Milliseconds from launch to the main window being shown.
+    pub window_shown_ms: Option<u64>,
+    /// Milliseconds from launch to the background warm-up finishing.
+    pub warmed_ms: Option<u64>,
+    /// Why the database couldn't be opened, if it couldn't. Every query
+    /// fails with the same reason.
+    pub database_error: Option<String>,
+}
+
+fn since_launch(at: Instant) -> u64 {
+    let started = *STARTED.get_or_init(|| at);
+    at.saturating_duration_since(started).as_millis() as u64
+}
+
+fn record<T>(name: &'static str, deferred: bool, work: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let output = work();
+    let phase = Phase {
+        name,
+        started_ms: since_launch(start),
+        duration_ms: start.elapsed().as_millis() as u64,
+        deferred,
+    };
+    METRICS.lock().unwrap().phases.push(phase);
+    output
+}
+
+/// Starts the clock. Called first thing on launch.
+pub fn begin() {
+    STARTED.get_or_init(Instant::now);
+}
+
+/// Times a part of setup the window waits on.
+pub fn phase<T>(name: &'static str, work: impl FnOnce() -> T) -> T {
+    record(name, false, work)
+}
+
+/// Times work put off until something needs it.
+pub fn deferred<T>(name: &'static str, work: impl FnOnce() -> T) -> T {
+    record(name, true, work)
+}
+
+pub fn window_shown() {
+    METRICS.lock().unwrap().window_shown_ms = Some(since_launch(Instant::now()));
+}
+
+/// Opens the database, fills the provider registry and warms the search
+/// index off the main thread, so the first request or search meets them
+/// ready. Whatever got there first has already been timed.
+pub fn warm(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let db = app.state::<Database>();
+        let opened = db.ensure_open();
+        app.state::<Providers>().ensure_loaded();
+        let warmed = opened.and_then(|()| deferred("search_index", || db.warm_search_index()));
+        let mut metrics = METRICS.lock().unwrap();
+        match warmed {
+            Err(err @ Error::Unavailable(_)) => metrics.database_error = Some(err.to_string()),
+            Err(err) => tracing::debug!("couldn't warm the search index: {err}"),
+            Ok(()) => {}
+        }
+        metrics.warmed_ms = Some(since_launch(Instant::now()));
+        drop(metrics);
+        WARMED.store(true, Ordering::Release);
+        WARM.notify_waiters();
+    });
+}
+
+/// Resolves once [`warm`] has finished, whether or not the database opened.
+/// Only for tasks started by the GUI's setup; nothing warms a headless run.
+pub async fn warmed() {
+    loop {
+        let notified = WARM.notified();
+        if WARMED.load(Ordering::Acquire) {
+            return;
+        }
+        notified.await;
+    }
+}
+
+#[tauri::command]
+pub fn get_startup_metrics() -> StartupMetrics {
+    METRICS.lock().unwrap().clone()
+}
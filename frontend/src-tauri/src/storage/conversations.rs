@@ -0,0 +1,648 @@
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, Row, ToSql};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use super::{new_id, now_ms, Database};
+use crate::error::{Error, Result};
+use crate::incognito::{self, Incognito};
+use crate::llm::Role;
+use crate::memory;
+use crate::os_search;
+use crate::titling;
+use crate::windows;
+
+pub(crate) const DEFAULT_TITLE: &str = "New conversation";
+const PREVIEW_LEN: usize = 120;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Conversation {
+    pub id: String,
+    pub title: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    /// Last message of the branch being shown.
+    pub head_id: Option<String>,
+    /// Written in the background once the conversation gets long.
+    pub summary: Option<String>,
+    /// Set while the conversation is archived.
+    pub archived_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Message {
+    pub id: String,
+    pub conversation_id: String,
+    /// `None` for the first message of a branch that starts at the top.
+    pub parent_id: Option<String>,
+    pub role: Role,
+    pub content: String,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewMessage {
+    pub role: Role,
+    pub content: String,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    /// Message to answer or follow; defaults to the head of the current
+    /// branch. Another parent starts a sibling branch.
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    /// The request that produced an answer, whose recorded sources it
+    /// cites; see `citations`.
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
+/// A conversation with the messages of its current branch, oldest first.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationDetail {
+    #[serde(flatten)]
+    pub conversation: Conversation,
+    pub messages: Vec<Message>,
+}
+
+/// One path from the top of the tree to a message with no replies.
+#[derive(Debug, Clone, Serialize)]
+pub struct Branch {
+    /// Pass to `switch_branch`.
+    pub leaf_id: String,
+    /// The deepest message on the path that has siblings, where this branch
+    /// last split off; `None` if it never forked.
+    pub fork_id: Option<String>,
+    /// Start of the forked message, or of the leaf if there is none.
+    pub preview: String,
+    pub length: usize,
+    pub updated_at: i64,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageNode {
+    #[serde(flatten)]
+    pub message: Message,
+    /// On the current branch.
+    pub active: bool,
+    /// Oldest first.
+    pub children: Vec<MessageNode>,
+}
+
+/// Every message of a conversation, nested under the one it follows.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationTree {
+    #[serde(flatten)]
+    pub conversation: Conversation,
+    pub roots: Vec<MessageNode>,
+}
+
+impl ToSql for Role {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let role = match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        };
+        Ok(role.into())
+    }
+}
+
+impl FromSql for Role {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "system" => Ok(Role::System),
+            "user" => Ok(Role::User),
+            "assistant" => Ok(Role::Assistant),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl Conversation {
+    pub(crate) const COLUMNS: &'static str =
+        "id, title, created_at, updated_at, head_id, summary, archived_at";
+
+    pub(crate) fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            created_at: row.get(2)?,
+            updated_at: row.get(3)?,
+            head_id: row.get(4)?,
+            summary: row.get(5)?,
+            archived_at: row.get(6)?,
+        })
+    }
+}
+
+impl Message {
+    pub(crate) const COLUMNS: &'static str =
+        "id, conversation_id, parent_id, role, content, provider, model, created_at";
+
+    pub(crate) fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            conversation_id: row.get(1)?,
+            parent_id: row.get(2)?,
+            role: row.get(3)?,
+            content: row.get(4)?,
+            provider: row.get(5)?,
+            model: row.get(6)?,
+            created_at: row.get(7)?,
+        })
+    }
+}
+
+fn preview(content: &str) -> String {
+    let content = content.trim();
+    match content.char_indices().nth(PREVIEW_LEN) {
+        Some((end, _)) => format!("{}…", &content[..end]),
+        None => content.to_string(),
+    }
+}
+
+fn find_conversation(conn: &Connection, id: &str) -> Result<Conversation> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM conversations WHERE id = ?1",
+            Conversation::COLUMNS
+        ),
+        [id],
+        Conversation::from_row,
+    )
+    .optional()?
+    .ok_or_else(|| Error::NotFound(format!("conversation {id}")))
+}
+
+fn find_message(conn: &Connection, id: &str) -> Result<Message> {
+    conn.query_row(
+        &format!("SELECT {} FROM messages WHERE id = ?1", Message::COLUMNS),
+        [id],
+        Message::from_row,
+    )
+    .optional()?
+    .ok_or_else(|| Error::NotFound(format!("message {id}")))
+}
+
+/// Every message of a conversation in the order they were written.
+fn all_messages(conn: &Connection, conversation_id: &str) -> Result<Vec<Message>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM messages WHERE conversation_id = ?1 ORDER BY created_at, rowid",
+        Message::COLUMNS
+    ))?;
+    let messages = stmt
+        .query_map([conversation_id], Message::from_row)?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(messages)
+}
+
+fn insert_message(conn: &Connection, message: &Message) -> Result<()> {
+    let updated = conn.execute(
+        "UPDATE conversations SET updated_at = ?2, head_id = ?3 WHERE id = ?1",
+        params![message.conversation_id, message.created_at, message.id],
+    )?;
+    if updated == 0 {
+        return Err(Error::NotFound(format!(
+            "conversation {}",
+            message.conversation_id
+        )));
+    }
+    conn.execute(
+        "INSERT INTO messages
+             (id, conversation_id, parent_id, role, content, provider, model, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            message.id,
+            message.conversation_id,
+            message.parent_id,
+            message.role,
+            message.content,
+            message.provider,
+            message.model,
+            message.created_at
+        ],
+    )?;
+    Ok(())
+}
+
+/// A conversation's messages indexed by the message they follow.
+struct Tree {
+    by_id: HashMap<String, Message>,
+    /// Ids in the order they were written; `None` holds the top-level ones.
+    children: HashMap<Option<String>, Vec<String>>,
+}
+
+impl Tree {
+    fn new(messages: Vec<Message>) -> Self {
+        let mut children: HashMap<Option<String>, Vec<String>> = HashMap::new();
+        for message in &messages {
+            children
+                .entry(message.parent_id.clone())
+                .or_default()
+                .push(message.id.clone());
+        }
+        let by_id = messages.into_iter().map(|m| (m.id.clone(), m)).collect();
+        Self { by_id, children }
+    }
+
+    fn children(&self, parent: Option<&str>) -> &[String] {
+        self.children
+            .get(&parent.map(str::to_string))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Ids from the top of the tree down to `id`.
+    fn path(&self, id: &str) -> Vec<&str> {
+        let mut path = Vec::new();
+        let mut next = self.by_id.get(id);
+        while let Some(message) = next {
+            path.push(message.id.as_str());
+            next = message.parent_id.as_deref().and_then(|p| self.by_id.get(p));
+        }
+        path.reverse();
+        path
+    }
+
+    /// The newest leaf under `id`, following the latest reply at each step.
+    fn latest_leaf<'a>(&'a self, mut id: &'a str) -> &'a str {
+        while let Some(child) = self.children(Some(id)).last() {
+            id = child;
+        }
+        id
+    }
+
+    fn node(&self, id: &str, active: &HashSet<&str>) -> MessageNode {
+        MessageNode {
+            message: self.by_id[id].clone(),
+            active: active.contains(id),
+            children: self
+                .children(Some(id))
+                .iter()
+                .map(|child| self.node(child, active))
+                .collect(),
+        }
+    }
+}
+
+impl Database {
+    pub fn create_conversation(&self, title: Option<String>) -> Result<Conversation> {
+        let now = now_ms();
+        let conversation = Conversation {
+            id: new_id(),
+            title: title
+                .filter(|t| !t.trim().is_empty())
+                .unwrap_or_else(|| DEFAULT_TITLE.to_string()),
+            created_at: now,
+            updated_at: now,
+            head_id: None,
+            summary: None,
+            archived_at: None,
+        };
+        self.conn()?.execute(
+            "INSERT INTO conversations (id, title, created_at, updated_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                conversation.id,
+                conversation.title,
+                conversation.created_at,
+                conversation.updated_at
+            ],
+        )?;
+        Ok(conversation)
+    }
+
+    /// Adds a message under `message.parent_id`, or the current head, and
+    /// makes it the new head.
+    pub fn append_message(&self, conversation_id: &str, message: NewMessage) -> Result<Message> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let conversation = find_conversation(&tx, conversation_id)?;
+        let parent_id = match message.parent_id {
+            Some(parent_id) => {
+                if find_message(&tx, &parent_id)?.conversation_id != conversation_id {
+                    return Err(Error::NotFound(format!(
+                        "message {parent_id} in conversation {conversation_id}"
+                    )));
+                }
+                Some(parent_id)
+            }
+            None => conversation.head_id,
+        };
+        let message = Message {
+            id: new_id(),
+            conversation_id: conversation_id.to_string(),
+            parent_id,
+            role: message.role,
+            content: message.content,
+            provider: message.provider,
+            model: message.model,
+            created_at: now_ms(),
+        };
+        insert_message(&tx, &message)?;
+        tx.commit()?;
+        Ok(message)
+    }
+
+    /// Edits a message by adding the new text as its sibling, which starts
+    /// a branch and becomes the head. The original and anything after it
+    /// stay in the tree.
+    pub fn fork_conversation(&self, message_id: &str, content: String) -> Result<Message> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let original = find_message(&tx, message_id)?;
+        let message = Message {
+            id: new_id(),
+            conversation_id: original.conversation_id,
+            parent_id: original.parent_id,
+            role: original.role,
+            content,
+            provider: None,
+            model: None,
+            created_at: now_ms(),
+        };
+        insert_message(&tx, &message)?;
+        tx.commit()?;
+        Ok(message)
+    }
+
+    pub fn list_conversations(&self) -> Result<Vec<Conversation>> {
+        self.filter_conversations(None)
+    }
+
+    /// Conversations, newest first: `Some(true)` for the archived ones
+    /// only, `Some(false)` for the others, `None` for all of them.
+    pub fn filter_conversations(&self, archived: Option<bool>) -> Result<Vec<Conversation>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM conversations
+             WHERE ?1 IS NULL OR (archived_at IS NOT NULL) = ?1
+             ORDER BY updated_at DESC",
+            Conversation::COLUMNS
+        ))?;
+        let rows = stmt.query_map([archived], Conversation::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Moves conversations out of the main list; their contents stay as
+    /// they are. Already archived ones keep their date.
+    pub fn archive_conversations(&self, ids: &[String]) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let now = now_ms();
+        for id in ids {
+            find_conversation(&tx, id)?;
+            tx.execute(
+                "UPDATE conversations SET archived_at = ?2
+                 WHERE id = ?1 AND archived_at IS NULL",
+                params![id, now],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Brings a conversation back to the main list. It counts as active
+    /// again, so the retention policy doesn't archive it right away.
+    pub fn unarchive_conversation(&self, id: &str) -> Result<Conversation> {
+        let conn = self.conn()?;
+        find_conversation(&conn, id)?;
+        conn.execute(
+            "UPDATE conversations SET archived_at = NULL, updated_at = ?2 WHERE id = ?1",
+            params![id, now_ms()],
+        )?;
+        find_conversation(&conn, id)
+    }
+
+    /// The conversation with the messages of its current branch.
+    pub fn get_conversation(&self, id: &str) -> Result<ConversationDetail> {
+        let conn = self.conn()?;
+        let conversation = find_conversation(&conn, id)?;
+        let mut tree = Tree::new(all_messages(&conn, id)?);
+        let path: Vec<String> = match &conversation.head_id {
+            Some(head) => tree.path(head).into_iter().map(str::to_string).collect(),
+            None => Vec::new(),
+        };
+        let messages = path.iter().filter_map(|id| tree.by_id.remove(id)).collect();
+        Ok(ConversationDetail {
+            conversation,
+            messages,
+        })
+    }
+
+    pub fn get_conversation_tree(&self, id: &str) -> Result<ConversationTree> {
+        let conn = self.conn()?;
+        let conversation = find_conversation(&conn, id)?;
+        let tree = Tree::new(all_messages(&conn, id)?);
+        let active: HashSet<&str> = conversation
+            .head_id
+            .as_deref()
+            .map(|head| tree.path(head).into_iter().collect())
+            .unwrap_or_default();
+        let roots = tree
+            .children(None)
+            .iter()
+            .map(|root| tree.node(root, &active))
+            .collect();
+        Ok(ConversationTree {
+            conversation,
+            roots,
+        })
+    }
+
+    /// Every branch of the conversation, oldest first.
+    pub fn list_branches(&self, conversation_id: &str) -> Result<Vec<Branch>> {
+        let conn = self.conn()?;
+        let conversation = find_conversation(&conn, conversation_id)?;
+        let tree = Tree::new(all_messages(&conn, conversation_id)?);
+        let mut leaves: Vec<&Message> = tree
+            .by_id
+            .values()
+            .filter(|m| tree.children(Some(&m.id)).is_empty())
+            .collect();
+        leaves.sort_by_key(|m| m.created_at);
+        let branches = leaves
+            .into_iter()
+            .map(|leaf| {
+                let path = tree.path(&leaf.id);
+                let fork_id = path.iter().rev().find(|id| {
+                    let parent = tree.by_id[**id].parent_id.as_deref();
+                    tree.children(parent).len() > 1
+                });
+                let shown = fork_id.map_or(leaf, |id| &tree.by_id[*id]);
+                Branch {
+                    leaf_id: leaf.id.clone(),
+                    fork_id: fork_id.map(|id| id.to_string()),
+                    preview: preview(&shown.content),
+                    length: path.len(),
+                    updated_at: leaf.created_at,
+                    active: conversation.head_id.as_deref() == Some(leaf.id.as_str()),
+                }
+            })
+            .collect();
+        Ok(branches)
+    }
+
+    /// Shows the branch through `message_id`, down to its newest leaf.
+    pub fn switch_branch(
+        &self,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<ConversationDetail> {
+        {
+            let conn = self.conn()?;
+            let tree = Tree::new(all_messages(&conn, conversation_id)?);
+            if !tree.by_id.contains_key(message_id) {
+                return Err(Error::NotFound(format!(
+                    "message {message_id} in conversation {conversation_id}"
+                )));
+            }
+            let updated = conn.execute(
+                "UPDATE conversations SET head_id = ?2 WHERE id = ?1",
+                params![conversation_id, tree.latest_leaf(message_id)],
+            )?;
+            if updated == 0 {
+                return Err(Error::NotFound(format!("conversation {conversation_id}")));
+            }
+        }
+        self.get_conversation(conversation_id)
+    }
+
+    pub fn get_message(&self, id: &str) -> Result<Message> {
+        find_message(&*self.conn()?, id)
+    }
+
+    pub fn delete_conversation(&self, id: &str) -> Result<()> {
+        self.conn()?
+            .execute("DELETE FROM conversations WHERE id = ?1", [id])?;
+        Ok(())
+    }
+}
+
+#[tauri::command]
+pub async fn create_conversation(
+    db: State<'_, Database>,
+    title: Option<String>,
+) -> Result<Conversation> {
+    db.create_conversation(title)
+}
+
+#[tauri::command]
+pub async fn append_message(
+    app: AppHandle,
+    db: State<'_, Database>,
+    conversation_id: String,
+    message: NewMessage,
+) -> Result<Message> {
+    if incognito::is_ephemeral(&conversation_id) {
+        let message = app.state::<Incognito>().append(&conversation_id, message)?;
+        windows::message_added(&app, &message);
+        return Ok(message);
+    }
+    let request_id = message.request_id.clone();
+    let message = db.append_message(&conversation_id, message)?;
+    if let Some(request_id) = &request_id {
+        db.link_response(&message.id, request_id)?;
+    }
+    windows::message_added(&app, &message);
+    titling::after_append(&app, &conversation_id);
+    memory::after_append(&app, &message);
+    Ok(message)
+}
+
+/// Edits `message_id` into a new branch; see [`Database::fork_conversation`].
+#[tauri::command]
+pub async fn fork_conversation(
+    app: AppHandle,
+    db: State<'_, Database>,
+    message_id: String,
+    content: String,
+) -> Result<ConversationDetail> {
+    if incognito::is_ephemeral(&message_id) {
+        let incognito = app.state::<Incognito>();
+        let message = incognito.fork(&message_id, content)?;
+        windows::message_added(&app, &message);
+        return incognito.get(&message.conversation_id);
+    }
+    let message = db.fork_conversation(&message_id, content)?;
+    windows::message_added(&app, &message);
+    db.get_conversation(&message.conversation_id)
+}
+
+#[tauri::command]
+pub async fn list_branches(
+    db: State<'_, Database>,
+    conversation_id: String,
+) -> Result<Vec<Branch>> {
+    db.list_branches(&conversation_id)
+}
+
+#[tauri::command]
+pub async fn switch_branch(
+    db: State<'_, Database>,
+    conversation_id: String,
+    message_id: String,
+) -> Result<ConversationDetail> {
+    db.switch_branch(&conversation_id, &message_id)
+}
+
+#[tauri::command]
+pub async fn get_conversation_tree(
+    db: State<'_, Database>,
+    id: String,
+) -> Result<ConversationTree> {
+    db.get_conversation_tree(&id)
+}
+
+/// The conversations not archived, or with `archived` the archived ones.
+#[tauri::command]
+pub async fn list_conversations(
+    db: State<'_, Database>,
+    archived: Option<bool>,
+) -> Result<Vec<Conversation>> {
+    db.filter_conversations(Some(archived.unwrap_or(false)))
+}
+
+#[tauri::command]
+pub async fn archive_conversation(db: State<'_, Database>, id: String) -> Result<Conversation> {
+    db.archive_conversations(std::slice::from_ref(&id))?;
+    find_conversation(&*db.conn()?, &id)
+}
+
+#[tauri::command]
+pub async fn unarchive_conversation(db: State<'_, Database>, id: String) -> Result<Conversation> {
+    db.unarchive_conversation(&id)
+}
+
+#[tauri::command]
+pub async fn get_conversation(
+    app: AppHandle,
+    db: State<'_, Database>,
+    id: String,
+) -> Result<ConversationDetail> {
+    if incognito::is_ephemeral(&id) {
+        return app.state::<Incognito>().get(&id);
+    }
+    db.get_conversation(&id)
+}
+
+#[tauri::command]
+pub async fn delete_conversation(
+    app: AppHandle,
+    db: State<'_, Database>,
+    id: String,
+) -> Result<()> {
+    if incognito::is_ephemeral(&id) {
+        incognito::wipe(&app, &id)?;
+        windows::conversation_deleted(&app, &id);
+        return Ok(());
+    }
+    db.delete_conversation(&id)?;
+    windows::conversation_deleted(&app, &id);
+    os_search::conversation_deleted(&app, &id);
+    Ok(())
+}
@@ -0,0 +1,154 @@
+//! Optional encryption at rest via SQLCipher. Keys are derived from the
+//! passphrase by SQLCipher itself (PBKDF2-HMAC-SHA512), so the passphrase is
+//! never stored; an encrypted database starts locked on every launch.
+
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use rusqlite::{params, Connection, DatabaseName};
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
+
+use super::{prepare, readable, remove_if_exists, sibling, Database};
+use crate::error::{Error, Result};
+use crate::os_search;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DatabaseStatus {
+    pub encrypted: bool,
+    pub locked: bool,
+}
+
+fn check_passphrase(passphrase: &str) -> Result<()> {
+    if passphrase.is_empty() {
+        return Err(Error::Encryption("the passphrase can't be empty".into()));
+    }
+    Ok(())
+}
+
+/// Opens `path` with `passphrase` as the key, failing if it doesn't decrypt.
+fn open_keyed(path: &Path, passphrase: &str) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.pragma_update(None, "key", passphrase)?;
+    if !readable(&conn) {
+        return Err(Error::WrongPassphrase);
+    }
+    Ok(conn)
+}
+
+impl Database {
+    pub fn status(&self) -> DatabaseStatus {
+        DatabaseStatus {
+            encrypted: self.is_encrypted(),
+            locked: self.is_locked(),
+        }
+    }
+
+    pub fn unlock(&self, passphrase: &str) -> Result<()> {
+        if !self.is_locked() {
+            return Ok(());
+        }
+        let mut conn = open_keyed(&self.path, passphrase)?;
+        prepare(&mut conn)?;
+        *self.conn()? = conn;
+        self.locked.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Encrypts a plaintext database. SQLCipher can't encrypt a file in place,
+    /// so the contents are exported into an encrypted copy that then replaces
+    /// the original.
+    pub fn encrypt(&self, passphrase: &str) -> Result<()> {
+        check_passphrase(passphrase)?;
+        if self.is_encrypted() {
+            return Err(Error::Encryption(
+                "the database is already encrypted; change the passphrase instead".into(),
+            ));
+        }
+        let staging = sibling(&self.path, ".encrypting");
+        remove_if_exists(&staging)?;
+
+        let mut conn = self.conn()?;
+        let export = || -> Result<()> {
+            conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")?;
+            conn.execute(
+                "ATTACH DATABASE ?1 AS encrypted KEY ?2",
+                params![staging.to_string_lossy(), passphrase],
+            )?;
+            conn.query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))?;
+            // The export copies schema and rows but not the migration version.
+            let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+            conn.pragma_update(
+                Some(DatabaseName::Attached("encrypted")),
+                "user_version",
+                version,
+            )?;
+            conn.execute_batch("DETACH DATABASE encrypted;")?;
+            Ok(())
+        };
+        if let Err(e) = export() {
+            let _ = conn.execute_batch("DETACH DATABASE encrypted;");
+            let _ = std::fs::remove_file(&staging);
+            return Err(e);
+        }
+
+        // Close the plaintext connection before replacing the file under it.
+        *conn = Connection::open_in_memory()?;
+        remove_if_exists(&sibling(&self.path, "-wal"))?;
+        remove_if_exists(&sibling(&self.path, "-shm"))?;
+        std::fs::rename(&staging, &self.path)?;
+
+        let mut keyed = open_keyed(&self.path, passphrase)?;
+        prepare(&mut keyed)?;
+        *conn = keyed;
+        self.encrypted.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn change_passphrase(&self, current: &str, new: &str) -> Result<()> {
+        check_passphrase(new)?;
+        if self.is_locked() {
+            return Err(Error::Locked);
+        }
+        if !self.is_encrypted() {
+            return Err(Error::Encryption("the database isn't encrypted".into()));
+        }
+        open_keyed(&self.path, current)?;
+        self.conn()?.pragma_update(None, "rekey", new)?;
+        Ok(())
+    }
+}
+
+/// Runs `f` against the database off the async runtime; key derivation and
+/// re-encrypting the whole file both take a noticeable moment.
+async fn blocking<T: Send + 'static>(
+    app: AppHandle,
+    f: impl FnOnce(&Database) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    tauri::async_runtime::spawn_blocking(move || f(&app.state::<Database>()))
+        .await
+        .map_err(|e| Error::Encryption(e.to_string()))?
+}
+
+#[tauri::command]
+pub async fn get_database_status(db: State<'_, Database>) -> Result<DatabaseStatus> {
+    Ok(db.status())
+}
+
+/// Encrypts the existing database with `passphrase`.
+#[tauri::command]
+pub async fn set_encryption_passphrase(app: AppHandle, passphrase: String) -> Result<()> {
+    blocking(app.clone(), move |db| db.encrypt(&passphrase)).await?;
+    os_search::database_encrypted(&app);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn unlock_database(app: AppHandle, passphrase: String) -> Result<()> {
+    blocking(app, move |db| db.unlock(&passphrase)).await
+}
+
+#[tauri::command]
+pub async fn change_passphrase(app: AppHandle, current: String, new: String) -> Result<()> {
+    blocking(app, move |db| db.change_passphrase(&current, &new)).await
+}
@@ -0,0 +1,723 @@
+//! Embedded SQLite database holding conversation history, managed as Tauri
+//! state so chats persist independently of the webview.
+
+pub mod conversations;
+pub mod encryption;
+
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::Connection;
+
+use crate::error::{Error, Result};
+use crate::startup;
+
+pub const DB_FILE: &str = "pentamind.db";
+
+/// Schema migrations, applied in order and tracked with `PRAGMA user_version`.
+/// Append new entries; never edit one that has shipped.
+const MIGRATIONS: &[&str] = &[
+    r#"
+    CREATE TABLE conversations (
+        id          TEXT PRIMARY KEY,
+        title       TEXT NOT NULL,
+        created_at  INTEGER NOT NULL,
+        updated_at  INTEGER NOT NULL
+    );
+    CREATE TABLE messages (
+        id               TEXT PRIMARY KEY,
+        conversation_id  TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+        role             TEXT NOT NULL,
+        content          TEXT NOT NULL,
+        provider         TEXT,
+        model            TEXT,
+        created_at       INTEGER NOT NULL
+    );
+    CREATE INDEX messages_conversation ON messages(conversation_id, created_at);
+"#,
+    r#"
+    -- Full-text index over message content, kept in sync by triggers.
+    CREATE VIRTUAL TABLE messages_fts USING fts5(
+        content,
+        content = 'messages',
+        content_rowid = 'rowid',
+        tokenize = 'unicode61 remove_diacritics 2'
+    );
+    CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+        INSERT INTO messages_fts (rowid, content) VALUES (new.rowid, new.content);
+    END;
+    CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+        INSERT INTO messages_fts (messages_fts, rowid, content)
+        VALUES ('delete', old.rowid, old.content);
+    END;
+    CREATE TRIGGER messages_fts_update AFTER UPDATE OF content ON messages BEGIN
+        INSERT INTO messages_fts (messages_fts, rowid, content)
+        VALUES ('delete', old.rowid, old.content);
+        INSERT INTO messages_fts (rowid, content) VALUES (new.rowid, new.content);
+    END;
+    INSERT INTO messages_fts (messages_fts) VALUES ('rebuild');
+"#,
+    r#"
+    -- Indexed documents for retrieval; `model` identifies the embedding space.
+    CREATE TABLE documents (
+        id          TEXT PRIMARY KEY,
+        source      TEXT NOT NULL UNIQUE,
+        title       TEXT NOT NULL,
+        created_at  INTEGER NOT NULL
+    );
+    CREATE TABLE chunks (
+        id            TEXT PRIMARY KEY,
+        document_id   TEXT NOT NULL REFERENCES documents(id) ON DELETE CASCADE,
+        ordinal       INTEGER NOT NULL,
+        content       TEXT NOT NULL,
+        start_offset  INTEGER NOT NULL,
+        end_offset    INTEGER NOT NULL,
+        model         TEXT NOT NULL,
+        embedding     BLOB NOT NULL
+    );
+    CREATE INDEX chunks_document ON chunks(document_id, ordinal);
+    CREATE INDEX chunks_model ON chunks(model);
+"#,
+    r#"
+    -- One row per completed generation. `cost_usd` is NULL for unpriced models.
+    CREATE TABLE usage (
+        id                 TEXT PRIMARY KEY,
+        provider           TEXT NOT NULL,
+        model              TEXT NOT NULL,
+        prompt_tokens      INTEGER NOT NULL,
+        completion_tokens  INTEGER NOT NULL,
+        cost_usd           REAL,
+        estimated          INTEGER NOT NULL,
+        created_at         INTEGER NOT NULL
+    );
+    CREATE INDEX usage_created ON usage(created_at);
+"#,
+    r#"
+    -- `vendor:id` for conversations brought in from another app's export.
+    ALTER TABLE conversations ADD COLUMN import_source TEXT;
+    CREATE UNIQUE INDEX conversations_import_source ON conversations(import_source)
+        WHERE import_source IS NOT NULL;
+"#,
+    r#"
+    -- Reusable prompts with `{{variable}}` placeholders.
+    CREATE TABLE templates (
+        id             TEXT PRIMARY KEY,
+        name           TEXT NOT NULL,
+        description    TEXT,
+        body           TEXT NOT NULL,
+        system_prompt  TEXT,
+        created_at     INTEGER NOT NULL,
+        updated_at     INTEGER NOT NULL
+    );
+    CREATE TABLE template_tags (
+        template_id  TEXT NOT NULL REFERENCES templates(id) ON DELETE CASCADE,
+        tag          TEXT NOT NULL,
+        PRIMARY KEY (template_id, tag)
+    );
+    CREATE INDEX template_tags_tag ON template_tags(tag);
+    -- System prompts that replace the template's own for a given model.
+    CREATE TABLE template_system_prompts (
+        template_id  TEXT NOT NULL REFERENCES templates(id) ON DELETE CASCADE,
+        model        TEXT NOT NULL,
+        prompt       TEXT NOT NULL,
+        PRIMARY KEY (template_id, model)
+    );
+"#,
+    r#"
+    -- Answers kept for reuse. `scope` hashes everything in the request but
+    -- the last message, `key` the whole request.
+    CREATE TABLE response_cache (
+        key                TEXT PRIMARY KEY,
+        scope              TEXT NOT NULL,
+        provider           TEXT NOT NULL,
+        model              TEXT NOT NULL,
+        content            TEXT NOT NULL,
+        prompt_tokens      INTEGER,
+        completion_tokens  INTEGER,
+        embedding_model    TEXT,
+        embedding          BLOB,
+        created_at         INTEGER NOT NULL,
+        expires_at         INTEGER NOT NULL
+    );
+    CREATE INDEX response_cache_scope ON response_cache(scope, expires_at);
+"#,
+    r#"
+    -- Messages form a tree: editing one forks a sibling under the same
+    -- parent. `head_id` is the last message of the branch being shown.
+    ALTER TABLE messages ADD COLUMN parent_id TEXT REFERENCES messages(id) ON DELETE CASCADE;
+    ALTER TABLE conversations ADD COLUMN head_id TEXT;
+    UPDATE messages SET parent_id = (
+        SELECT previous FROM (
+            SELECT id, LAG(id) OVER (
+                PARTITION BY conversation_id ORDER BY created_at, rowid
+            ) AS previous
+            FROM messages
+        ) ordered
+        WHERE ordered.id = messages.id
+    );
+    UPDATE conversations SET head_id = (
+        SELECT id FROM messages WHERE conversation_id = conversations.id
+        ORDER BY created_at DESC, rowid DESC LIMIT 1
+    );
+    CREATE INDEX messages_parent ON messages(parent_id);
+"#,
+    r#"
+    -- Rolling summary of the branch up to and including `summary_through`.
+    ALTER TABLE conversations ADD COLUMN summary TEXT;
+    ALTER TABLE conversations ADD COLUMN summary_through TEXT;
+"#,
+    r#"
+    -- Recurring prompts. `targets` is the fan-out's JSON target list, and
+    -- `next_run_at` is NULL while a schedule is disabled.
+    CREATE TABLE schedules (
+        id           TEXT PRIMARY KEY,
+        name         TEXT NOT NULL,
+        cron         TEXT NOT NULL,
+        prompt       TEXT NOT NULL,
+        targets      TEXT NOT NULL,
+        use_tools    INTEGER NOT NULL,
+        enabled      INTEGER NOT NULL,
+        next_run_at  INTEGER,
+        created_at   INTEGER NOT NULL,
+        updated_at   INTEGER NOT NULL
+    );
+    CREATE INDEX schedules_next_run ON schedules(next_run_at);
+    CREATE TABLE scheduled_runs (
+        id           TEXT PRIMARY KEY,
+        schedule_id  TEXT NOT NULL REFERENCES schedules(id) ON DELETE CASCADE,
+        status       TEXT NOT NULL,
+        results      TEXT,
+        error        TEXT,
+        started_at   INTEGER NOT NULL,
+        finished_at  INTEGER
+    );
+    CREATE INDEX scheduled_runs_schedule ON scheduled_runs(schedule_id, started_at);
+"#,
+    r#"
+    -- Sync bookkeeping. Triggers bump `dirty` on every local change; it is
+    -- reset once the change has been uploaded. `clock` is the entity's
+    -- vector clock as of its last sync.
+    CREATE TABLE sync_entities (
+        id               TEXT PRIMARY KEY,
+        kind             TEXT NOT NULL,
+        conversation_id  TEXT NOT NULL,
+        clock            TEXT NOT NULL DEFAULT '{}',
+        dirty            INTEGER NOT NULL DEFAULT 1,
+        deleted          INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX sync_entities_conversation ON sync_entities(conversation_id);
+    CREATE INDEX sync_entities_dirty ON sync_entities(dirty) WHERE dirty > 0;
+    -- ETag of each remote object as last seen, to skip unchanged downloads.
+    CREATE TABLE sync_objects (
+        name  TEXT PRIMARY KEY,
+        etag  TEXT NOT NULL
+    );
+    CREATE TRIGGER sync_conversation_insert AFTER INSERT ON conversations BEGIN
+        INSERT INTO sync_entities (id, kind, conversation_id)
+        VALUES (new.id, 'conversation', new.id)
+        ON CONFLICT (id) DO UPDATE SET dirty = dirty + 1, deleted = 0;
+    END;
+    CREATE TRIGGER sync_conversation_update AFTER UPDATE ON conversations BEGIN
+        UPDATE sync_entities SET dirty = dirty + 1 WHERE id = new.id;
+    END;
+    CREATE TRIGGER sync_conversation_delete AFTER DELETE ON conversations BEGIN
+        UPDATE sync_entities SET dirty = dirty + 1, deleted = 1 WHERE id = old.id;
+    END;
+    CREATE TRIGGER sync_message_insert AFTER INSERT ON messages BEGIN
+        INSERT INTO sync_entities (id, kind, conversation_id)
+        VALUES (new.id, 'message', new.conversation_id)
+        ON CONFLICT (id) DO UPDATE SET dirty = dirty + 1, deleted = 0;
+    END;
+    CREATE TRIGGER sync_message_update AFTER UPDATE ON messages BEGIN
+        UPDATE sync_entities SET dirty = dirty + 1 WHERE id = new.id;
+    END;
+    CREATE TRIGGER sync_message_delete AFTER DELETE ON messages BEGIN
+        UPDATE sync_entities SET deleted = 1 WHERE id = old.id;
+    END;
+    INSERT INTO sync_entities (id, kind, conversation_id)
+    SELECT id, 'conversation', id FROM conversations;
+    INSERT INTO sync_entities (id, kind, conversation_id)
+    SELECT id, 'message', conversation_id FROM messages;
+"#,
+    r#"
+    -- Named parameter sets. `provider` is empty for the values that apply
+    -- to every provider without one of its own.
+    CREATE TABLE presets (
+        id           TEXT PRIMARY KEY,
+        name         TEXT NOT NULL,
+        description  TEXT,
+        created_at   INTEGER NOT NULL,
+        updated_at   INTEGER NOT NULL
+    );
+    CREATE TABLE preset_params (
+        preset_id      TEXT NOT NULL REFERENCES presets(id) ON DELETE CASCADE,
+        provider       TEXT NOT NULL,
+        temperature    REAL,
+        top_p          REAL,
+        max_tokens     INTEGER,
+        system_prompt  TEXT,
+        PRIMARY KEY (preset_id, provider)
+    );
+"#,
+    r#"
+    -- Images made by `generate_image`, stored as attachments.
+    CREATE TABLE generated_images (
+        id               TEXT PRIMARY KEY,
+        attachment_id    TEXT NOT NULL,
+        path             TEXT NOT NULL,
+        provider         TEXT NOT NULL,
+        model            TEXT NOT NULL,
+        prompt           TEXT NOT NULL,
+        revised_prompt   TEXT,
+        negative_prompt  TEXT,
+        size             TEXT,
+        conversation_id  TEXT REFERENCES conversations(id) ON DELETE SET NULL,
+        message_id       TEXT REFERENCES messages(id) ON DELETE SET NULL,
+        created_at       INTEGER NOT NULL
+    );
+    CREATE INDEX generated_images_conversation ON generated_images (conversation_id, created_at);
+"#,
+    r#"
+    CREATE TABLE queued_requests (
+        id          TEXT PRIMARY KEY,
+        request_id  TEXT NOT NULL,
+        request     TEXT NOT NULL,
+        attempts    INTEGER NOT NULL DEFAULT 0,
+        created_at  INTEGER NOT NULL
+    );
+"#,
+    r#"
+    CREATE TABLE audit_log (
+        id               INTEGER PRIMARY KEY AUTOINCREMENT,
+        provider         TEXT NOT NULL,
+        model            TEXT,
+        method           TEXT NOT NULL,
+        endpoint         TEXT NOT NULL,
+        request_headers  TEXT NOT NULL,
+        request_bytes    INTEGER,
+        status           INTEGER,
+        error            TEXT,
+        latency_ms       INTEGER NOT NULL,
+        created_at       INTEGER NOT NULL
+    );
+    CREATE INDEX audit_log_created ON audit_log (created_at);
+    CREATE TRIGGER audit_log_append_only BEFORE UPDATE ON audit_log
+    BEGIN
+        SELECT RAISE(ABORT, 'the audit log is append-only');
+    END;
+"#,
+    r#"
+    -- Eval runs: a JSONL dataset fanned out to models, each answer graded.
+    -- `request` is the run's JSON request; `scores` each result's grades.
+    CREATE TABLE eval_runs (
+        id           TEXT PRIMARY KEY,
+        name         TEXT NOT NULL,
+        dataset      TEXT NOT NULL,
+        request      TEXT NOT NULL,
+        cases        INTEGER NOT NULL,
+        status       TEXT NOT NULL,
+        error        TEXT,
+        started_at   INTEGER NOT NULL,
+        finished_at  INTEGER
+    );
+    CREATE TABLE eval_results (
+        run_id      TEXT NOT NULL REFERENCES eval_runs(id) ON DELETE CASCADE,
+        case_index  INTEGER NOT NULL,
+        case_id     TEXT,
+        provider    TEXT NOT NULL,
+        model       TEXT NOT NULL,
+        response    TEXT,
+        error       TEXT,
+        scores      TEXT NOT NULL,
+        score       REAL,
+        latency_ms  INTEGER NOT NULL,
+        PRIMARY KEY (run_id, case_index, provider, model)
+    );
+"#,
+    r#"
+    -- Summaries standing in for a conversation's older turns, keyed by a
+    -- digest of the messages they cover. Requests outside a conversation
+    -- leave `conversation_id` empty.
+    CREATE TABLE context_summaries (
+        digest           TEXT PRIMARY KEY,
+        conversation_id  TEXT,
+        covers           INTEGER NOT NULL,
+        summary          TEXT NOT NULL,
+        created_at       INTEGER NOT NULL
+    );
+    CREATE INDEX context_summaries_conversation ON context_summaries (conversation_id);
+    CREATE TRIGGER context_summaries_conversation_delete AFTER DELETE ON conversations BEGIN
+        DELETE FROM context_summaries WHERE conversation_id = old.id;
+    END;
+"#,
+    r#"
+    -- Messages kept for later, and tags on any message. Tags are stored
+    -- lowercase without spaces.
+    CREATE TABLE bookmarks (
+        message_id  TEXT PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+        note        TEXT,
+        created_at  INTEGER NOT NULL
+    );
+    CREATE TABLE message_tags (
+        message_id  TEXT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+        tag         TEXT NOT NULL,
+        created_at  INTEGER NOT NULL,
+        PRIMARY KEY (message_id, tag)
+    );
+    CREATE INDEX message_tags_tag ON message_tags(tag);
+"#,
+    r#"
+    -- Markdown notes. `key` is the title as links match it; `note_links`
+    -- holds the `[[links]]` in each body by the key they name, whether or
+    -- not a note has it yet.
+    CREATE TABLE notes (
+        id          TEXT PRIMARY KEY,
+        title       TEXT NOT NULL,
+        key         TEXT NOT NULL UNIQUE,
+        body        TEXT NOT NULL,
+        created_at  INTEGER NOT NULL,
+        updated_at  INTEGER NOT NULL
+    );
+    CREATE TABLE note_links (
+        source_id   TEXT NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+        target_key  TEXT NOT NULL,
+        position    INTEGER NOT NULL,
+        PRIMARY KEY (source_id, target_key)
+    );
+    CREATE INDEX note_links_target ON note_links(target_key);
+    CREATE TABLE note_references (
+        note_id          TEXT NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+        conversation_id  TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+        message_id       TEXT REFERENCES messages(id) ON DELETE CASCADE,
+        created_at       INTEGER NOT NULL
+    );
+    CREATE UNIQUE INDEX note_references_unique
+        ON note_references(note_id, conversation_id, IFNULL(message_id, ''));
+    CREATE INDEX note_references_conversation ON note_references(conversation_id);
+"#,
+    r#"
+    -- Every saved state of a template, numbered from 1. Templates that
+    -- already exist start their history with what they hold now.
+    CREATE TABLE template_revisions (
+        template_id           TEXT NOT NULL REFERENCES templates(id) ON DELETE CASCADE,
+        version               INTEGER NOT NULL,
+        name                  TEXT NOT NULL,
+        description           TEXT,
+        body                  TEXT NOT NULL,
+        system_prompt         TEXT,
+        model_system_prompts  TEXT NOT NULL,
+        tags                  TEXT NOT NULL,
+        created_at            INTEGER NOT NULL,
+        PRIMARY KEY (template_id, version)
+    );
+    INSERT INTO template_revisions
+        (template_id, version, name, description, body, system_prompt,
+         model_system_prompts, tags, created_at)
+    SELECT t.id, 1, t.name, t.description, t.body, t.system_prompt,
+           (SELECT json_group_object(model, prompt)
+            FROM template_system_prompts WHERE template_id = t.id),
+           (SELECT json_group_array(tag)
+            FROM (SELECT tag FROM template_tags WHERE template_id = t.id ORDER BY tag)),
+           t.updated_at
+    FROM templates t;
+"#,
+    r#"
+    -- Facts about the user carried across conversations, with the message
+    -- and model each was taken from. They outlive their conversation.
+    CREATE TABLE memories (
+        id               TEXT PRIMARY KEY,
+        content          TEXT NOT NULL,
+        conversation_id  TEXT REFERENCES conversations(id) ON DELETE SET NULL,
+        message_id       TEXT REFERENCES messages(id) ON DELETE SET NULL,
+        provider         TEXT,
+        model            TEXT,
+        embedding_model  TEXT,
+        embedding        BLOB,
+        created_at       INTEGER NOT NULL,
+        updated_at       INTEGER NOT NULL
+    );
+"#,
+    r#"
+    -- When a conversation was put away, by hand or by the retention policy.
+    ALTER TABLE conversations ADD COLUMN archived_at INTEGER;
+    CREATE INDEX conversations_archived ON conversations(archived_at);
+"#,
+    r#"
+    -- Multi-stage orchestration runs and what each stage produced, one row
+    -- per model and stage, numbered in the order the stages ran.
+    CREATE TABLE orchestration_runs (
+        id               TEXT PRIMARY KEY,
+        mode             TEXT NOT NULL,
+        conversation_id  TEXT REFERENCES conversations(id) ON DELETE CASCADE,
+        request          TEXT NOT NULL,
+        status           TEXT NOT NULL,
+        error            TEXT,
+        started_at       INTEGER NOT NULL,
+        finished_at      INTEGER
+    );
+    CREATE INDEX orchestration_runs_conversation ON orchestration_runs(conversation_id);
+    CREATE TABLE orchestration_artifacts (
+        run_id      TEXT NOT NULL REFERENCES orchestration_runs(id) ON DELETE CASCADE,
+        step        INTEGER NOT NULL,
+        position    INTEGER NOT NULL,
+        stage       TEXT NOT NULL,
+        provider    TEXT NOT NULL,
+        model       TEXT NOT NULL,
+        content     TEXT,
+        error       TEXT,
+        latency_ms  INTEGER NOT NULL,
+        created_at  INTEGER NOT NULL,
+        PRIMARY KEY (run_id, step, position)
+    );
+"#,
+    r#"
+    -- Subscribed feeds and pages. `etag` and `last_modified` make the next
+    -- fetch of a feed conditional; `next_fetch_at` is NULL while a source
+    -- is disabled.
+    CREATE TABLE sources (
+        id                TEXT PRIMARY KEY,
+        name              TEXT NOT NULL,
+        url               TEXT NOT NULL UNIQUE,
+        kind              TEXT NOT NULL,
+        interval_minutes  INTEGER NOT NULL,
+        enabled           INTEGER NOT NULL,
+        etag              TEXT,
+        last_modified     TEXT,
+        error             TEXT,
+        last_fetched_at   INTEGER,
+        next_fetch_at     INTEGER,
+        created_at        INTEGER NOT NULL,
+        updated_at        INTEGER NOT NULL
+    );
+    -- What each source brought in. A page has a single item, replaced when
+    -- the page changes. An item's RAG document goes with it.
+    CREATE TABLE source_items (
+        source_id     TEXT NOT NULL REFERENCES sources(id) ON DELETE CASCADE,
+        key           TEXT NOT NULL,
+        title         TEXT NOT NULL,
+        url           TEXT,
+        published_at  INTEGER,
+        content       TEXT NOT NULL,
+        fetched_at    INTEGER NOT NULL,
+        indexed_at    INTEGER,
+        digested_at   INTEGER,
+        PRIMARY KEY (source_id, key)
+    );
+    CREATE INDEX source_items_new ON source_items(source_id) WHERE digested_at IS NULL;
+    CREATE TRIGGER source_items_delete AFTER DELETE ON source_items BEGIN
+        DELETE FROM documents WHERE source = 'source:' || old.source_id || '/' || old.key;
+    END;
+"#,
+    r#"
+    -- What a request put in front of the models, numbered as cited: RAG
+    -- excerpts and fetched pages, copied so later re-indexing doesn't
+    -- change them. `provider` is set on pages one model fetched through a
+    -- tool. Answers saved from the request are linked in
+    -- `message_requests`; a request's sources go with its last answer.
+    CREATE TABLE request_sources (
+        request_id    TEXT NOT NULL,
+        number        INTEGER NOT NULL,
+        provider      TEXT,
+        kind          TEXT NOT NULL,
+        source        TEXT NOT NULL,
+        title         TEXT,
+        url           TEXT,
+        document_id   TEXT,
+        chunk_id      TEXT,
+        start_offset  INTEGER,
+        end_offset    INTEGER,
+        excerpt       TEXT NOT NULL,
+        score         REAL,
+        created_at    INTEGER NOT NULL,
+        PRIMARY KEY (request_id, number)
+    );
+    CREATE INDEX request_sources_created ON request_sources(created_at);
+    CREATE TABLE message_requests (
+        message_id  TEXT PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+        request_id  TEXT NOT NULL
+    );
+    CREATE INDEX message_requests_request ON message_requests(request_id);
+    CREATE TRIGGER message_requests_delete AFTER DELETE ON message_requests
+    WHEN NOT EXISTS (SELECT 1 FROM message_requests WHERE request_id = old.request_id)
+    BEGIN
+        DELETE FROM request_sources WHERE request_id = old.request_id;
+    END;
+"#,
+];
+
+pub struct Database {
+    path: PathBuf,
+    /// Opened, and migrated, on first use rather than while the window
+    /// waits on startup. Failing to open is kept, and every query after
+    /// returns it.
+    conn: OnceLock<std::result::Result<Mutex<Connection>, String>>,
+    /// The file is encrypted and no passphrase has been given yet. Queries
+    /// fail until `unlock_database` swaps in a keyed connection.
+    locked: AtomicBool,
+    encrypted: AtomicBool,
+}
+
+impl Database {
+    /// Prepares to open the database in `dir`; the file itself is opened by
+    /// the first query. Whether it is encrypted is read off its header, so
+    /// asking doesn't open it.
+    pub fn open(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(DB_FILE);
+        let encrypted = has_foreign_header(&path)?;
+        Ok(Self {
+            path,
+            conn: OnceLock::new(),
+            locked: AtomicBool::new(encrypted),
+            encrypted: AtomicBool::new(encrypted),
+        })
+    }
+
+    fn connect(&self) -> Result<Connection> {
+        let mut conn = Connection::open(&self.path)?;
+        let encrypted = !readable(&conn);
+        if !encrypted {
+            prepare(&mut conn)?;
+        }
+        self.locked.store(encrypted, Ordering::Relaxed);
+        self.encrypted.store(encrypted, Ordering::Relaxed);
+        Ok(conn)
+    }
+
+    fn connection(&self) -> Result<&Mutex<Connection>> {
+        let opened = self.conn.get_or_init(|| {
+            startup::deferred("database", || self.connect())
+                .map(Mutex::new)
+                .map_err(|err| {
+                    tracing::error!("couldn't open {}: {err}", self.path.display());
+                    err.to_string()
+                })
+        });
+        opened
+            .as_ref()
+            .map_err(|err| Error::Unavailable(err.clone()))
+    }
+
+    pub fn conn(&self) -> Result<MutexGuard<'_, Connection>> {
+        Ok(self
+            .connection()?
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Opens the file now if nothing has yet, without waiting on queries.
+    pub fn ensure_open(&self) -> Result<()> {
+        self.connection().map(drop)
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted.load(Ordering::Relaxed)
+    }
+
+    /// Writes a consistent copy of the database to `dest`, encrypted with the
+    /// same key when the live one is.
+    pub fn snapshot(&self, dest: &Path) -> Result<()> {
+        if self.is_locked() {
+            return Err(Error::Locked);
+        }
+        self.conn()?
+            .execute("VACUUM INTO ?1", [dest.to_string_lossy()])?;
+        Ok(())
+    }
+
+    /// Swaps the database file for `source` and reopens it. An encrypted
+    /// replacement comes back locked.
+    pub fn replace(&self, source: &Path) -> Result<()> {
+        let staging = sibling(&self.path, ".restoring");
+        std::fs::copy(source, &staging)?;
+        let mut conn = self.conn()?;
+        // Close the current connection before replacing the file under it.
+        *conn = Connection::open_in_memory()?;
+        remove_if_exists(&sibling(&self.path, "-wal"))?;
+        remove_if_exists(&sibling(&self.path, "-shm"))?;
+        std::fs::rename(&staging, &self.path)?;
+
+        let mut reopened = Connection::open(&self.path)?;
+        let encrypted = !readable(&reopened);
+        if !encrypted {
+            prepare(&mut reopened)?;
+        }
+        *conn = reopened;
+        self.locked.store(encrypted, Ordering::Relaxed);
+        self.encrypted.store(encrypted, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// `path` with `suffix` appended to the file name, for SQLite's sidecar files.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Whether `path` holds something other than a plain SQLite database,
+/// which for our own file means SQLCipher encrypted it. A missing or empty
+/// file is a new database.
+fn has_foreign_header(path: &Path) -> Result<bool> {
+    const HEADER: &[u8; 16] = b"SQLite format 3\0";
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    let mut header = Vec::with_capacity(HEADER.len());
+    file.take(HEADER.len() as u64).read_to_end(&mut header)?;
+    Ok(!header.is_empty() && header != HEADER)
+}
+
+/// Whether the connection can read the schema; an encrypted file opened with
+/// no key (or the wrong one) can't.
+fn readable(conn: &Connection) -> bool {
+    conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+        .is_ok()
+}
+
+fn prepare(conn: &mut Connection) -> Result<()> {
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    migrate(conn)
+}
+
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (index, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Milliseconds since the Unix epoch, the timestamp format used in every table.
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+pub fn new_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
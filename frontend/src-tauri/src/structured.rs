@@ -0,0 +1,540 @@
+//! Answers as JSON matching a schema. The schema goes to the provider's
+//! native JSON mode where there is one and into the prompt everywhere, and
+//! the reply is checked here: stray fences, trailing commas and a cut-off
+//! ending are fixed locally, and a reply that still doesn't fit is sent
+//! back to the model with what's wrong.
+
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::error::Result;
+use crate::llm::{self, ChatMessage, ChatRequest, ChatToken, Role, Usage};
+use crate::providers::Providers;
+use crate::requests::{cancellable, Requests};
+use crate::usage;
+
+/// Rounds of feedback when `max_repairs` isn't given.
+const DEFAULT_REPAIRS: u32 = 1;
+const PARTIAL_INTERVAL: Duration = Duration::from_millis(250);
+/// Depth past which `$ref` chains are assumed to loop.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Violation {
+    /// JSON Pointer to the offending value; empty for the whole document.
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StructuredOutput {
+    pub provider: String,
+    pub model: String,
+    /// The parsed reply, even when it breaks the schema; `None` if it isn't
+    /// JSON at all.
+    pub value: Option<Value>,
+    pub valid: bool,
+    pub violations: Vec<Violation>,
+    /// The model's last reply as sent.
+    pub raw: String,
+    /// The reply only parsed after local fixes.
+    pub repaired: bool,
+    /// Model calls made, the first one included.
+    pub attempts: u32,
+    pub usage: Option<Usage>,
+}
+
+/// Payload of `structured-partial`, sent while the reply streams with as
+/// much of it as parses so far.
+#[derive(Debug, Clone, Serialize)]
+pub struct StructuredPartial {
+    pub request_id: String,
+    pub value: Value,
+}
+
+fn violation(path: &str, message: impl Into<String>) -> Violation {
+    Violation {
+        path: path.to_string(),
+        message: message.into(),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match (expected, value) {
+        ("integer", Value::Number(n)) => n.as_f64().is_some_and(|f| f.fract() == 0.0),
+        ("number", Value::Number(_)) => true,
+        (expected, value) => type_name(value) == expected,
+    }
+}
+
+/// Checks values against the commonly used part of JSON Schema: types,
+/// `enum` and `const`, object properties, array items, string lengths and
+/// patterns, numeric bounds, the `anyOf`/`oneOf`/`allOf` combinators and
+/// local `$ref`s. Other keywords are ignored.
+struct Validator<'a> {
+    root: &'a Value,
+    violations: Vec<Violation>,
+}
+
+impl<'a> Validator<'a> {
+    fn check(root: &'a Value, value: &Value) -> Vec<Violation> {
+        let mut validator = Validator {
+            root,
+            violations: Vec::new(),
+        };
+        validator.validate(root, value, "", 0);
+        validator.violations
+    }
+
+    fn matches(&self, schema: &Value, value: &Value, path: &str, depth: usize) -> bool {
+        let mut nested = Validator {
+            root: self.root,
+            violations: Vec::new(),
+        };
+        nested.validate(schema, value, path, depth);
+        nested.violations.is_empty()
+    }
+
+    fn push(&mut self, path: &str, message: impl Into<String>) {
+        self.violations.push(violation(path, message));
+    }
+
+    fn validate(&mut self, schema: &Value, value: &Value, path: &str, depth: usize) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        let schema = match schema {
+            Value::Bool(false) => return self.push(path, "no value is allowed here"),
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match reference
+                .strip_prefix('#')
+                .and_then(|pointer| self.root.pointer(pointer))
+            {
+                Some(target) => self.validate(target, value, path, depth + 1),
+                None => self.push(
+                    path,
+                    format!("the schema's `{reference}` can't be resolved"),
+                ),
+            }
+            return;
+        }
+
+        if let Some(expected) = schema.get("type") {
+            let allowed: Vec<&str> = match expected {
+                Value::String(t) => vec![t.as_str()],
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            if !allowed.is_empty() && !allowed.iter().any(|t| has_type(value, t)) {
+                return self.push(
+                    path,
+                    format!(
+                        "expected {}, got {}",
+                        allowed.join(" or "),
+                        type_name(value)
+                    ),
+                );
+            }
+        }
+        if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+            if !options.contains(value) {
+                let options: Vec<String> = options.iter().map(Value::to_string).collect();
+                self.push(path, format!("must be one of {}", options.join(", ")));
+            }
+        }
+        if let Some(constant) = schema.get("const") {
+            if constant != value {
+                self.push(path, format!("must be {constant}"));
+            }
+        }
+
+        match value {
+            Value::Object(object) => {
+                let properties = schema.get("properties").and_then(Value::as_object);
+                for name in schema
+                    .get("required")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                {
+                    if !object.contains_key(name) {
+                        self.push(path, format!("missing required property `{name}`"));
+                    }
+                }
+                for (name, item) in object {
+                    let item_path =
+                        format!("{path}/{}", name.replace('~', "~0").replace('/', "~1"));
+                    match properties.and_then(|p| p.get(name)) {
+                        Some(property) => self.validate(property, item, &item_path, depth + 1),
+                        None => match schema.get("additionalProperties") {
+                            Some(Value::Bool(false)) => {
+                                self.push(path, format!("unexpected property `{name}`"))
+                            }
+                            Some(additional) => {
+                                self.validate(additional, item, &item_path, depth + 1)
+                            }
+                            None => {}
+                        },
+                    }
+                }
+            }
+            Value::Array(items) => {
+                if let Some(item_schema) = schema.get("items") {
+                    for (i, item) in items.iter().enumerate() {
+                        self.validate(item_schema, item, &format!("{path}/{i}"), depth + 1);
+                    }
+                }
+                if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+                    if (items.len() as u64) < min {
+                        self.push(path, format!("needs at least {min} items"));
+                    }
+                }
+                if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+                    if items.len() as u64 > max {
+                        self.push(path, format!("allows at most {max} items"));
+                    }
+                }
+            }
+            Value::String(text) => {
+                let length = text.chars().count() as u64;
+                if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+                    if length < min {
+                        self.push(path, format!("must be at least {min} characters"));
+                    }
+                }
+                if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+                    if length > max {
+                        self.push(path, format!("must be at most {max} characters"));
+                    }
+                }
+                if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+                    if Regex::new(pattern).is_ok_and(|re| !re.is_match(text)) {
+                        self.push(path, format!("must match `{pattern}`"));
+                    }
+                }
+            }
+            Value::Number(number) => {
+                let number = number.as_f64().unwrap_or_default();
+                let bound = |key: &str| schema.get(key).and_then(Value::as_f64);
+                if bound("minimum").is_some_and(|min| number < min) {
+                    self.push(path, format!("must be at least {}", schema["minimum"]));
+                }
+                if bound("maximum").is_some_and(|max| number > max) {
+                    self.push(path, format!("must be at most {}", schema["maximum"]));
+                }
+                if bound("exclusiveMinimum").is_some_and(|min| number <= min) {
+                    self.push(
+                        path,
+                        format!("must be more than {}", schema["exclusiveMinimum"]),
+                    );
+                }
+                if bound("exclusiveMaximum").is_some_and(|max| number >= max) {
+                    self.push(
+                        path,
+                        format!("must be less than {}", schema["exclusiveMaximum"]),
+                    );
+                }
+            }
+            _ => {}
+        }
+
+        for sub in schema
+            .get("allOf")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            self.validate(sub, value, path, depth + 1);
+        }
+        if let Some(options) = schema.get("anyOf").and_then(Value::as_array) {
+            if !options
+                .iter()
+                .any(|o| self.matches(o, value, path, depth + 1))
+            {
+                self.push(path, "doesn't match any of the allowed shapes");
+            }
+        }
+        if let Some(options) = schema.get("oneOf").and_then(Value::as_array) {
+            let matching = options
+                .iter()
+                .filter(|o| self.matches(o, value, path, depth + 1))
+                .count();
+            if matching != 1 {
+                self.push(
+                    path,
+                    format!("must match exactly one allowed shape, matches {matching}"),
+                );
+            }
+        }
+    }
+}
+
+/// The JSON inside a reply: without code fences or prose around it.
+fn extract_json(text: &str) -> &str {
+    let text = text.trim();
+    let text = match text.find("```") {
+        Some(start) => {
+            let body = &text[start + 3..];
+            let body = body.split_once('\n').map_or(body, |(_, rest)| rest);
+            body.find("```").map_or(body, |end| &body[..end])
+        }
+        None => text,
+    };
+    let start = text.find(['{', '[']).unwrap_or(0);
+    let end = text.rfind(['}', ']']).map_or(text.len(), |end| end + 1);
+    text.get(start..end.max(start)).unwrap_or(text).trim()
+}
+
+/// Drops trailing commas and closes whatever is still open, so a reply cut
+/// off mid-stream or written a little carelessly parses.
+fn close_json(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 8);
+    let mut open = Vec::new();
+    let (mut in_string, mut escaped) = (false, false);
+    for c in text.chars() {
+        if in_string {
+            out.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => open.push('}'),
+            '[' => open.push(']'),
+            '}' | ']' => {
+                let trimmed = out.trim_end().len();
+                out.truncate(trimmed);
+                if out.ends_with(',') {
+                    out.pop();
+                }
+                open.pop();
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+    if in_string {
+        if escaped {
+            out.pop();
+        }
+        out.push('"');
+    }
+    let trimmed = out.trim_end().len();
+    out.truncate(trimmed);
+    while out.ends_with(',') || out.ends_with(':') {
+        out.pop();
+        // A key left without a value goes too.
+        if out.ends_with('"') && !out.ends_with("\\\"") {
+            if let Some(start) = out[..out.len() - 1].rfind('"') {
+                if out[..start].trim_end().ends_with(['{', ',']) {
+                    out.truncate(start);
+                }
+            }
+        }
+        let trimmed = out.trim_end().len();
+        out.truncate(trimmed);
+    }
+    while let Some(closer) = open.pop() {
+        out.push(closer);
+    }
+    out
+}
+
+/// Parses a reply, fixing it up if it has to. Returns the value and
+/// whether fixes were needed.
+fn parse_reply(text: &str) -> std::result::Result<(Value, bool), String> {
+    let json = extract_json(text);
+    if let Ok(value) = serde_json::from_str(json) {
+        return Ok((value, json != text.trim()));
+    }
+    serde_json::from_str(&close_json(json))
+        .map(|value| (value, true))
+        .map_err(|e| e.to_string())
+}
+
+fn instructions(schema: &Value) -> String {
+    format!(
+        "Reply with a single JSON value that matches this JSON Schema. \
+         Don't add prose or code fences.\n\n{}",
+        serde_json::to_string_pretty(schema).unwrap_or_default()
+    )
+}
+
+fn feedback(violations: &[Violation]) -> String {
+    let lines: Vec<String> = violations
+        .iter()
+        .map(|v| match v.path.as_str() {
+            "" => format!("- {}", v.message),
+            path => format!("- at `{path}`: {}", v.message),
+        })
+        .collect();
+    format!(
+        "That reply doesn't match the schema:\n{}\n\nReply with the corrected JSON only.",
+        lines.join("\n")
+    )
+}
+
+/// One model call, streamed as `chat-token` events with `structured-partial`
+/// snapshots in between.
+async fn attempt(
+    app: &AppHandle,
+    request_id: &str,
+    request: &ChatRequest,
+) -> Result<(String, Option<Usage>)> {
+    let provider = app.state::<Providers>().get(&request.provider)?;
+    let client = app.state::<Client>();
+    let mut text = String::new();
+    let mut last_partial = Instant::now();
+    let (completion, cached) = llm::complete_cached(
+        app,
+        provider.as_ref(),
+        &client,
+        request_id,
+        request,
+        &mut |delta| {
+            let _ = app.emit(
+                "chat-token",
+                ChatToken {
+                    request_id: request_id.to_string(),
+                    provider: request.provider.clone(),
+                    delta: delta.to_string(),
+                },
+            );
+            text.push_str(delta);
+            if last_partial.elapsed() >= PARTIAL_INTERVAL {
+                last_partial = Instant::now();
+                if let Ok(value) = serde_json::from_str(&close_json(extract_json(&text))) {
+                    let _ = app.emit(
+                        "structured-partial",
+                        StructuredPartial {
+                            request_id: request_id.to_string(),
+                            value,
+                        },
+                    );
+                }
+            }
+        },
+    )
+    .await?;
+    if !cached {
+        usage::record(app, request, &completion.content, completion.usage);
+    }
+    Ok((completion.content, completion.usage))
+}
+
+async fn run(
+    app: &AppHandle,
+    request_id: &str,
+    mut request: ChatRequest,
+    schema: Value,
+    max_repairs: u32,
+) -> Result<StructuredOutput> {
+    let insert_at = request
+        .messages
+        .iter()
+        .take_while(|m| m.role == Role::System)
+        .count();
+    request.messages.insert(
+        insert_at,
+        ChatMessage {
+            role: Role::System,
+            content: instructions(&schema),
+        },
+    );
+    request.response_schema = Some(schema.clone());
+
+    let mut output = StructuredOutput {
+        provider: request.provider.clone(),
+        model: request.model.clone(),
+        value: None,
+        valid: false,
+        violations: Vec::new(),
+        raw: String::new(),
+        repaired: false,
+        attempts: 0,
+        usage: None,
+    };
+    loop {
+        let (raw, usage) = attempt(app, request_id, &request).await?;
+        output.attempts += 1;
+        output.usage = llm::add_usage(output.usage, usage);
+        match parse_reply(&raw) {
+            Ok((value, repaired)) => {
+                output.violations = Validator::check(&schema, &value);
+                output.value = Some(value);
+                output.repaired = repaired;
+            }
+            Err(err) => {
+                output.violations = vec![violation("", format!("not valid JSON: {err}"))];
+                output.value = None;
+                output.repaired = false;
+            }
+        }
+        output.valid = output.violations.is_empty();
+        if output.valid || output.attempts > max_repairs {
+            output.raw = raw;
+            return Ok(output);
+        }
+        request.messages.push(ChatMessage {
+            role: Role::Assistant,
+            content: raw,
+        });
+        request.messages.push(ChatMessage {
+            role: Role::User,
+            content: feedback(&output.violations),
+        });
+    }
+}
+
+/// Asks for an answer matching the JSON Schema `schema`. A reply that still
+/// breaks it after `max_repairs` rounds of feedback (one by default) comes
+/// back with `valid: false` and the violations listed. Streams like
+/// `stream_chat` and can be stopped with `cancel_request`.
+#[tauri::command]
+pub async fn structured_output(
+    app: AppHandle,
+    requests: State<'_, Requests>,
+    request_id: String,
+    request: ChatRequest,
+    schema: Value,
+    max_repairs: Option<u32>,
+) -> Result<StructuredOutput> {
+    let guard = requests.register(&request_id);
+    cancellable(
+        guard.token(),
+        run(
+            &app,
+            &request_id,
+            request,
+            schema,
+            max_repairs.unwrap_or(DEFAULT_REPAIRS),
+        ),
+    )
+    .await
+}
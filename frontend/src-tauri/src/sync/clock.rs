@@ -0,0 +1,126 @@
+//! Vector clocks: a counter per device, bumped each time that device
+//! changes the entity. Comparing two tells whether one version already
+//! contains the other or they were written without seeing each other.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VectorClock(BTreeMap<String, u64>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Causality {
+    Equal,
+    /// Every change in this clock is also in the other one.
+    Before,
+    After,
+    Concurrent,
+}
+
+impl VectorClock {
+    pub fn tick(&mut self, device: &str) {
+        *self.0.entry(device.to_string()).or_default() += 1;
+    }
+
+    /// Takes the larger counter for every device.
+    pub fn merge(&mut self, other: &Self) {
+        for (device, &count) in &other.0 {
+            let entry = self.0.entry(device.clone()).or_default();
+            *entry = (*entry).max(count);
+        }
+    }
+
+    pub fn merged(&self, other: &Self) -> Self {
+        let mut clock = self.clone();
+        clock.merge(other);
+        clock
+    }
+
+    pub fn compare(&self, other: &Self) -> Causality {
+        let (mut behind, mut ahead) = (false, false);
+        for device in self.0.keys().chain(other.0.keys()) {
+            let mine = self.0.get(device).copied().unwrap_or(0);
+            let theirs = other.0.get(device).copied().unwrap_or(0);
+            match mine.cmp(&theirs) {
+                Ordering::Less => behind = true,
+                Ordering::Greater => ahead = true,
+                Ordering::Equal => {}
+            }
+        }
+        match (behind, ahead) {
+            (false, false) => Causality::Equal,
+            (true, false) => Causality::Before,
+            (false, true) => Causality::After,
+            (true, true) => Causality::Concurrent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(counts: &[(&str, u64)]) -> VectorClock {
+        VectorClock(
+            counts
+                .iter()
+                .map(|(device, count)| (device.to_string(), *count))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn ticks_the_device() {
+        let mut ours = VectorClock::default();
+        ours.tick("laptop");
+        ours.tick("laptop");
+        ours.tick("phone");
+        assert_eq!(ours, clock(&[("laptop", 2), ("phone", 1)]));
+    }
+
+    #[test]
+    fn compares_clocks() {
+        let base = clock(&[("a", 1), ("b", 2)]);
+        assert_eq!(base.compare(&base.clone()), Causality::Equal);
+        assert_eq!(
+            base.compare(&clock(&[("a", 2), ("b", 2)])),
+            Causality::Before
+        );
+        assert_eq!(base.compare(&clock(&[("a", 1)])), Causality::After);
+        assert_eq!(
+            base.compare(&clock(&[("a", 2), ("b", 1)])),
+            Causality::Concurrent
+        );
+        // A device missing from one side counts as zero there.
+        assert_eq!(
+            base.compare(&clock(&[("a", 1), ("b", 2), ("c", 1)])),
+            Causality::Before
+        );
+        assert_eq!(
+            clock(&[("a", 1)]).compare(&clock(&[("b", 1)])),
+            Causality::Concurrent
+        );
+        assert_eq!(
+            VectorClock::default().compare(&clock(&[("a", 0)])),
+            Causality::Equal
+        );
+    }
+
+    #[test]
+    fn merges_to_the_larger_counters() {
+        let ours = clock(&[("a", 3), ("b", 1)]);
+        let theirs = clock(&[("b", 4), ("c", 2)]);
+        let merged = ours.merged(&theirs);
+        assert_eq!(merged, clock(&[("a", 3), ("b", 4), ("c", 2)]));
+        assert_eq!(merged, theirs.merged(&ours));
+        assert_eq!(ours.compare(&merged), Causality::Before);
+        assert_eq!(theirs.compare(&merged), Causality::Before);
+        // Merging what is already contained changes nothing.
+        let mut again = merged.clone();
+        again.merge(&ours);
+        assert_eq!(again, merged);
+    }
+}
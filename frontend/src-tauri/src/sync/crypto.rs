@@ -0,0 +1,119 @@
+//! Client-side encryption of everything that leaves for the sync backend.
+//! The key comes from the sync passphrase and a salt stored next to the
+//! data, so every device with the passphrase derives the same key and the
+//! backend only ever holds ciphertext.
+
+use std::num::NonZeroU32;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// Leads every sealed object, ahead of the nonce.
+const MAGIC: &[u8] = b"PMS1";
+const SALT_LEN: usize = 16;
+const ITERATIONS: u32 = 600_000;
+/// Sealed into the key file so a wrong passphrase is caught up front
+/// rather than as a pile of undecryptable conversations.
+const CHECK: &[u8] = b"pentamind-sync";
+const CHECK_NAME: &str = "check";
+
+/// What a device needs, besides the passphrase, to derive the key. Stored
+/// unencrypted on the backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyFile {
+    pub salt: String,
+    pub iterations: u32,
+    pub check: String,
+}
+
+pub struct Key(LessSafeKey);
+
+fn crypto_error(what: &str) -> Error {
+    Error::Encryption(format!("couldn't {what} sync data"))
+}
+
+fn random(bytes: &mut [u8]) -> Result<()> {
+    SystemRandom::new()
+        .fill(bytes)
+        .map_err(|_| Error::Encryption("no system randomness available".into()))
+}
+
+fn derive(passphrase: &str, salt: &[u8], iterations: u32) -> Result<Key> {
+    let iterations = NonZeroU32::new(iterations)
+        .ok_or_else(|| Error::Encryption("sync key file has no iteration count".into()))?;
+    let mut bytes = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        salt,
+        passphrase.as_bytes(),
+        &mut bytes,
+    );
+    let key = UnboundKey::new(&CHACHA20_POLY1305, &bytes).map_err(|_| crypto_error("set up"))?;
+    Ok(Key(LessSafeKey::new(key)))
+}
+
+impl Key {
+    /// A new key with a fresh salt, for a backend that has none yet.
+    pub fn create(passphrase: &str) -> Result<(Self, KeyFile)> {
+        let mut salt = [0u8; SALT_LEN];
+        random(&mut salt)?;
+        let key = derive(passphrase, &salt, ITERATIONS)?;
+        let file = KeyFile {
+            salt: STANDARD.encode(salt),
+            iterations: ITERATIONS,
+            check: STANDARD.encode(key.seal(CHECK_NAME, CHECK)?),
+        };
+        Ok((key, file))
+    }
+
+    /// Derives the key described by `file`, failing with `WrongPassphrase`
+    /// if it can't open the check value.
+    pub fn unlock(passphrase: &str, file: &KeyFile) -> Result<Self> {
+        let invalid = |_| Error::Encryption("sync key file is damaged".into());
+        let salt = STANDARD.decode(&file.salt).map_err(invalid)?;
+        let check = STANDARD.decode(&file.check).map_err(invalid)?;
+        let key = derive(passphrase, &salt, file.iterations)?;
+        match key.open(CHECK_NAME, &check) {
+            Ok(value) if value == CHECK => Ok(key),
+            _ => Err(Error::WrongPassphrase),
+        }
+    }
+
+    /// Encrypts `plaintext` for the object called `name`. The name is
+    /// authenticated too, so objects can't be swapped for one another.
+    pub fn seal(&self, name: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        random(&mut nonce)?;
+        let mut data = plaintext.to_vec();
+        self.0
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(name.as_bytes()),
+                &mut data,
+            )
+            .map_err(|_| crypto_error("encrypt"))?;
+        Ok([MAGIC, &nonce, &data].concat())
+    }
+
+    pub fn open(&self, name: &str, sealed: &[u8]) -> Result<Vec<u8>> {
+        let body = sealed
+            .strip_prefix(MAGIC)
+            .filter(|body| body.len() >= NONCE_LEN)
+            .ok_or_else(|| Error::Encryption(format!("{name} isn't a sync object")))?;
+        let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| crypto_error("decrypt"))?;
+        let mut data = ciphertext.to_vec();
+        let plaintext = self
+            .0
+            .open_in_place(nonce, Aad::from(name.as_bytes()), &mut data)
+            .map_err(|_| crypto_error("decrypt"))?;
+        Ok(plaintext.to_vec())
+    }
+}
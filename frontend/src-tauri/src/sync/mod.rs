@@ -0,0 +1,340 @@
+//! Optional end-to-end encrypted sync of conversations through a bucket or
+//! WebDAV folder the user brings. Each conversation is one object, sealed
+//! with a key derived from the sync passphrase before it leaves the
+//! device. Every conversation and message carries a vector clock: a
+//! remote version that already contains the local one replaces it, and
+//! versions written without seeing each other are merged (messages are
+//! combined and the later conversation details win) and uploaded again.
+//!
+//! Templates, documents and settings stay local. Changing the passphrase
+//! later means starting over with an empty backend.
+
+mod clock;
+pub(crate) mod crypto;
+mod remote;
+mod store;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::Notify;
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::keys;
+use crate::startup;
+use crate::storage::{new_id, now_ms, Database};
+use crypto::{Key, KeyFile};
+use remote::Remote;
+use store::ConversationRecord;
+
+pub use remote::BackendConfig;
+
+const CONFIG_FILE: &str = "sync.json";
+/// S3 secret access key or WebDAV password.
+pub(crate) const SECRET_KEY: &str = "sync-secret";
+pub(crate) const PASSPHRASE_KEY: &str = "sync-passphrase";
+const KEY_FILE: &str = "keys.json";
+const CONVERSATIONS: &str = "conversations";
+/// How long the loop sleeps while automatic sync is off.
+const IDLE_WAIT: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    pub enabled: bool,
+    pub backend: Option<BackendConfig>,
+    /// Minutes between automatic syncs; 0 syncs only when asked.
+    pub interval_minutes: u32,
+    /// Names this install in vector clocks. Generated on first start and
+    /// never changed.
+    pub device_id: String,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            backend: None,
+            interval_minutes: 15,
+            device_id: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncSettings {
+    #[serde(flatten)]
+    pub config: SyncConfig,
+    pub has_secret: bool,
+    pub has_passphrase: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncStatus {
+    pub running: bool,
+    pub last_sync_at: Option<i64>,
+    pub last_error: Option<String>,
+    /// Counts from the last finished sync.
+    pub uploaded: usize,
+    pub downloaded: usize,
+    pub conflicts: usize,
+    /// Conversations with local changes not uploaded yet.
+    pub pending: usize,
+}
+
+/// Managed as Tauri state.
+pub struct SyncEngine {
+    config: Mutex<SyncConfig>,
+    status: Mutex<SyncStatus>,
+    /// Held for the length of a sync so runs never overlap.
+    running: tokio::sync::Mutex<()>,
+    /// The derived key and the salt it was derived with, as deriving takes
+    /// a noticeable moment.
+    key: Mutex<Option<(String, Arc<Key>)>>,
+    wake: Arc<Notify>,
+}
+
+pub fn init(app: &AppHandle) {
+    let mut config = config::read::<SyncConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    if config.device_id.is_empty() {
+        config.device_id = new_id();
+        if let Err(err) = config::write(app, CONFIG_FILE, &config) {
+            tracing::warn!("couldn't save the sync device id: {err}");
+        }
+    }
+    let wake = Arc::new(Notify::new());
+    app.manage(SyncEngine {
+        config: Mutex::new(config),
+        status: Mutex::default(),
+        running: tokio::sync::Mutex::new(()),
+        key: Mutex::new(None),
+        wake: wake.clone(),
+    });
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        startup::warmed().await;
+        loop {
+            let config = app.state::<SyncEngine>().config.lock().unwrap().clone();
+            let automatic = config.enabled && config.interval_minutes > 0;
+            if automatic && !app.state::<Database>().is_locked() {
+                if let Err(err) = run(&app).await {
+                    tracing::warn!("sync failed: {err}");
+                }
+            }
+            let wait = match automatic {
+                true => Duration::from_secs(u64::from(config.interval_minutes) * 60),
+                false => IDLE_WAIT,
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = wake.notified() => {}
+            }
+        }
+    });
+}
+
+fn object_name(conversation_id: &str) -> String {
+    format!("{CONVERSATIONS}/{conversation_id}")
+}
+
+fn settings(config: &SyncConfig) -> SyncSettings {
+    SyncSettings {
+        config: config.clone(),
+        has_secret: keys::load(SECRET_KEY).ok().flatten().is_some(),
+        has_passphrase: keys::load(PASSPHRASE_KEY).ok().flatten().is_some(),
+    }
+}
+
+fn status(app: &AppHandle) -> SyncStatus {
+    let mut status = app.state::<SyncEngine>().status.lock().unwrap().clone();
+    status.pending = app
+        .state::<Database>()
+        .unsynced_conversations()
+        .map_or(0, |ids| ids.len());
+    status
+}
+
+fn announce(app: &AppHandle) {
+    let _ = app.emit("sync-status", status(app));
+}
+
+/// The key for the backend's key file, writing one first if the backend is
+/// new.
+async fn unlock(app: &AppHandle, remote: &Remote, client: &Client) -> Result<Arc<Key>> {
+    let passphrase =
+        keys::load(PASSPHRASE_KEY)?.ok_or_else(|| Error::NotFound("sync passphrase".into()))?;
+    let engine = app.state::<SyncEngine>();
+    let existing = match remote.get(client, KEY_FILE).await? {
+        Some(bytes) => Some(serde_json::from_slice::<KeyFile>(&bytes)?),
+        None => None,
+    };
+    if let (Some(file), Some((salt, key))) = (&existing, &*engine.key.lock().unwrap()) {
+        if &file.salt == salt {
+            return Ok(key.clone());
+        }
+    }
+    let derived = tauri::async_runtime::spawn_blocking(move || match existing {
+        Some(file) => Key::unlock(&passphrase, &file).map(|key| (key, file, false)),
+        None => Key::create(&passphrase).map(|(key, file)| (key, file, true)),
+    })
+    .await
+    .map_err(|e| Error::Io(std::io::Error::other(e)))??;
+    let (key, file, created) = derived;
+    if created {
+        remote
+            .put(client, KEY_FILE, serde_json::to_vec_pretty(&file)?)
+            .await?;
+    }
+    let key = Arc::new(key);
+    *engine.key.lock().unwrap() = Some((file.salt, key.clone()));
+    Ok(key)
+}
+
+#[derive(Default)]
+struct Report {
+    uploaded: usize,
+    downloaded: usize,
+    conflicts: usize,
+    changed: Vec<String>,
+}
+
+/// Pulls what changed on the backend, merges it, then uploads every
+/// conversation with local changes.
+async fn sync_once(app: &AppHandle, config: &SyncConfig) -> Result<Report> {
+    let backend = config
+        .backend
+        .clone()
+        .ok_or_else(|| Error::NotFound("sync backend".into()))?;
+    let secret = keys::load(SECRET_KEY)?.unwrap_or_default();
+    let remote = Remote::new(backend, secret);
+    let client = app.state::<Client>();
+    let db = app.state::<Database>();
+    if db.is_locked() {
+        return Err(Error::Locked);
+    }
+    let key = unlock(app, &remote, &client).await?;
+    let device = config.device_id.as_str();
+    let mut report = Report::default();
+
+    for object in remote.list(&client, CONVERSATIONS).await? {
+        if db.object_etag(&object.name)?.as_deref() == Some(object.etag.as_str()) {
+            continue;
+        }
+        let Some(sealed) = remote.get(&client, &object.name).await? else {
+            continue;
+        };
+        let record: ConversationRecord = serde_json::from_slice(&key.open(&object.name, &sealed)?)?;
+        if object_name(&record.id) != object.name {
+            return Err(Error::Sync(format!(
+                "{} holds another conversation",
+                object.name
+            )));
+        }
+        let merge = db.merge_record(&record, device)?;
+        report.downloaded += 1;
+        report.conflicts += merge.conflicts;
+        if merge.changed {
+            report.changed.push(record.id.clone());
+        }
+        db.set_object_etag(&object.name, &object.etag)?;
+    }
+
+    for id in db.unsynced_conversations()? {
+        let upload = db.prepare_upload(&id, device)?;
+        let name = object_name(&id);
+        let sealed = key.seal(&name, &serde_json::to_vec(&upload.record)?)?;
+        let etag = remote.put(&client, &name, sealed).await?;
+        db.finish_upload(&upload)?;
+        // Without an ETag the next listing downloads it again, which merges
+        // to nothing.
+        db.set_object_etag(&name, etag.as_deref().unwrap_or_default())?;
+        report.uploaded += 1;
+    }
+    Ok(report)
+}
+
+/// Runs one sync unless one is already going, keeping the status current
+/// and emitting `sync-status` as it starts and ends.
+async fn run(app: &AppHandle) -> Result<SyncStatus> {
+    let engine = app.state::<SyncEngine>();
+    let Ok(_running) = engine.running.try_lock() else {
+        return Ok(status(app));
+    };
+    let config = engine.config.lock().unwrap().clone();
+    engine.status.lock().unwrap().running = true;
+    announce(app);
+    let result = sync_once(app, &config).await;
+    {
+        let mut status = engine.status.lock().unwrap();
+        status.running = false;
+        match &result {
+            Ok(report) => {
+                status.last_sync_at = Some(now_ms());
+                status.last_error = None;
+                status.uploaded = report.uploaded;
+                status.downloaded = report.downloaded;
+                status.conflicts = report.conflicts;
+            }
+            Err(err) => status.last_error = Some(err.to_string()),
+        }
+    }
+    announce(app);
+    let report = result?;
+    if !report.changed.is_empty() {
+        let _ = app.emit("conversations-synced", &report.changed);
+    }
+    Ok(status(app))
+}
+
+#[tauri::command]
+pub fn get_sync_config(engine: State<'_, SyncEngine>) -> SyncSettings {
+    settings(&engine.config.lock().unwrap())
+}
+
+/// Replaces the sync settings. `secret` (the S3 secret key or WebDAV
+/// password) and `passphrase` are stored when given; empty ones remove the
+/// stored value. The device id always stays as it is.
+#[tauri::command]
+pub fn set_sync_config(
+    app: AppHandle,
+    engine: State<'_, SyncEngine>,
+    mut config: SyncConfig,
+    secret: Option<String>,
+    passphrase: Option<String>,
+) -> Result<SyncSettings> {
+    for (entry, value) in [(SECRET_KEY, &secret), (PASSPHRASE_KEY, &passphrase)] {
+        match value.as_deref() {
+            Some("") => keys::delete(entry)?,
+            Some(value) => keys::store(entry, value)?,
+            None => {}
+        }
+    }
+    if passphrase.is_some() {
+        *engine.key.lock().unwrap() = None;
+    }
+    let mut current = engine.config.lock().unwrap();
+    config.device_id = current.device_id.clone();
+    config::write(&app, CONFIG_FILE, &config)?;
+    *current = config;
+    engine.wake.notify_one();
+    Ok(settings(&current))
+}
+
+/// Syncs right away and returns the resulting status.
+#[tauri::command]
+pub async fn sync_now(app: AppHandle) -> Result<SyncStatus> {
+    run(&app).await
+}
+
+#[tauri::command]
+pub fn get_sync_status(app: AppHandle) -> SyncStatus {
+    status(&app)
+}
@@ -0,0 +1,396 @@
+//! Where synced objects live: an S3-compatible bucket or a WebDAV folder.
+//! Objects are addressed by slash-separated names such as
+//! `conversations/<id>`; both backends only need listing with ETags,
+//! reads and whole-object writes.
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, Url};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::backup::hex;
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BackendConfig {
+    /// AWS S3 or anything speaking its API (R2, B2, MinIO). The secret
+    /// access key is kept in the credential store.
+    S3 {
+        /// e.g. `https://s3.eu-west-1.amazonaws.com`.
+        endpoint: String,
+        region: String,
+        bucket: String,
+        access_key_id: String,
+        /// Folder inside the bucket; empty for the top level.
+        #[serde(default)]
+        prefix: String,
+        /// Address the bucket as `bucket.host` rather than `host/bucket`.
+        #[serde(default)]
+        virtual_hosted: bool,
+    },
+    /// A WebDAV folder such as Nextcloud's. The password is kept in the
+    /// credential store.
+    Webdav { url: String, username: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct RemoteObject {
+    pub name: String,
+    pub etag: String,
+}
+
+pub struct Remote {
+    config: BackendConfig,
+    secret: String,
+}
+
+/// Percent-encodes everything but RFC 3986's unreserved characters, as
+/// SigV4 expects; `/` is kept when encoding a path.
+fn encode(value: &str, keep_slash: bool) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn invalid_url(url: &str) -> Error {
+    Error::InvalidSetting(format!("sync backend URL `{url}`"))
+}
+
+/// Turns an unsuccessful response into an error carrying the body.
+async fn check(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(Error::Sync(format!(
+        "the backend returned {status}: {}",
+        body.trim()
+    )))
+}
+
+fn etag(response: &Response) -> Option<String> {
+    response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim_matches('"').to_string())
+}
+
+/// Text of the elements named `fields` inside each `record` element, by
+/// local name so namespace prefixes don't matter.
+fn xml_records(xml: &str, record: &str, fields: &[&str]) -> Result<Vec<Vec<String>>> {
+    let mut reader = Reader::from_str(xml);
+    let mut records = Vec::new();
+    let mut current: Option<Vec<String>> = None;
+    let mut field: Option<usize> = None;
+    loop {
+        match reader
+            .read_event()
+            .map_err(|e| Error::Sync(format!("unexpected listing from the backend: {e}")))?
+        {
+            Event::Start(e) => {
+                let name = e.local_name();
+                if name.as_ref() == record.as_bytes() {
+                    current = Some(vec![String::new(); fields.len()]);
+                } else if current.is_some() {
+                    field = fields.iter().position(|f| f.as_bytes() == name.as_ref());
+                }
+            }
+            Event::Text(t) => {
+                if let (Some(values), Some(index)) = (current.as_mut(), field) {
+                    values[index].push_str(&t.unescape().unwrap_or_default());
+                }
+            }
+            Event::End(e) => {
+                if e.local_name().as_ref() == record.as_bytes() {
+                    records.extend(current.take());
+                }
+                field = None;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(records)
+}
+
+impl Remote {
+    pub fn new(config: BackendConfig, secret: String) -> Self {
+        Self { config, secret }
+    }
+
+    pub async fn list(&self, client: &Client, folder: &str) -> Result<Vec<RemoteObject>> {
+        match &self.config {
+            BackendConfig::S3 { .. } => self.s3_list(client, folder).await,
+            BackendConfig::Webdav { .. } => self.webdav_list(client, folder).await,
+        }
+    }
+
+    /// The object's contents, or `None` if it doesn't exist.
+    pub async fn get(&self, client: &Client, name: &str) -> Result<Option<Vec<u8>>> {
+        let request = match &self.config {
+            BackendConfig::S3 { .. } => {
+                let url = self.s3_url(name, &[])?;
+                self.s3_signed(client, Method::GET, url, &[])
+            }
+            BackendConfig::Webdav { .. } => {
+                self.webdav(client, Method::GET, self.webdav_url(name)?)
+            }
+        };
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(check(response).await?.bytes().await?.to_vec()))
+    }
+
+    /// Writes the object, returning its new ETag when the backend says.
+    pub async fn put(&self, client: &Client, name: &str, body: Vec<u8>) -> Result<Option<String>> {
+        match &self.config {
+            BackendConfig::S3 { .. } => {
+                let url = self.s3_url(name, &[])?;
+                let response = self
+                    .s3_signed(client, Method::PUT, url, &body)
+                    .body(body)
+                    .send()
+                    .await?;
+                Ok(etag(&check(response).await?))
+            }
+            BackendConfig::Webdav { .. } => self.webdav_put(client, name, body).await,
+        }
+    }
+
+    // S3
+
+    fn s3_key(&self, name: &str) -> String {
+        match &self.config {
+            BackendConfig::S3 { prefix, .. } if !prefix.trim_matches('/').is_empty() => {
+                format!("{}/{name}", prefix.trim_matches('/'))
+            }
+            _ => name.to_string(),
+        }
+    }
+
+    /// URL of the object `name`, or of the bucket itself when `name` is
+    /// empty.
+    fn s3_url(&self, name: &str, query: &[(&str, &str)]) -> Result<Url> {
+        let BackendConfig::S3 {
+            endpoint,
+            bucket,
+            virtual_hosted,
+            ..
+        } = &self.config
+        else {
+            unreachable!("not an S3 backend");
+        };
+        let mut url =
+            Url::parse(endpoint.trim_end_matches('/')).map_err(|_| invalid_url(endpoint))?;
+        let mut path = Vec::new();
+        if *virtual_hosted {
+            let host = url.host_str().ok_or_else(|| invalid_url(endpoint))?;
+            url.set_host(Some(&format!("{bucket}.{host}")))
+                .map_err(|_| invalid_url(endpoint))?;
+        } else {
+            path.push(encode(bucket, false));
+        }
+        if !name.is_empty() {
+            path.push(encode(&self.s3_key(name), true));
+        }
+        url.set_path(&format!("/{}", path.join("/")));
+        if !query.is_empty() {
+            let query: Vec<String> = query
+                .iter()
+                .map(|(k, v)| format!("{}={}", encode(k, false), encode(v, false)))
+                .collect();
+            url.set_query(Some(&query.join("&")));
+        }
+        Ok(url)
+    }
+
+    /// A request signed with AWS Signature Version 4.
+    fn s3_signed(&self, client: &Client, method: Method, url: Url, body: &[u8]) -> RequestBuilder {
+        let BackendConfig::S3 {
+            region,
+            access_key_id,
+            ..
+        } = &self.config
+        else {
+            unreachable!("not an S3 backend");
+        };
+        let now = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &now[..8];
+        let payload_hash = hex(&Sha256::digest(body));
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (host, None) => host.unwrap_or_default().to_string(),
+            (None, Some(_)) => String::new(),
+        };
+        let mut query: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(k, v)| (encode(&k, false), encode(&v, false)))
+            .collect();
+        query.sort();
+        let query: Vec<String> = query.into_iter().map(|(k, v)| format!("{k}={v}")).collect();
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{method}\n{}\n{}\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{now}\n\n{signed_headers}\n{payload_hash}",
+            url.path(),
+            query.join("&"),
+        );
+        let scope = format!("{date}/{region}/s3/aws4_request");
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{now}\n{scope}\n{}",
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let mut key = format!("AWS4{}", self.secret).into_bytes();
+        for part in [date, region.as_str(), "s3", "aws4_request"] {
+            key = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, &key), part.as_bytes())
+                .as_ref()
+                .to_vec();
+        }
+        let signature = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, &key),
+            string_to_sign.as_bytes(),
+        );
+        client
+            .request(method, url)
+            .header("x-amz-date", &now)
+            .header("x-amz-content-sha256", payload_hash)
+            .header(
+                reqwest::header::AUTHORIZATION,
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={access_key_id}/{scope}, SignedHeaders={signed_headers}, Signature={}",
+                    hex(signature.as_ref())
+                ),
+            )
+    }
+
+    async fn s3_list(&self, client: &Client, folder: &str) -> Result<Vec<RemoteObject>> {
+        let prefix = self.s3_key(&format!("{folder}/"));
+        let strip = prefix.len() - folder.len() - 1;
+        let mut objects = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix.as_str())];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token));
+            }
+            let url = self.s3_url("", &query)?;
+            let response = self.s3_signed(client, Method::GET, url, &[]).send().await?;
+            let xml = check(response).await?.text().await?;
+            for fields in xml_records(&xml, "Contents", &["Key", "ETag"])? {
+                objects.push(RemoteObject {
+                    name: fields[0].get(strip..).unwrap_or_default().to_string(),
+                    etag: fields[1].trim_matches('"').to_string(),
+                });
+            }
+            let next = xml_records(
+                &xml,
+                "ListBucketResult",
+                &["IsTruncated", "NextContinuationToken"],
+            )?;
+            match next.into_iter().next() {
+                Some(fields) if fields[0] == "true" && !fields[1].is_empty() => {
+                    token = Some(fields[1].clone())
+                }
+                _ => break,
+            }
+        }
+        Ok(objects)
+    }
+
+    // WebDAV
+
+    fn webdav_url(&self, name: &str) -> Result<Url> {
+        let BackendConfig::Webdav { url, .. } = &self.config else {
+            unreachable!("not a WebDAV backend");
+        };
+        let base = format!("{}/", url.trim_end_matches('/'));
+        Url::parse(&base)
+            .and_then(|base| base.join(&encode(name, true)))
+            .map_err(|_| invalid_url(url))
+    }
+
+    fn webdav(&self, client: &Client, method: Method, url: Url) -> RequestBuilder {
+        let BackendConfig::Webdav { username, .. } = &self.config else {
+            unreachable!("not a WebDAV backend");
+        };
+        client
+            .request(method, url)
+            .basic_auth(username, Some(&self.secret))
+    }
+
+    async fn webdav_list(&self, client: &Client, folder: &str) -> Result<Vec<RemoteObject>> {
+        let propfind = Method::from_bytes(b"PROPFIND").expect("valid method");
+        let response = self
+            .webdav(client, propfind, self.webdav_url(&format!("{folder}/"))?)
+            .header("Depth", "1")
+            .header(reqwest::header::CONTENT_TYPE, "application/xml")
+            .body(
+                r#"<?xml version="1.0"?><d:propfind xmlns:d="DAV:"><d:prop><d:getetag/></d:prop></d:propfind>"#,
+            )
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        let xml = check(response).await?.text().await?;
+        Ok(xml_records(&xml, "response", &["href", "getetag"])?
+            .into_iter()
+            // The folder itself comes back too, with a trailing slash.
+            .filter(|fields| !fields[0].ends_with('/'))
+            .filter_map(|fields| {
+                let file = fields[0].rsplit('/').next()?.to_string();
+                Some(RemoteObject {
+                    name: format!("{folder}/{file}"),
+                    etag: fields[1].trim_matches('"').to_string(),
+                })
+            })
+            .collect())
+    }
+
+    async fn webdav_put(
+        &self,
+        client: &Client,
+        name: &str,
+        body: Vec<u8>,
+    ) -> Result<Option<String>> {
+        let url = self.webdav_url(name)?;
+        let response = self
+            .webdav(client, Method::PUT, url.clone())
+            .body(body.clone())
+            .send()
+            .await?;
+        // Most servers answer 409 when the parent folder doesn't exist yet.
+        if !matches!(
+            response.status(),
+            StatusCode::CONFLICT | StatusCode::NOT_FOUND
+        ) {
+            return Ok(etag(&check(response).await?));
+        }
+        if let Some((folder, _)) = name.rsplit_once('/') {
+            let mkcol = Method::from_bytes(b"MKCOL").expect("valid method");
+            self.webdav(client, mkcol, self.webdav_url(&format!("{folder}/"))?)
+                .send()
+                .await?;
+        }
+        let response = self
+            .webdav(client, Method::PUT, url)
+            .body(body)
+            .send()
+            .await?;
+        Ok(etag(&check(response).await?))
+    }
+}
@@ -0,0 +1,474 @@
+//! The database side of sync: conversations packed into records carrying
+//! their vector clocks, and records from other devices merged back in.
+
+use std::collections::HashMap;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use super::clock::{Causality, VectorClock};
+use crate::error::Result;
+use crate::llm::Role;
+use crate::storage::Database;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConversationData {
+    pub title: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub head_id: Option<String>,
+    pub summary: Option<String>,
+    pub summary_through: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessageData {
+    pub id: String,
+    pub parent_id: Option<String>,
+    pub role: Role,
+    pub content: String,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageRecord {
+    pub clock: VectorClock,
+    #[serde(flatten)]
+    pub data: MessageData,
+}
+
+/// One conversation as stored on the backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationRecord {
+    pub id: String,
+    pub clock: VectorClock,
+    /// `None` once the conversation has been deleted.
+    pub conversation: Option<ConversationData>,
+    #[serde(default)]
+    pub messages: Vec<MessageRecord>,
+}
+
+/// A record ready to upload, with the change count of every entity in it
+/// so changes made during the upload aren't marked as synced.
+pub struct Upload {
+    pub record: ConversationRecord,
+    versions: Vec<(String, i64)>,
+}
+
+#[derive(Debug, Default)]
+pub struct Merge {
+    /// Whether anything local changed.
+    pub changed: bool,
+    /// Entities edited on both sides without seeing each other.
+    pub conflicts: usize,
+}
+
+struct Entity {
+    clock: VectorClock,
+    dirty: i64,
+}
+
+impl Entity {
+    /// The clock including unsynced local changes, which count as one more
+    /// tick from this device.
+    fn current(&self, device: &str) -> VectorClock {
+        let mut clock = self.clock.clone();
+        if self.dirty > 0 {
+            clock.tick(device);
+        }
+        clock
+    }
+}
+
+fn parse_clock(json: String) -> VectorClock {
+    serde_json::from_str(&json).unwrap_or_default()
+}
+
+fn entity(conn: &Connection, id: &str) -> Result<Option<Entity>> {
+    Ok(conn
+        .query_row(
+            "SELECT clock, dirty FROM sync_entities WHERE id = ?1",
+            [id],
+            |row| {
+                Ok(Entity {
+                    clock: parse_clock(row.get(0)?),
+                    dirty: row.get(1)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+fn set_entity(
+    conn: &Connection,
+    id: &str,
+    kind: &str,
+    conversation_id: &str,
+    clock: &VectorClock,
+    dirty: i64,
+    deleted: bool,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO sync_entities (id, kind, conversation_id, clock, dirty, deleted)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT (id) DO UPDATE SET
+             clock = excluded.clock, dirty = excluded.dirty, deleted = excluded.deleted",
+        params![
+            id,
+            kind,
+            conversation_id,
+            serde_json::to_string(clock)?,
+            dirty,
+            deleted
+        ],
+    )?;
+    Ok(())
+}
+
+fn conversation_data(conn: &Connection, id: &str) -> Result<Option<ConversationData>> {
+    Ok(conn
+        .query_row(
+            "SELECT title, created_at, updated_at, head_id, summary, summary_through
+             FROM conversations WHERE id = ?1",
+            [id],
+            |row| {
+                Ok(ConversationData {
+                    title: row.get(0)?,
+                    created_at: row.get(1)?,
+                    updated_at: row.get(2)?,
+                    head_id: row.get(3)?,
+                    summary: row.get(4)?,
+                    summary_through: row.get(5)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+const MESSAGE_COLUMNS: &str = "id, parent_id, role, content, provider, model, created_at";
+
+fn message_data(row: &rusqlite::Row<'_>) -> rusqlite::Result<MessageData> {
+    Ok(MessageData {
+        id: row.get(0)?,
+        parent_id: row.get(1)?,
+        role: row.get(2)?,
+        content: row.get(3)?,
+        provider: row.get(4)?,
+        model: row.get(5)?,
+        created_at: row.get(6)?,
+    })
+}
+
+fn write_conversation(conn: &Connection, id: &str, data: &ConversationData) -> Result<()> {
+    conn.execute(
+        "INSERT INTO conversations
+             (id, title, created_at, updated_at, head_id, summary, summary_through)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+         ON CONFLICT (id) DO UPDATE SET
+             title = excluded.title, created_at = excluded.created_at,
+             updated_at = excluded.updated_at, head_id = excluded.head_id,
+             summary = excluded.summary, summary_through = excluded.summary_through",
+        params![
+            id,
+            data.title,
+            data.created_at,
+            data.updated_at,
+            data.head_id,
+            data.summary,
+            data.summary_through
+        ],
+    )?;
+    Ok(())
+}
+
+/// Inserts or overwrites a message. A parent that hasn't arrived is
+/// dropped rather than failing the whole record.
+fn write_message(conn: &Connection, conversation_id: &str, data: &MessageData) -> Result<()> {
+    let parent_id = match &data.parent_id {
+        Some(parent) => conn
+            .query_row("SELECT id FROM messages WHERE id = ?1", [parent], |row| {
+                row.get::<_, String>(0)
+            })
+            .optional()?,
+        None => None,
+    };
+    conn.execute(
+        "INSERT INTO messages
+             (id, conversation_id, parent_id, role, content, provider, model, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT (id) DO UPDATE SET
+             parent_id = excluded.parent_id, role = excluded.role,
+             content = excluded.content, provider = excluded.provider,
+             model = excluded.model, created_at = excluded.created_at",
+        params![
+            data.id,
+            conversation_id,
+            parent_id,
+            data.role,
+            data.content,
+            data.provider,
+            data.model,
+            data.created_at
+        ],
+    )?;
+    Ok(())
+}
+
+/// Which side wins when both changed the conversation: a live one beats a
+/// deletion, otherwise the later update. Every device picks the same.
+fn remote_conversation_wins(
+    local: Option<&ConversationData>,
+    remote: Option<&ConversationData>,
+) -> bool {
+    match (local, remote) {
+        (Some(local), Some(remote)) => {
+            (remote.updated_at, &remote.title) >= (local.updated_at, &local.title)
+        }
+        (local, remote) => local.is_none() && remote.is_some(),
+    }
+}
+
+/// Concurrent edits of a message go to the later one, then the longer.
+fn remote_message_wins(local: &MessageData, remote: &MessageData) -> bool {
+    (remote.created_at, remote.content.len(), &remote.content)
+        >= (local.created_at, local.content.len(), &local.content)
+}
+
+impl Database {
+    /// Conversations with changes that haven't been uploaded.
+    pub(crate) fn unsynced_conversations(&self) -> Result<Vec<String>> {
+        let conn = self.conn()?;
+        let mut stmt =
+            conn.prepare("SELECT DISTINCT conversation_id FROM sync_entities WHERE dirty > 0")?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(ids)
+    }
+
+    /// Packs a conversation for upload, counting its unsynced changes as a
+    /// tick from `device`.
+    pub(crate) fn prepare_upload(&self, conversation_id: &str, device: &str) -> Result<Upload> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let mut clocks = HashMap::new();
+        let mut versions = Vec::new();
+        {
+            let mut stmt = tx.prepare(
+                "SELECT id, clock, dirty FROM sync_entities
+                 WHERE conversation_id = ?1 AND deleted = 0 OR id = ?1",
+            )?;
+            let rows = stmt.query_map([conversation_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    parse_clock(row.get(1)?),
+                    row.get::<_, i64>(2)?,
+                ))
+            })?;
+            for row in rows {
+                let (id, mut clock, dirty) = row?;
+                if dirty > 0 {
+                    clock.tick(device);
+                    tx.execute(
+                        "UPDATE sync_entities SET clock = ?2 WHERE id = ?1",
+                        params![id, serde_json::to_string(&clock)?],
+                    )?;
+                    versions.push((id.clone(), dirty));
+                }
+                clocks.insert(id, clock);
+            }
+        }
+        let conversation = conversation_data(&tx, conversation_id)?;
+        let mut messages = Vec::new();
+        if conversation.is_some() {
+            let mut stmt = tx.prepare(&format!(
+                "SELECT {MESSAGE_COLUMNS} FROM messages
+                 WHERE conversation_id = ?1 ORDER BY created_at, rowid"
+            ))?;
+            for data in stmt.query_map([conversation_id], message_data)? {
+                let data = data?;
+                messages.push(MessageRecord {
+                    clock: clocks.get(&data.id).cloned().unwrap_or_default(),
+                    data,
+                });
+            }
+        }
+        let record = ConversationRecord {
+            id: conversation_id.to_string(),
+            clock: clocks.remove(conversation_id).unwrap_or_default(),
+            conversation,
+            messages,
+        };
+        tx.commit()?;
+        Ok(Upload { record, versions })
+    }
+
+    /// Marks what went up in `upload` as synced, unless it changed again in
+    /// the meantime.
+    pub(crate) fn finish_upload(&self, upload: &Upload) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        for (id, dirty) in &upload.versions {
+            tx.execute(
+                "UPDATE sync_entities SET dirty = 0 WHERE id = ?1 AND dirty = ?2",
+                params![id, dirty],
+            )?;
+        }
+        // Deleted messages are covered by their conversation's tombstone.
+        tx.execute(
+            "DELETE FROM sync_entities
+             WHERE conversation_id = ?1 AND kind = 'message' AND deleted = 1",
+            [&upload.record.id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Merges a record from the backend. Whatever has to go back up, a
+    /// local win or a merged clock, is left marked unsynced.
+    pub(crate) fn merge_record(&self, record: &ConversationRecord, device: &str) -> Result<Merge> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let mut merge = Merge::default();
+
+        let local = entity(&tx, &record.id)?;
+        let local_data = conversation_data(&tx, &record.id)?;
+        let local_clock = local
+            .as_ref()
+            .map(|e| e.current(device))
+            .unwrap_or_default();
+        let (remote_wins, upload) = match record.clock.compare(&local_clock) {
+            Causality::Equal => (false, false),
+            Causality::After => (true, false),
+            Causality::Before => (false, true),
+            Causality::Concurrent => {
+                merge.conflicts += 1;
+                let wins =
+                    remote_conversation_wins(local_data.as_ref(), record.conversation.as_ref());
+                (wins, true)
+            }
+        };
+        if remote_wins && local_data != record.conversation {
+            match &record.conversation {
+                Some(data) => write_conversation(&tx, &record.id, data)?,
+                None => {
+                    tx.execute("DELETE FROM conversations WHERE id = ?1", [&record.id])?;
+                    tx.execute(
+                        "UPDATE sync_entities SET dirty = 0
+                         WHERE conversation_id = ?1 AND kind = 'message'",
+                        [&record.id],
+                    )?;
+                }
+            }
+            merge.changed = true;
+        }
+        let alive = if remote_wins {
+            record.conversation.is_some()
+        } else {
+            local_data.is_some()
+        };
+
+        if alive {
+            let mut messages: Vec<&MessageRecord> = record.messages.iter().collect();
+            messages.sort_by_key(|m| m.data.created_at);
+            for message in messages {
+                let local = entity(&tx, &message.data.id)?;
+                let local_row = tx
+                    .query_row(
+                        &format!("SELECT {MESSAGE_COLUMNS} FROM messages WHERE id = ?1"),
+                        [&message.data.id],
+                        message_data,
+                    )
+                    .optional()?;
+                let (clock, dirty) = match (local_row, local) {
+                    (Some(row), Some(local)) => {
+                        match message.clock.compare(&local.current(device)) {
+                            Causality::Equal => continue,
+                            Causality::Before => {
+                                (local.clock.merged(&message.clock), local.dirty.max(1))
+                            }
+                            Causality::After => {
+                                if row != message.data {
+                                    write_message(&tx, &record.id, &message.data)?;
+                                    merge.changed = true;
+                                }
+                                (message.clock.clone(), 0)
+                            }
+                            Causality::Concurrent => {
+                                merge.conflicts += 1;
+                                if remote_message_wins(&row, &message.data) {
+                                    write_message(&tx, &record.id, &message.data)?;
+                                    merge.changed = true;
+                                }
+                                (local.clock.merged(&message.clock), local.dirty.max(1))
+                            }
+                        }
+                    }
+                    _ => {
+                        write_message(&tx, &record.id, &message.data)?;
+                        merge.changed = true;
+                        (message.clock.clone(), 0)
+                    }
+                };
+                set_entity(
+                    &tx,
+                    &message.data.id,
+                    "message",
+                    &record.id,
+                    &clock,
+                    dirty,
+                    false,
+                )?;
+            }
+        }
+
+        let (clock, dirty) = match (remote_wins, upload) {
+            (true, false) => (record.clock.clone(), 0),
+            (_, true) => (
+                local
+                    .as_ref()
+                    .map(|e| e.clock.merged(&record.clock))
+                    .unwrap_or_else(|| record.clock.clone()),
+                local.as_ref().map_or(1, |e| e.dirty.max(1)),
+            ),
+            (false, false) => (
+                local.as_ref().map(|e| e.clock.clone()).unwrap_or_default(),
+                local.as_ref().map_or(0, |e| e.dirty),
+            ),
+        };
+        let deleted = conversation_data(&tx, &record.id)?.is_none();
+        set_entity(
+            &tx,
+            &record.id,
+            "conversation",
+            &record.id,
+            &clock,
+            dirty,
+            deleted,
+        )?;
+        tx.commit()?;
+        Ok(merge)
+    }
+
+    pub(crate) fn object_etag(&self, name: &str) -> Result<Option<String>> {
+        Ok(self
+            .conn()?
+            .query_row(
+                "SELECT etag FROM sync_objects WHERE name = ?1",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    pub(crate) fn set_object_etag(&self, name: &str, etag: &str) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO sync_objects (name, etag) VALUES (?1, ?2)
+             ON CONFLICT (name) DO UPDATE SET etag = excluded.etag",
+            params![name, etag],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,531 @@
+//! What the machine has to spare: memory, GPU memory where it can be read,
+//! CPU load and room on the disk holding the data directory. Mostly for
+//! local models, to tell before loading one whether it fits and to warn
+//! when it's pushing the system into swap.
+//!
+//! Each platform is read with what it ships: `/proc` and `sysfs` on Linux,
+//! `sysctl` and `vm_stat` on macOS, CIM through PowerShell on Windows.
+//! NVIDIA cards are read with `nvidia-smi` wherever it is installed; Apple
+//! Silicon shares system memory with the GPU, and is reported as such.
+//!
+//! While the monitor is on, a `system-stats` event goes out every interval,
+//! and `memory-pressure` whenever memory use crosses the warning level.
+
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::profile;
+use crate::storage::now_ms;
+
+const CONFIG_FILE: &str = "system_monitor.json";
+const MIN_INTERVAL_SECS: u64 = 1;
+/// Memory use must fall this far below the warning level before another
+/// warning can go out, so hovering at the line doesn't spam.
+const PRESSURE_HYSTERESIS: f32 = 5.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SystemMonitorConfig {
+    /// Emit `system-stats` periodically.
+    pub enabled: bool,
+    pub interval_secs: u64,
+    /// Percentage of RAM (or of any GPU's memory) in use above which
+    /// `memory-pressure` is emitted.
+    pub warn_percent: f32,
+}
+
+impl Default for SystemMonitorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 5,
+            warn_percent: 90.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MemoryStats {
+    pub total_bytes: u64,
+    /// What can be handed out without swapping, caches included.
+    pub available_bytes: u64,
+    pub used_percent: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GpuStats {
+    pub name: String,
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub used_percent: f32,
+    /// Shares system memory, as on Apple Silicon; then it is the same pool
+    /// as `memory`.
+    pub unified: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskStats {
+    /// The directory the figures are for: the data directory.
+    pub path: std::path::PathBuf,
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemStats {
+    pub memory: MemoryStats,
+    /// Empty when no GPU memory could be read.
+    pub gpus: Vec<GpuStats>,
+    /// Load across all cores, from 0 to 100; `None` until there are two
+    /// readings to compare where the platform only counts time.
+    pub cpu_percent: Option<f32>,
+    pub cpu_count: usize,
+    pub disk: Option<DiskStats>,
+    pub sampled_at: i64,
+}
+
+/// Payload of the `memory-pressure` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryPressure {
+    /// `memory`, or the name of the GPU that is filling up.
+    pub resource: String,
+    pub used_percent: f32,
+    pub available_bytes: u64,
+}
+
+/// Managed as Tauri state.
+pub struct SystemMonitor {
+    config: Mutex<SystemMonitorConfig>,
+}
+
+fn percent(used: u64, total: u64) -> f32 {
+    if total == 0 {
+        return 0.0;
+    }
+    (used as f64 * 100.0 / total as f64) as f32
+}
+
+impl MemoryStats {
+    fn new(total_bytes: u64, available_bytes: u64) -> Self {
+        let available_bytes = available_bytes.min(total_bytes);
+        Self {
+            total_bytes,
+            available_bytes,
+            used_percent: percent(total_bytes - available_bytes, total_bytes),
+        }
+    }
+}
+
+impl GpuStats {
+    fn new(name: String, total_bytes: u64, used_bytes: u64, unified: bool) -> Self {
+        Self {
+            used_percent: percent(used_bytes, total_bytes),
+            name,
+            total_bytes,
+            used_bytes,
+            unified,
+        }
+    }
+}
+
+/// `MemTotal` and `MemAvailable` from `/proc/meminfo`, in bytes.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_meminfo(text: &str) -> Option<MemoryStats> {
+    let field = |name: &str| -> Option<u64> {
+        let line = text.lines().find(|l| l.starts_with(name))?;
+        let kib: u64 = line[name.len()..]
+            .trim_start_matches(':')
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()?;
+        Some(kib * 1024)
+    };
+    Some(MemoryStats::new(field("MemTotal")?, field("MemAvailable")?))
+}
+
+/// Free, inactive, speculative and purgeable pages from `vm_stat`: what
+/// macOS hands out before it starts compressing or swapping.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_vm_stat(text: &str) -> Option<u64> {
+    let page_size: u64 = text
+        .lines()
+        .next()?
+        .split("page size of")
+        .nth(1)?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()?;
+    let pages = |name: &str| -> u64 {
+        text.lines()
+            .find(|l| l.starts_with(name))
+            .and_then(|l| l.rsplit(':').next())
+            .and_then(|v| v.trim().trim_end_matches('.').parse().ok())
+            .unwrap_or_default()
+    };
+    let free = pages("Pages free")
+        + pages("Pages inactive")
+        + pages("Pages speculative")
+        + pages("Pages purgeable");
+    Some(free * page_size)
+}
+
+/// Busy and total jiffies over all cores, from the first line of
+/// `/proc/stat`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_proc_stat(text: &str) -> Option<(u64, u64)> {
+    let fields: Vec<u64> = text
+        .lines()
+        .next()?
+        .strip_prefix("cpu ")?
+        .split_whitespace()
+        .filter_map(|f| f.parse().ok())
+        .collect();
+    // user nice system idle iowait irq softirq steal ...
+    let idle = fields.get(3)? + fields.get(4).copied().unwrap_or_default();
+    let total: u64 = fields.iter().take(8).sum();
+    Some((total - idle, total))
+}
+
+/// Rows of `nvidia-smi --query-gpu=name,memory.total,memory.used
+/// --format=csv,noheader,nounits`, in MiB.
+fn parse_nvidia_smi(text: &str) -> Vec<GpuStats> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split(',').map(str::trim);
+            let name = fields.next()?.to_string();
+            let total: u64 = fields.next()?.parse().ok()?;
+            let used: u64 = fields.next()?.parse().ok()?;
+            Some(GpuStats::new(
+                name,
+                total * 1024 * 1024,
+                used * 1024 * 1024,
+                false,
+            ))
+        })
+        .collect()
+}
+
+fn nvidia_gpus() -> Vec<GpuStats> {
+    crate::process::command("nvidia-smi")
+        .args([
+            "--query-gpu=name,memory.total,memory.used",
+            "--format=csv,noheader,nounits",
+        ])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .map(|out| parse_nvidia_smi(&String::from_utf8_lossy(&out.stdout)))
+        .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn disk(path: &Path) -> Option<DiskStats> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is a valid C string and `stat` a properly sized
+    // buffer the call fills in.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let block = stat.f_frsize as u64;
+    Some(DiskStats {
+        path: path.to_path_buf(),
+        total_bytes: stat.f_blocks as u64 * block,
+        available_bytes: stat.f_bavail as u64 * block,
+    })
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::sync::Mutex;
+
+    use super::{parse_meminfo, parse_proc_stat, GpuStats, MemoryStats};
+
+    /// The previous `/proc/stat` reading, to measure load against.
+    static LAST_CPU: Mutex<Option<(u64, u64)>> = Mutex::new(None);
+
+    pub(super) fn memory() -> Option<MemoryStats> {
+        parse_meminfo(&std::fs::read_to_string("/proc/meminfo").ok()?)
+    }
+
+    pub(super) fn cpu_percent() -> Option<f32> {
+        let (busy, total) = parse_proc_stat(&std::fs::read_to_string("/proc/stat").ok()?)?;
+        let previous = LAST_CPU.lock().unwrap().replace((busy, total));
+        let (last_busy, last_total) = previous?;
+        let elapsed = total.checked_sub(last_total).filter(|&t| t > 0)?;
+        Some(super::percent(busy.saturating_sub(last_busy), elapsed))
+    }
+
+    /// AMD cards, which the amdgpu driver describes in sysfs.
+    pub(super) fn gpus(_memory: &MemoryStats) -> Vec<GpuStats> {
+        let Ok(cards) = std::fs::read_dir("/sys/class/drm") else {
+            return Vec::new();
+        };
+        let mut gpus = Vec::new();
+        for card in cards.flatten() {
+            let name = card.file_name().to_string_lossy().into_owned();
+            if !name.starts_with("card") || name.contains('-') {
+                continue;
+            }
+            let device = card.path().join("device");
+            let read = |file: &str| std::fs::read_to_string(device.join(file)).ok();
+            let number = |file: &str| read(file).and_then(|v| v.trim().parse::<u64>().ok());
+            let (Some(total), Some(used)) =
+                (number("mem_info_vram_total"), number("mem_info_vram_used"))
+            else {
+                continue;
+            };
+            let label = read("product_name")
+                .map(|n| n.trim().to_string())
+                .filter(|n| !n.is_empty())
+                .unwrap_or_else(|| format!("AMD GPU ({name})"));
+            gpus.push(GpuStats::new(label, total, used, false));
+        }
+        gpus
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::{parse_vm_stat, GpuStats, MemoryStats};
+
+    fn run(program: &str, args: &[&str]) -> Option<String> {
+        let out = crate::process::command(program).args(args).output().ok()?;
+        out.status
+            .success()
+            .then(|| String::from_utf8_lossy(&out.stdout).into_owned())
+    }
+
+    pub(super) fn memory() -> Option<MemoryStats> {
+        let total = run("sysctl", &["-n", "hw.memsize"])?.trim().parse().ok()?;
+        let available = parse_vm_stat(&run("vm_stat", &[])?)?;
+        Some(MemoryStats::new(total, available))
+    }
+
+    pub(super) fn cpu_percent() -> Option<f32> {
+        let out = run("ps", &["-A", "-o", "%cpu="])?;
+        let sum: f32 = out
+            .lines()
+            .filter_map(|l| l.trim().replace(',', ".").parse::<f32>().ok())
+            .sum();
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Some((sum / cores as f32).clamp(0.0, 100.0))
+    }
+
+    /// Apple Silicon's GPU works out of system memory.
+    pub(super) fn gpus(memory: &MemoryStats) -> Vec<GpuStats> {
+        if !cfg!(target_arch = "aarch64") {
+            return Vec::new();
+        }
+        let name = run("sysctl", &["-n", "machdep.cpu.brand_string"])
+            .map(|n| n.trim().to_string())
+            .unwrap_or_else(|| "Apple Silicon".into());
+        vec![GpuStats::new(
+            name,
+            memory.total_bytes,
+            memory.total_bytes - memory.available_bytes,
+            true,
+        )]
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::{GpuStats, MemoryStats};
+
+    const SCRIPT: &str = "$os = Get-CimInstance Win32_OperatingSystem; \
+        $cpu = (Get-CimInstance Win32_Processor | Measure-Object -Property LoadPercentage -Average).Average; \
+        Write-Output \"$($os.TotalVisibleMemorySize) $($os.FreePhysicalMemory) $cpu\"";
+
+    fn sample() -> Option<(u64, u64, Option<f32>)> {
+        let out = crate::process::command("powershell")
+            .args(["-NoProfile", "-NonInteractive", "-Command", SCRIPT])
+            .output()
+            .ok()?;
+        let text = String::from_utf8_lossy(&out.stdout);
+        let mut fields = text.split_whitespace();
+        let total: u64 = fields.next()?.parse().ok()?;
+        let free: u64 = fields.next()?.parse().ok()?;
+        let cpu = fields.next().and_then(|c| c.replace(',', ".").parse().ok());
+        Some((total * 1024, free * 1024, cpu))
+    }
+
+    pub(super) fn memory() -> Option<MemoryStats> {
+        let (total, free, _) = sample()?;
+        Some(MemoryStats::new(total, free))
+    }
+
+    pub(super) fn cpu_percent() -> Option<f32> {
+        sample()?.2
+    }
+
+    pub(super) fn gpus(_memory: &MemoryStats) -> Vec<GpuStats> {
+        Vec::new()
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod platform {
+    use super::{GpuStats, MemoryStats};
+
+    pub(super) fn memory() -> Option<MemoryStats> {
+        None
+    }
+
+    pub(super) fn cpu_percent() -> Option<f32> {
+        None
+    }
+
+    pub(super) fn gpus(_memory: &MemoryStats) -> Vec<GpuStats> {
+        Vec::new()
+    }
+}
+
+#[cfg(windows)]
+fn disk(path: &Path) -> Option<DiskStats> {
+    let script = "$d = [System.IO.DriveInfo]::new($env:PM_STATS_PATH); \
+        Write-Output \"$($d.TotalSize) $($d.AvailableFreeSpace)\"";
+    let out = crate::process::command("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", script])
+        .env("PM_STATS_PATH", path)
+        .output()
+        .ok()?;
+    let text = String::from_utf8_lossy(&out.stdout);
+    let mut fields = text.split_whitespace();
+    Some(DiskStats {
+        path: path.to_path_buf(),
+        total_bytes: fields.next()?.parse().ok()?,
+        available_bytes: fields.next()?.parse().ok()?,
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+fn disk(_path: &Path) -> Option<DiskStats> {
+    None
+}
+
+/// Reads everything once. Blocking: it may run a few external tools.
+pub(crate) fn sample(app: &AppHandle) -> Result<SystemStats> {
+    let memory = platform::memory()
+        .ok_or_else(|| Error::Unsupported("reading memory use on this system".into()))?;
+    let mut gpus = nvidia_gpus();
+    gpus.extend(platform::gpus(&memory));
+    let disk = profile::data_dir(app)
+        .ok()
+        .and_then(|dir| disk(&dir).or_else(|| dir.parent().and_then(disk)));
+    Ok(SystemStats {
+        memory,
+        gpus,
+        cpu_percent: platform::cpu_percent(),
+        cpu_count: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        disk,
+        sampled_at: now_ms(),
+    })
+}
+
+/// Warnings for whatever went over the warning level since the last
+/// sample. `warned` holds the resources already over it.
+fn pressure(
+    stats: &SystemStats,
+    warn_percent: f32,
+    warned: &mut Vec<String>,
+) -> Vec<MemoryPressure> {
+    let mut readings = vec![(
+        "memory".to_string(),
+        stats.memory.used_percent,
+        stats.memory.available_bytes,
+    )];
+    readings.extend(stats.gpus.iter().filter(|gpu| !gpu.unified).map(|gpu| {
+        (
+            gpu.name.clone(),
+            gpu.used_percent,
+            gpu.total_bytes.saturating_sub(gpu.used_bytes),
+        )
+    }));
+    let mut pressures = Vec::new();
+    for (resource, used_percent, available_bytes) in readings {
+        let was_warned = warned.contains(&resource);
+        if used_percent >= warn_percent && !was_warned {
+            warned.push(resource.clone());
+            pressures.push(MemoryPressure {
+                resource,
+                used_percent,
+                available_bytes,
+            });
+        } else if used_percent < warn_percent - PRESSURE_HYSTERESIS && was_warned {
+            warned.retain(|r| r != &resource);
+        }
+    }
+    pressures
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<SystemMonitorConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(SystemMonitor {
+        config: Mutex::new(config),
+    });
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut warned = Vec::new();
+        loop {
+            let config = app.state::<SystemMonitor>().config.lock().unwrap().clone();
+            tokio::time::sleep(Duration::from_secs(
+                config.interval_secs.max(MIN_INTERVAL_SECS),
+            ))
+            .await;
+            if !config.enabled {
+                warned.clear();
+                continue;
+            }
+            let handle = app.clone();
+            let Ok(Ok(stats)) = tauri::async_runtime::spawn_blocking(move || sample(&handle)).await
+            else {
+                continue;
+            };
+            for warning in pressure(&stats, config.warn_percent, &mut warned) {
+                let _ = app.emit("memory-pressure", warning);
+            }
+            let _ = app.emit("system-stats", stats);
+        }
+    });
+}
+
+/// Memory, GPU memory, CPU load and disk space right now.
+#[tauri::command]
+pub async fn system_stats(app: AppHandle) -> Result<SystemStats> {
+    tauri::async_runtime::spawn_blocking(move || sample(&app)).await?
+}
+
+#[tauri::command]
+pub fn get_system_monitor_config(monitor: State<'_, SystemMonitor>) -> SystemMonitorConfig {
+    monitor.config.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn set_system_monitor_config(
+    app: AppHandle,
+    monitor: State<'_, SystemMonitor>,
+    config: SystemMonitorConfig,
+) -> Result<SystemMonitorConfig> {
+    if !(1.0..=100.0).contains(&config.warn_percent) {
+        return Err(Error::InvalidSetting(
+            "the warning level must be between 1 and 100 percent".into(),
+        ));
+    }
+    config::write(&app, CONFIG_FILE, &config)?;
+    *monitor.config.lock().unwrap() = config.clone();
+    Ok(config)
+}
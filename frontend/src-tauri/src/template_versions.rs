@@ -0,0 +1,231 @@
+//! Version history of prompt templates. Every save that changes a template
+//! adds a numbered revision holding all of it, so an earlier wording can be
+//! compared against the current one and brought back. Rolling back is a
+//! save like any other: it adds a revision rather than dropping the later
+//! ones.
+
+use std::collections::BTreeMap;
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use tauri::State;
+
+use crate::diff::{self, DiffSpan, Granularity};
+use crate::error::{Error, Result};
+use crate::storage::{now_ms, Database};
+use crate::templates::{Template, TemplateInput};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TemplateVersion {
+    pub version: u32,
+    pub name: String,
+    pub description: Option<String>,
+    pub body: String,
+    pub system_prompt: Option<String>,
+    pub model_system_prompts: BTreeMap<String, String>,
+    pub tags: Vec<String>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldDiff {
+    pub spans: Vec<DiffSpan>,
+    /// Share of tokens the versions have in common, from 0 to 1.
+    pub similarity: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateVersionDiff {
+    pub from: u32,
+    pub to: u32,
+    pub body: FieldDiff,
+    pub system_prompt: FieldDiff,
+    /// Names of the other fields that differ, such as `name` or `tags`.
+    pub changed: Vec<&'static str>,
+}
+
+impl TemplateVersion {
+    const COLUMNS: &'static str = "version, name, description, body, system_prompt,
+         model_system_prompts, tags, created_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            version: row.get(0)?,
+            name: row.get(1)?,
+            description: row.get(2)?,
+            body: row.get(3)?,
+            system_prompt: row.get(4)?,
+            model_system_prompts: serde_json::from_str(&row.get::<_, String>(5)?)
+                .unwrap_or_default(),
+            tags: serde_json::from_str(&row.get::<_, String>(6)?).unwrap_or_default(),
+            created_at: row.get(7)?,
+        })
+    }
+
+    fn same_content(&self, input: &TemplateInput) -> bool {
+        self.name == input.name
+            && self.description == input.description
+            && self.body == input.body
+            && self.system_prompt == input.system_prompt
+            && self.model_system_prompts == input.model_system_prompts
+            && self.tags == input.tags
+    }
+}
+
+fn latest(conn: &Connection, template_id: &str) -> Result<Option<TemplateVersion>> {
+    Ok(conn
+        .query_row(
+            &format!(
+                "SELECT {} FROM template_revisions WHERE template_id = ?1
+                 ORDER BY version DESC LIMIT 1",
+                TemplateVersion::COLUMNS
+            ),
+            [template_id],
+            TemplateVersion::from_row,
+        )
+        .optional()?)
+}
+
+/// Adds a revision for a template just saved as `input`, unless it is what
+/// the last revision already holds. `input` is expected normalized.
+pub(crate) fn record(conn: &Connection, template_id: &str, input: &TemplateInput) -> Result<()> {
+    let last = latest(conn, template_id)?;
+    if last.as_ref().is_some_and(|last| last.same_content(input)) {
+        return Ok(());
+    }
+    let version = last.map_or(1, |last| last.version + 1);
+    conn.execute(
+        "INSERT INTO template_revisions
+             (template_id, version, name, description, body, system_prompt,
+              model_system_prompts, tags, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            template_id,
+            version,
+            input.name,
+            input.description,
+            input.body,
+            input.system_prompt,
+            serde_json::to_string(&input.model_system_prompts)?,
+            serde_json::to_string(&input.tags)?,
+            now_ms()
+        ],
+    )?;
+    Ok(())
+}
+
+fn field_diff(left: Option<&str>, right: Option<&str>, granularity: Granularity) -> FieldDiff {
+    let (spans, similarity) = diff::diff_pair(
+        left.unwrap_or_default(),
+        right.unwrap_or_default(),
+        granularity,
+    );
+    FieldDiff { spans, similarity }
+}
+
+impl Database {
+    /// Revisions of a template, newest first.
+    pub fn template_history(&self, template_id: &str) -> Result<Vec<TemplateVersion>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM template_revisions WHERE template_id = ?1 ORDER BY version DESC",
+            TemplateVersion::COLUMNS
+        ))?;
+        let versions = stmt
+            .query_map([template_id], TemplateVersion::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        if versions.is_empty() {
+            return Err(Error::NotFound(format!("template {template_id}")));
+        }
+        Ok(versions)
+    }
+
+    pub fn template_version(&self, template_id: &str, version: u32) -> Result<TemplateVersion> {
+        self.conn()?
+            .query_row(
+                &format!(
+                    "SELECT {} FROM template_revisions WHERE template_id = ?1 AND version = ?2",
+                    TemplateVersion::COLUMNS
+                ),
+                params![template_id, version],
+                TemplateVersion::from_row,
+            )
+            .optional()?
+            .ok_or_else(|| Error::NotFound(format!("version {version} of template {template_id}")))
+    }
+
+    /// Saves the template as it was at `version`, as a new revision.
+    pub fn rollback_template(&self, template_id: &str, version: u32) -> Result<Template> {
+        let old = self.template_version(template_id, version)?;
+        self.update_template(
+            template_id,
+            TemplateInput {
+                name: old.name,
+                description: old.description,
+                body: old.body,
+                system_prompt: old.system_prompt,
+                model_system_prompts: old.model_system_prompts,
+                tags: old.tags,
+            },
+        )
+    }
+}
+
+/// Every saved state of a template, newest first.
+#[tauri::command]
+pub async fn get_prompt_history(
+    db: State<'_, Database>,
+    id: String,
+) -> Result<Vec<TemplateVersion>> {
+    db.template_history(&id)
+}
+
+/// What changed from version `from` to version `to`: a diff of the prompt
+/// and of the system prompt, and which other fields differ.
+#[tauri::command]
+pub async fn diff_prompt_versions(
+    db: State<'_, Database>,
+    id: String,
+    from: u32,
+    to: u32,
+    granularity: Option<Granularity>,
+) -> Result<TemplateVersionDiff> {
+    let granularity = granularity.unwrap_or_default();
+    let left = db.template_version(&id, from)?;
+    let right = db.template_version(&id, to)?;
+    let mut changed = Vec::new();
+    if left.name != right.name {
+        changed.push("name");
+    }
+    if left.description != right.description {
+        changed.push("description");
+    }
+    if left.model_system_prompts != right.model_system_prompts {
+        changed.push("model_system_prompts");
+    }
+    if left.tags != right.tags {
+        changed.push("tags");
+    }
+    Ok(TemplateVersionDiff {
+        from,
+        to,
+        body: field_diff(Some(&left.body), Some(&right.body), granularity),
+        system_prompt: field_diff(
+            left.system_prompt.as_deref(),
+            right.system_prompt.as_deref(),
+            granularity,
+        ),
+        changed,
+    })
+}
+
+/// Brings back the template as it was at `version`. The versions after it
+/// stay in the history.
+#[tauri::command]
+pub async fn rollback_prompt(
+    db: State<'_, Database>,
+    id: String,
+    version: u32,
+) -> Result<Template> {
+    db.rollback_template(&id, version)
+}
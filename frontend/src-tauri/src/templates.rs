@@ -0,0 +1,428 @@
+//! Prompt templates: reusable prompts with `{{variable}}` placeholders, an
+//! optional system prompt (overridable per model) and tags for organizing
+//! them. A placeholder may carry a default, as in `{{tone|friendly}}`; text
+//! in braces that isn't a valid variable name is left as it is, so code in
+//! a template survives. Every change is kept as a revision; see
+//! [`crate::template_versions`].
+
+use std::collections::{BTreeMap, HashMap};
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::error::{Error, Result};
+use crate::storage::{new_id, now_ms, Database};
+use crate::template_versions;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TemplateVariable {
+    pub name: String,
+    pub default: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Template {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub body: String,
+    pub system_prompt: Option<String>,
+    /// System prompts used instead of `system_prompt` for these models.
+    pub model_system_prompts: BTreeMap<String, String>,
+    pub tags: Vec<String>,
+    /// Placeholders across the body and system prompts, in order of first use.
+    pub variables: Vec<TemplateVariable>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TemplateInput {
+    pub name: String,
+    pub description: Option<String>,
+    pub body: String,
+    pub system_prompt: Option<String>,
+    #[serde(default)]
+    pub model_system_prompts: BTreeMap<String, String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RenderedTemplate {
+    pub prompt: String,
+    pub system_prompt: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub templates: u32,
+}
+
+enum Piece<'a> {
+    Text(&'a str),
+    Variable {
+        name: &'a str,
+        default: Option<&'a str>,
+    },
+}
+
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn parse(text: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    let mut literal = 0;
+    while let Some(open) = rest[literal..].find("{{").map(|i| literal + i) {
+        let Some(close) = rest[open + 2..].find("}}").map(|i| open + 2 + i) else {
+            break;
+        };
+        let inner = &rest[open + 2..close];
+        let (name, default) = match inner.split_once('|') {
+            Some((name, default)) => (name.trim(), Some(default)),
+            None => (inner.trim(), None),
+        };
+        if !is_variable_name(name) {
+            literal = open + 2;
+            continue;
+        }
+        if open > 0 {
+            pieces.push(Piece::Text(&rest[..open]));
+        }
+        pieces.push(Piece::Variable { name, default });
+        rest = &rest[close + 2..];
+        literal = 0;
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Text(rest));
+    }
+    pieces
+}
+
+fn collect_variables(text: &str, variables: &mut Vec<TemplateVariable>) {
+    for piece in parse(text) {
+        if let Piece::Variable { name, default } = piece {
+            match variables.iter_mut().find(|v| v.name == name) {
+                Some(existing) => {
+                    if existing.default.is_none() {
+                        existing.default = default.map(str::to_string);
+                    }
+                }
+                None => variables.push(TemplateVariable {
+                    name: name.to_string(),
+                    default: default.map(str::to_string),
+                }),
+            }
+        }
+    }
+}
+
+/// Substitutes `values` into `text`, collecting the names of variables that
+/// have neither a value nor a default into `missing`.
+pub(crate) fn substitute(
+    text: &str,
+    values: &HashMap<String, String>,
+    missing: &mut Vec<String>,
+) -> String {
+    let mut out = String::with_capacity(text.len());
+    for piece in parse(text) {
+        match piece {
+            Piece::Text(text) => out.push_str(text),
+            Piece::Variable { name, default } => match values.get(name).map(String::as_str) {
+                Some(value) => out.push_str(value),
+                None => match default {
+                    Some(default) => out.push_str(default),
+                    None => {
+                        if !missing.iter().any(|m| m == name) {
+                            missing.push(name.to_string());
+                        }
+                    }
+                },
+            },
+        }
+    }
+    out
+}
+
+impl Template {
+    const COLUMNS: &'static str =
+        "id, name, description, body, system_prompt, created_at, updated_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            description: row.get(2)?,
+            body: row.get(3)?,
+            system_prompt: row.get(4)?,
+            model_system_prompts: BTreeMap::new(),
+            tags: Vec::new(),
+            variables: Vec::new(),
+            created_at: row.get(5)?,
+            updated_at: row.get(6)?,
+        })
+    }
+
+    /// Loads tags and system prompt overrides, then works out the variables.
+    fn complete(mut self, conn: &Connection) -> Result<Self> {
+        let mut stmt = conn
+            .prepare_cached("SELECT tag FROM template_tags WHERE template_id = ?1 ORDER BY tag")?;
+        self.tags = stmt
+            .query_map([&self.id], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut stmt = conn.prepare_cached(
+            "SELECT model, prompt FROM template_system_prompts WHERE template_id = ?1",
+        )?;
+        self.model_system_prompts = stmt
+            .query_map([&self.id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut variables = Vec::new();
+        collect_variables(&self.body, &mut variables);
+        for prompt in self
+            .system_prompt
+            .iter()
+            .chain(self.model_system_prompts.values())
+        {
+            collect_variables(prompt, &mut variables);
+        }
+        self.variables = variables;
+        Ok(self)
+    }
+
+    /// Fills in the body and the system prompt for `model`. Every variable
+    /// without a default needs a value.
+    pub fn render(
+        &self,
+        values: &HashMap<String, String>,
+        model: Option<&str>,
+    ) -> Result<RenderedTemplate> {
+        let mut missing = Vec::new();
+        let prompt = substitute(&self.body, values, &mut missing);
+        let system_prompt = model
+            .and_then(|model| self.model_system_prompts.get(model))
+            .or(self.system_prompt.as_ref())
+            .map(|system| substitute(system, values, &mut missing));
+        if !missing.is_empty() {
+            return Err(Error::InvalidTemplate(format!(
+                "no value for {}",
+                missing.join(", ")
+            )));
+        }
+        Ok(RenderedTemplate {
+            prompt,
+            system_prompt,
+        })
+    }
+}
+
+impl TemplateInput {
+    /// Trims and validates the input, deduplicating tags.
+    fn normalize(mut self) -> Result<Self> {
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() {
+            return Err(Error::InvalidTemplate("the name is empty".into()));
+        }
+        if self.body.trim().is_empty() {
+            return Err(Error::InvalidTemplate("the prompt is empty".into()));
+        }
+        self.description = self.description.filter(|d| !d.trim().is_empty());
+        self.system_prompt = self.system_prompt.filter(|s| !s.trim().is_empty());
+        self.model_system_prompts = std::mem::take(&mut self.model_system_prompts)
+            .into_iter()
+            .map(|(model, prompt)| (model.trim().to_string(), prompt))
+            .filter(|(model, prompt)| !model.is_empty() && !prompt.trim().is_empty())
+            .collect();
+        let mut tags: Vec<String> = self
+            .tags
+            .iter()
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect();
+        tags.sort();
+        tags.dedup();
+        self.tags = tags;
+        Ok(self)
+    }
+}
+
+fn write_details(conn: &Connection, id: &str, input: &TemplateInput) -> Result<()> {
+    conn.execute("DELETE FROM template_tags WHERE template_id = ?1", [id])?;
+    conn.execute(
+        "DELETE FROM template_system_prompts WHERE template_id = ?1",
+        [id],
+    )?;
+    for tag in &input.tags {
+        conn.execute(
+            "INSERT INTO template_tags (template_id, tag) VALUES (?1, ?2)",
+            params![id, tag],
+        )?;
+    }
+    for (model, prompt) in &input.model_system_prompts {
+        conn.execute(
+            "INSERT INTO template_system_prompts (template_id, model, prompt) VALUES (?1, ?2, ?3)",
+            params![id, model, prompt],
+        )?;
+    }
+    Ok(())
+}
+
+impl Database {
+    pub fn create_template(&self, input: TemplateInput) -> Result<Template> {
+        let input = input.normalize()?;
+        let id = new_id();
+        let now = now_ms();
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO templates (id, name, description, body, system_prompt, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)",
+            params![
+                id,
+                input.name,
+                input.description,
+                input.body,
+                input.system_prompt,
+                now
+            ],
+        )?;
+        write_details(&tx, &id, &input)?;
+        template_versions::record(&tx, &id, &input)?;
+        tx.commit()?;
+        drop(conn);
+        self.get_template(&id)
+    }
+
+    pub fn update_template(&self, id: &str, input: TemplateInput) -> Result<Template> {
+        let input = input.normalize()?;
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let updated = tx.execute(
+            "UPDATE templates
+             SET name = ?2, description = ?3, body = ?4, system_prompt = ?5, updated_at = ?6
+             WHERE id = ?1",
+            params![
+                id,
+                input.name,
+                input.description,
+                input.body,
+                input.system_prompt,
+                now_ms()
+            ],
+        )?;
+        if updated == 0 {
+            return Err(Error::NotFound(format!("template {id}")));
+        }
+        write_details(&tx, id, &input)?;
+        template_versions::record(&tx, id, &input)?;
+        tx.commit()?;
+        drop(conn);
+        self.get_template(id)
+    }
+
+    pub fn get_template(&self, id: &str) -> Result<Template> {
+        let conn = self.conn()?;
+        conn.query_row(
+            &format!("SELECT {} FROM templates WHERE id = ?1", Template::COLUMNS),
+            [id],
+            Template::from_row,
+        )
+        .optional()?
+        .ok_or_else(|| Error::NotFound(format!("template {id}")))?
+        .complete(&conn)
+    }
+
+    /// Templates by name, optionally only those tagged `tag`.
+    pub fn list_templates(&self, tag: Option<&str>) -> Result<Vec<Template>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM templates
+             WHERE ?1 IS NULL
+                OR id IN (SELECT template_id FROM template_tags WHERE tag = ?1)
+             ORDER BY name COLLATE NOCASE",
+            Template::COLUMNS
+        ))?;
+        let templates = stmt
+            .query_map([tag], Template::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        templates
+            .into_iter()
+            .map(|template| template.complete(&conn))
+            .collect()
+    }
+
+    pub fn delete_template(&self, id: &str) -> Result<()> {
+        let deleted = self
+            .conn()?
+            .execute("DELETE FROM templates WHERE id = ?1", [id])?;
+        if deleted == 0 {
+            return Err(Error::NotFound(format!("template {id}")));
+        }
+        Ok(())
+    }
+
+    pub fn template_tags(&self) -> Result<Vec<TagCount>> {
+        let conn = self.conn()?;
+        let mut stmt =
+            conn.prepare("SELECT tag, COUNT(*) FROM template_tags GROUP BY tag ORDER BY tag")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(TagCount {
+                tag: row.get(0)?,
+                templates: row.get(1)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+#[tauri::command]
+pub async fn create_template(db: State<'_, Database>, template: TemplateInput) -> Result<Template> {
+    db.create_template(template)
+}
+
+/// Replaces a template's fields, tags and system prompt overrides.
+#[tauri::command]
+pub async fn update_template(
+    db: State<'_, Database>,
+    id: String,
+    template: TemplateInput,
+) -> Result<Template> {
+    db.update_template(&id, template)
+}
+
+#[tauri::command]
+pub async fn get_template(db: State<'_, Database>, id: String) -> Result<Template> {
+    db.get_template(&id)
+}
+
+#[tauri::command]
+pub async fn list_templates(db: State<'_, Database>, tag: Option<String>) -> Result<Vec<Template>> {
+    db.list_templates(tag.as_deref())
+}
+
+#[tauri::command]
+pub async fn delete_template(db: State<'_, Database>, id: String) -> Result<()> {
+    db.delete_template(&id)
+}
+
+#[tauri::command]
+pub async fn list_template_tags(db: State<'_, Database>) -> Result<Vec<TagCount>> {
+    db.template_tags()
+}
+
+/// Fills in a template for `model`, failing with the names of any variables
+/// left without a value.
+#[tauri::command]
+pub async fn render_template(
+    db: State<'_, Database>,
+    id: String,
+    values: Option<HashMap<String, String>>,
+    model: Option<String>,
+) -> Result<RenderedTemplate> {
+    db.get_template(&id)?
+        .render(&values.unwrap_or_default(), model.as_deref())
+}
@@ -0,0 +1,300 @@
+//! Small previews of image and PDF attachments, so a conversation full of
+//! screenshots doesn't load every one at full size. A preview is made the
+//! first time it's asked for, kept in the data directory next to the
+//! attachments and served from there on by the `thumb://` protocol, which
+//! the webview can point an `<img>` at without the bytes crossing the
+//! command bridge.
+//!
+//! Requested sizes are rounded up to a few fixed ones so the cache doesn't
+//! fill up with near-duplicates.
+
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use image::{DynamicImage, GenericImageView, GrayImage, ImageFormat, RgbImage};
+use lopdf::Document;
+use serde::Serialize;
+use tauri::http::{header, Request, Response, StatusCode};
+use tauri::{AppHandle, UriSchemeContext, UriSchemeResponder, Wry};
+
+use crate::attachments;
+use crate::error::{Error, Result};
+use crate::profile;
+use crate::storage::new_id;
+
+const DIR: &str = "thumbnails";
+pub const SCHEME: &str = "thumb";
+/// Edge lengths previews are made at; a request gets the first that fits.
+const SIZES: [u32; 5] = [64, 128, 256, 512, 1024];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Thumbnail {
+    pub attachment_id: String,
+    /// The edge length it was made for; the longer side is at most this.
+    pub size: u32,
+    pub width: u32,
+    pub height: u32,
+    pub mime: &'static str,
+    pub path: PathBuf,
+    /// Where the webview can load it from.
+    pub url: String,
+}
+
+pub(crate) fn dir(app: &AppHandle) -> Result<PathBuf> {
+    Ok(profile::data_dir(app)?.join(DIR))
+}
+
+fn bucket(size: u32) -> u32 {
+    SIZES
+        .into_iter()
+        .find(|&s| s >= size)
+        .unwrap_or(SIZES[SIZES.len() - 1])
+}
+
+fn url(attachment_id: &str, size: u32) -> String {
+    // Windows and Android webviews only take custom schemes dressed as http.
+    if cfg!(any(windows, target_os = "android")) {
+        format!("http://{SCHEME}.localhost/{attachment_id}?size={size}")
+    } else {
+        format!("{SCHEME}://localhost/{attachment_id}?size={size}")
+    }
+}
+
+fn mime(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("jpg") => "image/jpeg",
+        _ => "image/png",
+    }
+}
+
+/// Renders the first page of a PDF with whatever the platform offers,
+/// writing to the empty directory `out`.
+#[cfg(target_os = "macos")]
+fn render_pdf(path: &Path, size: u32, out: &Path) -> Option<DynamicImage> {
+    let status = crate::process::command("qlmanage")
+        .arg("-t")
+        .arg("-s")
+        .arg(size.to_string())
+        .arg("-o")
+        .arg(out)
+        .arg(path)
+        .output()
+        .ok()?;
+    if !status.status.success() {
+        return None;
+    }
+    let name = format!("{}.png", path.file_name()?.to_string_lossy());
+    image::open(out.join(name)).ok()
+}
+
+#[cfg(not(target_os = "macos"))]
+fn render_pdf(path: &Path, size: u32, out: &Path) -> Option<DynamicImage> {
+    // pdftoppm comes with poppler, which most Linux desktops have.
+    let prefix = out.join("page");
+    let status = crate::process::command("pdftoppm")
+        .args(["-png", "-f", "1", "-l", "1", "-singlefile", "-scale-to"])
+        .arg(size.to_string())
+        .arg(path)
+        .arg(&prefix)
+        .output()
+        .ok()?;
+    if !status.status.success() {
+        return None;
+    }
+    image::open(prefix.with_extension("png")).ok()
+}
+
+/// The largest picture on the first page of a PDF, for when it can't be
+/// rendered. Scanned documents are usually one picture per page, so this is
+/// often as good.
+fn embedded_pdf_image(path: &Path) -> Option<DynamicImage> {
+    let document = Document::load(path).ok()?;
+    let (_, &page) = document.get_pages().iter().next()?;
+    let mut images = document.get_page_images(page).ok()?;
+    images.sort_by_key(|image| std::cmp::Reverse(image.width * image.height));
+    images.into_iter().find_map(|pdf| {
+        let filters = pdf.filters.clone().unwrap_or_default();
+        if filters.iter().any(|f| f == "DCTDecode") {
+            return image::load_from_memory(pdf.content).ok();
+        }
+        if pdf.bits_per_component != Some(8) {
+            return None;
+        }
+        let (width, height) = (
+            u32::try_from(pdf.width).ok()?,
+            u32::try_from(pdf.height).ok()?,
+        );
+        let pixels = document
+            .get_object(pdf.id)
+            .and_then(|object| object.as_stream())
+            .ok()?
+            .decompressed_content()
+            .ok()?;
+        match pdf.color_space.as_deref() {
+            Some("DeviceRGB") => RgbImage::from_raw(width, height, pixels).map(Into::into),
+            Some("DeviceGray") => GrayImage::from_raw(width, height, pixels).map(Into::into),
+            _ => None,
+        }
+    })
+}
+
+fn source_image(path: &Path, size: u32, scratch: &Path) -> Result<DynamicImage> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    if attachments::image_mime(extension).is_some() {
+        return image::open(path).map_err(|err| Error::InvalidAttachment(err.to_string()));
+    }
+    if extension == "pdf" {
+        std::fs::create_dir_all(scratch)?;
+        let rendered = render_pdf(path, size, scratch);
+        let _ = std::fs::remove_dir_all(scratch);
+        return rendered
+            .or_else(|| embedded_pdf_image(path))
+            .ok_or_else(|| Error::Unsupported("a preview of this PDF".into()));
+    }
+    Err(Error::Unsupported(format!(
+        "previews of .{extension} files"
+    )))
+}
+
+/// Shrinks to `size` and encodes: JPEG when there's no transparency to
+/// keep, since it is far smaller for photos, PNG otherwise.
+fn encode(image: &DynamicImage, size: u32) -> Result<(Vec<u8>, &'static str)> {
+    let image = if image.width().max(image.height()) > size {
+        image.thumbnail(size, size)
+    } else {
+        image.clone()
+    };
+    let opaque = !image.color().has_alpha() || image.pixels().all(|(_, _, p)| p[3] == u8::MAX);
+    if opaque {
+        let mut jpeg = Cursor::new(Vec::new());
+        // Without JPEG support this fails and PNG is what gets written.
+        if DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_to(&mut jpeg, ImageFormat::Jpeg)
+            .is_ok()
+        {
+            return Ok((jpeg.into_inner(), "jpg"));
+        }
+    }
+    let mut png = Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, ImageFormat::Png)
+        .map_err(|err| Error::InvalidAttachment(err.to_string()))?;
+    Ok((png.into_inner(), "png"))
+}
+
+fn cached(dir: &Path, attachment_id: &str, size: u32) -> Option<PathBuf> {
+    ["jpg", "png"]
+        .into_iter()
+        .map(|ext| dir.join(format!("{attachment_id}-{size}.{ext}")))
+        .find(|path| path.is_file())
+}
+
+/// The preview of `attachment_id` at `size`, made now if it isn't cached.
+fn generate(app: &AppHandle, attachment_id: &str, size: u32) -> Result<Thumbnail> {
+    let source = attachments::locate(app, attachment_id)?;
+    let attachment_id = source
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(attachment_id)
+        .to_string();
+    let size = bucket(size);
+    let dir = dir(app)?;
+    let path = match cached(&dir, &attachment_id, size) {
+        Some(path) => path,
+        None => {
+            let scratch = dir.join(format!(".render-{}", new_id()));
+            let (bytes, extension) = encode(&source_image(&source, size, &scratch)?, size)?;
+            std::fs::create_dir_all(&dir)?;
+            let path = dir.join(format!("{attachment_id}-{size}.{extension}"));
+            let tmp = path.with_extension(format!("{extension}.tmp"));
+            std::fs::write(&tmp, bytes)?;
+            std::fs::rename(&tmp, &path)?;
+            path
+        }
+    };
+    let (width, height) =
+        image::image_dimensions(&path).map_err(|err| Error::InvalidAttachment(err.to_string()))?;
+    Ok(Thumbnail {
+        url: url(&attachment_id, size),
+        attachment_id,
+        size,
+        width,
+        height,
+        mime: mime(&path),
+        path,
+    })
+}
+
+/// A preview of an image or PDF attachment, at most `size` pixels on its
+/// longer side.
+#[tauri::command]
+pub async fn get_thumbnail(app: AppHandle, attachment_id: String, size: u32) -> Result<Thumbnail> {
+    tauri::async_runtime::spawn_blocking(move || generate(&app, &attachment_id, size)).await?
+}
+
+fn respond(status: StatusCode, body: Vec<u8>, mime: &str) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, mime)
+        .body(body)
+        .unwrap_or_default()
+}
+
+async fn serve(app: AppHandle, request: Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let attachment_id = request.uri().path().trim_matches('/').to_string();
+    let size = request
+        .uri()
+        .query()
+        .into_iter()
+        .flat_map(|q| q.split('&'))
+        .find_map(|pair| pair.strip_prefix("size="))
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(SIZES[2]);
+    let made = tauri::async_runtime::spawn_blocking(move || {
+        let thumbnail = generate(&app, &attachment_id, size)?;
+        let bytes = std::fs::read(&thumbnail.path)?;
+        Ok::<_, Error>((bytes, thumbnail.mime))
+    })
+    .await;
+    match made {
+        Ok(Ok((bytes, mime))) => {
+            let mut response = respond(StatusCode::OK, bytes, mime);
+            // Attachments are named by their contents, so a preview never
+            // goes stale.
+            if let Ok(value) = "public, max-age=31536000, immutable".parse() {
+                response.headers_mut().insert(header::CACHE_CONTROL, value);
+            }
+            response
+        }
+        Ok(Err(Error::NotFound(what))) => respond(StatusCode::NOT_FOUND, what.into(), "text/plain"),
+        Ok(Err(err @ Error::Unsupported(_))) => respond(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            err.to_string().into(),
+            "text/plain",
+        ),
+        Ok(Err(err)) => respond(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            err.to_string().into(),
+            "text/plain",
+        ),
+        Err(err) => respond(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            err.to_string().into(),
+            "text/plain",
+        ),
+    }
+}
+
+/// Handler for the `thumb://` protocol: `thumb://localhost/<attachment
+/// id>?size=<pixels>`, answered with the preview from `get_thumbnail`.
+pub fn protocol(
+    ctx: UriSchemeContext<'_, Wry>,
+    request: Request<Vec<u8>>,
+    responder: UriSchemeResponder,
+) {
+    let app = ctx.app_handle().clone();
+    tauri::async_runtime::spawn(async move { responder.respond(serve(app, request).await) });
+}
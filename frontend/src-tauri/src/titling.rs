@@ -0,0 +1,391 @@
+//! Titles and rolling summaries written in the background by a cheap model.
+//! A conversation still under the default title is named after its first
+//! exchanges, and every few messages after that the summary of its current
+//! branch is brought up to date. The summary can then stand in for the
+//! older messages when a long chat goes back to a model.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use reqwest::Client;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::llm::{ChatMessage, ChatRequest, Role};
+use crate::os_search;
+use crate::providers::priority::{self, Priority};
+use crate::providers::{Provider, Providers};
+use crate::storage::conversations::{Conversation, Message, DEFAULT_TITLE};
+use crate::storage::Database;
+use crate::usage;
+use crate::windows;
+
+const CONFIG_FILE: &str = "titling.json";
+/// Tried in order when no model is configured; an empty model means the
+/// provider's default.
+const CHEAP_MODELS: &[(&str, &str)] = &[
+    ("openai", "gpt-4o-mini"),
+    ("google", "gemini-2.0-flash"),
+    ("anthropic", "claude-3-5-haiku-latest"),
+    ("mistral", "mistral-small-latest"),
+    ("ollama", ""),
+];
+const TITLE_MAX_LEN: usize = 80;
+/// Longest stretch of a single message quoted to the model, in characters.
+const EXCERPT_LEN: usize = 2000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TitlingConfig {
+    pub enabled: bool,
+    /// Falls back to the first configured of a few cheap models.
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    /// Messages on the branch before a title is written.
+    pub title_after: usize,
+    /// New messages since the last summary before it is rewritten.
+    pub summarize_every: usize,
+}
+
+impl Default for TitlingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            provider: None,
+            model: None,
+            title_after: 4,
+            summarize_every: 10,
+        }
+    }
+}
+
+/// Managed as Tauri state.
+pub struct Titling {
+    config: Mutex<TitlingConfig>,
+    /// Conversations with a background update in flight.
+    busy: Mutex<HashSet<String>>,
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<TitlingConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(Titling {
+        config: Mutex::new(config),
+        busy: Mutex::default(),
+    });
+}
+
+impl Database {
+    /// The summary and the message it runs up to.
+    fn summary_of(&self, conversation_id: &str) -> Result<(Option<String>, Option<String>)> {
+        Ok(self.conn()?.query_row(
+            "SELECT summary, summary_through FROM conversations WHERE id = ?1",
+            [conversation_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?)
+    }
+
+    fn set_title(&self, conversation_id: &str, title: &str) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE conversations SET title = ?2 WHERE id = ?1",
+            params![conversation_id, title],
+        )?;
+        Ok(())
+    }
+
+    fn set_summary(&self, conversation_id: &str, summary: &str, through: &str) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE conversations SET summary = ?2, summary_through = ?3 WHERE id = ?1",
+            params![conversation_id, summary, through],
+        )?;
+        Ok(())
+    }
+}
+
+/// `provider` and `model` if given, or the first configured cheap model,
+/// for background work such as titles and translations.
+pub(crate) fn pick_model(
+    app: &AppHandle,
+    provider: Option<&str>,
+    model: Option<&str>,
+) -> Result<(Arc<dyn Provider>, String)> {
+    let providers = app.state::<Providers>();
+    if let Some(id) = provider {
+        let provider = providers.get(id)?;
+        let model = model
+            .map(str::to_string)
+            .unwrap_or_else(|| provider.default_model().to_string());
+        return Ok((provider, model));
+    }
+    CHEAP_MODELS
+        .iter()
+        .find_map(|(id, model)| {
+            let provider = providers.get(id).ok().filter(|p| p.configured())?;
+            let model = match *model {
+                "" => provider.default_model().to_string(),
+                model => model.to_string(),
+            };
+            Some((provider, model))
+        })
+        .ok_or_else(|| Error::Provider("no configured provider to write titles with".into()))
+}
+
+fn excerpt(content: &str) -> String {
+    let content = content.trim();
+    match content.char_indices().nth(EXCERPT_LEN) {
+        Some((end, _)) => format!("{} […]", &content[..end]),
+        None => content.to_string(),
+    }
+}
+
+fn transcript(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .filter(|m| m.role != Role::System)
+        .map(|m| {
+            let speaker = match m.role {
+                Role::User => "User",
+                _ => "Assistant",
+            };
+            format!("{speaker}: {}", excerpt(&m.content))
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+async fn ask(app: &AppHandle, config: &TitlingConfig, prompt: String) -> Result<String> {
+    let (provider, model) = pick_model(app, config.provider.as_deref(), config.model.as_deref())?;
+    let request = ChatRequest {
+        provider: provider.id().to_string(),
+        model,
+        messages: vec![ChatMessage {
+            role: Role::User,
+            content: prompt,
+        }],
+        temperature: Some(0.2),
+        top_p: None,
+        max_tokens: Some(400),
+        use_tools: false,
+        bypass_cache: false,
+        response_schema: None,
+        conversation_id: None,
+        tools: Vec::new(),
+        tool_rounds: Vec::new(),
+    };
+    let client = app.state::<Client>();
+    let completion = provider.stream(&client, &request, &mut |_| {}).await?;
+    usage::record(app, &request, &completion.content, completion.usage);
+    Ok(completion.content.trim().to_string())
+}
+
+/// The first line of a model's reply, without the quotes and trailing
+/// period models like to add.
+fn clean_title(reply: &str) -> String {
+    let line = reply.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+    let line = line
+        .trim()
+        .trim_start_matches("Title:")
+        .trim()
+        .trim_matches(|c| matches!(c, '"' | '\'' | '*' | '#' | '“' | '”'))
+        .trim_end_matches('.')
+        .trim();
+    match line.char_indices().nth(TITLE_MAX_LEN) {
+        Some((end, _)) => line[..end].trim_end().to_string(),
+        None => line.to_string(),
+    }
+}
+
+async fn write_title(app: &AppHandle, config: &TitlingConfig, conversation_id: &str) -> Result<()> {
+    let db = app.state::<Database>();
+    let detail = db.get_conversation(conversation_id)?;
+    let reply = ask(
+        app,
+        config,
+        format!(
+            "Write a title of at most six words for the conversation below. \
+             Reply with the title only.\n\n{}",
+            transcript(&detail.messages)
+        ),
+    )
+    .await?;
+    let title = clean_title(&reply);
+    if !title.is_empty() {
+        db.set_title(conversation_id, &title)?;
+    }
+    Ok(())
+}
+
+/// Messages on the branch after the one the summary runs up to; all of them
+/// if it was written for another branch.
+fn unsummarized<'a>(messages: &'a [Message], through: Option<&str>) -> &'a [Message] {
+    match through.and_then(|id| messages.iter().position(|m| m.id == id)) {
+        Some(index) => &messages[index + 1..],
+        None => messages,
+    }
+}
+
+async fn write_summary(
+    app: &AppHandle,
+    config: &TitlingConfig,
+    conversation_id: &str,
+) -> Result<()> {
+    let db = app.state::<Database>();
+    let detail = db.get_conversation(conversation_id)?;
+    let (summary, through) = db.summary_of(conversation_id)?;
+    let on_branch = through
+        .as_deref()
+        .is_some_and(|id| detail.messages.iter().any(|m| m.id == id));
+    let pending = unsummarized(&detail.messages, through.as_deref());
+    let Some(last) = pending.last() else {
+        return Ok(());
+    };
+    let instructions = "Summarize the conversation for someone who will carry it on. \
+                        Keep facts, decisions, code names and open questions. \
+                        Use at most 200 words and reply with the summary only.";
+    let prompt = match summary.filter(|_| on_branch) {
+        Some(summary) => format!(
+            "{instructions}\n\n## Summary so far\n{summary}\n\n## Newer messages\n{}",
+            transcript(pending)
+        ),
+        None => format!("{instructions}\n\n{}", transcript(pending)),
+    };
+    let summary = ask(app, config, prompt).await?;
+    if !summary.is_empty() {
+        db.set_summary(conversation_id, &summary, &last.id)?;
+    }
+    Ok(())
+}
+
+fn updated(app: &AppHandle, conversation_id: &str) -> Result<Conversation> {
+    let conversation = app
+        .state::<Database>()
+        .get_conversation(conversation_id)?
+        .conversation;
+    let _ = app.emit("conversation-updated", &conversation);
+    windows::conversation_renamed(app, &conversation);
+    os_search::conversation_updated(app, &conversation);
+    Ok(conversation)
+}
+
+/// Writes whatever is due: a title once the branch is long enough, and a
+/// new summary once enough messages have piled up since the last one.
+async fn refresh(app: &AppHandle, conversation_id: &str) -> Result<()> {
+    let config = app.state::<Titling>().config.lock().unwrap().clone();
+    let db = app.state::<Database>();
+    let detail = db.get_conversation(conversation_id)?;
+    let (_, through) = db.summary_of(conversation_id)?;
+    let mut changed = false;
+    if detail.conversation.title == DEFAULT_TITLE && detail.messages.len() >= config.title_after {
+        write_title(app, &config, conversation_id).await?;
+        changed = true;
+    }
+    if unsummarized(&detail.messages, through.as_deref()).len() >= config.summarize_every.max(1) {
+        write_summary(app, &config, conversation_id).await?;
+        changed = true;
+    }
+    if changed {
+        updated(app, conversation_id)?;
+    }
+    Ok(())
+}
+
+/// Called after a message is added; schedules a background refresh unless
+/// one is already running for the conversation.
+pub fn after_append(app: &AppHandle, conversation_id: &str) {
+    let Some(titling) = app.try_state::<Titling>() else {
+        return;
+    };
+    if !titling.config.lock().unwrap().enabled
+        || !titling
+            .busy
+            .lock()
+            .unwrap()
+            .insert(conversation_id.to_string())
+    {
+        return;
+    }
+    let app = app.clone();
+    let conversation_id = conversation_id.to_string();
+    tauri::async_runtime::spawn(async move {
+        if let Err(err) =
+            priority::scope(Priority::Background, refresh(&app, &conversation_id)).await
+        {
+            tracing::warn!("couldn't title or summarize {conversation_id}: {err}");
+        }
+        app.state::<Titling>()
+            .busy
+            .lock()
+            .unwrap()
+            .remove(&conversation_id);
+    });
+}
+
+#[tauri::command]
+pub async fn regenerate_title(app: AppHandle, conversation_id: String) -> Result<Conversation> {
+    let config = app.state::<Titling>().config.lock().unwrap().clone();
+    write_title(&app, &config, &conversation_id).await?;
+    updated(&app, &conversation_id)
+}
+
+/// Brings the summary up to date with the current branch.
+#[tauri::command]
+pub async fn summarize_conversation(
+    app: AppHandle,
+    conversation_id: String,
+) -> Result<Conversation> {
+    let config = app.state::<Titling>().config.lock().unwrap().clone();
+    write_summary(&app, &config, &conversation_id).await?;
+    updated(&app, &conversation_id)
+}
+
+/// The current branch ready to send to a model, with the summary in place
+/// of the messages it covers. Leading system messages are kept.
+#[tauri::command]
+pub fn get_conversation_context(
+    db: State<'_, Database>,
+    conversation_id: String,
+) -> Result<Vec<ChatMessage>> {
+    let detail = db.get_conversation(&conversation_id)?;
+    let (summary, through) = db.summary_of(&conversation_id)?;
+    let to_chat = |m: &Message| ChatMessage {
+        role: m.role,
+        content: m.content.clone(),
+    };
+    let rest = unsummarized(&detail.messages, through.as_deref());
+    let Some(summary) = summary.filter(|_| rest.len() < detail.messages.len()) else {
+        return Ok(detail.messages.iter().map(to_chat).collect());
+    };
+    let mut context: Vec<ChatMessage> = detail
+        .messages
+        .iter()
+        .take_while(|m| m.role == Role::System)
+        .map(to_chat)
+        .collect();
+    context.push(ChatMessage {
+        role: Role::System,
+        content: format!("Summary of the conversation so far:\n{summary}"),
+    });
+    context.extend(rest.iter().map(to_chat));
+    Ok(context)
+}
+
+#[tauri::command]
+pub fn get_titling_config(titling: State<'_, Titling>) -> TitlingConfig {
+    titling.config.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn set_titling_config(
+    app: AppHandle,
+    titling: State<'_, Titling>,
+    config: TitlingConfig,
+) -> Result<TitlingConfig> {
+    config::write(&app, CONFIG_FILE, &config)?;
+    *titling.config.lock().unwrap() = config.clone();
+    Ok(config)
+}
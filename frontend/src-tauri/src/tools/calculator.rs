@@ -0,0 +1,200 @@
+//! Arithmetic for the `calculator` tool, since models are unreliable at it.
+//! Supports `+ - * / % ^`, parentheses, unary minus, the constants `pi`
+//! and `e`, and common one-argument functions.
+
+fn function(name: &str) -> Option<fn(f64) -> f64> {
+    Some(match name {
+        "sqrt" => f64::sqrt,
+        "cbrt" => f64::cbrt,
+        "abs" => f64::abs,
+        "exp" => f64::exp,
+        "ln" => f64::ln,
+        "log" | "log10" => f64::log10,
+        "log2" => f64::log2,
+        "sin" => f64::sin,
+        "cos" => f64::cos,
+        "tan" => f64::tan,
+        "asin" => f64::asin,
+        "acos" => f64::acos,
+        "atan" => f64::atan,
+        "floor" => f64::floor,
+        "ceil" => f64::ceil,
+        "round" => f64::round,
+        _ => return None,
+    })
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_space();
+        self.input[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &str {
+        let start = self.pos;
+        let rest = &self.input[start..];
+        let len = rest.find(|c| !f(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &self.input[start..start + len]
+    }
+
+    /// sum := product (('+' | '-') product)*
+    fn sum(&mut self) -> Result<f64, String> {
+        let mut value = self.product()?;
+        loop {
+            if self.eat('+') {
+                value += self.product()?;
+            } else if self.eat('-') {
+                value -= self.product()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// product := unary (('*' | '/' | '%') unary)*
+    fn product(&mut self) -> Result<f64, String> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat('*') {
+                value *= self.unary()?;
+            } else if self.eat('/') {
+                let divisor = self.unary()?;
+                if divisor == 0.0 {
+                    return Err("division by zero".into());
+                }
+                value /= divisor;
+            } else if self.eat('%') {
+                let divisor = self.unary()?;
+                if divisor == 0.0 {
+                    return Err("division by zero".into());
+                }
+                value %= divisor;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// unary := '-' unary | '+' unary | power
+    fn unary(&mut self) -> Result<f64, String> {
+        if self.eat('-') {
+            return Ok(-self.unary()?);
+        }
+        if self.eat('+') {
+            return self.unary();
+        }
+        self.power()
+    }
+
+    /// power := atom ('^' unary)?, right-associative so 2^3^2 is 2^9.
+    fn power(&mut self) -> Result<f64, String> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            return Ok(base.powf(self.unary()?));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<f64, String> {
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let value = self.sum()?;
+                if !self.eat(')') {
+                    return Err(format!("expected `)` at position {}", self.pos));
+                }
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) if c.is_ascii_alphabetic() => {
+                let name = self
+                    .take_while(|c| c.is_ascii_alphanumeric())
+                    .to_lowercase();
+                match name.as_str() {
+                    "pi" => return Ok(std::f64::consts::PI),
+                    "e" => return Ok(std::f64::consts::E),
+                    _ => {}
+                }
+                let f = function(&name).ok_or_else(|| format!("unknown name `{name}`"))?;
+                if !self.eat('(') {
+                    return Err(format!("expected `(` after {name}"));
+                }
+                let argument = self.sum()?;
+                if !self.eat(')') {
+                    return Err(format!("expected `)` at position {}", self.pos));
+                }
+                Ok(f(argument))
+            }
+            Some(c) => Err(format!("unexpected `{c}` at position {}", self.pos)),
+            None => Err("unexpected end of expression".into()),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, String> {
+        let start = self.pos;
+        self.take_while(|c| c.is_ascii_digit() || c == '.' || c == '_');
+        // Exponent, as in 1.5e-3.
+        let rest = &self.input[self.pos..];
+        if rest.starts_with(['e', 'E']) {
+            let sign = usize::from(rest[1..].starts_with(['+', '-']));
+            if rest[1 + sign..].starts_with(|c: char| c.is_ascii_digit()) {
+                self.pos += 1 + sign;
+                self.take_while(|c| c.is_ascii_digit());
+            }
+        }
+        let text = self.input[start..self.pos].replace('_', "");
+        text.parse().map_err(|_| format!("invalid number `{text}`"))
+    }
+}
+
+/// Evaluates `expression`.
+pub fn evaluate(expression: &str) -> Result<f64, String> {
+    let mut parser = Parser {
+        input: expression,
+        pos: 0,
+    };
+    let value = parser.sum()?;
+    if let Some(c) = parser.peek() {
+        return Err(format!("unexpected `{c}` at position {}", parser.pos));
+    }
+    if !value.is_finite() {
+        return Err("the result is not a finite number".into());
+    }
+    Ok(value)
+}
+
+/// `value` without float noise: whole numbers print without a fraction.
+pub fn format(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        let rounded = format!("{value:.12}");
+        let trimmed = rounded.trim_end_matches('0').trim_end_matches('.');
+        if trimmed == "0" || trimmed == "-0" {
+            // Too small for twelve places.
+            format!("{value:e}")
+        } else {
+            trimmed.to_string()
+        }
+    }
+}
@@ -0,0 +1,594 @@
+//! Tools models can call during a chat: a few built into the backend plus
+//! those of connected MCP servers and installed plugins. Arguments are
+//! checked against the tool's schema before anything runs, and tools that
+//! act on the machine or the network (reading files, the shell, code
+//! execution, any MCP tool, plugins allowed network access) wait for the
+//! user: a `tool-approval` event goes out and nothing runs until
+//! `approve_tool_call` answers it. `web_fetch` runs unasked, so it refuses
+//! addresses on this computer or its local network.
+//!
+//! Built-in tools can be switched off; the choice is kept in `tools.json`
+//! in the config directory.
+
+mod calculator;
+mod sandbox;
+mod schema;
+mod shell;
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::oneshot;
+
+use crate::citations;
+use crate::config;
+use crate::error::{Error, Result};
+use crate::llm::{ToolCall, ToolResult, ToolSpec};
+use crate::mcp::{self, McpTool};
+use crate::network;
+use crate::patch::{self, PatchOptions};
+use crate::plugins::{self, PluginTool};
+use crate::storage::{new_id, Database};
+use crate::web::{self, FetchOptions};
+
+pub use shell::ShellPolicy;
+
+const CONFIG_FILE: &str = "tools.json";
+/// Unanswered approvals count as declined after this long.
+const APPROVAL_TIMEOUT: Duration = Duration::from_secs(300);
+/// Tool output beyond this many characters is cut before the model sees it.
+const MAX_OUTPUT_CHARS: usize = 20_000;
+const MAX_READ_BYTES: u64 = 256 * 1024;
+const SHELL_TOOL: &str = "run_shell";
+
+/// A tool implemented in the backend.
+struct Builtin {
+    name: &'static str,
+    description: &'static str,
+    parameters: fn() -> Value,
+    /// Asks the user before every call.
+    confirm: bool,
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "web_fetch",
+        description: "Download a web page or text file over HTTP(S) and return its readable \
+                      text as Markdown, without navigation and other page furniture.",
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "url": { "type": "string", "description": "http:// or https:// URL" }
+                },
+                "required": ["url"],
+                "additionalProperties": false
+            })
+        },
+        confirm: false,
+    },
+    Builtin {
+        name: "read_file",
+        description: "Read a UTF-8 text file on the user's computer. The user confirms \
+                      every read.",
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "Absolute path", "minLength": 1 },
+                    "offset": {
+                        "type": "integer",
+                        "description": "Byte offset to start reading at",
+                        "minimum": 0
+                    }
+                },
+                "required": ["path"],
+                "additionalProperties": false
+            })
+        },
+        confirm: true,
+    },
+    Builtin {
+        name: SHELL_TOOL,
+        description: "Run a shell command on the user's computer (sh on macOS and Linux, \
+                      cmd on Windows) and return its exit code and output. The user \
+                      confirms every command, and some are refused outright.",
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "command": { "type": "string", "minLength": 1 },
+                    "cwd": { "type": "string", "description": "Working directory" },
+                    "timeout_secs": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "maximum": shell::MAX_TIMEOUT_SECS
+                    }
+                },
+                "required": ["command"],
+                "additionalProperties": false
+            })
+        },
+        confirm: true,
+    },
+    Builtin {
+        name: "run_code",
+        description: "Run a Python, JavaScript (Node) or shell snippet in a temporary \
+                      directory on the user's computer and return its exit code and \
+                      output. Nothing persists between runs. The user confirms every run.",
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "language": { "type": "string", "enum": ["python", "javascript", "shell"] },
+                    "code": { "type": "string", "minLength": 1 },
+                    "timeout_secs": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "maximum": sandbox::MAX_TIMEOUT_SECS
+                    }
+                },
+                "required": ["language", "code"],
+                "additionalProperties": false
+            })
+        },
+        confirm: true,
+    },
+    Builtin {
+        name: "apply_patch",
+        description: "Apply a unified diff to files in a folder on the user's computer and \
+                      report which hunks fit. Paths in the diff are relative to the folder. \
+                      Nothing is written unless every hunk fits, and the changed files are \
+                      backed up first. The user confirms every patch.",
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "root": { "type": "string", "description": "Absolute path of the folder" },
+                    "patch": { "type": "string", "minLength": 1 }
+                },
+                "required": ["root", "patch"],
+                "additionalProperties": false
+            })
+        },
+        confirm: true,
+    },
+    Builtin {
+        name: "calculator",
+        description: "Evaluate an arithmetic expression exactly, e.g. `(2^10 - 24) / 5` or \
+                      `sqrt(2) * pi`. Supports + - * / % ^, parentheses, pi, e, sqrt, cbrt, \
+                      abs, exp, ln, log, log2, sin, cos, tan, asin, acos, atan, floor, ceil \
+                      and round.",
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "expression": { "type": "string", "minLength": 1 }
+                },
+                "required": ["expression"],
+                "additionalProperties": false
+            })
+        },
+        confirm: false,
+    },
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ToolsConfig {
+    disabled: BTreeSet<String>,
+    #[serde(default)]
+    shell: ShellPolicy,
+}
+
+/// What `list_tools` returns.
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolInfo {
+    /// The name models call it by.
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+    /// The MCP server or plugin providing it; `None` for built-in tools.
+    pub server: Option<String>,
+    pub enabled: bool,
+    pub requires_approval: bool,
+}
+
+/// Payload of the `tool-approval` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolApprovalRequest {
+    pub approval_id: String,
+    /// The chat request the call belongs to.
+    pub request_id: String,
+    pub tool: String,
+    /// The MCP server or plugin providing it; `None` for built-in tools.
+    pub server: Option<String>,
+    pub arguments: Value,
+}
+
+/// Switched-off tools and calls awaiting approval. Managed as Tauri state.
+#[derive(Default)]
+pub struct Tools {
+    config: Mutex<ToolsConfig>,
+    approvals: Mutex<HashMap<String, oneshot::Sender<bool>>>,
+}
+
+impl Tools {
+    fn enabled(&self, name: &str) -> bool {
+        !self.config.lock().unwrap().disabled.contains(name)
+    }
+}
+
+/// A tool a call resolved to.
+enum Target {
+    Builtin(&'static Builtin),
+    Mcp(McpTool),
+    Plugin(PluginTool),
+}
+
+impl Target {
+    fn find(app: &AppHandle, name: &str) -> Option<Self> {
+        match BUILTINS.iter().find(|b| b.name == name) {
+            Some(builtin) => Some(Self::Builtin(builtin)),
+            None => mcp::find_tool(app, name)
+                .map(Self::Mcp)
+                .or_else(|| plugins::find_tool(app, name).map(Self::Plugin)),
+        }
+    }
+
+    fn parameters(&self) -> Value {
+        match self {
+            Self::Builtin(builtin) => (builtin.parameters)(),
+            Self::Mcp(tool) => tool.input_schema.clone(),
+            Self::Plugin(tool) => tool.parameters.clone(),
+        }
+    }
+
+    /// MCP tools can do anything, so they always ask.
+    fn requires_approval(&self) -> bool {
+        match self {
+            Self::Builtin(builtin) => builtin.confirm,
+            Self::Mcp(_) => true,
+            Self::Plugin(tool) => tool.requires_approval,
+        }
+    }
+
+    fn server(&self) -> Option<String> {
+        match self {
+            Self::Builtin(_) => None,
+            Self::Mcp(tool) => Some(tool.server.clone()),
+            Self::Plugin(tool) => Some(tool.plugin.clone()),
+        }
+    }
+}
+
+pub fn init(app: &AppHandle) {
+    let mut config = config::read::<ToolsConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    // The shell tool was called `shell` before it streamed its output.
+    if config.disabled.remove("shell") {
+        config.disabled.insert(SHELL_TOOL.to_string());
+    }
+    app.manage(Tools {
+        config: Mutex::new(config),
+        ..Default::default()
+    });
+}
+
+fn all_tools(app: &AppHandle) -> Vec<ToolInfo> {
+    let tools = app.state::<Tools>();
+    let builtins = BUILTINS.iter().map(|builtin| ToolInfo {
+        name: builtin.name.to_string(),
+        description: builtin.description.to_string(),
+        parameters: (builtin.parameters)(),
+        server: None,
+        enabled: tools.enabled(builtin.name),
+        requires_approval: builtin.confirm,
+    });
+    let remote = mcp::tools(app).into_iter().map(|tool| ToolInfo {
+        enabled: tools.enabled(&tool.qualified_name),
+        name: tool.qualified_name,
+        description: tool.description.unwrap_or_default(),
+        parameters: tool.input_schema,
+        server: Some(tool.server),
+        requires_approval: true,
+    });
+    let plugged = plugins::tools(app).into_iter().map(|tool| ToolInfo {
+        enabled: tools.enabled(&tool.qualified_name),
+        name: tool.qualified_name,
+        description: tool.description,
+        parameters: tool.parameters,
+        server: Some(tool.plugin),
+        requires_approval: tool.requires_approval,
+    });
+    builtins.chain(remote).chain(plugged).collect()
+}
+
+/// The enabled tools, as offered to models.
+pub fn specs(app: &AppHandle) -> Vec<ToolSpec> {
+    all_tools(app)
+        .into_iter()
+        .filter(|tool| tool.enabled)
+        .map(|tool| ToolSpec {
+            name: tool.name,
+            description: tool.description,
+            parameters: tool.parameters,
+        })
+        .collect()
+}
+
+/// Asks the user whether `tool` may run with `arguments`, waiting for
+/// `approve_tool_call`.
+async fn approve(
+    app: &AppHandle,
+    request_id: &str,
+    tool: &str,
+    server: Option<String>,
+    arguments: &Value,
+) -> bool {
+    let tools = app.state::<Tools>();
+    let approval_id = new_id();
+    let (sender, answer) = oneshot::channel();
+    tools
+        .approvals
+        .lock()
+        .unwrap()
+        .insert(approval_id.clone(), sender);
+    let _ = app.emit(
+        "tool-approval",
+        ToolApprovalRequest {
+            approval_id: approval_id.clone(),
+            request_id: request_id.to_string(),
+            tool: tool.to_string(),
+            server,
+            arguments: arguments.clone(),
+        },
+    );
+    let approved = matches!(
+        tokio::time::timeout(APPROVAL_TIMEOUT, answer).await,
+        Ok(Ok(true))
+    );
+    tools.approvals.lock().unwrap().remove(&approval_id);
+    approved
+}
+
+fn declined() -> Error {
+    Error::Tool("The user declined to run this tool.".into())
+}
+
+fn truncate(mut text: String) -> String {
+    if let Some((cut, _)) = text.char_indices().nth(MAX_OUTPUT_CHARS) {
+        text.truncate(cut);
+        text.push_str("\n[output truncated]");
+    }
+    text
+}
+
+/// Runs a call a model made. Failures, including an unknown tool, bad
+/// arguments and a declined approval, become error results for the model
+/// to see rather than ending the chat.
+pub async fn run(app: &AppHandle, request_id: &str, call: &ToolCall) -> ToolResult {
+    let outcome = async {
+        let target = Target::find(app, &call.name)
+            .filter(|_| app.state::<Tools>().enabled(&call.name))
+            .ok_or_else(|| Error::Tool(format!("there is no tool called {}", call.name)))?;
+        schema::validate(&target.parameters(), &call.arguments).map_err(Error::Tool)?;
+        let is_shell = matches!(target, Target::Builtin(builtin) if builtin.name == SHELL_TOOL);
+        if is_shell {
+            let (command, _) = shell::arguments(&call.arguments);
+            let policy = app.state::<Tools>().config.lock().unwrap().shell.clone();
+            if let Err(reason) = policy.check(&command) {
+                shell::refused(request_id, &call.arguments, &reason);
+                return Err(Error::Tool(format!("The command was refused: {reason}.")));
+            }
+        }
+        if target.requires_approval()
+            && !approve(
+                app,
+                request_id,
+                &call.name,
+                target.server(),
+                &call.arguments,
+            )
+            .await
+        {
+            if is_shell {
+                shell::refused(request_id, &call.arguments, "declined by the user");
+            }
+            return Err(declined());
+        }
+        match &target {
+            Target::Builtin(builtin) => run_builtin(app, request_id, builtin.name, &call.arguments)
+                .await
+                .map(|content| (content, false)),
+            Target::Mcp(tool) => mcp::call_tool(app, tool, call.arguments.clone())
+                .await
+                .map(|output| (output.content, output.is_error)),
+            Target::Plugin(tool) => plugins::call_tool(app, tool, call.arguments.clone()).await,
+        }
+    }
+    .await;
+    let (content, is_error) = match outcome {
+        Ok(output) => output,
+        Err(err) => (err.to_string(), true),
+    };
+    ToolResult {
+        call_id: call.id.clone(),
+        name: call.name.clone(),
+        content: truncate(content),
+        is_error,
+    }
+}
+
+async fn run_builtin(
+    app: &AppHandle,
+    request_id: &str,
+    name: &str,
+    arguments: &Value,
+) -> Result<String> {
+    let text = |key: &str| arguments[key].as_str().unwrap_or_default().to_string();
+    match name {
+        "web_fetch" => {
+            let url = text("url");
+            if let Ok(parsed) = Url::parse(&url) {
+                network::check_public(&parsed)?;
+            }
+            let client = network::public_client(app)?;
+            let page = web::fetch(&client, &url, &FetchOptions::default()).await?;
+            if citations::recording() {
+                if let Err(err) = app.state::<Database>().record_page(request_id, &page) {
+                    tracing::warn!("couldn't record {} as a source: {err}", page.url);
+                }
+            }
+            Ok(page.to_prompt())
+        }
+        "read_file" => {
+            let (path, offset) = (text("path"), arguments["offset"].as_u64().unwrap_or(0));
+            tauri::async_runtime::spawn_blocking(move || read_file(Path::new(&path), offset))
+                .await
+                .map_err(|e| Error::Io(std::io::Error::other(e)))?
+        }
+        SHELL_TOOL => {
+            let timeout = arguments["timeout_secs"]
+                .as_u64()
+                .unwrap_or(shell::DEFAULT_TIMEOUT_SECS);
+            let (command, cwd) = shell::arguments(arguments);
+            let timeout = Duration::from_secs(timeout);
+            shell::run(app, request_id, &command, cwd.as_deref(), timeout).await
+        }
+        "run_code" => {
+            let language = serde_json::from_value(arguments["language"].clone())?;
+            let timeout = arguments["timeout_secs"]
+                .as_u64()
+                .unwrap_or(sandbox::DEFAULT_TIMEOUT_SECS);
+            let output =
+                sandbox::run(language, &text("code"), Duration::from_secs(timeout)).await?;
+            Ok(output.summary())
+        }
+        "apply_patch" => {
+            let root = text("root");
+            if !Path::new(&root).is_absolute() {
+                return Err(Error::Tool("the folder must be an absolute path".into()));
+            }
+            let outcome = patch::apply(
+                app,
+                Path::new(&root),
+                &text("patch"),
+                PatchOptions::default(),
+            )
+            .await?;
+            Ok(outcome.summary())
+        }
+        "calculator" => calculator::evaluate(&text("expression"))
+            .map(calculator::format)
+            .map_err(Error::Tool),
+        _ => Err(Error::Tool(format!("there is no tool called {name}"))),
+    }
+}
+
+fn read_file(path: &Path, offset: u64) -> Result<String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    if !path.is_absolute() {
+        return Err(Error::Tool("the path must be absolute".into()));
+    }
+    let mut file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = Vec::new();
+    file.take(MAX_READ_BYTES).read_to_end(&mut bytes)?;
+    if bytes.contains(&0) {
+        return Err(Error::Tool(format!(
+            "{} is not a text file",
+            path.display()
+        )));
+    }
+    let mut text = String::from_utf8_lossy(&bytes).into_owned();
+    let end = offset + bytes.len() as u64;
+    if end < size {
+        text.push_str(&format!(
+            "\n[read bytes {offset}-{end} of {size}; continue with offset {end}]"
+        ));
+    }
+    Ok(text)
+}
+
+/// Runs a snippet in the sandbox, once the user confirms it through a
+/// `tool-approval` event like a model's calls (with `request_id` as given),
+/// so nothing rendered in the webview can run code on its own.
+#[tauri::command]
+pub async fn run_code(
+    app: AppHandle,
+    request_id: Option<String>,
+    language: sandbox::Language,
+    code: String,
+    timeout_secs: Option<u64>,
+) -> Result<sandbox::CodeOutput> {
+    let arguments = json!({ "language": language, "code": code });
+    let request_id = request_id.unwrap_or_default();
+    if !approve(&app, &request_id, "run_code", None, &arguments).await {
+        return Err(declined());
+    }
+    let timeout = timeout_secs
+        .unwrap_or(sandbox::DEFAULT_TIMEOUT_SECS)
+        .clamp(1, sandbox::MAX_TIMEOUT_SECS);
+    sandbox::run(language, &code, Duration::from_secs(timeout)).await
+}
+
+/// Every tool models could be offered, built-in ones first.
+#[tauri::command]
+pub fn list_tools(app: AppHandle) -> Vec<ToolInfo> {
+    all_tools(&app)
+}
+
+/// Switches a tool on or off for models, and remembers the choice.
+#[tauri::command]
+pub fn set_tool_enabled(
+    app: AppHandle,
+    tools: State<'_, Tools>,
+    name: String,
+    enabled: bool,
+) -> Result<()> {
+    let mut config = tools.config.lock().unwrap();
+    if enabled {
+        config.disabled.remove(&name);
+    } else {
+        config.disabled.insert(name);
+    }
+    config::write(&app, CONFIG_FILE, &*config)
+}
+
+#[tauri::command]
+pub fn get_shell_policy(tools: State<'_, Tools>) -> ShellPolicy {
+    tools.config.lock().unwrap().shell.clone()
+}
+
+/// Sets what `run_shell` refuses without asking.
+#[tauri::command]
+pub fn set_shell_policy(
+    app: AppHandle,
+    tools: State<'_, Tools>,
+    policy: ShellPolicy,
+) -> Result<ShellPolicy> {
+    let mut config = tools.config.lock().unwrap();
+    config.shell = policy.clone();
+    config::write(&app, CONFIG_FILE, &*config)?;
+    Ok(policy)
+}
+
+/// Answers a `tool-approval` event. Returns whether the call was still
+/// waiting.
+#[tauri::command]
+pub fn approve_tool_call(tools: State<'_, Tools>, approval_id: String, approved: bool) -> bool {
+    match tools.approvals.lock().unwrap().remove(&approval_id) {
+        Some(sender) => sender.send(approved).is_ok(),
+        None => false,
+    }
+}
@@ -0,0 +1,249 @@
+//! Runs code snippets for `run_code`: each in a fresh temporary directory
+//! that is deleted afterwards, with a minimal environment, a wall-clock
+//! timeout and captured output. A snippet runs in a process group of its
+//! own, so a timeout stops anything it started too. On unix the process
+//! also gets CPU time, memory and file size limits; Windows gets the
+//! timeout only.
+
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::error::{Error, Result};
+use crate::process;
+use crate::storage::new_id;
+
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+pub const MAX_TIMEOUT_SECS: u64 = 300;
+/// Address space a snippet may use (unix only).
+const MEMORY_LIMIT_MB: u64 = 1024;
+/// Largest file a snippet may write (unix only).
+const FILE_SIZE_LIMIT: u64 = 64 * 1024 * 1024;
+/// Output kept from each of stdout and stderr.
+const MAX_OUTPUT_BYTES: u64 = 1024 * 1024;
+/// How long output is still collected after the process has exited.
+const PIPE_GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Language {
+    Python,
+    #[serde(rename = "javascript", alias = "js")]
+    JavaScript,
+    Shell,
+}
+
+impl Language {
+    fn file_name(self) -> &'static str {
+        match self {
+            Self::Python => "main.py",
+            Self::JavaScript => "main.js",
+            Self::Shell if cfg!(windows) => "main.ps1",
+            Self::Shell => "main.sh",
+        }
+    }
+
+    /// The interpreter and the arguments preceding the script path.
+    fn interpreter(self) -> (&'static str, Vec<String>) {
+        match self {
+            Self::Python if cfg!(windows) => ("python", vec!["-I".into()]),
+            Self::Python => ("python3", vec!["-I".into()]),
+            // Node reserves far more address space than it uses, so its heap
+            // is capped with its own flag rather than the address space limit.
+            Self::JavaScript => (
+                "node",
+                vec![format!("--max-old-space-size={MEMORY_LIMIT_MB}")],
+            ),
+            Self::Shell if cfg!(windows) => (
+                "powershell",
+                vec![
+                    "-NoProfile".into(),
+                    "-NonInteractive".into(),
+                    "-ExecutionPolicy".into(),
+                    "Bypass".into(),
+                    "-File".into(),
+                ],
+            ),
+            Self::Shell => ("sh", Vec::new()),
+        }
+    }
+}
+
+/// What `run_code` returns.
+#[derive(Debug, Clone, Serialize)]
+pub struct CodeOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// `None` when the process was killed: on timeout, over a limit, or by
+    /// a signal.
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    /// Output went past what is kept.
+    pub truncated: bool,
+    pub duration_ms: u64,
+}
+
+impl CodeOutput {
+    /// The output as text for a model.
+    pub fn summary(&self) -> String {
+        let status = match (self.exit_code, self.timed_out) {
+            (_, true) => "timed out".to_string(),
+            (Some(code), _) => format!("exit code {code}"),
+            (None, _) => "killed".to_string(),
+        };
+        format!(
+            "{status}{}\nstdout:\n{}\nstderr:\n{}",
+            if self.truncated {
+                " (output truncated)"
+            } else {
+                ""
+            },
+            self.stdout,
+            self.stderr
+        )
+    }
+}
+
+/// Deletes the snippet's directory however the run ends.
+struct TempDir(PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(unix)]
+fn limit_resources(command: &mut tokio::process::Command, language: Language, cpu_secs: u64) {
+    let limit_memory = language != Language::JavaScript;
+    let set = |resource, value: u64| {
+        let limit = libc::rlimit {
+            rlim_cur: value as libc::rlim_t,
+            rlim_max: value as libc::rlim_t,
+        };
+        // Only async-signal-safe calls are allowed between fork and exec;
+        // setrlimit is one.
+        unsafe { libc::setrlimit(resource, &limit) }
+    };
+    let pre_exec = move || {
+        set(libc::RLIMIT_CPU, cpu_secs);
+        set(libc::RLIMIT_FSIZE, FILE_SIZE_LIMIT);
+        set(libc::RLIMIT_CORE, 0);
+        if limit_memory {
+            set(libc::RLIMIT_AS, MEMORY_LIMIT_MB * 1024 * 1024);
+        }
+        Ok(())
+    };
+    unsafe { command.pre_exec(pre_exec) };
+}
+
+/// The environment a snippet sees: a search path and a home inside its own
+/// directory, nothing else from the app, whose API keys may be in its own.
+fn minimal_env(command: &mut tokio::process::Command, dir: &std::path::Path) {
+    command.env_clear();
+    #[cfg(unix)]
+    command
+        .env(
+            "PATH",
+            std::env::var_os("PATH").unwrap_or_else(|| "/usr/bin:/bin".into()),
+        )
+        .env("HOME", dir)
+        .env("TMPDIR", dir)
+        .env("LANG", "C.UTF-8");
+    #[cfg(windows)]
+    {
+        // What interpreters need to start at all.
+        for name in [
+            "PATH",
+            "PATHEXT",
+            "SystemRoot",
+            "SystemDrive",
+            "windir",
+            "COMSPEC",
+        ] {
+            if let Some(value) = std::env::var_os(name) {
+                command.env(name, value);
+            }
+        }
+        command
+            .env("USERPROFILE", dir)
+            .env("TEMP", dir)
+            .env("TMP", dir);
+    }
+}
+
+/// Reads up to `MAX_OUTPUT_BYTES` and discards the rest, so a noisy
+/// snippet never blocks on a full pipe. Returns whether anything was cut.
+async fn read_capped(pipe: Option<impl AsyncRead + Unpin>) -> (Vec<u8>, bool) {
+    let mut bytes = Vec::new();
+    let Some(mut pipe) = pipe else {
+        return (bytes, false);
+    };
+    let _ = (&mut pipe)
+        .take(MAX_OUTPUT_BYTES)
+        .read_to_end(&mut bytes)
+        .await;
+    let discarded = tokio::io::copy(&mut pipe, &mut tokio::io::sink())
+        .await
+        .unwrap_or_default();
+    (bytes, discarded > 0)
+}
+
+/// Runs `code` and waits up to `timeout` for it. A missing interpreter is
+/// an error; anything the snippet itself does wrong is in the output.
+pub async fn run(language: Language, code: &str, timeout: Duration) -> Result<CodeOutput> {
+    let dir = TempDir(std::env::temp_dir().join(format!("pentamind-run-{}", new_id())));
+    std::fs::create_dir_all(&dir.0)?;
+    let script = dir.0.join(language.file_name());
+    std::fs::write(&script, code)?;
+
+    let (program, args) = language.interpreter();
+    let mut command = tokio::process::Command::from(process::command(program));
+    command
+        .args(args)
+        .arg(&script)
+        .current_dir(&dir.0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    minimal_env(&mut command, &dir.0);
+    process::own_group(&mut command);
+    #[cfg(unix)]
+    limit_resources(&mut command, language, timeout.as_secs().max(1));
+    let started = Instant::now();
+    let mut child = command
+        .spawn()
+        .map_err(|e| Error::Tool(format!("couldn't start {program}: {e}")))?;
+    let stdout = tauri::async_runtime::spawn(read_capped(child.stdout.take()));
+    let stderr = tauri::async_runtime::spawn(read_capped(child.stderr.take()));
+
+    let (exit_code, timed_out) = match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(status) => (status?.code(), false),
+        Err(_) => {
+            process::kill_tree(&mut child).await;
+            (None, true)
+        }
+    };
+    let duration_ms = started.elapsed().as_millis() as u64;
+    // The pipes close once the process is gone, unless it left children
+    // holding them; don't wait on those for long.
+    let collect = |reader: tauri::async_runtime::JoinHandle<(Vec<u8>, bool)>| async move {
+        match tokio::time::timeout(PIPE_GRACE, reader).await {
+            Ok(Ok((bytes, truncated))) => (String::from_utf8_lossy(&bytes).into_owned(), truncated),
+            _ => (String::new(), false),
+        }
+    };
+    let ((stdout, out_cut), (stderr, err_cut)) = tokio::join!(collect(stdout), collect(stderr));
+    Ok(CodeOutput {
+        stdout,
+        stderr,
+        exit_code,
+        timed_out,
+        truncated: out_cut || err_cut,
+        duration_ms,
+    })
+}
@@ -0,0 +1,142 @@
+//! Checks tool arguments against the JSON Schema a tool declares before it
+//! runs. Covers the keywords tool schemas use in practice (`type`, `enum`,
+//! `const`, `required`, `properties`, `additionalProperties`, `items` and
+//! the numeric, length and count bounds); others are ignored.
+
+use serde_json::{Map, Value};
+
+fn type_matches(name: &str, value: &Value) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        // Unknown type names can't be checked; let them through.
+        _ => true,
+    }
+}
+
+fn check_type(schema: &Map<String, Value>, value: &Value, path: &str) -> Result<(), String> {
+    let matches = match schema.get("type") {
+        Some(Value::String(name)) => type_matches(name, value),
+        Some(Value::Array(names)) => names
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|name| type_matches(name, value)),
+        _ => true,
+    };
+    if matches {
+        return Ok(());
+    }
+    let expected = match &schema["type"] {
+        Value::Array(names) => names
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join(" or "),
+        other => other.as_str().unwrap_or_default().to_string(),
+    };
+    Err(format!("{path}: expected {expected}"))
+}
+
+fn check_bounds(schema: &Map<String, Value>, value: &Value, path: &str) -> Result<(), String> {
+    let bound = |key: &str| schema.get(key).and_then(Value::as_f64);
+    if let Some(n) = value.as_f64() {
+        if let Some(min) = bound("minimum").filter(|&min| n < min) {
+            return Err(format!("{path}: must be at least {min}"));
+        }
+        if let Some(max) = bound("maximum").filter(|&max| n > max) {
+            return Err(format!("{path}: must be at most {max}"));
+        }
+    }
+    let (len, unit) = match value {
+        Value::String(s) => (s.chars().count(), "characters"),
+        Value::Array(items) => (items.len(), "items"),
+        _ => return Ok(()),
+    };
+    let (min_key, max_key) = if value.is_string() {
+        ("minLength", "maxLength")
+    } else {
+        ("minItems", "maxItems")
+    };
+    if let Some(min) = bound(min_key).filter(|&min| (len as f64) < min) {
+        return Err(format!("{path}: needs at least {min} {unit}"));
+    }
+    if let Some(max) = bound(max_key).filter(|&max| len as f64 > max) {
+        return Err(format!("{path}: allows at most {max} {unit}"));
+    }
+    Ok(())
+}
+
+fn check(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    let Some(schema) = schema.as_object() else {
+        return Ok(());
+    };
+    check_type(schema, value, path)?;
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            let options: Vec<String> = allowed.iter().map(Value::to_string).collect();
+            return Err(format!("{path}: must be one of {}", options.join(", ")));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            return Err(format!("{path}: must be {expected}"));
+        }
+    }
+    check_bounds(schema, value, path)?;
+
+    match value {
+        Value::Object(fields) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for name in schema
+                .get("required")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+            {
+                if !fields.contains_key(name) {
+                    return Err(format!("{path}: missing `{name}`"));
+                }
+            }
+            for (name, field) in fields {
+                let field_path = format!("{path}.{name}");
+                match properties.and_then(|p| p.get(name)) {
+                    Some(field_schema) => check(field_schema, field, &field_path)?,
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            return Err(format!("{path}: unexpected `{name}`"));
+                        }
+                        Some(extra) => check(extra, field, &field_path)?,
+                        None => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check(item_schema, item, &format!("{path}[{i}]"))?;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Checks `arguments` against `schema`, describing the first problem found.
+pub fn validate(schema: &Value, arguments: &Value) -> Result<(), String> {
+    if let Value::String(raw) = arguments {
+        if schema["type"] == "object" {
+            return Err(format!("arguments aren't valid JSON: {raw}"));
+        }
+    }
+    check(schema, arguments, "arguments")
+}
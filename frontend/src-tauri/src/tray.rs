@@ -0,0 +1,222 @@
+//! Menu bar / system tray icon with quick actions. While models are
+//! streaming the icon pulses so progress is visible with the window hidden,
+//! and while the wake word listener has the microphone open a mic shows
+//! next to it.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tauri::async_runtime::JoinHandle;
+use tauri::image::Image;
+use tauri::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tauri::tray::{MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent};
+use tauri::{App, AppHandle, Emitter, Manager, State, Wry};
+
+use crate::audio::wake;
+use crate::error::Result;
+use crate::mini_window;
+use crate::requests::Requests;
+use crate::windows;
+
+const TRAY_ID: &str = "main";
+const FRAME_INTERVAL: Duration = Duration::from_millis(120);
+const PULSE_FRAMES: usize = 8;
+const MIC_TITLE: &str = "🎙";
+
+/// Payload of the `tray-action` event for actions the frontend handles.
+#[derive(Debug, Clone, Serialize)]
+pub struct TrayAction {
+    pub action: &'static str,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TrayStatus {
+    pub streaming: bool,
+    /// Number of models still responding, shown in the tooltip.
+    pub active: Option<u32>,
+}
+
+/// Handle of the running pulse animation, if any.
+#[derive(Default)]
+pub struct TrayAnimation(Mutex<Option<JoinHandle<()>>>);
+
+/// Menu items whose state changes while the app runs.
+struct TrayMenu {
+    wake_word: CheckMenuItem<Wry>,
+}
+
+pub fn init(app: &App) -> Result<()> {
+    let wake_word = CheckMenuItem::with_id(
+        app,
+        "wake_word",
+        "Listen for Wake Word",
+        true,
+        wake::enabled(app.handle()),
+        None::<&str>,
+    )?;
+    let menu = Menu::with_items(
+        app,
+        &[
+            &MenuItem::with_id(
+                app,
+                "new_conversation",
+                "New Conversation",
+                true,
+                None::<&str>,
+            )?,
+            &MenuItem::with_id(
+                app,
+                "toggle_window",
+                "Show/Hide Pentamind",
+                true,
+                None::<&str>,
+            )?,
+            &MenuItem::with_id(
+                app,
+                "toggle_mini_window",
+                "Show/Hide Mini Window",
+                true,
+                None::<&str>,
+            )?,
+            &MenuItem::with_id(
+                app,
+                "pause_streaming",
+                "Pause Streaming",
+                true,
+                None::<&str>,
+            )?,
+            &wake_word,
+            &PredefinedMenuItem::separator(app)?,
+            &MenuItem::with_id(app, "quit", "Quit Pentamind", true, None::<&str>)?,
+        ],
+    )?;
+
+    let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+        .tooltip("Pentamind")
+        .menu(&menu)
+        .show_menu_on_left_click(false)
+        .on_menu_event(on_menu_event)
+        .on_tray_icon_event(|tray, event| {
+            if let TrayIconEvent::Click {
+                button: MouseButton::Left,
+                button_state: MouseButtonState::Up,
+                ..
+            } = event
+            {
+                windows::toggle_main_window(tray.app_handle());
+            }
+        });
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    builder.build(app)?;
+    app.manage(TrayAnimation::default());
+    app.manage(TrayMenu { wake_word });
+    Ok(())
+}
+
+fn on_menu_event(app: &AppHandle, event: MenuEvent) {
+    match event.id.as_ref() {
+        "new_conversation" => {
+            windows::show_main_window(app);
+            let _ = app.emit(
+                "tray-action",
+                TrayAction {
+                    action: "new_conversation",
+                },
+            );
+        }
+        "toggle_window" => windows::toggle_main_window(app),
+        "toggle_mini_window" => {
+            if let Err(err) = mini_window::toggle_mini_window(app.clone()) {
+                tracing::warn!("couldn't toggle the mini window: {err}");
+            }
+        }
+        "pause_streaming" => {
+            app.state::<Requests>().cancel_all();
+            let _ = app.emit(
+                "tray-action",
+                TrayAction {
+                    action: "pause_streaming",
+                },
+            );
+        }
+        "wake_word" => {
+            if let Err(err) = wake::toggle(app) {
+                tracing::warn!("couldn't toggle the wake word: {err}");
+            }
+        }
+        "quit" => app.exit(0),
+        _ => {}
+    }
+}
+
+/// Shows or hides the mic next to the icon.
+pub fn set_listening(app: &AppHandle, listening: bool) {
+    if let Some(tray) = app.tray_by_id(TRAY_ID) {
+        let _ = tray.set_title(listening.then_some(MIC_TITLE));
+    }
+}
+
+/// Keeps the menu's checkmark in step with the wake word setting.
+pub fn set_wake_word_enabled(app: &AppHandle, enabled: bool) {
+    if let Some(menu) = app.try_state::<TrayMenu>() {
+        let _ = menu.wake_word.set_checked(enabled);
+    }
+}
+
+/// Builds the pulse animation by fading the app icon's alpha channel.
+fn pulse_frames(icon: &Image<'_>) -> Vec<Image<'static>> {
+    (0..PULSE_FRAMES)
+        .map(|i| {
+            let phase = i as f32 / PULSE_FRAMES as f32 * std::f32::consts::TAU;
+            let alpha = 0.65 + 0.35 * phase.cos();
+            let rgba = icon
+                .rgba()
+                .chunks_exact(4)
+                .flat_map(|px| [px[0], px[1], px[2], (px[3] as f32 * alpha) as u8])
+                .collect();
+            Image::new_owned(rgba, icon.width(), icon.height())
+        })
+        .collect()
+}
+
+fn start_animation(app: &AppHandle, tray: TrayIcon) -> Option<JoinHandle<()>> {
+    let frames = pulse_frames(app.default_window_icon()?);
+    Some(tauri::async_runtime::spawn(async move {
+        for frame in frames.iter().cycle() {
+            if tray.set_icon(Some(frame.clone())).is_err() {
+                break;
+            }
+            tokio::time::sleep(FRAME_INTERVAL).await;
+        }
+    }))
+}
+
+#[tauri::command]
+pub fn set_tray_status(
+    app: AppHandle,
+    animation: State<'_, TrayAnimation>,
+    status: TrayStatus,
+) -> Result<()> {
+    let Some(tray) = app.tray_by_id(TRAY_ID) else {
+        return Ok(());
+    };
+    let mut running = animation.0.lock().unwrap();
+    if let Some(handle) = running.take() {
+        handle.abort();
+    }
+    if status.streaming {
+        let tooltip = match status.active {
+            Some(n) => format!("Pentamind — {n} responding"),
+            None => "Pentamind — responding".to_string(),
+        };
+        tray.set_tooltip(Some(tooltip))?;
+        *running = start_animation(&app, tray);
+    } else {
+        tray.set_tooltip(Some("Pentamind"))?;
+        tray.set_icon(app.default_window_icon().cloned())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,324 @@
+//! Self-updates from the GitHub release feed. Each channel has its own
+//! manifest: `stable` follows the latest release, `beta` a rolling
+//! pre-release that gets builds before they are promoted. Updates are
+//! signed, so only builds made with `PENTAMIND_UPDATER_PUBKEY` set (and the
+//! `updater` feature on) can update themselves.
+//!
+//! `check_for_updates` looks for a newer build on the configured channel,
+//! `download_update` fetches it, emitting `update-download-progress`, and
+//! `install_and_restart` applies it. With `auto_update` on, the app checks
+//! every few hours, downloads in the background and installs on quit.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::error::{Error, Result};
+use crate::settings::SettingsStore;
+
+const STABLE_ENDPOINT: &str =
+    "https://github.com/bshiribaiev/pentamind/releases/latest/download/latest.json";
+const BETA_ENDPOINT: &str =
+    "https://github.com/bshiribaiev/pentamind/releases/download/beta/latest.json";
+
+/// The minisign key release builds are signed with, set when building them.
+#[cfg_attr(not(feature = "updater"), allow(dead_code))]
+const PUBKEY: Option<&str> = option_env!("PENTAMIND_UPDATER_PUBKEY");
+
+/// Automatic checks wait this long after launch, then repeat at the interval.
+const FIRST_CHECK: Duration = Duration::from_secs(60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+/// Bytes between `update-download-progress` events.
+const PROGRESS_STEP: u64 = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateChannel {
+    #[default]
+    Stable,
+    Beta,
+}
+
+impl UpdateChannel {
+    #[cfg_attr(not(feature = "updater"), allow(dead_code))]
+    fn endpoint(self) -> &'static str {
+        match self {
+            Self::Stable => STABLE_ENDPOINT,
+            Self::Beta => BETA_ENDPOINT,
+        }
+    }
+}
+
+/// What `check_for_updates` and `download_update` return.
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateInfo {
+    pub version: String,
+    pub current_version: String,
+    pub channel: UpdateChannel,
+    /// Release notes, as Markdown.
+    pub notes: Option<String>,
+    pub published: Option<String>,
+    /// Downloaded and ready for `install_and_restart`.
+    pub downloaded: bool,
+}
+
+/// Payload of `update-download-progress`.
+#[derive(Debug, Clone, Serialize)]
+struct DownloadProgress {
+    version: String,
+    downloaded: u64,
+    total: Option<u64>,
+}
+
+#[cfg(feature = "updater")]
+type Found = tauri_plugin_updater::Update;
+
+/// Stands in for an update in builds that can't find one.
+#[cfg(not(feature = "updater"))]
+enum Found {}
+
+/// The update the last check found, and its bytes once downloaded.
+struct Staged {
+    channel: UpdateChannel,
+    found: Found,
+    bytes: Option<Vec<u8>>,
+}
+
+impl Staged {
+    fn info(&self) -> UpdateInfo {
+        let (version, current_version, notes, published) = describe(&self.found);
+        UpdateInfo {
+            version,
+            current_version,
+            channel: self.channel,
+            notes,
+            published,
+            downloaded: self.bytes.is_some(),
+        }
+    }
+}
+
+/// Managed as Tauri state. The lock is held through a download so two
+/// callers never fetch the same update.
+#[derive(Default)]
+pub struct Updates {
+    staged: tokio::sync::Mutex<Option<Staged>>,
+}
+
+#[cfg(feature = "updater")]
+fn update_error(e: tauri_plugin_updater::Error) -> Error {
+    Error::Update(e.to_string())
+}
+
+#[cfg(feature = "updater")]
+async fn find(app: &AppHandle, channel: UpdateChannel) -> Result<Option<Found>> {
+    use tauri_plugin_updater::UpdaterExt;
+
+    let Some(pubkey) = PUBKEY else {
+        return Err(Error::Unsupported("updating an unsigned build".into()));
+    };
+    let endpoint = channel
+        .endpoint()
+        .parse()
+        .expect("endpoints are valid URLs");
+    app.updater_builder()
+        .pubkey(pubkey)
+        .endpoints(vec![endpoint])
+        .map_err(update_error)?
+        .build()
+        .map_err(update_error)?
+        .check()
+        .await
+        .map_err(update_error)
+}
+
+#[cfg(feature = "updater")]
+fn describe(found: &Found) -> (String, String, Option<String>, Option<String>) {
+    let published = found
+        .raw_json
+        .get("pub_date")
+        .and_then(serde_json::Value::as_str)
+        .map(str::to_string);
+    (
+        found.version.clone(),
+        found.current_version.clone(),
+        found.body.clone(),
+        published,
+    )
+}
+
+/// Downloads and verifies the update, reporting the running byte count.
+#[cfg(feature = "updater")]
+async fn fetch(found: &Found, mut progress: impl FnMut(u64, Option<u64>)) -> Result<Vec<u8>> {
+    let mut downloaded = 0;
+    found
+        .download(
+            |chunk, total| {
+                downloaded += chunk as u64;
+                progress(downloaded, total);
+            },
+            || {},
+        )
+        .await
+        .map_err(update_error)
+}
+
+#[cfg(feature = "updater")]
+fn apply(found: &Found, bytes: &[u8]) -> Result<()> {
+    found.install(bytes).map_err(update_error)
+}
+
+#[cfg(not(feature = "updater"))]
+async fn find(_app: &AppHandle, _channel: UpdateChannel) -> Result<Option<Found>> {
+    Err(Error::Unsupported("updating this build".into()))
+}
+
+#[cfg(not(feature = "updater"))]
+fn describe(found: &Found) -> (String, String, Option<String>, Option<String>) {
+    match *found {}
+}
+
+#[cfg(not(feature = "updater"))]
+async fn fetch(found: &Found, _progress: impl FnMut(u64, Option<u64>)) -> Result<Vec<u8>> {
+    match *found {}
+}
+
+#[cfg(not(feature = "updater"))]
+fn apply(found: &Found, _bytes: &[u8]) -> Result<()> {
+    match *found {}
+}
+
+fn settings(app: &AppHandle) -> crate::settings::Settings {
+    app.state::<SettingsStore>().get()
+}
+
+/// Checks the configured channel and stages what it offers. A download
+/// already made of the same version is kept.
+async fn refresh(app: &AppHandle, staged: &mut Option<Staged>) -> Result<()> {
+    let channel = settings(app).update_channel;
+    let Some(found) = find(app, channel).await? else {
+        *staged = None;
+        return Ok(());
+    };
+    let version = describe(&found).0;
+    let keep = staged
+        .as_ref()
+        .is_some_and(|s| s.channel == channel && s.info().version == version);
+    if !keep {
+        *staged = Some(Staged {
+            channel,
+            found,
+            bytes: None,
+        });
+    }
+    Ok(())
+}
+
+async fn download(app: &AppHandle) -> Result<UpdateInfo> {
+    let updates = app.state::<Updates>();
+    let mut staged = updates.staged.lock().await;
+    refresh(app, &mut staged).await?;
+    let Some(staged) = staged.as_mut() else {
+        return Err(Error::Update("there is no update to download".into()));
+    };
+    if staged.bytes.is_none() {
+        let version = staged.info().version;
+        let mut reported = 0;
+        let bytes = fetch(&staged.found, |downloaded, total| {
+            if downloaded - reported >= PROGRESS_STEP || Some(downloaded) == total {
+                reported = downloaded;
+                let _ = app.emit(
+                    "update-download-progress",
+                    DownloadProgress {
+                        version: version.clone(),
+                        downloaded,
+                        total,
+                    },
+                );
+            }
+        })
+        .await?;
+        staged.bytes = Some(bytes);
+    }
+    let info = staged.info();
+    let _ = app.emit("update-downloaded", &info);
+    Ok(info)
+}
+
+/// Registers the updater and starts the automatic checks, which do nothing
+/// while `auto_update` is off.
+pub fn init(app: &AppHandle) -> Result<()> {
+    #[cfg(feature = "updater")]
+    app.plugin(tauri_plugin_updater::Builder::new().build())?;
+    app.manage(Updates::default());
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(FIRST_CHECK).await;
+        loop {
+            if settings(&app).auto_update {
+                if let Err(err) = download(&app).await {
+                    tracing::warn!("automatic update failed: {err}");
+                }
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+    Ok(())
+}
+
+/// Installs an update downloaded in the background. Called as the app
+/// exits; skipped if a download is still running.
+pub fn install_on_exit(app: &AppHandle) {
+    if !settings(app).auto_update {
+        return;
+    }
+    let Some(updates) = app.try_state::<Updates>() else {
+        return;
+    };
+    let Ok(staged) = updates.staged.try_lock() else {
+        return;
+    };
+    if let Some(Staged {
+        found,
+        bytes: Some(bytes),
+        ..
+    }) = staged.as_ref()
+    {
+        let _ = apply(found, bytes);
+    }
+}
+
+/// The newest build on the configured channel, if it is newer than this one.
+#[tauri::command]
+pub async fn check_for_updates(app: AppHandle) -> Result<Option<UpdateInfo>> {
+    let updates = app.state::<Updates>();
+    let mut staged = updates.staged.lock().await;
+    refresh(&app, &mut staged).await?;
+    Ok(staged.as_ref().map(Staged::info))
+}
+
+/// Downloads the update `check_for_updates` found, checking first if it
+/// hasn't been called. Emits `update-download-progress` along the way and
+/// `update-downloaded` when done.
+#[tauri::command]
+pub async fn download_update(app: AppHandle) -> Result<UpdateInfo> {
+    download(&app).await
+}
+
+/// Installs the downloaded update and relaunches into it.
+#[tauri::command]
+pub async fn install_and_restart(app: AppHandle) -> Result<()> {
+    let updates = app.state::<Updates>();
+    let staged = updates.staged.lock().await;
+    let Some(Staged {
+        found,
+        bytes: Some(bytes),
+        ..
+    }) = staged.as_ref()
+    else {
+        return Err(Error::Update("no update has been downloaded".into()));
+    };
+    apply(found, bytes)?;
+    app.restart()
+}
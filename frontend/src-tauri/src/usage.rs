@@ -0,0 +1,330 @@
+//! Token and cost accounting. Every finished generation is recorded with the
+//! provider's reported usage (or a local estimate when it sends none), priced
+//! from a static table, and checked against an optional monthly budget.
+
+use std::sync::Mutex;
+
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::config;
+use crate::error::Result;
+use crate::incognito;
+use crate::llm::{tokens, ChatRequest, Usage};
+use crate::storage::{new_id, now_ms, Database};
+
+const CONFIG_FILE: &str = "budget.json";
+
+/// USD per million input and output tokens, matched by model prefix. More
+/// specific prefixes come first.
+const PRICES: &[(&str, &str, f64, f64)] = &[
+    ("openai", "gpt-4o-mini", 0.15, 0.60),
+    ("openai", "gpt-4o", 2.50, 10.00),
+    ("openai", "gpt-4.1-nano", 0.10, 0.40),
+    ("openai", "gpt-4.1-mini", 0.40, 1.60),
+    ("openai", "gpt-4.1", 2.00, 8.00),
+    ("openai", "gpt-4-turbo", 10.00, 30.00),
+    ("openai", "gpt-4", 30.00, 60.00),
+    ("openai", "gpt-3.5", 0.50, 1.50),
+    ("openai", "o1-mini", 1.10, 4.40),
+    ("openai", "o1", 15.00, 60.00),
+    ("openai", "o3-mini", 1.10, 4.40),
+    ("openai", "o3", 2.00, 8.00),
+    ("openai", "o4-mini", 1.10, 4.40),
+    ("anthropic", "claude-opus-4", 15.00, 75.00),
+    ("anthropic", "claude-sonnet-4", 3.00, 15.00),
+    ("anthropic", "claude-3-7-sonnet", 3.00, 15.00),
+    ("anthropic", "claude-3-5-sonnet", 3.00, 15.00),
+    ("anthropic", "claude-3-5-haiku", 0.80, 4.00),
+    ("anthropic", "claude-3-opus", 15.00, 75.00),
+    ("anthropic", "claude-3-haiku", 0.25, 1.25),
+    ("google", "gemini-2.5-pro", 1.25, 10.00),
+    ("google", "gemini-2.5-flash", 0.30, 2.50),
+    ("google", "gemini-2.0-flash", 0.10, 0.40),
+    ("google", "gemini-1.5-pro", 1.25, 5.00),
+    ("google", "gemini-1.5-flash", 0.075, 0.30),
+    ("mistral", "mistral-large", 2.00, 6.00),
+    ("mistral", "mistral-medium", 0.40, 2.00),
+    ("mistral", "mistral-small", 0.10, 0.30),
+    ("mistral", "codestral", 0.30, 0.90),
+    // Local models cost nothing.
+    ("ollama", "", 0.0, 0.0),
+];
+
+/// Cost of `usage` in USD, or `None` if the model isn't in the price table.
+pub fn cost(provider: &str, model: &str, usage: Usage) -> Option<f64> {
+    let model = model.to_ascii_lowercase();
+    PRICES
+        .iter()
+        .find(|(p, prefix, _, _)| *p == provider && model.starts_with(prefix))
+        .map(|(_, _, input, output)| {
+            (usage.prompt_tokens as f64 * input + usage.completion_tokens as f64 * output)
+                / 1_000_000.0
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageDimension {
+    Day,
+    Provider,
+    Model,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UsageQuery {
+    /// Defaults to grouping by day, provider and model.
+    #[serde(default)]
+    pub group_by: Vec<UsageDimension>,
+    /// Inclusive bounds in epoch milliseconds.
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+/// One row of the summary. Dimensions that weren't grouped on are `None`.
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageBucket {
+    /// Local calendar day, `YYYY-MM-DD`.
+    pub day: Option<String>,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub requests: u32,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost_usd: f64,
+    /// Requests whose token counts were estimated locally.
+    pub estimated: u32,
+    /// Requests for models with no known price, left out of `cost_usd`.
+    pub unpriced: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageSummary {
+    pub buckets: Vec<UsageBucket>,
+    pub total_cost_usd: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct BudgetConfig {
+    monthly_limit_usd: Option<f64>,
+    /// The `YYYY-MM` month an alert last fired for, so it fires once a month.
+    alerted_month: Option<String>,
+}
+
+pub struct Budget(Mutex<BudgetConfig>);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetStatus {
+    pub monthly_limit_usd: Option<f64>,
+    pub month: String,
+    pub spent_usd: f64,
+}
+
+/// Payload of the `budget-alert` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetAlert {
+    pub month: String,
+    pub spent_usd: f64,
+    pub limit_usd: f64,
+}
+
+impl Database {
+    pub fn record_usage(
+        &self,
+        provider: &str,
+        model: &str,
+        usage: Usage,
+        cost_usd: Option<f64>,
+        estimated: bool,
+    ) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO usage
+                 (id, provider, model, prompt_tokens, completion_tokens, cost_usd, estimated, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                new_id(),
+                provider,
+                model,
+                usage.prompt_tokens,
+                usage.completion_tokens,
+                cost_usd,
+                estimated,
+                now_ms()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The current local month and what has been spent in it so far.
+    pub fn month_to_date_cost(&self) -> Result<(String, f64)> {
+        Ok(self.conn()?.query_row(
+            "SELECT strftime('%Y-%m', 'now', 'localtime'), COALESCE(SUM(cost_usd), 0)
+             FROM usage
+             WHERE strftime('%Y-%m', created_at / 1000, 'unixepoch', 'localtime')
+                   = strftime('%Y-%m', 'now', 'localtime')",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?)
+    }
+
+    pub fn usage_summary(&self, query: &UsageQuery) -> Result<UsageSummary> {
+        let group_by = if query.group_by.is_empty() {
+            &[
+                UsageDimension::Day,
+                UsageDimension::Provider,
+                UsageDimension::Model,
+            ][..]
+        } else {
+            &query.group_by[..]
+        };
+        let grouped = |dimension| group_by.contains(&dimension);
+        let day = "date(created_at / 1000, 'unixepoch', 'localtime')";
+        let columns = [
+            (grouped(UsageDimension::Day), day),
+            (grouped(UsageDimension::Provider), "provider"),
+            (grouped(UsageDimension::Model), "model"),
+        ];
+        let select: Vec<&str> = columns
+            .iter()
+            .map(|&(on, column)| if on { column } else { "NULL" })
+            .collect();
+        let keys: Vec<&str> = columns
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|&(_, column)| column)
+            .collect();
+        let mut sql = format!(
+            "SELECT {}, COUNT(*), SUM(prompt_tokens), SUM(completion_tokens),
+                    COALESCE(SUM(cost_usd), 0), SUM(estimated), SUM(cost_usd IS NULL)
+             FROM usage
+             WHERE (?1 IS NULL OR created_at >= ?1) AND (?2 IS NULL OR created_at <= ?2)",
+            select.join(", ")
+        );
+        if !keys.is_empty() {
+            sql.push_str(&format!(" GROUP BY {0} ORDER BY {0}", keys.join(", ")));
+        }
+
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params![query.from, query.to], |row| {
+            Ok(UsageBucket {
+                day: row.get(0)?,
+                provider: row.get(1)?,
+                model: row.get(2)?,
+                requests: row.get(3)?,
+                prompt_tokens: row.get::<_, Option<u64>>(4)?.unwrap_or_default(),
+                completion_tokens: row.get::<_, Option<u64>>(5)?.unwrap_or_default(),
+                cost_usd: row.get(6)?,
+                estimated: row.get::<_, Option<u32>>(7)?.unwrap_or_default(),
+                unpriced: row.get::<_, Option<u32>>(8)?.unwrap_or_default(),
+            })
+        })?;
+        let buckets = rows
+            .filter(|bucket| !matches!(bucket, Ok(b) if b.requests == 0))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(UsageSummary {
+            total_cost_usd: buckets.iter().map(|b| b.cost_usd).sum(),
+            buckets,
+        })
+    }
+}
+
+/// Loads the saved budget into managed state.
+pub fn init(app: &AppHandle) {
+    let budget = config::read::<BudgetConfig>(app, CONFIG_FILE)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    app.manage(Budget(Mutex::new(budget)));
+}
+
+/// Records a finished generation. Failures are swallowed: accounting must
+/// never fail the chat it is accounting for.
+pub fn record(app: &AppHandle, request: &ChatRequest, content: &str, usage: Option<Usage>) {
+    let _ = try_record(app, request, content, usage);
+}
+
+fn try_record(
+    app: &AppHandle,
+    request: &ChatRequest,
+    content: &str,
+    usage: Option<Usage>,
+) -> Result<()> {
+    if incognito::is_ephemeral_request(request.conversation_id.as_deref()) {
+        return Ok(());
+    }
+    let Some(db) = app.try_state::<Database>() else {
+        return Ok(());
+    };
+    let estimated = usage.is_none();
+    let usage = usage.unwrap_or_else(|| Usage {
+        prompt_tokens: tokens::count(&request.provider, &request.model, &request.messages).tokens
+            as u32,
+        completion_tokens: tokens::count_text(&request.provider, &request.model, content) as u32,
+    });
+    let cost_usd = cost(&request.provider, &request.model, usage);
+    db.record_usage(
+        &request.provider,
+        &request.model,
+        usage,
+        cost_usd,
+        estimated,
+    )?;
+    check_budget(app, &db)?;
+    Ok(())
+}
+
+/// Emits `budget-alert` the first time this month's spend reaches the limit.
+fn check_budget(app: &AppHandle, db: &Database) -> Result<BudgetStatus> {
+    let (month, spent_usd) = db.month_to_date_cost()?;
+    let budget = app.state::<Budget>();
+    let mut config = budget.0.lock().unwrap();
+    let status = BudgetStatus {
+        monthly_limit_usd: config.monthly_limit_usd,
+        month: month.clone(),
+        spent_usd,
+    };
+    let Some(limit_usd) = config.monthly_limit_usd else {
+        return Ok(status);
+    };
+    if spent_usd >= limit_usd && config.alerted_month.as_deref() != Some(month.as_str()) {
+        config.alerted_month = Some(month.clone());
+        config::write(app, CONFIG_FILE, &*config)?;
+        app.emit(
+            "budget-alert",
+            BudgetAlert {
+                month,
+                spent_usd,
+                limit_usd,
+            },
+        )?;
+    }
+    Ok(status)
+}
+
+#[tauri::command]
+pub async fn get_usage_summary(
+    db: State<'_, Database>,
+    query: Option<UsageQuery>,
+) -> Result<UsageSummary> {
+    db.usage_summary(&query.unwrap_or_default())
+}
+
+/// Sets (or with `None`, clears) the monthly budget. Changing it re-arms the
+/// alert, which fires straight away if the new limit is already exceeded.
+#[tauri::command]
+pub async fn set_budget_alert(
+    app: AppHandle,
+    db: State<'_, Database>,
+    budget: State<'_, Budget>,
+    monthly_limit_usd: Option<f64>,
+) -> Result<BudgetStatus> {
+    {
+        let mut config = budget.0.lock().unwrap();
+        config.monthly_limit_usd = monthly_limit_usd.filter(|limit| *limit > 0.0);
+        config.alerted_month = None;
+        config::write(&app, CONFIG_FILE, &*config)?;
+    }
+    check_budget(&app, &db)
+}
@@ -0,0 +1,264 @@
+//! Keeps an eye on files and folders attached to a conversation and emits
+//! `context-changed` with a line diff of each edit, so the UI can offer to
+//! refresh the context before the next prompt. Watches are polled every
+//! few seconds, which behaves the same on every platform and filesystem,
+//! network shares included. They live for the session; the frontend
+//! registers them again after a restart.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::diff::{self, DiffSpan, Granularity};
+use crate::error::{Error, Result};
+use crate::project;
+use crate::storage::new_id;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+/// Files tracked per watched folder.
+const MAX_FILES: usize = 500;
+/// Contents are kept, for diffing, only up to this size.
+const MAX_DIFF_BYTES: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+    /// Line diff from the old contents to the new; `None` for binary or
+    /// large files.
+    pub spans: Option<Vec<DiffSpan>>,
+}
+
+/// Payload of `context-changed`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ContextChanged {
+    pub watch_id: String,
+    pub conversation_id: Option<String>,
+    pub path: PathBuf,
+    pub changes: Vec<FileChange>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchInfo {
+    pub id: String,
+    pub path: PathBuf,
+    pub conversation_id: Option<String>,
+    pub files: usize,
+}
+
+#[derive(Clone, PartialEq)]
+struct FileState {
+    modified: Option<SystemTime>,
+    size: u64,
+    /// Text contents, when small enough to keep.
+    text: Option<String>,
+}
+
+struct Watch {
+    path: PathBuf,
+    conversation_id: Option<String>,
+    files: HashMap<PathBuf, FileState>,
+}
+
+/// Managed as Tauri state.
+#[derive(Default)]
+pub struct Watches(Mutex<HashMap<String, Watch>>);
+
+fn read_state(path: &Path) -> Option<FileState> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let text = (metadata.len() <= MAX_DIFF_BYTES)
+        .then(|| std::fs::read(path).ok())
+        .flatten()
+        .filter(|bytes| !bytes.iter().take(8000).any(|&b| b == 0))
+        .and_then(|bytes| String::from_utf8(bytes).ok());
+    Some(FileState {
+        modified: metadata.modified().ok(),
+        size: metadata.len(),
+        text,
+    })
+}
+
+/// The files a watch covers: the path itself, or what a folder holds
+/// outside `.gitignore`.
+fn watched_files(path: &Path) -> Vec<PathBuf> {
+    if path.is_dir() {
+        project::list_files(path)
+            .into_iter()
+            .take(MAX_FILES)
+            .map(|file| path.join(file))
+            .collect()
+    } else {
+        vec![path.to_path_buf()]
+    }
+}
+
+fn snapshot(path: &Path) -> HashMap<PathBuf, FileState> {
+    watched_files(path)
+        .into_iter()
+        .filter_map(|file| read_state(&file).map(|state| (file, state)))
+        .collect()
+}
+
+/// Compares a watch against the disk, updating it in place.
+fn rescan(watch: &mut Watch) -> Vec<FileChange> {
+    let mut changes = Vec::new();
+    let mut current = HashMap::new();
+    for file in watched_files(&watch.path) {
+        let previous = watch.files.remove(&file);
+        // Unchanged size and time mean the contents needn't be read.
+        let unchanged = previous.as_ref().and_then(|previous| {
+            let metadata = std::fs::metadata(&file).ok()?;
+            (metadata.len() == previous.size && metadata.modified().ok() == previous.modified)
+                .then(|| previous.clone())
+        });
+        if let Some(state) = unchanged {
+            current.insert(file, state);
+            continue;
+        }
+        let Some(state) = read_state(&file) else {
+            continue;
+        };
+        match &previous {
+            None => changes.push(FileChange {
+                path: file.clone(),
+                kind: ChangeKind::Created,
+                spans: None,
+            }),
+            Some(previous) if previous.text.is_some() && previous.text == state.text => {}
+            Some(previous) => changes.push(FileChange {
+                path: file.clone(),
+                kind: ChangeKind::Modified,
+                spans: previous
+                    .text
+                    .as_deref()
+                    .zip(state.text.as_deref())
+                    .map(|(old, new)| diff::diff_pair(old, new, Granularity::Line).0),
+            }),
+        }
+        current.insert(file, state);
+    }
+    changes.extend(watch.files.drain().map(|(path, _)| FileChange {
+        path,
+        kind: ChangeKind::Removed,
+        spans: None,
+    }));
+    watch.files = current;
+    changes
+}
+
+fn poll(app: &AppHandle) -> Vec<ContextChanged> {
+    let watches = app.state::<Watches>();
+    let mut watches = watches.0.lock().unwrap();
+    watches
+        .iter_mut()
+        .filter_map(|(id, watch)| {
+            let changes = rescan(watch);
+            (!changes.is_empty()).then(|| ContextChanged {
+                watch_id: id.clone(),
+                conversation_id: watch.conversation_id.clone(),
+                path: watch.path.clone(),
+                changes,
+            })
+        })
+        .collect()
+}
+
+pub fn init(app: &AppHandle) {
+    app.manage(Watches::default());
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let handle = app.clone();
+            let Ok(events) = tauri::async_runtime::spawn_blocking(move || poll(&handle)).await
+            else {
+                continue;
+            };
+            for event in events {
+                let _ = app.emit("context-changed", event);
+            }
+        }
+    });
+}
+
+fn info(id: &str, watch: &Watch) -> WatchInfo {
+    WatchInfo {
+        id: id.to_string(),
+        path: watch.path.clone(),
+        conversation_id: watch.conversation_id.clone(),
+        files: watch.files.len(),
+    }
+}
+
+/// Starts watching a file or folder, optionally on behalf of a
+/// conversation. Watching the same path for the same conversation again
+/// returns the existing watch.
+#[tauri::command]
+pub async fn watch_path(
+    watches: State<'_, Watches>,
+    path: PathBuf,
+    conversation_id: Option<String>,
+) -> Result<WatchInfo> {
+    if !path.exists() {
+        return Err(Error::NotFound(path.display().to_string()));
+    }
+    if let Some((id, watch)) = watches
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(_, w)| w.path == path && w.conversation_id == conversation_id)
+    {
+        return Ok(info(id, watch));
+    }
+    let root = path.clone();
+    let files = tauri::async_runtime::spawn_blocking(move || snapshot(&root)).await?;
+    let id = new_id();
+    let watch = Watch {
+        path,
+        conversation_id,
+        files,
+    };
+    let info = info(&id, &watch);
+    watches.0.lock().unwrap().insert(id, watch);
+    Ok(info)
+}
+
+#[tauri::command]
+pub fn unwatch_path(watches: State<'_, Watches>, id: String) -> Result<()> {
+    watches
+        .0
+        .lock()
+        .unwrap()
+        .remove(&id)
+        .map(|_| ())
+        .ok_or_else(|| Error::NotFound(format!("watch {id}")))
+}
+
+/// Active watches, only those of `conversation_id` when given.
+#[tauri::command]
+pub fn list_watches(
+    watches: State<'_, Watches>,
+    conversation_id: Option<String>,
+) -> Vec<WatchInfo> {
+    watches
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, w)| conversation_id.is_none() || w.conversation_id == conversation_id)
+        .map(|(id, watch)| info(id, watch))
+        .collect()
+}
@@ -0,0 +1,601 @@
+//! Web pages fetched from Rust for use in prompts, which sidesteps the
+//! webview's CORS limits and keeps raw HTML out of the context window.
+//! `fetch_url` checks the site's robots.txt, downloads the page and keeps
+//! only the readable article: paragraphs are scored by length, commas and
+//! link density and the best-scoring container wins, as in Readability.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use dom_query::{Document, NodeId, NodeRef};
+use futures_util::StreamExt;
+use reqwest::header::{CONTENT_TYPE, USER_AGENT};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use tauri::{State, Url};
+
+use crate::citations;
+use crate::error::{Error, Result};
+use crate::storage::Database;
+
+pub(crate) const AGENT: &str = concat!("Pentamind/", env!("CARGO_PKG_VERSION"));
+/// The token matched against robots.txt `User-agent` lines.
+const ROBOTS_TOKEN: &str = "pentamind";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(20);
+const ROBOTS_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_PAGE_BYTES: usize = 5 * 1024 * 1024;
+const DEFAULT_MAX_CHARS: usize = 50_000;
+
+/// Removed before scoring: never part of an article.
+const NON_CONTENT: &str = "script, style, noscript, template, iframe, svg, canvas, form, \
+    button, input, select, textarea, nav, aside, footer, object, embed, dialog";
+/// Class or id fragments of page furniture.
+const UNLIKELY: &[&str] = &[
+    "banner",
+    "breadcrumb",
+    "comment",
+    "cookie",
+    "footer",
+    "menu",
+    "modal",
+    "nav",
+    "newsletter",
+    "popup",
+    "promo",
+    "related",
+    "share",
+    "sidebar",
+    "social",
+    "sponsor",
+    "subscribe",
+    "widget",
+    "advert",
+    "ad-",
+];
+/// Class or id fragments of content, which win over `UNLIKELY`.
+const LIKELY: &[&str] = &[
+    "article", "body", "content", "entry", "main", "page", "post", "story", "text", "blog",
+];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FetchOptions {
+    /// Fetch even if robots.txt disallows it.
+    pub ignore_robots: bool,
+    pub timeout_secs: Option<u64>,
+    /// Longest text to return; defaults to 50 000 characters.
+    pub max_chars: Option<usize>,
+}
+
+/// What `fetch_url` returns.
+#[derive(Debug, Clone, Serialize)]
+pub struct WebPage {
+    /// Where the page ended up after redirects.
+    pub url: String,
+    pub title: Option<String>,
+    pub byline: Option<String>,
+    pub site_name: Option<String>,
+    pub description: Option<String>,
+    pub published: Option<String>,
+    pub language: Option<String>,
+    pub content_type: Option<String>,
+    /// The article as Markdown, or the body as-is for plain-text responses.
+    pub text: String,
+    pub word_count: usize,
+    /// `text` was cut at `max_chars`.
+    pub truncated: bool,
+}
+
+impl WebPage {
+    /// The page as text for a model: title and address, then the body.
+    pub fn to_prompt(&self) -> String {
+        let mut out = String::new();
+        if let Some(title) = &self.title {
+            out.push_str(&format!("# {title}\n"));
+        }
+        out.push_str(&format!("Source: {}\n\n", self.url));
+        out.push_str(&self.text);
+        if self.truncated {
+            out.push_str("\n\n[truncated]");
+        }
+        out
+    }
+}
+
+fn fetch_error(message: impl Into<String>) -> Error {
+    Error::Fetch(message.into())
+}
+
+/// An Allow (`true`) or Disallow rule and its path pattern.
+type Rule = (bool, String);
+
+#[derive(Default)]
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+}
+
+/// The robots.txt rules that apply to this app.
+#[derive(Debug, Default)]
+struct Robots {
+    rules: Vec<Rule>,
+}
+
+impl Robots {
+    /// Uses the group naming this app if there is one, else the `*` group.
+    fn parse(text: &str) -> Self {
+        let mut groups: Vec<Group> = Vec::new();
+        let mut in_agents = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (key, value) = (key.trim().to_ascii_lowercase(), value.trim());
+            match key.as_str() {
+                "user-agent" => {
+                    if !in_agents {
+                        groups.push(Group::default());
+                    }
+                    in_agents = true;
+                    if let Some(group) = groups.last_mut() {
+                        group.agents.push(value.to_ascii_lowercase());
+                    }
+                }
+                "allow" | "disallow" => {
+                    in_agents = false;
+                    // An empty Disallow allows everything.
+                    if let (Some(group), false) = (groups.last_mut(), value.is_empty()) {
+                        group.rules.push((key == "allow", value.to_string()));
+                    }
+                }
+                _ => in_agents = false,
+            }
+        }
+        let ours = groups
+            .iter()
+            .position(|g| {
+                g.agents
+                    .iter()
+                    .any(|a| a.split('/').next() == Some(ROBOTS_TOKEN))
+            })
+            .or_else(|| {
+                groups
+                    .iter()
+                    .position(|g| g.agents.iter().any(|a| a == "*"))
+            });
+        Self {
+            rules: ours
+                .map(|i| groups.swap_remove(i).rules)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The longest matching rule decides; Allow wins a tie.
+    fn allows(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .filter(|(_, pattern)| robots_match(pattern, path))
+            .max_by_key(|(allow, pattern)| (pattern.len(), *allow))
+            .is_none_or(|(allow, _)| *allow)
+    }
+}
+
+/// Matches a robots.txt path pattern, where `*` is any run of characters
+/// and a trailing `$` anchors the end.
+fn robots_match(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let Some(mut rest) = path.strip_prefix(parts[0]) else {
+        return false;
+    };
+    let Some((last, middle)) = parts[1..].split_last() else {
+        return !anchored || rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    if anchored {
+        rest.ends_with(last)
+    } else {
+        rest.contains(last)
+    }
+}
+
+/// Whether robots.txt lets this app fetch `url`. As RFC 9309 has it, a
+/// missing file (any 4xx) allows everything; one that can't be read
+/// (unreachable, 5xx) allows nothing, which is reported as an error.
+async fn robots_allow(client: &Client, url: &Url) -> Result<bool> {
+    let mut robots_url = url.clone();
+    robots_url.set_path("/robots.txt");
+    robots_url.set_query(None);
+    robots_url.set_fragment(None);
+    let response = client
+        .get(robots_url)
+        .header(USER_AGENT, AGENT)
+        .timeout(ROBOTS_TIMEOUT)
+        .send()
+        .await;
+    let host = url.host_str().unwrap_or_default();
+    let response =
+        response.map_err(|e| fetch_error(format!("couldn't read robots.txt of {host}: {e}")))?;
+    let status = response.status();
+    if status.is_client_error() {
+        return Ok(true);
+    }
+    if !status.is_success() {
+        return Err(fetch_error(format!(
+            "couldn't read robots.txt of {host}: the server answered {status}"
+        )));
+    }
+    let text = response.text().await.unwrap_or_default();
+    let mut path = url.path().to_string();
+    if let Some(query) = url.query() {
+        path.push('?');
+        path.push_str(query);
+    }
+    Ok(Robots::parse(&text).allows(&path))
+}
+
+fn clean(text: &str) -> Option<String> {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+/// The first of the named `<meta>` tags that has content.
+fn meta(doc: &Document, names: &[&str]) -> Option<String> {
+    names.iter().find_map(|name| {
+        let selector = format!(r#"meta[property="{name}"], meta[name="{name}"]"#);
+        doc.select(&selector)
+            .nodes()
+            .iter()
+            .find_map(|node| clean(&node.attr("content")?))
+    })
+}
+
+fn class_and_id(node: &NodeRef) -> String {
+    let mut names = node.class().map(|c| c.to_string()).unwrap_or_default();
+    if let Some(id) = node.id_attr() {
+        names.push(' ');
+        names.push_str(&id);
+    }
+    names.to_ascii_lowercase()
+}
+
+/// Bonus or penalty for what a container's class and id suggest.
+fn class_weight(node: &NodeRef) -> f32 {
+    let names = class_and_id(node);
+    let mut weight = 0.0;
+    if LIKELY.iter().any(|w| names.contains(w)) {
+        weight += 25.0;
+    }
+    if UNLIKELY.iter().any(|w| names.contains(w)) {
+        weight -= 25.0;
+    }
+    weight
+}
+
+fn initial_score(node: &NodeRef) -> f32 {
+    let tag = node.node_name().map(|n| n.to_string()).unwrap_or_default();
+    let base = match tag.as_str() {
+        "article" | "main" => 10.0,
+        "div" => 5.0,
+        "pre" | "td" | "blockquote" | "section" => 3.0,
+        "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+    base + class_weight(node)
+}
+
+fn text_len(node: &NodeRef) -> usize {
+    node.text()
+        .split_whitespace()
+        .map(|w| w.chars().count() + 1)
+        .sum()
+}
+
+/// The share of a node's text that sits inside links.
+fn link_density(node: &NodeRef) -> f32 {
+    let total = text_len(node);
+    if total == 0 {
+        return 0.0;
+    }
+    let linked: usize = node
+        .descendants_it()
+        .filter(|n| n.has_name("a"))
+        .map(|a| text_len(&a))
+        .sum();
+    linked as f32 / total as f32
+}
+
+/// Drops elements a page wraps its content in: navigation, ads, comment
+/// sections and the like, by tag and by class or id.
+fn strip_furniture(doc: &Document) {
+    doc.select(NON_CONTENT).remove();
+    let unlikely: Vec<NodeRef> = doc
+        .select("body *")
+        .nodes()
+        .iter()
+        .filter(|node| {
+            if node.has_name("article") || node.has_name("main") || node.has_name("body") {
+                return false;
+            }
+            let names = class_and_id(node);
+            UNLIKELY.iter().any(|w| names.contains(w)) && !LIKELY.iter().any(|w| names.contains(w))
+        })
+        .copied()
+        .collect();
+    for node in unlikely {
+        node.remove_from_parent();
+    }
+}
+
+/// The element holding the article, by Readability's scoring.
+fn top_candidate<'a>(doc: &'a Document) -> Option<(NodeRef<'a>, HashMap<NodeId, f32>)> {
+    let mut scores: HashMap<NodeId, f32> = HashMap::new();
+    let mut nodes: HashMap<NodeId, NodeRef<'a>> = HashMap::new();
+    for paragraph in doc.select("p, pre, td, blockquote").nodes() {
+        let text = paragraph.text();
+        let len = text.trim().chars().count();
+        if len < 25 {
+            continue;
+        }
+        let score = 1.0 + text.matches(',').count() as f32 + (len / 100).min(3) as f32;
+        let Some(parent) = paragraph.parent() else {
+            continue;
+        };
+        let grandparent = parent.parent().filter(|g| g.is_element());
+        for (node, share) in [(Some(parent), 1.0), (grandparent, 0.5)] {
+            let Some(node) = node.filter(|n| n.is_element()) else {
+                continue;
+            };
+            *scores
+                .entry(node.id)
+                .or_insert_with(|| initial_score(&node)) += score * share;
+            nodes.insert(node.id, node);
+        }
+    }
+    for (id, score) in scores.iter_mut() {
+        *score *= 1.0 - link_density(&nodes[id]);
+    }
+    let (best, _) = scores
+        .iter()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(id, score)| (*id, *score))?;
+    Some((nodes[&best], scores))
+}
+
+/// The article's parts: the top candidate plus siblings that look like
+/// more of the same (content often arrives split over several blocks).
+fn article_nodes<'a>(top: NodeRef<'a>, scores: &HashMap<NodeId, f32>) -> Vec<NodeRef<'a>> {
+    let top_score = scores.get(&top.id).copied().unwrap_or_default();
+    let threshold = (top_score * 0.2).max(10.0);
+    let Some(parent) = top.parent() else {
+        return vec![top];
+    };
+    parent
+        .element_children()
+        .into_iter()
+        .filter(|sibling| {
+            if sibling.id == top.id {
+                return true;
+            }
+            let bonus =
+                if class_and_id(sibling) == class_and_id(&top) && !class_and_id(&top).is_empty() {
+                    top_score * 0.2
+                } else {
+                    0.0
+                };
+            if scores
+                .get(&sibling.id)
+                .is_some_and(|s| s + bonus >= threshold)
+            {
+                return true;
+            }
+            if !sibling.has_name("p") {
+                return false;
+            }
+            let (len, density) = (text_len(sibling), link_density(sibling));
+            (len > 80 && density < 0.25)
+                || (len > 0 && density == 0.0 && sibling.text().contains(". "))
+        })
+        .collect()
+}
+
+/// Collapses runs of blank lines and drops the escaping the Markdown
+/// writer puts on ordinary punctuation (`end\.`), which only costs tokens.
+fn tidy_markdown(markdown: &str) -> String {
+    let mut out = String::with_capacity(markdown.len());
+    let (mut blank, mut in_fence) = (false, false);
+    for line in markdown.lines().map(str::trim_end) {
+        if line.trim().is_empty() {
+            blank = true;
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str(if blank { "\n\n" } else { "\n" });
+        }
+        blank = false;
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if in_fence {
+            out.push_str(line);
+            continue;
+        }
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '\\' && chars.peek().is_some_and(|n| ".!-()+".contains(*n)) {
+                continue;
+            }
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Metadata and readable text of an HTML page.
+fn extract(html: &str, page: &mut WebPage) {
+    let doc = Document::from(html);
+    page.title = meta(&doc, &["og:title", "twitter:title"])
+        .or_else(|| clean(&doc.select("title").text()))
+        .or_else(|| clean(&doc.select("h1").first().text()));
+    page.byline = meta(&doc, &["author", "article:author", "twitter:creator"]);
+    page.site_name = meta(&doc, &["og:site_name", "application-name"]);
+    page.description = meta(
+        &doc,
+        &["description", "og:description", "twitter:description"],
+    );
+    page.published = meta(&doc, &["article:published_time", "date", "pubdate"]).or_else(|| {
+        doc.select("time[datetime]")
+            .nodes()
+            .first()
+            .and_then(|t| clean(&t.attr("datetime")?))
+    });
+    page.language = doc
+        .select("html")
+        .nodes()
+        .first()
+        .and_then(|html| clean(&html.attr("lang")?));
+
+    strip_furniture(&doc);
+    let markdown = match top_candidate(&doc) {
+        Some((top, scores)) => article_nodes(top, &scores)
+            .iter()
+            .map(|node| node.md(None).to_string())
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        None => doc
+            .body()
+            .map(|body| body.md(None).to_string())
+            .unwrap_or_default(),
+    };
+    page.text = tidy_markdown(&markdown);
+}
+
+/// An HTML fragment, such as a feed entry's content, as Markdown. Unlike a
+/// page, all of it is kept apart from scripts and page furniture.
+pub(crate) fn fragment_markdown(html: &str) -> String {
+    let doc = Document::from(html);
+    doc.select(NON_CONTENT).remove();
+    let markdown = doc
+        .body()
+        .map(|body| body.md(None).to_string())
+        .unwrap_or_default();
+    tidy_markdown(&markdown)
+}
+
+/// Downloads `url` and extracts its readable text. Fails if robots.txt
+/// disallows it (unless told to ignore that), on an error status, and for
+/// responses that aren't HTML or text.
+pub async fn fetch(client: &Client, url: &str, options: &FetchOptions) -> Result<WebPage> {
+    let url = Url::parse(url).map_err(|e| fetch_error(format!("bad URL: {e}")))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(fetch_error("only http and https URLs can be fetched"));
+    }
+    if !options.ignore_robots && !robots_allow(client, &url).await? {
+        return Err(fetch_error(format!(
+            "robots.txt of {} disallows fetching this page",
+            url.host_str().unwrap_or_default()
+        )));
+    }
+    let timeout = options
+        .timeout_secs
+        .map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+    let response = client
+        .get(url)
+        .header(USER_AGENT, AGENT)
+        .timeout(timeout)
+        .send()
+        .await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(fetch_error(match status {
+            StatusCode::NOT_FOUND => "the page doesn't exist (404)".to_string(),
+            _ => format!("the server answered {status}"),
+        }));
+    }
+    let final_url = response.url().to_string();
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_ascii_lowercase());
+    let kind = content_type.as_deref().unwrap_or("text/html");
+    let is_html = kind.contains("html");
+    if !is_html && !kind.starts_with("text/") && !kind.contains("json") && !kind.contains("xml") {
+        return Err(fetch_error(format!("can't read {kind} content")));
+    }
+
+    let mut body = Vec::new();
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = tokio::time::timeout(timeout, stream.next())
+        .await
+        .map_err(|_| fetch_error("timed out reading the page"))?
+    {
+        body.extend_from_slice(&chunk?);
+        if body.len() >= MAX_PAGE_BYTES {
+            body.truncate(MAX_PAGE_BYTES);
+            break;
+        }
+    }
+    let body = String::from_utf8_lossy(&body).into_owned();
+
+    let mut page = WebPage {
+        url: final_url,
+        title: None,
+        byline: None,
+        site_name: None,
+        description: None,
+        published: None,
+        language: None,
+        content_type,
+        text: String::new(),
+        word_count: 0,
+        truncated: false,
+    };
+    if is_html {
+        // The DOM isn't Send, so parse and score off the async threads.
+        page = tauri::async_runtime::spawn_blocking(move || {
+            extract(&body, &mut page);
+            page
+        })
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?;
+    } else {
+        page.text = body;
+    }
+    let max_chars = options.max_chars.unwrap_or(DEFAULT_MAX_CHARS);
+    if let Some((cut, _)) = page.text.char_indices().nth(max_chars) {
+        page.text.truncate(cut);
+        page.truncated = true;
+    }
+    page.word_count = page.text.split_whitespace().count();
+    Ok(page)
+}
+
+/// Fetches a page for inclusion in a prompt, as readable text plus
+/// metadata. Under a `request_id` the page is recorded as one of the
+/// request's sources, for citations, unless `conversation_id` is incognito.
+#[tauri::command]
+pub async fn fetch_url(
+    client: State<'_, Client>,
+    db: State<'_, Database>,
+    url: String,
+    options: Option<FetchOptions>,
+    request_id: Option<String>,
+    conversation_id: Option<String>,
+) -> Result<WebPage> {
+    let page = fetch(&client, &url, &options.unwrap_or_default()).await?;
+    if let Some(request_id) = &citations::recorded_under(request_id, conversation_id.as_deref()) {
+        db.record_page(request_id, &page)?;
+    }
+    Ok(page)
+}
@@ -0,0 +1,128 @@
+//! Remembers each window's geometry across launches. Positions are kept in
+//! physical pixels together with the monitor name, and a saved position is
+//! only reapplied if it still lands on a connected monitor.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{
+    AppHandle, LogicalSize, Manager, Monitor, PhysicalPosition, PhysicalSize, State, WebviewWindow,
+    Window, WindowEvent,
+};
+
+use crate::config;
+use crate::error::Result;
+
+const STATE_FILE: &str = "window-state.json";
+/// How much of the window must overlap a monitor for a saved position to count
+/// as visible.
+const MIN_VISIBLE: i32 = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WindowGeometry {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    maximized: bool,
+    monitor: Option<String>,
+}
+
+/// Last known geometry per window label.
+#[derive(Default)]
+pub struct WindowStates(Mutex<HashMap<String, WindowGeometry>>);
+
+fn capture(window: &Window) -> Result<WindowGeometry> {
+    let position = window.outer_position()?;
+    let size = window.inner_size()?;
+    Ok(WindowGeometry {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+        maximized: window.is_maximized()?,
+        monitor: window.current_monitor()?.and_then(|m| m.name().cloned()),
+    })
+}
+
+fn is_visible_on(geometry: &WindowGeometry, monitor: &Monitor) -> bool {
+    let origin = monitor.position();
+    let size = monitor.size();
+    let left = geometry.x.max(origin.x);
+    let top = geometry.y.max(origin.y);
+    let right = (geometry.x + geometry.width as i32).min(origin.x + size.width as i32);
+    let bottom = (geometry.y + geometry.height as i32).min(origin.y + size.height as i32);
+    right - left >= MIN_VISIBLE && bottom - top >= MIN_VISIBLE
+}
+
+/// Loads saved state and applies it to `window`. Call before the window is
+/// first shown to avoid a visible jump.
+pub fn restore(app: &AppHandle, window: &WebviewWindow) -> Result<()> {
+    let saved: HashMap<String, WindowGeometry> = config::read(app, STATE_FILE)?.unwrap_or_default();
+    let states = app.state::<WindowStates>();
+    let mut states = states.0.lock().unwrap();
+    *states = saved;
+    let Some(geometry) = states.get(window.label()) else {
+        return Ok(());
+    };
+
+    window.set_size(PhysicalSize::new(geometry.width, geometry.height))?;
+    let monitors = window.available_monitors()?;
+    let on_screen = monitors
+        .iter()
+        .filter(|m| geometry.monitor.is_none() || m.name() == geometry.monitor.as_ref())
+        .any(|m| is_visible_on(geometry, m));
+    if on_screen {
+        window.set_position(PhysicalPosition::new(geometry.x, geometry.y))?;
+    }
+    if geometry.maximized {
+        window.maximize()?;
+    }
+    Ok(())
+}
+
+fn save(window: &Window) -> Result<()> {
+    let geometry = capture(window)?;
+    let app = window.app_handle();
+    let states = app.state::<WindowStates>();
+    let mut states = states.0.lock().unwrap();
+    states.insert(window.label().to_string(), geometry);
+    config::write(app, STATE_FILE, &*states)
+}
+
+/// Saves geometry when a window closes. Windows that only hide (tray mode)
+/// are covered by [`save_all`] on exit.
+pub fn on_window_event(window: &Window, event: &WindowEvent) {
+    if matches!(event, WindowEvent::CloseRequested { .. }) {
+        let _ = save(window);
+    }
+}
+
+pub fn save_all(app: &AppHandle) {
+    for window in app.webview_windows().values() {
+        let _ = save(&window.as_ref().window());
+    }
+}
+
+/// Forgets saved geometry and puts the main window back at its configured
+/// size, centered; for when a saved position ends up somewhere unreachable.
+#[tauri::command]
+pub fn reset_window_state(app: AppHandle, states: State<'_, WindowStates>) -> Result<()> {
+    states.0.lock().unwrap().clear();
+    config::remove(&app, STATE_FILE)?;
+    for window in app.webview_windows().values() {
+        if let Some(default) = app
+            .config()
+            .app
+            .windows
+            .iter()
+            .find(|w| w.label == window.label())
+        {
+            window.unmaximize()?;
+            window.set_size(LogicalSize::new(default.width, default.height))?;
+        }
+        window.center()?;
+    }
+    Ok(())
+}
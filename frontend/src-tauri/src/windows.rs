@@ -0,0 +1,187 @@
+//! Helpers shared by everything that shows or hides the main window
+//! (dock reopen, tray, shortcuts), and conversations opened in windows of
+//! their own. Events about a conversation go to the main window and to the
+//! windows showing it, not to every window.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::{
+    AppHandle, Emitter, EventTarget, Manager, State, WebviewUrl, WebviewWindowBuilder, Window,
+    WindowEvent,
+};
+
+use crate::error::Result;
+use crate::storage::conversations::{Conversation, Message};
+use crate::storage::{now_ms, Database};
+use crate::window_state;
+
+pub const MAIN_LABEL: &str = "main";
+
+pub fn show_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window(MAIN_LABEL) {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+}
+
+pub fn toggle_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window(MAIN_LABEL) {
+        if window.is_visible().unwrap_or(false) {
+            let _ = window.hide();
+        } else {
+            show_main_window(app);
+        }
+    }
+}
+
+const CONVERSATION_PREFIX: &str = "conversation-";
+
+/// A conversation open in a window of its own.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationWindow {
+    pub label: String,
+    pub conversation_id: String,
+    pub opened_at: i64,
+}
+
+/// Conversation windows by label. Managed as Tauri state.
+#[derive(Default)]
+pub struct ConversationWindows(Mutex<HashMap<String, ConversationWindow>>);
+
+/// Payload of the `message-added` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageAdded {
+    pub conversation_id: String,
+    pub message: Message,
+}
+
+/// The main window and every window showing `conversation_id`.
+fn conversation_labels(app: &AppHandle, conversation_id: &str) -> Vec<String> {
+    let mut labels = vec![MAIN_LABEL.to_string()];
+    labels.extend(
+        app.state::<ConversationWindows>()
+            .0
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|w| w.conversation_id == conversation_id)
+            .map(|w| w.label.clone()),
+    );
+    labels
+}
+
+/// Sends `event` to the windows that show `conversation_id` rather than to
+/// all of them.
+pub fn emit_to_conversation<S: Serialize + Clone>(
+    app: &AppHandle,
+    conversation_id: &str,
+    event: &str,
+    payload: S,
+) {
+    for label in conversation_labels(app, conversation_id) {
+        let _ = app.emit_to(EventTarget::webview_window(label), event, payload.clone());
+    }
+}
+
+/// Announces a stored message to the windows showing its conversation.
+pub fn message_added(app: &AppHandle, message: &Message) {
+    emit_to_conversation(
+        app,
+        &message.conversation_id,
+        "message-added",
+        MessageAdded {
+            conversation_id: message.conversation_id.clone(),
+            message: message.clone(),
+        },
+    );
+}
+
+/// Keeps the titles of a conversation's windows in step with it.
+pub fn conversation_renamed(app: &AppHandle, conversation: &Conversation) {
+    for label in conversation_labels(app, &conversation.id) {
+        if label == MAIN_LABEL {
+            continue;
+        }
+        if let Some(window) = app.get_webview_window(&label) {
+            let _ = window.set_title(&conversation.title);
+        }
+    }
+}
+
+/// Closes the windows of a conversation that no longer exists.
+pub fn conversation_deleted(app: &AppHandle, conversation_id: &str) {
+    for label in conversation_labels(app, conversation_id) {
+        if label == MAIN_LABEL {
+            continue;
+        }
+        if let Some(window) = app.get_webview_window(&label) {
+            let _ = window.close();
+        }
+    }
+}
+
+/// Forgets a conversation window once it is gone.
+pub fn on_window_event(window: &Window, event: &WindowEvent) {
+    if matches!(event, WindowEvent::Destroyed) && window.label().starts_with(CONVERSATION_PREFIX) {
+        window
+            .app_handle()
+            .state::<ConversationWindows>()
+            .0
+            .lock()
+            .unwrap()
+            .remove(window.label());
+    }
+}
+
+/// Opens `conversation_id` in a window of its own, or focuses the one it is
+/// already in. Returns the window's label.
+#[tauri::command]
+pub fn open_conversation_window(
+    app: AppHandle,
+    db: State<'_, Database>,
+    windows: State<'_, ConversationWindows>,
+    conversation_id: String,
+) -> Result<String> {
+    let conversation = db.get_conversation(&conversation_id)?.conversation;
+    let label = format!("{CONVERSATION_PREFIX}{}", conversation.id);
+    if let Some(window) = app.get_webview_window(&label) {
+        window.unminimize()?;
+        window.show()?;
+        window.set_focus()?;
+        return Ok(label);
+    }
+    let window = WebviewWindowBuilder::new(
+        &app,
+        &label,
+        WebviewUrl::App(format!("index.html?view=conversation&id={}", conversation.id).into()),
+    )
+    .title(&conversation.title)
+    .inner_size(900.0, 700.0)
+    .min_inner_size(480.0, 360.0)
+    .visible(false)
+    .build()?;
+    windows.0.lock().unwrap().insert(
+        label.clone(),
+        ConversationWindow {
+            label: label.clone(),
+            conversation_id: conversation.id,
+            opened_at: now_ms(),
+        },
+    );
+    let _ = window_state::restore(&app, &window);
+    window.show()?;
+    window.set_focus()?;
+    Ok(label)
+}
+
+#[tauri::command]
+pub fn list_conversation_windows(
+    windows: State<'_, ConversationWindows>,
+) -> Vec<ConversationWindow> {
+    let mut open: Vec<_> = windows.0.lock().unwrap().values().cloned().collect();
+    open.sort_by_key(|w| w.opened_at);
+    open
+}